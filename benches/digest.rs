@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use pbft::crypto::DigestAlgorithm;
+use pbft::messages::{ClientIdentity, ClientRequest, Operation};
+use pbft::state::store_digest;
+use pbft::{Key, Value};
+
+/// Average time of the operation over enough runs to take about half a second
+fn time<T>(mut op: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < Duration::from_millis(500) {
+        black_box(op());
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+/// Compares the digest algorithms on the digests replicas compute on their hot paths:
+/// client request digests, state digests at checkpoints, and raw hashing of large payloads.
+///
+/// Usage: cargo bench --bench digest
+fn main() {
+    let respond_addr: SocketAddr = "127.0.0.1:7100".parse().unwrap();
+    let request = ClientRequest {
+        respond_addr,
+        time_stamp: 1,
+        key: Key::from("key"),
+        operation: Operation::Set(Value::from("5")),
+        relay_id: Some(0),
+        read_only: false,
+        batch: Vec::new(),
+        bulk: None,
+        client_id: None,
+        signature: Vec::new(),
+    };
+    let store: BTreeMap<Key, Value> = (0..10_000)
+        .map(|i| (Key::from(format!("key{}", i)), Value::from(i.to_string())))
+        .collect();
+    let key_owners = store
+        .keys()
+        .map(|key| (key.clone(), ClientIdentity::Addr(respond_addr)))
+        .collect();
+    let payload = vec![0xabu8; 1 << 20];
+
+    println!(
+        "{:<12} {:>16} {:>22} {:>14}",
+        "algorithm", "request digest", "state (10k keys)", "1 MiB"
+    );
+    for algorithm in DigestAlgorithm::ALL {
+        let request_time = time(|| request.digest_with(algorithm));
+        let state_time = time(|| store_digest(&store, &key_owners, algorithm));
+        let payload_time = time(|| algorithm.hash(&payload));
+        println!(
+            "{:<12} {:>16?} {:>22?} {:>14?}",
+            algorithm.to_string(),
+            request_time,
+            state_time,
+            payload_time
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use pbft::config::Config;
+use pbft::messages::{ClientRequest, Operation, PrePrepare};
+use pbft::state::State;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+/// System allocator which keeps count of the bytes allocated and not freed yet
+struct Counting;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn live_bytes() -> isize {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+const REQUESTS: usize = 1_000;
+
+/// Bytes the log of a replica holds per request for requests with values of the size,
+/// each pre-prepared in a view and re-proposed in the next one
+fn log_bytes_per_request(value_size: usize) -> isize {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.log_window = 4 * REQUESTS;
+    let mut state = State::new(1, config);
+    let primaries = [MessageBuilder::generate(0), MessageBuilder::generate(1)];
+
+    // the requests and pre-prepares are counted until they are dropped, so only what
+    // the log keeps of them remains
+    let before = live_bytes();
+    let requests: Vec<ClientRequest> = (1..=REQUESTS)
+        .map(|time_stamp| ClientRequest {
+            respond_addr: SocketAddr::from(([127, 0, 0, 1], 7100)),
+            time_stamp,
+            key: Key::from(format!("key{}", time_stamp).as_str()),
+            operation: Operation::Set(Value::from(vec![7u8; value_size])),
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        })
+        .collect();
+    let entries: Vec<(PrePrepare, PrePrepare)> = requests
+        .iter()
+        .enumerate()
+        .map(|(i, request)| {
+            let builder = |view: usize| {
+                primaries[view]
+                    .clone()
+                    .view(view)
+                    .seq_num(i + 1)
+                    .client_request(request.clone())
+            };
+            (builder(0).pre_prepare(), builder(1).pre_prepare())
+        })
+        .collect();
+    drop(requests);
+    for (first, second) in entries {
+        state.log_pre_prepare(first);
+        state.log_pre_prepare(second);
+    }
+    (live_bytes() - before) / REQUESTS as isize
+}
+
+/// Measures the memory the log takes per request, as request bodies grow. Every body is
+/// held once however many log entries refer to it, so the overhead per request stays flat
+/// while the bodies grow.
+///
+/// Usage: cargo bench --bench memory
+fn main() {
+    println!(
+        "{:>12} {:>18} {:>22}",
+        "value bytes", "log bytes/request", "overhead bytes/request"
+    );
+    for value_size in [16, 1 << 10, 16 << 10, 64 << 10] {
+        let bytes = log_bytes_per_request(value_size);
+        println!(
+            "{:>12} {:>18} {:>22}",
+            value_size,
+            bytes,
+            bytes - value_size as isize
+        );
+    }
+}
@@ -0,0 +1,12 @@
+fn main() {
+    // the gRPC front end is generated from its service definition, with a vendored protoc
+    // so that building it needs no protoc installed
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this host"),
+        );
+        tonic_build::compile_protos("proto/pbft.proto").expect("proto/pbft.proto compiles");
+    }
+}
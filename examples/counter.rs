@@ -0,0 +1,59 @@
+//! Replicated counter: a client increments a counter stored in a cluster of four replicas
+//! running in this process, while one of the replicas is cut off from the others and later
+//! reconnects. Run with `cargo run --example counter`
+
+use std::net::SocketAddr;
+
+use pbft::config::Config;
+use pbft::sim::{LinkConfig, Network, Simulation};
+use pbft::{Key, Value};
+
+const NUM_INCREMENTS: u32 = 10;
+
+fn addr_of(id: usize) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))
+}
+
+#[tokio::main]
+async fn main() {
+    // only warnings of the replicas are logged, unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(1, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+    let counter = Key::from("counter");
+
+    for increment in 1..=NUM_INCREMENTS {
+        if increment == 4 {
+            // f = 1 replica may fail, the other three still form a quorum
+            println!("cutting off replica 3");
+            sim.network.partition(&[3]);
+        }
+        if increment == 8 {
+            println!("reconnecting replica 3");
+            sim.network.heal();
+        }
+        // the client is the only writer, so reading and then writing the counter is safe.
+        // The counter is stored as decimal text
+        let current: u32 = client
+            .get(counter.clone())
+            .await
+            .expect("read timed out")
+            .value
+            .map_or(0, |value| {
+                value.to_string().parse().expect("counter is a number")
+            });
+        client
+            .put(counter.clone(), Value::from((current + 1).to_string()))
+            .await
+            .expect("increment timed out");
+        println!("counter = {}", current + 1);
+    }
+
+    let response = client.get(counter).await.expect("read timed out");
+    assert_eq!(
+        response.value,
+        Some(Value::from(NUM_INCREMENTS.to_string()))
+    );
+    println!("the replicas agree the counter is {}", NUM_INCREMENTS);
+}
@@ -0,0 +1,99 @@
+//! Inventory: orders take several items out of stock at once, applied as atomic batches by
+//! a cluster of four replicas running in this process. Orders which exceed the stock are
+//! refused, and an order placed while the primary is cut off goes through once the other
+//! replicas replace it. Run with `cargo run --example inventory`
+
+use std::net::SocketAddr;
+
+use pbft::config::Config;
+use pbft::messages::BatchOp;
+use pbft::sim::{LinkConfig, Network, SimClient, Simulation};
+use pbft::{Key, Value};
+
+fn addr_of(id: usize) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))
+}
+
+fn stock_key(item: &str) -> Key {
+    Key::from(format!("stock:{}", item).as_str())
+}
+
+/// Quantities are stored as decimal text
+fn quantity(quantity: u32) -> Value {
+    Value::from(quantity.to_string())
+}
+
+async fn stock(client: &mut SimClient, item: &str) -> u32 {
+    client
+        .get(stock_key(item))
+        .await
+        .expect("read timed out")
+        .value
+        .map_or(0, |value| {
+            value.to_string().parse().expect("stock is a quantity")
+        })
+}
+
+/// Takes the items out of stock and records the order, all or nothing.
+/// Returns false if there is not enough stock
+async fn place_order(client: &mut SimClient, order_id: u32, items: &[(&str, u32)]) -> bool {
+    let mut ops = Vec::new();
+    for (item, ordered) in items {
+        let in_stock = stock(client, item).await;
+        if in_stock < *ordered {
+            return false;
+        }
+        ops.push(BatchOp::Put {
+            key: stock_key(item),
+            value: quantity(in_stock - ordered),
+        });
+    }
+    ops.push(BatchOp::Put {
+        key: Key::from(format!("order:{}", order_id).as_str()),
+        value: quantity(items.iter().map(|(_, ordered)| ordered).sum()),
+    });
+    let response = client.batch(ops).await.expect("order timed out");
+    response.success
+}
+
+#[tokio::main]
+async fn main() {
+    // only warnings of the replicas are logged, unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(2, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+
+    let restock = vec![
+        BatchOp::Put {
+            key: stock_key("widget"),
+            value: quantity(10),
+        },
+        BatchOp::Put {
+            key: stock_key("gadget"),
+            value: quantity(4),
+        },
+    ];
+    assert!(
+        client
+            .batch(restock)
+            .await
+            .expect("restock timed out")
+            .success
+    );
+    println!("stocked 10 widgets and 4 gadgets");
+
+    assert!(place_order(&mut client, 1, &[("widget", 3), ("gadget", 2)]).await);
+    println!("order 1: 3 widgets and 2 gadgets");
+    assert!(!place_order(&mut client, 2, &[("widget", 1), ("gadget", 5)]).await);
+    println!("order 2 refused: only 2 gadgets left");
+
+    println!("cutting off the primary, replica 0");
+    sim.network.partition(&[0]);
+    assert!(place_order(&mut client, 3, &[("widget", 7)]).await);
+    println!("order 3: 7 widgets, after the replicas moved to a new primary");
+
+    assert_eq!(stock(&mut client, "widget").await, 0);
+    assert_eq!(stock(&mut client, "gadget").await, 2);
+    println!("0 widgets and 2 gadgets left");
+}
@@ -0,0 +1,180 @@
+//! Distributed lock: two clients take turns holding a lock stored in a cluster of four
+//! replicas listening on localhost. A client takes the lock with a compare-and-swap which
+//! only succeeds while nobody holds it, and a client which finds it taken waits for the key
+//! to change over the watch streams of the replicas, rather than polling. The primary the
+//! replicas follow is watched too. Run with `cargo run --example lock`
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::client::PbftClient;
+use pbft::codec::MessageReader;
+use pbft::config::Config;
+use pbft::consensus::Consensus;
+use pbft::keystore::Keystore;
+use pbft::messages::{FailureReason, Message, Operation, WatchLeader};
+use pbft::node::Node;
+use pbft::{Key, NodeId, Value};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout};
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7500 + id as u16))
+}
+
+/// Starts the replica with the node and consensus engine wired together as `pbft_node` does
+fn start_replica(id: NodeId, config: &Config, keystore: &Keystore) {
+    let (tx_consensus, rx_consensus) = channel(config.consensus_queue_capacity);
+    let (tx_node, rx_node) = channel(config.node_queue_capacity);
+    let mut node = Node::new(
+        id,
+        config.clone(),
+        keystore,
+        rx_node,
+        tx_consensus.clone(),
+        tx_node.clone(),
+    );
+    let mut consensus = Consensus::new(
+        id,
+        config.clone(),
+        keystore,
+        rx_consensus,
+        tx_consensus,
+        tx_node,
+        node.inner.peer_pub_keys.clone(),
+    );
+    node.inner.rx_backpressure = consensus.subscribe_backpressure();
+    node.inner.rx_stable_seq_num = consensus.subscribe_stable_seq_num();
+    node.inner.rx_status = consensus.subscribe_status();
+    node.inner.observers = consensus.observers();
+    node.inner.pipeline = consensus.pipeline();
+    // the progress of the keys clients wait on
+    node.inner.key_versions = consensus.key_versions();
+    tokio::spawn(async move { node.spawn().await });
+    tokio::spawn(async move { consensus.spawn().await });
+}
+
+/// Prints the primary the replica follows, and again whenever it moves to another view
+async fn watch_leader(id: NodeId) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(addr_of(id)).await?;
+    let message = Message::WatchLeaderMessage(WatchLeader {});
+    stream.write_all(message.serialize().as_slice()).await?;
+    let mut reader = MessageReader::new(&mut stream);
+    while let Ok(Some(message)) = reader.read().await {
+        if let Message::LeaderMessage(leader) = message {
+            println!(
+                "replica {} follows primary {} in view {}",
+                leader.id, leader.primary, leader.view
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Takes the lock for the holder if nobody holds it. Returns the holder it found otherwise,
+/// with the sequence number the attempt was committed at
+async fn try_lock(client: &PbftClient, lock: &Key, holder: &str) -> Result<(), (Value, usize)> {
+    let certificate = client
+        .execute(
+            lock.clone(),
+            Operation::Cas {
+                expected: None,
+                new: Value::from(holder),
+            },
+        )
+        .await
+        .expect("lock request failed");
+    let response = certificate.response();
+    match response.reason {
+        None => Ok(()),
+        Some(FailureReason::UnexpectedValue) => Err((
+            response.previous.clone().expect("the lock is held"),
+            response.seq_num,
+        )),
+        Some(reason) => panic!("lock request rejected: {:?}", reason),
+    }
+}
+
+/// Releases the lock. Only its holder deletes the key, so it does not need to be conditional
+async fn unlock(client: &PbftClient, lock: &Key) {
+    let certificate = client
+        .execute(lock.clone(), Operation::Delete)
+        .await
+        .expect("unlock request failed");
+    assert_eq!(certificate.response().reason, None);
+}
+
+async fn start_client(config: &Config, port: u16) -> PbftClient {
+    let client = PbftClient::new(config, SocketAddr::from(([127, 0, 0, 1], port)));
+    let runner = client.clone();
+    tokio::spawn(async move { runner.run().await });
+    client
+}
+
+#[tokio::main]
+async fn main() {
+    // only warnings of the replicas are logged, unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let keystores: Vec<Keystore> = (0..4).map(|_| Keystore::generate()).collect();
+    let mut config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    config.peer_pub_keys = keystores
+        .iter()
+        .enumerate()
+        .map(|(id, keystore)| (id, keystore.public_key()))
+        .collect::<HashMap<_, _>>();
+    for (id, keystore) in keystores.iter().enumerate() {
+        start_replica(id, &config, keystore);
+    }
+    sleep(Duration::from_millis(300)).await;
+    tokio::spawn(watch_leader(1));
+
+    let alice = start_client(&config, 7510).await;
+    let bob = start_client(&config, 7511).await;
+    let lock = Key::from("lock");
+
+    try_lock(&alice, &lock, "alice")
+        .await
+        .expect("the lock is free");
+    println!("alice holds the lock");
+
+    let (holder, seq_num) = try_lock(&bob, &lock, "bob")
+        .await
+        .expect_err("alice holds the lock");
+    assert_eq!(holder, Value::from("alice"));
+    println!("bob finds the lock held by {}, and waits for it", holder);
+    // every request on the key, even a rejected one, is a new version of it, so the lock
+    // changes once f + 1 replicas report a version after bob's attempt
+    let waiter = bob.clone();
+    let wait_key = lock.clone();
+    let released =
+        tokio::spawn(async move { waiter.wait_for_key_version(wait_key, seq_num + 1).await });
+
+    sleep(Duration::from_millis(500)).await;
+    assert!(!released.is_finished());
+    unlock(&alice, &lock).await;
+    println!("alice releases the lock");
+
+    let certificate = timeout(Duration::from_secs(10), released)
+        .await
+        .expect("the release was not reported in time")
+        .unwrap();
+    println!(
+        "replicas {:?} report the lock changed",
+        certificate
+            .reports
+            .iter()
+            .map(|report| report.id)
+            .collect::<Vec<_>>()
+    );
+    try_lock(&bob, &lock, "bob")
+        .await
+        .expect("alice released the lock");
+    println!("bob holds the lock");
+
+    assert_eq!(bob.get(lock).await.unwrap(), Some(Value::from("bob")));
+    println!("the replicas agree bob holds the lock");
+}
@@ -0,0 +1,135 @@
+use crate::crypto::{self, SigningInput};
+use crate::keystore::Keystore;
+use crate::transport::hmac_sha256;
+use crate::NodeId;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{ExpandedSecretKey, PublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// How replicas authenticate the prepares and commits they send each other. View changes,
+/// new views, checkpoints and pre-prepares are always signed, as they are relayed to
+/// replicas other than those they were sent to. Every replica of the cluster must use the
+/// same mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Authentication {
+    /// Votes are signed with the identity key of the replica
+    #[default]
+    Signatures,
+    /// Votes carry a MAC for every replica, computed with the key the sender shares with
+    /// that replica, which is much cheaper than a signature
+    Authenticators,
+}
+
+/// MACs of a vote, one for each replica the sender knew the key of, by replica id
+pub type AuthenticatorVector = BTreeMap<NodeId, [u8; 32]>;
+
+/// Key shared with each peer, with the public key of the peer it was derived for
+type PairwiseKeys = HashMap<NodeId, (PublicKey, [u8; 32])>;
+
+/// Computes and checks the authenticators of the votes of this replica. The key shared
+/// with a peer is derived from an X25519 exchange between the identity keys of both
+/// replicas (converted from ed25519) once the key of the peer is learned, usually from the
+/// identifier it sends when it connects. As the key only changes with the identity keys,
+/// a prepare relayed in a view change can still be checked by every replica against the
+/// MAC meant for it. Cheap to clone, all clones share the derived keys
+#[derive(Clone)]
+pub struct Authenticator {
+    id: NodeId,
+    keystore: Keystore,
+    pairwise_keys: Arc<Mutex<PairwiseKeys>>,
+}
+
+impl Authenticator {
+    pub fn new(id: NodeId, keystore: &Keystore) -> Self {
+        Self {
+            id,
+            keystore: keystore.clone(),
+            pairwise_keys: Arc::default(),
+        }
+    }
+
+    /// MACs of the input for every replica whose key is known, including this one
+    pub fn authenticate(
+        &self,
+        input: &SigningInput,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+    ) -> AuthenticatorVector {
+        pub_keys
+            .iter()
+            .filter_map(|(peer_id, pub_key)| {
+                let key = self.pairwise_key(*peer_id, pub_key)?;
+                Some((*peer_id, mac(&key, self.id, input)))
+            })
+            .collect()
+    }
+
+    /// Whether the vector carries a valid MAC of the input from the sender for this replica
+    pub fn verify(
+        &self,
+        sender_id: NodeId,
+        sender_key: &PublicKey,
+        input: &SigningInput,
+        authenticator: &AuthenticatorVector,
+    ) -> bool {
+        let (Some(key), Some(tag)) = (
+            self.pairwise_key(sender_id, sender_key),
+            authenticator.get(&self.id),
+        ) else {
+            return false;
+        };
+        // compare in constant time
+        mac(&key, sender_id, input)
+            .iter()
+            .zip(tag.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+
+    /// Key shared with the peer, derived again if the peer changed its key.
+    /// None if the public key is not a valid curve point
+    fn pairwise_key(&self, peer_id: NodeId, peer_key: &PublicKey) -> Option<[u8; 32]> {
+        let mut pairwise_keys = self.pairwise_keys.lock().unwrap();
+        if let Some((derived_for, key)) = pairwise_keys.get(&peer_id) {
+            if derived_for == peer_key {
+                return Some(*key);
+            }
+        }
+        let peer_point = CompressedEdwardsY(peer_key.to_bytes())
+            .decompress()?
+            .to_montgomery();
+        let secret = ExpandedSecretKey::from(&self.keystore.keypair()?.secret).to_bytes();
+        let shared = peer_point * Scalar::from_bits(secret[..32].try_into().unwrap());
+
+        // both replicas derive the same key, whichever of them is the sender
+        let own_key = self.keystore.public_key();
+        let (low, high) = if (self.id, own_key.as_bytes()) < (peer_id, peer_key.as_bytes()) {
+            ((self.id, own_key), (peer_id, *peer_key))
+        } else {
+            ((peer_id, *peer_key), (self.id, own_key))
+        };
+        let key: [u8; 32] = Sha256::new()
+            .chain_update(b"pbft authenticator")
+            .chain_update(shared.as_bytes())
+            .chain_update(crypto::encode_usize(low.0))
+            .chain_update(low.1.as_bytes())
+            .chain_update(crypto::encode_usize(high.0))
+            .chain_update(high.1.as_bytes())
+            .finalize()
+            .into();
+        pairwise_keys.insert(peer_id, (*peer_key, key));
+        Some(key)
+    }
+}
+
+/// The key is shared by both replicas, so the MAC covers the sender,
+/// which keeps a replica from passing its own vote off as the vote of the other
+fn mac(key: &[u8; 32], sender_id: NodeId, input: &SigningInput) -> [u8; 32] {
+    hmac_sha256(key, &[&crypto::encode_usize(sender_id), input.as_bytes()])
+}
@@ -0,0 +1,143 @@
+use crate::messages::{CheckPoint, ClientIdentity};
+use crate::state::StateSnapshot;
+use crate::{Key, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Durable copy of the application state at the last stable checkpoint, together with the
+/// proof of the checkpoint. A replica which restarts starts from it and only replays the
+/// commits after it from the write-ahead log, which then no longer holds a snapshot of the store
+pub trait StateBackend: Send + Sync {
+    /// Stable checkpoint the backend holds the state of, with its proof, if any
+    fn load(&self) -> io::Result<Option<(StateSnapshot, Vec<CheckPoint>)>>;
+
+    /// Sequence number of the stable checkpoint the backend holds, if any
+    fn stable_seq_num(&self) -> Option<usize>;
+
+    /// Moves the backend to the stable checkpoint of the snapshot, writing the entries of the
+    /// keys written since the one it holds, or every entry if these are not known. The state
+    /// of the checkpoint is durable once this returns, and replaces the previous one atomically
+    fn persist(
+        &mut self,
+        snapshot: &StateSnapshot,
+        proof: &[CheckPoint],
+        written: Option<&BTreeSet<Key>>,
+    ) -> io::Result<()>;
+}
+
+/// Entries are stored under their key behind this prefix, next to the checkpoint record
+const ENTRY_PREFIX: &[u8] = b"entry/";
+const CHECKPOINT_KEY: &[u8] = b"checkpoint";
+
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    value: Value,
+    owner: Option<ClientIdentity>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredCheckpoint {
+    committed_seq_num: usize,
+    proof: Vec<CheckPoint>,
+}
+
+/// State backend on a sled database in a directory of its own
+pub struct SledBackend {
+    db: sled::Db,
+    stable_seq_num: Option<usize>,
+}
+
+impl SledBackend {
+    /// Opens the database in the directory, creating it if needed
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        let stable_seq_num = match db.get(CHECKPOINT_KEY)? {
+            Some(record) => {
+                Some(serde_json::from_slice::<StoredCheckpoint>(&record)?.committed_seq_num)
+            }
+            None => None,
+        };
+        Ok(Self { db, stable_seq_num })
+    }
+}
+
+impl StateBackend for SledBackend {
+    fn load(&self) -> io::Result<Option<(StateSnapshot, Vec<CheckPoint>)>> {
+        let checkpoint: StoredCheckpoint = match self.db.get(CHECKPOINT_KEY)? {
+            Some(record) => serde_json::from_slice(&record)?,
+            None => return Ok(None),
+        };
+        let mut store = BTreeMap::new();
+        let mut key_owners = BTreeMap::new();
+        for item in self.db.scan_prefix(ENTRY_PREFIX) {
+            let (key, entry) = item?;
+            let key = Key::from(&key[ENTRY_PREFIX.len()..]);
+            let entry: StoredEntry = serde_json::from_slice(&entry)?;
+            if let Some(owner) = entry.owner {
+                key_owners.insert(key.clone(), owner);
+            }
+            store.insert(key, entry.value);
+        }
+        let snapshot = StateSnapshot {
+            committed_seq_num: checkpoint.committed_seq_num,
+            store,
+            key_owners,
+        };
+        Ok(Some((snapshot, checkpoint.proof)))
+    }
+
+    fn stable_seq_num(&self) -> Option<usize> {
+        self.stable_seq_num
+    }
+
+    fn persist(
+        &mut self,
+        snapshot: &StateSnapshot,
+        proof: &[CheckPoint],
+        written: Option<&BTreeSet<Key>>,
+    ) -> io::Result<()> {
+        let entry_key = |key: &Key| [ENTRY_PREFIX, key.as_bytes()].concat();
+        let mut batch = sled::Batch::default();
+        let keys: Box<dyn Iterator<Item = &Key>> = match written {
+            Some(written) => Box::new(written.iter()),
+            None => {
+                // entries of keys no longer in the store are removed too
+                for item in self.db.scan_prefix(ENTRY_PREFIX).keys() {
+                    let key = item?;
+                    if !snapshot
+                        .store
+                        .contains_key(&Key::from(&key[ENTRY_PREFIX.len()..]))
+                    {
+                        batch.remove(key);
+                    }
+                }
+                Box::new(snapshot.store.keys())
+            }
+        };
+        for key in keys {
+            match snapshot.store.get(key) {
+                Some(value) => {
+                    let entry = StoredEntry {
+                        value: value.clone(),
+                        owner: snapshot.key_owners.get(key).copied(),
+                    };
+                    batch.insert(entry_key(key), serde_json::to_vec(&entry)?);
+                }
+                None => batch.remove(entry_key(key)),
+            }
+        }
+        let checkpoint = StoredCheckpoint {
+            committed_seq_num: snapshot.committed_seq_num,
+            proof: proof.to_vec(),
+        };
+        batch.insert(CHECKPOINT_KEY, serde_json::to_vec(&checkpoint)?);
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        self.stable_seq_num = Some(snapshot.committed_seq_num);
+        Ok(())
+    }
+}
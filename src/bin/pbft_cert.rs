@@ -0,0 +1,103 @@
+use pbft::keys::{decode_hex, decode_keypair, encode_hex, GeneratedKeyProvider, KeyProvider};
+use pbft::keystore::Keystore;
+use pbft::membership::{MembershipChange, Reconfiguration};
+use pbft::pki::{self, IdentityCertificate};
+use pbft::registry::{ClusterRegistry, RegistryUpdate};
+use pbft::NodeId;
+
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use ed25519_dalek::{Keypair, PublicKey};
+
+/// Manages the root key of a cluster and the identity certificates it issues to replicas.
+/// `root` generates a root key, writing it to the file and printing its public key, which
+/// nodes are given with `--root-key` or `"root_pub_key"` in a config file. `issue` prints the
+/// certificate of the node key for the given number of days, which the node presents with
+/// `--certificate`. `check` verifies a certificate against the root public key.
+/// `registry-update` prints the update of the cluster registry to the given contents, signed
+/// by the root key, which clients submit with `update-registry`. Its version must follow the
+/// version of the registry. `add-node` and `remove-node` print the reconfiguration moving
+/// the cluster to the given epoch, which must follow the current one, signed by the root key.
+/// Clients submit it with `update-registry` too.
+///
+/// Usage: pbft_cert root [root_key_path]
+///        pbft_cert issue [root_key_path] [id] [node_pub_key] [days]
+///        pbft_cert check [certificate_path] [root_pub_key]
+///        pbft_cert registry-update [root_key_path] [registry_path]
+///        pbft_cert add-node [root_key_path] [epoch] [id] [addr] [node_pub_key]
+///        pbft_cert remove-node [root_key_path] [epoch] [id]
+fn main() -> pbft::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("root") => {
+            let keypair_bytes = GeneratedKeyProvider.keypair_bytes()?;
+            std::fs::write(&args[2], encode_hex(&keypair_bytes[..32]))?;
+            let keypair = Keypair::from_bytes(&keypair_bytes)?;
+            println!("{}", encode_hex(keypair.public.as_bytes()));
+        }
+        Some("issue") => {
+            let root_keypair_bytes = decode_keypair(&std::fs::read_to_string(&args[2])?)?;
+            let id = args[3].parse::<NodeId>()?;
+            let pub_key = PublicKey::from_bytes(&decode_hex(&args[4])?)?;
+            let days = args[5].parse::<u64>()?;
+            let not_before = pki::unix_time();
+            let certificate = IdentityCertificate::issue(
+                &root_keypair_bytes,
+                id,
+                &pub_key,
+                not_before,
+                not_before + days * 24 * 60 * 60,
+            );
+            println!("{}", serde_json::to_string_pretty(&certificate)?);
+        }
+        Some("check") => {
+            let certificate = IdentityCertificate::read(Path::new(&args[2]))?;
+            let root_pub_key = PublicKey::from_bytes(&decode_hex(&args[3])?)?;
+            match certificate.verify(&root_pub_key, pki::unix_time()) {
+                Ok(_) => println!(
+                    "certificate of node {} is valid for {} more seconds",
+                    certificate.id,
+                    certificate.not_after - pki::unix_time()
+                ),
+                Err(e) => {
+                    println!("certificate of node {} is not valid: {}", certificate.id, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("registry-update") => {
+            let root_keystore =
+                Keystore::from_bytes(&decode_keypair(&std::fs::read_to_string(&args[2])?)?)?;
+            let registry: ClusterRegistry =
+                serde_json::from_str(&std::fs::read_to_string(&args[3])?)?;
+            let update = RegistryUpdate::new_with_signature(&root_keystore, registry);
+            println!("{}", serde_json::to_string_pretty(&update)?);
+        }
+        Some(command @ ("add-node" | "remove-node")) => {
+            let root_keystore =
+                Keystore::from_bytes(&decode_keypair(&std::fs::read_to_string(&args[2])?)?)?;
+            let epoch = args[3].parse::<usize>()?;
+            let id = args[4].parse::<NodeId>()?;
+            let change = if command == "add-node" {
+                PublicKey::from_bytes(&decode_hex(&args[6])?)?;
+                MembershipChange::AddNode {
+                    id,
+                    addr: args[5].parse::<SocketAddr>()?,
+                    pub_key: args[6].clone(),
+                }
+            } else {
+                MembershipChange::RemoveNode { id }
+            };
+            let reconfiguration =
+                Reconfiguration::new_with_signature(&root_keystore, epoch, change);
+            println!("{}", serde_json::to_string_pretty(&reconfiguration)?);
+        }
+        _ => {
+            eprintln!("Usage: pbft_cert root|issue|check|registry-update|add-node|remove-node ...");
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
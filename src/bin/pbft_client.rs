@@ -1,123 +1,140 @@
-use pbft::messages::{ClientRequest, ClientResponse, Message};
-use pbft::{Key, NodeId, Value};
+use pbft::client::{ClientError, PbftClient, VoteCertificate};
+use pbft::config::Config;
+use pbft::keys::{read_pub_keys, FileKeyProvider, KeyProvider};
+use pbft::messages::{BatchOp, FailureReason, Operation};
+use pbft::registry::{read_snapshot, registry_key};
+use pbft::{ClientId, Key, NodeId, Value};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::env;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
+use ed25519_dalek::Keypair;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::time::sleep;
-use tokio::{net::TcpListener, net::TcpStream};
-
-#[derive(Clone)]
-pub struct Client {
-    peer_addrs: HashMap<usize, SocketAddr>,
-    listen_addr: SocketAddr,
-    vote_counter: VoteCounter,
-    timestamp: usize,
-}
-
-#[derive(Clone)]
-pub struct VoteCounter {
-    pub success_vote_quorum: Arc<Mutex<HashMap<usize, HashSet<NodeId>>>>,
-    pub votes: Arc<Mutex<HashMap<(usize, usize), ClientResponse>>>,
-    pub tx_client: Sender<VoteCertificate>,
-    pub vote_threshold: usize,
-}
-
-#[derive(Clone)]
-pub struct VoteCertificate {
-    timestamp: usize,
-    votes: Vec<ClientResponse>,
-}
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     // note that the client only needs f + 1 replies before accepting
     let args: Vec<String> = env::args().collect();
     let mut index = 1;
-    let num_nodes = args[index].parse::<usize>().unwrap();
-    let mut peer_addrs = HashMap::new();
-    index += 1;
-    for id in 0..num_nodes {
-        let addr = args[index].clone();
-        peer_addrs.insert(id, SocketAddr::from_str(addr.as_str()).unwrap());
+    // the cluster is given either as a config file or as the number of nodes and their addresses
+    let config = if args[index] == "--config" {
+        index += 2;
+        Config::from_file(Path::new(&args[index - 1]))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+    } else {
+        let num_nodes = args[index].parse::<usize>().unwrap();
+        let mut peer_addrs = HashMap::new();
         index += 1;
-    }
+        for id in 0..num_nodes {
+            let addr = args[index].clone();
+            peer_addrs.insert(id, SocketAddr::from_str(addr.as_str()).unwrap());
+            index += 1;
+        }
+        Config::new(peer_addrs)
+    };
     let me_addr = SocketAddr::from_str(args[index].clone().as_str()).unwrap();
     index += 1;
 
-    println!("pBFT Client. Listening for reponses on {:?}. Ready for commands...", me_addr);
+    println!(
+        "pBFT Client. Listening for reponses on {:?}. Ready for commands...",
+        me_addr
+    );
 
     let mut client_mode = true;
     let mut interval_millis: usize = 0;
-    if index < args.len() {
+    let mut relays = Vec::new();
+    let mut pub_keys = config.peer_pub_keys.clone();
+    let mut request_timeout = None;
+    let mut max_retries = None;
+    let mut registry = None;
+    let mut identity: Option<(ClientId, Vec<u8>)> = None;
+    while index < args.len() {
         let flag = args[index].clone();
         index += 1;
         if flag.as_str().eq("test") {
             client_mode = false;
             interval_millis = args[index].clone().parse::<usize>().unwrap();
+            index += 1;
+        } else if flag.as_str().eq("relay") {
+            // submit all requests through a single replica, or through the first healthy
+            // replica of a comma separated list
+            relays = args[index]
+                .split(',')
+                .map(|relay_id| relay_id.parse::<NodeId>().unwrap())
+                .collect();
+            index += 1;
+        } else if flag.as_str().eq("pub-keys") {
+            // public keys of the nodes, needed to verify key proofs
+            pub_keys = read_pub_keys(Path::new(&args[index])).unwrap();
+            index += 1;
+        } else if flag.as_str().eq("registry") {
+            // trust the keys of the registry snapshot exported by a replica
+            let (_, snapshot_registry) = read_snapshot(Path::new(&args[index])).unwrap();
+            registry = Some(snapshot_registry);
+            index += 1;
+        } else if flag.as_str().eq("timeout") {
+            // milliseconds the replicas have to agree before a request is sent to all of them
+            request_timeout = Some(Duration::from_millis(args[index].parse::<u64>().unwrap()));
+            index += 1;
+        } else if flag.as_str().eq("retries") {
+            max_retries = Some(args[index].parse::<usize>().unwrap());
+            index += 1;
+        } else if flag.as_str().eq("identity") {
+            // sign requests as the client with the id, by the hex encoded keypair in the file
+            let key_provider = FileKeyProvider {
+                path: args[index + 1].clone().into(),
+            };
+            let keypair_bytes = key_provider
+                .keypair_bytes()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            identity = Some((args[index].parse::<ClientId>().unwrap(), keypair_bytes));
+            index += 2;
         }
     }
 
-    let (tx_client, mut rx_client) = tokio::sync::mpsc::channel(32);
-
-    let num_faulty = (num_nodes - 1) / 3;
-
-    let vote_counter = VoteCounter {
-        success_vote_quorum: Arc::new(Mutex::new(HashMap::new())),
-        votes: Arc::new(Mutex::new(HashMap::new())),
-        tx_client,
-        vote_threshold: num_faulty + 1, /* number of faulty processes. We need to exceed this value */
-    };
-
-    let outer_client = Client {
-        peer_addrs,
-        listen_addr: me_addr,
-        vote_counter,
-        timestamp: 0,
-    };
-
-    // future listening for vote count results from the client
-    let vote_count_fut = tokio::spawn(async move {
-        let mut succ_votes = HashMap::<usize, VoteCertificate>::new();
-
-        loop {
-            let vote_certificate = rx_client.recv().await.unwrap();
-            if succ_votes.contains_key(&vote_certificate.timestamp) {
-                continue;
-            }
-            succ_votes.insert(vote_certificate.timestamp, vote_certificate.clone());
-            println!("**********************");
-            println!("**********************");
-            println!(
-                "Got enough votes for request with timestamp {}. Value: {:?}. VOTES: {:?}",
-                vote_certificate.timestamp, vote_certificate.votes.get(0).unwrap().value, vote_certificate.votes
-            );
-            println!("**********************");
-            println!("**********************");
-        }
-    });
+    let mut client = PbftClient::new(&config, me_addr)
+        .with_relays(relays)
+        .with_pub_keys(pub_keys);
+    if let Some(registry) = registry.as_ref() {
+        client = client.with_registry(registry);
+    }
+    if let Some(request_timeout) = request_timeout {
+        client.request_timeout = request_timeout;
+    }
+    if let Some(max_retries) = max_retries {
+        client.max_retries = max_retries;
+    }
+    if let Some((client_id, keypair_bytes)) = identity.as_ref() {
+        client = client.with_identity(*client_id, Keypair::from_bytes(keypair_bytes).unwrap());
+    }
 
     // message sending logic which can be changed for new tests
-    let mut client = outer_client.clone();
+    let test_client = client.clone();
     let send_fut = async move {
-        loop {
-            client
-                .issue_set(String::from("abc"), client.timestamp as u32)
-                .await;
-            sleep(std::time::Duration::from_millis(interval_millis as u64)).await;
-            client.issue_get(String::from("abc")).await;
-            sleep(std::time::Duration::from_millis(interval_millis as u64)).await;
+        for round in 0.. {
+            let value = Value::from(round.to_string());
+            report(&test_client, |client| async move {
+                client
+                    .execute(Key::from("abc"), Operation::Set(value))
+                    .await
+            });
+            sleep(Duration::from_millis(interval_millis as u64)).await;
+            report(&test_client, |client| async move {
+                client.execute(Key::from("abc"), Operation::Get).await
+            });
+            sleep(Duration::from_millis(interval_millis as u64)).await;
         }
     };
 
-    let mut client = outer_client.clone();
+    let cli_client = client.clone();
     let read_cli = async move {
+        let client = cli_client;
         let mut reader = BufReader::new(tokio::io::stdin());
         loop {
             let mut line = String::new();
@@ -125,12 +142,156 @@ async fn main() -> std::io::Result<()> {
             let mut args_iter = line.split_ascii_whitespace();
 
             let cmd = args_iter.next().unwrap();
-            let key = args_iter.next().unwrap();
+            let key = args_iter.next().unwrap_or_default();
             if cmd.eq("set") {
-                let val = args_iter.next().unwrap().parse::<u32>().unwrap();
-                client.issue_set(key.to_string(), val).await;
+                let val = args_iter.next().unwrap().parse::<Value>().unwrap();
+                let key = key.parse::<Key>().unwrap();
+                report(&client, |client| async move {
+                    client.execute(key, Operation::Set(val)).await
+                });
             } else if cmd.eq("get") {
-                client.issue_get(key.to_string()).await;
+                let key = key.parse::<Key>().unwrap();
+                report(&client, |client| async move { client.read(key).await });
+            } else if cmd.eq("del") {
+                let key = key.parse::<Key>().unwrap();
+                report(&client, |client| async move {
+                    client.execute(key, Operation::Delete).await
+                });
+            } else if cmd.eq("cas") {
+                // e.g. "cas x 1 2", or "cas x - 2" to set x only if it is unset
+                let expected = match args_iter.next().unwrap() {
+                    "-" => None,
+                    expected => Some(expected.parse::<Value>().unwrap()),
+                };
+                let new = args_iter.next().unwrap().parse::<Value>().unwrap();
+                let key = key.parse::<Key>().unwrap();
+                report(&client, |client| async move {
+                    client.execute(key, Operation::Cas { expected, new }).await
+                });
+            } else if cmd.eq("batch") {
+                // e.g. "batch set x 1 del y set z 2"
+                let mut ops = std::iter::once(key).chain(args_iter);
+                let mut batch_ops = Vec::new();
+                while let Some(op) = ops.next() {
+                    let key = ops.next().unwrap().parse::<Key>().unwrap();
+                    match op {
+                        "set" => batch_ops.push(BatchOp::Put {
+                            key,
+                            value: ops.next().unwrap().parse::<Value>().unwrap(),
+                        }),
+                        "del" => batch_ops.push(BatchOp::Delete { key }),
+                        _ => {}
+                    }
+                }
+                report(
+                    &client,
+                    |client| async move { client.batch(batch_ops).await },
+                );
+            } else if cmd.eq("load") {
+                // e.g. "load data.txt", with a "key value" line for every put
+                let ops: Vec<BatchOp> = match std::fs::read_to_string(key) {
+                    Ok(data) => data
+                        .lines()
+                        .filter_map(|line| line.split_once(' '))
+                        .map(|(key, value)| BatchOp::Put {
+                            key: key.parse::<Key>().unwrap(),
+                            value: value.trim().parse::<Value>().unwrap(),
+                        })
+                        .collect(),
+                    Err(e) => {
+                        println!("Could not read {}: {}", key, e);
+                        continue;
+                    }
+                };
+                report(&client, |client| async move { client.bulk_load(ops).await });
+            } else if cmd.eq("proof") {
+                let key = key.parse::<Key>().unwrap();
+                let client = client.clone();
+                tokio::spawn(async move {
+                    match client.get_proof(key.clone()).await {
+                        Ok((key_proof, value)) => println!(
+                            "Verified proof from node {}: {} = {} at seq-num {}",
+                            key_proof.id, key_proof.key, value, key_proof.committed_seq_num
+                        ),
+                        Err(e) => println!("Could not verify proof for {}: {}", key, e),
+                    }
+                });
+            } else if cmd.eq("cert") {
+                let timestamp = key.parse::<usize>().unwrap();
+                match client.certificate(timestamp) {
+                    Some(certificate) => {
+                        println!("{}", serde_json::to_string(&certificate).unwrap())
+                    }
+                    None => println!("No certificate for request with timestamp {}", timestamp),
+                }
+            } else if cmd.eq("wait") {
+                // e.g. "wait 12" for sequence number 12, or "wait x 12" for version 12 of x
+                let client = client.clone();
+                let version = args_iter
+                    .next()
+                    .map(|version| version.parse::<usize>().unwrap());
+                let key = key.to_string();
+                tokio::spawn(async move {
+                    let certificate = match version {
+                        Some(version) => {
+                            let key = key.parse::<Key>().unwrap();
+                            client.wait_for_key_version(key, version).await
+                        }
+                        None => client.wait_for_seq(key.parse::<usize>().unwrap()).await,
+                    };
+                    let nodes: Vec<NodeId> =
+                        certificate.reports.iter().map(|report| report.id).collect();
+                    println!("Reached by nodes {:?}", nodes);
+                });
+            } else if cmd.eq("registry") {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    match client.registry().await {
+                        Ok((snapshot, registry)) => println!(
+                            "Verified registry at seq-num {}: {}",
+                            snapshot.committed_seq_num,
+                            serde_json::to_string(&registry).unwrap()
+                        ),
+                        Err(e) => println!("Could not verify the registry: {}", e),
+                    }
+                });
+            } else if cmd.eq("update-registry") {
+                // the update or reconfiguration signed by the root key, e.g. with pbft_cert
+                // registry-update or add-node
+                match std::fs::read(key) {
+                    Ok(update) => {
+                        let update = Value::from(update);
+                        report(&client, |client| async move {
+                            client.execute(registry_key(), Operation::Set(update)).await
+                        });
+                    }
+                    Err(e) => println!("Could not read {}: {}", key, e),
+                }
+            } else if cmd.eq("register") {
+                // register the public key of the identity of the client with the cluster
+                match identity.as_ref() {
+                    Some((client_id, keypair_bytes)) => {
+                        let client = client.clone();
+                        let client_id = *client_id;
+                        let keypair = Keypair::from_bytes(keypair_bytes).unwrap();
+                        tokio::spawn(async move {
+                            match client.register(client_id, keypair).await {
+                                Ok(_) => println!("Registered as client {}", client_id),
+                                Err(e) => println!("Could not register: {}", e),
+                            }
+                        });
+                    }
+                    None => println!("Start the client with identity [id] [key-file] first"),
+                }
+            } else if cmd.eq("pending") {
+                println!("Requests awaiting replies: {:?}", client.outstanding());
+            } else if cmd.eq("export") {
+                match client.export_certificates(Path::new(key)) {
+                    Ok(num_exported) => {
+                        println!("Exported {} certificates to {}", num_exported, key)
+                    }
+                    Err(e) => println!("Could not export certificates to {}: {}", key, e),
+                }
             }
         }
     };
@@ -138,115 +299,76 @@ async fn main() -> std::io::Result<()> {
     if client_mode {
         tokio::select! {
             _ = read_cli => {}
-            _ = outer_client.listen() => {}
-            _ = vote_count_fut => {}
+            res = client.run() => res?,
         }
     } else {
         tokio::select! {
             _ = send_fut => {}
-            _ = outer_client.listen() => {}
-            _ = vote_count_fut => {}
+            res = client.run() => res?,
         }
     }
 
     Ok(())
 }
 
-impl Client {
-    async fn listen(&self) {
-        let listener = TcpListener::bind(self.listen_addr).await.unwrap();
-        loop {
-            match listener.accept().await {
-                Ok((stream, _)) => {
-                    let mut vote_counter = self.vote_counter.clone();
-                    tokio::spawn(async move {
-                        let _ = vote_counter.read_response(stream).await;
-                    });
-                }
-                Err(e) => {
-                    println!("{:?}", e);
-                }
+/// Prints the outcome of the request once the replicas agreed on it,
+/// without holding up the next command
+fn report<F, R>(client: &PbftClient, request: F)
+where
+    F: FnOnce(PbftClient) -> R + Send + 'static,
+    R: Future<Output = Result<VoteCertificate, ClientError>> + Send,
+{
+    let client = client.clone();
+    tokio::spawn(async move {
+        let vote_certificate = match request(client).await {
+            Ok(vote_certificate) => vote_certificate,
+            Err(ClientError::ConflictingReplies {
+                timestamp,
+                evidence,
+            }) => {
+                println!(
+                    "ALERT: conflicting replies for request with timestamp {}. EVIDENCE: {:?}",
+                    timestamp, evidence
+                );
+                return;
             }
-        }
-    }
-
-    async fn broadcast_message(&self, message: Message) {
-        for (_, addr) in self.peer_addrs.iter() {
-            let node_stream = TcpStream::connect(addr).await;
-            if let Ok(mut stream) = node_stream {
-                let _bytes_written = stream.write(message.serialize().as_slice()).await;
-            }
-        }
-    }
-
-    async fn issue_set(&mut self, key: Key, value: Value) {
-        let set_message: Message = Message::ClientRequestMessage(ClientRequest {
-            respond_addr: self.listen_addr,
-            time_stamp: self.timestamp,
-            key,
-            value: Some(value),
-        });
-        self.timestamp += 1;
-        self.broadcast_message(set_message).await;
-    }
-
-    async fn issue_get(&mut self, key: Key) {
-        let get_message: Message = Message::ClientRequestMessage(ClientRequest {
-            respond_addr: self.listen_addr,
-            time_stamp: self.timestamp,
-            key,
-            value: None,
-        });
-        self.timestamp += 1;
-        self.broadcast_message(get_message).await;
-    }
-}
-impl VoteCounter {
-    async fn read_response(&mut self, mut stream: TcpStream) -> std::io::Result<()> {
-        let mut reader = BufReader::new(&mut stream);
-        let mut res = String::new();
-        let bytes_read = reader.read_line(&mut res).await.unwrap();
-        if bytes_read == 0 {
-            return Ok(());
-        }
-        let response: Message = serde_json::from_str(&res).unwrap();
-        let response = match response {
-            Message::ClientResponseMessage(response) => response,
-            _ => {
-                /* received a response which was not a client response, so just return */
-                return Ok(());
+            Err(e) => {
+                println!("Request failed [{}]: {}", e.code().name(), e);
+                return;
             }
         };
-
-        // if the response is not a success, then we drop it
-
-        if response.success {
-            let mut success_vote_quorum = self.success_vote_quorum.lock().await;
-            let mut votes = self.votes.lock().await;
-
-            if success_vote_quorum.get_mut(&response.time_stamp).is_none() {
-                success_vote_quorum.insert(response.time_stamp, HashSet::<NodeId>::new());
-            }
-
-            votes.insert((response.time_stamp, response.id), response.clone());
-            let curr_quorum = success_vote_quorum.get_mut(&response.time_stamp).unwrap();
-            curr_quorum.insert(response.id);
-            if curr_quorum.len() > self.vote_threshold {
-                // send message alerting enough votes
-                let mut succ_votes = Vec::<ClientResponse>::new();
-                for id in curr_quorum.iter() {
-                    succ_votes.push(votes.get(&(response.time_stamp, *id)).unwrap().clone());
-                }
-
-                let _ = self
-                    .tx_client
-                    .send(VoteCertificate {
-                        timestamp: response.time_stamp,
-                        votes: succ_votes,
-                    })
-                    .await;
+        let response = vote_certificate.response();
+        if let Some(reason) = response.reason {
+            println!(
+                "Request with timestamp {} was rejected [{}]: {}",
+                vote_certificate.timestamp,
+                reason.code().name(),
+                reason
+            );
+            if reason == FailureReason::UnexpectedValue {
+                println!("The value is {:?}", response.previous);
             }
+            return;
         }
-        Ok(())
-    }
+        println!("**********************");
+        println!("**********************");
+        if let Some(previous) = &response.previous {
+            println!(
+                "Got enough votes for request with timestamp {}. Previous value: {}. VOTES: {:?}",
+                vote_certificate.timestamp, previous, vote_certificate.votes
+            );
+        } else if response.results.is_empty() {
+            println!(
+                "Got enough votes for request with timestamp {}. Value: {:?}. VOTES: {:?}",
+                vote_certificate.timestamp, response.value, vote_certificate.votes
+            );
+        } else {
+            println!(
+                "Got enough votes for batch with timestamp {}. Previous values: {:?}. VOTES: {:?}",
+                vote_certificate.timestamp, response.results, vote_certificate.votes
+            );
+        }
+        println!("**********************");
+        println!("**********************");
+    });
 }
@@ -0,0 +1,723 @@
+use pbft::codec::MessageReader;
+use pbft::messages::{
+    CheckpointDiff, CompactLog, Drain, EvidenceReport, GetAt, GetDiff, GetEntry, GetEvidence,
+    Leader, Message, NodeStatus, StatusRequest, WatchLeader,
+};
+use pbft::{Key, NodeId};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::net::SocketAddr;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout};
+
+/// Operational commands for a live cluster.
+///
+/// `status` prints the status of every node.
+/// `pipeline` prints the messages each node queued for its consensus engine, by message type.
+/// `dead-letters` prints the responses each node gave up delivering to their clients.
+/// `connectivity` prints the round trips each node measured to its peers by pinging them,
+/// one row per node, with the peers whose connection the node takes for dead.
+/// `evidence` prints the evidence each node holds against replicas proven faulty, the replicas
+/// which vouched for it, and the view from which the node passes the faulty replica over as primary.
+/// `compact` has every node compact its write-ahead log now, even if it is busy.
+/// `watch-leader` prints the primary of the current view and of every view the cluster moves
+/// to, once f + 1 nodes announced it, so that a faulty node cannot redirect the watcher.
+/// `rolling-restart` restarts the nodes one at a time with the given command. After each restart
+/// it waits for the node to be ready (in the current view, not in a view change, and caught up
+/// past the sequence number committed before the restart) before moving on to the next node.
+/// It aborts if fewer than 2f + 1 nodes respond, as restarting a node could then stall the cluster.
+/// `drain` has a node stop taking client requests and finish the instances in flight (up to the
+/// target sequence number, if given), and waits until the node reports that it is safe to stop.
+/// `diff` prints the keys written after the stable checkpoint at `--from`, up to the one at
+/// `--to` (by default the latest one f + 1 nodes have), as the union of the diffs of f + 1 nodes
+/// so that a faulty node cannot leave a key out.
+/// `entry` prints the entry of the log of every node at the sequence number: the commit applied
+/// there and its request. `get-at` prints the value of the key every node had after applying
+/// the sequence number. Archive nodes answer for the whole history, other nodes only since their
+/// last stable checkpoint. The answers are not signed, so compare those of f + 1 nodes.
+///
+/// Usage: pbft_ctl n [addr_1] ... [addr_n] status
+///        pbft_ctl n [addr_1] ... [addr_n] pipeline
+///        pbft_ctl n [addr_1] ... [addr_n] dead-letters
+///        pbft_ctl n [addr_1] ... [addr_n] connectivity
+///        pbft_ctl n [addr_1] ... [addr_n] evidence
+///        pbft_ctl n [addr_1] ... [addr_n] compact
+///        pbft_ctl n [addr_1] ... [addr_n] watch-leader
+///        pbft_ctl n [addr_1] ... [addr_n] rolling-restart --restart-cmd "cmd {id}" [--ready-timeout secs]
+///        pbft_ctl n [addr_1] ... [addr_n] drain --node id [--target seq] [--ready-timeout secs]
+///        pbft_ctl n [addr_1] ... [addr_n] diff --from seq [--to seq]
+///        pbft_ctl n [addr_1] ... [addr_n] entry --seq seq
+///        pbft_ctl n [addr_1] ... [addr_n] get-at --key key --seq seq
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut index = 1;
+    let num_nodes = args[index].parse::<usize>().unwrap();
+    let mut peer_addrs = HashMap::new();
+    index += 1;
+    for id in 0..num_nodes {
+        let addr = args[index].clone();
+        peer_addrs.insert(id, SocketAddr::from_str(addr.as_str()).unwrap());
+        index += 1;
+    }
+    let cmd = args[index].clone();
+    index += 1;
+
+    let mut restart_cmd = None;
+    let mut ready_timeout = Duration::from_secs(120);
+    let mut node = None;
+    let mut target_seq_num = None;
+    let mut from_seq_num = None;
+    let mut to_seq_num = None;
+    let mut seq_num = None;
+    let mut key = None;
+    while index < args.len() {
+        let flag = args[index].clone();
+        index += 1;
+        match flag.as_str() {
+            "--restart-cmd" => {
+                restart_cmd = Some(args[index].clone());
+                index += 1;
+            }
+            "--ready-timeout" => {
+                ready_timeout = Duration::from_secs(args[index].parse::<u64>().unwrap());
+                index += 1;
+            }
+            "--node" => {
+                node = Some(args[index].parse::<NodeId>().unwrap());
+                index += 1;
+            }
+            "--target" => {
+                target_seq_num = Some(args[index].parse::<usize>().unwrap());
+                index += 1;
+            }
+            "--from" => {
+                from_seq_num = Some(args[index].parse::<usize>().unwrap());
+                index += 1;
+            }
+            "--to" => {
+                to_seq_num = Some(args[index].parse::<usize>().unwrap());
+                index += 1;
+            }
+            "--seq" => {
+                seq_num = Some(args[index].parse::<usize>().unwrap());
+                index += 1;
+            }
+            "--key" => {
+                key = Some(Key::from(args[index].as_str()));
+                index += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let ctl = Ctl {
+        num_faulty: (num_nodes - 1) / 3,
+        peer_addrs,
+        status_timeout: Duration::from_secs(2),
+        ready_timeout,
+    };
+
+    let res = match cmd.as_str() {
+        "status" => {
+            ctl.print_status().await;
+            Ok(())
+        }
+        "pipeline" => {
+            ctl.print_pipeline().await;
+            Ok(())
+        }
+        "dead-letters" => {
+            ctl.print_dead_letters().await;
+            Ok(())
+        }
+        "connectivity" => {
+            ctl.print_connectivity().await;
+            Ok(())
+        }
+        "evidence" => {
+            ctl.print_evidence().await;
+            Ok(())
+        }
+        "compact" => {
+            ctl.compact().await;
+            Ok(())
+        }
+        "watch-leader" => {
+            ctl.watch_leader().await;
+            Ok(())
+        }
+        "rolling-restart" => match restart_cmd {
+            Some(restart_cmd) => ctl.rolling_restart(&restart_cmd).await,
+            None => Err(String::from("rolling-restart needs --restart-cmd")),
+        },
+        "drain" => match node {
+            Some(id) => ctl.drain(id, target_seq_num).await,
+            None => Err(String::from("drain needs --node")),
+        },
+        "diff" => match from_seq_num {
+            Some(from_seq_num) => ctl.diff(from_seq_num, to_seq_num).await,
+            None => Err(String::from("diff needs --from")),
+        },
+        "entry" => match seq_num {
+            Some(seq_num) => {
+                ctl.print_entries(seq_num).await;
+                Ok(())
+            }
+            None => Err(String::from("entry needs --seq")),
+        },
+        "get-at" => match (key, seq_num) {
+            (Some(key), Some(seq_num)) => {
+                ctl.print_values_at(key, seq_num).await;
+                Ok(())
+            }
+            _ => Err(String::from("get-at needs --key and --seq")),
+        },
+        _ => Err(format!("unknown command {}", cmd)),
+    };
+    if let Err(e) = res {
+        println!("ABORTED: {}", e);
+        std::process::exit(1);
+    }
+}
+
+struct Ctl {
+    num_faulty: usize,
+    peer_addrs: HashMap<NodeId, SocketAddr>,
+    /// How long we wait for a node to answer a status request
+    status_timeout: Duration,
+    /// How long we wait for a restarted node to be ready
+    ready_timeout: Duration,
+}
+
+impl Ctl {
+    /// Status of the node, or None if it does not respond
+    async fn status(&self, id: NodeId) -> Option<NodeStatus> {
+        self.request_status(id, Message::StatusRequestMessage(StatusRequest {}))
+            .await
+    }
+
+    /// Sends the request to the node, which answers it with its status
+    async fn request_status(&self, id: NodeId, request: Message) -> Option<NodeStatus> {
+        let addr = *self.peer_addrs.get(&id)?;
+        let request = async move {
+            let mut stream = TcpStream::connect(addr).await.ok()?;
+            stream
+                .write_all(request.serialize().as_slice())
+                .await
+                .ok()?;
+            match MessageReader::new(stream).read().await.ok()?? {
+                Message::StatusMessage(status) => Some(*status),
+                _ => None,
+            }
+        };
+        timeout(self.status_timeout, request).await.ok().flatten()
+    }
+
+    /// Diff the node sends for the request, or None if it does not respond
+    async fn request_diff(&self, id: NodeId, get_diff: GetDiff) -> Option<CheckpointDiff> {
+        let addr = *self.peer_addrs.get(&id)?;
+        let request = async move {
+            let mut stream = TcpStream::connect(addr).await.ok()?;
+            let request = Message::GetDiffMessage(get_diff);
+            stream
+                .write_all(request.serialize().as_slice())
+                .await
+                .ok()?;
+            match MessageReader::new(stream).read().await.ok()?? {
+                Message::CheckpointDiffMessage(diff) if diff.id == id => Some(diff),
+                _ => None,
+            }
+        };
+        timeout(self.status_timeout, request).await.ok().flatten()
+    }
+
+    /// Evidence the node holds, or None if it does not respond
+    async fn request_evidence(&self, id: NodeId) -> Option<EvidenceReport> {
+        let addr = *self.peer_addrs.get(&id)?;
+        let request = async move {
+            let mut stream = TcpStream::connect(addr).await.ok()?;
+            let request = Message::GetEvidenceMessage(GetEvidence {});
+            stream
+                .write_all(request.serialize().as_slice())
+                .await
+                .ok()?;
+            match MessageReader::new(stream).read().await.ok()?? {
+                Message::EvidenceMessage(report) if report.id == id => Some(report),
+                _ => None,
+            }
+        };
+        timeout(self.status_timeout, request).await.ok().flatten()
+    }
+
+    /// Sends the query made for the address we listen on to the node, which answers it over
+    /// a connection of its own to that address, like it answers clients
+    async fn query(
+        &self,
+        id: NodeId,
+        query: impl FnOnce(SocketAddr) -> Message,
+    ) -> Option<Message> {
+        let addr = *self.peer_addrs.get(&id)?;
+        let request = async move {
+            let mut stream = TcpStream::connect(addr).await.ok()?;
+            // we listen on the interface the node is reachable from
+            let local_ip = stream.local_addr().ok()?.ip();
+            let listener = TcpListener::bind((local_ip, 0)).await.ok()?;
+            let request = query(listener.local_addr().ok()?);
+            stream
+                .write_all(request.serialize().as_slice())
+                .await
+                .ok()?;
+            let (answer, _) = listener.accept().await.ok()?;
+            MessageReader::new(answer).read().await.ok()?
+        };
+        timeout(self.status_timeout, request).await.ok().flatten()
+    }
+
+    /// Statuses of the nodes which respond, indexed by node id
+    async fn statuses(&self) -> HashMap<NodeId, NodeStatus> {
+        let mut statuses = HashMap::new();
+        for id in 0..self.peer_addrs.len() {
+            if let Some(status) = self.status(id).await {
+                statuses.insert(id, status);
+            }
+        }
+        statuses
+    }
+
+    async fn print_status(&self) {
+        for id in 0..self.peer_addrs.len() {
+            match self.status(id).await {
+                Some(status) => println!(
+                    "node {}: view {}{}, committed {}, stable {}, {} stale messages dropped, {} queued (high watermark {}), {} dropped from a full queue, {} malformed messages received, {} connections dropped for oversized frames and {} for slow reads, {} requests pending in the mempool ({} rejected), {} unverified messages dropped, {} shed unverified while overloaded, {} held for unreachable peers, clocks off by up to {} ms ({} past the alert threshold), suspected down: {:?}, protocol version {} with features [{}]",
+                    id,
+                    status.view,
+                    if status.in_view_change {
+                        " (in view change)"
+                    } else {
+                        ""
+                    },
+                    status.last_seq_num_committed,
+                    status.last_stable_seq_num,
+                    status.stale_messages_dropped,
+                    status.pipeline.depth,
+                    status.pipeline.high_watermark,
+                    status.pipeline.dropped(),
+                    status.malformed.total(),
+                    status.malformed.oversized_frames,
+                    status.malformed.slow_connections,
+                    status.mempool.pending,
+                    status.mempool.rejected,
+                    status.unverified_messages.values().sum::<usize>(),
+                    status.verification.total_shed(),
+                    status.outbox.total_held(),
+                    status.clock.max_skew_millis(),
+                    status.clock.skewed_peers.len(),
+                    status.suspected_nodes,
+                    status.capabilities.protocol_version,
+                    status.capabilities.features
+                ),
+                None => println!("node {}: not responding", id),
+            }
+        }
+    }
+
+    async fn print_pipeline(&self) {
+        for id in 0..self.peer_addrs.len() {
+            let status = match self.status(id).await {
+                Some(status) => status,
+                None => {
+                    println!("node {}: not responding", id);
+                    continue;
+                }
+            };
+            println!(
+                "node {}: {} of {} queued, high watermark {}, {} of {} queued to send, {} client requests rejected as busy",
+                id,
+                status.pipeline.depth,
+                status.pipeline.capacity,
+                status.pipeline.high_watermark,
+                status.pipeline.node_queue_depth,
+                status.pipeline.node_queue_capacity,
+                status.pipeline.busy_rejections
+            );
+            for (kind, counts) in status.pipeline.by_kind.iter() {
+                println!(
+                    "  {:<16} enqueued {:>8}  dequeued {:>8}  dropped {:>8}",
+                    kind, counts.enqueued, counts.dequeued, counts.dropped
+                );
+            }
+        }
+    }
+
+    async fn print_dead_letters(&self) {
+        for id in 0..self.peer_addrs.len() {
+            let status = match self.status(id).await {
+                Some(status) => status,
+                None => {
+                    println!("node {}: not responding", id);
+                    continue;
+                }
+            };
+            println!("node {}: {} dead letters", id, status.dead_letters.len());
+            for dead_letter in status.dead_letters.iter() {
+                println!(
+                    "  {:<21} time stamp {:>8}  {} attempts  {}",
+                    dead_letter.respond_addr,
+                    dead_letter.response.time_stamp,
+                    dead_letter.attempts,
+                    dead_letter.reason
+                );
+            }
+        }
+    }
+
+    /// Prints the connectivity matrix: the smoothed round trip from each node (row) to
+    /// each peer (column) in milliseconds, `down` for a peer the node takes for dead
+    /// and `-` where the node has not measured it yet
+    async fn print_connectivity(&self) {
+        let num_nodes = self.peer_addrs.len();
+        print!("{:>8}", "");
+        for peer_id in 0..num_nodes {
+            print!("{:>10}", format!("node {}", peer_id));
+        }
+        println!();
+        for id in 0..num_nodes {
+            print!("{:>8}", format!("node {}", id));
+            let status = match self.status(id).await {
+                Some(status) => status,
+                None => {
+                    println!("  not responding");
+                    continue;
+                }
+            };
+            for peer_id in 0..num_nodes {
+                let cell = match status.connectivity.peers.get(&peer_id) {
+                    _ if peer_id == id => String::new(),
+                    Some(link) if link.is_down => String::from("down"),
+                    Some(link) => match link.rtt_micros {
+                        Some(rtt_micros) => format!("{:.1}", rtt_micros as f64 / 1000.0),
+                        None => String::from("-"),
+                    },
+                    None => String::from("-"),
+                };
+                print!("{:>10}", cell);
+            }
+            println!();
+        }
+    }
+
+    async fn print_evidence(&self) {
+        for id in 0..self.peer_addrs.len() {
+            let report = match self.request_evidence(id).await {
+                Some(report) => report,
+                None => {
+                    println!("node {}: not responding", id);
+                    continue;
+                }
+            };
+            let summary = report.summary;
+            println!(
+                "node {}: evidence against {} nodes",
+                id,
+                summary.evidence.len()
+            );
+            for evidence in summary.evidence.iter() {
+                let accused = evidence.accused();
+                println!(
+                    "  node {:<3} {:<16} in view {:>6}  passed over from view {:>6}  vouched for by {:?}",
+                    accused,
+                    evidence.kind(),
+                    evidence.view(),
+                    summary.excluded_from.get(&accused).copied().unwrap_or_default(),
+                    summary.blamers.get(&accused).cloned().unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    async fn diff(&self, from_seq_num: usize, to_seq_num: Option<usize>) -> Result<(), String> {
+        // keys of the diffs of the nodes, by the checkpoint they go up to
+        let mut diffs: BTreeMap<usize, Vec<Vec<Key>>> = BTreeMap::new();
+        for id in 0..self.peer_addrs.len() {
+            let get_diff = GetDiff {
+                from_seq_num,
+                to_seq_num,
+            };
+            match self.request_diff(id, get_diff).await {
+                Some(CheckpointDiff {
+                    keys: Some(keys),
+                    to_seq_num,
+                    ..
+                }) => {
+                    println!(
+                        "node {}: {} keys written from seq-num {} to {}",
+                        id,
+                        keys.len(),
+                        from_seq_num,
+                        to_seq_num
+                    );
+                    diffs.entry(to_seq_num).or_default().push(keys);
+                }
+                Some(diff) => println!(
+                    "node {}: {} (diffs from seq-num {} to {})",
+                    id,
+                    diff.error.unwrap_or_default(),
+                    diff.oldest_seq_num,
+                    diff.last_stable_seq_num
+                ),
+                None => println!("node {}: not responding", id),
+            }
+        }
+        let (to_seq_num, keys) = diffs
+            .into_iter()
+            .rev()
+            .find(|(_, diffs)| diffs.len() > self.num_faulty)
+            .ok_or_else(|| {
+                String::from("fewer than f + 1 nodes sent a diff up to the same checkpoint")
+            })?;
+        let keys: BTreeSet<Key> = keys.into_iter().flatten().collect();
+        println!(
+            "{} keys written from seq-num {} to {}:",
+            keys.len(),
+            from_seq_num,
+            to_seq_num
+        );
+        for key in keys {
+            println!("  {}", key);
+        }
+        Ok(())
+    }
+
+    async fn print_entries(&self, seq_num: usize) {
+        for id in 0..self.peer_addrs.len() {
+            let get_entry = |respond_addr| {
+                Message::GetEntryMessage(GetEntry {
+                    respond_addr,
+                    seq_num,
+                })
+            };
+            let entry = match self.query(id, get_entry).await {
+                Some(Message::LogEntryMessage(entry)) if entry.id == id => entry,
+                _ => {
+                    println!("node {}: not responding", id);
+                    continue;
+                }
+            };
+            match (entry.commit, entry.request) {
+                (Some(commit), Some(request)) => println!(
+                    "node {}: seq-num {} committed in view {}: {:?} of {} by client {}{}",
+                    id,
+                    seq_num,
+                    commit.view,
+                    request.operation,
+                    request.key,
+                    request.respond_addr,
+                    entry
+                        .rejected
+                        .map(|reason| format!(" (rejected: {:?})", reason))
+                        .unwrap_or_default()
+                ),
+                _ => println!("node {}: no entry at seq-num {}", id, seq_num),
+            }
+        }
+    }
+
+    async fn print_values_at(&self, key: Key, seq_num: usize) {
+        for id in 0..self.peer_addrs.len() {
+            let get_at = |respond_addr| {
+                Message::GetAtMessage(GetAt {
+                    respond_addr,
+                    key: key.clone(),
+                    seq_num,
+                })
+            };
+            match self.query(id, get_at).await {
+                Some(Message::ValueAtMessage(value_at)) if value_at.id == id => {
+                    if value_at.retained {
+                        println!(
+                            "node {}: {} was {:?} after seq-num {}",
+                            id, key, value_at.value, seq_num
+                        );
+                    } else {
+                        println!("node {}: no history of seq-num {}", id, seq_num);
+                    }
+                }
+                _ => println!("node {}: not responding", id),
+            }
+        }
+    }
+
+    async fn compact(&self) {
+        for id in 0..self.peer_addrs.len() {
+            let request = Message::CompactLogMessage(CompactLog {});
+            match self.request_status(id, request).await {
+                Some(status) => println!(
+                    "node {}: compacting up to stable seq-num {}",
+                    id, status.last_stable_seq_num
+                ),
+                None => println!("node {}: not responding", id),
+            }
+        }
+    }
+
+    async fn watch_leader(&self) {
+        let (tx_leader, mut rx_leader) = channel::<Leader>(64);
+        for addr in self.peer_addrs.values().copied() {
+            let tx_leader = tx_leader.clone();
+            tokio::spawn(async move {
+                let mut stream = TcpStream::connect(addr).await.ok()?;
+                let request = Message::WatchLeaderMessage(WatchLeader {});
+                stream
+                    .write_all(request.serialize().as_slice())
+                    .await
+                    .ok()?;
+                let mut reader = MessageReader::new(stream);
+                while let Message::LeaderMessage(leader) = reader.read().await.ok()?? {
+                    tx_leader.send(leader).await.ok()?;
+                }
+                None::<()>
+            });
+        }
+        drop(tx_leader);
+
+        // nodes announcing each (view, primary, address)
+        let mut announcements: HashMap<(usize, NodeId, SocketAddr), HashSet<NodeId>> =
+            HashMap::new();
+        let mut last_view = None;
+        while let Some(leader) = rx_leader.recv().await {
+            let announced_by = announcements
+                .entry((leader.view, leader.primary, leader.primary_addr))
+                .or_default();
+            announced_by.insert(leader.id);
+            if announced_by.len() == self.num_faulty + 1
+                && last_view.is_none_or(|last_view| leader.view > last_view)
+            {
+                println!(
+                    "view {}: primary is node {} at {}",
+                    leader.view, leader.primary, leader.primary_addr
+                );
+                last_view = Some(leader.view);
+            }
+        }
+        println!("No node is announcing leadership changes anymore");
+    }
+
+    async fn drain(&self, id: NodeId, target_seq_num: Option<usize>) -> Result<(), String> {
+        let request = Message::DrainMessage(Drain { target_seq_num });
+        let status = self
+            .request_status(id, request)
+            .await
+            .ok_or_else(|| format!("node {} is not responding", id))?;
+        if status.view % self.peer_addrs.len() == id {
+            println!(
+                "WARNING: node {} may be the primary of view {}, so stopping it will cause a view change",
+                id, status.view
+            );
+        }
+
+        let started = Instant::now();
+        loop {
+            if let Some(drain) = self.status(id).await.and_then(|status| status.drain) {
+                if drain.safe_to_stop {
+                    println!(
+                        "Node {} drained up to seq-num {} and is safe to stop",
+                        id, drain.target_seq_num
+                    );
+                    return Ok(());
+                }
+            }
+            if started.elapsed() > self.ready_timeout {
+                return Err(format!(
+                    "node {} did not drain within {:?}",
+                    id, self.ready_timeout
+                ));
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Makes sure enough nodes respond to keep the cluster live
+    /// if the node with the given id goes down
+    fn check_quorum(
+        &self,
+        statuses: &HashMap<NodeId, NodeStatus>,
+        restarting_id: NodeId,
+    ) -> Result<(), String> {
+        let num_healthy = statuses.keys().filter(|id| **id != restarting_id).count();
+        if num_healthy < 2 * self.num_faulty + 1 {
+            return Err(format!(
+                "only {} nodes besides node {} respond, but {} are needed for a quorum",
+                num_healthy,
+                restarting_id,
+                2 * self.num_faulty + 1
+            ));
+        }
+        Ok(())
+    }
+
+    async fn rolling_restart(&self, restart_cmd: &str) -> Result<(), String> {
+        for id in 0..self.peer_addrs.len() {
+            let statuses = self.statuses().await;
+            self.check_quorum(&statuses, id)?;
+
+            // the node has to catch up to the progress of the cluster before the restart
+            let view = statuses.values().map(|status| status.view).max().unwrap();
+            let seq_num = statuses
+                .values()
+                .map(|status| status.last_seq_num_committed)
+                .max()
+                .unwrap();
+
+            let cmd = restart_cmd.replace("{id}", &id.to_string());
+            println!("Restarting node {} ({})", id, cmd);
+            let status = Command::new("sh").arg("-c").arg(&cmd).status();
+            if !matches!(status, Ok(status) if status.success()) {
+                return Err(format!("restart command for node {} failed", id));
+            }
+
+            self.wait_until_ready(id, view, seq_num).await?;
+            println!("Node {} is ready", id);
+        }
+        println!("Restarted all {} nodes", self.peer_addrs.len());
+        Ok(())
+    }
+
+    /// Waits until the node is in at least the given view, is not in a view change,
+    /// and has committed at least the given sequence number
+    async fn wait_until_ready(
+        &self,
+        id: NodeId,
+        view: usize,
+        seq_num: usize,
+    ) -> Result<(), String> {
+        let started = Instant::now();
+        loop {
+            if started.elapsed() > self.ready_timeout {
+                return Err(format!(
+                    "node {} was not ready within {:?}",
+                    id, self.ready_timeout
+                ));
+            }
+            sleep(Duration::from_secs(1)).await;
+
+            let statuses = self.statuses().await;
+            self.check_quorum(&statuses, id)?;
+            if let Some(status) = statuses.get(&id) {
+                if status.view >= view
+                    && !status.in_view_change
+                    && status.last_seq_num_committed >= seq_num
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use pbft::keys::encode_hex;
+use pbft::keystore::{self, Keystore};
+
+use std::env;
+use std::path::Path;
+
+/// Manages the encrypted keystore file of a node, opened with the passphrase in
+/// PBFT_KEYSTORE_PASSPHRASE. `generate` writes a fresh keypair to the file, `rotate` replaces
+/// it with a fresh one, keeping the previous file with the `.previous` suffix, and `show`
+/// checks the passphrase opens the file. Each prints the public key of the file, which the
+/// nodes pin in their config. Nodes load the file with `--keystore`.
+///
+/// Usage: pbft_keystore generate|rotate|show [keystore_path]
+fn main() -> pbft::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let passphrase = env::var("PBFT_KEYSTORE_PASSPHRASE")
+        .map_err(|e| format!("PBFT_KEYSTORE_PASSPHRASE: {}", e))?;
+    let keystore = match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("generate"), Some(path)) => {
+            if Path::new(path).exists() {
+                return Err(format!("{} already exists, rotate it instead", path).into());
+            }
+            let keystore = Keystore::generate();
+            keystore.save(Path::new(path), &passphrase)?;
+            keystore
+        }
+        (Some("rotate"), Some(path)) => {
+            let keystore = Keystore::rotate(Path::new(path), &passphrase)?;
+            eprintln!(
+                "previous key kept in {}",
+                keystore::previous_path(Path::new(path)).display()
+            );
+            keystore
+        }
+        (Some("show"), Some(path)) => Keystore::load(Path::new(path), &passphrase)?,
+        _ => {
+            eprintln!("Usage: pbft_keystore generate|rotate|show [keystore_path]");
+            std::process::exit(1);
+        }
+    };
+    println!("{}", encode_hex(keystore.public_key().as_bytes()));
+    Ok(())
+}
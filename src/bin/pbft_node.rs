@@ -1,15 +1,29 @@
-use pbft::config::Config;
-use pbft::consensus::Consensus;
-use pbft::messages::{ConsensusCommand, NodeCommand};
-use pbft::node::Node;
-use pbft::Result;
+use pbft::prelude::*;
 
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use ed25519_dalek::Keypair;
-use rand::rngs::OsRng;
+use pbft::authenticator::Authentication;
+use pbft::byzantine;
+use pbft::config::ArchiveRole;
+use pbft::crypto::{DigestAlgorithm, DigestMigration};
+use pbft::data_dir::{DataDir, LegacyPaths};
+use pbft::features::Feature;
+use pbft::keys::{
+    decode_hex, encode_hex, CommandKeyProvider, EnvKeyProvider, FileKeyProvider,
+    GeneratedKeyProvider, KeyProvider,
+};
+use pbft::keystore::{Keystore, KeystoreKeyProvider};
 
-use tokio::sync::mpsc::channel;
+use ed25519_dalek::PublicKey;
+use pbft::logging;
+use pbft::observer::{AuditLogObserver, MetricsObserver};
+use pbft::pki::{CertificateError, IdentityCertificate};
+use pbft::registry::read_snapshot;
+use pbft::trace::TraceObserver;
+use tokio::sync::mpsc::{channel, Sender};
 
 use std::{collections::HashMap, env, net::SocketAddr};
 
@@ -17,70 +31,387 @@ use std::{collections::HashMap, env, net::SocketAddr};
 async fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
     let mut index = 1;
-    let num_nodes = args[index].parse::<usize>().unwrap();
-    let mut peer_addrs = HashMap::new();
-    index += 1;
-    for id in 0..num_nodes {
-        let addr = args[index].clone();
-        peer_addrs.insert(id, SocketAddr::from_str(addr.as_str()).unwrap());
+    // the cluster is given either as a config file or as the number of nodes and their addresses
+    let mut config = if args[index] == "--config" {
+        index += 2;
+        Config::from_file(Path::new(&args[index - 1]))?
+    } else {
+        let num_nodes = args[index].parse::<usize>().unwrap();
+        let mut peer_addrs = HashMap::new();
         index += 1;
-    }
+        for id in 0..num_nodes {
+            let addr = args[index].clone();
+            peer_addrs.insert(id, SocketAddr::from_str(addr.as_str()).unwrap());
+            index += 1;
+        }
+        Config::new(peer_addrs)
+    };
     let id = args[index].parse::<usize>().unwrap();
     index += 1;
 
-    let mut is_equivocator = false;
-    if index < args.len() {
-        let byzantine_flag = args[index].clone();
-        is_equivocator = byzantine_flag.as_str().eq("b");
+    let mut audit_log = None;
+    let mut trace = None;
+    let mut metrics_interval = None;
+    let mut faults = Vec::new();
+    let mut data_dir = None;
+    let mut key_provider: Option<Box<dyn KeyProvider + Send + Sync>> = None;
+    while index < args.len() {
+        let flag = args[index].clone();
+        index += 1;
+        match flag.as_str() {
+            "b" => config.is_equivocator = true,
+            "a" => config.is_archive = true,
+            "--archive" => {
+                // archive node which votes (voting) or only follows the cluster (non-voting)
+                config.is_archive = true;
+                config.archive_role = args[index].parse::<ArchiveRole>()?;
+                index += 1;
+            }
+            "--log-file" => {
+                config.log_file = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--log-json" => config.log_json = true,
+            "--log-sample" => {
+                config.log_sample_rate = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--log-filter" => {
+                config.log_filter = Some(args[index].clone());
+                index += 1;
+            }
+            "--key-file" => {
+                key_provider = Some(Box::new(FileKeyProvider {
+                    path: PathBuf::from(args[index].clone()),
+                }));
+                index += 1;
+            }
+            "--key-env" => {
+                key_provider = Some(Box::new(EnvKeyProvider {
+                    var: args[index].clone(),
+                }));
+                index += 1;
+            }
+            "--key-cmd" => {
+                key_provider = Some(Box::new(CommandKeyProvider {
+                    command: args[index].clone(),
+                }));
+                index += 1;
+            }
+            "--keystore" => {
+                // keystore file written by pbft_keystore, opened with the passphrase in
+                // PBFT_KEYSTORE_PASSPHRASE
+                key_provider = Some(Box::new(KeystoreKeyProvider {
+                    path: PathBuf::from(args[index].clone()),
+                    passphrase_var: String::from("PBFT_KEYSTORE_PASSPHRASE"),
+                }));
+                index += 1;
+            }
+            "--max-keys" => {
+                config.max_total_keys = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--max-bytes" => {
+                config.max_total_bytes = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--max-client-keys" => {
+                config.max_client_keys = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--max-client-bytes" => {
+                config.max_client_bytes = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--digest" => {
+                config.digest_policy.algorithm = args[index].parse::<DigestAlgorithm>()?;
+                index += 1;
+            }
+            "--migrate-digest" => {
+                // switch to the new algorithm from the given sequence number on
+                config.digest_policy.migration = Some(DigestMigration {
+                    algorithm: args[index].parse::<DigestAlgorithm>()?,
+                    from_seq_num: args[index + 1].parse::<usize>()?,
+                });
+                index += 2;
+            }
+            "--initial-view" => {
+                config.initial_view = args[index].parse::<usize>()?;
+                index += 1;
+            }
+            "--wal" => {
+                config.wal_path = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--state-dir" => {
+                config.state_path = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--data-dir" => {
+                data_dir = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--snapshot-export" => {
+                config.snapshot_export = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--snapshot-import" => {
+                config.snapshot_import = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--disable-feature" => {
+                // stop advertising the feature, e.g. read-only, to clients
+                config.features.remove(args[index].parse::<Feature>()?);
+                index += 1;
+            }
+            "--registry" => {
+                // a node joining a running cluster pins the keys of its peers from a registry
+                // snapshot exported by a replica, where its config does not give them
+                let (_, registry) = read_snapshot(Path::new(&args[index]))?;
+                for (peer_id, pub_key) in registry.pub_keys() {
+                    match config.peer_pub_keys.get(&peer_id) {
+                        Some(configured_key) if *configured_key != pub_key => {
+                            return Err(format!(
+                                "the key of node {} in the registry is not the one in the config",
+                                peer_id
+                            )
+                            .into());
+                        }
+                        Some(_) => {}
+                        None => {
+                            config.peer_pub_keys.insert(peer_id, pub_key);
+                        }
+                    }
+                }
+                // a node added by a reconfiguration joins with the membership of its epoch
+                if registry.epoch > config.epoch {
+                    config.apply_membership(&registry);
+                }
+                index += 1;
+            }
+            "--registry-export" => {
+                config.registry_export = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--compaction-rate" => {
+                config.compaction_commit_rate = args[index].parse::<usize>()?;
+                index += 1;
+            }
+            "--compaction-max-delay" => {
+                config.compaction_max_delay = Duration::from_secs(args[index].parse::<u64>()?);
+                index += 1;
+            }
+            "--audit-log" => {
+                audit_log = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--trace" => {
+                trace = Some(PathBuf::from(args[index].clone()));
+                index += 1;
+            }
+            "--metrics-interval" => {
+                metrics_interval = Some(Duration::from_secs(args[index].parse::<u64>()?));
+                index += 1;
+            }
+            "--metrics-addr" => {
+                config.metrics_addr = Some(SocketAddr::from_str(args[index].as_str())?);
+                index += 1;
+            }
+            "--grpc" => {
+                config.grpc_addr = Some(SocketAddr::from_str(args[index].as_str())?);
+                index += 1;
+            }
+            "--http" => {
+                config.http_addr = Some(SocketAddr::from_str(args[index].as_str())?);
+                index += 1;
+            }
+            "--fault" => {
+                // equivocate, drop-commits, stale-view or corrupt-digests (used for testing)
+                let fault = byzantine::strategy(&args[index])
+                    .ok_or_else(|| format!("unknown fault {}", args[index]))?;
+                faults.push(fault);
+                index += 1;
+            }
+            "--encrypt" => config.encrypt_transport = true,
+            "--authenticators" => config.authentication = Authentication::Authenticators,
+            "--root-key" => {
+                let root_pub_key = decode_hex(&args[index])?;
+                config.root_pub_key = Some(PublicKey::from_bytes(&root_pub_key)?);
+                index += 1;
+            }
+            "--certificate" => {
+                config.certificate = Some(IdentityCertificate::read(Path::new(&args[index]))?);
+                index += 1;
+            }
+            "--skip-malformed" => config.skip_malformed_frames = true,
+            "--max-malformed" => {
+                config.max_malformed_frames = args[index].parse::<usize>()?;
+                index += 1;
+            }
+            "--verification-workers" => {
+                config.verification_workers = args[index].parse::<usize>()?;
+                index += 1;
+            }
+            "--bind" => {
+                config
+                    .bind_addrs
+                    .push(SocketAddr::from_str(args[index].as_str()).unwrap());
+                index += 1;
+            }
+            _ => {}
+        }
     }
-
-    let num_faulty: usize = (num_nodes - 1) / 3;
-
-    let config = Config {
-        num_nodes,
-        num_faulty,
-        peer_addrs,
-        request_timeout: std::time::Duration::from_secs(3),
-        rebroadcast_timeout: std::time::Duration::from_secs(8),
-        identity_broadcast_interval: std::time::Duration::from_secs(6),
-        checkpoint_frequency: 10,
-        is_equivocator,
+    // a data directory holds the log, state, exports and key of the node, and takes over
+    // the log and state given with --wal and --state-dir the first time the node uses it
+    let data_dir = match data_dir {
+        Some(root) => {
+            let legacy = LegacyPaths {
+                wal_path: config.wal_path.clone(),
+                state_path: config.state_path.clone(),
+            };
+            let data_dir = DataDir::open(root, id, &legacy)?;
+            data_dir.configure(&mut config);
+            Some(data_dir)
+        }
+        None => None,
     };
+    config.validate()?;
+    // records of the node are tagged with its id, and go where the config says
+    let log = logging::init(id, &config)?;
+    log.scope(async move {
+        let log_sample_rate = config.log_sample_rate;
 
-    let (tx_consensus, rx_consensus) = channel::<ConsensusCommand>(32);
-    let (tx_node, rx_node) = channel::<NodeCommand>(32);
-
-    // generate a keypair for the node
-    let mut rng = OsRng {};
-    let keypair: Keypair = Keypair::generate(&mut rng);
-    let keypair_bytes = keypair.to_bytes().to_vec();
-
-    let mut node = Node::new(
-        id,
-        config.clone(),
-        keypair_bytes.clone(),
-        keypair.public,
-        rx_node,
-        tx_consensus.clone(),
-        tx_node.clone(),
-    );
-    let node_fut = tokio::spawn(async move {
-        node.spawn().await;
-    });
+        let (tx_consensus, rx_consensus) =
+            channel::<ConsensusCommand>(config.consensus_queue_capacity);
+        let (tx_node, rx_node) = channel::<NodeCommand>(config.node_queue_capacity);
+
+        // load the keypair of the node. Without a key source it is the one kept in the data
+        // directory, or a fresh one each time the node starts
+        let key_provider: Box<dyn KeyProvider + Send + Sync> = match (key_provider, data_dir) {
+            (Some(key_provider), _) => key_provider,
+            (None, Some(data_dir)) => Box::new(data_dir),
+            (None, None) => Box::new(GeneratedKeyProvider),
+        };
+        let keystore = Keystore::from_provider(key_provider)?;
+        // authenticators are keyed by the secret key, which a provider may keep to itself
+        if config.authentication == Authentication::Authenticators && keystore.keypair().is_none() {
+            return Err("authenticators need a key source which hands out the secret key".into());
+        }
+        let pub_key = keystore.public_key();
+        if config
+            .peer_pub_keys
+            .get(&id)
+            .is_some_and(|expected_key| *expected_key != pub_key)
+        {
+            return Err(format!("the key of node {} is not the one in the config", id).into());
+        }
+        // peers drop our identifiers if they require a certificate we cannot present
+        if let Some(root_pub_key) = config.root_pub_key.as_ref() {
+            if !config.peer_pub_keys.contains_key(&id) {
+                config
+                    .certificate
+                    .as_ref()
+                    .ok_or(CertificateError::Missing)?
+                    .certifies(id, &pub_key, root_pub_key)?;
+            }
+        }
+
+        let mut node = Node::new(
+            id,
+            config.clone(),
+            &keystore,
+            rx_node,
+            tx_consensus.clone(),
+            tx_node.clone(),
+        );
+        for fault in faults {
+            node.inject_fault(fault);
+        }
+        log::info!("Public key: {}", encode_hex(pub_key.as_bytes()));
+        let peer_pub_keys = node.inner.peer_pub_keys.clone();
+
+        let mut consensus = Consensus::new(
+            id,
+            config.clone(),
+            &keystore,
+            rx_consensus,
+            tx_consensus.clone(),
+            tx_node.clone(),
+            peer_pub_keys,
+        );
+        node.inner.rx_backpressure = consensus.subscribe_backpressure();
+        node.inner.rx_stable_seq_num = consensus.subscribe_stable_seq_num();
+        node.inner.rx_status = consensus.subscribe_status();
+        node.inner.rx_suspected_nodes = consensus.subscribe_suspected_nodes();
+        node.inner.observers = consensus.observers();
+        node.inner.pipeline = consensus.pipeline();
+        node.inner.key_versions = consensus.key_versions();
+        node.inner.checkpoint_diffs = consensus.checkpoint_diffs();
+        node.inner.evidence = consensus.evidence();
+        node.inner.membership = consensus.membership();
+        node.inner.connectivity = consensus.connectivity();
 
-    let mut consensus = Consensus::new(
-        id,
-        config.clone(),
-        keypair_bytes.clone(),
-        rx_consensus,
-        tx_consensus.clone(),
-        tx_node.clone(),
-    );
-    let consensus_fut = tokio::spawn(async move {
-        consensus.spawn().await;
+        if let Some(path) = audit_log {
+            consensus.register_observer(Arc::new(AuditLogObserver::new(&path)?));
+        }
+        if let Some(path) = trace {
+            consensus.register_observer(Arc::new(TraceObserver::new(&path, id)?));
+        }
+        if let Some(interval) = metrics_interval {
+            let metrics = Arc::new(MetricsObserver::default());
+            consensus.register_observer(metrics.clone());
+            logging::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    log::info!("Metrics: {:?}", metrics.metrics());
+                }
+            });
+        }
+
+        handle_signals(log_sample_rate, tx_consensus.clone());
+
+        let node_fut = logging::spawn(async move {
+            node.spawn().await;
+        });
+        let consensus_fut = logging::spawn(async move {
+            consensus.spawn().await;
+        });
+
+        node_fut.await?;
+        consensus_fut.await?;
+        Ok(())
+    })
+    .await
+}
+
+/// SIGUSR1 switches between logging every per-message event and sampling them, and SIGUSR2
+/// exports a snapshot of the state at the last stable checkpoint
+#[cfg(unix)]
+fn handle_signals(log_sample_rate: usize, tx_consensus: Sender<ConsensusCommand>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    logging::spawn(async move {
+        let mut toggle = signal(SignalKind::user_defined1()).unwrap();
+        while toggle.recv().await.is_some() {
+            let sample_rate = if logging::sample_rate() == 1 {
+                log_sample_rate.max(100)
+            } else {
+                1
+            };
+            logging::set_sample_rate(sample_rate);
+            log::info!("Logging every {} per-message events", sample_rate);
+        }
     });
 
-    node_fut.await?;
-    consensus_fut.await?;
-    Ok(())
+    logging::spawn(async move {
+        let mut export = signal(SignalKind::user_defined2()).unwrap();
+        while export.recv().await.is_some() {
+            let _ = tx_consensus.send(ConsensusCommand::ExportSnapshot).await;
+        }
+    });
 }
+
+/// Without signals, the sample rate stays as configured and snapshots are only exported by
+/// the node itself
+#[cfg(not(unix))]
+fn handle_signals(_log_sample_rate: usize, _tx_consensus: Sender<ConsensusCommand>) {}
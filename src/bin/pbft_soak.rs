@@ -0,0 +1,520 @@
+use pbft::codec::{CodecError, MessageReader};
+use pbft::linearizability::{Counterexample, History};
+use pbft::messages::{ClientRequest, ClientResponse, FailureReason, Message, Operation};
+use pbft::scenario::{Expectations, Fault, Outcome, Scenario, ScheduledFault, Workload};
+use pbft::{Key, NodeId, Value};
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+
+/// Long running soak test. Starts a local cluster of n nodes and continuously applies load
+/// through relay replicas while restarting replicas (forcing view changes when the primary
+/// is hit) and pausing replicas (cutting them off from the cluster) on a schedule.
+/// Reads are checked against the acknowledged writes, every reply is compared with
+/// the accepted result to detect replicas whose state diverged, and the history of the
+/// operations is checked to be linearizable once the run is over.
+///
+/// Instead of the random faults, the cluster, faults, workload and expected outcome
+/// can be taken from a scenario file (see `pbft::scenario`).
+///
+/// Usage: pbft_soak n [--duration secs] [--fault-interval secs] [--seed s]
+///                    [--base-port p] [--node-bin path] [--log-dir path]
+///        pbft_soak --scenario path [--base-port p] [--node-bin path] [--log-dir path]
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut index = 1;
+    let mut num_nodes = 0;
+    if !args[index].starts_with("--") {
+        num_nodes = args[index].parse::<usize>().unwrap();
+        index += 1;
+    }
+
+    let mut workload = Workload::default();
+    let mut fault_interval = Duration::from_secs(20);
+    let mut scenario = None;
+    let mut base_port = 7400;
+    let mut node_bin = env::current_exe().unwrap().with_file_name("pbft_node");
+    let mut log_dir = None;
+    while index < args.len() {
+        let flag = args[index].clone();
+        index += 1;
+        match flag.as_str() {
+            "--duration" => workload.duration_secs = args[index].parse().unwrap(),
+            "--fault-interval" => {
+                fault_interval = Duration::from_secs(args[index].parse().unwrap())
+            }
+            "--seed" => workload.seed = Some(args[index].parse().unwrap()),
+            "--scenario" => match Scenario::load(&PathBuf::from(args[index].clone())) {
+                Ok(loaded) => scenario = Some(loaded),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                }
+            },
+            "--base-port" => base_port = args[index].parse().unwrap(),
+            "--node-bin" => node_bin = PathBuf::from(args[index].clone()),
+            "--log-dir" => log_dir = Some(PathBuf::from(args[index].clone())),
+            _ => continue,
+        }
+        index += 1;
+    }
+
+    let mut byzantine = HashSet::new();
+    let mut expect = Expectations::default();
+    if let Some(scenario) = &scenario {
+        println!("Running scenario {:?}", scenario.name);
+        num_nodes = scenario.num_nodes;
+        byzantine = scenario.byzantine.iter().copied().collect();
+        workload = scenario.workload.clone();
+        expect = scenario.expect.clone();
+    }
+    let duration = workload.duration();
+    let seed = workload.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    });
+
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let peer_addrs: Vec<SocketAddr> = (0..num_nodes)
+        .map(|id| SocketAddr::new(localhost, base_port + id as u16))
+        .collect();
+    let resp_addr = SocketAddr::new(localhost, base_port + num_nodes as u16);
+
+    match &scenario {
+        Some(scenario) => println!(
+            "pBFT soak test: {} nodes, {:?}, {} scheduled faults, seed {}",
+            num_nodes,
+            duration,
+            scenario.schedule.len(),
+            seed
+        ),
+        None => println!(
+            "pBFT soak test: {} nodes, {:?}, fault every {:?}, seed {}",
+            num_nodes, duration, fault_interval, seed
+        ),
+    }
+
+    let cluster = Arc::new(Mutex::new(Cluster {
+        node_bin,
+        log_dir,
+        peer_addrs: peer_addrs.clone(),
+        children: (0..num_nodes).map(|_| None).collect(),
+        byzantine,
+        down: HashSet::new(),
+        paused: HashSet::new(),
+    }));
+    for id in 0..num_nodes {
+        cluster.lock().await.start(id);
+    }
+    // give the nodes time to exchange identities
+    sleep(Duration::from_secs(8)).await;
+
+    let start = Instant::now();
+    let deadline = start + duration;
+    let mut report = Report::default();
+
+    // inject faults on a schedule until the deadline
+    let fault_cluster = cluster.clone();
+    let schedule = scenario.map(|scenario| scenario.schedule);
+    let faults = tokio::spawn(async move {
+        if let Some(schedule) = schedule {
+            return inject_scheduled_faults(&fault_cluster, schedule, start, deadline).await;
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut faults_injected = Vec::new();
+        while Instant::now() + fault_interval < deadline {
+            sleep(fault_interval).await;
+            let mut cluster = fault_cluster.lock().await;
+            cluster.heal().await;
+            let id = rng.gen_range(0, num_nodes);
+            let fault = if rng.gen::<bool>() {
+                cluster.stop(id).await;
+                format!("restart node {}", id)
+            } else {
+                cluster.pause(id).await;
+                format!("pause node {}", id)
+            };
+            println!("Injecting fault: {}", fault);
+            faults_injected.push(fault);
+        }
+        fault_cluster.lock().await.heal().await;
+        faults_injected
+    });
+
+    let mut load = Load {
+        num_faulty: (num_nodes - 1) / 3,
+        peer_addrs,
+        resp_addr,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as usize,
+        num_keys: workload.num_keys,
+        write_ratio: workload.write_ratio,
+        rng: StdRng::seed_from_u64(seed.wrapping_add(1)),
+        expected: HashMap::new(),
+        history: History::new(),
+    };
+    while Instant::now() < deadline {
+        let relay_id = match cluster.lock().await.pick_live_node(&mut load.rng) {
+            Some(relay_id) => relay_id,
+            None => {
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        load.step(relay_id, &mut report).await;
+    }
+
+    report.faults_injected = faults.await.unwrap();
+    cluster.lock().await.shutdown().await;
+    report.non_linearizable = load.history.check().err();
+
+    let violations = expect.violations(&Outcome {
+        operations: report.operations,
+        succeeded: report.succeeded,
+        rejected: report.rejected,
+        timed_out: report.timed_out,
+        stale_reads: report.stale_reads.len(),
+        divergent_replies: report.divergent_replies.len(),
+        non_linearizable: report.non_linearizable.is_some(),
+    });
+    let passed = violations.is_empty();
+    println!("pBFT soak test report");
+    println!("  operations:        {}", report.operations);
+    println!("  succeeded:         {}", report.succeeded);
+    println!("  rejected:          {}", report.rejected);
+    println!("  timed out:         {}", report.timed_out);
+    println!("  faults injected:   {}", report.faults_injected.len());
+    println!("  stale reads:       {}", report.stale_reads.len());
+    for stale_read in report.stale_reads.iter() {
+        println!("    {}", stale_read);
+    }
+    println!("  divergent replies: {}", report.divergent_replies.len());
+    for divergent_reply in report.divergent_replies.iter() {
+        println!("    {}", divergent_reply);
+    }
+    match &report.non_linearizable {
+        Some(counterexample) => println!("  linearizable:      no, {}", counterexample),
+        None => println!("  linearizable:      yes"),
+    }
+    for violation in violations.iter() {
+        println!("  expectation failed: {}", violation);
+    }
+    println!("RESULT: {}", if passed { "PASS" } else { "FAIL" });
+    if !passed {
+        std::process::exit(1);
+    }
+}
+
+/// Injects the faults of the scenario at their times, skipping those after the deadline
+async fn inject_scheduled_faults(
+    cluster: &Mutex<Cluster>,
+    schedule: Vec<ScheduledFault>,
+    start: Instant,
+    deadline: Instant,
+) -> Vec<String> {
+    let mut faults_injected = Vec::new();
+    for scheduled in schedule {
+        let at = start + scheduled.at();
+        if at >= deadline {
+            break;
+        }
+        tokio::time::sleep_until(at.into()).await;
+        let mut cluster = cluster.lock().await;
+        let fault = match &scheduled.fault {
+            Fault::Stop { nodes } => {
+                for id in nodes.iter() {
+                    cluster.stop(*id).await;
+                }
+                format!("stop nodes {:?}", nodes)
+            }
+            Fault::Partition { nodes } => {
+                for id in nodes.iter() {
+                    cluster.pause(*id).await;
+                }
+                format!("partition nodes {:?}", nodes)
+            }
+            Fault::Heal => {
+                cluster.heal().await;
+                String::from("heal")
+            }
+        };
+        println!("Injecting fault at {}s: {}", scheduled.at_secs, fault);
+        faults_injected.push(fault);
+    }
+    cluster.lock().await.heal().await;
+    faults_injected
+}
+
+/// Local cluster of node processes
+struct Cluster {
+    node_bin: PathBuf,
+    log_dir: Option<PathBuf>,
+    peer_addrs: Vec<SocketAddr>,
+    children: Vec<Option<Child>>,
+    /// Nodes which run as equivocators
+    byzantine: HashSet<NodeId>,
+    /// Nodes which were stopped and are restarted when the fault is healed
+    down: HashSet<NodeId>,
+    /// Nodes which were paused and are resumed when the fault is healed
+    paused: HashSet<NodeId>,
+}
+
+impl Cluster {
+    fn start(&mut self, id: NodeId) {
+        let mut command = Command::new(&self.node_bin);
+        command.arg(self.peer_addrs.len().to_string());
+        for peer_addr in self.peer_addrs.iter() {
+            command.arg(peer_addr.to_string());
+        }
+        command.arg(id.to_string());
+        if self.byzantine.contains(&id) {
+            command.arg("b");
+        }
+        if let Some(log_dir) = &self.log_dir {
+            command
+                .arg("--log-file")
+                .arg(log_dir.join(format!("node_{}.log", id)));
+        }
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        self.children[id] = Some(child);
+        self.down.remove(&id);
+    }
+
+    async fn stop(&mut self, id: NodeId) {
+        if let Some(mut child) = self.children[id].take() {
+            let _ = child.kill().await;
+        }
+        self.down.insert(id);
+    }
+
+    async fn signal(&self, id: NodeId, signal: &str) {
+        if let Some(pid) = self.children[id].as_ref().and_then(|child| child.id()) {
+            let _ = Command::new("kill")
+                .arg(signal)
+                .arg(pid.to_string())
+                .status()
+                .await;
+        }
+    }
+
+    async fn pause(&mut self, id: NodeId) {
+        self.signal(id, "-STOP").await;
+        self.paused.insert(id);
+    }
+
+    /// Restarts stopped nodes and resumes paused nodes
+    async fn heal(&mut self) {
+        for id in self.down.clone() {
+            self.start(id);
+        }
+        for id in self.paused.drain().collect::<Vec<NodeId>>() {
+            self.signal(id, "-CONT").await;
+        }
+    }
+
+    fn pick_live_node(&self, rng: &mut StdRng) -> Option<NodeId> {
+        let live_nodes: Vec<NodeId> = (0..self.peer_addrs.len())
+            .filter(|id| !self.down.contains(id) && !self.paused.contains(id))
+            .collect();
+        if live_nodes.is_empty() {
+            return None;
+        }
+        Some(live_nodes[rng.gen_range(0, live_nodes.len())])
+    }
+
+    async fn shutdown(&mut self) {
+        self.heal().await;
+        for id in 0..self.children.len() {
+            self.stop(id).await;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    operations: usize,
+    succeeded: usize,
+    rejected: usize,
+    timed_out: usize,
+    faults_injected: Vec<String>,
+    /// Reads which did not return the last acknowledged write
+    stale_reads: Vec<String>,
+    /// Replies which disagree with the result accepted from f + 1 matching replies
+    divergent_replies: Vec<String>,
+    /// Smallest part of the history which is not linearizable, if any
+    non_linearizable: Option<Counterexample>,
+}
+
+/// Sequential load against a small set of keys, so that every read can be checked
+/// against the last acknowledged write of the key
+struct Load {
+    num_faulty: usize,
+    peer_addrs: Vec<SocketAddr>,
+    resp_addr: SocketAddr,
+    timestamp: usize,
+    num_keys: usize,
+    /// Fraction of the operations which are writes
+    write_ratio: f64,
+    rng: StdRng,
+    /// Last acknowledged value of each key. A key whose last write timed out is removed,
+    /// as that write may or may not be applied later
+    expected: HashMap<Key, Option<Value>>,
+    history: History,
+}
+
+/// How long we wait for a result before counting the request as timed out
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long we keep collecting replies after accepting a result, to compare them against it
+const REPLY_GRACE: Duration = Duration::from_millis(500);
+
+impl Load {
+    async fn step(&mut self, relay_id: NodeId, report: &mut Report) {
+        let key_index = self.rng.gen_range(0, self.num_keys);
+        let key = Key::from(format!("__pbft_soak_{}", key_index));
+        let value = if self.rng.gen_bool(self.write_ratio) {
+            Some(Value::from(self.rng.gen::<u32>().to_string()))
+        } else {
+            None
+        };
+
+        report.operations += 1;
+        let operation = value.clone().map_or(Operation::Get, Operation::Set);
+        let id = self.history.invoke(self.resp_addr, key.clone(), operation);
+        let (result, replies) = match timeout(
+            REQUEST_TIMEOUT,
+            self.request(relay_id, key.clone(), value.clone()),
+        )
+        .await
+        {
+            Ok(Some(res)) => res,
+            _ => {
+                report.timed_out += 1;
+                if value.is_some() {
+                    self.expected.remove(&key);
+                }
+                return;
+            }
+        };
+        self.history.complete(id, &result);
+
+        for reply in replies.iter() {
+            if (&reply.value, reply.reason) != (&result.value, result.reason) {
+                report.divergent_replies.push(format!(
+                    "node {} replied {:?} to request {} but {:?} was accepted",
+                    reply.id, reply, result.time_stamp, result
+                ));
+            }
+        }
+
+        if result.reason.is_some() {
+            report.rejected += 1;
+            return;
+        }
+        report.succeeded += 1;
+        match value {
+            Some(value) => {
+                self.expected.insert(key, Some(value));
+            }
+            None => {
+                let expected = self
+                    .expected
+                    .entry(key.clone())
+                    .or_insert(result.value.clone())
+                    .clone();
+                if result.value != expected {
+                    report.stale_reads.push(format!(
+                        "read of {} returned {:?} but the last acknowledged write was {:?}",
+                        key, result.value, expected
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Submits the request through the relay replica. Returns the result accepted from
+    /// f + 1 matching replies, together with every reply received
+    async fn request(
+        &mut self,
+        relay_id: NodeId,
+        key: Key,
+        value: Option<Value>,
+    ) -> Option<(ClientResponse, Vec<ClientResponse>)> {
+        self.timestamp += 1;
+        let request = Message::ClientRequestMessage(ClientRequest {
+            respond_addr: self.resp_addr,
+            time_stamp: self.timestamp,
+            key,
+            operation: value.map_or(Operation::Get, Operation::Set),
+            relay_id: Some(relay_id),
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        });
+        let mut stream = TcpStream::connect(self.peer_addrs[relay_id]).await.ok()?;
+        stream
+            .write_all(request.serialize().as_slice())
+            .await
+            .ok()?;
+
+        let mut reader = MessageReader::new(stream);
+        let mut replies = HashMap::<NodeId, ClientResponse>::new();
+        let mut result: Option<ClientResponse> = None;
+        let mut grace_deadline = None;
+        while replies.len() < self.peer_addrs.len() {
+            let read = match grace_deadline {
+                Some(grace_deadline) => {
+                    match tokio::time::timeout_at(grace_deadline, reader.read()).await {
+                        Ok(read) => read,
+                        Err(_) => break,
+                    }
+                }
+                None => reader.read().await,
+            };
+            match read {
+                Ok(Some(Message::ClientResponseMessage(reply))) => {
+                    replies.insert(reply.id, reply);
+                }
+                Ok(Some(_)) | Err(CodecError::Malformed(_)) => {}
+                Ok(None) | Err(CodecError::Io(_)) => break,
+            }
+            if result.is_none() {
+                let mut vote_counts =
+                    HashMap::<(&Option<Value>, Option<FailureReason>), usize>::new();
+                for reply in replies.values() {
+                    let votes = vote_counts.entry((&reply.value, reply.reason)).or_insert(0);
+                    *votes += 1;
+                    if *votes > self.num_faulty {
+                        result = Some(reply.clone());
+                        grace_deadline = Some(tokio::time::Instant::now() + REPLY_GRACE);
+                    }
+                }
+            }
+        }
+        result.map(|result| (result, replies.into_values().collect()))
+    }
+}
@@ -0,0 +1,92 @@
+use pbft::itf;
+use pbft::trace::{self, TraceEntry};
+use pbft::NodeId;
+
+use std::env;
+use std::path::PathBuf;
+
+/// Prints the traces recorded by replicas started with `--trace` as a narrative of the run,
+/// e.g. "node 2 prepared (v=0, n=5) with votes from {0,2,3}", one event per line.
+/// The traces of several replicas are merged by time. Only the messages each replica sent
+/// are described unless `--received` is given, and `--node` and `--seq` restrict the
+/// narrative to the events of one replica or to one sequence number.
+/// With `--itf`, the merged traces are instead printed as states of the protocol in the
+/// Informal Trace Format, to check the run against a TLA+ specification with Apalache,
+/// starting from the given initial view (0 by default).
+///
+/// Usage: pbft_trace [trace_1] ... [trace_k] [--received] [--node id] [--seq n]
+///        pbft_trace [trace_1] ... [trace_k] --itf [--initial-view v]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut index = 1;
+    let mut paths = Vec::new();
+    let mut with_received = false;
+    let mut only_node: Option<NodeId> = None;
+    let mut only_seq_num: Option<usize> = None;
+    let mut as_itf = false;
+    let mut initial_view = 0;
+    while index < args.len() {
+        let arg = args[index].clone();
+        index += 1;
+        match arg.as_str() {
+            "--received" => with_received = true,
+            "--node" => {
+                only_node = Some(args[index].parse::<NodeId>().unwrap());
+                index += 1;
+            }
+            "--seq" => {
+                only_seq_num = Some(args[index].parse::<usize>().unwrap());
+                index += 1;
+            }
+            "--itf" => as_itf = true,
+            "--initial-view" => {
+                initial_view = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut entries = Vec::new();
+    for path in paths.iter() {
+        match trace::read_trace(path) {
+            Ok(trace) => entries.extend(trace),
+            Err(e) => {
+                eprintln!("Could not read {} ({})", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // the sort is stable, so the events of a replica keep their order
+    entries.sort_by_key(|entry| entry.at_millis);
+
+    if as_itf {
+        println!("{}", itf::export(&entries, initial_view));
+        return;
+    }
+
+    let start_millis = entries.first().map(|entry| entry.at_millis).unwrap_or(0);
+    for entry in entries.iter() {
+        if only_node.is_some_and(|id| id != entry.node) {
+            continue;
+        }
+        if only_seq_num.is_some_and(|seq_num| seq_num_of(entry) != Some(seq_num)) {
+            continue;
+        }
+        if let Some(sentence) = trace::narrate(entry, with_received) {
+            println!("[+{:>6}ms] {}", entry.at_millis - start_millis, sentence);
+        }
+    }
+}
+
+fn seq_num_of(entry: &TraceEntry) -> Option<usize> {
+    match &entry.event {
+        trace::TraceEvent::Received { message } | trace::TraceEvent::Sent { message, .. } => {
+            message.get_seq_num()
+        }
+        trace::TraceEvent::Quorum { seq_num, .. } | trace::TraceEvent::Applied { seq_num, .. } => {
+            Some(*seq_num)
+        }
+        trace::TraceEvent::ViewChange { .. } => None,
+    }
+}
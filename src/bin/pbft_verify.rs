@@ -0,0 +1,409 @@
+use pbft::codec::{CodecError, MessageReader};
+use pbft::keys::read_pub_keys;
+use pbft::messages::{ClientRequest, ClientResponse, GetProof, KeyProof, Message, Operation};
+use pbft::probe::{check_key_proof, proof_verdict, ProofVerdict, ResponseTally};
+use pbft::{Key, NodeId, Value};
+
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::PublicKey;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Instant};
+
+/// Probes the safety and availability of a live cluster.
+/// Sentinel keys are written through one replica and read back through f + 1 others (using
+/// relay mode), counting only responses signed by the replicas they claim to come from.
+/// The replicas are asked for proofs of the sentinels against their stable checkpoints, which
+/// are sent to resp_addr: f + 1 of them have to answer, and one has to prove the value. Until
+/// a stable checkpoint covers a sentinel, up to `--max-fill` filler keys (20 by default) are
+/// written to move the cluster to the next one. Then up to f nodes are killed with the given
+/// command and the probe is repeated to check that the cluster is still available and consistent.
+/// The nodes are killed by the command rather than through the node API, which has no shutdown
+/// message: any client can reach the API, and must not be able to stop replicas.
+///
+/// Usage: pbft_verify n [addr_1] ... [addr_n] [resp_addr] --pub-keys [file] [--sentinels k] [--max-fill k] [--kill-cmd "cmd {id}"]
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut index = 1;
+    let num_nodes = args[index].parse::<usize>().unwrap();
+    let mut peer_addrs = HashMap::new();
+    index += 1;
+    for id in 0..num_nodes {
+        let addr = args[index].clone();
+        peer_addrs.insert(id, SocketAddr::from_str(addr.as_str()).unwrap());
+        index += 1;
+    }
+    let resp_addr = SocketAddr::from_str(args[index].clone().as_str()).unwrap();
+    index += 1;
+
+    let mut num_sentinels = 5;
+    let mut max_fillers = 20;
+    let mut kill_cmd = None;
+    let mut pub_keys = None;
+    while index < args.len() {
+        let flag = args[index].clone();
+        index += 1;
+        match flag.as_str() {
+            "--sentinels" => {
+                num_sentinels = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--max-fill" => {
+                max_fillers = args[index].parse::<usize>().unwrap();
+                index += 1;
+            }
+            "--kill-cmd" => {
+                kill_cmd = Some(args[index].clone());
+                index += 1;
+            }
+            "--pub-keys" => {
+                // public keys of the replicas in order of their ids, one hex key per line
+                pub_keys = Some(read_pub_keys(Path::new(&args[index])).unwrap());
+                index += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let pub_keys = match pub_keys {
+        Some(pub_keys) => pub_keys,
+        None => {
+            eprintln!(
+                "The public keys of the replicas are needed to check their responses (--pub-keys)"
+            );
+            std::process::exit(2);
+        }
+    };
+
+    // key proofs are sent to the address the verifier listens on
+    let listener = TcpListener::bind(resp_addr).await.unwrap();
+    let (tx_proof, rx_proof) = mpsc::channel(64);
+    tokio::spawn(receive_key_proofs(listener, tx_proof));
+
+    let num_faulty = (num_nodes - 1) / 3;
+    let start_millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as usize;
+
+    let mut verifier = Verifier {
+        peer_addrs,
+        pub_keys,
+        num_faulty,
+        resp_addr,
+        timestamp: start_millis,
+        request_timeout: Duration::from_secs(15),
+        proof_timeout: Duration::from_secs(2),
+        max_fillers,
+        live_nodes: (0..num_nodes).collect(),
+        sentinels: HashMap::new(),
+        rx_proof,
+        report: Vec::new(),
+    };
+
+    verifier.probe("healthy cluster", num_sentinels).await;
+
+    if let Some(kill_cmd) = kill_cmd {
+        // kill the f nodes with the highest ids
+        for id in (num_nodes - num_faulty..num_nodes).rev() {
+            let cmd = kill_cmd.replace("{id}", &id.to_string());
+            let status = Command::new("sh").arg("-c").arg(&cmd).status();
+            let killed = matches!(status, Ok(status) if status.success());
+            verifier.check(format!("kill node {} ({})", id, cmd), killed);
+            verifier.live_nodes.retain(|live_id| *live_id != id);
+        }
+        // give the cluster time to notice the failures
+        sleep(Duration::from_secs(2)).await;
+        verifier
+            .probe(&format!("{} nodes killed", num_faulty), num_sentinels)
+            .await;
+    }
+
+    let passed = verifier.report.iter().all(|(_, passed)| *passed);
+    println!("pBFT verification report");
+    for (check, check_passed) in verifier.report.iter() {
+        println!(
+            "  [{}] {}",
+            if *check_passed { "PASS" } else { "FAIL" },
+            check
+        );
+    }
+    println!("RESULT: {}", if passed { "PASS" } else { "FAIL" });
+    if !passed {
+        std::process::exit(1);
+    }
+}
+
+struct Verifier {
+    peer_addrs: HashMap<NodeId, SocketAddr>,
+    pub_keys: HashMap<NodeId, PublicKey>,
+    num_faulty: usize,
+    resp_addr: SocketAddr,
+    timestamp: usize,
+    /// How long we wait for f + 1 matching responses to a request
+    request_timeout: Duration,
+    /// How long we wait for the replicas to send proofs of a sentinel
+    proof_timeout: Duration,
+    /// Most filler keys written to move the cluster to a stable checkpoint covering a sentinel
+    max_fillers: usize,
+    /// Nodes which have not been killed by the verifier
+    live_nodes: Vec<NodeId>,
+    /// Sentinel keys written so far with their expected values and the sequence numbers
+    /// they were written at
+    sentinels: HashMap<Key, (Value, usize)>,
+    /// Key proofs received on the response address
+    rx_proof: mpsc::Receiver<KeyProof>,
+    /// Description of each check together with whether it passed
+    report: Vec<(String, bool)>,
+}
+
+impl Verifier {
+    fn check(&mut self, description: String, passed: bool) {
+        self.report.push((description, passed));
+    }
+
+    /// Writes new sentinels, then reads back every sentinel written so far through f + 1
+    /// replicas other than the one it was written through, and checks the proofs the
+    /// replicas give for it
+    async fn probe(&mut self, phase: &str, num_sentinels: usize) {
+        let first_sentinel = self.sentinels.len();
+        for i in first_sentinel..first_sentinel + num_sentinels {
+            let key = Key::from(format!("__pbft_verify_{}_{}", self.timestamp, i));
+            let value = Value::from(rand::random::<u64>().to_string());
+            let relay_id = self.live_nodes[i % self.live_nodes.len()];
+            let res = self
+                .request(relay_id, key.clone(), Some(value.clone()))
+                .await;
+            let description = format!("[{}] write {} through node {}", phase, key, relay_id);
+            match res {
+                Ok(response) if response.success => {
+                    self.check(description, true);
+                    self.sentinels.insert(key, (value, response.seq_num));
+                }
+                Ok(response) => self.check(
+                    format!("{} (rejected: {:?})", description, response.reason),
+                    false,
+                ),
+                Err(e) => self.check(format!("{} ({})", description, e), false),
+            }
+        }
+
+        let mut sentinels: Vec<(Key, (Value, usize))> = self
+            .sentinels
+            .iter()
+            .map(|(key, sentinel)| (key.clone(), sentinel.clone()))
+            .collect();
+        sentinels.sort();
+        for (i, (key, (value, written_at))) in sentinels.into_iter().enumerate() {
+            let num_readers = (self.num_faulty + 1).min(self.live_nodes.len());
+            for reader in 0..num_readers {
+                let relay_id = self.live_nodes[(i + 1 + reader) % self.live_nodes.len()];
+                let res = self.request(relay_id, key.clone(), None).await;
+                let description = format!("[{}] read {} through node {}", phase, key, relay_id);
+                match res {
+                    Ok(response) => self.check(
+                        format!(
+                            "{} (expected {}, got {:?})",
+                            description, value, response.value
+                        ),
+                        response.value == Some(value.clone()),
+                    ),
+                    Err(e) => self.check(format!("{} ({})", description, e), false),
+                }
+            }
+            self.check_proofs(phase, key, value, written_at).await;
+        }
+    }
+
+    /// Asks every live replica for a proof of the sentinel against its stable checkpoint.
+    /// At least f + 1 replicas have to send one, every proof has to be certified, and one of
+    /// them has to prove the value. While no stable checkpoint covers the write, filler keys
+    /// are written to drive the cluster to the next one, up to `max_fillers` of them
+    async fn check_proofs(&mut self, phase: &str, key: Key, value: Value, written_at: usize) {
+        let description = format!("[{}] proof of {}", phase, key);
+        let mut num_fillers = 0;
+        loop {
+            let proofs = self.request_proofs(&key).await;
+            let checks = proofs
+                .iter()
+                .map(|(id, key_proof)| {
+                    let check = check_key_proof(
+                        key_proof,
+                        &key,
+                        &value,
+                        written_at,
+                        &self.pub_keys,
+                        self.num_faulty,
+                    );
+                    (*id, check)
+                })
+                .collect();
+            match proof_verdict(checks, self.num_faulty) {
+                ProofVerdict::Proven(proven_by) => {
+                    return self.check(format!("{} from nodes {:?}", description, proven_by), true);
+                }
+                ProofVerdict::Invalid(invalid) => {
+                    for (id, e) in invalid {
+                        self.check(
+                            format!(
+                                "{} from node {} at seq-num {} ({})",
+                                description, id, proofs[&id].committed_seq_num, e
+                            ),
+                            false,
+                        );
+                    }
+                    return;
+                }
+                ProofVerdict::TooFewProofs(num_proofs) => {
+                    return self.check(
+                        format!(
+                            "{} (only {} live replicas sent one, f + 1 = {} are needed)",
+                            description,
+                            num_proofs,
+                            self.num_faulty + 1
+                        ),
+                        false,
+                    );
+                }
+                ProofVerdict::NotYetCovered if num_fillers == self.max_fillers => {
+                    return self.check(
+                        format!(
+                            "{} (no stable checkpoint covers the write after {} more writes)",
+                            description, num_fillers
+                        ),
+                        false,
+                    );
+                }
+                ProofVerdict::NotYetCovered => {
+                    num_fillers += 1;
+                    let filler = Key::from("__pbft_verify_fill");
+                    let relay_id = self.live_nodes[num_fillers % self.live_nodes.len()];
+                    let filled = self
+                        .request(relay_id, filler, Some(Value::from(num_fillers.to_string())))
+                        .await;
+                    if let Err(e) = filled {
+                        return self.check(
+                            format!("{} (could not write a filler key: {})", description, e),
+                            false,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Proofs of the key the live replicas send within the proof timeout, by replica.
+    /// A replica without a stable checkpoint does not answer
+    async fn request_proofs(&mut self, key: &Key) -> HashMap<NodeId, KeyProof> {
+        let get_proof = Message::GetProofMessage(GetProof {
+            respond_addr: self.resp_addr,
+            key: key.clone(),
+        });
+        for id in self.live_nodes.iter() {
+            let addr = self.peer_addrs[id];
+            if let Ok(mut stream) = TcpStream::connect(addr).await {
+                let _ = stream.write_all(get_proof.serialize().as_slice()).await;
+            }
+        }
+
+        let mut proofs = HashMap::new();
+        let deadline = Instant::now() + self.proof_timeout;
+        while proofs.len() < self.live_nodes.len() {
+            match timeout(deadline - Instant::now(), self.rx_proof.recv()).await {
+                // a late proof of an earlier request is superseded by the one we asked for
+                Ok(Some(key_proof)) if key_proof.key == *key => {
+                    proofs.insert(key_proof.id, key_proof);
+                }
+                Ok(Some(_)) => {}
+                _ => break,
+            }
+        }
+        proofs
+    }
+
+    /// Submits a request through the relay replica and waits for f + 1 matching responses
+    /// signed by distinct replicas
+    async fn request(
+        &mut self,
+        relay_id: NodeId,
+        key: Key,
+        value: Option<Value>,
+    ) -> Result<ClientResponse, String> {
+        self.timestamp += 1;
+        let request = ClientRequest {
+            respond_addr: self.resp_addr,
+            time_stamp: self.timestamp,
+            key,
+            operation: value.map_or(Operation::Get, Operation::Set),
+            relay_id: Some(relay_id),
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        };
+        let mut tally = ResponseTally::new(self.pub_keys.clone(), &request, self.num_faulty + 1);
+        let request = Message::ClientRequestMessage(request);
+        let addr = *self.peer_addrs.get(&relay_id).unwrap();
+
+        let collect_votes = async move {
+            let mut stream = TcpStream::connect(addr)
+                .await
+                .map_err(|e| format!("could not connect to {}: {}", addr, e))?;
+            stream
+                .write_all(request.serialize().as_slice())
+                .await
+                .map_err(|e| format!("could not send request: {}", e))?;
+
+            let mut reader = MessageReader::new(stream);
+            loop {
+                let message = match reader.read().await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => return Err(String::from("relay closed the connection")),
+                    Err(CodecError::Malformed(_)) => continue,
+                    Err(e) => return Err(e.to_string()),
+                };
+                if let Message::ClientResponseMessage(response) = message {
+                    if let Some(response) = tally.add(response) {
+                        return Ok(response);
+                    }
+                }
+            }
+        };
+
+        match timeout(self.request_timeout, collect_votes).await {
+            Ok(res) => res,
+            Err(_) => Err(String::from(
+                "timed out waiting for f + 1 matching signed responses",
+            )),
+        }
+    }
+}
+
+/// Passes on the key proofs the replicas send to the verifier
+async fn receive_key_proofs(listener: TcpListener, tx_proof: mpsc::Sender<KeyProof>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let tx_proof = tx_proof.clone();
+        tokio::spawn(async move {
+            let mut reader = MessageReader::new(stream);
+            loop {
+                match reader.read().await {
+                    Ok(Some(Message::KeyProofMessage(key_proof))) => {
+                        let _ = tx_proof.send(key_proof).await;
+                    }
+                    Ok(Some(_)) | Err(CodecError::Malformed(_)) => {}
+                    Ok(None) | Err(_) => return,
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,147 @@
+use crate::limits::MAX_BATCH_OPS;
+use crate::messages::{
+    BatchOp, BulkBatch, BulkLoadPart, BulkLoadSummary, ClientRequest, FailureReason, Operation,
+};
+use crate::Key;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+
+/// Bulk loads streamed to us as primary. The parts of a load are reassembled by offset, as
+/// they may arrive out of order, and chopped into batches as large as a request can carry
+/// (in operations and in bytes). The batches wait here rather than in the mempool, where
+/// they would expire or fill it, and are proposed once no client request is pending
+pub struct BulkIngest {
+    /// Bytes of keys and values in a batch past which it is not added to
+    max_batch_bytes: usize,
+    /// Load each client is streaming, identified by its response address
+    loads: HashMap<SocketAddr, PendingLoad>,
+    batches: VecDeque<ClientRequest>,
+}
+
+struct PendingLoad {
+    time_stamp: usize,
+    /// Parts received ahead of the operations before them, by offset
+    parts: BTreeMap<usize, BulkLoadPart>,
+    /// Offset of the next operation of the load to take
+    next_offset: usize,
+    /// Whether we took the last part, after which the parts of the load are dropped
+    is_done: bool,
+    /// Operations taken which do not fill a batch yet, from the offset of the first one
+    ops: Vec<BatchOp>,
+    ops_offset: usize,
+    ops_bytes: usize,
+}
+
+impl BulkIngest {
+    pub fn new(max_batch_bytes: usize) -> Self {
+        Self {
+            max_batch_bytes,
+            loads: HashMap::new(),
+            batches: VecDeque::new(),
+        }
+    }
+
+    /// Takes the part of a load. A part of an earlier load of the client than the one it is
+    /// streaming is dropped, as is a part we already took
+    pub fn take(&mut self, part: BulkLoadPart) {
+        let addr = part.respond_addr;
+        let load = self
+            .loads
+            .entry(addr)
+            .or_insert_with(|| PendingLoad::new(part.time_stamp));
+        if part.time_stamp < load.time_stamp {
+            return;
+        }
+        if part.time_stamp > load.time_stamp {
+            // the client gave up on the load it was streaming
+            *load = PendingLoad::new(part.time_stamp);
+        }
+        if load.is_done || part.offset < load.next_offset {
+            return;
+        }
+        load.parts.insert(part.offset, part);
+
+        while let Some(part) = load.parts.remove(&load.next_offset) {
+            load.next_offset += part.ops.len();
+            for op in part.ops {
+                load.ops_bytes += op.size();
+                load.ops.push(op);
+                if load.ops.len() == MAX_BATCH_OPS || load.ops_bytes >= self.max_batch_bytes {
+                    self.batches.push_back(load.batch(addr, false));
+                }
+            }
+            if part.last {
+                // the last batch is proposed even if it is empty, as it is answered
+                self.batches.push_back(load.batch(addr, true));
+                load.is_done = true;
+                load.parts.clear();
+                return;
+            }
+        }
+    }
+
+    /// Next batch to propose
+    pub fn pop(&mut self) -> Option<ClientRequest> {
+        self.batches.pop_front()
+    }
+
+    /// Drops the loads, once we are no longer primary. The batches not proposed are lost,
+    /// so the loads come short
+    pub fn clear(&mut self) {
+        self.loads.clear();
+        self.batches.clear();
+    }
+
+    /// Batches waiting to be proposed
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+}
+
+impl PendingLoad {
+    fn new(time_stamp: usize) -> Self {
+        Self {
+            time_stamp,
+            parts: BTreeMap::new(),
+            next_offset: 0,
+            is_done: false,
+            ops: Vec::new(),
+            ops_offset: 0,
+            ops_bytes: 0,
+        }
+    }
+
+    /// Request for the operations taken since the last batch
+    fn batch(&mut self, addr: SocketAddr, last: bool) -> ClientRequest {
+        let ops = std::mem::take(&mut self.ops);
+        let offset = self.ops_offset;
+        self.ops_offset += ops.len();
+        self.ops_bytes = 0;
+        ClientRequest {
+            respond_addr: addr,
+            time_stamp: self.time_stamp,
+            key: Key::default(),
+            operation: Operation::Get,
+            relay_id: None,
+            read_only: false,
+            batch: ops,
+            bulk: Some(BulkBatch { offset, last }),
+            client_id: None,
+            signature: Vec::new(),
+        }
+    }
+}
+
+/// Progress of the bulk load of a client as the replicas apply its batches
+#[derive(Debug, Clone, Default)]
+pub struct BulkProgress {
+    pub time_stamp: usize,
+    pub summary: BulkLoadSummary,
+    /// Why a batch of the load was rejected, after which the rest of the load is not applied
+    pub reason: Option<FailureReason>,
+}
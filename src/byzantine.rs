@@ -0,0 +1,164 @@
+use crate::keystore::Keystore;
+use crate::messages::{Commit, Message, Operation, PrePrepare, Prepare};
+use crate::{NodeId, Value};
+
+use std::sync::{Arc, RwLock};
+
+/// Byzantine behavior injected into the messages a replica sends, used to test that the
+/// cluster stays safe and live with faulty replicas. The honest engine runs unchanged,
+/// and its messages are tampered with on their way to each peer
+pub trait Fault: Send + Sync {
+    /// Returns the message sent to the peer in place of the given one, or None to drop it.
+    /// The keystore of the replica is passed to sign the tampered message
+    fn tamper(&self, keystore: &Keystore, peer_id: NodeId, message: Message) -> Option<Message>;
+}
+
+/// Sends pre-prepares for a request with another value to the peers with odd ids
+pub struct EquivocatePrePrepares;
+
+impl Fault for EquivocatePrePrepares {
+    fn tamper(&self, keystore: &Keystore, peer_id: NodeId, message: Message) -> Option<Message> {
+        match message {
+            Message::PrePrepareMessage(pre_prepare) if peer_id % 2 == 1 => {
+                let mut request = (*pre_prepare.client_request).clone();
+                let mut value = match request.operation {
+                    Operation::Set(value) => value.into_bytes(),
+                    _ => Vec::new(),
+                };
+                value.push(0);
+                request.operation = Operation::Set(Value::from(value));
+                Some(Message::PrePrepareMessage(PrePrepare::new_with_signature(
+                    keystore,
+                    pre_prepare.id,
+                    pre_prepare.epoch,
+                    pre_prepare.view,
+                    pre_prepare.seq_num,
+                    &request,
+                )))
+            }
+            message => Some(message),
+        }
+    }
+}
+
+/// Never sends commits
+pub struct DropCommits;
+
+impl Fault for DropCommits {
+    fn tamper(&self, _keystore: &Keystore, _peer_id: NodeId, message: Message) -> Option<Message> {
+        match message {
+            Message::CommitMessage(_) => None,
+            message => Some(message),
+        }
+    }
+}
+
+/// Sends the messages of the normal case protocol for the view before the current one
+pub struct StaleView;
+
+impl Fault for StaleView {
+    fn tamper(&self, keystore: &Keystore, _peer_id: NodeId, message: Message) -> Option<Message> {
+        let message = match message {
+            Message::PrePrepareMessage(pre_prepare) => {
+                Message::PrePrepareMessage(PrePrepare::new_with_signature(
+                    keystore,
+                    pre_prepare.id,
+                    pre_prepare.epoch,
+                    pre_prepare.view.saturating_sub(1),
+                    pre_prepare.seq_num,
+                    &pre_prepare.client_request,
+                ))
+            }
+            Message::PrepareMessage(prepare) => Message::PrepareMessage(Prepare::new_with_digest(
+                keystore,
+                prepare.id,
+                prepare.epoch,
+                prepare.view.saturating_sub(1),
+                prepare.seq_num,
+                prepare.client_request_digest,
+            )),
+            Message::CommitMessage(commit) => Message::CommitMessage(Commit::new_with_signature(
+                keystore,
+                commit.id,
+                commit.epoch,
+                commit.view.saturating_sub(1),
+                commit.seq_num,
+                commit.client_request_digest,
+            )),
+            message => message,
+        };
+        Some(message)
+    }
+}
+
+/// Signs the messages of the normal case protocol over a digest other than the digest
+/// of their request
+pub struct CorruptDigests;
+
+impl Fault for CorruptDigests {
+    fn tamper(&self, keystore: &Keystore, _peer_id: NodeId, message: Message) -> Option<Message> {
+        let corrupt = |digest: &[u8]| digest.iter().map(|byte| !byte).collect::<Vec<u8>>();
+        let message = match message {
+            Message::PrePrepareMessage(mut pre_prepare) => {
+                pre_prepare.client_request_digest = corrupt(&pre_prepare.client_request_digest);
+                Message::PrePrepareMessage(pre_prepare)
+            }
+            Message::PrepareMessage(prepare) => Message::PrepareMessage(Prepare::new_with_digest(
+                keystore,
+                prepare.id,
+                prepare.epoch,
+                prepare.view,
+                prepare.seq_num,
+                corrupt(&prepare.client_request_digest),
+            )),
+            Message::CommitMessage(commit) => Message::CommitMessage(Commit::new_with_signature(
+                keystore,
+                commit.id,
+                commit.epoch,
+                commit.view,
+                commit.seq_num,
+                corrupt(&commit.client_request_digest),
+            )),
+            message => message,
+        };
+        Some(message)
+    }
+}
+
+/// The fault strategy of the given name, as passed to `pbft_node --fault`
+pub fn strategy(name: &str) -> Option<Arc<dyn Fault>> {
+    match name {
+        "equivocate" => Some(Arc::new(EquivocatePrePrepares)),
+        "drop-commits" => Some(Arc::new(DropCommits)),
+        "stale-view" => Some(Arc::new(StaleView)),
+        "corrupt-digests" => Some(Arc::new(CorruptDigests)),
+        _ => None,
+    }
+}
+
+/// Faults injected into the messages a replica sends, applied in the order they were added
+#[derive(Clone, Default)]
+pub struct Faults {
+    faults: Arc<RwLock<Vec<Arc<dyn Fault>>>>,
+}
+
+impl Faults {
+    pub fn register(&self, fault: Arc<dyn Fault>) {
+        self.faults.write().unwrap().push(fault);
+    }
+
+    pub fn tamper(
+        &self,
+        keystore: &Keystore,
+        peer_id: NodeId,
+        message: Message,
+    ) -> Option<Message> {
+        self.faults
+            .read()
+            .unwrap()
+            .iter()
+            .try_fold(message, |message, fault| {
+                fault.tamper(keystore, peer_id, message)
+            })
+    }
+}
@@ -0,0 +1,1238 @@
+use crate::codec::{CodecError, MessageReader};
+use crate::config::Config;
+use crate::features::{Capabilities, Feature};
+use crate::keys::encode_hex;
+use crate::leader::LeaderPolicy;
+use crate::limits::MAX_BATCH_OPS;
+use crate::membership::Reconfiguration;
+use crate::merkle::{verify_key_proof, ProofError};
+use crate::messages::{
+    BatchOp, BulkLoadPart, BulkLoadSummary, ClientRequest, ClientResponse, CommitProgress,
+    ErrorCode, FailureReason, GetDiff, GetProof, GetRequestStatus, KeyProof, Message, NodeStatus,
+    Operation, RequestStatus, RequestStatusReport, ResubmitHint, StatusRequest, WatchProgress,
+};
+use crate::registry::{self, ClusterRegistry};
+use crate::time;
+use crate::{ClientId, Key, NodeId, Value};
+
+use ed25519_dalek::{Keypair, PublicKey};
+use log::{info, warn};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+
+/// f + 1 signed matching responses for a request (2f + 1 for a read-only request)
+#[derive(Debug, Clone, Serialize)]
+pub struct VoteCertificate {
+    pub timestamp: usize,
+    pub votes: Vec<ClientResponse>,
+}
+
+impl VoteCertificate {
+    /// The response the replicas agreed on
+    pub fn response(&self) -> &ClientResponse {
+        self.votes.first().unwrap()
+    }
+}
+
+/// Signed progress of f + 1 replicas which reached a point, at least one of which is correct
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressCertificate {
+    pub reports: Vec<CommitProgress>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClientError {
+    /// The responses for the request conflict such that no result
+    /// can reach enough matching votes anymore
+    ConflictingReplies {
+        timestamp: usize,
+        evidence: Vec<ClientResponse>,
+    },
+    /// No result reached enough matching votes, even after the request was sent again
+    Timeout { timestamp: usize },
+    /// The replicas agreed to reject the request
+    Rejected {
+        timestamp: usize,
+        reason: FailureReason,
+        /// Value of the key, if a compare-and-swap did not find the expected value
+        previous: Option<Value>,
+    },
+    /// The batch has more operations than a request can carry (`limits::MAX_BATCH_OPS`)
+    BatchTooLarge { ops: usize },
+    /// The replicas applied fewer operations of the bulk load than it has, from its start,
+    /// as when the primary changed while the load streamed
+    BulkLoadIncomplete {
+        timestamp: usize,
+        loaded: usize,
+        ops: usize,
+    },
+    /// The replica asked for a key proof did not answer in time
+    NoProof { node: NodeId },
+    /// The key proof of the replica does not verify
+    InvalidProof(ProofError),
+    /// Fewer than f + 1 replicas answered with the diff between the checkpoints
+    NoDiff {
+        from_seq_num: usize,
+        to_seq_num: usize,
+    },
+    /// Too few replicas support the feature the operation relies on (see `PbftClient::supports`)
+    Unsupported { feature: Feature },
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::ConflictingReplies { timestamp, .. } => {
+                write!(f, "conflicting replies for request {}", timestamp)
+            }
+            ClientError::Timeout { timestamp } => write!(f, "request {} timed out", timestamp),
+            ClientError::Rejected {
+                timestamp, reason, ..
+            } => write!(f, "request {} was rejected: {}", timestamp, reason),
+            ClientError::BatchTooLarge { ops } => {
+                write!(f, "a batch of {} operations is too large", ops)
+            }
+            ClientError::BulkLoadIncomplete {
+                timestamp,
+                loaded,
+                ops,
+            } => write!(
+                f,
+                "bulk load {} applied {} of {} operations",
+                timestamp, loaded, ops
+            ),
+            ClientError::NoProof { node } => write!(f, "node {} sent no key proof", node),
+            ClientError::InvalidProof(e) => write!(f, "invalid key proof: {}", e),
+            ClientError::NoDiff {
+                from_seq_num,
+                to_seq_num,
+            } => write!(
+                f,
+                "too few nodes sent the diff from seq-num {} to {}",
+                from_seq_num, to_seq_num
+            ),
+            ClientError::Unsupported { feature } => {
+                write!(f, "too few nodes support {}", feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl ClientError {
+    /// The code applications branch on
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ClientError::ConflictingReplies { .. } => ErrorCode::ConflictingReplies,
+            ClientError::Timeout { .. }
+            | ClientError::NoProof { .. }
+            | ClientError::NoDiff { .. } => ErrorCode::QuorumUnavailable,
+            ClientError::Rejected { reason, .. } => reason.code(),
+            ClientError::BatchTooLarge { .. } => ErrorCode::PayloadTooLarge,
+            ClientError::BulkLoadIncomplete { .. } => ErrorCode::Unordered,
+            ClientError::InvalidProof(_) => ErrorCode::InvalidProof,
+            ClientError::Unsupported { .. } => ErrorCode::Unsupported,
+        }
+    }
+}
+
+/// What became of a request the client is waiting for
+enum Outcome {
+    Certified(VoteCertificate),
+    Conflicting(Vec<ClientResponse>),
+    /// The replicas answered a read-only request with different values, as replicas which
+    /// are behind do, and no value can reach enough matching votes anymore
+    Inconclusive,
+    /// f + 1 replicas executed a later request of the client, so the request never will be,
+    /// or was but the replicas no longer have the reply
+    Superseded,
+    /// A replica reported that the primary of a later view filled the sequence number of the
+    /// request with a no-op, so the request is to be sent again
+    Unordered(ResubmitHint),
+}
+
+/// A request the client is waiting for the responses to
+struct Waiter {
+    threshold: usize,
+    read_only: bool,
+    /// The request, if it is ordered, which a reconciliation may send again
+    request: Option<ClientRequest>,
+    /// Whether a replica reporting the request unordered ends the wait. It does so only once
+    /// for each request, so that a faulty replica cannot use up the retries of the client
+    resubmit_on_unordered: bool,
+    tx_outcome: oneshot::Sender<Outcome>,
+}
+
+/// Collects the responses of the replicas, and hands the outcome of each request to the
+/// client waiting for it
+#[derive(Clone)]
+struct VoteCounter {
+    /// Maps a timestamp to the responses we received for it, indexed by node id
+    votes: Arc<Mutex<HashMap<usize, HashMap<NodeId, ClientResponse>>>>,
+    waiters: Arc<Mutex<HashMap<usize, Waiter>>>,
+    /// Requests for key proofs waiting for the replica to answer
+    proof_waiters: Arc<Mutex<HashMap<Key, oneshot::Sender<KeyProof>>>>,
+    /// The reconciliation waiting for the replicas to report the status of requests
+    status_waiter: Arc<Mutex<Option<mpsc::UnboundedSender<RequestStatusReport>>>>,
+    /// Maps a timestamp to the reply certificate of the completed request,
+    /// retained as a proof of the operation
+    certificates: Arc<Mutex<HashMap<usize, VoteCertificate>>>,
+    /// Capabilities each replica last advertised, in a response or its status
+    capabilities: Arc<Mutex<HashMap<NodeId, Capabilities>>>,
+    /// Number of matching responses needed to accept a result (f + 1)
+    vote_threshold: usize,
+    /// Number of nodes in the cluster
+    num_nodes: usize,
+}
+
+/// Client of a PBFT cluster which applications can embed.
+///
+/// The replicas send their responses to the address the client listens on, so `run` must be
+/// polled (or spawned) for requests to complete. A request is sent to the primary of the
+/// view the replicas last reported, or through the first healthy relay replica if relays
+/// are configured. If f + 1 replicas do not agree on a result in time, it is broadcast to
+/// every replica, which answer it again from their reply cache if they already executed it.
+/// When the replicas report a new view, the requests still waiting are reconciled
+/// (see `reconcile`) rather than sent again blindly
+#[derive(Clone)]
+pub struct PbftClient {
+    peer_addrs: HashMap<NodeId, SocketAddr>,
+    num_faulty: usize,
+    listen_addr: SocketAddr,
+    vote_counter: VoteCounter,
+    timestamp: Arc<AtomicUsize>,
+    /// Latest view f + 1 replicas reported in their responses, whose primary is sent requests first
+    view: Arc<AtomicUsize>,
+    /// Replicas which requests are submitted through in relay mode, in order of preference.
+    /// Requests fail over to the next healthy relay, or are broadcast if none is healthy
+    relays: Vec<NodeId>,
+    /// Relays which failed their last health check or did not pass back a response in time
+    unhealthy_relays: Arc<Mutex<HashSet<NodeId>>>,
+    /// Chooses the primary of each view, as the replicas do
+    leader_election: LeaderPolicy,
+    /// Held while reconciling, so that reconciliations do not overlap
+    reconciling: Arc<tokio::sync::Mutex<()>>,
+    /// Public keys of the nodes, used to verify key proofs
+    pub_keys: Arc<HashMap<NodeId, PublicKey>>,
+    /// Id of the client and the key it signs its requests with, if it has an identity
+    identity: Option<(ClientId, Arc<Keypair>)>,
+    /// How long the replicas have to agree on a result before the request is sent again
+    pub request_timeout: Duration,
+    /// How many times a request is sent again before it times out
+    pub max_retries: usize,
+    /// How long the replicas have to agree on the value read by a read-only request
+    /// before the read is ordered instead
+    pub read_only_timeout: Duration,
+    /// How often relays are checked for health
+    pub relay_check_interval: Duration,
+}
+
+impl PbftClient {
+    /// Client of the cluster which listens for responses on the given address
+    pub fn new(config: &Config, listen_addr: SocketAddr) -> Self {
+        Self {
+            peer_addrs: config.peer_addrs.clone(),
+            num_faulty: config.num_faulty,
+            listen_addr,
+            vote_counter: VoteCounter {
+                votes: Arc::new(Mutex::new(HashMap::new())),
+                waiters: Arc::new(Mutex::new(HashMap::new())),
+                proof_waiters: Arc::new(Mutex::new(HashMap::new())),
+                status_waiter: Arc::new(Mutex::new(None)),
+                certificates: Arc::new(Mutex::new(HashMap::new())),
+                capabilities: Arc::new(Mutex::new(HashMap::new())),
+                // at least one of f + 1 matching responses is from a correct node
+                vote_threshold: config.num_faulty + 1,
+                num_nodes: config.num_nodes,
+            },
+            // replicas ignore requests older than the last one they replied to from this
+            // address, so a restarted client continues from the current time rather than zero
+            timestamp: Arc::new(AtomicUsize::new(time::unix_millis() as usize)),
+            view: Arc::new(AtomicUsize::new(config.initial_view)),
+            leader_election: config.leader_election.clone(),
+            reconciling: Arc::new(tokio::sync::Mutex::new(())),
+            relays: Vec::new(),
+            unhealthy_relays: Arc::new(Mutex::new(HashSet::new())),
+            pub_keys: Arc::new(config.peer_pub_keys.clone()),
+            identity: None,
+            request_timeout: Duration::from_secs(5),
+            max_retries: 3,
+            read_only_timeout: Duration::from_secs(1),
+            relay_check_interval: Duration::from_secs(2),
+        }
+    }
+
+    /// Submits requests through the given replicas, in order of preference
+    pub fn with_relays(mut self, relays: Vec<NodeId>) -> Self {
+        self.relays = relays;
+        self
+    }
+
+    /// Verifies key proofs against the given public keys of the replicas
+    pub fn with_pub_keys(mut self, pub_keys: HashMap<NodeId, PublicKey>) -> Self {
+        self.pub_keys = Arc::new(pub_keys);
+        self
+    }
+
+    /// Trusts the keys of the members of the registry, e.g. one read from a snapshot
+    /// exported by a replica, rather than the keys of the configuration
+    pub fn with_registry(mut self, registry: &ClusterRegistry) -> Self {
+        self.pub_keys = Arc::new(registry.pub_keys());
+        self.num_faulty = registry.num_faulty;
+        self
+    }
+
+    /// Signs the requests as the client with the id, which must be registered with the
+    /// public key of the keypair (see `register`). The replicas then know the client by its
+    /// id, whatever address its requests come from, e.g. from behind a NAT. Bulk loads are
+    /// still known by the address the client listens on
+    pub fn with_identity(mut self, client_id: ClientId, keypair: Keypair) -> Self {
+        self.identity = Some((client_id, Arc::new(keypair)));
+        self
+    }
+
+    /// Registers the public key of the keypair as the identity of the client with the id,
+    /// returning the client which signs its requests with it. Registering again with the
+    /// same key succeeds, while an id registered with another key is rejected as reserved
+    pub async fn register(
+        &self,
+        client_id: ClientId,
+        keypair: Keypair,
+    ) -> Result<PbftClient, ClientError> {
+        let operation = Operation::Set(Value::from(encode_hex(keypair.public.as_bytes())));
+        let client = self.clone().with_identity(client_id, keypair);
+        accepted(
+            client
+                .execute(registry::client_key(client_id), operation)
+                .await?,
+        )?;
+        Ok(client)
+    }
+
+    /// Listens for the responses of the replicas and checks the health of the relays.
+    /// Only returns if the listen address cannot be bound
+    pub async fn run(&self) -> std::io::Result<()> {
+        let listener = TcpListener::bind(self.listen_addr).await?;
+        tokio::select! {
+            _ = self.listen(listener) => {}
+            _ = self.check_relays() => {}
+        }
+        Ok(())
+    }
+
+    /// Reads the key. The replicas answer a read-only request from their committed state
+    /// without ordering it, and unless 2f + 1 of them agree on the value in time,
+    /// the key is read again through the ordered path
+    pub async fn get(&self, key: Key) -> Result<Option<Value>, ClientError> {
+        accepted(self.read(key).await?).map(|response| response.value)
+    }
+
+    /// Sets the key to the value
+    pub async fn set(&self, key: Key, value: Value) -> Result<(), ClientError> {
+        accepted(self.execute(key, Operation::Set(value)).await?).map(|_| ())
+    }
+
+    /// Reads the key like `get`, returning the certificate of the responses. The read is
+    /// ordered right away if too few replicas answer read-only requests
+    pub async fn read(&self, key: Key) -> Result<VoteCertificate, ClientError> {
+        if !self.supports(Feature::ReadOnly) {
+            return self.execute(key, Operation::Get).await;
+        }
+        let request = self.request(key.clone(), Operation::Get, true, Vec::new());
+        let time_stamp = request.time_stamp;
+        let rx_outcome = self.wait_for(&request, 2 * self.num_faulty + 1);
+        self.send(request, 0).await;
+        if let Ok(Ok(Outcome::Certified(certificate))) =
+            timeout(self.read_only_timeout, rx_outcome).await
+        {
+            return Ok(certificate);
+        }
+        self.forget(time_stamp);
+        info!(
+            "Replicas did not agree on the read-only request with timestamp {}, ordering the read",
+            time_stamp
+        );
+        self.execute(key, Operation::Get).await
+    }
+
+    /// Orders the operation on the key, returning the certificate of the responses.
+    /// A rejected request is certified like any other
+    pub async fn execute(
+        &self,
+        key: Key,
+        operation: Operation,
+    ) -> Result<VoteCertificate, ClientError> {
+        let request = self.request(key, operation, false, Vec::new());
+        self.order(request).await
+    }
+
+    /// Applies the puts and deletes atomically: either all of them are applied or, if one
+    /// is rejected, none of them are. The response carries the previous value of the key
+    /// of each operation
+    pub async fn batch(&self, ops: Vec<BatchOp>) -> Result<VoteCertificate, ClientError> {
+        if ops.len() > MAX_BATCH_OPS {
+            return Err(ClientError::BatchTooLarge { ops: ops.len() });
+        }
+        self.require(Feature::Batches)?;
+        let request = self.request(Key::default(), Operation::Get, false, ops);
+        self.order(request).await
+    }
+
+    /// Loads the puts and deletes in bulk, e.g. to fill a new cluster. The operations are
+    /// streamed to the primary, which orders them in batches as large as a request can carry.
+    /// The replicas do not answer each batch, but the load as a whole, with the certificate
+    /// of how many operations they applied. Unlike a batch, the load is not atomic: if the
+    /// primary changes while it streams, the replicas apply the operations up to the first
+    /// batch which was lost, and the load fails with how far it got
+    pub async fn bulk_load(&self, ops: Vec<BatchOp>) -> Result<VoteCertificate, ClientError> {
+        self.require(Feature::BulkLoads)?;
+        let time_stamp = self.timestamp.fetch_add(1, Ordering::SeqCst);
+        let num_ops = ops.len();
+        let (tx_outcome, rx_outcome) = oneshot::channel();
+        self.vote_counter.waiters.lock().unwrap().insert(
+            time_stamp,
+            Waiter {
+                threshold: self.vote_counter.vote_threshold,
+                read_only: false,
+                request: None,
+                resubmit_on_unordered: false,
+                tx_outcome,
+            },
+        );
+
+        let primary = self.primary();
+        let num_parts = num_ops.div_ceil(MAX_BATCH_OPS).max(1);
+        let mut parts = ops.chunks(MAX_BATCH_OPS);
+        for index in 0..num_parts {
+            let part = Message::BulkLoadMessage(BulkLoadPart {
+                respond_addr: self.listen_addr,
+                time_stamp,
+                offset: index * MAX_BATCH_OPS,
+                ops: parts.next().map(<[BatchOp]>::to_vec).unwrap_or_default(),
+                last: index + 1 == num_parts,
+            });
+            self.send_to(primary, &part).await;
+        }
+
+        // every part gets the time a request gets to be ordered
+        let load_timeout = self.request_timeout.saturating_mul(num_parts as u32);
+        let certificate = match timeout(load_timeout, rx_outcome).await {
+            Ok(Ok(Outcome::Certified(certificate))) => certificate,
+            Ok(Ok(Outcome::Conflicting(evidence))) => {
+                return Err(ClientError::ConflictingReplies {
+                    timestamp: time_stamp,
+                    evidence,
+                })
+            }
+            _ => {
+                self.forget(time_stamp);
+                return Err(ClientError::Timeout {
+                    timestamp: time_stamp,
+                });
+            }
+        };
+        let response = certificate.response();
+        if let Some(reason) = response.reason {
+            return Err(ClientError::Rejected {
+                timestamp: time_stamp,
+                reason,
+                previous: None,
+            });
+        }
+        let loaded = response.bulk.map_or(0, |summary| summary.ops);
+        if loaded < num_ops {
+            return Err(ClientError::BulkLoadIncomplete {
+                timestamp: time_stamp,
+                loaded,
+                ops: num_ops,
+            });
+        }
+        Ok(certificate)
+    }
+
+    /// Asks a single node (the active relay replica, if any, else the primary) for the value
+    /// of the key with a proof against its latest stable checkpoint, and verifies the proof
+    pub async fn get_proof(&self, key: Key) -> Result<(KeyProof, Value), ClientError> {
+        self.require(Feature::KeyProofs)?;
+        let node_id = self.active_relay().unwrap_or_else(|| self.primary());
+        let (tx_proof, rx_proof) = oneshot::channel();
+        self.vote_counter
+            .proof_waiters
+            .lock()
+            .unwrap()
+            .insert(key.clone(), tx_proof);
+        let get_proof_message = Message::GetProofMessage(GetProof {
+            respond_addr: self.listen_addr,
+            key: key.clone(),
+        });
+        self.send_to(node_id, &get_proof_message).await;
+        let key_proof = match timeout(self.request_timeout, rx_proof).await {
+            Ok(Ok(key_proof)) => key_proof,
+            _ => {
+                self.vote_counter.proof_waiters.lock().unwrap().remove(&key);
+                return Err(ClientError::NoProof { node: node_id });
+            }
+        };
+        match verify_key_proof(&key_proof, &self.pub_keys, self.num_faulty) {
+            Ok(value) => Ok((key_proof, value)),
+            Err(e) => Err(ClientError::InvalidProof(e)),
+        }
+    }
+
+    /// Fetches the registry of the cluster from a single node, with a proof against its
+    /// latest stable checkpoint which is verified like any key proof
+    pub async fn registry(&self) -> Result<(KeyProof, ClusterRegistry), ClientError> {
+        let (snapshot, value) = self.get_proof(registry::registry_key()).await?;
+        let registry = ClusterRegistry::decode(&value)
+            .ok_or(ClientError::InvalidProof(ProofError::InvalidInclusion))?;
+        Ok((snapshot, registry))
+    }
+
+    /// Submits the reconfiguration signed by the root key of the cluster. The replicas switch
+    /// to the new membership once the request is covered by a stable checkpoint
+    pub async fn reconfigure(&self, reconfiguration: &Reconfiguration) -> Result<(), ClientError> {
+        let operation = Operation::Set(reconfiguration.encode());
+        accepted(self.execute(registry::registry_key(), operation).await?).map(|_| ())
+    }
+
+    /// Keys written after the stable checkpoint at `from_seq_num`, up to and including the one
+    /// at `to_seq_num`, for a reader which synced the store up to the first to fetch the keys
+    /// which changed rather than replay the log. This is the union of the diffs of f + 1
+    /// replicas, one of which is correct, so a faulty replica cannot leave a key out
+    pub async fn checkpoint_diff(
+        &self,
+        from_seq_num: usize,
+        to_seq_num: usize,
+    ) -> Result<BTreeSet<Key>, ClientError> {
+        let mut requests = JoinSet::new();
+        for (node_id, addr) in self.peer_addrs.iter() {
+            let (node_id, addr) = (*node_id, *addr);
+            requests.spawn(timeout(self.request_timeout, async move {
+                let mut stream = TcpStream::connect(addr).await.ok()?;
+                let request = Message::GetDiffMessage(GetDiff {
+                    from_seq_num,
+                    to_seq_num: Some(to_seq_num),
+                });
+                stream
+                    .write_all(request.serialize().as_slice())
+                    .await
+                    .ok()?;
+                match MessageReader::new(stream).read().await.ok()?? {
+                    Message::CheckpointDiffMessage(diff) if diff.id == node_id => diff.keys,
+                    _ => None,
+                }
+            }));
+        }
+        let mut keys = BTreeSet::new();
+        let mut num_diffs = 0;
+        while let Some(joined) = requests.join_next().await {
+            if let Ok(Ok(Some(diff))) = joined {
+                keys.extend(diff);
+                num_diffs += 1;
+                if num_diffs > self.num_faulty {
+                    return Ok(keys);
+                }
+            }
+        }
+        Err(ClientError::NoDiff {
+            from_seq_num,
+            to_seq_num,
+        })
+    }
+
+    /// Asks every replica for its status, and records the protocol version and features it
+    /// advertises. Replicas also advertise them in each response, so this is only needed to
+    /// learn them before the first request, or again after replicas were upgraded.
+    /// Returns the capabilities of the replicas which answered in time
+    pub async fn negotiate(&self) -> HashMap<NodeId, Capabilities> {
+        let mut requests = JoinSet::new();
+        for node_id in self.peer_addrs.keys().copied() {
+            let client = self.clone();
+            requests.spawn(async move { (node_id, client.status(node_id).await) });
+        }
+        let mut capabilities = HashMap::new();
+        while let Some(joined) = requests.join_next().await {
+            if let Ok((node_id, Some(status))) = joined {
+                capabilities.insert(node_id, status.capabilities);
+            }
+        }
+        self.vote_counter
+            .capabilities
+            .lock()
+            .unwrap()
+            .extend(capabilities.iter());
+        capabilities
+    }
+
+    /// Whether the client relies on the feature: at least 2f + 1 replicas, a quorum, must
+    /// support it. Replicas the client has not heard from yet are assumed to support it,
+    /// and replicas which advertised no capabilities, as versions before their advertisement
+    /// do, are assumed not to
+    pub fn supports(&self, feature: Feature) -> bool {
+        let capabilities = self.vote_counter.capabilities.lock().unwrap();
+        let lacking = self
+            .peer_addrs
+            .keys()
+            .filter(|node_id| {
+                capabilities
+                    .get(node_id)
+                    .is_some_and(|capabilities| !capabilities.features.contains(feature))
+            })
+            .count();
+        self.peer_addrs.len() - lacking > 2 * self.num_faulty
+    }
+
+    fn require(&self, feature: Feature) -> Result<(), ClientError> {
+        match self.supports(feature) {
+            true => Ok(()),
+            false => Err(ClientError::Unsupported { feature }),
+        }
+    }
+
+    /// Waits until f + 1 replicas committed the sequence number, so that a correct replica
+    /// has, e.g. the one a request whose response carried that sequence number was
+    /// committed at. This waits as long as it takes, so callers bound it with a timeout
+    pub async fn wait_for_seq(&self, seq_num: usize) -> ProgressCertificate {
+        self.watch_progress(None, |progress| progress.last_seq_num_committed >= seq_num)
+            .await
+    }
+
+    /// Waits until f + 1 replicas report the key at the version or a later one. The version
+    /// of a key is the sequence number of the last request which wrote it, which the
+    /// response to a write carries. This waits as long as it takes, like `wait_for_seq`
+    pub async fn wait_for_key_version(&self, key: Key, min_version: usize) -> ProgressCertificate {
+        self.watch_progress(Some(key), |progress| progress.version >= min_version)
+            .await
+    }
+
+    /// Timestamps of the requests still waiting for the replicas to agree, in order
+    pub fn outstanding(&self) -> Vec<usize> {
+        let mut timestamps: Vec<usize> = self
+            .vote_counter
+            .waiters
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        timestamps.sort_unstable();
+        timestamps
+    }
+
+    /// Reply certificate of the completed request with the given timestamp
+    pub fn certificate(&self, timestamp: usize) -> Option<VoteCertificate> {
+        self.vote_counter
+            .certificates
+            .lock()
+            .unwrap()
+            .get(&timestamp)
+            .cloned()
+    }
+
+    /// Writes the reply certificates of all completed requests to the file as a JSON array,
+    /// ordered by timestamp. Returns the number of certificates written
+    pub fn export_certificates(&self, path: &Path) -> std::io::Result<usize> {
+        let certificates = self.vote_counter.certificates.lock().unwrap();
+        let mut timestamps: Vec<&usize> = certificates.keys().collect();
+        timestamps.sort();
+        let ordered: Vec<&VoteCertificate> = timestamps
+            .into_iter()
+            .map(|timestamp| certificates.get(timestamp).unwrap())
+            .collect();
+        std::fs::write(path, serde_json::to_vec_pretty(&ordered)?)?;
+        Ok(ordered.len())
+    }
+
+    async fn listen(&self, listener: TcpListener) {
+        loop {
+            if let Ok((stream, _)) = listener.accept().await {
+                let vote_counter = self.vote_counter.clone();
+                tokio::spawn(async move {
+                    let _ = vote_counter.read_responses(stream).await;
+                });
+            }
+        }
+    }
+
+    /// Next request of the client
+    fn request(
+        &self,
+        key: Key,
+        operation: Operation,
+        read_only: bool,
+        batch: Vec<BatchOp>,
+    ) -> ClientRequest {
+        let request = ClientRequest {
+            respond_addr: self.listen_addr,
+            time_stamp: self.timestamp.fetch_add(1, Ordering::SeqCst),
+            key,
+            operation,
+            relay_id: None,
+            read_only,
+            batch,
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        };
+        match &self.identity {
+            Some((client_id, keypair)) => request.signed_by(*client_id, keypair),
+            None => request,
+        }
+    }
+
+    /// Sends the request, and again to every replica each time f + 1 of them
+    /// do not agree on a result in time
+    async fn order(&self, request: ClientRequest) -> Result<VoteCertificate, ClientError> {
+        let time_stamp = request.time_stamp;
+        let mut rx_outcome = self.wait_for(&request, self.vote_counter.vote_threshold);
+        for attempt in 0..=self.max_retries {
+            let relay_id = self.send(request.clone(), attempt).await;
+            match timeout(self.request_timeout, &mut rx_outcome).await {
+                Ok(Ok(Outcome::Certified(certificate))) => {
+                    if self.learn_view(&certificate) {
+                        // requests sent to the former primary may or may not have been ordered
+                        let client = self.clone();
+                        tokio::spawn(async move { client.reconcile().await });
+                    }
+                    return Ok(certificate);
+                }
+                Ok(Ok(Outcome::Superseded)) => {
+                    return Err(ClientError::Rejected {
+                        timestamp: time_stamp,
+                        reason: FailureReason::StaleTimestamp,
+                        previous: None,
+                    })
+                }
+                Ok(Ok(Outcome::Conflicting(evidence))) => {
+                    return Err(ClientError::ConflictingReplies {
+                        timestamp: time_stamp,
+                        evidence,
+                    })
+                }
+                Ok(Ok(Outcome::Unordered(resubmit_hint))) => {
+                    // the next attempt sends the request to every replica
+                    info!(
+                        "Request with timestamp {} was not ordered, sending it again in view {}",
+                        time_stamp, resubmit_hint.view
+                    );
+                    rx_outcome = self.wait_for(&request, self.vote_counter.vote_threshold);
+                    if let Some(waiter) = self
+                        .vote_counter
+                        .waiters
+                        .lock()
+                        .unwrap()
+                        .get_mut(&time_stamp)
+                    {
+                        waiter.resubmit_on_unordered = false;
+                    }
+                }
+                Ok(Ok(Outcome::Inconclusive)) | Ok(Err(_)) => break,
+                Err(_) => {
+                    if let Some(relay_id) = relay_id {
+                        warn!(
+                            "Relay {} did not handle request with timestamp {}, failing over",
+                            relay_id, time_stamp
+                        );
+                        self.unhealthy_relays.lock().unwrap().insert(relay_id);
+                    }
+                }
+            }
+        }
+        self.forget(time_stamp);
+        Err(ClientError::Timeout {
+            timestamp: time_stamp,
+        })
+    }
+
+    /// Registers the request, whose outcome is sent on the returned channel
+    fn wait_for(&self, request: &ClientRequest, threshold: usize) -> oneshot::Receiver<Outcome> {
+        let (tx_outcome, rx_outcome) = oneshot::channel();
+        self.vote_counter.waiters.lock().unwrap().insert(
+            request.time_stamp,
+            Waiter {
+                threshold,
+                read_only: request.read_only,
+                request: (!request.read_only).then(|| request.clone()),
+                resubmit_on_unordered: !request.read_only,
+                tx_outcome,
+            },
+        );
+        rx_outcome
+    }
+
+    /// Asks every replica what became of the ordered requests still waiting for them, as
+    /// requests in flight when the view changed may or may not have been ordered. Requests
+    /// f + 1 replicas executed are answered again from their reply caches, and those f + 1
+    /// replicas executed a later request than fail as stale. Only the others are sent again,
+    /// to every replica, so that no request is lost nor sent again once executed.
+    /// Returns the status of each request, as f + 1 replicas reported it
+    pub async fn reconcile(&self) -> BTreeMap<usize, RequestStatus> {
+        let _reconciling = self.reconciling.lock().await;
+        let requests: BTreeMap<usize, ClientRequest> = self
+            .vote_counter
+            .waiters
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(time_stamp, waiter)| Some((*time_stamp, waiter.request.clone()?)))
+            .collect();
+        if requests.is_empty() {
+            return BTreeMap::new();
+        }
+
+        let (tx_reports, mut rx_reports) = mpsc::unbounded_channel();
+        *self.vote_counter.status_waiter.lock().unwrap() = Some(tx_reports);
+        let query = Message::GetRequestStatusMessage(GetRequestStatus {
+            respond_addr: self.listen_addr,
+            time_stamps: requests.keys().copied().collect(),
+            client_id: self.identity.as_ref().map(|(client_id, _)| *client_id),
+        });
+        self.broadcast_message(&query).await;
+
+        let mut reports: HashMap<NodeId, HashMap<usize, RequestStatus>> = HashMap::new();
+        let mut statuses = BTreeMap::new();
+        let collect = async {
+            while let Some(report) = rx_reports.recv().await {
+                let pub_key = self.pub_keys.get(&report.id);
+                if report.respond_addr != self.listen_addr
+                    || pub_key.is_some_and(|pub_key| !report.is_properly_signed_by(pub_key))
+                {
+                    warn!("Dropping request statuses reported for node {}", report.id);
+                    continue;
+                }
+                reports.insert(report.id, report.statuses.into_iter().collect());
+                statuses = requests
+                    .keys()
+                    .filter_map(|time_stamp| {
+                        let status = self.agreed_status(&reports, *time_stamp)?;
+                        Some((*time_stamp, status))
+                    })
+                    .collect();
+                if statuses.len() == requests.len() || reports.len() == self.peer_addrs.len() {
+                    return;
+                }
+            }
+        };
+        let _ = timeout(self.request_timeout, collect).await;
+        *self.vote_counter.status_waiter.lock().unwrap() = None;
+
+        for (time_stamp, request) in requests {
+            let status = *statuses.entry(time_stamp).or_insert(RequestStatus::Unknown);
+            match status {
+                // the replies sent again complete the request
+                RequestStatus::Executed => {}
+                RequestStatus::Superseded => {
+                    let waiter = self
+                        .vote_counter
+                        .waiters
+                        .lock()
+                        .unwrap()
+                        .remove(&time_stamp);
+                    if let Some(waiter) = waiter {
+                        let _ = waiter.tx_outcome.send(Outcome::Superseded);
+                    }
+                }
+                RequestStatus::Unknown => {
+                    info!("Sending request with timestamp {} again", time_stamp);
+                    self.broadcast_message(&Message::ClientRequestMessage(request))
+                        .await;
+                }
+            }
+        }
+        statuses
+    }
+
+    /// The status f + 1 replicas reported for the request, if any
+    fn agreed_status(
+        &self,
+        reports: &HashMap<NodeId, HashMap<usize, RequestStatus>>,
+        time_stamp: usize,
+    ) -> Option<RequestStatus> {
+        let mut counts: HashMap<RequestStatus, usize> = HashMap::new();
+        for status in reports
+            .values()
+            .filter_map(|report| report.get(&time_stamp))
+        {
+            *counts.entry(*status).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .find(|(_, count)| *count > self.num_faulty)
+            .map(|(status, _)| status)
+    }
+
+    fn forget(&self, time_stamp: usize) {
+        self.vote_counter
+            .waiters
+            .lock()
+            .unwrap()
+            .remove(&time_stamp);
+    }
+
+    /// Watches the progress of every replica until f + 1 of them reach the point.
+    /// A replica whose connection fails is watched again after the request timeout
+    async fn watch_progress(
+        &self,
+        key: Option<Key>,
+        reached: impl Fn(&CommitProgress) -> bool,
+    ) -> ProgressCertificate {
+        let (tx_progress, mut rx_progress) = mpsc::channel(self.peer_addrs.len());
+        // the watches are aborted when this returns, or is dropped
+        let mut watches = JoinSet::new();
+        for (node_id, addr) in self.peer_addrs.iter() {
+            let (node_id, addr) = (*node_id, *addr);
+            let watch = WatchProgress { key: key.clone() };
+            let pub_key = self.pub_keys.get(&node_id).copied();
+            let tx_progress = tx_progress.clone();
+            let retry_interval = self.request_timeout;
+            watches.spawn(async move {
+                loop {
+                    let _ = watch_node(node_id, addr, &watch, pub_key, &tx_progress).await;
+                    sleep(retry_interval).await;
+                }
+            });
+        }
+
+        let mut reports = HashMap::new();
+        loop {
+            // the watches never end, so neither does the channel
+            let progress = rx_progress.recv().await.unwrap();
+            if progress.key == key && reached(&progress) {
+                reports.insert(progress.id, progress);
+            }
+            if reports.len() > self.num_faulty {
+                return ProgressCertificate {
+                    reports: reports.into_values().collect(),
+                };
+            }
+        }
+    }
+
+    /// Sends the request through the active relay, returning the relay. Without a healthy
+    /// relay, the first attempt goes to the primary and the next ones to every replica.
+    /// Read-only requests always go to every replica, as 2f + 1 of them must answer
+    async fn send(&self, mut request: ClientRequest, attempt: usize) -> Option<NodeId> {
+        while let Some(relay_id) = self.active_relay() {
+            request.relay_id = Some(relay_id);
+            let message = Message::ClientRequestMessage(request.clone());
+            if self.relay_message(relay_id, &message).await {
+                return Some(relay_id);
+            }
+            warn!("Could not reach relay {}, failing over", relay_id);
+            self.unhealthy_relays.lock().unwrap().insert(relay_id);
+        }
+        request.relay_id = None;
+        let read_only = request.read_only;
+        let message = Message::ClientRequestMessage(request);
+        if attempt == 0 && !read_only && self.relays.is_empty() {
+            self.send_to(self.primary(), &message).await;
+        } else {
+            self.broadcast_message(&message).await;
+        }
+        None
+    }
+
+    /// Primary of the latest view the replicas reported
+    fn primary(&self) -> NodeId {
+        self.leader_election
+            .leader(self.view.load(Ordering::SeqCst), self.peer_addrs.len())
+    }
+
+    /// Moves on to the highest view which f + 1 of the votes report, so that at least one
+    /// correct replica is in that view. Returns whether that view is a later one
+    fn learn_view(&self, certificate: &VoteCertificate) -> bool {
+        let mut views: Vec<usize> = certificate.votes.iter().map(|vote| vote.view).collect();
+        views.sort_unstable_by(|a, b| b.cmp(a));
+        match views.get(self.num_faulty) {
+            Some(view) => self.view.fetch_max(*view, Ordering::SeqCst) < *view,
+            None => false,
+        }
+    }
+
+    async fn send_to(&self, node_id: NodeId, message: &Message) {
+        if let Some(addr) = self.peer_addrs.get(&node_id) {
+            if let Ok(mut stream) = TcpStream::connect(addr).await {
+                let _ = stream.write_all(message.serialize().as_slice()).await;
+            }
+        }
+    }
+
+    async fn broadcast_message(&self, message: &Message) {
+        for node_id in self.peer_addrs.keys() {
+            self.send_to(*node_id, message).await;
+        }
+    }
+
+    /// Sends the message to the relay replica and reads
+    /// the responses it passes back over the same connection.
+    /// Returns false if the relay could not be reached
+    async fn relay_message(&self, relay_id: NodeId, message: &Message) -> bool {
+        let addr = self.peer_addrs.get(&relay_id).unwrap();
+        let mut stream = match TcpStream::connect(addr).await {
+            Ok(stream) => stream,
+            Err(_) => return false,
+        };
+        if stream
+            .write_all(message.serialize().as_slice())
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        let vote_counter = self.vote_counter.clone();
+        tokio::spawn(async move {
+            let _ = vote_counter.read_responses(stream).await;
+        });
+        true
+    }
+
+    /// First relay in order of preference which is currently healthy
+    fn active_relay(&self) -> Option<NodeId> {
+        let unhealthy_relays = self.unhealthy_relays.lock().unwrap();
+        self.relays
+            .iter()
+            .find(|relay_id| !unhealthy_relays.contains(relay_id))
+            .copied()
+    }
+
+    /// Periodically asks every relay for its status, so that relays which stopped responding
+    /// are avoided and relays which recovered are used again
+    async fn check_relays(&self) {
+        loop {
+            for relay_id in self.relays.iter() {
+                let healthy = self.is_responsive(*relay_id).await;
+                let mut unhealthy_relays = self.unhealthy_relays.lock().unwrap();
+                if healthy {
+                    unhealthy_relays.remove(relay_id);
+                } else {
+                    unhealthy_relays.insert(*relay_id);
+                }
+            }
+            sleep(self.relay_check_interval).await;
+        }
+    }
+
+    /// Whether the node answers a status request in time and is not in a view change
+    async fn is_responsive(&self, node_id: NodeId) -> bool {
+        matches!(self.status(node_id).await, Some(status) if !status.in_view_change)
+    }
+
+    /// Status of the node, if it answers in time
+    async fn status(&self, node_id: NodeId) -> Option<NodeStatus> {
+        let addr = *self.peer_addrs.get(&node_id)?;
+        let request = async move {
+            let mut stream = TcpStream::connect(addr).await.ok()?;
+            let request = Message::StatusRequestMessage(StatusRequest {});
+            stream
+                .write_all(request.serialize().as_slice())
+                .await
+                .ok()?;
+            match MessageReader::new(stream).read().await.ok()?? {
+                Message::StatusMessage(status) => Some(*status),
+                _ => None,
+            }
+        };
+        timeout(Duration::from_secs(1), request).await.ok()?
+    }
+}
+
+/// The response of a certificate, or the reason the replicas rejected the request
+/// Passes on the progress the node announces until the connection fails.
+/// Announcements which are not the node's own, or are not signed by its key if we know it,
+/// are dropped, so that a faulty replica cannot stand in for others
+async fn watch_node(
+    node_id: NodeId,
+    addr: SocketAddr,
+    watch: &WatchProgress,
+    pub_key: Option<PublicKey>,
+    tx_progress: &mpsc::Sender<CommitProgress>,
+) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let message = Message::WatchProgressMessage(watch.clone());
+    stream.write_all(message.serialize().as_slice()).await?;
+    let mut reader = MessageReader::new(&mut stream);
+    loop {
+        let progress = match reader.read().await {
+            Ok(Some(Message::CommitProgressMessage(progress))) => progress,
+            Ok(Some(_)) | Err(CodecError::Malformed(_)) => continue,
+            Ok(None) => return Ok(()),
+            Err(CodecError::Io(e)) => return Err(e),
+        };
+        if progress.id != node_id
+            || pub_key.is_some_and(|pub_key| !progress.is_properly_signed_by(&pub_key))
+        {
+            warn!(
+                "Dropping progress announced by node {} for node {}",
+                node_id, progress.id
+            );
+            continue;
+        }
+        if tx_progress.send(progress).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn accepted(certificate: VoteCertificate) -> Result<ClientResponse, ClientError> {
+    let response = certificate.response().clone();
+    match response.reason {
+        Some(reason) => Err(ClientError::Rejected {
+            timestamp: certificate.timestamp,
+            reason,
+            previous: response.previous,
+        }),
+        None => Ok(response),
+    }
+}
+
+impl VoteCounter {
+    /// Reads responses from the stream until it is closed. Nodes responding directly
+    /// send one response per connection, while a relay replica passes back all responses
+    async fn read_responses(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut reader = MessageReader::new(&mut stream);
+        loop {
+            match reader.read().await {
+                Ok(Some(response)) => self.read_response(response),
+                Ok(None) => return Ok(()),
+                Err(CodecError::Malformed(_)) => continue,
+                Err(CodecError::Io(e)) => return Err(e),
+            }
+        }
+    }
+
+    fn read_response(&self, response: Message) {
+        let response = match response {
+            Message::ClientResponseMessage(response) => response,
+            Message::KeyProofMessage(key_proof) => {
+                let tx_proof = self.proof_waiters.lock().unwrap().remove(&key_proof.key);
+                if let Some(tx_proof) = tx_proof {
+                    let _ = tx_proof.send(key_proof);
+                }
+                return;
+            }
+            Message::RequestStatusMessage(report) => {
+                if let Some(tx_reports) = self.status_waiter.lock().unwrap().as_ref() {
+                    let _ = tx_reports.send(report);
+                }
+                return;
+            }
+            _ => {
+                /* received a response which was not a client response, so just return */
+                return;
+            }
+        };
+
+        self.capabilities
+            .lock()
+            .unwrap()
+            .insert(response.id, response.capabilities);
+
+        // a single replica is trusted to tell that the request is to be sent again, but not
+        // that it failed, so its report is no vote
+        if let Some(FailureReason::Unordered { resubmit_hint }) = response.reason {
+            let mut waiters = self.waiters.lock().unwrap();
+            let resubmit = waiters
+                .get(&response.time_stamp)
+                .is_some_and(|waiter| waiter.resubmit_on_unordered);
+            if resubmit {
+                let waiter = waiters.remove(&response.time_stamp).unwrap();
+                let _ = waiter.tx_outcome.send(Outcome::Unordered(resubmit_hint));
+            }
+            return;
+        }
+
+        let mut waiters = self.waiters.lock().unwrap();
+        let (vote_threshold, is_read_only) = match waiters.get(&response.time_stamp) {
+            Some(waiter) => (waiter.threshold, waiter.read_only),
+            None => (self.vote_threshold, false),
+        };
+
+        let mut votes = self.votes.lock().unwrap();
+        let curr_votes = votes.entry(response.time_stamp).or_default();
+        curr_votes.insert(response.id, response.clone());
+
+        let matching_votes: Vec<ClientResponse> = curr_votes
+            .values()
+            .filter(|vote| {
+                vote.key == response.key
+                    && vote.value == response.value
+                    && vote.previous == response.previous
+                    && vote.results == response.results
+                    && vote.bulk == response.bulk
+                    && vote.reason == response.reason
+                    && vote.seq_num == response.seq_num
+            })
+            .cloned()
+            .collect();
+
+        let outcome = if matching_votes.len() >= vote_threshold {
+            let certificate = VoteCertificate {
+                timestamp: response.time_stamp,
+                votes: matching_votes,
+            };
+            self.certificates
+                .lock()
+                .unwrap()
+                .entry(response.time_stamp)
+                .or_insert_with(|| certificate.clone());
+            Outcome::Certified(certificate)
+        } else {
+            // check whether any result can still reach enough matching votes
+            // from the nodes which have not responded yet
+            let mut vote_counts = HashMap::<
+                (
+                    &Key,
+                    &Option<Value>,
+                    &Option<Value>,
+                    &Vec<Option<Value>>,
+                    Option<BulkLoadSummary>,
+                    Option<FailureReason>,
+                    usize,
+                ),
+                usize,
+            >::new();
+            for vote in curr_votes.values() {
+                *vote_counts
+                    .entry((
+                        &vote.key,
+                        &vote.value,
+                        &vote.previous,
+                        &vote.results,
+                        vote.bulk,
+                        vote.reason,
+                        vote.seq_num,
+                    ))
+                    .or_insert(0) += 1;
+            }
+            let max_matching = vote_counts.values().copied().max().unwrap_or(0);
+            let num_outstanding = self.num_nodes.saturating_sub(curr_votes.len());
+            if max_matching + num_outstanding >= vote_threshold {
+                return;
+            }
+            if is_read_only {
+                // replicas which are behind answer reads with older values, which is no
+                // evidence of a fault, and the read is ordered instead
+                Outcome::Inconclusive
+            } else {
+                Outcome::Conflicting(curr_votes.values().cloned().collect())
+            }
+        };
+
+        if let Some(waiter) = waiters.remove(&response.time_stamp) {
+            let _ = waiter.tx_outcome.send(outcome);
+        }
+    }
+}
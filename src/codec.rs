@@ -0,0 +1,339 @@
+use crate::messages::Message;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout_at, Instant};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+/// Length of the header of a frame, which holds the length of the payload
+pub const HEADER_LEN: usize = 4;
+
+/// Largest payload accepted, which bounds what a peer can make us buffer.
+/// Checkpoints carry the whole store, so this is well above the size of other messages
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// Number of bytes at the start of a malformed payload which are kept for debugging
+pub const MALFORMED_EXCERPT_LEN: usize = 256;
+
+/// Number of the most recent malformed frames kept for debugging
+const RECENT_MALFORMED_FRAMES: usize = 16;
+
+/// Wire format of messages between nodes and clients: each message is sent as a frame
+/// of a big-endian u32 payload length followed by the JSON encoding of the message.
+/// Unlike newline-delimited messages, frames do not depend on the contents of the payload
+/// and the receiver knows how much to read before it parses anything
+pub struct MessageCodec {
+    frames: LengthDelimitedCodec,
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::with_max_frame_len(MAX_FRAME_LEN)
+    }
+}
+
+impl MessageCodec {
+    /// Codec which rejects frames with a payload over the length
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        Self {
+            frames: LengthDelimitedCodec::builder()
+                .length_field_length(HEADER_LEN)
+                .max_frame_length(max_frame_len)
+                .new_codec(),
+        }
+    }
+}
+
+/// Bounds on how a peer sends us a message, so that it can neither make us buffer more
+/// than the largest frame we accept nor hold a connection open by trickling bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Largest payload accepted. A frame announcing a longer one is refused before
+    /// any of it is buffered
+    pub max_frame_len: usize,
+    /// Time the peer has to send a message on top of the time its payload takes at the
+    /// minimum rate (zero for no deadline)
+    pub timeout: Duration,
+    /// Slowest rate in bytes per second at which a payload must arrive, so that large
+    /// frames such as checkpoints get more time than small ones (zero for none, in which
+    /// case the whole message must arrive within the timeout)
+    pub min_rate: usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_len: MAX_FRAME_LEN,
+            timeout: Duration::ZERO,
+            min_rate: 0,
+        }
+    }
+}
+
+impl ReadLimits {
+    /// Time by which a message started at `started` must have arrived, once the length of
+    /// its payload is known. None if there is no deadline
+    pub fn deadline(&self, started: Instant, len: Option<usize>) -> Option<Instant> {
+        if self.timeout.is_zero() {
+            return None;
+        }
+        let transfer = match (len, self.min_rate) {
+            (Some(len), min_rate) if min_rate > 0 => {
+                Duration::from_secs_f64(len as f64 / min_rate as f64)
+            }
+            _ => Duration::ZERO,
+        };
+        Some(started + self.timeout + transfer)
+    }
+
+    /// Refuses a payload over the maximum frame length
+    pub fn check_len(&self, len: usize) -> std::io::Result<()> {
+        if len > self.max_frame_len {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "frame of {} bytes is over the limit of {} bytes",
+                    len, self.max_frame_len
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the read, failing with a `TimedOut` error if it is not done by the deadline
+pub async fn read_by<T>(
+    deadline: Option<Instant>,
+    read: impl Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, read).await.unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "peer did not send its message in time",
+            ))
+        }),
+        None => read.await,
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, CodecError> {
+        match self.frames.decode(src)? {
+            // a malformed payload still consumes its frame, so the next message can be read
+            Some(frame) => serde_json::from_slice(&frame)
+                .map(Some)
+                .map_err(|e| CodecError::Malformed(MalformedFrame::new(&frame, &e))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let payload = serde_json::to_vec(message)
+            .map_err(|e| CodecError::Malformed(MalformedFrame::new(&[], &e)))?;
+        self.frames.encode(Bytes::from(payload), dst)?;
+        Ok(())
+    }
+}
+
+/// Reads the messages sent over a connection, one frame at a time
+pub struct MessageReader<R> {
+    inner: R,
+    buf: BytesMut,
+    codec: MessageCodec,
+    limits: ReadLimits,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_limits(inner, ReadLimits::default())
+    }
+
+    pub fn with_limits(inner: R, limits: ReadLimits) -> Self {
+        Self {
+            inner,
+            buf: BytesMut::new(),
+            codec: MessageCodec::with_max_frame_len(limits.max_frame_len),
+            limits,
+        }
+    }
+
+    /// Reads the next message, or returns None if the connection was closed between messages.
+    /// Fails with an `InvalidData` error if the frame is over the limit and a `TimedOut` error
+    /// if the peer is too slow to send it
+    pub async fn read(&mut self) -> Result<Option<Message>, CodecError> {
+        let started = Instant::now();
+        let mut deadline = self.limits.deadline(started, None);
+        let mut len = None;
+        loop {
+            if len.is_none() && self.buf.len() >= HEADER_LEN {
+                let header = u32::from_be_bytes(self.buf[..HEADER_LEN].try_into().unwrap());
+                self.limits.check_len(header as usize)?;
+                len = Some(header as usize);
+                deadline = self.limits.deadline(started, len);
+            }
+            if let Some(message) = self.codec.decode(&mut self.buf)? {
+                return Ok(Some(message));
+            }
+            if read_by(deadline, self.inner.read_buf(&mut self.buf)).await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(CodecError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+}
+
+/// Writes the message to the connection as a single frame
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+) -> Result<(), CodecError> {
+    writer.write_all(&message.serialize()).await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// The connection failed, or a frame was cut off or too large
+    Io(std::io::Error),
+    /// The payload of the frame is not a message. The frame is consumed,
+    /// so the next message on the connection can still be read
+    Malformed(MalformedFrame),
+}
+
+impl From<std::io::Error> for CodecError {
+    fn from(e: std::io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "failed to read message ({})", e),
+            CodecError::Malformed(frame) => write!(f, "malformed message {}", frame),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// Payload of a frame which could not be decoded into a message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MalformedFrame {
+    /// What failed to decode, e.g. a missing field or a value of the wrong type
+    pub reason: String,
+    /// Offset in the payload of the byte at which decoding failed
+    pub offset: usize,
+    pub len: usize,
+    /// Start of the payload, up to `MALFORMED_EXCERPT_LEN` bytes
+    pub excerpt: String,
+}
+
+impl MalformedFrame {
+    fn new(payload: &[u8], e: &serde_json::Error) -> Self {
+        // serde_json reports the 1-based line and column of the error
+        let line_start: usize = payload
+            .split(|byte| *byte == b'\n')
+            .take(e.line().saturating_sub(1))
+            .map(|line| line.len() + 1)
+            .sum();
+        let excerpt_len = payload.len().min(MALFORMED_EXCERPT_LEN);
+        Self {
+            reason: e.to_string(),
+            offset: (line_start + e.column())
+                .saturating_sub(1)
+                .min(payload.len()),
+            len: payload.len(),
+            excerpt: String::from_utf8_lossy(&payload[..excerpt_len]).into_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for MalformedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "at byte {} of {} ({})",
+            self.offset, self.len, self.reason
+        )
+    }
+}
+
+/// Malformed frames a node received, shared by its connection handlers
+#[derive(Clone, Default)]
+pub struct MalformedLog {
+    stats: Arc<Mutex<MalformedStats>>,
+}
+
+/// Counts of the malformed frames received from each address, reported in node statuses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MalformedStats {
+    pub by_sender: BTreeMap<IpAddr, usize>,
+    /// The most recent malformed frames with their senders
+    pub recent: VecDeque<(IpAddr, MalformedFrame)>,
+    /// Connections dropped because the peer announced a frame over the maximum length
+    #[serde(default)]
+    pub oversized_frames: usize,
+    /// Connections dropped because the peer did not send its message in time
+    #[serde(default)]
+    pub slow_connections: usize,
+}
+
+impl MalformedStats {
+    pub fn total(&self) -> usize {
+        self.by_sender.values().sum()
+    }
+}
+
+impl MalformedLog {
+    /// Records the frame, returning the number of malformed frames received from the sender
+    pub fn record(&self, sender: IpAddr, frame: MalformedFrame) -> usize {
+        let mut stats = self.stats.lock().unwrap();
+        if stats.recent.len() == RECENT_MALFORMED_FRAMES {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back((sender, frame));
+        let count = stats.by_sender.entry(sender).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Records a connection dropped for breaking the read limits, from the error of the read.
+    /// Returns false if the read failed for another reason
+    pub fn record_abuse(&self, error: &std::io::Error) -> bool {
+        let mut stats = self.stats.lock().unwrap();
+        match error.kind() {
+            ErrorKind::InvalidData => stats.oversized_frames += 1,
+            ErrorKind::TimedOut => stats.slow_connections += 1,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn count(&self, sender: IpAddr) -> usize {
+        let stats = self.stats.lock().unwrap();
+        stats.by_sender.get(&sender).copied().unwrap_or(0)
+    }
+
+    pub fn stats(&self) -> MalformedStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
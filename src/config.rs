@@ -1,26 +1,727 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::authenticator::Authentication;
+use crate::codec::{self, ReadLimits};
+use crate::crypto::DigestPolicy;
+use crate::features::Features;
+use crate::keys::decode_hex;
+use crate::leader::{LeaderPolicy, WeightedRotation};
+use crate::limits;
+use crate::pki::IdentityCertificate;
+use crate::registry::ClusterRegistry;
 use crate::NodeId;
 
+use ed25519_dalek::PublicKey;
+use serde::Deserialize;
+
 #[derive(Clone, Default)]
 pub struct Config {
     /// Number of nodes in the system
     pub num_nodes: usize,
     /// How many faulty nodes the system can tolerate
     pub num_faulty: usize,
-    /// Address which each node is listening on
+    /// Address which each node is reachable on (advertised to peers and clients)
     pub peer_addrs: HashMap<NodeId, SocketAddr>,
+    /// Public keys of the nodes, if known in advance.
+    /// Identifiers announcing another key for one of these nodes are dropped
+    pub peer_pub_keys: HashMap<NodeId, PublicKey>,
+    /// Epoch of the membership above. The nodes of the genesis epoch (0) have the ids 0 to
+    /// n - 1, and every reconfiguration of the membership moves the cluster to the next epoch
+    pub epoch: usize,
+    /// Root key of the cluster. If set, identifiers of nodes without a configured key
+    /// are only accepted with an identity certificate for their key issued by the root key
+    pub root_pub_key: Option<PublicKey>,
+    /// Identity certificate this node presents in its identifiers
+    pub certificate: Option<IdentityCertificate>,
+    /// Addresses this node binds to. If empty, the node binds to its advertised address.
+    /// These differ from the advertised address behind NAT or in containers
+    pub bind_addrs: Vec<SocketAddr>,
     /// How long we wait after receiving a pre-prepare request
     /// which we have not yet executed before initiating a view-change
     pub request_timeout: std::time::Duration,
+    /// Bounds of the view change timers, which start at the request timeout and double with
+    /// every consecutive view change until a request is executed again
+    pub view_change_timeout_min: std::time::Duration,
+    pub view_change_timeout_max: std::time::Duration,
     /// How long a node should wait if it is currently leader
     /// to rebroadcast a pre-prepare which has not been applied to yet
     pub rebroadcast_timeout: std::time::Duration,
-    /// How often a node should broadcast its identity (with pub key) to the network
+    /// How often a node should broadcast its identity (with pub key) to the network.
+    /// Identifiers carry readings of the wall clock, which estimate the skew of the clocks
     pub identity_broadcast_interval: std::time::Duration,
+    /// Skew between the clocks of replicas past which operators are warned (zero for no
+    /// warnings). The protocol does not depend on clocks, but client timestamps and logs do
+    pub clock_skew_alert: std::time::Duration,
+    /// How often a node pings each of its peers to measure the round trip (zero disables pings)
+    pub ping_interval: std::time::Duration,
+    /// Pings in a row a peer may leave unanswered before its connection is taken for dead
+    pub ping_misses: usize,
+    /// Multiple of the round trip to a quorum of peers below which the view change timers
+    /// do not start, so that on slow links views do not change before a request can commit
+    /// (zero to only start them at the request timeout)
+    pub rtt_timeout_factor: u32,
+    /// How long we wait to connect to a peer before giving up (zero disables the timeout)
+    pub connect_timeout: std::time::Duration,
+    /// Delay before trying the next known address of a peer
+    /// while the connection attempts to its previous addresses are still pending
+    pub connect_attempt_delay: std::time::Duration,
+    /// How long we wait to read a message from an incoming connection, on top of the time
+    /// its payload takes at the minimum read rate (zero disables the timeout)
+    pub read_timeout: std::time::Duration,
+    /// Slowest rate in bytes per second at which a peer must send the payload of a message,
+    /// below which the connection is dropped (zero to only bound the whole read by the read timeout)
+    pub min_read_rate: usize,
+    /// Largest payload of a message we accept, which bounds what a peer can make us buffer
+    pub max_frame_len: usize,
+    /// How long we wait to write a message to a peer (zero disables the timeout)
+    pub write_timeout: std::time::Duration,
+    /// How long a replica keeps a relayed client connection open
+    /// waiting for responses to pass back to the client
+    pub relay_timeout: std::time::Duration,
+    /// How many times a response which could not be delivered to its client is sent again
+    /// before it is recorded as a dead letter
+    pub response_retries: usize,
+    /// Delay before the first retry of a response, doubled for every further retry
+    pub response_retry_backoff: std::time::Duration,
+    /// Most protocol messages held for a peer we cannot reach, sent once it is reachable
+    /// again (0 to hold none)
+    pub peer_outbox_capacity: usize,
+    /// How long a peer may stay unreachable before the messages held for it are dropped,
+    /// leaving it to catch up once it is back
+    pub peer_outbox_retention: std::time::Duration,
+    /// Most commands queued for the consensus engine. Protocol messages wait for room
+    /// in the queue, client requests are rejected as busy when there is none
+    pub consensus_queue_capacity: usize,
+    /// Most commands the consensus engine queues for the node to send
+    pub node_queue_capacity: usize,
+    /// Number of queued consensus commands at which the consensus engine signals
+    /// connection handlers to reject low-priority traffic (client requests) as busy
+    pub backpressure_high_watermark: usize,
+    /// Number of queued consensus commands at which connection handlers accept client
+    /// requests again
+    pub backpressure_low_watermark: usize,
+    /// How long a connection handler waits for room in the queue of the consensus engine
+    /// before rejecting the client request it read as busy
+    pub enqueue_timeout: std::time::Duration,
+    /// Time per verification tick the overloaded consensus engine spends verifying the
+    /// signatures of messages beyond the pipeline head before it puts them off (zero for no
+    /// bound)
+    pub verification_budget: std::time::Duration,
+    /// Length of the ticks the verification budget is granted for
+    pub verification_tick: std::time::Duration,
+    /// Number of messages the overloaded consensus engine puts off verifying before it sheds
+    /// those which are sent again or recovered through state transfer
+    pub verification_backlog: usize,
+    /// Number of blocking workers which verify the signatures of incoming messages before
+    /// they reach the consensus engine (0 to verify them on the engine task)
+    pub verification_workers: usize,
+    /// Most messages the verification workers take from the queue at once
+    pub verification_batch_size: usize,
+    /// Whether messages between replicas are sent over encrypted channels authenticated
+    /// with the identity keys of the replicas. Plaintext messages from replicas are then dropped
+    pub encrypt_transport: bool,
+    /// Whether to keep reading a connection after a frame which is not a well formed message,
+    /// rather than closing it
+    pub skip_malformed_frames: bool,
+    /// Connections from an address which sent this many malformed frames are refused
+    /// (0 for no limit)
+    pub max_malformed_frames: usize,
+    /// Maximum number of client requests the primary holds before proposing them (0 for no limit)
+    pub mempool_capacity: usize,
+    /// Messages referring to a sequence number more than this far below
+    /// the last stable sequence number are dropped when they are received
+    pub stale_message_window: usize,
+    /// Whether to notify the sender of a dropped stale message
+    pub notify_stale_senders: bool,
+    /// Maximum number of messages for future views which are buffered until we move
+    /// to their view (0 to drop them)
+    pub future_view_buffer_size: usize,
+    /// How many views ahead of the current view messages are buffered for
+    pub future_view_window: usize,
+    /// Maximum number of keys in the store (0 for no limit)
+    pub max_total_keys: usize,
+    /// Maximum size of the store in bytes, counting keys and values (0 for no limit)
+    pub max_total_bytes: usize,
+    /// Maximum number of keys a single client may create (0 for no limit)
+    pub max_client_keys: usize,
+    /// Maximum size in bytes of the keys a single client created, with their values (0 for no limit)
+    pub max_client_bytes: usize,
+    /// Algorithms digests and signatures are produced and accepted with,
+    /// including any ongoing migration to a new algorithm
+    pub digest_policy: DigestPolicy,
+    /// Whether prepares and commits are signed or carry authenticators (vectors of MACs).
+    /// Every replica of the cluster must use the same mode
+    pub authentication: Authentication,
+    /// Should view change messages carry prepared certificates by digest
+    /// instead of the full pre-prepares and prepares
+    pub compact_view_changes: bool,
+    /// Optional features the node advertises to clients, by default those this build
+    /// supports. Disabling one on some replicas keeps clients from relying on it
+    pub features: Features,
+    /// Write-ahead log of the accepted protocol messages, replayed when the node restarts
+    /// (the log is only kept in memory if not set)
+    pub wal_path: Option<PathBuf>,
+    /// Directory of the database holding the store at the last stable checkpoint, which a
+    /// restarted node starts from (snapshots of the store are written to the write-ahead log
+    /// instead if not set)
+    pub state_path: Option<PathBuf>,
+    /// File operators have the node export a snapshot of its state at the last stable
+    /// checkpoint to (see `SnapshotFile`), on demand
+    pub snapshot_export: Option<PathBuf>,
+    /// Snapshot file the node installs when it starts, unless its state is already past it
+    pub snapshot_import: Option<PathBuf>,
+    /// File the registry of the cluster is exported to, with the checkpoint certificate
+    /// proving it, whenever it changed at a stable checkpoint (not exported if not set)
+    pub registry_export: Option<PathBuf>,
+    /// Commits per second below which the write-ahead log is compacted after a stable
+    /// checkpoint. Busier nodes defer the compaction to a quieter moment (0 compacts at once)
+    pub compaction_commit_rate: usize,
+    /// Longest a compaction is deferred while the node stays busy
+    pub compaction_max_delay: std::time::Duration,
     /// How many requests we see in between stable checkpoints
     pub checkpoint_frequency: usize,
+    /// Operations applied since the last checkpoint after which a checkpoint is taken
+    /// before the frequency is reached (0 disables it)
+    pub checkpoint_entries: usize,
+    /// Bytes of keys and values applied since the last checkpoint after which a checkpoint
+    /// is taken before the frequency is reached (0 disables it)
+    pub checkpoint_bytes: usize,
+    /// Size of the window of sequence numbers above the last stable checkpoint (the low water
+    /// mark) which pre-prepares are accepted for. The primary holds requests in its mempool
+    /// rather than propose them beyond the window
+    pub log_window: usize,
+    /// View the replicas start in, whose primary proposes first. Every replica
+    /// of the cluster must start in the same view, and a later view recovered from the
+    /// write-ahead log takes precedence
+    pub initial_view: usize,
+    /// Chooses the primary of each view (view mod n by default).
+    /// Every replica and client of the cluster must use the same policy
+    pub leader_election: LeaderPolicy,
+    /// After how many observed quorums we analyze which nodes were absent from them
+    /// (0 disables quorum diagnostics)
+    pub quorum_diagnostics_interval: usize,
+    /// File which this node writes its logs to (stderr if not set)
+    pub log_file: Option<PathBuf>,
+    /// Should logs be emitted as one JSON object per line
+    pub log_json: bool,
+    /// Per-message events are logged once every this many occurrences
+    pub log_sample_rate: usize,
+    /// Log filter directives in the syntax of `RUST_LOG`, applied on top of it
+    /// (`pbft::instance=debug` logs the phases of every request)
+    pub log_filter: Option<String>,
+    /// Address the node serves its metrics on in the Prometheus format (not served if not set)
+    pub metrics_addr: Option<SocketAddr>,
+    /// Address the node serves the gRPC front end for clients on (see `proto/pbft.proto`),
+    /// if the crate is built with the `grpc` feature (not served if not set)
+    pub grpc_addr: Option<SocketAddr>,
+    /// Address the node serves the HTTP gateway to the store on (see `http::HttpFrontEnd`),
+    /// if the crate is built with the `http` feature (not served if not set)
+    pub http_addr: Option<SocketAddr>,
     /// Does this node equivocate (used for testing)
     pub is_equivocator: bool,
+    /// Is this node an archive node, which never truncates its log
+    /// and retains the state at every stable checkpoint
+    pub is_archive: bool,
+    /// Whether an archive node votes like any replica or only follows the cluster
+    pub archive_role: ArchiveRole,
+    /// Number of stable checkpoints for which the keys written since the previous one are
+    /// retained, which bounds how far back a reader can ask what changed (0 to retain them
+    /// all, as archive nodes do)
+    pub retained_diffs: usize,
+    /// Most entries of the store a replica sends in one chunk to a replica fetching the state
+    /// at a stable checkpoint
+    pub state_chunk_size: usize,
+    /// How long a replica fetching the state waits for a chunk before asking another replica
+    pub state_chunk_timeout: std::time::Duration,
+}
+
+impl Config {
+    /// Configuration of a cluster of nodes at the given addresses with the default settings,
+    /// tolerating as many faulty nodes as their number allows
+    pub fn new(peer_addrs: HashMap<NodeId, SocketAddr>) -> Self {
+        let num_nodes = peer_addrs.len();
+        Self {
+            num_nodes,
+            num_faulty: num_nodes.saturating_sub(1) / 3,
+            peer_addrs,
+            peer_pub_keys: HashMap::new(),
+            epoch: 0,
+            root_pub_key: None,
+            certificate: None,
+            bind_addrs: Vec::new(),
+            request_timeout: Duration::from_secs(3),
+            view_change_timeout_min: Duration::from_secs(1),
+            view_change_timeout_max: Duration::from_secs(60),
+            rebroadcast_timeout: Duration::from_secs(8),
+            identity_broadcast_interval: Duration::from_secs(6),
+            clock_skew_alert: Duration::from_secs(1),
+            ping_interval: Duration::from_secs(1),
+            ping_misses: 3,
+            rtt_timeout_factor: 10,
+            connect_timeout: Duration::from_secs(2),
+            connect_attempt_delay: Duration::from_millis(250),
+            read_timeout: Duration::from_secs(5),
+            min_read_rate: 64 * 1024,
+            max_frame_len: codec::MAX_FRAME_LEN,
+            write_timeout: Duration::from_secs(2),
+            relay_timeout: Duration::from_secs(10),
+            response_retries: 3,
+            response_retry_backoff: Duration::from_millis(200),
+            peer_outbox_capacity: 256,
+            peer_outbox_retention: Duration::from_secs(10),
+            consensus_queue_capacity: 32,
+            node_queue_capacity: 32,
+            backpressure_high_watermark: 24,
+            backpressure_low_watermark: 8,
+            enqueue_timeout: Duration::from_secs(2),
+            verification_budget: Duration::from_millis(50),
+            verification_tick: Duration::from_millis(100),
+            verification_backlog: 4096,
+            verification_workers: 0,
+            verification_batch_size: 64,
+            mempool_capacity: 1024,
+            encrypt_transport: false,
+            skip_malformed_frames: false,
+            max_malformed_frames: 0,
+            stale_message_window: 20,
+            notify_stale_senders: true,
+            future_view_buffer_size: 256,
+            future_view_window: 2,
+            max_total_keys: 0,
+            max_total_bytes: 0,
+            max_client_keys: 0,
+            max_client_bytes: 0,
+            digest_policy: DigestPolicy::default(),
+            authentication: Authentication::default(),
+            compact_view_changes: true,
+            features: Features::supported(),
+            wal_path: None,
+            state_path: None,
+            snapshot_export: None,
+            snapshot_import: None,
+            registry_export: None,
+            compaction_commit_rate: 0,
+            compaction_max_delay: Duration::from_secs(60),
+            checkpoint_frequency: 10,
+            checkpoint_entries: 0,
+            checkpoint_bytes: 0,
+            log_window: 40,
+            initial_view: 0,
+            leader_election: LeaderPolicy::default(),
+            quorum_diagnostics_interval: 50,
+            log_file: None,
+            log_json: false,
+            log_sample_rate: 1,
+            log_filter: None,
+            metrics_addr: None,
+            grpc_addr: None,
+            http_addr: None,
+            is_equivocator: false,
+            is_archive: false,
+            archive_role: ArchiveRole::Voting,
+            retained_diffs: 64,
+            state_chunk_size: 256,
+            state_chunk_timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Loads the cluster configuration from a JSON file (see `ConfigFile`),
+    /// with the default settings for everything the file does not specify
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Unreadable(format!("{}: {}", path.display(), e)))?;
+        let file: ConfigFile = serde_json::from_str(&contents)
+            .map_err(|e| ConfigError::Unreadable(format!("{}: {}", path.display(), e)))?;
+        file.into_config()
+    }
+
+    /// Checks that the configuration describes a cluster which can run the protocol
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.num_nodes == 0 {
+            return Err(ConfigError::Invalid("there are no nodes".to_string()));
+        }
+        if self.num_nodes < 3 * self.num_faulty + 1 {
+            return Err(ConfigError::Invalid(format!(
+                "{} nodes cannot tolerate {} faulty nodes (at least 3f + 1 = {} are needed)",
+                self.num_nodes,
+                self.num_faulty,
+                3 * self.num_faulty + 1
+            )));
+        }
+        // reconfigurations add and remove nodes by id, leaving gaps
+        if self.epoch == 0 {
+            if let Some(id) = (0..self.num_nodes).find(|id| !self.peer_addrs.contains_key(id)) {
+                return Err(ConfigError::Invalid(format!("node {} has no address", id)));
+            }
+        }
+        // every replica may contribute a checkpoint to the proof of a stable checkpoint
+        if self.num_nodes > limits::MAX_CHECKPOINT_PROOF {
+            return Err(ConfigError::Invalid(format!(
+                "there are more than {} nodes",
+                limits::MAX_CHECKPOINT_PROOF
+            )));
+        }
+        if self.peer_addrs.len() != self.num_nodes {
+            return Err(ConfigError::Invalid(format!(
+                "node ids must be 0 to {}",
+                self.num_nodes - 1
+            )));
+        }
+        if let Some(id) = self
+            .peer_pub_keys
+            .keys()
+            .find(|id| !self.peer_addrs.contains_key(id))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "there is a public key for node {}, which is not in the cluster",
+                id
+            )));
+        }
+        if self.checkpoint_frequency == 0 {
+            return Err(ConfigError::Invalid(
+                "the checkpoint frequency must be positive".to_string(),
+            ));
+        }
+        // the window must reach the next checkpoint, or it never moves
+        if self.log_window < self.checkpoint_frequency {
+            return Err(ConfigError::Invalid(format!(
+                "the log window ({}) is smaller than the checkpoint frequency ({})",
+                self.log_window, self.checkpoint_frequency
+            )));
+        }
+        // view changes carry the prepared requests of the whole window
+        if self.log_window > limits::MAX_SUBSEQUENT_PREPARES {
+            return Err(ConfigError::Invalid(format!(
+                "the log window ({}) is larger than {}",
+                self.log_window,
+                limits::MAX_SUBSEQUENT_PREPARES
+            )));
+        }
+        if self.view_change_timeout_min > self.view_change_timeout_max {
+            return Err(ConfigError::Invalid(
+                "the minimum view change timeout is above the maximum".to_string(),
+            ));
+        }
+        if self.backpressure_low_watermark > self.backpressure_high_watermark {
+            return Err(ConfigError::Invalid(
+                "the backpressure low watermark is above the high watermark".to_string(),
+            ));
+        }
+        if self.consensus_queue_capacity == 0 || self.node_queue_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "the queue capacities must be positive".to_string(),
+            ));
+        }
+        if self.state_chunk_size == 0 {
+            return Err(ConfigError::Invalid(
+                "the state chunk size must be positive".to_string(),
+            ));
+        }
+        if self.verification_batch_size == 0 {
+            return Err(ConfigError::Invalid(
+                "the verification batch size must be positive".to_string(),
+            ));
+        }
+        if self.max_frame_len == 0 {
+            return Err(ConfigError::Invalid(
+                "the maximum frame length must be positive".to_string(),
+            ));
+        }
+        // the cluster has to make progress without the votes of a non-voting archive node
+        if !self.is_voting() && self.num_faulty == 0 {
+            return Err(ConfigError::Invalid(
+                "a non-voting archive node needs a cluster which tolerates a faulty node"
+                    .to_string(),
+            ));
+        }
+        // the engine must signal backpressure before its queue is full
+        if self.backpressure_high_watermark > self.consensus_queue_capacity {
+            return Err(ConfigError::Invalid(format!(
+                "the backpressure high watermark ({}) is above the consensus queue capacity ({})",
+                self.backpressure_high_watermark, self.consensus_queue_capacity
+            )));
+        }
+        Ok(())
+    }
+
+    /// Takes the membership of the cluster from the registry, which a reconfiguration
+    /// moved to a later epoch
+    pub fn apply_membership(&mut self, registry: &ClusterRegistry) {
+        self.peer_addrs = registry
+            .members
+            .iter()
+            .map(|(id, addr)| (*id, *addr))
+            .collect();
+        self.num_nodes = self.peer_addrs.len();
+        self.num_faulty = registry.num_faulty;
+        self.peer_pub_keys = registry.pub_keys();
+        self.epoch = registry.epoch;
+    }
+
+    /// Bounds on how peers send us messages over incoming connections
+    pub fn read_limits(&self) -> ReadLimits {
+        ReadLimits {
+            max_frame_len: self.max_frame_len,
+            timeout: self.read_timeout,
+            min_rate: self.min_read_rate,
+        }
+    }
+
+    /// Is this a single node deployment (n = 1, f = 0) used for development.
+    /// Consensus short-circuits in this mode, committing requests as soon as they are received
+    pub fn is_single_node(&self) -> bool {
+        self.num_nodes == 1
+    }
+
+    /// Does this node vote: every node does, bar a non-voting archive node
+    pub fn is_voting(&self) -> bool {
+        !self.is_archive || self.archive_role == ArchiveRole::Voting
+    }
+}
+
+/// Part an archive node takes in the protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchiveRole {
+    /// Votes like any replica
+    #[default]
+    Voting,
+    /// Applies what the cluster commits without proposing or voting. Its peers get none of
+    /// its pre-prepares, prepares, commits, checkpoints or view changes, so it counts against
+    /// the f faulty replicas the cluster tolerates like a crashed replica would
+    NonVoting,
+}
+
+impl std::str::FromStr for ArchiveRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "voting" => Ok(ArchiveRole::Voting),
+            "non-voting" => Ok(ArchiveRole::NonVoting),
+            _ => Err(format!("unknown archive role {}", s)),
+        }
+    }
 }
+
+/// Cluster configuration shared by the nodes and clients of a deployment.
+/// Only the nodes are required, every other setting has a default:
+///
+/// ```json
+/// {
+///   "nodes": [
+///     { "id": 0, "addr": "10.0.0.1:7000", "pub_key": "3b6a27bc..." },
+///     { "id": 1, "addr": "10.0.0.2:7000", "leader_weight": 2 },
+///     { "id": 2, "addr": "10.0.0.3:7000" },
+///     { "id": 3, "addr": "10.0.0.4:7000" }
+///   ],
+///   "num_faulty": 1,
+///   "root_pub_key": "d75a9801...",
+///   "checkpoint_frequency": 10,
+///   "checkpoint_bytes": 65536,
+///   "initial_view": 0,
+///   "digest": "sha512",
+///   "authentication": "authenticators",
+///   "timeouts": { "request_ms": 3000, "rebroadcast_ms": 8000 }
+/// }
+/// ```
+///
+/// TOML and YAML are not supported, as the crate only depends on a JSON parser
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub nodes: Vec<NodeEntry>,
+    /// Defaults to as many faulty nodes as the number of nodes allows
+    #[serde(default)]
+    pub num_faulty: Option<usize>,
+    /// Hex encoded root key which issues the identity certificates of the nodes
+    #[serde(default)]
+    pub root_pub_key: Option<String>,
+    #[serde(default)]
+    pub checkpoint_frequency: Option<usize>,
+    /// Checkpoint earlier once this many operations were applied since the last one
+    #[serde(default)]
+    pub checkpoint_entries: Option<usize>,
+    /// Checkpoint earlier once this many bytes were applied since the last one
+    #[serde(default)]
+    pub checkpoint_bytes: Option<usize>,
+    /// Defaults to four times the checkpoint frequency
+    #[serde(default)]
+    pub log_window: Option<usize>,
+    /// View the cluster starts in, e.g. the view it agreed on before a restart of every node
+    #[serde(default)]
+    pub initial_view: Option<usize>,
+    /// Algorithm digests and signatures are produced with, e.g. "sha512_256"
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// "signatures" or "authenticators", how replicas authenticate their prepares and commits
+    #[serde(default)]
+    pub authentication: Option<Authentication>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeEntry {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+    /// Hex encoded public key of the node, which its peers and clients then require
+    #[serde(default)]
+    pub pub_key: Option<String>,
+    /// Share of the views the node is primary for, relative to the other nodes (1 if not
+    /// given). If no node has a weight, the nodes are primary in turn
+    #[serde(default)]
+    pub leader_weight: Option<usize>,
+}
+
+/// Timeouts of `Config` in milliseconds, where given
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub request_ms: Option<u64>,
+    pub view_change_min_ms: Option<u64>,
+    pub view_change_max_ms: Option<u64>,
+    pub rebroadcast_ms: Option<u64>,
+    pub identity_broadcast_ms: Option<u64>,
+    pub connect_ms: Option<u64>,
+    pub read_ms: Option<u64>,
+    pub write_ms: Option<u64>,
+    pub relay_ms: Option<u64>,
+    pub clock_skew_alert_ms: Option<u64>,
+    pub ping_ms: Option<u64>,
+}
+
+impl ConfigFile {
+    /// The configuration the file describes, which is validated
+    pub fn into_config(self) -> Result<Config, ConfigError> {
+        let mut peer_addrs = HashMap::new();
+        let mut peer_pub_keys = HashMap::new();
+        for node in self.nodes.iter() {
+            if peer_addrs.insert(node.id, node.addr).is_some() {
+                return Err(ConfigError::Invalid(format!(
+                    "node {} is listed twice",
+                    node.id
+                )));
+            }
+            if let Some(pub_key) = &node.pub_key {
+                let pub_key = decode_hex(pub_key)
+                    .ok()
+                    .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+                    .ok_or_else(|| {
+                        ConfigError::Invalid(format!("invalid public key for node {}", node.id))
+                    })?;
+                peer_pub_keys.insert(node.id, pub_key);
+            }
+        }
+
+        let mut config = Config::new(peer_addrs);
+        config.peer_pub_keys = peer_pub_keys;
+        if let Some(root_pub_key) = &self.root_pub_key {
+            let root_pub_key = decode_hex(root_pub_key)
+                .ok()
+                .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+                .ok_or_else(|| ConfigError::Invalid("invalid root public key".to_string()))?;
+            config.root_pub_key = Some(root_pub_key);
+        }
+        if let Some(num_faulty) = self.num_faulty {
+            config.num_faulty = num_faulty;
+        }
+        if let Some(checkpoint_frequency) = self.checkpoint_frequency {
+            config.checkpoint_frequency = checkpoint_frequency;
+            config.log_window = 4 * checkpoint_frequency;
+        }
+        if let Some(checkpoint_entries) = self.checkpoint_entries {
+            config.checkpoint_entries = checkpoint_entries;
+        }
+        if let Some(checkpoint_bytes) = self.checkpoint_bytes {
+            config.checkpoint_bytes = checkpoint_bytes;
+        }
+        if let Some(log_window) = self.log_window {
+            config.log_window = log_window;
+        }
+        if let Some(initial_view) = self.initial_view {
+            config.initial_view = initial_view;
+        }
+        if self.nodes.iter().any(|node| node.leader_weight.is_some()) {
+            let weights: BTreeMap<NodeId, usize> = self
+                .nodes
+                .iter()
+                .map(|node| (node.id, node.leader_weight.unwrap_or(1)))
+                .collect();
+            // f nodes which are all faulty must not be primary of every view
+            let num_leaders = weights.values().filter(|weight| **weight > 0).count();
+            if num_leaders <= config.num_faulty {
+                return Err(ConfigError::Invalid(format!(
+                    "only {} nodes have a positive leader weight, at least f + 1 = {} must",
+                    num_leaders,
+                    config.num_faulty + 1
+                )));
+            }
+            config.leader_election = LeaderPolicy::new(WeightedRotation::new(&weights));
+        }
+        if let Some(digest) = &self.digest {
+            config.digest_policy.algorithm = digest.parse().map_err(ConfigError::Invalid)?;
+        }
+        if let Some(authentication) = self.authentication {
+            config.authentication = authentication;
+        }
+        let timeouts = [
+            (self.timeouts.request_ms, &mut config.request_timeout),
+            (
+                self.timeouts.view_change_min_ms,
+                &mut config.view_change_timeout_min,
+            ),
+            (
+                self.timeouts.view_change_max_ms,
+                &mut config.view_change_timeout_max,
+            ),
+            (
+                self.timeouts.rebroadcast_ms,
+                &mut config.rebroadcast_timeout,
+            ),
+            (
+                self.timeouts.identity_broadcast_ms,
+                &mut config.identity_broadcast_interval,
+            ),
+            (self.timeouts.connect_ms, &mut config.connect_timeout),
+            (self.timeouts.read_ms, &mut config.read_timeout),
+            (self.timeouts.write_ms, &mut config.write_timeout),
+            (self.timeouts.relay_ms, &mut config.relay_timeout),
+            (
+                self.timeouts.clock_skew_alert_ms,
+                &mut config.clock_skew_alert,
+            ),
+            (self.timeouts.ping_ms, &mut config.ping_interval),
+        ];
+        for (millis, timeout) in timeouts {
+            if let Some(millis) = millis {
+                *timeout = Duration::from_millis(millis);
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Reasons a configuration could not be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The file could not be read or is not a configuration
+    Unreadable(String),
+    /// The configuration is inconsistent, e.g. it has too few nodes for its number of faulty nodes
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Unreadable(reason) => write!(f, "could not read config ({})", reason),
+            ConfigError::Invalid(reason) => write!(f, "invalid config ({})", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,179 @@
+use crate::messages::{Ping, Pong};
+use crate::NodeId;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Round trips to the peers, measured by pinging them periodically, and the peers which
+/// stopped answering. Pings carry when they were sent on a monotonic clock of ours, which
+/// the pong echoes, so that the round trip is measured without trusting the clock of the peer.
+/// The round trips of a quorum of peers lengthen the view change timers on slow links
+#[derive(Clone)]
+pub struct Connectivity {
+    /// Unanswered pings after which the connection to a peer is taken for dead
+    max_missed: u64,
+    started: Instant,
+    inner: Arc<Mutex<BTreeMap<NodeId, PeerPings>>>,
+}
+
+#[derive(Default)]
+struct PeerPings {
+    /// Sequence number of the next ping to the peer
+    next_seq: u64,
+    /// Highest sequence number the peer answered
+    last_answered: Option<u64>,
+    pongs_received: u64,
+    smoothed_rtt: Option<Duration>,
+    last_rtt: Option<Duration>,
+    is_down: bool,
+}
+
+impl PeerPings {
+    /// Pings sent before the next one which the peer has not answered
+    fn missed(&self) -> u64 {
+        self.next_seq - self.last_answered.map_or(0, |seq| seq + 1)
+    }
+}
+
+/// Round trips to the peers and the peers whose connection is dead, reported in node
+/// statuses. Together the reports of the replicas form the connectivity matrix of the cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectivityStats {
+    pub peers: BTreeMap<NodeId, PeerLink>,
+}
+
+impl ConnectivityStats {
+    /// Peers which stopped answering our pings
+    pub fn down_peers(&self) -> Vec<NodeId> {
+        self.peers
+            .iter()
+            .filter(|(_, link)| link.is_down)
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerLink {
+    /// Round trip smoothed over the recent pongs, as TCP does, None before the first pong
+    pub rtt_micros: Option<u64>,
+    /// Round trip of the last pong
+    pub last_rtt_micros: Option<u64>,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    /// Pings sent after the last one the peer answered, the latest of which may be in flight
+    pub missed: u64,
+    /// Whether the peer missed enough pings for its connection to be taken for dead
+    pub is_down: bool,
+}
+
+impl Connectivity {
+    /// Takes the connection to a peer for dead once it misses as many pings in a row
+    pub fn new(max_missed: usize) -> Self {
+        Self {
+            max_missed: max_missed.max(1) as u64,
+            started: Instant::now(),
+            inner: Arc::default(),
+        }
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+
+    /// Next ping from us to the peer. The peer is taken for dead if it did not answer
+    /// the pings before it
+    pub fn ping(&self, id: NodeId, peer_id: NodeId) -> Ping {
+        let mut inner = self.inner.lock().unwrap();
+        let peer = inner.entry(peer_id).or_default();
+        if !peer.is_down && peer.missed() >= self.max_missed {
+            warn!(
+                "Node {} did not answer our last {} pings, its connection is dead",
+                peer_id,
+                peer.missed()
+            );
+            peer.is_down = true;
+        }
+        let seq = peer.next_seq;
+        peer.next_seq += 1;
+        Ping {
+            id,
+            seq,
+            sent_at_micros: self.now_micros(),
+        }
+    }
+
+    /// Takes the answer of the peer to one of our pings, returning true if the peer was
+    /// taken for dead until then. Pongs for pings we never sent are ignored
+    pub fn pong(&self, pong: &Pong) -> bool {
+        let now_micros = self.now_micros();
+        let mut inner = self.inner.lock().unwrap();
+        let peer = match inner.get_mut(&pong.id) {
+            Some(peer) if pong.seq < peer.next_seq && pong.sent_at_micros <= now_micros => peer,
+            _ => return false,
+        };
+        let rtt = Duration::from_micros(now_micros - pong.sent_at_micros);
+        peer.last_rtt = Some(rtt);
+        peer.smoothed_rtt = Some(match peer.smoothed_rtt {
+            Some(smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        });
+        peer.pongs_received += 1;
+        peer.last_answered = peer.last_answered.max(Some(pong.seq));
+        let was_down = peer.is_down;
+        peer.is_down = false;
+        if was_down {
+            info!("Node {} answers our pings again", pong.id);
+        }
+        was_down
+    }
+
+    pub fn is_down(&self, peer_id: NodeId) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .get(&peer_id)
+            .is_some_and(|peer| peer.is_down)
+    }
+
+    /// Round trip within which 2f of the peers answer, so that with us they make a quorum.
+    /// None until that many peers which are up answered a ping
+    pub fn quorum_rtt(&self, num_faulty: usize) -> Option<Duration> {
+        if num_faulty == 0 {
+            return None;
+        }
+        let inner = self.inner.lock().unwrap();
+        let mut rtts: Vec<Duration> = inner
+            .values()
+            .filter(|peer| !peer.is_down)
+            .filter_map(|peer| peer.smoothed_rtt)
+            .collect();
+        rtts.sort();
+        rtts.get(2 * num_faulty - 1).copied()
+    }
+
+    pub fn stats(&self) -> ConnectivityStats {
+        let inner = self.inner.lock().unwrap();
+        ConnectivityStats {
+            peers: inner
+                .iter()
+                .map(|(peer_id, peer)| {
+                    let link = PeerLink {
+                        rtt_micros: peer.smoothed_rtt.map(|rtt| rtt.as_micros() as u64),
+                        last_rtt_micros: peer.last_rtt.map(|rtt| rtt.as_micros() as u64),
+                        pings_sent: peer.next_seq,
+                        pongs_received: peer.pongs_received,
+                        missed: peer.missed(),
+                        is_down: peer.is_down,
+                    };
+                    (*peer_id, link)
+                })
+                .collect(),
+        }
+    }
+}
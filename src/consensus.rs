@@ -1,18 +1,58 @@
+use crate::authenticator::{Authentication, Authenticator};
+use crate::backend::{SledBackend, StateBackend};
+use crate::bulk::BulkIngest;
+use crate::codec::MalformedStats;
 use crate::config::Config;
+use crate::connectivity::{Connectivity, ConnectivityStats};
+use crate::crypto;
+use crate::diagnostics::QuorumDiagnostics;
+use crate::diffs::CheckpointDiffs;
+use crate::evidence::{check_new_view, Evidence, EvidenceLog, Exclusion};
+use crate::features::Capabilities;
+use crate::future_view::FutureViewBuffer;
+use crate::keystore::Keystore;
+use crate::limits;
+use crate::logging::{self, instance_event, sampled};
+use crate::membership::Membership;
+use crate::mempool::{Admission, Mempool, Priority};
 use crate::messages::{
-    BroadCastMessage, CheckPoint, ClientRequest, ClientResponse, Commit, ConsensusCommand, Message,
-    NewView, NodeCommand, PrePrepare, Prepare, SendMessage, ViewChange,
+    Blame, BroadCastMessage, CatchUp, CheckPoint, ClientIdentity, ClientRequest, ClientResponse,
+    Commit, ConsensusCommand, DrainStatus, EquivocationProof, FailureReason, FetchRequestBody,
+    GetRequestStatus, LogEntry, Message, NewView, NodeCommand, NodeStatus, Operation, PrePrepare,
+    Prepare, Progress, RelayedClientResponse, RequestBody, RequestStatus, RequestStatusReport,
+    ResubmitHint, SendMessage, StateChunkRequest, StateChunkResponse, ValueAt, ViewChange,
 };
-use crate::state::State;
-use crate::view_changer::ViewChanger;
-use crate::NodeId;
-
-use tokio::sync::mpsc::{Receiver, Sender};
-
-use std::collections::{HashMap, HashSet};
+use crate::metrics::CommitRate;
+use crate::observer::{Observer, Observers, QuorumKind};
+use crate::outbox::OutboxStats;
+use crate::pipeline::{Pipeline, PipelineStats};
+use crate::registry::{self, ClusterRegistry};
+use crate::state::{SnapshotError, State};
+use crate::state_transfer::StateTransfer;
+use crate::storage::{self, Wal, WalRecord};
+use crate::time::ClockStats;
+use crate::verification::{Triage, VerificationBudget, VerificationPool};
+use crate::versions::KeyVersions;
+use crate::view_changer::{NewViewRequests, ViewChanger};
+use crate::{NodeId, Value};
+
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration, Instant};
+
+use ed25519_dalek::PublicKey;
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
 
-use log::info;
+use log::{debug, error, info, warn};
+use tracing::Instrument;
+
+/// Window over which the commit rate deciding when to compact the log is measured
+const COMMIT_RATE_WINDOW: Duration = Duration::from_secs(10);
+/// How often a deferred compaction checks whether the node became quiet enough
+const COMPACTION_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 // Note that all communication between the Node and the Consensus engine takes place
 // by the outer consensus struct
@@ -23,83 +63,1045 @@ pub struct Consensus {
     /// Configuration of the cluster this node is in
     pub config: Config,
     /// Keypair of the node
-    pub keypair_bytes: Vec<u8>,
+    pub keystore: Keystore,
+    /// Authenticates our prepares and commits, if the cluster uses authenticators
+    /// rather than signatures for them
+    pub authenticator: Option<Authenticator>,
     /// Receiver of Consensus Commands
     pub rx_consensus: Receiver<ConsensusCommand>,
     /// Sends Commands to Node
     pub tx_node: Sender<NodeCommand>,
-    /// Sends Consensus Commands to itself
+    /// Sends Consensus Commands to itself from other tasks
     pub tx_consensus: Sender<ConsensusCommand>,
+    /// Commands the engine gives itself while handling a command, carried out before the
+    /// next queued one. Kept apart from the bounded queue, which the engine would otherwise
+    /// wait on itself to drain while it is full
+    follow_ups: VecDeque<ConsensusCommand>,
     /// Current State of the Consensus
     pub state: State,
     /// Responsible for outstanding requests and changing views
     pub view_changer: ViewChanger,
+    /// Known public keys of peers (shared with the node)
+    pub peer_pub_keys: Arc<tokio::sync::Mutex<HashMap<NodeId, PublicKey>>>,
+    /// Publishes the nodes suspected of being crashed or partitioned by the quorum diagnostics
+    pub tx_suspected_nodes: watch::Sender<Vec<NodeId>>,
+    /// Signals connection handlers whether the engine is overloaded
+    /// and they should stop reading low-priority traffic
+    pub tx_backpressure: watch::Sender<bool>,
+    /// Publishes the last stable sequence number, so that stale messages can be dropped early
+    pub tx_stable_seq_num: watch::Sender<usize>,
+    /// Progress of the engine, reported to operators by the node
+    pub tx_status: watch::Sender<NodeStatus>,
+    /// Observers registered on this replica
+    pub observers: Observers,
+    /// Accounting of the messages queued for this engine (shared with the node)
+    pub pipeline: Pipeline,
+    /// Durable log of the accepted protocol messages, if the node persists them
+    pub wal: Option<Wal>,
+    /// Durable copy of the state at the last stable checkpoint, if the node keeps one
+    pub backend: Option<Box<dyn StateBackend>>,
+    /// Since when a compaction of the write-ahead log has been deferred, if one is
+    pub pending_compaction: Option<Instant>,
+    /// Recent commits, which tell whether the node is quiet enough to compact the log
+    pub commit_rate: CommitRate,
+    /// Client requests we accepted as primary and have not proposed yet
+    pub mempool: Mempool,
+    /// Batches of the bulk loads streamed to us as primary which we have not proposed yet
+    pub bulk_ingest: BulkIngest,
+    /// Messages dropped because their signature could not be verified, by claimed sender
+    pub unverified_messages: BTreeMap<NodeId, usize>,
+    /// Bounds the time spent verifying signatures while overloaded
+    pub verification_budget: VerificationBudget,
+    /// Progress of the drain, if an operator asked us to drain before a planned shutdown
+    pub drain: Option<DrainStatus>,
+    /// Version of the cluster registry we last exported, if any
+    pub exported_registry_version: Option<usize>,
+    /// Fetch of the state at a stable checkpoint we fell behind, if one is under way
+    pub state_transfer: Option<StateTransfer>,
+    /// Members of the epoch we are in (shared with the node)
+    pub membership: Membership,
+    /// Sequence number of the reconfiguration we proposed as primary. We propose nothing
+    /// after it, as the requests after it are ordered in the next epoch
+    pub proposed_reconfiguration: Option<usize>,
+    /// Latest checkpoint and view change of each replica from an epoch after ours, by sender
+    /// and kind, processed once we move to their epoch
+    pub later_epoch_messages: BTreeMap<(NodeId, &'static str), Message>,
 }
 
 impl Consensus {
     pub fn new(
         id: NodeId,
         config: Config,
-        keypair_bytes: Vec<u8>,
+        keystore: &Keystore,
         rx_consensus: Receiver<ConsensusCommand>,
         tx_consensus: Sender<ConsensusCommand>,
         tx_node: Sender<NodeCommand>,
+        peer_pub_keys: Arc<tokio::sync::Mutex<HashMap<NodeId, PublicKey>>>,
     ) -> Self {
-        let state = State {
-            config: config.clone(),
-            id,
-            ..Default::default()
+        let mut state = State::new(id, config.clone());
+
+        // recover the protocol state from before a restart, starting from the stable
+        // checkpoint the state backend holds
+        let backend = config.state_path.as_ref().map(|state_path| {
+            Box::new(SledBackend::open(state_path).unwrap()) as Box<dyn StateBackend>
+        });
+        let checkpoint = backend.as_ref().and_then(|backend| backend.load().unwrap());
+        if let Some((snapshot, _)) = &checkpoint {
+            info!(
+                "Loaded the state at seq-num {} from the state backend",
+                snapshot.committed_seq_num
+            );
+        }
+        let (wal, records) = match config.wal_path.as_ref() {
+            Some(wal_path) => {
+                let (wal, records) = Wal::open(wal_path).unwrap();
+                (Some(wal), records)
+            }
+            None => (None, Vec::new()),
         };
+        if checkpoint.is_some() || !records.is_empty() {
+            info!("Replaying {} write-ahead log records", records.len());
+            storage::recover(&mut state, checkpoint, records);
+            info!(
+                "Recovered view {} seq-num {} committed {}",
+                state.view, state.seq_num, state.last_seq_num_committed
+            );
+        }
+        // a snapshot file seeds a replica which is behind it, e.g. a new one
+        if let Some(path) = config.snapshot_import.as_ref() {
+            match state.import_snapshot(path, &config.peer_pub_keys) {
+                Ok(seq_num) => info!("Imported the state at seq-num {}", seq_num),
+                Err(SnapshotError::Stale { committed_seq_num }) => info!(
+                    "Not importing the snapshot at seq-num {}, our state is past it",
+                    committed_seq_num
+                ),
+                Err(e) => error!("Could not import the snapshot: {}", e),
+            }
+        }
+        // the write-ahead log or the snapshot may have moved us to a later epoch
+        let config = state.config.clone();
 
         let view_changer = ViewChanger {
             id,
             config: config.clone(),
             tx_consensus: tx_consensus.clone(),
             wait_set: Arc::new(Mutex::new(HashSet::new())),
+            resets: Arc::new(AtomicUsize::new(0)),
+            consecutive_view_changes: Arc::new(AtomicUsize::new(0)),
             sent_pre_prepares: Arc::new(Mutex::new(HashSet::new())),
+            connectivity: Connectivity::new(config.ping_misses),
+        };
+
+        let (tx_suspected_nodes, _) = watch::channel(Vec::new());
+        let (tx_backpressure, _) = watch::channel(false);
+        let (tx_stable_seq_num, _) = watch::channel(0);
+        let (tx_status, _) = watch::channel(NodeStatus {
+            id,
+            ..Default::default()
+        });
+        let mempool = Mempool::new(config.mempool_capacity, config.request_timeout);
+        let bulk_ingest = BulkIngest::new(config.max_frame_len / 4);
+        let verification_budget = VerificationBudget::new(&config);
+        let membership = Membership::new(&config);
+        let authenticator = match config.authentication {
+            Authentication::Signatures => None,
+            Authentication::Authenticators => Some(Authenticator::new(id, keystore)),
         };
 
         Self {
             id,
             config,
-            keypair_bytes,
+            keystore: keystore.clone(),
+            authenticator,
             rx_consensus,
             tx_node,
             tx_consensus,
+            follow_ups: VecDeque::new(),
+            state_transfer: None,
             state,
             view_changer,
+            peer_pub_keys,
+            tx_suspected_nodes,
+            tx_backpressure,
+            tx_stable_seq_num,
+            tx_status,
+            observers: Observers::default(),
+            pipeline: Pipeline::default(),
+            mempool,
+            bulk_ingest,
+            wal,
+            backend,
+            pending_compaction: None,
+            commit_rate: CommitRate::new(COMMIT_RATE_WINDOW),
+            unverified_messages: BTreeMap::new(),
+            verification_budget,
+            drain: None,
+            exported_registry_version: None,
+            membership,
+            proposed_reconfiguration: None,
+            later_epoch_messages: BTreeMap::new(),
+        }
+    }
+
+    /// Subscribe to the last stable sequence number of this node
+    pub fn subscribe_stable_seq_num(&self) -> watch::Receiver<usize> {
+        self.tx_stable_seq_num.subscribe()
+    }
+
+    /// Subscribe to the backpressure signal of the engine, which is true while
+    /// the engine is overloaded and low-priority traffic should not be read
+    pub fn subscribe_backpressure(&self) -> watch::Receiver<bool> {
+        self.tx_backpressure.subscribe()
+    }
+
+    /// The next command to process: follow-ups of the previous one first, then the messages
+    /// put off verifying once the engine is no longer overloaded, then the queue. Whether
+    /// the command is a message which was put off is returned with it, as it already left
+    /// the queue
+    async fn next_command(&mut self) -> (ConsensusCommand, bool) {
+        if let Some(cmd) = self.follow_ups.pop_front() {
+            return (cmd, false);
+        }
+        self.update_backpressure();
+        if !*self.tx_backpressure.borrow() {
+            if let Some(message) = self.verification_budget.take_deferred() {
+                return (ConsensusCommand::ProcessMessage(message), true);
+            }
+        }
+        (self.rx_consensus.recv().await.unwrap(), false)
+    }
+
+    /// Updates the backpressure signal from the number of queued commands.
+    /// The signal is raised at the high watermark and cleared at the low watermark
+    fn update_backpressure(&self) {
+        let queued = self.rx_consensus.len();
+        self.tx_backpressure.send_if_modified(|overloaded| {
+            let now_overloaded = if *overloaded {
+                queued > self.config.backpressure_low_watermark
+            } else {
+                queued >= self.config.backpressure_high_watermark
+            };
+            let modified = now_overloaded != *overloaded;
+            *overloaded = now_overloaded;
+            modified
+        });
+    }
+
+    /// Registers an observer of the events of this replica
+    pub fn register_observer(&self, observer: Arc<dyn Observer>) {
+        self.observers.register(observer);
+    }
+
+    /// Observers registered on this replica, to be shared with its node
+    pub fn observers(&self) -> Observers {
+        self.observers.clone()
+    }
+
+    /// Accounting of the messages queued for this engine, which the node enqueues through
+    pub fn pipeline(&self) -> Pipeline {
+        self.pipeline.clone()
+    }
+
+    /// Versions of the keys written by the requests this engine applies
+    pub fn key_versions(&self) -> KeyVersions {
+        self.state.key_versions.clone()
+    }
+
+    /// Keys written between the stable checkpoints of this engine
+    pub fn checkpoint_diffs(&self) -> CheckpointDiffs {
+        self.state.checkpoint_diffs.clone()
+    }
+
+    pub fn evidence(&self) -> EvidenceLog {
+        self.state.evidence.clone()
+    }
+
+    /// Round trips to the peers the view change timers adapt to, which the node measures
+    pub fn connectivity(&self) -> Connectivity {
+        self.view_changer.connectivity.clone()
+    }
+
+    /// Members of the epoch this engine is in
+    pub fn membership(&self) -> Membership {
+        self.membership.clone()
+    }
+
+    /// Subscribe to the progress of the engine
+    pub fn subscribe_status(&self) -> watch::Receiver<NodeStatus> {
+        self.tx_status.subscribe()
+    }
+
+    /// Appends the record to the write-ahead log, if the node persists its log
+    fn persist(&mut self, record: WalRecord) {
+        if let Some(wal) = self.wal.as_mut() {
+            if let Err(e) = wal.append(&record) {
+                error!("Could not append to the write-ahead log: {}", e);
+            }
+        }
+    }
+
+    /// Processes the messages buffered for the view we just moved to.
+    /// These are queued from a separate task, as there may be more of them
+    /// than fit in the command channel this loop is draining
+    fn replay_future_view_messages(&mut self) {
+        let messages = self.state.future_view_messages.drain(self.state.view);
+        if messages.is_empty() {
+            return;
+        }
+        info!(
+            "Replaying {} messages buffered for view {}",
+            messages.len(),
+            self.state.view
+        );
+        let tx_consensus = self.tx_consensus.clone();
+        let pipeline = self.pipeline.clone();
+        logging::spawn(async move {
+            for message in messages {
+                pipeline.send(&tx_consensus, message).await;
+            }
+        });
+    }
+
+    /// Compacts the write-ahead log up to the last stable checkpoint if the commit rate is
+    /// below the configured threshold or the compaction was deferred for too long, and
+    /// otherwise checks again later
+    fn compact_wal_when_quiet(&mut self) {
+        let deferred_since = match self.pending_compaction {
+            Some(deferred_since) => deferred_since,
+            None => return,
+        };
+        let threshold = self.config.compaction_commit_rate;
+        if threshold == 0
+            || self.commit_rate.per_second() < threshold as f64
+            || deferred_since.elapsed() >= self.config.compaction_max_delay
+        {
+            self.compact_wal();
+            return;
+        }
+        let tx_consensus = self.tx_consensus.clone();
+        logging::spawn(async move {
+            sleep(COMPACTION_POLL_INTERVAL).await;
+            let _ = tx_consensus
+                .send(ConsensusCommand::CompactLog { forced: false })
+                .await;
+        });
+    }
+
+    /// Drops the records of the write-ahead log which the last stable checkpoint makes unneeded
+    fn compact_wal(&mut self) {
+        self.pending_compaction = None;
+        let wal = match self.wal.as_mut() {
+            Some(wal) => wal,
+            None => return,
+        };
+        let mut head = Vec::new();
+        let in_backend = self.backend.as_ref().is_some_and(|backend| {
+            backend.stable_seq_num() == Some(self.state.last_stable_seq_num)
+        });
+        if let (false, Some(snapshot)) = (in_backend, self.state.stable_snapshot()) {
+            head.push(WalRecord::Snapshot(snapshot.clone()));
+        }
+        head.push(WalRecord::StableCheckpoint(
+            self.state.last_checkpoint_proof.clone(),
+        ));
+        head.push(WalRecord::View(self.state.view));
+        if let Err(e) = wal.compact(self.state.last_stable_seq_num, &head) {
+            error!("Could not compact the write-ahead log: {}", e);
+        }
+    }
+
+    /// Completes the drain once we committed up to its target: our state is announced in a
+    /// final checkpoint, unless one was due there anyway, and the write-ahead log is compacted
+    async fn continue_drain(&mut self) {
+        let drain = match self.drain {
+            Some(drain) if !drain.safe_to_stop => drain,
+            _ => return,
+        };
+        if self.state.last_seq_num_committed < drain.target_seq_num {
+            return;
+        }
+        if !self.state.is_at_checkpoint_boundary() {
+            self.init_checkpoint().await;
+        }
+        self.compact_wal();
+        info!(
+            "Drained at seq-num {}, safe to stop",
+            self.state.last_seq_num_committed
+        );
+        self.drain = Some(DrainStatus {
+            safe_to_stop: true,
+            ..drain
+        });
+    }
+
+    /// Sends the summary of our progress to the peer
+    async fn send_progress(&self, peer_id: NodeId, is_reply: bool) {
+        let peer_addr = match self.config.peer_addrs.get(&peer_id) {
+            Some(peer_addr) => *peer_addr,
+            None => return,
+        };
+        let progress = Progress::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.state.view,
+            self.state.last_stable_seq_num,
+            self.state.last_seq_num_committed,
+            is_reply,
+        );
+        let _ = self
+            .tx_node
+            .send(NodeCommand::SendMessageCommand(SendMessage {
+                destination: peer_addr,
+                message: Message::ProgressMessage(progress),
+            }))
+            .await;
+    }
+
+    /// Sends a peer which is behind us what it needs to catch up, ahead of any other traffic:
+    /// the proof of our stable checkpoint if the peer has not reached it or our view,
+    /// which transfers the state and the view, followed by the pre-prepare, prepares
+    /// and commits of every request we committed after the checkpoint and the peer did not
+    async fn catch_up(&self, progress: &Progress) {
+        if progress.id == self.id
+            || (progress.view >= self.state.view
+                && progress.last_seq_num_committed >= self.state.last_seq_num_committed)
+        {
+            return;
+        }
+
+        let mut messages = Vec::new();
+        if progress.last_stable_seq_num < self.state.last_stable_seq_num
+            || progress.view < self.state.view
+        {
+            for checkpoint in self.state.last_checkpoint_proof.iter() {
+                messages.push(Message::CheckPointMessage(checkpoint.clone()));
+            }
+        }
+
+        let first_seq_num = std::cmp::max(
+            progress.last_seq_num_committed,
+            self.state.last_stable_seq_num,
+        ) + 1;
+        for seq_num in first_seq_num..self.state.last_seq_num_committed + 1 {
+            let view = match self.state.message_bank.applied_commits.get(&seq_num) {
+                Some(applied) => applied.commit.view,
+                None => continue,
+            };
+            let pre_prepare = match self
+                .state
+                .message_bank
+                .accepted_pre_prepare_requests
+                .get(&(view, seq_num))
+            {
+                Some(pre_prepare) => pre_prepare,
+                None => continue,
+            };
+            messages.push(Message::PrePrepareMessage(pre_prepare.clone()));
+            if let Some(prepares) = self.state.prepare_votes.get(&(view, seq_num)) {
+                messages.extend(prepares.values().cloned().map(Message::PrepareMessage));
+            }
+            if let Some(commits) = self.state.commit_votes.get(&(view, seq_num)) {
+                messages.extend(commits.values().cloned().map(Message::CommitMessage));
+            }
+        }
+        if messages.is_empty() {
+            return;
+        }
+
+        info!(
+            "Catching up node {} (view {}, committed seq-num {}) with {} messages",
+            progress.id,
+            progress.view,
+            progress.last_seq_num_committed,
+            messages.len()
+        );
+        let _ = self
+            .tx_node
+            .send(NodeCommand::CatchUpCommand(CatchUp {
+                peer_id: progress.id,
+                messages,
+            }))
+            .await;
+    }
+
+    /// Broadcasts a view change for the pending view, carrying the requests which prepared
+    /// after the last stable checkpoint, and escalates to the next view if the primary
+    /// of the pending view does not complete the view change in time
+    async fn broadcast_view_change(&mut self) {
+        // find all pre-prepares that we have at least 2f + 1 votes for that occurred after the last stable seq-num
+        let mut subsequent_prepares = BTreeMap::<usize, (PrePrepare, Vec<Prepare>)>::new();
+        for ((view, seq_num), pre_prepare) in
+            self.state.message_bank.accepted_pre_prepare_requests.iter()
+        {
+            if *seq_num <= self.state.last_stable_seq_num {
+                // only consider requests with seq_num which come after the last stable seq-num
+                continue;
+            }
+            if let Some(vote_set) = self.state.prepare_votes.get(&(*view, *seq_num)) {
+                if vote_set.len() > 2 * self.config.num_faulty
+                    && subsequent_prepares
+                        .get(seq_num)
+                        .is_none_or(|(e_pre_prepare, _)| e_pre_prepare.view < *view)
+                {
+                    subsequent_prepares.insert(
+                        *seq_num,
+                        (pre_prepare.clone(), vote_set.values().cloned().collect()),
+                    );
+                }
+            }
+        }
+
+        let mut view_change = ViewChange::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.config.epoch,
+            self.state.pending_view,
+            self.state.last_stable_seq_num,
+            self.state.last_checkpoint_proof.clone(),
+            subsequent_prepares,
+        );
+        if self.config.compact_view_changes {
+            view_change = view_change.compacted(self.config.num_faulty);
+        }
+
+        let _ = self
+            .tx_node
+            .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
+                message: Message::ViewChangeMessage(view_change),
+            }))
+            .await;
+
+        // wait twice as long for each view we escalate past
+        let timeout = self.view_changer.timeout();
+        self.view_changer.record_view_change();
+        let view_changer = self.view_changer.clone();
+        let pending_view = self.state.pending_view;
+        logging::spawn(async move {
+            view_changer.wait_for_new_view(pending_view, timeout).await;
+        });
+    }
+
+    /// Reissues the requests we are waiting for after the primary changed: the primary
+    /// assigns them new sequence numbers if they never prepared, and the other replicas
+    /// pass them on to the primary, which may not know of them, and keep waiting for them
+    async fn reissue_pending_requests(&mut self) {
+        let pending_requests = self.view_changer.wait_set();
+        self.view_changer.reset();
+        if self.state.current_leader() == self.id {
+            for request in pending_requests.iter() {
+                info!("Issuing old {:?}", request);
+                self.admit(request.clone(), Priority::Reissued);
+            }
+            return;
+        }
+        let leader_addr = match self.config.peer_addrs.get(&self.state.current_leader()) {
+            Some(leader_addr) => *leader_addr,
+            None => return,
+        };
+        for request in pending_requests {
+            let _ = self
+                .tx_node
+                .send(NodeCommand::SendMessageCommand(SendMessage {
+                    destination: leader_addr,
+                    message: Message::ClientRequestMessage(request.clone()),
+                }))
+                .await;
+            self.view_changer.add_to_wait_set(&request);
+            let view_changer = self.view_changer.clone();
+            logging::spawn(async move {
+                view_changer.wait_for(&request).await;
+            });
+        }
+    }
+
+    /// Assigns the next sequence number to the request and broadcasts a pre-prepare for it.
+    /// We are primary, and the request was accepted into the mempool
+    async fn init_pre_prepare(&mut self, request: ClientRequest) {
+        if self
+            .state
+            .message_bank
+            .sent_requests
+            .contains(&(self.state.view, request.digest()))
+        {
+            return;
+        }
+
+        self.state.seq_num += 1;
+        if request.is_reconfiguration() {
+            self.proposed_reconfiguration = Some(self.state.seq_num);
+        }
+
+        if self.config.is_equivocator {
+            // this node is an equivocator, so we send
+            // different messages to different nodes
+            self.equivocate_pre_prepare(request).await;
+            return;
+        }
+
+        let pre_prepare = PrePrepare::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.config.epoch,
+            self.state.view,
+            self.state.seq_num,
+            &request,
+        );
+        instance_event!(
+            pre_prepare.view,
+            pre_prepare.seq_num,
+            pre_prepare.client_request_digest,
+            "Proposed request"
+        );
+
+        if self.config.is_single_node() {
+            self.state
+                .message_bank
+                .sent_requests
+                .insert((self.state.view, request.digest()));
+            self.commit_single_node(pre_prepare).await;
+            return;
+        }
+
+        // the sequence number is ours to the end of the view, even if we restart before
+        // our own pre-prepare reaches us
+        self.persist(WalRecord::Proposed(pre_prepare.clone()));
+        self.view_changer
+            .add_to_sent_pre_prepares(&(pre_prepare.view, pre_prepare.seq_num));
+
+        let view_changer = self.view_changer.clone();
+        logging::spawn(async move {
+            view_changer
+                .wait_for_sent_pre_prepares(&(pre_prepare.view, pre_prepare.seq_num))
+                .await;
+        });
+
+        let pre_prepare_message = Message::PrePrepareMessage(pre_prepare.clone());
+
+        self.state
+            .message_bank
+            .sent_requests
+            .insert((self.state.view, request.digest()));
+        let _ = self
+            .tx_node
+            .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
+                message: pre_prepare_message.clone(),
+            }))
+            .await;
+    }
+
+    /// Rebroadcasts the pre-prepares we proposed in this view before a restart
+    /// which are not executed by the time their timers expire
+    fn resume_proposals(&mut self) {
+        if self.state.current_leader() != self.id {
+            return;
+        }
+        let proposed: Vec<(usize, usize)> = self
+            .state
+            .message_bank
+            .accepted_pre_prepare_requests
+            .iter()
+            .filter(|((view, seq_num), pre_prepare)| {
+                *view == self.state.view
+                    && *seq_num > self.state.last_seq_num_committed
+                    && pre_prepare.id == self.id
+            })
+            .map(|(view_seq_num_pair, _)| *view_seq_num_pair)
+            .collect();
+        for view_seq_num_pair in proposed {
+            self.view_changer
+                .add_to_sent_pre_prepares(&view_seq_num_pair);
+            let view_changer = self.view_changer.clone();
+            logging::spawn(async move {
+                view_changer
+                    .wait_for_sent_pre_prepares(&view_seq_num_pair)
+                    .await;
+            });
+        }
+    }
+
+    /// Offers a client request to the mempool, which we hold as primary
+    fn admit(&mut self, request: ClientRequest, priority: Priority) {
+        match self.mempool.insert(request, priority) {
+            Admission::Admitted | Admission::Duplicate => {}
+            Admission::Full => sampled!(
+                warn,
+                "mempool_full",
+                "Rejecting client request, the mempool holds {} requests",
+                self.mempool.len()
+            ),
+        }
+    }
+
+    /// Orders the evidence we hold which is not ordered yet, and then proposes the pending
+    /// requests of the mempool and the batches of bulk loads, unless a view change is under way.
+    /// Requests beyond the high water mark stay pending until the next stable checkpoint,
+    /// and requests after a reconfiguration until we move to its epoch
+    async fn propose_pending(&mut self) {
+        if self.state.in_view_change
+            || self.state.current_leader() != self.id
+            || self.proposed_reconfiguration.is_some()
+        {
+            return;
+        }
+        // the accused is passed over from the view after ours, at every replica which
+        // applies the exclusion (a proposal repeated in the view is dropped)
+        for evidence in self.state.evidence.unordered() {
+            if self.state.seq_num >= self.state.high_water_mark() {
+                return;
+            }
+            let exclusion = Exclusion {
+                evidence,
+                from_view: self.state.view + 1,
+            };
+            self.init_pre_prepare(ClientRequest::for_exclusion(&exclusion))
+                .await;
+        }
+        while self.state.seq_num < self.state.high_water_mark()
+            && self.proposed_reconfiguration.is_none()
+        {
+            let request = match self.mempool.pop() {
+                Some(pending) => Some(pending.request),
+                None => self.bulk_ingest.pop(),
+            };
+            match request {
+                Some(request) => self.init_pre_prepare(request).await,
+                None => break,
+            }
+        }
+    }
+
+    /// After a view change, proposes the pending requests if we are the new primary,
+    /// and otherwise passes them on to the new primary
+    async fn release_mempool(&mut self) {
+        if self.state.current_leader() == self.id {
+            self.propose_pending().await;
+            return;
+        }
+        self.bulk_ingest.clear();
+        for pending in self.mempool.drain() {
+            self.follow_ups
+                .push_back(ConsensusCommand::MisdirectedClientRequest(pending.request));
+        }
+    }
+
+    fn update_status(&self) {
+        self.tx_status.send_if_modified(|status| {
+            let new_status = NodeStatus {
+                id: self.id,
+                view: self.state.view,
+                in_view_change: self.state.in_view_change,
+                last_seq_num_committed: self.state.last_seq_num_committed,
+                last_stable_seq_num: self.state.last_stable_seq_num,
+                epoch: self.config.epoch,
+                capabilities: Capabilities::new(self.config.features),
+                // these are filled in by the node
+                stale_messages_dropped: 0,
+                pipeline: PipelineStats::default(),
+                malformed: MalformedStats::default(),
+                mempool: self.mempool.stats(),
+                unverified_messages: self.unverified_messages.clone(),
+                verification: self.verification_budget.stats(),
+                dead_letters: Vec::new(),
+                outbox: OutboxStats::default(),
+                drain: self.drain,
+                clock: ClockStats::default(),
+                connectivity: ConnectivityStats::default(),
+                suspected_nodes: Vec::new(),
+            };
+            let modified = new_status != *status;
+            *status = new_status;
+            modified
+        });
+    }
+
+    /// Public keys of the replicas, preferring the keys pinned in the configuration
+    /// to those peers identified themselves with
+    async fn pub_keys(&self) -> HashMap<NodeId, PublicKey> {
+        let mut pub_keys = self.peer_pub_keys.lock().await.clone();
+        pub_keys.extend(self.config.peer_pub_keys.iter());
+        pub_keys.insert(self.id, self.keystore.public_key());
+        pub_keys
+    }
+
+    /// Our prepare for the request, signed or with an authenticator as the cluster requires
+    async fn prepare(
+        &self,
+        view: usize,
+        seq_num: usize,
+        client_request_digest: Vec<u8>,
+    ) -> Prepare {
+        match &self.authenticator {
+            Some(authenticator) => Prepare::new_with_authenticator(
+                authenticator,
+                &self.pub_keys().await,
+                self.id,
+                self.config.epoch,
+                view,
+                seq_num,
+                client_request_digest,
+            ),
+            None => Prepare::new_with_digest(
+                &self.keystore,
+                self.id,
+                self.config.epoch,
+                view,
+                seq_num,
+                client_request_digest,
+            ),
+        }
+    }
+
+    /// Our commit for the request, signed or with an authenticator as the cluster requires
+    async fn commit(&self, view: usize, seq_num: usize, client_request_digest: Vec<u8>) -> Commit {
+        match &self.authenticator {
+            Some(authenticator) => Commit::new_with_authenticator(
+                authenticator,
+                &self.pub_keys().await,
+                self.id,
+                self.config.epoch,
+                view,
+                seq_num,
+                client_request_digest,
+            ),
+            None => Commit::new_with_signature(
+                &self.keystore,
+                self.id,
+                self.config.epoch,
+                view,
+                seq_num,
+                client_request_digest,
+            ),
+        }
+    }
+
+    /// Checks that a message from a replica is signed by that replica (or for prepares and
+    /// commits, authenticated for us), and for view changes that the votes they carry are
+    /// too, using the verdict of the verification workers if they already verified it. Other
+    /// messages, including those from replicas whose key we do not know, are dropped and
+    /// counted against the replica they claim to come from. Messages from clients are not
+    /// signed. While overloaded, a message may be put off to be verified later, or shed
+    /// (see `VerificationBudget`), and is not authentic for now
+    async fn is_authentic(&mut self, message: &Message, verdict: Option<bool>) -> bool {
+        let peer_id = match message.get_id() {
+            Some(peer_id) => peer_id,
+            None => return true,
+        };
+        let authentic = match verdict {
+            Some(authentic) => {
+                self.verification_budget.record_offloaded();
+                authentic
+            }
+            None => {
+                let overloaded = *self.tx_backpressure.borrow();
+                match self.verification_budget.admit(
+                    message,
+                    self.state.last_stable_seq_num,
+                    overloaded,
+                ) {
+                    Triage::Verify => {}
+                    Triage::Defer => return false,
+                    Triage::Shed => {
+                        sampled!(
+                            warn,
+                            "shed_verification",
+                            "Shedding {} message from {} unverified while overloaded ({} shed)",
+                            message.kind(),
+                            peer_id,
+                            self.verification_budget.stats().total_shed()
+                        );
+                        return false;
+                    }
+                }
+                let started = Instant::now();
+                let authentic = match message {
+                    Message::ViewChangeMessage(view_change) => {
+                        let pub_keys = self.pub_keys().await;
+                        pub_keys
+                            .get(&peer_id)
+                            .is_some_and(|pub_key| message.is_properly_signed_by(pub_key))
+                            && view_change
+                                .are_votes_authentic(&pub_keys, self.authenticator.as_ref())
+                    }
+                    _ => {
+                        let pub_key = match self.config.peer_pub_keys.get(&peer_id) {
+                            Some(pub_key) => Some(*pub_key),
+                            None => self.peer_pub_keys.lock().await.get(&peer_id).copied(),
+                        };
+                        pub_key.is_some_and(|pub_key| {
+                            message.is_authentic(&pub_key, self.authenticator.as_ref())
+                        })
+                    }
+                };
+                self.verification_budget.record(started.elapsed());
+                authentic
+            }
+        };
+        if !authentic {
+            let count = self.unverified_messages.entry(peer_id).or_default();
+            *count += 1;
+            sampled!(
+                warn,
+                "unverified_message",
+                "Dropping {} message from {} as its signature could not be verified ({} dropped from {})",
+                message.kind(),
+                peer_id,
+                count,
+                peer_id
+            );
+        }
+        authentic
+    }
+
+    /// Whether the message is from a member of our epoch and, for protocol messages, for our
+    /// epoch. Checkpoints and view changes of a later epoch are held until we move to it,
+    /// as we may have fallen behind a reconfiguration
+    fn is_of_our_epoch(&mut self, message: &Message) -> bool {
+        match message.epoch() {
+            Some(epoch) if epoch > self.config.epoch => {
+                let progress = |message: &Message| match message {
+                    Message::CheckPointMessage(checkpoint) => {
+                        Some((checkpoint.epoch, checkpoint.committed_seq_num))
+                    }
+                    Message::ViewChangeMessage(view_change) => {
+                        Some((view_change.epoch, view_change.new_view))
+                    }
+                    _ => None,
+                };
+                if let (Some(id), Some(reached)) = (message.get_id(), progress(message)) {
+                    // the senders are not checked yet, so we hold as many as can be members
+                    let key = (id, message.kind());
+                    let is_later = match self.later_epoch_messages.get(&key) {
+                        Some(held) => progress(held).is_some_and(|held| held < reached),
+                        None => self.later_epoch_messages.len() < 2 * limits::MAX_CHECKPOINT_PROOF,
+                    };
+                    if is_later {
+                        self.later_epoch_messages.insert(key, message.clone());
+                    }
+                }
+                return false;
+            }
+            Some(epoch) if epoch < self.config.epoch => return false,
+            _ => {}
+        }
+        match message {
+            // clients are not members, and identifiers are handled by the node
+            Message::IdentifierMessage(_) => true,
+            message => message
+                .get_id()
+                .is_none_or(|id| self.config.peer_addrs.contains_key(&id)),
         }
     }
 
+    /// Subscribe to the nodes which the quorum diagnostics suspect
+    /// of being crashed or partitioned
+    pub fn subscribe_suspected_nodes(&self) -> watch::Receiver<Vec<NodeId>> {
+        self.tx_suspected_nodes.subscribe()
+    }
+
     pub async fn spawn(&mut self) {
+        if self.config.verification_workers > 0 {
+            // the workers take over the queue, and pass on what they verified
+            let (tx_verified, rx_verified) = channel(self.config.consensus_queue_capacity);
+            let rx_queued = std::mem::replace(&mut self.rx_consensus, rx_verified);
+            let pool = VerificationPool::new(
+                self.id,
+                self.keystore.public_key(),
+                &self.config,
+                self.membership.clone(),
+                self.peer_pub_keys.clone(),
+                self.authenticator.clone(),
+            );
+            logging::spawn(pool.run(rx_queued, tx_verified));
+        }
+        self.resume_proposals();
         loop {
-            let res = self.rx_consensus.recv().await;
-            let cmd = res.unwrap();
-            //info!("Consensus Engine Received Command {:?}", cmd);
+            self.continue_drain().await;
+            self.update_status();
+            let (cmd, deferred) = self.next_command().await;
+            self.update_backpressure();
+            let verdict = match &cmd {
+                ConsensusCommand::ProcessVerifiedMessage { authentic, .. } => Some(*authentic),
+                _ => None,
+            };
             match cmd {
-                ConsensusCommand::ProcessMessage(message) => {
-                    match message.clone() {
-                        Message::IdentifierMessage(_) => {
-                            // Identifier messages are not passed to the consensus engine
-                            unreachable!()
+                ConsensusCommand::ProcessMessage(message)
+                | ConsensusCommand::ProcessVerifiedMessage { message, .. } => {
+                    if !deferred {
+                        self.pipeline.record_dequeued(&message);
+                    }
+                    if !self.is_of_our_epoch(&message) {
+                        continue;
+                    }
+                    if !self.is_authentic(&message, verdict).await {
+                        continue;
+                    }
+                    // messages for a view we have not moved to yet are kept until we do
+                    if FutureViewBuffer::view_of(&message)
+                        .is_some_and(|view| view > self.state.view)
+                    {
+                        if !self
+                            .state
+                            .future_view_messages
+                            .push(self.state.view, message)
+                        {
+                            sampled!(
+                                info,
+                                "drop_future_view",
+                                "Dropping message for a future view (my view: {})",
+                                self.state.view
+                            );
+                        }
+                        continue;
+                    }
+                    match message {
+                        Message::IdentifierMessage(identifier) => {
+                            // identities are established by the node, which does not pass
+                            // identifiers on, so one reaching the engine carries nothing new
+                            debug!("Ignoring identifier of node {}", identifier.id);
                         }
 
                         Message::PrePrepareMessage(pre_prepare) => {
-                            //info!("Saw preprepare from {}", pre_prepare.id);
                             if self.state.should_accept_pre_prepare(&pre_prepare) {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::AcceptPrePrepare(pre_prepare))
-                                    .await;
+                                self.follow_ups
+                                    .push_back(ConsensusCommand::AcceptPrePrepare(pre_prepare));
+                            } else if let Some(proof) = self.state.equivocation(&pre_prepare) {
+                                self.expose_equivocation(proof).await;
+                            }
+                        }
+
+                        Message::EquivocationProofMessage(proof) => {
+                            let pub_keys = self.pub_keys().await;
+                            if self
+                                .state
+                                .should_accept_equivocation_proof(&proof, &pub_keys)
+                            {
+                                self.expose_equivocation(proof).await;
+                            }
+                        }
+
+                        Message::BlameMessage(blame) => {
+                            let pub_keys = self.pub_keys().await;
+                            if !blame.evidence.is_valid(&pub_keys, self.config.num_faulty) {
+                                warn!("Dropping blame from {} with invalid evidence", blame.id);
+                                continue;
                             }
+                            let accused = blame.evidence.accused();
+                            match blame.evidence {
+                                Evidence::Equivocation(proof)
+                                    if self
+                                        .state
+                                        .should_accept_equivocation_proof(&proof, &pub_keys) =>
+                                {
+                                    self.expose_equivocation(*proof).await
+                                }
+                                evidence => self.blame(evidence).await,
+                            }
+                            self.state.evidence.corroborate(accused, blame.id);
                         }
                         Message::PrepareMessage(prepare) => {
-                            //info!("Saw prepare from {}", prepare.id);
                             if self.state.should_accept_prepare(&prepare) {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::AcceptPrepare(prepare))
-                                    .await;
+                                self.follow_ups
+                                    .push_back(ConsensusCommand::AcceptPrepare(prepare));
                             } else {
                                 self.state
                                     .message_bank
@@ -108,12 +1110,9 @@ impl Consensus {
                             }
                         }
                         Message::CommitMessage(commit) => {
-                            //info!("Saw commit from {}", commit.id);
                             if self.state.should_accept_commit(&commit) {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::AcceptCommit(commit))
-                                    .await;
+                                self.follow_ups
+                                    .push_back(ConsensusCommand::AcceptCommit(commit));
                             } else {
                                 self.state
                                     .message_bank
@@ -124,66 +1123,288 @@ impl Consensus {
 
                         Message::ViewChangeMessage(view_change) => {
                             if self.state.should_accept_view_change(&view_change) {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::AcceptViewChange(view_change))
-                                    .await;
+                                self.follow_ups
+                                    .push_back(ConsensusCommand::AcceptViewChange(view_change));
                             }
                         }
 
                         Message::NewViewMessage(new_view) => {
-                            if self.state.should_accept_new_view(&new_view) {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::AcceptNewView(new_view))
-                                    .await;
+                            let pub_keys = self.pub_keys().await;
+                            if self.state.should_accept_new_view(&new_view, &pub_keys) {
+                                self.follow_ups
+                                    .push_back(ConsensusCommand::AcceptNewView(new_view));
+                            } else if check_new_view(&new_view, &pub_keys, self.config.num_faulty)
+                                .is_err_and(|fault| fault.is_provable())
+                            {
+                                self.blame(Evidence::InvalidNewView(new_view)).await;
                             }
                         }
 
                         Message::CheckPointMessage(checkpoint) => {
-                            info!(
-                                "Saw checkpoint from {} {} {:?} {:?}",
+                            sampled!(
+                                info,
+                                "saw_checkpoint",
+                                "Saw checkpoint from {} {}",
                                 checkpoint.id,
-                                checkpoint.committed_seq_num,
-                                checkpoint.state,
-                                checkpoint.state_digest
+                                checkpoint.committed_seq_num
                             );
 
                             if self.state.should_accept_checkpoint(&checkpoint) {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::AcceptCheckpoint(checkpoint))
-                                    .await;
+                                self.follow_ups
+                                    .push_back(ConsensusCommand::AcceptCheckpoint(checkpoint));
                             }
                         }
 
                         Message::ClientRequestMessage(client_request) => {
-                            //info!("Saw client request");
+                            // a request which does not authenticate is rejected before it
+                            // is ordered, and again when executed if a primary orders it
+                            if let Err(reason) = self.state.authenticate(&client_request) {
+                                let client_response = ClientResponse::new_with_signature(
+                                    &self.keystore,
+                                    self.id,
+                                    client_request.time_stamp,
+                                    client_request.key.clone(),
+                                    None,
+                                    Vec::new(),
+                                    Some(reason),
+                                )
+                                .with_view(self.state.view);
+                                self.send_client_response(&client_request, client_response)
+                                    .await;
+                                continue;
+                            }
+                            if client_request.is_read_only() {
+                                self.answer_read_only(&client_request).await;
+                                continue;
+                            }
+                            // the client did not get our reply to the request, so we send it again
+                            if let Some(client_response) =
+                                self.state.cached_reply(&client_request).cloned()
+                            {
+                                self.send_client_response(&client_request, client_response)
+                                    .await;
+                                continue;
+                            }
+                            // every correct replica which executed the later request rejects
+                            // this one, so the client learns it waits in vain
+                            if self.state.is_stale_client_request(&client_request) {
+                                let client_response = ClientResponse::new_with_signature(
+                                    &self.keystore,
+                                    self.id,
+                                    client_request.time_stamp,
+                                    client_request.key.clone(),
+                                    None,
+                                    Vec::new(),
+                                    Some(FailureReason::StaleTimestamp),
+                                )
+                                .with_view(self.state.view);
+                                self.send_client_response(&client_request, client_response)
+                                    .await;
+                                continue;
+                            }
                             if self.state.should_process_client_request(&client_request) {
                                 if self.id != self.state.current_leader() {
-                                    let _ = self
-                                        .tx_consensus
-                                        .send(ConsensusCommand::MisdirectedClientRequest(
+                                    self.follow_ups.push_back(
+                                        ConsensusCommand::MisdirectedClientRequest(
                                             client_request.clone(),
-                                        ))
-                                        .await;
+                                        ),
+                                    );
                                 } else {
-                                    // at this point we are the leader and we have accepted a client request
-                                    // which we may begin to process
-                                    let _ = self
-                                        .tx_consensus
-                                        .send(ConsensusCommand::InitPrePrepare(
-                                            client_request.clone(),
-                                        ))
-                                        .await;
+                                    // at this point we are the leader and we have accepted a client request,
+                                    // which waits in the mempool until we assign it a sequence number
+                                    self.admit(client_request, Priority::Normal);
+                                    self.propose_pending().await;
                                 }
                             }
                         }
 
+                        Message::BulkLoadMessage(part) => {
+                            // only the primary chops bulk loads into batches, which the other
+                            // replicas learn of from its pre-prepares
+                            if self.state.in_view_change
+                                || self.id != self.state.current_leader()
+                                || self.state.request_status(
+                                    &ClientIdentity::Addr(part.respond_addr),
+                                    part.time_stamp,
+                                ) != RequestStatus::Unknown
+                            {
+                                continue;
+                            }
+                            self.bulk_ingest.take(part);
+                            self.propose_pending().await;
+                        }
+
                         Message::ClientResponseMessage(_) => {
                             // we should never receive a client response message, so we ignore
                             continue;
                         }
+
+                        Message::RelayedClientResponseMessage(_) => {
+                            // relayed responses are passed back to the client by the node
+                            continue;
+                        }
+
+                        Message::StaleMessageNotice(_) => {
+                            // stale message notices are handled by the node
+                            continue;
+                        }
+
+                        Message::GetProofMessage(get_proof) => {
+                            // proofs are only given against a stable checkpoint
+                            let key_proof = match self.state.key_proof(&get_proof.key) {
+                                Some(key_proof) => key_proof,
+                                None => continue,
+                            };
+                            let _ = self
+                                .tx_node
+                                .send(NodeCommand::SendMessageCommand(SendMessage {
+                                    destination: get_proof.respond_addr,
+                                    message: Message::KeyProofMessage(key_proof),
+                                }))
+                                .await;
+                        }
+
+                        Message::KeyProofMessage(_) => {
+                            // key proofs are sent to clients
+                            continue;
+                        }
+
+                        Message::GetEntryMessage(get_entry) => {
+                            let entry = self.state.get_entry(get_entry.seq_num);
+                            let log_entry = LogEntry {
+                                id: self.id,
+                                seq_num: get_entry.seq_num,
+                                commit: entry.map(|entry| entry.commit.clone()),
+                                request: entry.map(|entry| entry.request.as_ref().clone()),
+                                rejected: entry.and_then(|entry| entry.rejected),
+                            };
+                            let _ = self
+                                .tx_node
+                                .send(NodeCommand::SendMessageCommand(SendMessage {
+                                    destination: get_entry.respond_addr,
+                                    message: Message::LogEntryMessage(Box::new(log_entry)),
+                                }))
+                                .await;
+                        }
+
+                        Message::GetAtMessage(get_at) => {
+                            let value = self.state.get_at(&get_at.key, get_at.seq_num);
+                            let value_at = ValueAt {
+                                id: self.id,
+                                retained: value.is_some(),
+                                value: value.flatten(),
+                                key: get_at.key,
+                                seq_num: get_at.seq_num,
+                            };
+                            let _ = self
+                                .tx_node
+                                .send(NodeCommand::SendMessageCommand(SendMessage {
+                                    destination: get_at.respond_addr,
+                                    message: Message::ValueAtMessage(value_at),
+                                }))
+                                .await;
+                        }
+
+                        Message::LogEntryMessage(_) | Message::ValueAtMessage(_) => {
+                            // log entries and values of the history are sent to clients
+                            continue;
+                        }
+
+                        Message::GetRequestStatusMessage(get_status) => {
+                            self.send_request_statuses(get_status).await;
+                        }
+
+                        Message::RequestStatusMessage(_) => {
+                            // request statuses are sent to clients
+                            continue;
+                        }
+
+                        Message::ProgressMessage(progress) => {
+                            if !progress.is_reply {
+                                self.send_progress(progress.id, true).await;
+                            }
+                            self.catch_up(&progress).await;
+                        }
+
+                        Message::StatusRequestMessage(_)
+                        | Message::StatusMessage(_)
+                        | Message::WatchLeaderMessage(_)
+                        | Message::LeaderMessage(_)
+                        | Message::CompactLogMessage(_)
+                        | Message::WatchProgressMessage(_)
+                        | Message::CommitProgressMessage(_)
+                        | Message::DrainMessage(_)
+                        | Message::GetDiffMessage(_)
+                        | Message::CheckpointDiffMessage(_)
+                        | Message::GetEvidenceMessage(_)
+                        | Message::EvidenceMessage(_)
+                        | Message::PingMessage(_)
+                        | Message::PongMessage(_) => {
+                            // status requests, watches, compactions, drains, diffs, evidence
+                            // requests and pings are handled by the node
+                            continue;
+                        }
+
+                        Message::StateChunkRequestMessage(request) => {
+                            // a replica which fell behind is fetching the state at a stable checkpoint
+                            self.send_state_chunk(request).await;
+                        }
+
+                        Message::StateChunkResponseMessage(response) => {
+                            self.accept_state_chunk(response).await;
+                        }
+
+                        Message::FetchRequestBodyMessage(fetch) => {
+                            // a new primary is missing the body of a request prepared in a compact view change
+                            let client_request =
+                                self.state.message_bank.request_body(&fetch.digest);
+                            let peer_addr = self.config.peer_addrs.get(&fetch.id);
+                            if let (Some(client_request), Some(peer_addr)) =
+                                (client_request, peer_addr)
+                            {
+                                let _ = self
+                                    .tx_node
+                                    .send(NodeCommand::SendMessageCommand(SendMessage {
+                                        destination: *peer_addr,
+                                        message: Message::RequestBodyMessage(RequestBody {
+                                            id: self.id,
+                                            client_request: (*client_request).clone(),
+                                        }),
+                                    }))
+                                    .await;
+                            }
+                        }
+
+                        Message::RequestBodyMessage(request_body) => {
+                            // the request was digested with an algorithm we accept,
+                            // which depends on the sequence number it was prepared at
+                            let digest = crypto::policy()
+                                .accepted_algorithms()
+                                .into_iter()
+                                .map(|algorithm| request_body.client_request.digest_with(algorithm))
+                                .find(|digest| self.state.awaiting_request_bodies.contains(digest));
+                            let digest = match digest {
+                                Some(digest) => digest,
+                                None => continue,
+                            };
+                            self.state.awaiting_request_bodies.remove(&digest);
+                            self.state
+                                .message_bank
+                                .store_request_body(&digest, &request_body.client_request);
+                            if self.state.awaiting_request_bodies.is_empty() {
+                                // we have every request we need, so we retry the new view
+                                if let Some(view_change) = self
+                                    .state
+                                    .view_change_votes
+                                    .values()
+                                    .max_by_key(|view_change| view_change.new_view)
+                                    .cloned()
+                                {
+                                    self.follow_ups
+                                        .push_back(ConsensusCommand::AcceptViewChange(view_change));
+                                }
+                            }
+                        }
                     }
                 }
 
@@ -197,7 +1418,7 @@ impl Consensus {
                         .state
                         .message_bank
                         .sent_requests
-                        .contains(&(self.state.view, request.clone()))
+                        .contains(&(self.state.view, request.digest()))
                     {
                         continue;
                     }
@@ -211,7 +1432,7 @@ impl Consensus {
                     self.state
                         .message_bank
                         .sent_requests
-                        .insert((self.state.view, request.clone()));
+                        .insert((self.state.view, request.digest()));
                     let leader = self.state.current_leader();
                     let leader_addr = self.config.peer_addrs.get(&leader).unwrap();
                     let _ = self
@@ -226,66 +1447,12 @@ impl Consensus {
                     let newly_added = self.view_changer.add_to_wait_set(&request);
                     if newly_added {
                         let view_changer = self.view_changer.clone();
-                        tokio::spawn(async move {
+                        logging::spawn(async move {
                             view_changer.wait_for(&request.clone()).await;
                         });
                     }
                 }
 
-                ConsensusCommand::InitPrePrepare(request) => {
-                    // Here we are primary and received a client request which we deemed valid
-                    // so we broadcast a Pre_prepare Message to the network and assign
-                    // the next sequence number to this request
-                    if self
-                        .state
-                        .message_bank
-                        .sent_requests
-                        .contains(&(self.state.view, request.clone()))
-                    {
-                        continue;
-                    }
-
-                    self.state.seq_num += 1;
-
-                    if self.config.is_equivocator {
-                        // this node is an equivocator, so we send
-                        // different messages to different nodes
-                        self.equivocate_pre_prepare(request).await;
-                        continue;
-                    }
-
-                    let pre_prepare = PrePrepare::new_with_signature(
-                        self.keypair_bytes.clone(),
-                        self.id,
-                        self.state.view,
-                        self.state.seq_num,
-                        &request,
-                    );
-
-                    self.view_changer
-                        .add_to_sent_pre_prepares(&(pre_prepare.view, pre_prepare.seq_num));
-
-                    let view_changer = self.view_changer.clone();
-                    tokio::spawn(async move {
-                        view_changer
-                            .wait_for_sent_pre_prepares(&(pre_prepare.view, pre_prepare.seq_num))
-                            .await;
-                    });
-
-                    let pre_prepare_message = Message::PrePrepareMessage(pre_prepare.clone());
-
-                    self.state
-                        .message_bank
-                        .sent_requests
-                        .insert((self.state.view, request.clone()));
-                    let _ = self
-                        .tx_node
-                        .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
-                            message: pre_prepare_message.clone(),
-                        }))
-                        .await;
-                }
-
                 ConsensusCommand::RebroadcastPrePrepare(view_seq_num_pair) => {
                     // we are the leader and a pre-prepare message we sent has not been execute for some time
                     // so we rebroadcast the message to the networks
@@ -313,7 +1480,7 @@ impl Consensus {
                         }))
                         .await;
                     let view_changer = self.view_changer.clone();
-                    tokio::spawn(async move {
+                    logging::spawn(async move {
                         view_changer
                             .wait_for_sent_pre_prepares(&(pre_prepare.view, pre_prepare.seq_num))
                             .await;
@@ -321,118 +1488,21 @@ impl Consensus {
                 }
 
                 ConsensusCommand::AcceptPrePrepare(pre_prepare) => {
-                    // We received a PrePrepare message from the network, and we see no violations
-                    // So we will broadcast a corresponding prepare message and begin to count votes
-                    //info!("Accepted PrePrepare from {}", pre_prepare.id);
-                    self.state
-                        .message_bank
-                        .accepted_pre_prepare_requests
-                        .insert((pre_prepare.view, pre_prepare.seq_num), pre_prepare.clone());
-
-                    let prepare = Prepare::new_with_signature(
-                        self.keypair_bytes.clone(),
-                        self.id,
-                        pre_prepare.view,
-                        pre_prepare.seq_num,
-                        &pre_prepare.clone().client_request,
-                    );
-
-                    let prepare_message = Message::PrepareMessage(prepare.clone());
-                    let _ = self
-                        .tx_node
-                        .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
-                            message: prepare_message.clone(),
-                        }))
-                        .await;
-
-                    // we may already have a got a prepare message which we did not accept because
-                    // we did not receive this pre-prepare message message yet
-                    for e_prepare in self.state.message_bank.outstanding_prepares.iter() {
-                        if e_prepare.corresponds_to(&pre_prepare) {
-                            info!("Found outstanding prepare from {}", e_prepare.id);
-                            let _ = self
-                                .tx_consensus
-                                .send(ConsensusCommand::AcceptPrepare(e_prepare.clone()))
-                                .await;
-                        }
-                    }
-
-                    // at this point, we need to trigger a timer, and if the timer expires
-                    // and the request is still outstanding, then we need to trigger a view change
-                    // as this is evidence that the system has stopped making progress
-                    let newly_added = self
-                        .view_changer
-                        .add_to_wait_set(&pre_prepare.client_request);
-                    if newly_added {
-                        let view_changer = self.view_changer.clone();
-                        tokio::spawn(async move {
-                            view_changer.wait_for(&pre_prepare.client_request).await;
-                        });
-                    }
+                    let span = logging::instance_span(pre_prepare.view, pre_prepare.seq_num);
+                    self.accept_pre_prepare(pre_prepare).instrument(span).await;
                 }
 
                 ConsensusCommand::AcceptPrepare(prepare) => {
-                    // We saw a prepare message from the network that we deemed was valid
-                    // to we increment the vote count, and if we have enough prepare votes
-                    // then we move to the commit phases
-
-                    //info!("Accepted Prepare from {}", prepare.id);
-
-                    // we are now accepting this prepare, so if it is our outstanding set, then
-                    // we may remove it
-                    self.state
-                        .message_bank
-                        .outstanding_prepares
-                        .remove(&prepare);
-
-                    // TODO: Move the prepare votes into the state struct
-                    // Count votes for this prepare message and see if we have enough to move to the commit phases
-                    if let Some(curr_vote_set) = self
-                        .state
-                        .prepare_votes
-                        .get_mut(&(prepare.view, prepare.seq_num))
-                    {
-                        curr_vote_set.insert(prepare.id, prepare.clone());
-                        if curr_vote_set.len() > 2 * self.config.num_faulty {
-                            // at this point, we have enough prepare votes to move into the commit phase.
-                            let _ = self
-                                .view_changer
-                                .tx_consensus
-                                .send(ConsensusCommand::EnterCommit(prepare.clone()))
-                                .await;
-                        }
-                    } else {
-                        // first time we got a prepare message for this view and sequence number
-                        let mut new_vote_set = HashMap::<usize, Prepare>::new();
-                        new_vote_set.insert(prepare.id, prepare.clone());
-                        self.state
-                            .prepare_votes
-                            .insert((prepare.view, prepare.seq_num), new_vote_set);
-                    }
-
-                    // we may already have a got a commit message which we did not accept because
-                    // we did not receive this prepare message message yet
-                    for e_commit in self.state.message_bank.outstanding_commits.iter() {
-                        if e_commit.corresponds_to(&prepare) {
-                            info!("Found outstanding commit from {}", e_commit.id);
-                            let _ = self
-                                .tx_consensus
-                                .send(ConsensusCommand::AcceptCommit(e_commit.clone()))
-                                .await;
-                        }
-                    }
+                    let span = logging::instance_span(prepare.view, prepare.seq_num);
+                    self.accept_prepare(prepare).instrument(span).await;
                 }
 
                 ConsensusCommand::EnterCommit(prepare) => {
                     //todo make a new commit message builder
 
-                    let commit = Commit::new_with_signature(
-                        self.keypair_bytes.clone(),
-                        self.id,
-                        prepare.view,
-                        prepare.seq_num,
-                        prepare.client_request_digest,
-                    );
+                    let commit = self
+                        .commit(prepare.view, prepare.seq_num, prepare.client_request_digest)
+                        .await;
 
                     let commit_message = Message::CommitMessage(commit);
                     let _ = self
@@ -444,32 +1514,8 @@ impl Consensus {
                 }
 
                 ConsensusCommand::AcceptCommit(commit) => {
-                    // We received a Commit Message for a request that we deemed valid
-                    // so we increment the vote count
-
-                    self.state.message_bank.outstanding_commits.remove(&commit);
-
-                    if let Some(curr_vote_set) = self
-                        .state
-                        .commit_votes
-                        .get_mut(&(commit.view, commit.seq_num))
-                    {
-                        curr_vote_set.insert(commit.id);
-                        if curr_vote_set.len() > 2 * self.config.num_faulty {
-                            // At this point, we have enough commit votes to commit the message
-                            let _ = self
-                                .tx_consensus
-                                .send(ConsensusCommand::ApplyCommit(commit))
-                                .await;
-                        }
-                    } else {
-                        // first time we got a prepare message for this view and sequence number
-                        let mut new_vote_set = HashSet::new();
-                        new_vote_set.insert(commit.id);
-                        self.state
-                            .commit_votes
-                            .insert((commit.view, commit.seq_num), new_vote_set);
-                    }
+                    let span = logging::instance_span(commit.view, commit.seq_num);
+                    self.accept_commit(commit).instrument(span).await;
                 }
 
                 ConsensusCommand::InitViewChange(_request) => {
@@ -478,129 +1524,215 @@ impl Consensus {
                         continue;
                     }
                     self.state.in_view_change = true;
+                    self.state.pending_view = self.state.view + 1;
+                    self.broadcast_view_change().await;
+                }
 
-                    // find all pre-prepares that we have at least 2f + 1 votes for that occurred after the last stable seq-num
-
-                    let mut subsequent_prepares =
-                        HashMap::<usize, (PrePrepare, Vec<Prepare>)>::new();
-                    for ((view, seq_num), pre_prepare) in
-                        self.state.message_bank.accepted_pre_prepare_requests.iter()
-                    {
-                        if *seq_num <= self.state.last_stable_seq_num {
-                            // only consider requests with seq_num which come after the last stable seq-num
-                            continue;
-                        }
-                        if let Some(vote_set) = self.state.prepare_votes.get(&(*view, *seq_num)) {
-                            if vote_set.len() > 2 * self.config.num_faulty {
-                                subsequent_prepares.insert(
-                                    *seq_num,
-                                    (
-                                        pre_prepare.clone(),
-                                        vote_set
-                                            .clone()
-                                            .into_iter()
-                                            .map(|(_, prepare)| prepare)
-                                            .collect(),
-                                    ),
-                                );
-                            }
-                        }
+                ConsensusCommand::NewViewTimeout(view) => {
+                    if !self.state.in_view_change || self.state.pending_view != view {
+                        // we moved on since the view change for this view
+                        continue;
                     }
+                    // the primary of the view did not complete the view change in time,
+                    // so we move on to the view after it
+                    self.state.pending_view += 1;
+                    warn!(
+                        "No new view for view {}, changing to view {}",
+                        view, self.state.pending_view
+                    );
+                    self.broadcast_view_change().await;
+                }
 
-                    let view_change = ViewChange::new_with_signature(
-                        self.keypair_bytes.clone(),
-                        self.id,
-                        self.state.view + 1,
-                        self.state.last_stable_seq_num,
-                        self.state.last_checkpoint_proof.clone(),
-                        subsequent_prepares,
+                ConsensusCommand::RequestBodiesTimeout(view) => {
+                    if self.state.awaiting_request_bodies.is_empty() || self.state.view >= view {
+                        continue;
+                    }
+                    warn!(
+                        "Giving up on fetching {} client requests for the new view {}",
+                        self.state.awaiting_request_bodies.len(),
+                        view
                     );
+                    self.state.awaiting_request_bodies.clear();
+                    // we cannot complete the view change, so as when its timer expires
+                    // we move on to the view after it
+                    if !self.state.in_view_change || self.state.pending_view <= view {
+                        self.state.in_view_change = true;
+                        self.state.pending_view = view;
+                        self.follow_ups
+                            .push_back(ConsensusCommand::NewViewTimeout(view));
+                    }
+                }
 
-                    let _ = self
-                        .tx_node
-                        .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
-                            message: Message::ViewChangeMessage(view_change),
-                        }))
-                        .await;
+                ConsensusCommand::ExportSnapshot => {
+                    let path = match self.config.snapshot_export.as_ref() {
+                        Some(path) => path,
+                        None => continue,
+                    };
+                    match self.state.export_snapshot(path, &self.keystore) {
+                        Ok(seq_num) => info!(
+                            "Exported the state at seq-num {} to {}",
+                            seq_num,
+                            path.display()
+                        ),
+                        Err(e) => error!("Could not export a snapshot: {}", e),
+                    }
+                }
+
+                ConsensusCommand::CompactLog { forced } => {
+                    if forced {
+                        info!("Compacting the write-ahead log on request");
+                        self.compact_wal();
+                    } else {
+                        self.compact_wal_when_quiet();
+                    }
+                }
+
+                ConsensusCommand::Drain { target_seq_num } => {
+                    // the instances we accepted a pre-prepare for are in flight, and as primary
+                    // also those we assigned a sequence number to
+                    let mut in_flight = self
+                        .state
+                        .message_bank
+                        .accepted_pre_prepare_requests
+                        .keys()
+                        .map(|(_, seq_num)| *seq_num)
+                        .max()
+                        .unwrap_or(0);
+                    if self.state.current_leader() == self.id {
+                        in_flight = in_flight.max(self.state.seq_num);
+                    }
+                    let target_seq_num = target_seq_num
+                        .unwrap_or(0)
+                        .max(in_flight)
+                        .max(self.state.last_seq_num_committed);
+                    info!("Draining up to seq-num {}", target_seq_num);
+                    self.drain = Some(DrainStatus {
+                        target_seq_num,
+                        safe_to_stop: false,
+                    });
+                }
+
+                ConsensusCommand::PeerReconnected(peer_id) => {
+                    // whichever of us is behind is caught up by the other
+                    // once it has the summary of its progress
+                    info!("Exchanging progress with node {}", peer_id);
+                    self.send_progress(peer_id, false).await;
                 }
 
                 ConsensusCommand::AcceptViewChange(view_change) => {
                     // update the vote count
                     // if there are enough votes (and we are the primary for the next view)
                     // then we broadcast a corresponding new_view message
+                    if self
+                        .state
+                        .view_change_votes
+                        .get(&view_change.id)
+                        .is_some_and(|e_view_change| e_view_change.new_view > view_change.new_view)
+                    {
+                        continue;
+                    }
                     self.state
                         .view_change_votes
                         .insert(view_change.id, view_change.clone());
-                    if self.state.view_change_votes.len() > 2 * self.config.num_faulty {
+                    let view_change_messages = self
+                        .state
+                        .view_change_votes
+                        .values()
+                        .filter(|e_view_change| e_view_change.new_view == view_change.new_view)
+                        .cloned()
+                        .collect::<Vec<ViewChange>>();
+                    if view_change_messages.len() > 2 * self.config.num_faulty {
                         // broadcast a new view message
+                        self.observers.on_quorum(
+                            QuorumKind::ViewChange,
+                            view_change.new_view,
+                            view_change.last_stable_seq_num,
+                            &view_change_messages
+                                .iter()
+                                .map(|e_view_change| e_view_change.id)
+                                .collect::<Vec<NodeId>>(),
+                        );
 
-                        let mut view_change_messages = Vec::<ViewChange>::new();
-                        for (_, view_change) in self.state.view_change_votes.iter() {
-                            view_change_messages.push(view_change.clone());
-                        }
+                        let requests = NewViewRequests::from_view_changes(&view_change_messages);
 
-                        let mut latest_stable_seq_num = self.state.last_stable_seq_num;
-                        let mut max_seq_num = self.state.last_stable_seq_num;
-                        for view_change in view_change_messages.iter() {
-                            latest_stable_seq_num = std::cmp::max(
-                                latest_stable_seq_num,
-                                view_change.last_stable_seq_num,
+                        // compact view changes only carry digests, so we fetch
+                        // the bodies of any requests we do not have before moving on
+                        let mut client_requests = HashMap::<usize, ClientRequest>::new();
+                        let mut missing_bodies = HashSet::<Vec<u8>>::new();
+                        for (seq_num, (_, digest, client_request)) in requests.prepared.iter() {
+                            let client_request = client_request.clone().or_else(|| {
+                                self.state
+                                    .message_bank
+                                    .request_body(digest)
+                                    .map(|client_request| (*client_request).clone())
+                            });
+                            match client_request {
+                                Some(client_request) => {
+                                    client_requests.insert(*seq_num, client_request);
+                                }
+                                None => {
+                                    missing_bodies.insert(digest.clone());
+                                }
+                            }
+                        }
+                        if !missing_bodies.is_empty() {
+                            info!(
+                                "Fetching {} client requests before the new view",
+                                missing_bodies.len()
                             );
-                            for (seq_num, _) in view_change.subsequent_prepares.iter() {
-                                max_seq_num = std::cmp::max(max_seq_num, *seq_num);
+                            for digest in missing_bodies.iter() {
+                                let _ = self
+                                    .tx_node
+                                    .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
+                                        message: Message::FetchRequestBodyMessage(
+                                            FetchRequestBody {
+                                                id: self.id,
+                                                digest: digest.clone(),
+                                            },
+                                        ),
+                                    }))
+                                    .await;
+                            }
+                            if self.state.awaiting_request_bodies != missing_bodies {
+                                // the fetch is bounded by the view change timer, as the
+                                // replicas holding the requests may be faulty
+                                let tx_consensus = self.tx_consensus.clone();
+                                let timeout = self.view_changer.timeout();
+                                let view = view_change.new_view;
+                                logging::spawn(async move {
+                                    sleep(timeout).await;
+                                    let _ = tx_consensus
+                                        .send(ConsensusCommand::RequestBodiesTimeout(view))
+                                        .await;
+                                });
                             }
+                            self.state.awaiting_request_bodies = missing_bodies;
+                            continue;
                         }
 
+                        // re-propose the prepared requests in the new view,
+                        // filling gaps in the sequence numbers with no-op requests
                         let mut outstanding_pre_prepares = Vec::<PrePrepare>::new();
-                        self.state.seq_num = latest_stable_seq_num;
-                        for seq_num in latest_stable_seq_num + 1..max_seq_num + 1 {
-                            let mut pre_prepare_highest_view_at_seq_num: Option<PrePrepare> = None;
-                            for view_change in view_change_messages.iter() {
-                                if let Some((pre_prepare, _)) =
-                                    view_change.subsequent_prepares.get(&seq_num)
-                                {
-                                    if pre_prepare_highest_view_at_seq_num.clone().is_none()
-                                        || pre_prepare.view
-                                            > pre_prepare_highest_view_at_seq_num
-                                                .clone()
-                                                .unwrap()
-                                                .view
-                                    {
-                                        pre_prepare_highest_view_at_seq_num =
-                                            Some(pre_prepare.clone());
-                                    }
-                                }
-                            }
-
-                            let new_pre_prepare_for_view =
-                                if let Some(e_pre_prepare) = pre_prepare_highest_view_at_seq_num {
-                                    PrePrepare::new_with_signature(
-                                        self.keypair_bytes.clone(),
-                                        self.id,
-                                        self.state.view + 1,
-                                        seq_num,
-                                        &e_pre_prepare.client_request.clone(),
-                                    )
-                                } else {
-                                    // create a pre-prepare with a no-op request
-                                    // to fill in gaps in sequence number
-                                    PrePrepare::new_with_signature(
-                                        self.keypair_bytes.clone(),
-                                        self.id,
-                                        self.state.view + 1,
-                                        seq_num,
-                                        &ClientRequest::no_op(),
-                                    )
-                                };
-                            outstanding_pre_prepares.push(new_pre_prepare_for_view);
+                        for seq_num in requests.min_seq_num + 1..requests.max_seq_num + 1 {
+                            let no_op = ClientRequest::no_op();
+                            let client_request = client_requests.get(&seq_num).unwrap_or(&no_op);
+                            outstanding_pre_prepares.push(PrePrepare::new_with_signature(
+                                &self.keystore,
+                                self.id,
+                                self.config.epoch,
+                                view_change.new_view,
+                                seq_num,
+                                client_request,
+                            ));
                         }
 
                         let new_view = NewView::new_with_signature(
-                            self.keypair_bytes.clone(),
+                            &self.keystore,
                             self.id,
+                            self.config.epoch,
                             view_change.new_view,
                             view_change_messages,
-                            outstanding_pre_prepares.clone(),
+                            outstanding_pre_prepares,
                         );
 
                         let _ = self
@@ -615,57 +1747,98 @@ impl Consensus {
                 ConsensusCommand::AcceptNewView(new_view) => {
                     self.state.in_view_change = false;
                     self.state.checkpoint_votes.clear();
+                    self.state
+                        .view_change_votes
+                        .retain(|_, view_change| view_change.new_view > new_view.view);
                     self.state.view = new_view.view;
+                    self.observers.on_view_change(new_view.view);
+                    self.persist(WalRecord::View(new_view.view));
 
                     info!("Moving to view {}", new_view.view);
-                    if self.state.current_leader() == self.id {
+                    let is_leader = self.state.current_leader() == self.id;
+                    if is_leader {
                         info!("I AM NEW LEADER (Node {})", self.id);
                     }
 
-                    if self.state.current_leader() == self.id {
-                        // if we are the leader in this new view,
-                        // then we need to broadcast outstanding pre-prepares in this new view
-                        for pre_prepare in new_view.outstanding_pre_prepares.iter() {
-                            let _ = self
-                                .tx_consensus
-                                .send(ConsensusCommand::InitPrePrepare(
-                                    pre_prepare.clone().client_request,
-                                ))
-                                .await;
+                    // every replica processes the re-proposed requests as pre-prepares of the
+                    // new view, so requests prepared in an earlier view keep their sequence numbers
+                    let outstanding_pre_prepares = new_view
+                        .outstanding_pre_prepares
+                        .into_iter()
+                        .filter(|pre_prepare| pre_prepare.seq_num > self.state.last_stable_seq_num)
+                        .collect::<Vec<PrePrepare>>();
+                    self.notify_unordered(&outstanding_pre_prepares).await;
+                    if is_leader {
+                        for pre_prepare in outstanding_pre_prepares.iter() {
+                            self.state.seq_num = self.state.seq_num.max(pre_prepare.seq_num);
+                            self.state
+                                .message_bank
+                                .sent_requests
+                                .insert((self.state.view, pre_prepare.client_request.digest()));
                         }
-                        for request in self.view_changer.wait_set().iter() {
-                            info!("Issuing old {:?}", request);
-                            let _ = self
-                                .tx_consensus
-                                .send(ConsensusCommand::InitPrePrepare(request.clone()))
+                    }
+                    let tx_consensus = self.tx_consensus.clone();
+                    let pipeline = self.pipeline.clone();
+                    logging::spawn(async move {
+                        for pre_prepare in outstanding_pre_prepares {
+                            pipeline
+                                .send(&tx_consensus, Message::PrePrepareMessage(pre_prepare))
                                 .await;
                         }
-                    }
+                    });
+                    self.replay_future_view_messages();
 
-                    self.view_changer.reset();
+                    // a reconfiguration we proposed is re-proposed by the new primary if it
+                    // prepared, and otherwise reissued like any other request
+                    self.proposed_reconfiguration = None;
+                    self.reissue_pending_requests().await;
+                    self.release_mempool().await;
                 }
 
                 ConsensusCommand::ApplyCommit(commit) => {
+                    // the requests after a reconfiguration are ordered again in its epoch
+                    if self
+                        .state
+                        .pending_reconfiguration
+                        .is_some_and(|seq_num| commit.seq_num > seq_num)
+                    {
+                        continue;
+                    }
                     // we now have permission to apply the client request
-                    let pre_prepare = self
+                    let client_request = match self
                         .state
                         .message_bank
                         .accepted_pre_prepare_requests
-                        .get(&(commit.view, commit.seq_num));
-
-                    if pre_prepare.is_none() {
-                        continue;
+                        .get(&(commit.view, commit.seq_num))
+                        .and_then(|pre_prepare| {
+                            self.state
+                                .message_bank
+                                .request_body(&pre_prepare.client_request_digest)
+                        }) {
+                        Some(client_request) => client_request,
+                        None => continue,
+                    };
+
+                    self.apply_commit(&commit, client_request).await;
+                    // a reconfiguration we proposed which was rejected holds nothing back
+                    if self
+                        .proposed_reconfiguration
+                        .is_some_and(|seq_num| seq_num <= self.state.last_seq_num_committed)
+                        && self.state.pending_reconfiguration.is_none()
+                    {
+                        self.proposed_reconfiguration = None;
+                        self.propose_pending().await;
                     }
-                    let client_request = pre_prepare.unwrap().clone().client_request;
-
-                    self.apply_commit(&commit, &client_request).await;
-                    info!(
-                        "Current State: {}: {:?}",
-                        self.state.last_seq_num_committed, self.state.store
+                    sampled!(
+                        info,
+                        "current_state",
+                        "Current State: {}: {} keys",
+                        self.state.last_seq_num_committed,
+                        self.state.store.len()
                     );
 
                     // The request we just committed was enough to now trigger a checkpoint
-                    if self.state.last_seq_num_committed % self.config.checkpoint_frequency == 0
+                    if self.state.is_at_checkpoint_boundary()
                         && self.state.last_seq_num_committed > self.state.last_stable_seq_num
                     {
                         // The request we just committed was enough to now trigger a checkpoint
@@ -673,6 +1846,21 @@ impl Consensus {
                     }
                 }
 
+                ConsensusCommand::StateChunkTimeout {
+                    committed_seq_num,
+                    start,
+                } => {
+                    // the source did not send the chunk in time, so we ask another replica
+                    if let Some(transfer) = self.state_transfer.as_mut() {
+                        if transfer.checkpoint.committed_seq_num == committed_seq_num
+                            && transfer.next_start() == start
+                        {
+                            transfer.next_source();
+                            self.request_state_chunk().await;
+                        }
+                    }
+                }
+
                 ConsensusCommand::AcceptCheckpoint(checkpoint) => {
                     self.state.message_bank.checkpoint_messages.insert(
                         (
@@ -686,125 +1874,346 @@ impl Consensus {
                         .checkpoints_current_round
                         .insert(checkpoint.id, checkpoint.clone());
 
-                    // increment vote count for checkpoint with given committed seq num and given digest
-                    if let Some(curr_vote_set) = self.state.checkpoint_votes.get_mut(&(
-                        checkpoint.committed_seq_num,
-                        checkpoint.state_digest.clone(),
-                    )) {
-                        curr_vote_set.insert(checkpoint.id);
-
-                        if curr_vote_set.len() >= 2 * self.config.num_faulty {
-                            // At this point, we have enough checkpoint messages to update out state
-                            info!("Updating state from checkpoint");
-
-                            if self.state.last_seq_num_committed < checkpoint.committed_seq_num {
-                                // if this node is still behind after applying all commits in the checkpoint,
-                                // we fast-forward its state, but note that no client responses are sent.
-                                self.state.store = checkpoint.state;
-                                self.state.last_seq_num_committed = checkpoint.committed_seq_num;
+                    // increment vote count for checkpoint with given committed seq num and given digest.
+                    // Votes we already counted and checkpoints which are stable already change nothing
+                    let curr_vote_set = self
+                        .state
+                        .checkpoint_votes
+                        .entry((
+                            checkpoint.committed_seq_num,
+                            checkpoint.state_digest.clone(),
+                        ))
+                        .or_default();
+                    let is_new_vote = curr_vote_set.insert(checkpoint.id);
+                    // after a reconfiguration, the checkpoint we moved to the new epoch at
+                    // becomes stable again in the new epoch
+                    let is_later = checkpoint.committed_seq_num > self.state.last_stable_seq_num
+                        || (checkpoint.committed_seq_num == self.state.last_stable_seq_num
+                            && self
+                                .state
+                                .last_checkpoint_proof
+                                .first()
+                                .is_some_and(|proof| proof.epoch < checkpoint.epoch));
+                    if is_new_vote && is_later && curr_vote_set.len() > 2 * self.config.num_faulty {
+                        // At this point, we have enough checkpoint messages to update out state
+                        info!("Updating state from checkpoint");
+                        self.observers.on_quorum(
+                            QuorumKind::CheckPoint,
+                            checkpoint.view,
+                            checkpoint.committed_seq_num,
+                            &curr_vote_set.iter().copied().collect::<Vec<NodeId>>(),
+                        );
+
+                        if self.state.last_seq_num_committed < checkpoint.committed_seq_num {
+                            // if this node is still behind after applying all commits in the checkpoint,
+                            // we fetch the state certified by the checkpoints of 2f + 1 nodes in chunks,
+                            // and only move to the checkpoint once we have all of it.
+                            // Note that no client responses are sent for the requests it covers
+                            if self.state_transfer.as_ref().is_some_and(|transfer| {
+                                transfer.checkpoint.committed_seq_num
+                                    >= checkpoint.committed_seq_num
+                            }) {
+                                continue;
+                            }
+                            let certificate: Vec<CheckPoint> = curr_vote_set
+                                .iter()
+                                .filter_map(|node_id| {
+                                    self.state.checkpoints_current_round.get(node_id)
+                                })
+                                .cloned()
+                                .collect();
+                            let pub_keys = self.pub_keys().await;
+                            match self.state.validate_certificate(
+                                checkpoint.committed_seq_num,
+                                &checkpoint.state_digest,
+                                &certificate,
+                                &pub_keys,
+                            ) {
+                                Ok(()) => {
+                                    self.state_transfer = Some(StateTransfer::new(
+                                        self.id,
+                                        checkpoint.clone(),
+                                        certificate,
+                                    ));
+                                    self.request_state_chunk().await;
+                                }
+                                Err(e) => warn!(
+                                    "Not fetching the state at seq-num {}: {}",
+                                    checkpoint.committed_seq_num, e
+                                ),
                             }
+                            continue;
+                        }
 
-                            // make a new proof of this checkpoint for subsequent view change messages
-                            self.state.update_checkpoint_meta(
-                                &checkpoint.committed_seq_num,
-                                &checkpoint.state_digest.clone(),
-                            );
+                        // make a new proof of this checkpoint for subsequent view change messages
+                        self.state.update_checkpoint_meta(
+                            &checkpoint.committed_seq_num,
+                            &checkpoint.state_digest.clone(),
+                        );
+                        self.stabilize_checkpoint(&checkpoint).await;
+                    }
+                }
+            }
+        }
+    }
 
-                            // update the stable seq num
-                            self.state.last_stable_seq_num = checkpoint.committed_seq_num;
+    async fn accept_pre_prepare(&mut self, pre_prepare: PrePrepare) {
+        // We received a PrePrepare message from the network, and we see no violations
+        // So we will broadcast a corresponding prepare message and begin to count votes
+        self.state.log_pre_prepare(pre_prepare.clone());
+        self.persist(WalRecord::PrePrepare(pre_prepare.clone()));
+        instance_event!(
+            pre_prepare.view,
+            pre_prepare.seq_num,
+            pre_prepare.client_request_digest,
+            "Accepted pre-prepare from {}",
+            pre_prepare.id
+        );
 
-                            // we update the view to the largest sequence number in the commits
-                            // in the checkpoint
-                            let new_view = checkpoint.view;
+        let prepare = self
+            .prepare(
+                pre_prepare.view,
+                pre_prepare.seq_num,
+                pre_prepare.client_request_digest.clone(),
+            )
+            .await;
 
-                            if new_view != self.state.view {
-                                // if we update to a new view,
-                                // then we need to reset any view change processes
-                                // which we initiated
-                                self.state.in_view_change = false;
-                                self.view_changer.reset();
-                            }
+        let prepare_message = Message::PrepareMessage(prepare.clone());
+        let _ = self
+            .tx_node
+            .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
+                message: prepare_message.clone(),
+            }))
+            .await;
 
-                            self.state.view = new_view;
+        // we may already have a got a prepare message which we did not accept because
+        // we did not receive this pre-prepare message message yet
+        for e_prepare in self.state.message_bank.outstanding_prepares.iter() {
+            if e_prepare.corresponds_to(&pre_prepare) {
+                sampled!(
+                    info,
+                    "outstanding_prepare",
+                    "Found outstanding prepare from {}",
+                    e_prepare.id
+                );
+                self.follow_ups
+                    .push_back(ConsensusCommand::AcceptPrepare(e_prepare.clone()));
+            }
+        }
 
-                            for commit in self.state.get_next_consecutive_commits().iter() {
-                                let _ = self
-                                    .tx_consensus
-                                    .send(ConsensusCommand::ApplyCommit(commit.clone()))
-                                    .await;
-                            }
+        // the commits may have reached a quorum before the pre-prepare arrived
+        if let Some(commit) = self
+            .state
+            .commit_votes
+            .get(&(pre_prepare.view, pre_prepare.seq_num))
+            .filter(|vote_set| vote_set.len() > 2 * self.config.num_faulty)
+            .and_then(|vote_set| vote_set.values().next().cloned())
+        {
+            self.follow_ups
+                .push_back(ConsensusCommand::ApplyCommit(commit));
+        }
 
-                            // remove all of the messages pertaining to requests with seq_num < last_stable_seq_num
-                            self.state.garbage_collect();
-                        }
-                    } else {
-                        // first time we got a prepare message for this view and sequence number
-                        let mut new_vote_set = HashSet::new();
-                        new_vote_set.insert(checkpoint.id);
-                        self.state.checkpoint_votes.insert(
-                            (checkpoint.committed_seq_num, checkpoint.state_digest),
-                            new_vote_set,
-                        );
-                    }
-                }
+        // at this point, we need to trigger a timer, and if the timer expires
+        // and the request is still outstanding, then we need to trigger a view change
+        // as this is evidence that the system has stopped making progress.
+        // A retransmitted pre-prepare for a request we executed already starts no timer
+        let newly_added = pre_prepare.seq_num > self.state.last_seq_num_committed
+            && self
+                .view_changer
+                .add_to_wait_set(&pre_prepare.client_request);
+        if newly_added {
+            let view_changer = self.view_changer.clone();
+            logging::spawn(async move {
+                view_changer.wait_for(&pre_prepare.client_request).await;
+            });
+        }
+    }
+
+    async fn accept_prepare(&mut self, prepare: Prepare) {
+        // We saw a prepare message from the network that we deemed was valid
+        // to we increment the vote count, and if we have enough prepare votes
+        // then we move to the commit phases
+
+        // we are now accepting this prepare, so if it is our outstanding set, then
+        // we may remove it
+        self.state
+            .message_bank
+            .outstanding_prepares
+            .remove(&prepare);
+        self.persist(WalRecord::Prepare(prepare.clone()));
+
+        // Count votes for this prepare message and see if we have enough to move to the commit phases.
+        // A vote we already counted does not count again, and we move to the commit phase
+        // only once, when the vote completing the quorum arrives
+        let curr_vote_set = self
+            .state
+            .prepare_votes
+            .entry((prepare.view, prepare.seq_num))
+            .or_default();
+        let is_new_vote = curr_vote_set.insert(prepare.id, prepare.clone()).is_none();
+        if is_new_vote {
+            instance_event!(
+                prepare.view,
+                prepare.seq_num,
+                prepare.client_request_digest,
+                "Counted prepare from {} ({} votes)",
+                prepare.id,
+                curr_vote_set.len()
+            );
+        }
+        if is_new_vote && curr_vote_set.len() == 2 * self.config.num_faulty + 1 {
+            instance_event!(
+                prepare.view,
+                prepare.seq_num,
+                prepare.client_request_digest,
+                "Prepared"
+            );
+            Self::record_quorum(
+                &mut self.state.quorum_diagnostics,
+                &self.config,
+                &self.tx_suspected_nodes,
+                curr_vote_set.keys(),
+            );
+            self.observers.on_quorum(
+                QuorumKind::Prepare,
+                prepare.view,
+                prepare.seq_num,
+                &curr_vote_set.keys().copied().collect::<Vec<NodeId>>(),
+            );
+            // at this point, we have enough prepare votes to move into the commit phase.
+            self.follow_ups
+                .push_back(ConsensusCommand::EnterCommit(prepare.clone()));
+        }
+
+        // we may already have a got a commit message which we did not accept because
+        // we did not receive this prepare message message yet
+        for e_commit in self.state.message_bank.outstanding_commits.iter() {
+            if e_commit.corresponds_to(&prepare) {
+                sampled!(
+                    info,
+                    "outstanding_commit",
+                    "Found outstanding commit from {}",
+                    e_commit.id
+                );
+                self.follow_ups
+                    .push_back(ConsensusCommand::AcceptCommit(e_commit.clone()));
             }
         }
     }
 
+    async fn accept_commit(&mut self, commit: Commit) {
+        // We received a Commit Message for a request that we deemed valid
+        // so we increment the vote count
+
+        self.state.message_bank.outstanding_commits.remove(&commit);
+        self.persist(WalRecord::Commit(commit.clone()));
+
+        let curr_vote_set = self
+            .state
+            .commit_votes
+            .entry((commit.view, commit.seq_num))
+            .or_default();
+        let is_new_vote = curr_vote_set.insert(commit.id, commit.clone()).is_none();
+        if is_new_vote {
+            instance_event!(
+                commit.view,
+                commit.seq_num,
+                commit.client_request_digest,
+                "Counted commit from {} ({} votes)",
+                commit.id,
+                curr_vote_set.len()
+            );
+        }
+        if is_new_vote && curr_vote_set.len() == 2 * self.config.num_faulty + 1 {
+            instance_event!(
+                commit.view,
+                commit.seq_num,
+                commit.client_request_digest,
+                "Committed"
+            );
+            Self::record_quorum(
+                &mut self.state.quorum_diagnostics,
+                &self.config,
+                &self.tx_suspected_nodes,
+                curr_vote_set.keys(),
+            );
+            self.observers.on_quorum(
+                QuorumKind::Commit,
+                commit.view,
+                commit.seq_num,
+                &curr_vote_set.keys().copied().collect::<Vec<NodeId>>(),
+            );
+            // At this point, we have enough commit votes to commit the message.
+            // If the pre-prepare has not arrived yet, the request is applied when it does
+            self.follow_ups
+                .push_back(ConsensusCommand::ApplyCommit(commit));
+        }
+    }
+
     #[allow(clippy::comparison_chain)]
-    pub async fn apply_commit(&mut self, commit: &Commit, client_request: &ClientRequest) {
+    pub async fn apply_commit(&mut self, commit: &Commit, client_request: Arc<ClientRequest>) {
         // remove this request from the view changer so that we don't trigger a view change
-        self.view_changer.remove_from_wait_set(client_request);
+        self.view_changer.remove_from_wait_set(&client_request);
         self.view_changer
             .remove_from_sent_pre_prepares(&(commit.view, commit.seq_num));
 
         if commit.seq_num == self.state.last_seq_num_committed + 1 {
-            info!(
+            sampled!(
+                info,
+                "apply_commit",
                 "Applying client request with view {} seq-num {}",
-                commit.view, commit.seq_num
+                commit.view,
+                commit.seq_num
             );
 
-            let (ret, new_applies) = self.state.apply_commit(client_request, commit);
+            let (ret, new_applies) = self.state.apply_commit(client_request.clone(), commit);
+            let slot = ret.slot;
+            self.commit_rate.record();
+            self.view_changer.record_progress();
             for commit in new_applies.iter() {
-                let _ = self
-                    .tx_consensus
-                    .send(ConsensusCommand::ApplyCommit(commit.clone()))
-                    .await;
+                self.follow_ups
+                    .push_back(ConsensusCommand::ApplyCommit(commit.clone()));
             }
 
             // build the client response and send to client
+            if let Some(reason) = ret.reason {
+                sampled!(
+                    warn,
+                    "rejected_request",
+                    "Rejected request with seq-num {}: {}",
+                    commit.seq_num,
+                    reason
+                );
+            }
 
-            let res_val = if ret.is_some() { ret.unwrap() } else { None };
-            //let res_success = res_val.is_some() || client_request.value.is_some();
-            let res_success = true;
-
-            let client_response = if res_val.is_some() {
-                ClientResponse::new_with_signature(
-                    self.keypair_bytes.clone(),
-                    self.id,
-                    client_request.time_stamp,
-                    client_request.key.clone(),
-                    Some(*(res_val.unwrap())),
-                    res_success,
-                )
-            } else {
-                ClientResponse::new_with_signature(
-                    self.keypair_bytes.clone(),
-                    self.id,
-                    client_request.time_stamp,
-                    client_request.key.clone(),
-                    None,
-                    res_success,
-                )
-            };
-
-            let _ = self
-                .tx_node
-                .send(NodeCommand::SendMessageCommand(SendMessage {
-                    message: Message::ClientResponseMessage(client_response),
-                    destination: client_request.respond_addr,
-                }))
-                .await;
+            let client_response = ClientResponse::new_with_signature(
+                &self.keystore,
+                self.id,
+                client_request.time_stamp,
+                client_request.key.clone(),
+                ret.value,
+                ret.results,
+                ret.reason,
+            )
+            .with_previous(ret.previous)
+            .with_bulk(ret.bulk)
+            .with_view(self.state.view)
+            .with_seq_num(&self.keystore, commit.seq_num);
+            instance_event!(
+                commit.view,
+                commit.seq_num,
+                commit.client_request_digest,
+                "Applied request from {}",
+                client_request.respond_addr
+            );
+            self.observers
+                .on_commit(&slot, &client_request, &client_response);
+            // a bulk load is answered once, with the response to its last batch
+            if client_request.has_client() && client_request.bulk.is_none_or(|bulk| bulk.last) {
+                self.state.cache_reply(&client_request, &client_response);
+                self.send_client_response(&client_request, client_response)
+                    .await;
+            }
         } else if commit.seq_num > self.state.last_seq_num_committed + 1 {
             //the sequence number for this commit is too large, so we do not apply it yet
             if self
@@ -814,22 +2223,553 @@ impl Consensus {
                 .insert(commit.seq_num, commit.clone())
                 .is_none()
             {
-                info!("Buffering client request with seq_num {}", commit.seq_num);
+                sampled!(
+                    info,
+                    "buffer_commit",
+                    "Buffering client request with seq_num {}",
+                    commit.seq_num
+                );
+            }
+        }
+    }
+
+    /// Tells the clients of requests we accepted pre-prepares for in an earlier view, at
+    /// sequence numbers the new view fills with no-ops, that their requests were not ordered,
+    /// so that they send them again rather than wait for replies which never come
+    async fn notify_unordered(&self, outstanding_pre_prepares: &[PrePrepare]) {
+        let no_op_digest = ClientRequest::no_op().digest();
+        let re_proposed: HashSet<&[u8]> = outstanding_pre_prepares
+            .iter()
+            .map(|pre_prepare| pre_prepare.client_request_digest.as_slice())
+            .collect();
+        let no_op_seq_nums: HashSet<usize> = outstanding_pre_prepares
+            .iter()
+            .filter(|pre_prepare| pre_prepare.client_request_digest == no_op_digest)
+            .map(|pre_prepare| pre_prepare.seq_num)
+            .collect();
+        // the request we last accepted at each of those sequence numbers, as one superseded by
+        // the no-op of an earlier new view was reported then
+        let mut last_accepted: BTreeMap<usize, (usize, &[u8])> = BTreeMap::new();
+        for ((view, seq_num), pre_prepare) in
+            self.state.message_bank.accepted_pre_prepare_requests.iter()
+        {
+            if *view >= self.state.view || !no_op_seq_nums.contains(seq_num) {
+                continue;
+            }
+            let digest = pre_prepare.client_request_digest.as_slice();
+            if last_accepted
+                .get(seq_num)
+                .is_none_or(|(e_view, _)| e_view < view)
+            {
+                last_accepted.insert(*seq_num, (*view, digest));
+            }
+        }
+        let unordered: BTreeMap<&[u8], Arc<ClientRequest>> = last_accepted
+            .into_values()
+            .filter(|(_, digest)| *digest != no_op_digest && !re_proposed.contains(digest))
+            .filter_map(|(_, digest)| {
+                let client_request = self.state.message_bank.request_body(digest)?;
+                Some((digest, client_request))
+            })
+            .collect();
+
+        let resubmit_hint = ResubmitHint {
+            view: self.state.view,
+            primary: self.state.current_leader(),
+        };
+        for client_request in unordered.into_values() {
+            // a request the client had executed since is answered from the reply cache
+            let status = self
+                .state
+                .request_status(&client_request.identity(), client_request.time_stamp);
+            if status != RequestStatus::Unknown {
+                continue;
+            }
+            info!(
+                "Request with timestamp {} from {} was not ordered before view {}",
+                client_request.time_stamp, client_request.respond_addr, self.state.view
+            );
+            let client_response = ClientResponse::new_with_signature(
+                &self.keystore,
+                self.id,
+                client_request.time_stamp,
+                client_request.key.clone(),
+                None,
+                Vec::new(),
+                Some(FailureReason::Unordered { resubmit_hint }),
+            )
+            .with_view(self.state.view);
+            self.send_client_response(&client_request, client_response)
+                .await;
+        }
+    }
+
+    /// Blames the primary with the proof that it equivocated, so that every replica changes
+    /// the view at once rather than wait for requests to time out, and changes it
+    async fn expose_equivocation(&mut self, proof: EquivocationProof) {
+        warn!(
+            "Primary {} of view {} sent conflicting pre-prepares for seq-num {}",
+            proof.first.id,
+            proof.view(),
+            proof.first.seq_num
+        );
+        let request = (*proof.second.client_request).clone();
+        self.state.record_equivocation(proof.clone());
+        self.blame(Evidence::Equivocation(Box::new(proof))).await;
+        self.follow_ups
+            .push_back(ConsensusCommand::InitViewChange(request));
+    }
+
+    /// Records the evidence, which was checked, and vouches for it to every replica
+    /// the first time we learn that the accused is faulty
+    async fn blame(&mut self, evidence: Evidence) {
+        let accused = evidence.accused();
+        if !self.state.evidence.record(evidence.clone()) {
+            return;
+        }
+        warn!(
+            "Replica {} is faulty ({} in view {}), ordering its exclusion as primary",
+            accused,
+            evidence.kind(),
+            evidence.view()
+        );
+        self.state.evidence.corroborate(accused, self.id);
+        let blame = Blame::new_with_signature(&self.keystore, self.id, evidence);
+        let _ = self
+            .tx_node
+            .send(NodeCommand::BroadCastMessageCommand(BroadCastMessage {
+                message: Message::BlameMessage(blame),
+            }))
+            .await;
+        self.propose_pending().await;
+    }
+
+    /// Tells the client what became of its requests, from our reply cache, and sends
+    /// our reply again if one of them is the last request we executed for the client
+    async fn send_request_statuses(&mut self, get_status: GetRequestStatus) {
+        let respond_addr = get_status.respond_addr;
+        let client = get_status.identity();
+        let statuses: Vec<(usize, RequestStatus)> = get_status
+            .time_stamps
+            .into_iter()
+            .map(|time_stamp| (time_stamp, self.state.request_status(&client, time_stamp)))
+            .collect();
+        let executed = statuses
+            .iter()
+            .any(|(_, status)| *status == RequestStatus::Executed);
+        let mut messages = Vec::new();
+        if let Some(cached) = self.state.reply_cache.get(&client).filter(|_| executed) {
+            messages.push(Message::ClientResponseMessage(
+                cached
+                    .clone()
+                    .with_capabilities(Capabilities::new(self.config.features)),
+            ));
+        }
+        messages.push(Message::RequestStatusMessage(
+            RequestStatusReport::new_with_signature(
+                &self.keystore,
+                self.id,
+                respond_addr,
+                statuses,
+            ),
+        ));
+        for message in messages {
+            let _ = self
+                .tx_node
+                .send(NodeCommand::SendMessageCommand(SendMessage {
+                    destination: respond_addr,
+                    message,
+                }))
+                .await;
+        }
+    }
+
+    /// Answers the read-only request from our committed state, without ordering it.
+    /// A relay replica passes the request on to its peers, as the client only sent it
+    /// to the relay and needs 2f + 1 matching replies
+    async fn answer_read_only(&self, client_request: &ClientRequest) {
+        if client_request.relay_id == Some(self.id) {
+            for (peer_id, peer_addr) in self.config.peer_addrs.iter() {
+                if *peer_id == self.id {
+                    continue;
+                }
+                let _ = self
+                    .tx_node
+                    .send(NodeCommand::SendMessageCommand(SendMessage {
+                        destination: *peer_addr,
+                        message: Message::ClientRequestMessage(client_request.clone()),
+                    }))
+                    .await;
+            }
+        }
+        let client_response = ClientResponse::new_with_signature(
+            &self.keystore,
+            self.id,
+            client_request.time_stamp,
+            client_request.key.clone(),
+            self.state.store.get(&client_request.key).cloned(),
+            Vec::new(),
+            None,
+        )
+        .with_view(self.state.view);
+        self.send_client_response(client_request, client_response)
+            .await;
+    }
+
+    /// Sends the response to the client which submitted the request
+    async fn send_client_response(
+        &self,
+        client_request: &ClientRequest,
+        client_response: ClientResponse,
+    ) {
+        let client_response =
+            client_response.with_capabilities(Capabilities::new(self.config.features));
+        // if the client submitted this request through a relay replica,
+        // then the response goes back through that replica
+        let relay_addr = client_request
+            .relay_id
+            .and_then(|relay_id| self.config.peer_addrs.get(&relay_id));
+        let send_message = match relay_addr {
+            Some(relay_addr) => SendMessage {
+                destination: *relay_addr,
+                message: Message::RelayedClientResponseMessage(RelayedClientResponse {
+                    respond_addr: client_request.respond_addr,
+                    response: client_response,
+                }),
+            },
+            None => SendMessage {
+                destination: client_request.respond_addr,
+                message: Message::ClientResponseMessage(client_response),
+            },
+        };
+
+        let _ = self
+            .tx_node
+            .send(NodeCommand::SendMessageCommand(send_message))
+            .await;
+    }
+
+    /// Records the participants of a quorum we formed in the quorum diagnostics,
+    /// and publishes the suspected nodes if the current analysis window is complete
+    fn record_quorum<'a>(
+        quorum_diagnostics: &mut QuorumDiagnostics,
+        config: &Config,
+        tx_suspected_nodes: &watch::Sender<Vec<NodeId>>,
+        participants: impl Iterator<Item = &'a NodeId>,
+    ) {
+        let analyze =
+            quorum_diagnostics.record_quorum(participants, config.quorum_diagnostics_interval);
+        if !analyze {
+            return;
+        }
+
+        let suspected_nodes = quorum_diagnostics.analyze(config.num_nodes).clone();
+        if !suspected_nodes.is_empty() {
+            warn!(
+                "Nodes {:?} were absent from most recent quorums and may be crashed or partitioned",
+                suspected_nodes
+            );
+        }
+        tx_suspected_nodes.send_replace(suspected_nodes);
+    }
+
+    /// Writes the registry of the cluster, with the proof of the stable checkpoint, to the
+    /// export file of the config if the registry changed since we last exported it
+    fn export_registry(&mut self) {
+        let path = match self.config.registry_export.as_ref() {
+            Some(path) => path,
+            None => return,
+        };
+        let snapshot = match self.state.key_proof(&registry::registry_key()) {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        let version = snapshot
+            .value
+            .as_ref()
+            .and_then(ClusterRegistry::decode)
+            .map(|registry| registry.version);
+        if version.is_none() || version == self.exported_registry_version {
+            return;
+        }
+        match registry::write_snapshot(path, &snapshot) {
+            Ok(()) => {
+                info!(
+                    "Exported version {} of the cluster registry at seq-num {}",
+                    version.unwrap(),
+                    snapshot.committed_seq_num
+                );
+                self.exported_registry_version = version;
             }
+            Err(e) => warn!("Could not export the cluster registry: {}", e),
         }
     }
 
+    /// Moves the state backend to the last stable checkpoint, writing the keys written since
+    /// the checkpoint it holds. This happens before the write-ahead log is compacted into it
+    fn persist_state(&mut self) {
+        let (backend, snapshot) = match (self.backend.as_mut(), self.state.stable_snapshot()) {
+            (Some(backend), Some(snapshot)) => (backend, snapshot),
+            _ => return,
+        };
+        if backend.stable_seq_num() >= Some(snapshot.committed_seq_num) {
+            return;
+        }
+        // every entry is written if the diff is not retained, e.g. after a state transfer
+        let written = backend.stable_seq_num().and_then(|seq_num| {
+            let diff = self
+                .state
+                .checkpoint_diffs
+                .diff(seq_num, Some(snapshot.committed_seq_num));
+            diff.ok().map(|(keys, _)| keys)
+        });
+        if let Err(e) = backend.persist(
+            snapshot,
+            &self.state.last_checkpoint_proof,
+            written.as_ref(),
+        ) {
+            error!(
+                "Could not persist the state at seq-num {}: {}",
+                snapshot.committed_seq_num, e
+            );
+        }
+    }
+
+    /// Moves to the checkpoint, which became stable, once our store is at it and its proof
+    /// is our last checkpoint proof
+    async fn stabilize_checkpoint(&mut self, checkpoint: &CheckPoint) {
+        // we caught up with the checkpoint, so there is no need to fetch its state any more
+        if self.state_transfer.as_ref().is_some_and(|transfer| {
+            transfer.checkpoint.committed_seq_num <= checkpoint.committed_seq_num
+        }) {
+            self.state_transfer = None;
+        }
+        // update the stable seq num
+        self.state.last_stable_seq_num = checkpoint.committed_seq_num;
+        self.tx_stable_seq_num
+            .send_replace(checkpoint.committed_seq_num);
+        self.state
+            .checkpoint_diffs
+            .stabilize(checkpoint.committed_seq_num);
+        self.state.stabilize_snapshot(checkpoint.committed_seq_num);
+        self.persist_state();
+        self.export_registry();
+
+        // we update the view to the largest sequence number in the commits
+        // in the checkpoint
+        let new_view = checkpoint.view;
+
+        if new_view != self.state.view {
+            // if we update to a new view,
+            // then we need to reset any view change processes
+            // which we initiated
+            self.state.in_view_change = false;
+            self.view_changer.reset();
+            self.observers.on_view_change(new_view);
+        }
+
+        self.state.view = new_view;
+        self.replay_future_view_messages();
+
+        // the log before the checkpoint is no longer needed to recover
+        if self.wal.is_some() && self.pending_compaction.is_none() {
+            self.pending_compaction = Some(Instant::now());
+            self.compact_wal_when_quiet();
+        }
+
+        for commit in self.state.get_next_consecutive_commits().iter() {
+            self.follow_ups
+                .push_back(ConsensusCommand::ApplyCommit(commit.clone()));
+        }
+
+        // remove all of the messages pertaining to requests with seq_num < last_stable_seq_num
+        self.state.garbage_collect();
+
+        if self.state.adopt_registry_epoch() {
+            self.change_epoch().await;
+        }
+
+        // the water marks moved, so requests held back by the window can be proposed
+        self.propose_pending().await;
+    }
+
+    /// Moves to the membership of the epoch the state moved to, once the reconfiguration
+    /// leading to it became stable. The checkpoint is announced again in the new epoch,
+    /// which lets nodes which joined fetch the state at it, and the requests ordered after
+    /// the reconfiguration in the previous epoch are ordered again
+    async fn change_epoch(&mut self) {
+        self.config.apply_membership(&self.registry());
+        self.view_changer.config = self.config.clone();
+        self.membership.update(&self.config);
+        self.proposed_reconfiguration = None;
+        info!(
+            "Moving to epoch {} with members {:?}",
+            self.config.epoch,
+            self.membership.members()
+        );
+        if !self.config.peer_addrs.contains_key(&self.id) {
+            warn!(
+                "Node {} was removed from the cluster in epoch {}",
+                self.id, self.config.epoch
+            );
+            return;
+        }
+
+        self.reissue_pending_requests().await;
+        self.release_mempool().await;
+        self.init_checkpoint().await;
+
+        // checkpoints and view changes of the epoch which reached us before we moved to it
+        let epoch = self.config.epoch;
+        let mut messages = Vec::new();
+        self.later_epoch_messages.retain(|_, message| {
+            if message.epoch() == Some(epoch) {
+                messages.push(message.clone());
+            }
+            message.epoch() > Some(epoch)
+        });
+        let tx_consensus = self.tx_consensus.clone();
+        let pipeline = self.pipeline.clone();
+        logging::spawn(async move {
+            for message in messages {
+                pipeline.send(&tx_consensus, message).await;
+            }
+        });
+    }
+
+    /// The cluster registry in our store
+    fn registry(&self) -> ClusterRegistry {
+        self.state
+            .store
+            .get(&registry::registry_key())
+            .and_then(ClusterRegistry::decode)
+            .unwrap_or_default()
+    }
+
+    /// Asks the current source of the state we are fetching for the next chunk,
+    /// and to look for another source if it does not answer in time
+    async fn request_state_chunk(&mut self) {
+        let transfer = match self.state_transfer.as_ref() {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        let request = transfer.request(self.id);
+        let peer_addr = match transfer
+            .source()
+            .and_then(|source| self.config.peer_addrs.get(&source))
+        {
+            Some(peer_addr) => *peer_addr,
+            None => return,
+        };
+        let _ = self
+            .tx_node
+            .send(NodeCommand::SendMessageCommand(SendMessage {
+                destination: peer_addr,
+                message: Message::StateChunkRequestMessage(request.clone()),
+            }))
+            .await;
+
+        let tx_consensus = self.tx_consensus.clone();
+        let timeout = self.config.state_chunk_timeout;
+        logging::spawn(async move {
+            sleep(timeout).await;
+            let _ = tx_consensus
+                .send(ConsensusCommand::StateChunkTimeout {
+                    committed_seq_num: request.committed_seq_num,
+                    start: request.start,
+                })
+                .await;
+        });
+    }
+
+    /// Takes a chunk of the state we are fetching, and once we have all of it,
+    /// installs the state and moves to its checkpoint
+    async fn accept_state_chunk(&mut self, response: StateChunkResponse) {
+        let transfer = match self.state_transfer.as_mut() {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        let source = response.id;
+        if let Err(e) = transfer.accept(response) {
+            warn!("Rejecting chunk of the state from node {}: {}", source, e);
+            if transfer.source() == Some(source) {
+                transfer.next_source();
+                self.request_state_chunk().await;
+            }
+            return;
+        }
+        if !transfer.is_complete() {
+            self.request_state_chunk().await;
+            return;
+        }
+
+        let transfer = self.state_transfer.take().unwrap();
+        let checkpoint = transfer.checkpoint.clone();
+        let certificate = transfer.certificate.clone();
+        info!(
+            "Fetched the state at seq-num {}",
+            checkpoint.committed_seq_num
+        );
+        self.state.install_snapshot(&transfer.into_snapshot());
+        self.state.last_seq_num_committed = checkpoint.committed_seq_num;
+        self.state.install_checkpoint_proof(certificate);
+        self.stabilize_checkpoint(&checkpoint).await;
+    }
+
+    /// Sends a chunk of our snapshot at a stable checkpoint to a replica fetching it
+    async fn send_state_chunk(&mut self, request: StateChunkRequest) {
+        let snapshot = match self
+            .state
+            .checkpoint_snapshots
+            .get(&request.committed_seq_num)
+        {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        let (algorithm, peer_addr) = match (
+            crypto::algorithm_of(&request.state_digest),
+            self.config.peer_addrs.get(&request.id),
+        ) {
+            (Some(algorithm), Some(peer_addr)) => (algorithm, *peer_addr),
+            _ => return,
+        };
+        let (entries, proof) =
+            snapshot.chunk(request.start, self.config.state_chunk_size, algorithm);
+        let _ = self
+            .tx_node
+            .send(NodeCommand::SendMessageCommand(SendMessage {
+                destination: peer_addr,
+                message: Message::StateChunkResponseMessage(StateChunkResponse {
+                    id: self.id,
+                    committed_seq_num: request.committed_seq_num,
+                    state_digest: request.state_digest,
+                    entries,
+                    proof,
+                }),
+            }))
+            .await;
+    }
+
     pub async fn init_checkpoint(&mut self) {
         info!("Initiating checkpoint");
 
         let checkpoint = CheckPoint::new_with_signature(
-            self.keypair_bytes.clone(),
+            &self.keystore,
             self.id,
+            self.config.epoch,
             self.state.last_seq_num_committed,
             self.state.view,
             self.state.digest(),
-            self.state.store.clone(),
         );
+        // replicas which fall behind fetch the state we announce from our snapshot
+        self.state.take_snapshot();
+
+        if self.config.is_single_node() {
+            // our own checkpoint is a quorum
+            self.follow_ups
+                .push_back(ConsensusCommand::AcceptCheckpoint(checkpoint));
+            return;
+        }
 
         let _ = self
             .tx_node
@@ -839,22 +2779,59 @@ impl Consensus {
             .await;
     }
 
+    /// Commits the request of the pre-prepare without a round of messages,
+    /// which is sound as this node is the only one in the system.
+    /// The request still goes through the log, and the commit carries our signature
+    async fn commit_single_node(&mut self, pre_prepare: PrePrepare) {
+        self.state.log_pre_prepare(pre_prepare.clone());
+
+        let prepare = self
+            .prepare(
+                pre_prepare.view,
+                pre_prepare.seq_num,
+                pre_prepare.client_request_digest.clone(),
+            )
+            .await;
+        self.persist(WalRecord::PrePrepare(pre_prepare.clone()));
+        self.persist(WalRecord::Prepare(prepare.clone()));
+        self.state.prepare_votes.insert(
+            (prepare.view, prepare.seq_num),
+            HashMap::from([(self.id, prepare)]),
+        );
+
+        let commit = self
+            .commit(
+                pre_prepare.view,
+                pre_prepare.seq_num,
+                pre_prepare.client_request_digest,
+            )
+            .await;
+        // open the vote set so our own commit completes the quorum
+        self.state
+            .commit_votes
+            .insert((commit.view, commit.seq_num), HashMap::new());
+        self.follow_ups
+            .push_back(ConsensusCommand::AcceptCommit(commit));
+    }
+
     async fn equivocate_pre_prepare(&self, request: ClientRequest) {
         // mutate the given request
         let mut d_request = request.clone();
-        d_request.value = Some(42);
+        d_request.operation = Operation::Set(Value::from("42"));
 
         let pre_prepare = PrePrepare::new_with_signature(
-            self.keypair_bytes.clone(),
+            &self.keystore,
             self.id,
+            self.config.epoch,
             self.state.view,
             self.state.seq_num,
             &request,
         );
 
         let d_pre_prepare = PrePrepare::new_with_signature(
-            self.keypair_bytes.clone(),
+            &self.keystore,
             self.id,
+            self.config.epoch,
             self.state.view,
             self.state.seq_num,
             &d_request,
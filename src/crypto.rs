@@ -0,0 +1,255 @@
+use std::sync::RwLock;
+
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+/// Hash functions digests and signatures can be produced with.
+/// Digests and signatures are prefixed with the id of the algorithm which produced them,
+/// so a cluster can move to a new algorithm while still verifying the old one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DigestAlgorithm {
+    Sha512,
+    Sha256,
+    /// SHA-512/256, which runs on the 64-bit SHA-512 core with digests half as long.
+    /// It is the faster of the 32-byte digests on 64-bit CPUs without SHA extensions,
+    /// while SHA-256 is faster where they are available (see the `digest` benchmark)
+    Sha512_256,
+}
+
+impl DigestAlgorithm {
+    pub const ALL: [DigestAlgorithm; 3] = [
+        DigestAlgorithm::Sha512,
+        DigestAlgorithm::Sha256,
+        DigestAlgorithm::Sha512_256,
+    ];
+
+    pub fn id(&self) -> u8 {
+        match self {
+            DigestAlgorithm::Sha512 => 1,
+            DigestAlgorithm::Sha256 => 2,
+            DigestAlgorithm::Sha512_256 => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(DigestAlgorithm::Sha512),
+            2 => Some(DigestAlgorithm::Sha256),
+            3 => Some(DigestAlgorithm::Sha512_256),
+            _ => None,
+        }
+    }
+
+    /// Hash of the data, without the algorithm id
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Sha512 => <sha2::Sha512 as sha2::Digest>::digest(data)
+                .as_slice()
+                .to_vec(),
+            DigestAlgorithm::Sha256 => <sha2::Sha256 as sha2::Digest>::digest(data)
+                .as_slice()
+                .to_vec(),
+            DigestAlgorithm::Sha512_256 => <sha2::Sha512_256 as sha2::Digest>::digest(data)
+                .as_slice()
+                .to_vec(),
+        }
+    }
+
+    /// Hash of the data, prefixed with the algorithm id
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut digest = vec![self.id()];
+        digest.extend(self.hash(data));
+        digest
+    }
+}
+
+impl std::str::FromStr for DigestAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha512" => Ok(DigestAlgorithm::Sha512),
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "sha512_256" => Ok(DigestAlgorithm::Sha512_256),
+            _ => Err(format!("unknown digest algorithm {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DigestAlgorithm::Sha512 => write!(f, "sha512"),
+            DigestAlgorithm::Sha256 => write!(f, "sha256"),
+            DigestAlgorithm::Sha512_256 => write!(f, "sha512_256"),
+        }
+    }
+}
+
+/// Algorithm which produced the tagged digest or signature
+pub fn algorithm_of(tagged: &[u8]) -> Option<DigestAlgorithm> {
+    tagged.first().and_then(|id| DigestAlgorithm::from_id(*id))
+}
+
+/// Which algorithms a node produces and accepts digests and signatures with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestPolicy {
+    /// Algorithm used outside of a migration
+    pub algorithm: DigestAlgorithm,
+    /// Migration to a new algorithm, during which both algorithms are accepted
+    pub migration: Option<DigestMigration>,
+}
+
+/// Switch to a new algorithm, ordered by sequence number. Digests of requests and states
+/// at sequence numbers from `from_seq_num` on are produced with the new algorithm,
+/// so every replica produces the same digest for a slot whatever its progress.
+/// The migration is completed by making the new algorithm the algorithm of the policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestMigration {
+    pub algorithm: DigestAlgorithm,
+    pub from_seq_num: usize,
+}
+
+impl Default for DigestPolicy {
+    fn default() -> Self {
+        DEFAULT_POLICY
+    }
+}
+
+const DEFAULT_POLICY: DigestPolicy = DigestPolicy {
+    algorithm: DigestAlgorithm::Sha512,
+    migration: None,
+};
+
+impl DigestPolicy {
+    /// Algorithm digests and signatures for the given sequence number are produced with
+    pub fn algorithm_at(&self, seq_num: usize) -> DigestAlgorithm {
+        match &self.migration {
+            Some(migration) if seq_num >= migration.from_seq_num => migration.algorithm,
+            _ => self.algorithm,
+        }
+    }
+
+    pub fn accepts(&self, algorithm: DigestAlgorithm) -> bool {
+        algorithm == self.algorithm
+            || matches!(&self.migration, Some(migration) if migration.algorithm == algorithm)
+    }
+
+    /// Algorithms which are accepted, the algorithm of the policy first
+    pub fn accepted_algorithms(&self) -> Vec<DigestAlgorithm> {
+        let mut accepted = vec![self.algorithm];
+        if let Some(migration) = &self.migration {
+            if migration.algorithm != self.algorithm {
+                accepted.push(migration.algorithm);
+            }
+        }
+        accepted
+    }
+
+    /// Checks the tagged signature over the input, if it was made with an accepted algorithm
+    pub fn verify(&self, pub_key: &PublicKey, input: &SigningInput, signature: &[u8]) -> bool {
+        let algorithm = match algorithm_of(signature) {
+            Some(algorithm) if self.accepts(algorithm) => algorithm,
+            _ => return false,
+        };
+        let signature = match Signature::from_bytes(&signature[1..]) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        match algorithm {
+            DigestAlgorithm::Sha512 => pub_key
+                .verify_prehashed(input.prehashed(), None, &signature)
+                .is_ok(),
+            _ => pub_key
+                .verify(&algorithm.hash(&input.bytes), &signature)
+                .is_ok(),
+        }
+    }
+}
+
+static POLICY: RwLock<DigestPolicy> = RwLock::new(DEFAULT_POLICY);
+
+/// Sets the digest policy of this process (shared by all nodes in the process)
+pub fn set_policy(policy: DigestPolicy) {
+    *POLICY.write().unwrap() = policy;
+}
+
+pub fn policy() -> DigestPolicy {
+    POLICY.read().unwrap().clone()
+}
+
+/// Platform independent encoding of a number which is hashed or signed
+pub fn encode_usize(n: usize) -> [u8; 8] {
+    (n as u64).to_le_bytes()
+}
+
+/// Data a message signature is computed over
+#[derive(Debug, Clone, Default)]
+pub struct SigningInput {
+    bytes: Vec<u8>,
+}
+
+impl SigningInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.bytes.extend_from_slice(data.as_ref());
+    }
+
+    /// Appends the number as 8 little-endian bytes, so that the input is the same
+    /// whatever the width and byte order of usize on the platform
+    pub fn update_usize(&mut self, n: usize) {
+        self.update(encode_usize(n));
+    }
+
+    /// Binds the signature to the membership epoch. Nothing is added for the genesis
+    /// epoch, so that signatures from before reconfiguration existed stay valid
+    pub fn update_epoch(&mut self, epoch: usize) {
+        if epoch > 0 {
+            self.update(b"epoch");
+            self.update_usize(epoch);
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn prehashed(&self) -> ed25519_dalek::Sha512 {
+        let mut pre_hashed = <ed25519_dalek::Sha512 as ed25519_dalek::Digest>::new();
+        ed25519_dalek::Digest::update(&mut pre_hashed, &self.bytes);
+        pre_hashed
+    }
+}
+
+/// Signs the input with the algorithm, returning the tagged signature
+pub fn sign(keypair: &Keypair, input: &SigningInput, algorithm: DigestAlgorithm) -> Vec<u8> {
+    tag_signature(algorithm, &untagged_signature(keypair, input, algorithm))
+}
+
+/// Signature of the input with the algorithm: Ed25519ph of the input for sha512, for which
+/// the input is hashed as it is added, and Ed25519 of the digest of the input otherwise
+pub fn untagged_signature(
+    keypair: &Keypair,
+    input: &SigningInput,
+    algorithm: DigestAlgorithm,
+) -> Signature {
+    match algorithm {
+        DigestAlgorithm::Sha512 => keypair.sign_prehashed(input.prehashed(), None).unwrap(),
+        _ => keypair.sign(&algorithm.hash(&input.bytes)),
+    }
+}
+
+/// Signature tagged with the algorithm it was produced with, as carried by messages
+pub fn tag_signature(algorithm: DigestAlgorithm, signature: &Signature) -> Vec<u8> {
+    let mut tagged = vec![algorithm.id()];
+    tagged.extend_from_slice(&signature.to_bytes());
+    tagged
+}
+
+/// Checks the tagged signature against the policy of this process
+pub fn verify(pub_key: &PublicKey, input: &SigningInput, signature: &[u8]) -> bool {
+    policy().verify(pub_key, input, signature)
+}
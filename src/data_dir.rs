@@ -0,0 +1,277 @@
+use crate::config::Config;
+use crate::keys::{decode_keypair, encode_hex, GeneratedKeyProvider, KeyError, KeyProvider};
+use crate::NodeId;
+
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// Version of the layout of data directories this build writes. A node opening a directory
+/// of an earlier version migrates it on startup, and refuses one of a later version
+pub const STORAGE_VERSION: u32 = 1;
+
+/// Data directory of a node, which holds everything the node persists:
+///
+/// ```text
+/// VERSION                    storage format version, written once a migration completed
+/// wal/log.jsonl              write-ahead log
+/// snapshots/state/           sled database with the store at the last stable checkpoint
+/// snapshots/export.snapshot  snapshot exported on demand
+/// keys/node.key              keypair of the node, generated on first start
+/// meta/node.json             id of the node the directory belongs to
+/// meta/registry.json         registry of the cluster with its proof
+/// ```
+///
+/// Files are replaced by renaming a complete copy over them (see `write_atomically`), and
+/// every migration step can be run again, so a crash at any point leaves the directory in
+/// a state the next start picks up from
+pub struct DataDir {
+    root: PathBuf,
+}
+
+/// Files a node was pointed at one by one before data directories, which the first start
+/// with a data directory moves into it
+#[derive(Debug, Clone, Default)]
+pub struct LegacyPaths {
+    pub wal_path: Option<PathBuf>,
+    pub state_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataDirError {
+    /// The directory could not be read or written
+    Io(String),
+    /// The directory was written by a later build, which this one cannot read
+    NewerVersion { found: u32 },
+    /// The directory belongs to another node
+    OtherNode(NodeId),
+    /// A file of the layout is not what it should be
+    Malformed(String),
+}
+
+impl std::fmt::Display for DataDirError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataDirError::Io(reason) => write!(f, "data directory unavailable ({})", reason),
+            DataDirError::NewerVersion { found } => write!(
+                f,
+                "data directory has storage version {}, this build supports up to {}",
+                found, STORAGE_VERSION
+            ),
+            DataDirError::OtherNode(id) => {
+                write!(f, "data directory belongs to node {}", id)
+            }
+            DataDirError::Malformed(reason) => write!(f, "malformed data directory ({})", reason),
+        }
+    }
+}
+
+impl std::error::Error for DataDirError {}
+
+/// Identity of the node a data directory belongs to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NodeMeta {
+    id: NodeId,
+}
+
+/// Migration of a data directory from the version it is indexed by to the next one
+type Migration = fn(&DataDir, NodeId, &LegacyPaths) -> io::Result<()>;
+
+const MIGRATIONS: [Migration; STORAGE_VERSION as usize] = [DataDir::lay_out];
+
+impl DataDir {
+    /// Opens the data directory of the node, creating it on first start and migrating
+    /// the layout of an earlier version. The legacy paths are only moved in while the
+    /// directory is laid out
+    pub fn open(
+        root: impl Into<PathBuf>,
+        id: NodeId,
+        legacy: &LegacyPaths,
+    ) -> Result<Self, DataDirError> {
+        let data_dir = Self { root: root.into() };
+        fs::create_dir_all(&data_dir.root).map_err(|e| data_dir.io_error(e))?;
+        let version = data_dir.version()?;
+        if version > STORAGE_VERSION {
+            return Err(DataDirError::NewerVersion { found: version });
+        }
+        if let Some(meta) = data_dir.node_meta()? {
+            if meta.id != id {
+                return Err(DataDirError::OtherNode(meta.id));
+            }
+        }
+        if version == STORAGE_VERSION && (legacy.wal_path.is_some() || legacy.state_path.is_some())
+        {
+            warn!(
+                "Ignoring the write-ahead log and state paths given, the data directory {} holds them",
+                data_dir.root.display()
+            );
+        }
+        for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            info!(
+                "Migrating the data directory {} from storage version {} to {}",
+                data_dir.root.display(),
+                from_version,
+                from_version + 1
+            );
+            migration(&data_dir, id, legacy).map_err(|e| data_dir.io_error(e))?;
+            let version_line = format!("{}\n", from_version + 1);
+            write_atomically(&data_dir.version_path(), version_line.as_bytes())
+                .map_err(|e| data_dir.io_error(e))?;
+        }
+        Ok(data_dir)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn wal_path(&self) -> PathBuf {
+        self.root.join("wal").join("log.jsonl")
+    }
+
+    pub fn state_path(&self) -> PathBuf {
+        self.root.join("snapshots").join("state")
+    }
+
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.root.join("snapshots").join("export.snapshot")
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        self.root.join("keys").join("node.key")
+    }
+
+    pub fn registry_path(&self) -> PathBuf {
+        self.root.join("meta").join("registry.json")
+    }
+
+    fn version_path(&self) -> PathBuf {
+        self.root.join("VERSION")
+    }
+
+    fn node_meta_path(&self) -> PathBuf {
+        self.root.join("meta").join("node.json")
+    }
+
+    /// Points the write-ahead log and state backend of the config into the directory,
+    /// and the snapshot and registry exports where the config does not place them elsewhere
+    pub fn configure(&self, config: &mut Config) {
+        config.wal_path = Some(self.wal_path());
+        config.state_path = Some(self.state_path());
+        config
+            .snapshot_export
+            .get_or_insert_with(|| self.snapshot_path());
+        config
+            .registry_export
+            .get_or_insert_with(|| self.registry_path());
+    }
+
+    /// Storage version of the directory, 0 if it was never laid out
+    pub fn version(&self) -> Result<u32, DataDirError> {
+        match fs::read_to_string(self.version_path()) {
+            Ok(contents) => contents.trim().parse().map_err(|_| {
+                DataDirError::Malformed(format!("storage version {:?}", contents.trim()))
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(self.io_error(e)),
+        }
+    }
+
+    fn node_meta(&self) -> Result<Option<NodeMeta>, DataDirError> {
+        match fs::read(self.node_meta_path()) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map(Some)
+                .map_err(|e| DataDirError::Malformed(format!("meta/node.json: {}", e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.io_error(e)),
+        }
+    }
+
+    fn io_error(&self, e: io::Error) -> DataDirError {
+        DataDirError::Io(format!("{}: {}", self.root.display(), e))
+    }
+
+    /// Version 0 to 1: creates the directories of the layout and records the node, then
+    /// moves in the files of the legacy paths. A legacy file is first renamed to a staging
+    /// name in the root, as it may sit where a directory of the layout goes
+    fn lay_out(&self, id: NodeId, legacy: &LegacyPaths) -> io::Result<()> {
+        let moves = [
+            (&legacy.wal_path, "wal.migrating", self.wal_path()),
+            (&legacy.state_path, "state.migrating", self.state_path()),
+        ];
+        for (legacy_path, staging_name, _) in moves.iter() {
+            if let Some(legacy_path) = legacy_path {
+                if legacy_path.exists() {
+                    fs::rename(legacy_path, self.root.join(staging_name))?;
+                }
+            }
+        }
+        for dir in ["wal", "snapshots", "meta"] {
+            fs::create_dir_all(self.root.join(dir))?;
+        }
+        // only the owner gets to the key of the node, where the platform has permission bits
+        let mut keys_dir = DirBuilder::new();
+        keys_dir.recursive(true);
+        #[cfg(unix)]
+        keys_dir.mode(0o700);
+        keys_dir.create(self.root.join("keys"))?;
+        if !self.node_meta_path().exists() {
+            let meta = serde_json::to_vec_pretty(&NodeMeta { id })?;
+            write_atomically(&self.node_meta_path(), &meta)?;
+        }
+        for (_, staging_name, path) in moves.iter() {
+            let staging_path = self.root.join(staging_name);
+            if staging_path.exists() {
+                info!("Moving {} to {}", staging_path.display(), path.display());
+                fs::rename(&staging_path, path)?;
+                sync_parent(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The key of the node is kept in the directory, generated when the node first starts
+/// without another source for its key
+impl KeyProvider for DataDir {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let path = self.key_path();
+        let unavailable =
+            |e: io::Error| KeyError::Unavailable(format!("{}: {}", path.display(), e));
+        match fs::read_to_string(&path) {
+            Ok(encoded) => decode_keypair(&encoded),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let keypair_bytes = GeneratedKeyProvider.keypair_bytes()?;
+                write_atomically(&path, encode_hex(&keypair_bytes).as_bytes())
+                    .map_err(unavailable)?;
+                Ok(keypair_bytes)
+            }
+            Err(e) => Err(unavailable(e)),
+        }
+    }
+}
+
+/// Replaces the file with the contents at once: they are written and synced to a temporary
+/// file, which is renamed over the file, and the rename is synced. A crash leaves either the
+/// previous contents or the new ones
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(contents)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_parent(path)
+}
+
+/// Syncs the directory holding the path, which makes a rename to the path durable
+pub fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
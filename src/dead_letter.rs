@@ -0,0 +1,55 @@
+use crate::messages::ClientResponse;
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+/// Response which could not be delivered to its client after every retry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub respond_addr: SocketAddr,
+    pub response: ClientResponse,
+    /// Times delivery was attempted
+    pub attempts: usize,
+    /// Why the last attempt failed
+    pub reason: String,
+}
+
+/// Responses this replica could not deliver, reported in node statuses.
+/// Like the reply cache, only the response to the last request of each client is kept,
+/// so a dead letter is dropped once a later request of its client is answered
+#[derive(Clone, Default)]
+pub struct DeadLetters {
+    letters: Arc<Mutex<BTreeMap<SocketAddr, DeadLetter>>>,
+}
+
+impl DeadLetters {
+    /// Records the undeliverable response unless we answered a later request of the client
+    pub fn record(&self, dead_letter: DeadLetter) {
+        let mut letters = self.letters.lock().unwrap();
+        if letters
+            .get(&dead_letter.respond_addr)
+            .is_some_and(|letter| letter.response.time_stamp > dead_letter.response.time_stamp)
+        {
+            return;
+        }
+        letters.insert(dead_letter.respond_addr, dead_letter);
+    }
+
+    /// Drops the dead letter of the client if the response is to a later request
+    pub fn supersede(&self, respond_addr: SocketAddr, response: &ClientResponse) {
+        let mut letters = self.letters.lock().unwrap();
+        if letters
+            .get(&respond_addr)
+            .is_some_and(|letter| letter.response.time_stamp < response.time_stamp)
+        {
+            letters.remove(&respond_addr);
+        }
+    }
+
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().values().cloned().collect()
+    }
+}
@@ -0,0 +1,47 @@
+use crate::NodeId;
+
+use std::collections::HashMap;
+
+/// Records which nodes participated in the quorum certificates this node formed,
+/// so that nodes which are consistently absent from quorums (and so are likely crashed
+/// or partitioned) can be noticed before they cause a view change
+#[derive(Default)]
+pub struct QuorumDiagnostics {
+    /// Number of quorums observed since the last analysis
+    pub quorums_observed: usize,
+    /// Maps a node id to the number of observed quorums it participated in
+    pub participation: HashMap<NodeId, usize>,
+    /// Nodes which were absent from most of the quorums in the last analysis window
+    pub suspected_nodes: Vec<NodeId>,
+}
+
+impl QuorumDiagnostics {
+    /// Records a quorum formed from the given participants.
+    /// Returns true if enough quorums were observed to analyze the current window
+    pub fn record_quorum<'a>(
+        &mut self,
+        participants: impl Iterator<Item = &'a NodeId>,
+        interval: usize,
+    ) -> bool {
+        self.quorums_observed += 1;
+        for node_id in participants {
+            *self.participation.entry(*node_id).or_insert(0) += 1;
+        }
+        interval > 0 && self.quorums_observed >= interval
+    }
+
+    /// Finds the nodes which participated in at most half of the observed quorums
+    /// and starts a new analysis window
+    pub fn analyze(&mut self, num_nodes: usize) -> &Vec<NodeId> {
+        self.suspected_nodes = (0..num_nodes)
+            .filter(|node_id| {
+                let participated = self.participation.get(node_id).copied().unwrap_or(0);
+                2 * participated <= self.quorums_observed
+            })
+            .collect();
+
+        self.quorums_observed = 0;
+        self.participation.clear();
+        &self.suspected_nodes
+    }
+}
@@ -0,0 +1,144 @@
+use crate::Key;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+/// Keys written between consecutive stable checkpoints, retained for the last few stable
+/// checkpoints, so that a reader which synced the store up to one of them only needs to
+/// fetch the keys written since rather than replay the log. Shared by the consensus engine,
+/// which records the writes it applies, and the node, which answers diff requests
+#[derive(Clone, Default)]
+pub struct CheckpointDiffs {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Keys written by each request applied after the last stable checkpoint
+    pending: BTreeMap<usize, BTreeSet<Key>>,
+    /// Keys written after the previous stable checkpoint, by stable checkpoint
+    diffs: BTreeMap<usize, BTreeSet<Key>>,
+    /// Oldest stable checkpoint diffs can be computed from. The writes before it are not
+    /// known, e.g. as they were covered by an installed snapshot
+    oldest_seq_num: usize,
+    /// Number of stable checkpoints whose diff is retained (0 to retain them all)
+    retained: usize,
+}
+
+/// Why the keys written between two checkpoints could not be computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffError {
+    /// The diffs of checkpoints this old are no longer retained
+    NotRetained { oldest_seq_num: usize },
+    /// There is no stable checkpoint at the sequence number
+    NotStable(usize),
+}
+
+impl std::fmt::Display for DiffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffError::NotRetained { oldest_seq_num } => write!(
+                f,
+                "diffs are only retained from the checkpoint at seq-num {}",
+                oldest_seq_num
+            ),
+            DiffError::NotStable(seq_num) => {
+                write!(f, "there is no stable checkpoint at seq-num {}", seq_num)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiffError {}
+
+impl CheckpointDiffs {
+    /// Diffs which are retained for the given number of stable checkpoints (0 for all)
+    pub fn new(retained: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                retained,
+                ..Inner::default()
+            })),
+        }
+    }
+
+    /// Records that the request applied at the sequence number wrote the keys
+    pub fn record<'a>(&self, keys: impl IntoIterator<Item = &'a Key>, seq_num: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let written = inner.pending.entry(seq_num).or_default();
+        written.extend(keys.into_iter().cloned());
+    }
+
+    /// The checkpoint at the sequence number became stable, so the writes up to it
+    /// make up its diff
+    pub fn stabilize(&self, seq_num: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        if seq_num <= inner.last_stable_seq_num() {
+            return;
+        }
+        let later = inner.pending.split_off(&(seq_num + 1));
+        let diff = std::mem::replace(&mut inner.pending, later)
+            .into_values()
+            .flatten()
+            .collect();
+        inner.diffs.insert(seq_num, diff);
+        while inner.retained > 0 && inner.diffs.len() > inner.retained {
+            let (oldest_seq_num, _) = inner.diffs.pop_first().unwrap();
+            inner.oldest_seq_num = oldest_seq_num;
+        }
+    }
+
+    /// A snapshot of the store at the sequence number was installed, so the writes
+    /// before it are not known
+    pub fn install_snapshot(&self, seq_num: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.diffs.clear();
+        inner.pending = inner.pending.split_off(&(seq_num + 1));
+        inner.oldest_seq_num = seq_num;
+    }
+
+    /// Keys written after the stable checkpoint at `from_seq_num`, up to and including the
+    /// stable checkpoint at `to_seq_num` (the last one if None). Returns the keys with the
+    /// sequence number of the checkpoint they go up to
+    pub fn diff(
+        &self,
+        from_seq_num: usize,
+        to_seq_num: Option<usize>,
+    ) -> Result<(BTreeSet<Key>, usize), DiffError> {
+        let inner = self.inner.lock().unwrap();
+        let to_seq_num = to_seq_num.unwrap_or_else(|| inner.last_stable_seq_num());
+        if from_seq_num < inner.oldest_seq_num {
+            return Err(DiffError::NotRetained {
+                oldest_seq_num: inner.oldest_seq_num,
+            });
+        }
+        for seq_num in [from_seq_num, to_seq_num] {
+            if seq_num != inner.oldest_seq_num && !inner.diffs.contains_key(&seq_num) {
+                return Err(DiffError::NotStable(seq_num));
+            }
+        }
+        if to_seq_num <= from_seq_num {
+            return Ok((BTreeSet::new(), to_seq_num));
+        }
+        let keys = inner
+            .diffs
+            .range(from_seq_num + 1..=to_seq_num)
+            .flat_map(|(_, keys)| keys.iter().cloned())
+            .collect();
+        Ok((keys, to_seq_num))
+    }
+
+    /// Oldest and last stable checkpoints diffs can be computed between
+    pub fn range(&self) -> (usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        (inner.oldest_seq_num, inner.last_stable_seq_num())
+    }
+}
+
+impl Inner {
+    fn last_stable_seq_num(&self) -> usize {
+        self.diffs
+            .last_key_value()
+            .map_or(self.oldest_seq_num, |(seq_num, _)| *seq_num)
+    }
+}
@@ -0,0 +1,300 @@
+use crate::crypto::SigningInput;
+use crate::messages::{EquivocationProof, NewView};
+use crate::view_changer::NewViewRequests;
+use crate::{NodeId, Value};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use ed25519_dalek::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Misbehavior of a replica which any replica can check against the public key of the
+/// replica alone, as it rests on messages the replica signed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Evidence {
+    /// Conflicting pre-prepares the primary signed for the same view and sequence number
+    Equivocation(Box<EquivocationProof>),
+    /// New view the primary signed, which the view changes it carries do not justify
+    InvalidNewView(NewView),
+}
+
+impl Evidence {
+    /// Replica the evidence is against
+    pub fn accused(&self) -> NodeId {
+        match self {
+            Evidence::Equivocation(proof) => proof.first.id,
+            Evidence::InvalidNewView(new_view) => new_view.id,
+        }
+    }
+
+    /// View the replica misbehaved in
+    pub fn view(&self) -> usize {
+        match self {
+            Evidence::Equivocation(proof) => proof.view(),
+            Evidence::InvalidNewView(new_view) => new_view.view,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Evidence::Equivocation(_) => "equivocation",
+            Evidence::InvalidNewView(_) => "invalid_new_view",
+        }
+    }
+
+    /// Does the evidence prove the accused faulty, given the public keys of the replicas
+    pub fn is_valid(&self, pub_keys: &HashMap<NodeId, PublicKey>, num_faulty: usize) -> bool {
+        let pub_key = match pub_keys.get(&self.accused()) {
+            Some(pub_key) => pub_key,
+            None => return false,
+        };
+        match self {
+            Evidence::Equivocation(proof) => proof.is_valid(self.accused(), pub_key),
+            Evidence::InvalidNewView(new_view) => {
+                new_view.is_properly_signed_by(pub_key)
+                    && check_new_view(new_view, pub_keys, num_faulty)
+                        .is_err_and(|fault| fault.is_provable())
+            }
+        }
+    }
+
+    /// Signatures of the accused the evidence rests on, which identify it
+    pub(crate) fn update_signing_input(&self, signing_input: &mut SigningInput) {
+        signing_input.update(self.kind());
+        signing_input.update_usize(self.accused());
+        signing_input.update_usize(self.view());
+        match self {
+            Evidence::Equivocation(proof) => {
+                signing_input.update(&proof.first.signature);
+                signing_input.update(&proof.second.signature);
+            }
+            Evidence::InvalidNewView(new_view) => signing_input.update(&new_view.signature),
+        }
+    }
+}
+
+/// Evidence ordered through consensus, with the first view its accused is passed over as
+/// primary for. The primary which orders it picks the view after its own, and the view is
+/// part of the request, so every replica which applies the request passes the accused over
+/// from the same view, however late it learned of the evidence itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub evidence: Evidence,
+    pub from_view: usize,
+}
+
+impl Exclusion {
+    /// The value written to the evidence key of the accused
+    pub fn encode(&self) -> Value {
+        Value::from(serde_json::to_vec(self).unwrap())
+    }
+
+    pub fn decode(value: &Value) -> Option<Self> {
+        serde_json::from_slice(value.as_bytes()).ok()
+    }
+}
+
+/// Why the view changes a new view carries do not justify it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NewViewFault {
+    /// The view change is not signed by the replica it claims to come from
+    UnsignedViewChange(NodeId),
+    /// The view change is for another view
+    ViewChangeForOtherView(NodeId),
+    /// Fewer than 2f + 1 replicas asked for the view
+    TooFewViewChanges(usize),
+    /// A re-proposed pre-prepare is not one of the primary for the view
+    ForeignReProposal,
+    /// The re-proposed requests are not those the view changes determine
+    WrongReProposals,
+}
+
+impl NewViewFault {
+    /// Is the fault that of the primary which signed the new view. The new view only signs
+    /// the signatures of the view changes and the digests of the re-proposed requests, so a
+    /// replica passing it on could have tampered with the rest
+    pub fn is_provable(&self) -> bool {
+        !matches!(
+            self,
+            NewViewFault::UnsignedViewChange(_) | NewViewFault::ForeignReProposal
+        )
+    }
+}
+
+impl std::fmt::Display for NewViewFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NewViewFault::UnsignedViewChange(id) => {
+                write!(f, "the view change from {} is not properly signed", id)
+            }
+            NewViewFault::ViewChangeForOtherView(id) => {
+                write!(f, "the view change from {} is for another view", id)
+            }
+            NewViewFault::TooFewViewChanges(num_signers) => {
+                write!(f, "it is based on only {} view changes", num_signers)
+            }
+            NewViewFault::ForeignReProposal => {
+                write!(f, "it re-proposes pre-prepares of another view or primary")
+            }
+            NewViewFault::WrongReProposals => {
+                write!(f, "it does not re-propose the prepared requests")
+            }
+        }
+    }
+}
+
+/// Checks that the new view is based on 2f + 1 properly signed view changes for it,
+/// and re-proposes exactly the requests those view changes determine
+pub fn check_new_view(
+    new_view: &NewView,
+    pub_keys: &HashMap<NodeId, PublicKey>,
+    num_faulty: usize,
+) -> Result<(), NewViewFault> {
+    for view_change in new_view.view_change_messages.iter() {
+        let signed = pub_keys
+            .get(&view_change.id)
+            .is_some_and(|pub_key| view_change.is_properly_signed_by(pub_key));
+        if !signed {
+            return Err(NewViewFault::UnsignedViewChange(view_change.id));
+        }
+    }
+    let mut signers = HashSet::new();
+    for view_change in new_view.view_change_messages.iter() {
+        if view_change.new_view != new_view.view {
+            return Err(NewViewFault::ViewChangeForOtherView(view_change.id));
+        }
+        signers.insert(view_change.id);
+    }
+    if signers.len() <= 2 * num_faulty {
+        return Err(NewViewFault::TooFewViewChanges(signers.len()));
+    }
+
+    let expected = NewViewRequests::from_view_changes(&new_view.view_change_messages).digests();
+    let proposed = new_view
+        .outstanding_pre_prepares
+        .iter()
+        .map(|pre_prepare| {
+            (
+                pre_prepare.seq_num,
+                pre_prepare.client_request_digest.clone(),
+            )
+        })
+        .collect::<BTreeMap<usize, Vec<u8>>>();
+    if proposed.len() != new_view.outstanding_pre_prepares.len() || proposed != expected {
+        return Err(NewViewFault::WrongReProposals);
+    }
+    let foreign = new_view
+        .outstanding_pre_prepares
+        .iter()
+        .any(|pre_prepare| pre_prepare.view != new_view.view || pre_prepare.id != new_view.id);
+    if foreign {
+        return Err(NewViewFault::ForeignReProposal);
+    }
+    Ok(())
+}
+
+/// Evidence a replica holds against the other replicas, with the replicas which vouched
+/// for it. A replica proven faulty is only passed over as primary once its evidence is
+/// ordered (see `Exclusion`), as replicas learn of the evidence in different views but apply
+/// the ordered evidence alike. Shared by the consensus engine, which records the evidence,
+/// and the node, which answers evidence requests and announces the primaries
+#[derive(Clone, Default)]
+pub struct EvidenceLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// First evidence we checked against each replica
+    evidence: BTreeMap<NodeId, Evidence>,
+    /// First view each replica whose evidence was ordered is passed over as primary for
+    excluded_from: BTreeMap<NodeId, usize>,
+    /// Replicas which vouched for the evidence against each replica
+    blamers: BTreeMap<NodeId, BTreeSet<NodeId>>,
+}
+
+/// Evidence a replica holds, as it reports it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvidenceSummary {
+    pub evidence: Vec<Evidence>,
+    /// Replicas which vouched for the evidence against each replica
+    pub blamers: BTreeMap<NodeId, BTreeSet<NodeId>>,
+    /// First view each replica whose evidence was ordered is passed over as primary for
+    pub excluded_from: BTreeMap<NodeId, usize>,
+}
+
+impl EvidenceLog {
+    /// Records the evidence, which was checked.
+    /// Returns false if we already hold evidence against the replica
+    pub fn record(&self, evidence: Evidence) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let accused = evidence.accused();
+        if inner.evidence.contains_key(&accused) {
+            return false;
+        }
+        inner.evidence.insert(accused, evidence);
+        true
+    }
+
+    /// Passes the accused over as primary from the view of the exclusion, which was ordered.
+    /// The evidence ordered replaces any we hold, so that the log reports what was applied
+    pub fn exclude(&self, exclusion: Exclusion) {
+        let mut inner = self.inner.lock().unwrap();
+        let accused = exclusion.evidence.accused();
+        inner.evidence.insert(accused, exclusion.evidence);
+        inner.excluded_from.insert(accused, exclusion.from_view);
+    }
+
+    /// Replaces the exclusions with those of a store we installed
+    pub fn install_exclusions(&self, exclusions: Vec<Exclusion>) {
+        self.inner.lock().unwrap().excluded_from.clear();
+        for exclusion in exclusions {
+            self.exclude(exclusion);
+        }
+    }
+
+    /// Evidence we hold which is not ordered yet, which the primary orders
+    pub fn unordered(&self) -> Vec<Evidence> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .evidence
+            .iter()
+            .filter(|(id, _)| !inner.excluded_from.contains_key(id))
+            .map(|(_, evidence)| evidence.clone())
+            .collect()
+    }
+
+    /// The replica vouched for the evidence against the accused
+    pub fn corroborate(&self, accused: NodeId, blamer: NodeId) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.blamers.entry(accused).or_default().insert(blamer);
+    }
+
+    pub fn is_proven_faulty(&self, id: NodeId) -> bool {
+        self.inner.lock().unwrap().evidence.contains_key(&id)
+    }
+
+    /// Replicas passed over as primary of the view, at most `max` of them (the fault
+    /// threshold), those with the lowest ids first, so that there is always a primary
+    pub fn excluded(&self, view: usize, max: usize) -> BTreeSet<NodeId> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .excluded_from
+            .iter()
+            .filter(|(_, excluded_from)| **excluded_from <= view)
+            .map(|(id, _)| *id)
+            .take(max)
+            .collect()
+    }
+
+    pub fn summary(&self) -> EvidenceSummary {
+        let inner = self.inner.lock().unwrap();
+        EvidenceSummary {
+            evidence: inner.evidence.values().cloned().collect(),
+            blamers: inner.blamers.clone(),
+            excluded_from: inner.excluded_from.clone(),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the protocol spoken by this build. Replicas which predate the advertisement
+/// of capabilities report version 0 and no features
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional feature of the protocol. Replicas advertise the features they support, and
+/// clients only rely on one once enough replicas support it, so that a feature can be rolled
+/// out one replica at a time across a cluster running mixed versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Feature {
+    /// Several writes applied atomically in one request
+    Batches,
+    /// Reads answered from the committed state without ordering them
+    ReadOnly,
+    /// Values with a proof against the last stable checkpoint
+    KeyProofs,
+    /// Operations streamed to the primary and ordered in batches
+    BulkLoads,
+    /// Compressed frames, which no replica of this version supports yet
+    Compression,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::Batches,
+        Feature::ReadOnly,
+        Feature::KeyProofs,
+        Feature::BulkLoads,
+        Feature::Compression,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::Batches => "batches",
+            Feature::ReadOnly => "read-only",
+            Feature::KeyProofs => "key-proofs",
+            Feature::BulkLoads => "bulk-loads",
+            Feature::Compression => "compression",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.to_string() == s)
+            .ok_or_else(|| format!("unknown feature {}", s))
+    }
+}
+
+/// Set of features, sent as a bit mask so that the features of newer versions which this
+/// one does not know survive a round trip
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Features(u32);
+
+impl Features {
+    /// Features this build supports
+    pub fn supported() -> Self {
+        [
+            Feature::Batches,
+            Feature::ReadOnly,
+            Feature::KeyProofs,
+            Feature::BulkLoads,
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn insert(&mut self, feature: Feature) {
+        self.0 |= feature.bit();
+    }
+
+    pub fn remove(&mut self, feature: Feature) {
+        self.0 &= !feature.bit();
+    }
+
+    /// The known features in the set
+    pub fn iter(&self) -> impl Iterator<Item = Feature> + '_ {
+        Feature::ALL
+            .into_iter()
+            .filter(|feature| self.contains(*feature))
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.iter().map(|feature| feature.to_string()).collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+impl FromIterator<Feature> for Features {
+    fn from_iter<I: IntoIterator<Item = Feature>>(iter: I) -> Self {
+        let mut features = Features::default();
+        for feature in iter {
+            features.insert(feature);
+        }
+        features
+    }
+}
+
+/// What a replica advertises in its responses to clients and in its status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    pub features: Features,
+}
+
+impl Capabilities {
+    /// Capabilities of a replica of this version with the features enabled
+    pub fn new(features: Features) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            features,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::messages::Message;
+
+use std::collections::{BTreeMap, VecDeque};
+
+/// Bounded buffer of pre-prepare, prepare and commit messages for views after the current one.
+/// Nodes which completed a view change before us start sending messages for the new view
+/// right away, so these are kept until we move to that view instead of being dropped.
+///
+/// Only messages for the next `window` views are buffered. When the buffer is full,
+/// a message for a nearer view evicts the most recent message for the furthest view,
+/// and otherwise the new message is dropped
+#[derive(Debug, Default)]
+pub struct FutureViewBuffer {
+    /// Maximum number of buffered messages (0 disables the buffer)
+    capacity: usize,
+    /// How many views ahead of the current view messages are buffered for
+    window: usize,
+    /// Buffered messages by view, in the order they were received
+    messages: BTreeMap<usize, VecDeque<Message>>,
+    len: usize,
+}
+
+impl FutureViewBuffer {
+    pub fn new(capacity: usize, window: usize) -> Self {
+        Self {
+            capacity,
+            window,
+            ..Default::default()
+        }
+    }
+
+    /// View of the message, if it is a kind of message which is buffered
+    pub fn view_of(message: &Message) -> Option<usize> {
+        match message {
+            Message::PrePrepareMessage(pre_prepare) => Some(pre_prepare.view),
+            Message::PrepareMessage(prepare) => Some(prepare.view),
+            Message::CommitMessage(commit) => Some(commit.view),
+            _ => None,
+        }
+    }
+
+    /// Buffers the message if it is for a view within the window after the current view.
+    /// Returns whether the message was buffered
+    pub fn push(&mut self, current_view: usize, message: Message) -> bool {
+        let view = match Self::view_of(&message) {
+            Some(view) => view,
+            None => return false,
+        };
+        if self.capacity == 0 || view <= current_view || view > current_view + self.window {
+            return false;
+        }
+
+        if self.len >= self.capacity {
+            let furthest_view = *self.messages.keys().next_back().unwrap();
+            if view >= furthest_view {
+                return false;
+            }
+            let furthest = self.messages.get_mut(&furthest_view).unwrap();
+            furthest.pop_back();
+            if furthest.is_empty() {
+                self.messages.remove(&furthest_view);
+            }
+            self.len -= 1;
+        }
+
+        self.messages.entry(view).or_default().push_back(message);
+        self.len += 1;
+        true
+    }
+
+    /// Removes and returns the messages for the view, which we just moved to,
+    /// in the order they were received. Messages for earlier views are discarded
+    pub fn drain(&mut self, view: usize) -> Vec<Message> {
+        self.messages = self.messages.split_off(&view);
+        let ready = self.messages.remove(&view).unwrap_or_default();
+        self.len = self.messages.values().map(VecDeque::len).sum();
+        ready.into()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
@@ -0,0 +1,171 @@
+use crate::messages::{ClientRequest, ClientResponse, FailureReason, Operation};
+use crate::node::InnerNode;
+use crate::{time, Key, NodeId};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{self, channel};
+use tokio::time::{sleep, Duration};
+
+/// Submits requests through a node for clients which do not speak the protocol of the
+/// cluster, as the gRPC and HTTP front ends do. Each request is relayed by the node itself
+/// under the address the front end serves on, and its response returned once f + 1
+/// replicas agree on it (2f + 1 for a read-only request), so that a front end is as
+/// trustworthy as the native client, provided the node itself is correct
+#[derive(Clone)]
+pub struct Gateway {
+    pub node: InnerNode,
+    /// Address the replicas know the requests of the front end by
+    respond_addr: SocketAddr,
+    time_stamp: Arc<AtomicUsize>,
+    /// Held while a write is in flight. The requests share the identity of a single
+    /// client, whose requests the replicas execute in the order of their timestamps
+    ordering: Arc<tokio::sync::Mutex<()>>,
+    /// How long the replicas have to agree on the value read by a read-only request
+    /// before the read is ordered instead
+    pub read_only_timeout: Duration,
+}
+
+/// Why a request submitted through a gateway did not succeed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GatewayError {
+    /// The node is draining and takes no more requests
+    Draining,
+    /// Fewer than `quorum` replicas agreed on a result in time
+    NoQuorum { quorum: usize },
+    /// The replicas agreed on rejecting the request
+    Rejected(FailureReason),
+}
+
+impl std::fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GatewayError::Draining => write!(f, "the node is draining"),
+            GatewayError::NoQuorum { quorum } => write!(
+                f,
+                "fewer than {} replicas agreed on a result in time",
+                quorum
+            ),
+            GatewayError::Rejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+impl Gateway {
+    pub fn new(node: InnerNode, respond_addr: SocketAddr) -> Self {
+        Self {
+            node,
+            respond_addr,
+            // replicas ignore requests older than the last one they replied to from this
+            // address, so a restarted front end continues from the current time
+            time_stamp: Arc::new(AtomicUsize::new(time::unix_millis() as usize)),
+            ordering: Arc::new(tokio::sync::Mutex::new(())),
+            read_only_timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Reads the key read-only, and through the ordered path unless 2f + 1 replicas agree
+    /// on its value in time
+    pub async fn read(&self, key: Key) -> Result<ClientResponse, GatewayError> {
+        match self.submit(key.clone(), Operation::Get, true).await {
+            Err(GatewayError::NoQuorum { .. }) => self.submit(key, Operation::Get, false).await,
+            res => res,
+        }
+    }
+
+    /// Submits the request through the node and waits for enough replicas to agree on
+    /// the response, failing if they rejected the request
+    pub async fn submit(
+        &self,
+        key: Key,
+        operation: Operation,
+        read_only: bool,
+    ) -> Result<ClientResponse, GatewayError> {
+        if self.node.draining.load(Ordering::SeqCst) {
+            return Err(GatewayError::Draining);
+        }
+        let _ordering = match read_only {
+            true => None,
+            false => Some(self.ordering.lock().await),
+        };
+        let request = ClientRequest {
+            respond_addr: self.respond_addr,
+            time_stamp: self.time_stamp.fetch_add(1, Ordering::SeqCst),
+            key,
+            operation,
+            relay_id: Some(self.node.id),
+            read_only,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        };
+        let num_faulty = self.node.membership.num_faulty();
+        let (quorum, wait) = match read_only {
+            true => (2 * num_faulty + 1, self.read_only_timeout),
+            false => (num_faulty + 1, self.node.config.relay_timeout),
+        };
+
+        let relay_key = (request.respond_addr, request.time_stamp);
+        let (tx_relay, rx_relay) = channel(self.node.membership.num_nodes().max(1));
+        self.node
+            .relayed_requests
+            .lock()
+            .await
+            .insert(relay_key, tx_relay);
+        let outcome = match self.node.enqueue_client_request(request).await {
+            Ok(()) => collect(rx_relay, quorum, wait).await,
+            Err(busy) => Some(busy),
+        };
+        self.node.relayed_requests.lock().await.remove(&relay_key);
+
+        let response = outcome.ok_or(GatewayError::NoQuorum { quorum })?;
+        match response.reason {
+            Some(reason) => Err(GatewayError::Rejected(reason)),
+            None => Ok(response),
+        }
+    }
+}
+
+/// Waits for the responses of the replicas until `quorum` of them match, returning the
+/// response they agree on, or None once the wait is over
+async fn collect(
+    mut rx_relay: mpsc::Receiver<ClientResponse>,
+    quorum: usize,
+    wait: Duration,
+) -> Option<ClientResponse> {
+    let deadline = sleep(wait);
+    tokio::pin!(deadline);
+    let mut responses: HashMap<NodeId, ClientResponse> = HashMap::new();
+    loop {
+        let response = tokio::select! {
+            Some(response) = rx_relay.recv() => response,
+            _ = &mut deadline => return None,
+        };
+        // a replica which reports the request unordered casts no vote, and the request
+        // times out unless it is ordered after all
+        if let Some(FailureReason::Unordered { .. }) = response.reason {
+            continue;
+        }
+        responses.insert(response.id, response.clone());
+        let num_matching = responses
+            .values()
+            .filter(|vote| {
+                vote.key == response.key
+                    && vote.value == response.value
+                    && vote.previous == response.previous
+                    && vote.results == response.results
+                    && vote.reason == response.reason
+                    && vote.seq_num == response.seq_num
+            })
+            .count();
+        if num_matching >= quorum {
+            return Some(response);
+        }
+    }
+}
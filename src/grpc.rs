@@ -0,0 +1,163 @@
+use crate::gateway::{Gateway, GatewayError};
+use crate::logging;
+use crate::messages::{ClientResponse, ErrorCode, Operation};
+use crate::node::InnerNode;
+use crate::{Key, Value};
+
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::channel;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Code, Request, Response, Status};
+
+/// Messages and service generated from `proto/pbft.proto`
+pub mod proto {
+    tonic::include_proto!("pbft");
+}
+
+use proto::pbft_server::{Pbft, PbftServer};
+use proto::{
+    DeleteRequest, DeleteResponse, GetRequest, GetResponse, KeyEvent, SetRequest, SetResponse,
+    SubscribeRequest,
+};
+
+/// Metadata key of the error code of a call the replicas rejected
+pub const ERROR_CODE_METADATA: &str = "pbft-error-code";
+
+/// gRPC front end of a node, for clients which do not speak the protocol of the cluster.
+/// Each call becomes a client request submitted through the `Gateway` of the front end
+#[derive(Clone)]
+pub struct GrpcFrontEnd {
+    pub gateway: Gateway,
+}
+
+impl GrpcFrontEnd {
+    pub fn new(node: InnerNode, respond_addr: SocketAddr) -> Self {
+        Self {
+            gateway: Gateway::new(node, respond_addr),
+        }
+    }
+
+    /// Serves calls on the listener until it fails
+    pub async fn serve(self, listener: TcpListener) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(PbftServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+}
+
+/// Status of a call which did not succeed, with the error code in its metadata if the
+/// replicas rejected it
+fn failed(e: GatewayError) -> Status {
+    let reason = match e {
+        GatewayError::Draining => return Status::unavailable(e.to_string()),
+        GatewayError::NoQuorum { .. } => return Status::deadline_exceeded(e.to_string()),
+        GatewayError::Rejected(reason) => reason,
+    };
+    let error_code = reason.code();
+    let code = match error_code {
+        ErrorCode::Busy | ErrorCode::Unordered => Code::Unavailable,
+        ErrorCode::QuotaExceeded => Code::ResourceExhausted,
+        ErrorCode::PayloadTooLarge => Code::InvalidArgument,
+        ErrorCode::Unauthorized => Code::PermissionDenied,
+        ErrorCode::StaleTimestamp | ErrorCode::UnexpectedValue => Code::FailedPrecondition,
+        _ => Code::Aborted,
+    };
+    let mut status = Status::new(code, reason.to_string());
+    status
+        .metadata_mut()
+        .insert(ERROR_CODE_METADATA, error_code.code().into());
+    status
+}
+
+fn seq_num(response: &ClientResponse) -> u64 {
+    response.seq_num as u64
+}
+
+#[tonic::async_trait]
+impl Pbft for GrpcFrontEnd {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let key = Key::from(request.into_inner().key);
+        let response = self.gateway.read(key).await.map_err(failed)?;
+        Ok(Response::new(GetResponse {
+            seq_num: seq_num(&response),
+            value: response.value.map(Value::into_bytes),
+        }))
+    }
+
+    async fn set(&self, request: Request<SetRequest>) -> Result<Response<SetResponse>, Status> {
+        let SetRequest { key, value } = request.into_inner();
+        let operation = Operation::Set(Value::from(value));
+        let response = self
+            .gateway
+            .submit(Key::from(key), operation, false)
+            .await
+            .map_err(failed)?;
+        Ok(Response::new(SetResponse {
+            seq_num: seq_num(&response),
+        }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let key = Key::from(request.into_inner().key);
+        let response = self
+            .gateway
+            .submit(key, Operation::Delete, false)
+            .await
+            .map_err(failed)?;
+        Ok(Response::new(DeleteResponse {
+            seq_num: seq_num(&response),
+            previous: response.previous.map(Value::into_bytes),
+        }))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<KeyEvent, Status>>;
+
+    /// Reads the key, and again each time the node applies a write to it
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let key = Key::from(request.into_inner().key);
+        let (tx_events, rx_events) = channel(16);
+        let gateway = self.gateway.clone();
+        logging::spawn(async move {
+            let mut rx_status = gateway.node.rx_status.clone();
+            let mut announced = None;
+            loop {
+                rx_status.borrow_and_update();
+                let version = gateway.node.key_versions.get(&key);
+                if announced != Some(version) {
+                    let event = gateway
+                        .read(key.clone())
+                        .await
+                        .map_err(failed)
+                        .map(|response| KeyEvent {
+                            key: key.as_bytes().to_vec(),
+                            version: version as u64,
+                            value: response.value.map(Value::into_bytes),
+                        });
+                    let failed = event.is_err();
+                    if tx_events.send(event).await.is_err() || failed {
+                        return;
+                    }
+                    announced = Some(version);
+                }
+                tokio::select! {
+                    changed = rx_status.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                    _ = tx_events.closed() => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx_events)))
+    }
+}
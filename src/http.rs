@@ -0,0 +1,124 @@
+use crate::gateway::{Gateway, GatewayError};
+use crate::messages::{ClientResponse, ErrorCode, NodeStatus, Operation};
+use crate::node::InnerNode;
+use crate::{Key, Value};
+
+use std::net::SocketAddr;
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use tokio::net::TcpListener;
+
+/// Header of the sequence number a read or write was committed at
+pub const SEQ_NUM_HEADER: &str = "pbft-seq-num";
+/// Header of the error code of a request which did not succeed
+pub const ERROR_CODE_HEADER: &str = "pbft-error-code";
+
+/// HTTP gateway of a node, for quick integrations with the store:
+///
+/// - `GET /kv/{key}` answers with the value of the key, or 404 if it is not in the store
+/// - `PUT /kv/{key}` sets the key to the body of the request, and answers 204 once the
+///   write is committed
+/// - `GET /status` answers with the status of the node, as JSON
+///
+/// Both answers on a key carry the sequence number they were committed at in the
+/// `pbft-seq-num` header. Reads and writes are submitted through the `Gateway` of the
+/// front end, so they are answered once enough replicas agree on the result
+#[derive(Clone)]
+pub struct HttpFrontEnd {
+    pub gateway: Gateway,
+}
+
+impl HttpFrontEnd {
+    pub fn new(node: InnerNode, respond_addr: SocketAddr) -> Self {
+        Self {
+            gateway: Gateway::new(node, respond_addr),
+        }
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/kv/*key", get(get_key).put(put_key))
+            .route("/status", get(get_status))
+            .with_state(self)
+    }
+
+    /// Serves requests on the listener until it fails
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+}
+
+async fn get_key(State(front_end): State<HttpFrontEnd>, Path(key): Path<String>) -> Response {
+    match front_end.gateway.read(Key::from(key.into_bytes())).await {
+        Ok(response) => {
+            let status = match response.value {
+                Some(_) => StatusCode::OK,
+                None => StatusCode::NOT_FOUND,
+            };
+            let value = response.value.clone().map_or(Vec::new(), Value::into_bytes);
+            (status, [seq_num(&response)], value).into_response()
+        }
+        Err(e) => failed(e),
+    }
+}
+
+async fn put_key(
+    State(front_end): State<HttpFrontEnd>,
+    Path(key): Path<String>,
+    value: Bytes,
+) -> Response {
+    let operation = Operation::Set(Value::from(value.to_vec()));
+    match front_end
+        .gateway
+        .submit(Key::from(key.into_bytes()), operation, false)
+        .await
+    {
+        Ok(response) => (StatusCode::NO_CONTENT, [seq_num(&response)]).into_response(),
+        Err(e) => failed(e),
+    }
+}
+
+async fn get_status(State(front_end): State<HttpFrontEnd>) -> Json<NodeStatus> {
+    Json(front_end.gateway.node.status())
+}
+
+fn seq_num(response: &ClientResponse) -> (&'static str, String) {
+    (SEQ_NUM_HEADER, response.seq_num.to_string())
+}
+
+/// Answer to a request which did not succeed, with its error code in a header where it
+/// has one and the reason in the body
+fn failed(e: GatewayError) -> Response {
+    let (status, error_code) = match &e {
+        GatewayError::Draining => (StatusCode::SERVICE_UNAVAILABLE, None),
+        GatewayError::NoQuorum { .. } => (
+            StatusCode::GATEWAY_TIMEOUT,
+            Some(ErrorCode::QuorumUnavailable),
+        ),
+        GatewayError::Rejected(reason) => {
+            let error_code = reason.code();
+            let status = match error_code {
+                ErrorCode::Busy | ErrorCode::Unordered => StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+                ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorCode::Unauthorized => StatusCode::FORBIDDEN,
+                _ => StatusCode::CONFLICT,
+            };
+            (status, Some(error_code))
+        }
+    };
+    match error_code {
+        Some(error_code) => (
+            status,
+            [(ERROR_CODE_HEADER, error_code.code().to_string())],
+            e.to_string(),
+        )
+            .into_response(),
+        None => (status, e.to_string()).into_response(),
+    }
+}
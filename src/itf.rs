@@ -0,0 +1,253 @@
+use crate::keys::encode_hex;
+use crate::messages::Message;
+use crate::observer::QuorumKind;
+use crate::trace::{TraceEntry, TraceEvent};
+use crate::NodeId;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{json, Value};
+
+/// State variables of an exported trace, in the order they are listed
+pub const VARIABLES: [&str; 7] = [
+    "view",
+    "prepared",
+    "committed",
+    "executed",
+    "stable",
+    "sent",
+    "action",
+];
+
+/// Protocol message in the `sent` set, with the fields every kind of message shares.
+/// The digest is empty for view changes and new views, and the sequence number is the
+/// last stable one of a view change and 0 for a new view
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SentMessage {
+    kind: &'static str,
+    src: NodeId,
+    view: usize,
+    seq: usize,
+    digest: String,
+}
+
+/// Protocol state of every replica after each step of a recorded run
+#[derive(Default)]
+struct Replicas {
+    view: BTreeMap<NodeId, usize>,
+    prepared: BTreeMap<NodeId, BTreeSet<(usize, usize)>>,
+    committed: BTreeMap<NodeId, BTreeSet<(usize, usize)>>,
+    /// Digest of the request each replica executed at each sequence number
+    executed: BTreeMap<NodeId, BTreeMap<usize, String>>,
+    stable: BTreeMap<NodeId, usize>,
+    sent: BTreeSet<SentMessage>,
+}
+
+impl Replicas {
+    /// Applies the event, returning the name of the action it is, or None if the event
+    /// changes no state variable
+    fn step(&mut self, entry: &TraceEntry) -> Option<&'static str> {
+        let node = entry.node;
+        match &entry.event {
+            TraceEvent::Received { .. } => None,
+            TraceEvent::Sent { message, .. } => {
+                let sent = sent_message(message)?;
+                let action = match sent.kind {
+                    "PrePrepare" => "SendPrePrepare",
+                    "Prepare" => "SendPrepare",
+                    "Commit" => "SendCommit",
+                    "CheckPoint" => "SendCheckpoint",
+                    "ViewChange" => "SendViewChange",
+                    _ => "SendNewView",
+                };
+                self.sent.insert(sent).then_some(action)
+            }
+            TraceEvent::Quorum {
+                kind,
+                view,
+                seq_num,
+                ..
+            } => match kind {
+                QuorumKind::Prepare => self
+                    .prepared
+                    .entry(node)
+                    .or_default()
+                    .insert((*view, *seq_num))
+                    .then_some("Prepared"),
+                QuorumKind::Commit => self
+                    .committed
+                    .entry(node)
+                    .or_default()
+                    .insert((*view, *seq_num))
+                    .then_some("Committed"),
+                QuorumKind::CheckPoint => {
+                    let stable = self.stable.entry(node).or_default();
+                    if *seq_num <= *stable {
+                        return None;
+                    }
+                    *stable = *seq_num;
+                    Some("StableCheckpoint")
+                }
+                QuorumKind::ViewChange => None,
+            },
+            TraceEvent::Applied {
+                seq_num, request, ..
+            } => {
+                self.executed
+                    .entry(node)
+                    .or_default()
+                    .insert(*seq_num, encode_hex(&request.digest_at(*seq_num)));
+                Some("Execute")
+            }
+            TraceEvent::ViewChange { view } => {
+                self.view.insert(node, *view);
+                Some("EnterView")
+            }
+        }
+    }
+
+    fn to_itf(&self, nodes: &BTreeSet<NodeId>, action: &str) -> Value {
+        let per_node = |value: &dyn Fn(NodeId) -> Value| {
+            itf_map(nodes.iter().map(|id| (json!(id), value(*id))))
+        };
+        let pairs = |pairs: Option<&BTreeSet<(usize, usize)>>| {
+            itf_set(
+                pairs
+                    .into_iter()
+                    .flatten()
+                    .map(|(view, seq)| json!({ "#tup": [view, seq] })),
+            )
+        };
+        json!({
+            "view": per_node(&|id| json!(self.view.get(&id).copied().unwrap_or_default())),
+            "prepared": per_node(&|id| pairs(self.prepared.get(&id))),
+            "committed": per_node(&|id| pairs(self.committed.get(&id))),
+            "executed": per_node(&|id| itf_map(
+                self.executed
+                    .get(&id)
+                    .into_iter()
+                    .flatten()
+                    .map(|(seq, digest)| (json!(seq), json!(digest))),
+            )),
+            "stable": per_node(&|id| json!(self.stable.get(&id).copied().unwrap_or_default())),
+            "sent": itf_set(self.sent.iter().map(|sent| json!({
+                "type": sent.kind,
+                "src": sent.src,
+                "view": sent.view,
+                "seq": sent.seq,
+                "digest": sent.digest,
+            }))),
+            "action": action,
+        })
+    }
+}
+
+/// Exports recorded traces in the Informal Trace Format (ITF) of Apalache, so that a run
+/// can be checked against a TLA+ specification of PBFT. The entries are taken in the order
+/// given (merge the traces of the replicas by time first). The first state has every
+/// replica in the initial view with empty logs, and every event which changes the
+/// variables below is a step, named by the `action` variable:
+///
+/// - `view`: view of each replica
+/// - `prepared`, `committed`: `<<view, seq>>` pairs each replica collected a quorum for
+/// - `executed`: digest of the request each replica executed at each sequence number
+/// - `stable`: sequence number of the last stable checkpoint of each replica
+/// - `sent`: protocol messages sent by any replica, as records of their type, sender,
+///   view, sequence number and request digest
+///
+/// The meta data of each state holds its index, and the replica and wall clock time of
+/// the event
+pub fn export(entries: &[TraceEntry], initial_view: usize) -> Value {
+    let nodes: BTreeSet<NodeId> = entries.iter().map(|entry| entry.node).collect();
+    let mut replicas = Replicas::default();
+    for id in nodes.iter() {
+        replicas.view.insert(*id, initial_view);
+    }
+
+    let mut states = vec![with_meta(
+        replicas.to_itf(&nodes, "Init"),
+        json!({ "index": 0 }),
+    )];
+    for entry in entries {
+        if let Some(action) = replicas.step(entry) {
+            let meta = json!({
+                "index": states.len(),
+                "node": entry.node,
+                "at_millis": entry.at_millis,
+            });
+            states.push(with_meta(replicas.to_itf(&nodes, action), meta));
+        }
+    }
+
+    json!({
+        "#meta": {
+            "format": "ITF",
+            "format-description": "https://apalache-mc.org/docs/adr/015adr-trace.html",
+            "source": "pbft_trace",
+        },
+        "vars": VARIABLES,
+        "states": states,
+    })
+}
+
+fn sent_message(message: &Message) -> Option<SentMessage> {
+    let sent = |kind, src, view, seq, digest: &[u8]| SentMessage {
+        kind,
+        src,
+        view,
+        seq,
+        digest: encode_hex(digest),
+    };
+    Some(match message {
+        Message::PrePrepareMessage(pre_prepare) => sent(
+            "PrePrepare",
+            pre_prepare.id,
+            pre_prepare.view,
+            pre_prepare.seq_num,
+            &pre_prepare.client_request_digest,
+        ),
+        Message::PrepareMessage(prepare) => sent(
+            "Prepare",
+            prepare.id,
+            prepare.view,
+            prepare.seq_num,
+            &prepare.client_request_digest,
+        ),
+        Message::CommitMessage(commit) => sent(
+            "Commit",
+            commit.id,
+            commit.view,
+            commit.seq_num,
+            &commit.client_request_digest,
+        ),
+        Message::CheckPointMessage(checkpoint) => sent(
+            "CheckPoint",
+            checkpoint.id,
+            checkpoint.view,
+            checkpoint.committed_seq_num,
+            &checkpoint.state_digest,
+        ),
+        Message::ViewChangeMessage(view_change) => sent(
+            "ViewChange",
+            view_change.id,
+            view_change.new_view,
+            view_change.last_stable_seq_num,
+            &[],
+        ),
+        Message::NewViewMessage(new_view) => sent("NewView", new_view.id, new_view.view, 0, &[]),
+        _ => return None,
+    })
+}
+
+fn with_meta(mut state: Value, meta: Value) -> Value {
+    state["#meta"] = meta;
+    state
+}
+
+fn itf_map(pairs: impl Iterator<Item = (Value, Value)>) -> Value {
+    json!({ "#map": pairs.map(|(key, value)| json!([key, value])).collect::<Vec<Value>>() })
+}
+
+fn itf_set(elements: impl Iterator<Item = Value>) -> Value {
+    json!({ "#set": elements.collect::<Vec<Value>>() })
+}
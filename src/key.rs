@@ -0,0 +1,143 @@
+use crate::keys::{decode_hex, encode_hex};
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Prefix of the text form of keys and values which are not UTF-8, or which start with the prefix
+pub(crate) const HEX_PREFIX: &str = "hex:";
+
+/// Key of the store, an arbitrary byte string. Keys are ordered by their bytes,
+/// which for UTF-8 keys is the order of their characters.
+///
+/// Keys are written as text in messages, snapshots and commands: UTF-8 keys as they are,
+/// and other keys hex encoded after `hex:` (e.g. `hex:00ff`)
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for Key {
+    fn from(bytes: Vec<u8>) -> Self {
+        Key(bytes)
+    }
+}
+
+impl From<&[u8]> for Key {
+    fn from(bytes: &[u8]) -> Self {
+        Key(bytes.to_vec())
+    }
+}
+
+impl From<String> for Key {
+    fn from(key: String) -> Self {
+        Key(key.into_bytes())
+    }
+}
+
+impl From<&str> for Key {
+    fn from(key: &str) -> Self {
+        Key(key.as_bytes().to_vec())
+    }
+}
+
+impl FromStr for Key {
+    type Err = InvalidKey;
+
+    /// Parses the text form of the key
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_text(s)
+            .map(Key)
+            .ok_or_else(|| InvalidKey(s.to_string()))
+    }
+}
+
+impl fmt::Display for Key {
+    /// Writes the text form of the key
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_text(f, &self.0)
+    }
+}
+
+/// Bytes of the text form of a byte string, or None if it is not hex encoded after `hex:`
+pub(crate) fn parse_text(s: &str) -> Option<Vec<u8>> {
+    match s.strip_prefix(HEX_PREFIX) {
+        Some(encoded) => decode_hex(encoded).ok(),
+        None => Some(s.as_bytes().to_vec()),
+    }
+}
+
+/// Writes the text form of a byte string: UTF-8 as it is, anything else hex encoded after `hex:`
+pub(crate) fn write_text(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.starts_with(HEX_PREFIX) => write!(f, "{}", text),
+        _ => write!(f, "{}{}", HEX_PREFIX, encode_hex(bytes)),
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // the text form, so that keys can also be the keys of JSON maps
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl Visitor<'_> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a key, as text or hex encoded after \"{}\"", HEX_PREFIX)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Key, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(KeyVisitor)
+    }
+}
+
+/// Text which starts with `hex:` but is not followed by hex encoded bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKey(pub String);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key {} is not hex encoded after \"{}\"",
+            self.0, HEX_PREFIX
+        )
+    }
+}
+
+impl std::error::Error for InvalidKey {}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature};
+use rand::rngs::OsRng;
+
+use crate::crypto::{self, DigestAlgorithm, SigningInput};
+use crate::keystore::Keystore;
+use crate::NodeId;
+
+/// Source of the ed25519 keypair a node signs its messages with.
+/// Keys are given hex encoded, either as a 32 byte secret key or as a 64 byte keypair.
+/// A provider which keeps the secret key to itself (a key management service, or an
+/// external command) refuses to hand out the keypair with `KeyError::Unexportable`,
+/// and signs the messages of the node itself (see `Keystore::from_provider`)
+pub trait KeyProvider {
+    /// Loads the keypair, in the byte encoding used by `Keypair::from_bytes`
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError>;
+
+    /// Public key the node is known by
+    fn public_key(&self) -> Result<PublicKey, KeyError> {
+        Ok(Keystore::from_bytes(&self.keypair_bytes()?)?.public_key())
+    }
+
+    /// Signs the input with the algorithm, as `crypto::untagged_signature` does with the
+    /// keypair: Ed25519ph of the input for sha512, and Ed25519 of its digest otherwise
+    fn sign(
+        &self,
+        input: &SigningInput,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Signature, KeyError> {
+        let keystore = Keystore::from_bytes(&self.keypair_bytes()?)?;
+        Ok(crypto::untagged_signature(
+            keystore.keypair().ok_or(KeyError::Unexportable)?,
+            input,
+            algorithm,
+        ))
+    }
+}
+
+/// Reads the key from a file
+pub struct FileKeyProvider {
+    pub path: PathBuf,
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let encoded = std::fs::read_to_string(&self.path)
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", self.path.display(), e)))?;
+        decode_keypair(&encoded)
+    }
+}
+
+/// Reads the key from an environment variable
+pub struct EnvKeyProvider {
+    pub var: String,
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let encoded = std::env::var(&self.var)
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", self.var, e)))?;
+        decode_keypair(&encoded)
+    }
+}
+
+/// Reads the key from the output of an external command,
+/// e.g. a script which fetches it from a key management service
+pub struct CommandKeyProvider {
+    pub command: String,
+}
+
+impl KeyProvider for CommandKeyProvider {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", self.command, e)))?;
+        if !output.status.success() {
+            return Err(KeyError::Unavailable(format!(
+                "{}: exited with {}",
+                self.command, output.status
+            )));
+        }
+        decode_keypair(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// Generates a fresh keypair each time the node starts
+pub struct GeneratedKeyProvider;
+
+impl KeyProvider for GeneratedKeyProvider {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let mut rng = OsRng {};
+        let keypair: Keypair = Keypair::generate(&mut rng);
+        Ok(keypair.to_bytes().to_vec())
+    }
+}
+
+/// Decodes a hex encoded secret key or keypair into keypair bytes
+pub fn decode_keypair(encoded: &str) -> Result<Vec<u8>, KeyError> {
+    let bytes = decode_hex(encoded)?;
+
+    match bytes.len() {
+        32 => {
+            let secret = SecretKey::from_bytes(&bytes).map_err(|_| KeyError::InvalidKey)?;
+            let public: PublicKey = (&secret).into();
+            Ok(Keypair { secret, public }.to_bytes().to_vec())
+        }
+        64 => {
+            // make sure the public half actually matches the secret half
+            let keypair = Keypair::from_bytes(&bytes).map_err(|_| KeyError::InvalidKey)?;
+            let public: PublicKey = (&keypair.secret).into();
+            if public != keypair.public {
+                return Err(KeyError::InvalidKey);
+            }
+            Ok(bytes)
+        }
+        _ => Err(KeyError::InvalidKey),
+    }
+}
+
+/// Decodes a hex string, ignoring surrounding whitespace
+pub fn decode_hex(encoded: &str) -> Result<Vec<u8>, KeyError> {
+    let encoded = encoded.trim();
+    if !encoded.is_ascii() || !encoded.len().is_multiple_of(2) {
+        return Err(KeyError::InvalidKey);
+    }
+    (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| KeyError::InvalidKey)
+}
+
+/// Hex encoding of the bytes, e.g. to share a public key
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads hex encoded public keys, one per line in the order of the node ids
+pub fn read_pub_keys(path: &Path) -> Result<HashMap<NodeId, PublicKey>, KeyError> {
+    let encoded = std::fs::read_to_string(path)
+        .map_err(|e| KeyError::Unavailable(format!("{}: {}", path.display(), e)))?;
+    encoded
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(id, line)| {
+            let bytes = decode_hex(line)?;
+            let pub_key = PublicKey::from_bytes(&bytes).map_err(|_| KeyError::InvalidKey)?;
+            Ok((id, pub_key))
+        })
+        .collect()
+}
+
+/// Reasons a key provider could not supply a keypair or a signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    /// The key could not be read from its source
+    Unavailable(String),
+    /// The key is not a hex encoded ed25519 secret key or keypair
+    InvalidKey,
+    /// The keystore file could not be decrypted: the passphrase is wrong or the file was altered
+    WrongPassphrase,
+    /// The provider signs with the secret key itself, and does not hand it out
+    Unexportable,
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::Unavailable(reason) => write!(f, "key unavailable ({})", reason),
+            KeyError::InvalidKey => {
+                write!(f, "key is not a hex encoded ed25519 secret key or keypair")
+            }
+            KeyError::WrongPassphrase => {
+                write!(f, "wrong passphrase or altered keystore file")
+            }
+            KeyError::Unexportable => write!(f, "the secret key is kept by its provider"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
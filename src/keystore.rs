@@ -0,0 +1,232 @@
+use crate::crypto::{self, DigestAlgorithm, SigningInput};
+use crate::keys::{decode_hex, encode_hex, KeyError, KeyProvider};
+use crate::transport::{apply_keystream, hmac_sha256};
+
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ed25519_dalek::{Keypair, PublicKey};
+use log::error;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Rounds of HMAC-SHA256 deriving the keys of a keystore file from its passphrase
+pub const KDF_ITERATIONS: u32 = 100_000;
+
+/// Suffix of the file the previous key is kept in when a keystore is rotated
+pub const PREVIOUS_SUFFIX: &str = "previous";
+
+/// Handle on the ed25519 keypair a node signs its messages with. Cheap to clone,
+/// all clones share the keypair
+#[derive(Clone)]
+pub struct Keystore {
+    signer: Signer,
+    public_key: PublicKey,
+}
+
+/// What signs the messages of a keystore
+#[derive(Clone)]
+enum Signer {
+    /// The keypair, held in memory
+    Keypair(Arc<Keypair>),
+    /// The provider of the key, which keeps the secret key to itself
+    Provider(Arc<dyn KeyProvider + Send + Sync>),
+}
+
+impl Keystore {
+    /// Keystore with a freshly generated keypair
+    pub fn generate() -> Self {
+        let mut rng = OsRng {};
+        Self::from_keypair(Keypair::generate(&mut rng))
+    }
+
+    /// Keystore of the keypair, e.g. one generated from a seeded rng in tests
+    pub fn from_keypair(keypair: Keypair) -> Self {
+        Self {
+            public_key: keypair.public,
+            signer: Signer::Keypair(Arc::new(keypair)),
+        }
+    }
+
+    /// Keystore of the keypair in the byte encoding used by `Keypair::from_bytes`,
+    /// whose public half must match its secret half
+    pub fn from_bytes(keypair_bytes: &[u8]) -> Result<Self, KeyError> {
+        let keypair = Keypair::from_bytes(keypair_bytes).map_err(|_| KeyError::InvalidKey)?;
+        let public: PublicKey = (&keypair.secret).into();
+        if public != keypair.public {
+            return Err(KeyError::InvalidKey);
+        }
+        Ok(Self::from_keypair(keypair))
+    }
+
+    /// Keystore of the key of the provider. The keypair is loaded into memory if the provider
+    /// hands it out, otherwise every signature is asked from the provider
+    pub fn from_provider(provider: Box<dyn KeyProvider + Send + Sync>) -> Result<Self, KeyError> {
+        match provider.keypair_bytes() {
+            Ok(keypair_bytes) => Self::from_bytes(&keypair_bytes),
+            Err(KeyError::Unexportable) => Ok(Self {
+                public_key: provider.public_key()?,
+                signer: Signer::Provider(provider.into()),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The keypair, unless the secret key is kept by the provider
+    pub fn keypair(&self) -> Option<&Keypair> {
+        match &self.signer {
+            Signer::Keypair(keypair) => Some(keypair),
+            Signer::Provider(_) => None,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    pub fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let keypair = self.keypair().ok_or(KeyError::Unexportable)?;
+        Ok(keypair.to_bytes().to_vec())
+    }
+
+    /// Signs the input with the algorithm, returning the tagged signature. A message the
+    /// provider fails to sign goes out unsigned, and is dropped by its receivers like a
+    /// lost one
+    pub fn sign(&self, input: &SigningInput, algorithm: DigestAlgorithm) -> Vec<u8> {
+        match &self.signer {
+            Signer::Keypair(keypair) => crypto::sign(keypair, input, algorithm),
+            Signer::Provider(provider) => match provider.sign(input, algorithm) {
+                Ok(signature) => crypto::tag_signature(algorithm, &signature),
+                Err(e) => {
+                    error!("Could not sign with the key provider: {}", e);
+                    Vec::new()
+                }
+            },
+        }
+    }
+
+    /// Writes the keypair to the file encrypted with the passphrase, readable by the owner only.
+    /// The public key is stored in the clear, so it can be shared without the passphrase
+    pub fn save(&self, path: &Path, passphrase: &str) -> Result<(), KeyError> {
+        let mut salt = [0u8; 16];
+        OsRng {}.fill_bytes(&mut salt);
+        let (enc_key, mac_key) = derive_keys(passphrase, &salt, KDF_ITERATIONS);
+        let keypair = self.keypair().ok_or(KeyError::Unexportable)?;
+        let public_key = keypair.public.as_bytes().to_vec();
+        // the salt is fresh, and so is the key, so the keystream is never reused
+        let mut secret_key = keypair.secret.as_bytes().to_vec();
+        apply_keystream(&enc_key, 0, &mut secret_key);
+        let tag = hmac_sha256(&mac_key, &[&salt, &public_key, &secret_key]);
+        let file = KeystoreFile {
+            public_key: encode_hex(&public_key),
+            kdf_iterations: KDF_ITERATIONS,
+            salt: encode_hex(&salt),
+            encrypted_secret_key: encode_hex(&secret_key),
+            tag: encode_hex(&tag),
+        };
+
+        // write to a temporary file first, so a crash never leaves a truncated keystore
+        let tmp_path = path.with_extension("tmp");
+        let unavailable =
+            |e: std::io::Error| KeyError::Unavailable(format!("{}: {}", path.display(), e));
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // only the owner reads the keystore, where the platform has permission bits
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut tmp = options.open(&tmp_path).map_err(unavailable)?;
+        tmp.write_all(serde_json::to_string_pretty(&file).unwrap().as_bytes())
+            .and_then(|_| tmp.sync_all())
+            .map_err(unavailable)?;
+        std::fs::rename(&tmp_path, path).map_err(unavailable)
+    }
+
+    /// Reads the keypair from a file written by `save`, checking that it was not altered
+    /// and that the secret key matches the public key stored with it
+    pub fn load(path: &Path, passphrase: &str) -> Result<Self, KeyError> {
+        let encoded = std::fs::read_to_string(path)
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", path.display(), e)))?;
+        let file: KeystoreFile = serde_json::from_str(&encoded)
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", path.display(), e)))?;
+        let public_key = decode_hex(&file.public_key)?;
+        let salt = decode_hex(&file.salt)?;
+        let mut secret_key = decode_hex(&file.encrypted_secret_key)?;
+        let (enc_key, mac_key) = derive_keys(passphrase, &salt, file.kdf_iterations);
+        let tag = hmac_sha256(&mac_key, &[&salt, &public_key, &secret_key]);
+        if decode_hex(&file.tag)? != tag {
+            return Err(KeyError::WrongPassphrase);
+        }
+        apply_keystream(&enc_key, 0, &mut secret_key);
+        secret_key.extend(public_key);
+        Self::from_bytes(&secret_key)
+    }
+
+    /// Replaces the keypair of the file with a freshly generated one, encrypted with the
+    /// same passphrase. The previous file is kept next to it with the `previous` extension
+    /// until the peers pin the new public key
+    pub fn rotate(path: &Path, passphrase: &str) -> Result<Self, KeyError> {
+        // make sure the passphrase opens the current key before replacing it
+        Self::load(path, passphrase)?;
+        std::fs::copy(path, previous_path(path))
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", path.display(), e)))?;
+        let keystore = Self::generate();
+        keystore.save(path, passphrase)?;
+        Ok(keystore)
+    }
+}
+
+/// Path the previous keypair of a rotated keystore file is kept at
+pub fn previous_path(path: &Path) -> PathBuf {
+    let mut previous = path.as_os_str().to_owned();
+    previous.push(".");
+    previous.push(PREVIOUS_SUFFIX);
+    PathBuf::from(previous)
+}
+
+/// Loads the key from a keystore file, with the passphrase in an environment variable
+pub struct KeystoreKeyProvider {
+    pub path: PathBuf,
+    pub passphrase_var: String,
+}
+
+impl KeyProvider for KeystoreKeyProvider {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        let passphrase = std::env::var(&self.passphrase_var)
+            .map_err(|e| KeyError::Unavailable(format!("{}: {}", self.passphrase_var, e)))?;
+        Keystore::load(&self.path, &passphrase)?.keypair_bytes()
+    }
+}
+
+/// Encrypted keypair as stored on disk, hex encoded
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    public_key: String,
+    kdf_iterations: u32,
+    salt: String,
+    encrypted_secret_key: String,
+    tag: String,
+}
+
+/// Encryption and MAC keys of a keystore file, derived from the passphrase with
+/// PBKDF2-HMAC-SHA256. Passphrases are hashed first to fit the HMAC key
+fn derive_keys(passphrase: &str, salt: &[u8], iterations: u32) -> ([u8; 32], [u8; 32]) {
+    let password: [u8; 32] = Sha256::digest(passphrase.as_bytes()).into();
+    let mut block = hmac_sha256(&password, &[salt, &1u32.to_be_bytes()]);
+    let mut master = block;
+    for _ in 1..iterations {
+        block = hmac_sha256(&password, &[&block]);
+        for (byte, block_byte) in master.iter_mut().zip(block) {
+            *byte ^= block_byte;
+        }
+    }
+    (
+        hmac_sha256(&master, &[b"pbft keystore enc"]),
+        hmac_sha256(&master, &[b"pbft keystore mac"]),
+    )
+}
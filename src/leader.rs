@@ -0,0 +1,135 @@
+use crate::NodeId;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// Policy choosing the primary of each view. Replicas only accept pre-prepares and new views
+/// from the primary they compute, so every replica of a cluster, and its clients, must use
+/// the same policy. For the cluster to stay live, the primaries of any f + 1 consecutive
+/// views should not all be the same f nodes
+pub trait LeaderElection: Send + Sync {
+    fn leader(&self, view: usize, num_nodes: usize) -> NodeId;
+
+    /// Number of views after which the primaries repeat
+    fn period(&self, num_nodes: usize) -> usize {
+        num_nodes
+    }
+}
+
+/// Every node in turn, in the order of their ids (view mod n), as in the PBFT paper
+pub struct RoundRobin;
+
+impl LeaderElection for RoundRobin {
+    fn leader(&self, view: usize, num_nodes: usize) -> NodeId {
+        view % num_nodes
+    }
+}
+
+/// Nodes in turn, each for a share of the views proportional to its weight, e.g. to favor
+/// the replicas closest to the clients. The turns of a node are spread over the rotation
+/// (smooth weighted round robin), so no node is primary for long stretches of views
+pub struct WeightedRotation {
+    schedule: Vec<NodeId>,
+}
+
+impl WeightedRotation {
+    /// Rotation over the nodes with a positive weight
+    pub fn new(weights: &BTreeMap<NodeId, usize>) -> Self {
+        let total: usize = weights.values().sum();
+        let mut credits: BTreeMap<NodeId, i64> = weights.keys().map(|id| (*id, 0)).collect();
+        let mut schedule = Vec::with_capacity(total);
+        for _ in 0..total {
+            for (id, weight) in weights.iter() {
+                *credits.get_mut(id).unwrap() += *weight as i64;
+            }
+            // ties go to the lowest id
+            let next = *credits
+                .iter()
+                .max_by_key(|(id, credit)| (**credit, Reverse(**id)))
+                .unwrap()
+                .0;
+            *credits.get_mut(&next).unwrap() -= total as i64;
+            schedule.push(next);
+        }
+        Self { schedule }
+    }
+}
+
+impl LeaderElection for WeightedRotation {
+    fn leader(&self, view: usize, num_nodes: usize) -> NodeId {
+        if self.schedule.is_empty() {
+            return RoundRobin.leader(view, num_nodes);
+        }
+        self.schedule[view % self.schedule.len()]
+    }
+
+    fn period(&self, num_nodes: usize) -> usize {
+        if self.schedule.is_empty() {
+            return RoundRobin.period(num_nodes);
+        }
+        self.schedule.len()
+    }
+}
+
+/// The leader election policy of a cluster, round robin unless configured otherwise
+#[derive(Clone)]
+pub struct LeaderPolicy {
+    policy: Arc<dyn LeaderElection>,
+}
+
+impl LeaderPolicy {
+    pub fn new(policy: impl LeaderElection + 'static) -> Self {
+        Self {
+            policy: Arc::new(policy),
+        }
+    }
+
+    pub fn leader(&self, view: usize, num_nodes: usize) -> NodeId {
+        self.policy.leader(view, num_nodes)
+    }
+
+    /// Primary of the view, or if it is excluded, that of the first view after it whose
+    /// primary is not. The primary of the view is kept if the policy has no other
+    pub fn leader_excluding(
+        &self,
+        view: usize,
+        num_nodes: usize,
+        excluded: &BTreeSet<NodeId>,
+    ) -> NodeId {
+        let leader = self.leader(view, num_nodes);
+        if !excluded.contains(&leader) {
+            return leader;
+        }
+        (view..view.saturating_add(self.policy.period(num_nodes)))
+            .map(|view| self.leader(view, num_nodes))
+            .find(|leader| !excluded.contains(leader))
+            .unwrap_or(leader)
+    }
+
+    /// Primary of the view among the members, whose ids a reconfiguration may have left with
+    /// gaps: the policy picks the position of the primary among the members in the order of
+    /// their ids, which for the ids 0 to n - 1 of the genesis epoch is the id itself.
+    /// Excluded members are passed over as with `leader_excluding`
+    pub fn leader_among(
+        &self,
+        view: usize,
+        members: &[NodeId],
+        excluded: &BTreeSet<NodeId>,
+    ) -> NodeId {
+        let excluded_positions: BTreeSet<usize> = members
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| excluded.contains(id))
+            .map(|(position, _)| position)
+            .collect();
+        let position = self.leader_excluding(view, members.len(), &excluded_positions);
+        members[position % members.len()]
+    }
+}
+
+impl Default for LeaderPolicy {
+    fn default() -> Self {
+        Self::new(RoundRobin)
+    }
+}
@@ -1,16 +1,70 @@
-/// Byzantine Fault Tolerant KV-Store
+//! Byzantine Fault Tolerant KV-Store
 
 pub type NodeId = usize;
+/// Identity a client registers with its public key (see `registry::client_key`)
+pub type ClientId = u64;
 
-pub type Key = String;
-pub type Value = u32;
+pub use key::Key;
+pub use value::Value;
 
+pub mod authenticator;
+pub mod backend;
+pub mod bulk;
+pub mod byzantine;
+pub mod client;
+pub mod codec;
 pub mod config;
+pub mod connectivity;
 pub mod consensus;
-pub mod message_bank;
+pub mod crypto;
+pub mod data_dir;
+pub mod dead_letter;
+pub mod diagnostics;
+pub mod diffs;
+pub mod evidence;
+pub mod features;
+pub mod future_view;
+pub mod gateway;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod itf;
+pub mod key;
+pub mod keys;
+pub mod keystore;
+pub mod leader;
+pub mod limits;
+pub mod linearizability;
+pub mod logging;
+pub mod membership;
+pub mod mempool;
+pub mod merkle;
+pub(crate) mod message_bank;
 pub mod messages;
+pub mod metrics;
 pub mod node;
+pub mod observer;
+pub mod outbox;
+pub mod pipeline;
+pub mod pki;
+pub mod prelude;
+pub mod probe;
+pub mod registry;
+pub mod scenario;
+pub mod sim;
+pub mod snapshot;
 pub mod state;
+pub mod state_transfer;
+pub mod storage;
+pub mod testing;
+pub mod testkit;
+pub mod time;
+pub mod trace;
+pub mod transport;
+pub mod value;
+pub mod verification;
+pub mod versions;
 pub mod view_changer;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+
+/// Most operations in a batched client request
+pub const MAX_BATCH_OPS: usize = 1024;
+
+/// Most checkpoints proving a stable checkpoint, at most one from each replica
+/// (which also bounds the size of a cluster)
+pub const MAX_CHECKPOINT_PROOF: usize = 256;
+
+/// Most sequence numbers whose prepared requests a view change carries,
+/// which are at most the log window above its last stable checkpoint
+pub const MAX_SUBSEQUENT_PREPARES: usize = 4096;
+
+/// Most requests of a client whose status is asked for at once
+pub const MAX_REQUEST_STATUSES: usize = 1024;
+
+/// Longest signature, an algorithm tag followed by an ed25519 signature
+pub const MAX_SIGNATURE_LEN: usize = 1 + 64;
+
+/// Serde helpers enforcing the limits of the wire format. Both the encoder and the decoder
+/// go through them: a message beyond a limit is not encoded, and decoding fails at the first
+/// element past the limit, so the allocation for a field never grows with a length the
+/// sender claims, only with the elements actually read, up to the limit
+macro_rules! bounded_seq {
+    ($name:ident, $max:expr, $what:expr) => {
+        pub mod $name {
+            use super::*;
+
+            pub fn serialize<S: Serializer, T: Serialize>(
+                items: &[T],
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serialize_seq(items, $max, $what, serializer)
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+                deserializer: D,
+            ) -> Result<Vec<T>, D::Error> {
+                deserializer.deserialize_seq(BoundedSeq {
+                    max: $max,
+                    what: $what,
+                    marker: PhantomData,
+                })
+            }
+        }
+    };
+}
+
+macro_rules! bounded_map {
+    ($name:ident, $max:expr, $what:expr) => {
+        pub mod $name {
+            use super::*;
+
+            pub fn serialize<S: Serializer, K: Serialize, V: Serialize>(
+                entries: &BTreeMap<K, V>,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                if entries.len() > $max {
+                    return Err(ser::Error::custom(too_long($what, $max)));
+                }
+                serializer.collect_map(entries)
+            }
+
+            pub fn deserialize<'de, D, K, V>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+            where
+                D: Deserializer<'de>,
+                K: Deserialize<'de> + Ord,
+                V: Deserialize<'de>,
+            {
+                deserializer.deserialize_map(BoundedMap {
+                    max: $max,
+                    what: $what,
+                    marker: PhantomData,
+                })
+            }
+        }
+    };
+}
+
+bounded_seq!(batch_ops, MAX_BATCH_OPS, "batch operations");
+bounded_seq!(checkpoint_proof, MAX_CHECKPOINT_PROOF, "checkpoints");
+bounded_seq!(signature, MAX_SIGNATURE_LEN, "signature bytes");
+bounded_seq!(request_statuses, MAX_REQUEST_STATUSES, "request statuses");
+bounded_map!(authenticator, MAX_CHECKPOINT_PROOF, "authenticator entries");
+bounded_map!(
+    subsequent_prepares,
+    MAX_SUBSEQUENT_PREPARES,
+    "prepared sequence numbers"
+);
+
+fn too_long(what: &str, max: usize) -> String {
+    format!("more than {} {}", max, what)
+}
+
+fn serialize_seq<S: Serializer, T: Serialize>(
+    items: &[T],
+    max: usize,
+    what: &'static str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if items.len() > max {
+        return Err(ser::Error::custom(too_long(what, max)));
+    }
+    serializer.collect_seq(items)
+}
+
+struct BoundedSeq<T> {
+    max: usize,
+    what: &'static str,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for BoundedSeq<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at most {} {}", self.max, self.what)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<T>, A::Error> {
+        // the size hint comes from the sender, so it is only trusted up to the limit
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(self.max));
+        while let Some(item) = seq.next_element()? {
+            if items.len() == self.max {
+                return Err(de::Error::custom(too_long(self.what, self.max)));
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+struct BoundedMap<K, V> {
+    max: usize,
+    what: &'static str,
+    marker: PhantomData<(K, V)>,
+}
+
+impl<'de, K, V> Visitor<'de> for BoundedMap<K, V>
+where
+    K: Deserialize<'de> + Ord,
+    V: Deserialize<'de>,
+{
+    type Value = BTreeMap<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at most {} {}", self.max, self.what)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<BTreeMap<K, V>, A::Error> {
+        let mut entries = BTreeMap::new();
+        let mut read = 0;
+        while let Some((key, value)) = map.next_entry()? {
+            // duplicate keys count too, as each was parsed
+            if read == self.max {
+                return Err(de::Error::custom(too_long(self.what, self.max)));
+            }
+            read += 1;
+            entries.insert(key, value);
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,260 @@
+use crate::messages::{ClientResponse, FailureReason, Operation};
+use crate::{Key, Value};
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Operations clients invoked on the store and the responses they accepted, in the order
+/// they happened. Cheap to clone, all clones record to the same history, so the clients
+/// of a run share one and the history is checked once they are done
+#[derive(Clone, Default)]
+pub struct History {
+    log: Arc<Mutex<Log>>,
+}
+
+#[derive(Default)]
+struct Log {
+    entries: Vec<Entry>,
+    /// Position of the next invocation or response among the events of the history
+    next_event: usize,
+}
+
+/// A single-key operation of a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub client: SocketAddr,
+    pub key: Key,
+    pub operation: Operation,
+    /// Position of the invocation among the events of the history
+    pub call: usize,
+    /// None if the client gave the operation up, in which case it may have taken effect
+    /// at any point after it was invoked, or never
+    pub response: Option<Response>,
+}
+
+/// The result a client accepted for an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// Position of the response among the events of the history
+    pub at: usize,
+    pub value: Option<Value>,
+    pub previous: Option<Value>,
+    pub reason: Option<FailureReason>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the client invoked the operation, returning the id of the operation
+    /// to complete it with
+    pub fn invoke(&self, client: SocketAddr, key: Key, operation: Operation) -> usize {
+        let mut log = self.log.lock().unwrap();
+        let call = log.next_event;
+        log.next_event += 1;
+        log.entries.push(Entry {
+            client,
+            key,
+            operation,
+            call,
+            response: None,
+        });
+        log.entries.len() - 1
+    }
+
+    /// Records the response the client accepted for the operation
+    pub fn complete(&self, id: usize, response: &ClientResponse) {
+        let mut log = self.log.lock().unwrap();
+        let at = log.next_event;
+        log.next_event += 1;
+        log.entries[id].response = Some(Response {
+            at,
+            value: response.value.clone(),
+            previous: response.previous.clone(),
+            reason: response.reason,
+        });
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.log.lock().unwrap().entries.clone()
+    }
+
+    /// Checks that the history is linearizable, see `check`
+    pub fn check(&self) -> Result<(), Counterexample> {
+        check(&self.entries())
+    }
+}
+
+/// Checks that the operations are linearizable with respect to a key-value store: that
+/// each took effect at once at some point between its invocation and its response, in
+/// an order which explains every response. Keys are independent, so the operations of
+/// each key are checked apart. Returns the smallest part of the history of a key which
+/// is not linearizable, if any
+pub fn check(entries: &[Entry]) -> Result<(), Counterexample> {
+    let mut by_key: BTreeMap<&Key, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        // a read which was given up has no effect to account for
+        if entry.operation == Operation::Get && entry.response.is_none() {
+            continue;
+        }
+        by_key.entry(&entry.key).or_default().push(entry);
+    }
+    for (key, entries) in by_key {
+        if !is_linearizable(&entries) {
+            return Err(Counterexample {
+                key: key.clone(),
+                entries: shrink(entries).into_iter().cloned().collect(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Operations on a key which no order of their effects explains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+    pub key: Key,
+    /// Operations of the history of the key, none of which can be left out for the
+    /// remaining ones to be linearizable
+    pub entries: Vec<Entry>,
+}
+
+impl std::fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "history of key {:?} is not linearizable:", self.key)?;
+        for entry in self.entries.iter() {
+            write!(
+                f,
+                "\n  {} invoked {:?} at {}",
+                entry.client, entry.operation, entry.call
+            )?;
+            match &entry.response {
+                Some(response) => write!(
+                    f,
+                    ", returned at {} with value {:?}, previous {:?}, failure {:?}",
+                    response.at, response.value, response.previous, response.reason
+                )?,
+                None => write!(f, ", gave up")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Counterexample {}
+
+/// Leaves out operations one at a time, as long as the rest is still not linearizable
+fn shrink(mut entries: Vec<&Entry>) -> Vec<&Entry> {
+    let mut index = 0;
+    while index < entries.len() {
+        let mut smaller = entries.clone();
+        smaller.remove(index);
+        if is_linearizable(&smaller) {
+            index += 1;
+        } else {
+            entries = smaller;
+        }
+    }
+    entries
+}
+
+/// Searches for an order of the operations explaining every response, as in Wing & Gong's
+/// algorithm, remembering the states already explored as Lowe suggests
+fn is_linearizable(entries: &[&Entry]) -> bool {
+    let mut search = Search {
+        entries,
+        linearized: vec![0; entries.len().div_ceil(64)],
+        explored: HashSet::new(),
+    };
+    search.explore(entries.len(), None)
+}
+
+struct Search<'a> {
+    entries: &'a [&'a Entry],
+    /// Bitset of the operations already given a place in the order
+    linearized: Vec<u64>,
+    explored: HashSet<(Vec<u64>, Option<Value>)>,
+}
+
+impl Search<'_> {
+    fn is_linearized(&self, index: usize) -> bool {
+        self.linearized[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn toggle(&mut self, index: usize) {
+        self.linearized[index / 64] ^= 1 << (index % 64);
+    }
+
+    fn explore(&mut self, remaining: usize, state: Option<Value>) -> bool {
+        if remaining == 0 {
+            return true;
+        }
+        if !self
+            .explored
+            .insert((self.linearized.clone(), state.clone()))
+        {
+            return false;
+        }
+        // the next operation to take effect must have been invoked before
+        // any of the remaining operations returned
+        let returned_by = (0..self.entries.len())
+            .filter(|index| !self.is_linearized(*index))
+            .filter_map(|index| self.entries[index].response.as_ref())
+            .map(|response| response.at)
+            .min()
+            .unwrap_or(usize::MAX);
+        for index in 0..self.entries.len() {
+            if self.is_linearized(index) || self.entries[index].call >= returned_by {
+                continue;
+            }
+            if let Some(next) = apply(&state, self.entries[index]) {
+                self.toggle(index);
+                if self.explore(remaining - 1, next) {
+                    return true;
+                }
+                self.toggle(index);
+            }
+        }
+        false
+    }
+}
+
+/// Value of the key once the operation took effect on the value it had, or None if the
+/// response of the operation does not match that value
+fn apply(state: &Option<Value>, entry: &Entry) -> Option<Option<Value>> {
+    let response = match &entry.response {
+        Some(response) => response,
+        // the effect of an operation given up can be put last, so assuming it took effect
+        // also covers the case it never did
+        None => {
+            return Some(match &entry.operation {
+                Operation::Get => state.clone(),
+                Operation::Set(value) => Some(value.clone()),
+                Operation::Delete => None,
+                Operation::Cas { expected, new } if expected == state => Some(new.clone()),
+                Operation::Cas { .. } => state.clone(),
+            })
+        }
+    };
+    match &entry.operation {
+        Operation::Get => (response.value == *state).then(|| state.clone()),
+        Operation::Set(value) => Some(match response.reason {
+            None => Some(value.clone()),
+            // rejected by a quota
+            Some(_) => state.clone(),
+        }),
+        Operation::Delete => (response.previous == *state).then_some(None),
+        Operation::Cas { expected, new } => {
+            if response.previous != *state {
+                return None;
+            }
+            match response.reason {
+                None => (expected == state).then(|| Some(new.clone())),
+                Some(FailureReason::UnexpectedValue) => (expected != state).then(|| state.clone()),
+                Some(_) => (expected == state).then(|| state.clone()),
+            }
+        }
+    }
+}
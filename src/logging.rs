@@ -0,0 +1,234 @@
+use crate::config::Config;
+use crate::NodeId;
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use env_logger::Env;
+use log::kv::{Error, Key, Value, VisitSource};
+use tokio::task::JoinHandle;
+
+tokio::task_local! {
+    /// Log of the node the running task belongs to
+    static NODE_LOG: Option<NodeLog>;
+}
+
+/// Where the records of a node go: tagged with its id, to stderr or to the log file of the
+/// node, as plain text or as one JSON object per line. Records are routed to the log of the
+/// node whose task logs them (see `NodeLog::scope` and `spawn`), so that every node of a
+/// cluster run in a single process keeps its own id and file
+#[derive(Clone)]
+pub struct NodeLog {
+    id: NodeId,
+    json: bool,
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl NodeLog {
+    /// Opens the log of the node given in the config
+    pub fn open(id: NodeId, config: &Config) -> io::Result<Self> {
+        let file = match &config.log_file {
+            Some(path) => Some(Arc::new(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))),
+            None => None,
+        };
+        Ok(Self {
+            id,
+            json: config.log_json,
+            file,
+        })
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Runs the future with its records, and those of the tasks it spawns with `spawn`,
+    /// going to this log
+    pub fn scope<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        NODE_LOG.scope(Some(self.clone()), future)
+    }
+}
+
+/// Initializes the logging of a node, returning its log, which the tasks of the node run in.
+/// The structured fields of a record (the view, sequence number and request of a protocol
+/// instance) are top-level keys of the JSON object, or follow the message as `key=value`
+/// in plain text. The logger of the process is installed by the first node, whose filter
+/// applies to all of them. Records logged outside the tasks of any node are not tagged,
+/// and go to stderr
+pub fn init(id: NodeId, config: &Config) -> io::Result<NodeLog> {
+    let node_log = NodeLog::open(id, config)?;
+    set_sample_rate(config.log_sample_rate);
+
+    let log_json = config.log_json;
+    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+    if let Some(log_filter) = &config.log_filter {
+        logger.parse_filters(log_filter);
+    }
+    logger.format(move |buf, record| {
+        let node_log = NODE_LOG
+            .try_with(|node_log| node_log.clone())
+            .ok()
+            .flatten();
+        let id = node_log.as_ref().map(|node_log| node_log.id);
+        let time_stamp = buf.timestamp();
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        let line = if node_log.as_ref().map_or(log_json, |node_log| node_log.json) {
+            let mut line = serde_json::json!({
+                "ts": time_stamp.to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "msg": record.args().to_string(),
+            });
+            if let Some(id) = id {
+                line["node"] = serde_json::Value::from(id);
+            }
+            for (key, value) in fields.0 {
+                line[key] = value;
+            }
+            line.to_string()
+        } else {
+            let node = id.map_or(String::new(), |id| format!(" node {}", id));
+            let mut line = format!(
+                "[{} {}{} {}] {}",
+                time_stamp,
+                record.level(),
+                node,
+                record.target(),
+                record.args()
+            );
+            for (key, value) in fields.0 {
+                match value {
+                    serde_json::Value::String(value) => line += &format!(" {}={}", key, value),
+                    value => line += &format!(" {}={}", key, value),
+                }
+            }
+            line
+        };
+
+        match node_log
+            .as_ref()
+            .and_then(|node_log| node_log.file.as_ref())
+        {
+            Some(log_file) => writeln!(log_file.lock().unwrap(), "{}", line),
+            None => writeln!(buf, "{}", line),
+        }
+    });
+    let _ = logger.try_init();
+    Ok(node_log)
+}
+
+/// Spawns the task in the log of the node whose task spawns it
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(inherit(future))
+}
+
+/// The future, logging to the log of the node whose task creates it
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let node_log = NODE_LOG
+        .try_with(|node_log| node_log.clone())
+        .ok()
+        .flatten();
+    NODE_LOG.scope(node_log, future)
+}
+
+/// Structured fields of a log record, with integers kept as JSON numbers
+#[derive(Default)]
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = match value.to_u64() {
+            Some(value) => serde_json::Value::from(value),
+            None => serde_json::Value::from(value.to_string()),
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+/// Short id of a request in logs, to follow it through the phases. Taken from the end of
+/// its digest, as the digest starts with the tag of the algorithm
+pub fn request_id(digest: &[u8]) -> String {
+    crate::keys::encode_hex(&digest[digest.len().saturating_sub(6)..])
+}
+
+/// Only every nth occurrence of a sampled event is logged
+static SAMPLE_RATE: AtomicUsize = AtomicUsize::new(1);
+/// Occurrences of each sampled event since it was last logged
+static EVENT_COUNTS: Mutex<BTreeMap<&'static str, usize>> = Mutex::new(BTreeMap::new());
+
+/// Sets how many occurrences of a per-message event are aggregated into one log line
+/// (1 logs every occurrence). This can be changed while the node is running
+pub fn set_sample_rate(sample_rate: usize) {
+    SAMPLE_RATE.store(sample_rate.max(1), Ordering::Relaxed);
+}
+
+pub fn sample_rate() -> usize {
+    SAMPLE_RATE.load(Ordering::Relaxed)
+}
+
+/// Records an occurrence of the event. Returns the number of occurrences
+/// since the event was last logged if this occurrence should be logged
+pub fn sample(event: &'static str) -> Option<usize> {
+    let sample_rate = sample_rate();
+    let mut event_counts = EVENT_COUNTS.lock().unwrap();
+    let count = event_counts.entry(event).or_insert(0);
+    *count += 1;
+    if *count < sample_rate {
+        return None;
+    }
+    let occurrences = *count;
+    *count = 0;
+    Some(occurrences)
+}
+
+/// Logs a per-message event at the given level, subject to the sample rate.
+/// The arguments are only formatted when the event is logged
+macro_rules! sampled {
+    ($level:ident, $event:expr, $($arg:tt)+) => {
+        if let Some(occurrences) = $crate::logging::sample($event) {
+            if occurrences > 1 {
+                log::$level!("{} ({} occurrences)", format_args!($($arg)+), occurrences);
+            } else {
+                log::$level!($($arg)+);
+            }
+        }
+    };
+}
+pub(crate) use sampled;
+
+/// Logs an event of the protocol instance of a view and sequence number at debug level,
+/// under the `pbft::instance` target. The view, sequence number and request (from the
+/// digest of the request) are fields of the record, so that the lifecycle of a request
+/// can be followed with `--log-filter pbft::instance=debug`
+macro_rules! instance_event {
+    ($view:expr, $seq_num:expr, $digest:expr, $($arg:tt)+) => {
+        log::debug!(
+            target: "pbft::instance",
+            view = $view,
+            seq_num = $seq_num,
+            request = $crate::logging::request_id(&$digest);
+            $($arg)+
+        )
+    };
+}
+pub(crate) use instance_event;
+
+/// The span of the protocol instance of a view and sequence number, which the handlers of
+/// its pre-prepare, prepare and commit messages run in. Subscribers of the `tracing` crate
+/// see every event of the instance with its view and sequence number; without one, entering
+/// and leaving the span is logged at trace level (target `tracing::span::active`)
+pub fn instance_span(view: usize, seq_num: usize) -> tracing::Span {
+    tracing::debug_span!(target: "pbft::instance", "instance", view, seq_num)
+}
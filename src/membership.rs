@@ -0,0 +1,172 @@
+use crate::config::Config;
+use crate::crypto::{self, SigningInput};
+use crate::keys::{decode_hex, encode_hex};
+use crate::keystore::Keystore;
+use crate::{NodeId, Value};
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use ed25519_dalek::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Change to the members of the cluster
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipChange {
+    /// Admits a node with an id which is not taken, reachable on the address
+    AddNode {
+        id: NodeId,
+        addr: SocketAddr,
+        /// Hex encoded public key of the node
+        pub_key: String,
+    },
+    /// Removes a member
+    RemoveNode { id: NodeId },
+}
+
+impl MembershipChange {
+    /// Node the change is about
+    pub fn id(&self) -> NodeId {
+        match self {
+            MembershipChange::AddNode { id, .. } | MembershipChange::RemoveNode { id } => *id,
+        }
+    }
+}
+
+/// Change to the members of the cluster, signed by the root key of the cluster, which moves
+/// the cluster to the next epoch. Clients submit it as the value of a write to the registry
+/// key. Replicas stop executing requests after the one carrying it until its sequence number
+/// is a stable checkpoint, and then switch to the new membership all at once
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reconfiguration {
+    /// Epoch the cluster moves to, which must follow the current one
+    pub epoch: usize,
+    pub change: MembershipChange,
+    /// Hex encoded signature of the root key
+    pub signature: String,
+}
+
+impl Reconfiguration {
+    pub fn new_with_signature(
+        root_keystore: &Keystore,
+        epoch: usize,
+        change: MembershipChange,
+    ) -> Self {
+        let signature = root_keystore.sign(
+            &Self::signing_input(epoch, &change),
+            crypto::policy().algorithm,
+        );
+        Self {
+            epoch,
+            change,
+            signature: encode_hex(&signature),
+        }
+    }
+
+    pub fn is_properly_signed_by(&self, root_pub_key: &PublicKey) -> bool {
+        match decode_hex(&self.signature) {
+            Ok(signature) => crypto::verify(
+                root_pub_key,
+                &Self::signing_input(self.epoch, &self.change),
+                &signature,
+            ),
+            Err(_) => false,
+        }
+    }
+
+    /// The value written to the registry key to reconfigure the cluster
+    pub fn encode(&self) -> Value {
+        Value::from(serde_json::to_vec(self).unwrap())
+    }
+
+    pub fn decode(value: &Value) -> Option<Self> {
+        serde_json::from_slice(value.as_bytes()).ok()
+    }
+
+    fn signing_input(epoch: usize, change: &MembershipChange) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Reconfiguration");
+        signing_input.update_usize(epoch);
+        signing_input.update(serde_json::to_vec(change).unwrap());
+        signing_input
+    }
+}
+
+/// Members of the cluster in the epoch the consensus engine is in. Shared by the engine,
+/// which moves it to the next epoch, and the node, which sends to the members and pins
+/// their keys
+#[derive(Clone, Default)]
+pub struct Membership {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    epoch: usize,
+    addrs: BTreeMap<NodeId, SocketAddr>,
+    pub_keys: HashMap<NodeId, PublicKey>,
+    num_faulty: usize,
+}
+
+impl Membership {
+    pub fn new(config: &Config) -> Self {
+        let membership = Self::default();
+        membership.update(config);
+        membership
+    }
+
+    /// Takes the members of the configuration, which moved to another epoch
+    pub fn update(&self, config: &Config) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.epoch = config.epoch;
+        inner.addrs = config
+            .peer_addrs
+            .iter()
+            .map(|(id, addr)| (*id, *addr))
+            .collect();
+        inner.pub_keys = config.peer_pub_keys.clone();
+        inner.num_faulty = config.num_faulty;
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.inner.lock().unwrap().epoch
+    }
+
+    /// Ids of the members, in ascending order
+    pub fn members(&self) -> Vec<NodeId> {
+        self.inner.lock().unwrap().addrs.keys().copied().collect()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.inner.lock().unwrap().addrs.contains_key(&id)
+    }
+
+    /// Address the member was configured with
+    pub fn addr(&self, id: NodeId) -> Option<SocketAddr> {
+        self.inner.lock().unwrap().addrs.get(&id).copied()
+    }
+
+    /// Addresses of the members, by id
+    pub fn addrs(&self) -> BTreeMap<NodeId, SocketAddr> {
+        self.inner.lock().unwrap().addrs.clone()
+    }
+
+    /// Key pinned for the member, if it is known in advance
+    pub fn pub_key(&self, id: NodeId) -> Option<PublicKey> {
+        self.inner.lock().unwrap().pub_keys.get(&id).copied()
+    }
+
+    pub fn pub_keys(&self) -> HashMap<NodeId, PublicKey> {
+        self.inner.lock().unwrap().pub_keys.clone()
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.inner.lock().unwrap().addrs.len()
+    }
+
+    pub fn num_faulty(&self) -> usize {
+        self.inner.lock().unwrap().num_faulty
+    }
+}
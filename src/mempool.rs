@@ -0,0 +1,189 @@
+use crate::messages::ClientRequest;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Order in which pending requests are proposed. Requests of the same priority
+/// are proposed in the order they arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    Normal,
+    /// Requests which were outstanding in an earlier view, which clients have waited longest for
+    Reissued,
+}
+
+/// Client request waiting for the primary to assign it a sequence number
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub request: ClientRequest,
+    pub digest: Vec<u8>,
+    pub priority: Priority,
+    pub arrived_at: Instant,
+    /// Size of the JSON encoding of the request
+    pub size: usize,
+}
+
+impl PendingRequest {
+    pub fn client(&self) -> SocketAddr {
+        self.request.respond_addr
+    }
+}
+
+/// Outcome of offering a request to the mempool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Admitted,
+    /// A request with the same digest is already pending
+    Duplicate,
+    /// The mempool is full of requests of at least the same priority
+    Full,
+}
+
+/// Client requests which the primary accepted but has not proposed yet.
+/// Requests are deduplicated by digest and proposed by priority, then in order of arrival.
+/// Requests which waited longer than the maximum wait are expired, since their clients
+/// retransmit them by then. When the mempool is full, an incoming request evicts the most
+/// recent request of the lowest priority if it has a higher priority, and is rejected otherwise
+pub struct Mempool {
+    /// Maximum number of pending requests (0 for no limit)
+    capacity: usize,
+    /// How long a request may stay pending (zero to keep requests until they are proposed)
+    max_wait: Duration,
+    queue: BTreeMap<(Reverse<Priority>, u64), PendingRequest>,
+    by_digest: HashMap<Vec<u8>, (Reverse<Priority>, u64)>,
+    next_arrival: u64,
+    stats: MempoolStats,
+}
+
+/// State of the mempool of a replica, reported in node statuses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolStats {
+    pub pending: usize,
+    pub pending_bytes: usize,
+    pub admitted: usize,
+    pub proposed: usize,
+    pub duplicates: usize,
+    /// Requests rejected because the mempool was full
+    pub rejected: usize,
+    /// Pending requests dropped for a request of a higher priority
+    pub evicted: usize,
+    /// Pending requests dropped after waiting longer than the maximum wait
+    pub expired: usize,
+    /// Longest time a proposed request was pending, in milliseconds
+    pub max_wait_millis: u64,
+}
+
+impl Mempool {
+    pub fn new(capacity: usize, max_wait: Duration) -> Self {
+        Self {
+            capacity,
+            max_wait,
+            queue: BTreeMap::new(),
+            by_digest: HashMap::new(),
+            next_arrival: 0,
+            stats: MempoolStats::default(),
+        }
+    }
+
+    pub fn insert(&mut self, request: ClientRequest, priority: Priority) -> Admission {
+        let digest = request.digest();
+        if self.by_digest.contains_key(&digest) {
+            self.stats.duplicates += 1;
+            return Admission::Duplicate;
+        }
+        self.expire();
+        if self.capacity != 0 && self.queue.len() >= self.capacity {
+            match self.queue.keys().next_back().copied() {
+                Some(lowest) if lowest.0 .0 < priority => {
+                    self.remove(&lowest);
+                    self.stats.evicted += 1;
+                }
+                _ => {
+                    self.stats.rejected += 1;
+                    return Admission::Full;
+                }
+            }
+        }
+
+        let position = (Reverse(priority), self.next_arrival);
+        self.next_arrival += 1;
+        let size = serde_json::to_vec(&request).map_or(0, |encoded| encoded.len());
+        self.by_digest.insert(digest.clone(), position);
+        self.queue.insert(
+            position,
+            PendingRequest {
+                request,
+                digest,
+                priority,
+                arrived_at: Instant::now(),
+                size,
+            },
+        );
+        self.stats.admitted += 1;
+        self.stats.pending += 1;
+        self.stats.pending_bytes += size;
+        Admission::Admitted
+    }
+
+    /// Takes the next request to propose
+    pub fn pop(&mut self) -> Option<PendingRequest> {
+        self.expire();
+        let position = *self.queue.keys().next()?;
+        let pending = self.remove(&position)?;
+        self.stats.proposed += 1;
+        self.stats.max_wait_millis = self
+            .stats
+            .max_wait_millis
+            .max(pending.arrived_at.elapsed().as_millis() as u64);
+        Some(pending)
+    }
+
+    /// Takes every pending request, e.g. to pass them on to a new primary
+    pub fn drain(&mut self) -> Vec<PendingRequest> {
+        self.by_digest.clear();
+        self.stats.pending = 0;
+        self.stats.pending_bytes = 0;
+        std::mem::take(&mut self.queue).into_values().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn stats(&self) -> MempoolStats {
+        self.stats
+    }
+
+    fn expire(&mut self) {
+        if self.max_wait.is_zero() {
+            return;
+        }
+        let expired = self
+            .queue
+            .iter()
+            .filter(|(_, pending)| pending.arrived_at.elapsed() > self.max_wait)
+            .map(|(position, _)| *position)
+            .collect::<Vec<_>>();
+        for position in expired {
+            self.remove(&position);
+            self.stats.expired += 1;
+        }
+    }
+
+    fn remove(&mut self, position: &(Reverse<Priority>, u64)) -> Option<PendingRequest> {
+        let pending = self.queue.remove(position)?;
+        self.by_digest.remove(&pending.digest);
+        self.stats.pending -= 1;
+        self.stats.pending_bytes -= pending.size;
+        Some(pending)
+    }
+}
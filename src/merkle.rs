@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ed25519_dalek::PublicKey;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, DigestAlgorithm};
+use crate::messages::{ClientIdentity, KeyProof};
+use crate::{Key, NodeId, Value};
+
+/// Inclusion proof of a leaf in a Merkle tree over the store
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Position of the leaf among the leaves, which are ordered by key
+    pub index: usize,
+    pub num_leaves: usize,
+    /// Hashes of the sibling of each node on the path from the leaf to the root.
+    /// Levels where the node has no sibling are skipped
+    pub siblings: Vec<Vec<u8>>,
+}
+
+/// Proof that a run of consecutive leaves is included in a Merkle tree over the store, so
+/// that a replica fetching the store in chunks can check each chunk on its own
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RangeProof {
+    /// Position of the first leaf of the run among the leaves
+    pub start: usize,
+    pub num_leaves: usize,
+    /// Hashes of the nodes bordering the run on each level, from the leaves up,
+    /// the one on the left of a level before the one on its right
+    pub siblings: Vec<Vec<u8>>,
+}
+
+/// Hash of the entry of the key in the store, together with the client which created it
+pub fn leaf_hash(
+    key: &Key,
+    value: &Value,
+    owner: Option<&ClientIdentity>,
+    algorithm: DigestAlgorithm,
+) -> Vec<u8> {
+    let mut data = vec![0u8];
+    data.extend_from_slice(&crypto::encode_usize(key.len()));
+    data.extend_from_slice(key.as_bytes());
+    data.extend_from_slice(&crypto::encode_usize(value.len()));
+    data.extend_from_slice(value.as_bytes());
+    match owner {
+        Some(owner) => {
+            let owner = owner.to_string();
+            data.push(1u8);
+            data.extend_from_slice(&crypto::encode_usize(owner.len()));
+            data.extend_from_slice(owner.as_bytes());
+        }
+        None => data.push(0u8),
+    }
+    algorithm.hash(&data)
+}
+
+fn node_hash(left: &[u8], right: &[u8], algorithm: DigestAlgorithm) -> Vec<u8> {
+    let mut data = vec![1u8];
+    data.extend_from_slice(left);
+    data.extend_from_slice(right);
+    algorithm.hash(&data)
+}
+
+fn leaves(
+    store: &BTreeMap<Key, Value>,
+    key_owners: &BTreeMap<Key, ClientIdentity>,
+    algorithm: DigestAlgorithm,
+) -> Vec<Vec<u8>> {
+    store
+        .iter()
+        .map(|(key, value)| leaf_hash(key, value, key_owners.get(key), algorithm))
+        .collect()
+}
+
+/// Hashes each pair of nodes of a level, promoting the last node if it has no sibling
+fn next_level(level: &[Vec<u8>], algorithm: DigestAlgorithm) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right, algorithm),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Merkle root of the store, which is the state digest replicas agree on at checkpoints.
+/// Like other digests, the root is prefixed with the id of the algorithm
+pub fn root(
+    store: &BTreeMap<Key, Value>,
+    key_owners: &BTreeMap<Key, ClientIdentity>,
+    algorithm: DigestAlgorithm,
+) -> Vec<u8> {
+    root_of(leaves(store, key_owners, algorithm), algorithm)
+}
+
+fn root_of(mut level: Vec<Vec<u8>>, algorithm: DigestAlgorithm) -> Vec<u8> {
+    if level.is_empty() {
+        return algorithm.digest(&[]);
+    }
+    while level.len() > 1 {
+        level = next_level(&level, algorithm);
+    }
+    let mut root = vec![algorithm.id()];
+    root.extend(level.remove(0));
+    root
+}
+
+/// Leaf hashes of a store, kept from one root to the next so that only the entries written
+/// in between are hashed again. Writes have to be reported, as the entries are not compared
+#[derive(Debug, Default)]
+pub struct LeafCache {
+    /// Algorithm the cached leaves were hashed with
+    algorithm: Option<DigestAlgorithm>,
+    leaves: HashMap<Key, Vec<u8>>,
+}
+
+impl LeafCache {
+    /// The entries of the keys were written, so their leaves are hashed again
+    pub fn invalidate<'a>(&mut self, keys: impl IntoIterator<Item = &'a Key>) {
+        for key in keys {
+            self.leaves.remove(key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.leaves.clear();
+    }
+
+    /// Merkle root of the store, the same as `root` computes
+    pub fn root(
+        &mut self,
+        store: &BTreeMap<Key, Value>,
+        key_owners: &BTreeMap<Key, ClientIdentity>,
+        algorithm: DigestAlgorithm,
+    ) -> Vec<u8> {
+        if self.algorithm != Some(algorithm) {
+            self.leaves.clear();
+            self.algorithm = Some(algorithm);
+        }
+        let level = store
+            .iter()
+            .map(|(key, value)| match self.leaves.get(key) {
+                Some(leaf) => leaf.clone(),
+                None => {
+                    let leaf = leaf_hash(key, value, key_owners.get(key), algorithm);
+                    self.leaves.insert(key.clone(), leaf.clone());
+                    leaf
+                }
+            })
+            .collect();
+        root_of(level, algorithm)
+    }
+}
+
+/// Inclusion proof of the entry of the key, if the key is in the store
+pub fn prove(
+    store: &BTreeMap<Key, Value>,
+    key_owners: &BTreeMap<Key, ClientIdentity>,
+    key: &Key,
+    algorithm: DigestAlgorithm,
+) -> Option<MerkleProof> {
+    let index = store.keys().position(|e_key| e_key == key)?;
+    let mut level = leaves(store, key_owners, algorithm);
+    let num_leaves = level.len();
+
+    let mut siblings = Vec::new();
+    let mut curr_index = index;
+    while level.len() > 1 {
+        let sibling_index = curr_index ^ 1;
+        if sibling_index < level.len() {
+            siblings.push(level[sibling_index].clone());
+        }
+        level = next_level(&level, algorithm);
+        curr_index /= 2;
+    }
+
+    Some(MerkleProof {
+        index,
+        num_leaves,
+        siblings,
+    })
+}
+
+/// Proof of the inclusion of the leaves from `start` (inclusive) to `end` (exclusive),
+/// if they are in the store
+pub fn prove_range(
+    store: &BTreeMap<Key, Value>,
+    key_owners: &BTreeMap<Key, ClientIdentity>,
+    start: usize,
+    end: usize,
+    algorithm: DigestAlgorithm,
+) -> Option<RangeProof> {
+    let mut level = leaves(store, key_owners, algorithm);
+    let num_leaves = level.len();
+    if start >= end || end > num_leaves {
+        return None;
+    }
+
+    let mut siblings = Vec::new();
+    let (mut first, mut last) = (start, end - 1);
+    while level.len() > 1 {
+        if first % 2 == 1 {
+            siblings.push(level[first - 1].clone());
+        }
+        if last.is_multiple_of(2) && last + 1 < level.len() {
+            siblings.push(level[last + 1].clone());
+        }
+        level = next_level(&level, algorithm);
+        first /= 2;
+        last /= 2;
+    }
+
+    Some(RangeProof {
+        start,
+        num_leaves,
+        siblings,
+    })
+}
+
+/// Checks that the leaf hashes are the run of leaves starting at the position given by
+/// the proof in the tree with the root, using the algorithm the root was produced with
+pub fn verify_range(root: &[u8], leaves: Vec<Vec<u8>>, proof: &RangeProof) -> bool {
+    let algorithm = match crypto::algorithm_of(root) {
+        Some(algorithm) => algorithm,
+        None => return false,
+    };
+    if leaves.is_empty() || proof.start + leaves.len() > proof.num_leaves {
+        return false;
+    }
+    let mut siblings = proof.siblings.iter();
+    let mut level = leaves;
+    let mut first = proof.start;
+    let mut width = proof.num_leaves;
+    while width > 1 {
+        let last = first + level.len() - 1;
+        if first % 2 == 1 {
+            match siblings.next() {
+                Some(sibling) => level.insert(0, sibling.clone()),
+                None => return false,
+            }
+        }
+        if last.is_multiple_of(2) && last + 1 < width {
+            match siblings.next() {
+                Some(sibling) => level.push(sibling.clone()),
+                None => return false,
+            }
+        }
+        level = next_level(&level, algorithm);
+        first /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && level.len() == 1 && level[0] == root[1..]
+}
+
+/// Checks that the leaf hash is included at the position given by the proof
+/// in the tree with the root, using the algorithm the root was produced with
+pub fn verify(root: &[u8], leaf: Vec<u8>, proof: &MerkleProof) -> bool {
+    let algorithm = match crypto::algorithm_of(root) {
+        Some(algorithm) => algorithm,
+        None => return false,
+    };
+    if proof.index >= proof.num_leaves {
+        return false;
+    }
+    let mut siblings = proof.siblings.iter();
+    let mut curr = leaf;
+    let mut index = proof.index;
+    let mut width = proof.num_leaves;
+    while width > 1 {
+        if index % 2 == 1 {
+            match siblings.next() {
+                Some(sibling) => curr = node_hash(sibling, &curr, algorithm),
+                None => return false,
+            }
+        } else if index + 1 < width {
+            match siblings.next() {
+                Some(sibling) => curr = node_hash(&curr, sibling, algorithm),
+                None => return false,
+            }
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && curr == root[1..]
+}
+
+/// Verifies a key proof returned by a single replica, so a light client can trust the read
+/// without collecting f + 1 matching responses. The value is accepted if it is included in
+/// a state whose root is certified by the checkpoints of 2f + 1 distinct nodes
+pub fn verify_key_proof(
+    key_proof: &KeyProof,
+    pub_keys: &HashMap<NodeId, PublicKey>,
+    num_faulty: usize,
+) -> Result<Value, ProofError> {
+    let mut signers = HashSet::new();
+    for checkpoint in key_proof.certificate.iter() {
+        if checkpoint.committed_seq_num != key_proof.committed_seq_num
+            || checkpoint.state_digest != key_proof.state_digest
+        {
+            continue;
+        }
+        match pub_keys.get(&checkpoint.id) {
+            Some(pub_key) if checkpoint.is_properly_signed_by(pub_key) => {
+                signers.insert(checkpoint.id);
+            }
+            _ => {}
+        }
+    }
+    if signers.len() < 2 * num_faulty + 1 {
+        return Err(ProofError::InsufficientCertificate {
+            signers: signers.len(),
+        });
+    }
+
+    let (value, proof) = match (&key_proof.value, &key_proof.proof) {
+        (Some(value), Some(proof)) => (value, proof),
+        _ => return Err(ProofError::MissingProof),
+    };
+    let algorithm =
+        crypto::algorithm_of(&key_proof.state_digest).ok_or(ProofError::InvalidInclusion)?;
+    let leaf = leaf_hash(&key_proof.key, value, key_proof.owner.as_ref(), algorithm);
+    if !verify(&key_proof.state_digest, leaf, proof) {
+        return Err(ProofError::InvalidInclusion);
+    }
+    Ok(value.clone())
+}
+
+/// Reasons a key proof is not accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    /// The state root is not certified by 2f + 1 properly signed checkpoints
+    InsufficientCertificate { signers: usize },
+    /// The replica returned no inclusion proof, e.g. because the key is not in the store
+    MissingProof,
+    /// The inclusion proof does not lead to the certified root
+    InvalidInclusion,
+}
+
+impl std::fmt::Display for ProofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProofError::InsufficientCertificate { signers } => {
+                write!(f, "state root is only certified by {} checkpoints", signers)
+            }
+            ProofError::MissingProof => write!(f, "no inclusion proof for the key"),
+            ProofError::InvalidInclusion => {
+                write!(f, "inclusion proof does not match the certified root")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
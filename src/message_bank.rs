@@ -1,13 +1,17 @@
-use crate::messages::{CheckPoint, ClientRequest, Commit, PrePrepare, Prepare};
+use crate::messages::{CheckPoint, ClientRequest, Commit, FailureReason, PrePrepare, Prepare};
 
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct MessageBank {
-    /// (view, PrePrepare) requests which were sent either as the leader
+    /// (view, request digest) of requests which were sent either as the leader
     /// or were rebroadcast to the leader if this node is a replica
     /// This is used to make sure we do not broadcast the same request multiple times to the network
-    pub sent_requests: HashSet<(usize, ClientRequest)>,
+    pub sent_requests: HashSet<(usize, Vec<u8>)>,
+    /// Client requests we accepted pre-prepares for, indexed by digest.
+    /// Each request body is stored once here and shared by the log entries referencing it
+    pub request_bodies: HashMap<Vec<u8>, Arc<ClientRequest>>,
     /// Pre-prepare messages by (view, seq_num) that
     /// we have accepted but have not applied yet
     pub accepted_pre_prepare_requests: HashMap<(usize, usize), PrePrepare>,
@@ -20,13 +24,47 @@ pub struct MessageBank {
     /// Commits we accepted but did not apply the associated request yet
     pub accepted_commits_not_applied: HashMap<usize, Commit>,
     /// Maps a sequence number to the commit applied at a given sequence number
-    /// together with the associated client request
-    pub applied_commits: HashMap<usize, (Commit, ClientRequest)>,
+    /// together with the associated client request and the outcome of applying it
+    pub applied_commits: HashMap<usize, AppliedCommit>,
     /// Maps a (seq_num, state_digest) pair to checkpoints we saw for that pair
     pub checkpoint_messages: HashMap<(usize, Vec<u8>), CheckPoint>,
 }
 
+/// A commit applied to the store, with its client request
+#[derive(Debug, Clone)]
+pub struct AppliedCommit {
+    pub commit: Commit,
+    pub request: Arc<ClientRequest>,
+    /// Why the request was rejected, if it was, in which case it wrote nothing
+    pub rejected: Option<FailureReason>,
+}
+
 impl MessageBank {
+    /// Stores the body of a client request in the body table if it is not already there
+    pub fn store_request_body(&mut self, digest: &[u8], request: &ClientRequest) {
+        if !self.request_bodies.contains_key(digest) {
+            self.request_bodies
+                .insert(digest.to_vec(), Arc::new(request.clone()));
+        }
+    }
+
+    /// Logs the accepted pre-prepare, whose client request is then shared with the body
+    /// table rather than stored with every log entry
+    pub fn log_pre_prepare(&mut self, mut pre_prepare: PrePrepare) {
+        let body = self
+            .request_bodies
+            .entry(pre_prepare.client_request_digest.clone())
+            .or_insert_with(|| pre_prepare.client_request.clone());
+        pre_prepare.client_request = body.clone();
+        self.accepted_pre_prepare_requests
+            .insert((pre_prepare.view, pre_prepare.seq_num), pre_prepare);
+    }
+
+    /// Shared body of the client request with the given digest
+    pub fn request_body(&self, digest: &[u8]) -> Option<Arc<ClientRequest>> {
+        self.request_bodies.get(digest).cloned()
+    }
+
     /// Removes all state pertaining to messages with
     /// with sequence number < upper_seq_num
     pub fn garbage_collect(&mut self, upper_seq_num: usize) {
@@ -41,5 +79,18 @@ impl MessageBank {
             self.accepted_pre_prepare_requests
                 .remove(&(*view, *seq_num));
         }
+
+        self.applied_commits
+            .retain(|seq_num, _| *seq_num >= upper_seq_num);
+
+        // remove the request bodies which are no longer referenced by any log entry
+        let referenced_digests: HashSet<&Vec<u8>> = self
+            .accepted_pre_prepare_requests
+            .values()
+            .map(|pre_prepare| &pre_prepare.client_request_digest)
+            .collect();
+        self.request_bodies.retain(|digest, body| {
+            Arc::strong_count(body) > 1 || referenced_digests.contains(digest)
+        });
     }
 }
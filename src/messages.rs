@@ -1,15 +1,36 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
 
+use bytes::BytesMut;
 use serde::{Deserialize, Serialize};
-
-use crate::{Key, NodeId, Value};
-
-use ed25519_dalek::{Digest, Sha512};
-use ed25519_dalek::{Keypair, PublicKey, Signature};
+use tokio_util::codec::Encoder;
+
+use crate::authenticator::{Authenticator, AuthenticatorVector};
+use crate::codec::{MalformedStats, MessageCodec};
+use crate::connectivity::ConnectivityStats;
+use crate::crypto::{self, DigestAlgorithm, SigningInput};
+use crate::dead_letter::DeadLetter;
+use crate::evidence::{Evidence, EvidenceSummary, Exclusion};
+use crate::features::Capabilities;
+use crate::keystore::Keystore;
+use crate::limits;
+use crate::membership::Reconfiguration;
+use crate::mempool::MempoolStats;
+use crate::merkle::{MerkleProof, RangeProof};
+use crate::outbox::OutboxStats;
+use crate::pipeline::PipelineStats;
+use crate::pki::IdentityCertificate;
+use crate::registry;
+use crate::time::{ClockSample, ClockStats};
+use crate::verification::VerificationStats;
+use crate::{ClientId, Key, NodeId, Value};
+
+use ed25519_dalek::{Keypair, PublicKey};
 
 /// Messages which are communicated between nodes in the network
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum Message {
     IdentifierMessage(Identifier),
     PrePrepareMessage(PrePrepare),
@@ -19,18 +40,51 @@ pub enum Message {
     NewViewMessage(NewView),
     CheckPointMessage(CheckPoint),
     ClientRequestMessage(ClientRequest),
+    BulkLoadMessage(BulkLoadPart),
     ClientResponseMessage(ClientResponse),
+    RelayedClientResponseMessage(RelayedClientResponse),
+    StaleMessageNotice(StaleMessage),
+    FetchRequestBodyMessage(FetchRequestBody),
+    RequestBodyMessage(RequestBody),
+    GetProofMessage(GetProof),
+    KeyProofMessage(KeyProof),
+    GetEntryMessage(GetEntry),
+    LogEntryMessage(Box<LogEntry>),
+    GetAtMessage(GetAt),
+    ValueAtMessage(ValueAt),
+    StatusRequestMessage(StatusRequest),
+    StatusMessage(Box<NodeStatus>),
+    ProgressMessage(Progress),
+    WatchLeaderMessage(WatchLeader),
+    LeaderMessage(Leader),
+    CompactLogMessage(CompactLog),
+    WatchProgressMessage(WatchProgress),
+    CommitProgressMessage(CommitProgress),
+    DrainMessage(Drain),
+    GetDiffMessage(GetDiff),
+    CheckpointDiffMessage(CheckpointDiff),
+    StateChunkRequestMessage(StateChunkRequest),
+    StateChunkResponseMessage(StateChunkResponse),
+    GetRequestStatusMessage(GetRequestStatus),
+    RequestStatusMessage(RequestStatusReport),
+    EquivocationProofMessage(EquivocationProof),
+    BlameMessage(Blame),
+    GetEvidenceMessage(GetEvidence),
+    EvidenceMessage(EvidenceReport),
+    PingMessage(Ping),
+    PongMessage(Pong),
 }
 
 impl Message {
+    /// Encodes the message as a frame of the wire format (see `codec::MessageCodec`)
     pub fn serialize(&self) -> Vec<u8> {
-        let mut serialized_message = serde_json::to_string(self).unwrap();
-        serialized_message.push('\n');
-        serialized_message.into_bytes()
+        let mut frame = BytesMut::new();
+        MessageCodec::default().encode(self, &mut frame).unwrap();
+        frame.to_vec()
     }
 
     pub fn get_id(&self) -> Option<NodeId> {
-        match self.clone() {
+        match self {
             Message::IdentifierMessage(identifier) => Some(identifier.id),
             Message::PrePrepareMessage(pre_prepare) => Some(pre_prepare.id),
             Message::PrepareMessage(prepare) => Some(prepare.id),
@@ -39,36 +93,247 @@ impl Message {
             Message::CheckPointMessage(check_point) => Some(check_point.id),
             Message::ClientResponseMessage(client_response) => Some(client_response.id),
             Message::NewViewMessage(new_view) => Some(new_view.id),
-            Message::ClientRequestMessage(_) => {
-                // client request messages are not sent from nodes
-                // so they have no associated ids
+            Message::RelayedClientResponseMessage(relayed) => Some(relayed.response.id),
+            Message::StaleMessageNotice(stale_message) => Some(stale_message.id),
+            Message::FetchRequestBodyMessage(fetch) => Some(fetch.id),
+            Message::RequestBodyMessage(request_body) => Some(request_body.id),
+            Message::KeyProofMessage(key_proof) => Some(key_proof.id),
+            Message::LogEntryMessage(entry) => Some(entry.id),
+            Message::ValueAtMessage(value_at) => Some(value_at.id),
+            Message::StatusMessage(status) => Some(status.id),
+            Message::ProgressMessage(progress) => Some(progress.id),
+            Message::LeaderMessage(leader) => Some(leader.id),
+            Message::CommitProgressMessage(progress) => Some(progress.id),
+            Message::CheckpointDiffMessage(diff) => Some(diff.id),
+            Message::StateChunkRequestMessage(request) => Some(request.id),
+            Message::StateChunkResponseMessage(response) => Some(response.id),
+            Message::RequestStatusMessage(report) => Some(report.id),
+            Message::BlameMessage(blame) => Some(blame.id),
+            Message::EvidenceMessage(report) => Some(report.id),
+            Message::PingMessage(ping) => Some(ping.id),
+            Message::PongMessage(pong) => Some(pong.id),
+            Message::ClientRequestMessage(_)
+            | Message::BulkLoadMessage(_)
+            | Message::GetProofMessage(_)
+            | Message::GetEntryMessage(_)
+            | Message::GetAtMessage(_)
+            | Message::StatusRequestMessage(_)
+            | Message::WatchLeaderMessage(_)
+            | Message::CompactLogMessage(_)
+            | Message::WatchProgressMessage(_)
+            | Message::DrainMessage(_)
+            | Message::GetDiffMessage(_)
+            | Message::GetRequestStatusMessage(_)
+            | Message::GetEvidenceMessage(_)
+            | Message::EquivocationProofMessage(_) => {
+                // client request messages are not sent from nodes, and equivocation proofs
+                // are passed on by any replica, so they have no associated ids
                 None
             }
         }
     }
 
+    /// Epoch of the membership a protocol message belongs to. Other messages are not
+    /// tied to an epoch
+    pub fn epoch(&self) -> Option<usize> {
+        match self {
+            Message::PrePrepareMessage(pre_prepare) => Some(pre_prepare.epoch),
+            Message::PrepareMessage(prepare) => Some(prepare.epoch),
+            Message::CommitMessage(commit) => Some(commit.epoch),
+            Message::CheckPointMessage(checkpoint) => Some(checkpoint.epoch),
+            Message::ViewChangeMessage(view_change) => Some(view_change.epoch),
+            Message::NewViewMessage(new_view) => Some(new_view.epoch),
+            _ => None,
+        }
+    }
+
     /// Is this message propertly signed by the given public key
     pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
-        match self.clone() {
-            Message::IdentifierMessage(_) => {
-                unreachable!()
+        match self {
+            Message::IdentifierMessage(identifier) => {
+                identifier.verified_pub_key() == Some(*pub_key)
             }
             Message::PrePrepareMessage(pre_prepare) => pre_prepare.is_properly_signed_by(pub_key),
             Message::PrepareMessage(prepare) => prepare.is_properly_signed_by(pub_key),
             Message::CommitMessage(commit) => commit.is_properly_signed_by(pub_key),
             Message::CheckPointMessage(checkpoint) => checkpoint.is_properly_signed_by(pub_key),
             Message::ViewChangeMessage(view_change) => view_change.is_properly_signed_by(pub_key),
+            Message::NewViewMessage(new_view) => new_view.is_properly_signed_by(pub_key),
+            Message::StaleMessageNotice(stale_message) => {
+                stale_message.is_properly_signed_by(pub_key)
+            }
+            Message::ProgressMessage(progress) => progress.is_properly_signed_by(pub_key),
+            Message::CommitProgressMessage(progress) => progress.is_properly_signed_by(pub_key),
+            Message::RequestStatusMessage(report) => report.is_properly_signed_by(pub_key),
+            Message::BlameMessage(blame) => blame.is_properly_signed_by(pub_key),
+            Message::ClientResponseMessage(response) => response.is_properly_signed_by(pub_key),
+            Message::RelayedClientResponseMessage(relayed) => {
+                relayed.response.is_properly_signed_by(pub_key)
+            }
             _ => true,
         }
     }
+
+    /// Is this message authenticated by the replica with the given public key: signed by
+    /// it, or for prepares and commits, carrying its MAC for the replica checking it if
+    /// that replica authenticates votes with authenticators
+    pub fn is_authentic(&self, pub_key: &PublicKey, authenticator: Option<&Authenticator>) -> bool {
+        match self {
+            Message::PrepareMessage(prepare) => prepare.is_authentic(pub_key, authenticator),
+            Message::CommitMessage(commit) => commit.is_authentic(pub_key, authenticator),
+            _ => self.is_properly_signed_by(pub_key),
+        }
+    }
+
+    /// Name of the type of the message, used to account for messages by type
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::IdentifierMessage(_) => "Identifier",
+            Message::PrePrepareMessage(_) => "PrePrepare",
+            Message::PrepareMessage(_) => "Prepare",
+            Message::CommitMessage(_) => "Commit",
+            Message::ViewChangeMessage(_) => "ViewChange",
+            Message::NewViewMessage(_) => "NewView",
+            Message::CheckPointMessage(_) => "CheckPoint",
+            Message::ClientRequestMessage(_) => "ClientRequest",
+            Message::BulkLoadMessage(_) => "BulkLoad",
+            Message::ClientResponseMessage(_) => "ClientResponse",
+            Message::RelayedClientResponseMessage(_) => "RelayedClientResponse",
+            Message::StaleMessageNotice(_) => "StaleMessage",
+            Message::FetchRequestBodyMessage(_) => "FetchRequestBody",
+            Message::RequestBodyMessage(_) => "RequestBody",
+            Message::GetProofMessage(_) => "GetProof",
+            Message::KeyProofMessage(_) => "KeyProof",
+            Message::GetEntryMessage(_) => "GetEntry",
+            Message::LogEntryMessage(_) => "LogEntry",
+            Message::GetAtMessage(_) => "GetAt",
+            Message::ValueAtMessage(_) => "ValueAt",
+            Message::StatusRequestMessage(_) => "StatusRequest",
+            Message::StatusMessage(_) => "Status",
+            Message::ProgressMessage(_) => "Progress",
+            Message::WatchLeaderMessage(_) => "WatchLeader",
+            Message::LeaderMessage(_) => "Leader",
+            Message::CompactLogMessage(_) => "CompactLog",
+            Message::WatchProgressMessage(_) => "WatchProgress",
+            Message::CommitProgressMessage(_) => "CommitProgress",
+            Message::DrainMessage(_) => "Drain",
+            Message::GetDiffMessage(_) => "GetDiff",
+            Message::CheckpointDiffMessage(_) => "CheckpointDiff",
+            Message::StateChunkRequestMessage(_) => "StateChunkRequest",
+            Message::StateChunkResponseMessage(_) => "StateChunkResponse",
+            Message::GetRequestStatusMessage(_) => "GetRequestStatus",
+            Message::RequestStatusMessage(_) => "RequestStatus",
+            Message::EquivocationProofMessage(_) => "EquivocationProof",
+            Message::BlameMessage(_) => "Blame",
+            Message::GetEvidenceMessage(_) => "GetEvidence",
+            Message::EvidenceMessage(_) => "Evidence",
+            Message::PingMessage(_) => "Ping",
+            Message::PongMessage(_) => "Pong",
+        }
+    }
+
+    /// Does the message take part in ordering requests or moving to a view, which a
+    /// non-voting archive node withholds from its peers
+    pub fn is_vote(&self) -> bool {
+        matches!(
+            self,
+            Message::PrePrepareMessage(_)
+                | Message::PrepareMessage(_)
+                | Message::CommitMessage(_)
+                | Message::CheckPointMessage(_)
+                | Message::ViewChangeMessage(_)
+                | Message::NewViewMessage(_)
+        )
+    }
+
+    /// Can a replica pass the message on for the replica which signed it, as when it catches
+    /// up a peer. Other messages of replicas are only accepted from their sender
+    pub fn is_relayable(&self) -> bool {
+        matches!(
+            self,
+            Message::PrePrepareMessage(_)
+                | Message::PrepareMessage(_)
+                | Message::CommitMessage(_)
+                | Message::CheckPointMessage(_)
+        )
+    }
+
+    /// Sequence number the message refers to, if it is part of the normal case protocol
+    pub fn get_seq_num(&self) -> Option<usize> {
+        match self {
+            Message::PrePrepareMessage(pre_prepare) => Some(pre_prepare.seq_num),
+            Message::PrepareMessage(prepare) => Some(prepare.seq_num),
+            Message::CommitMessage(commit) => Some(commit.seq_num),
+            Message::CheckPointMessage(checkpoint) => Some(checkpoint.committed_seq_num),
+            _ => None,
+        }
+    }
 }
 
 // Messages
 
+/// Announcement of the key and address of a replica. The signature proves the replica holds
+/// the key, not that it sent this copy: the identifier is signed once, so anyone may replay
+/// it, and only encrypted channels (see `transport::SecureChannel`) authenticate a connection
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Identifier {
     pub id: NodeId,
     pub pub_key_vec: Vec<u8>,
+    /// Address on which the node can be reached by peers and clients
+    pub advertised_addr: SocketAddr,
+    /// Signature with the announced key, proving the node holds it
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+    /// Certificate of the announced key issued by the root key of the cluster, if any
+    #[serde(default)]
+    pub certificate: Option<IdentityCertificate>,
+    /// Reading of the wall clock of the node, in the identifiers it broadcasts periodically
+    #[serde(default)]
+    pub clock: Option<ClockSample>,
+}
+
+impl Identifier {
+    pub fn new_with_signature(
+        keystore: &Keystore,
+        id: NodeId,
+        advertised_addr: SocketAddr,
+    ) -> Self {
+        let pub_key_vec = keystore.public_key().as_bytes().to_vec();
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Identifier");
+        signing_input.update_usize(id);
+        signing_input.update(pub_key_vec.clone());
+        signing_input.update(advertised_addr.to_string());
+
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm);
+
+        Self {
+            id,
+            pub_key_vec,
+            advertised_addr,
+            signature,
+            certificate: None,
+            clock: None,
+        }
+    }
+
+    pub fn with_certificate(mut self, certificate: Option<IdentityCertificate>) -> Self {
+        self.certificate = certificate;
+        self
+    }
+
+    /// Public key announced by the identifier, if the identifier is signed with it
+    pub fn verified_pub_key(&self) -> Option<PublicKey> {
+        let pub_key = PublicKey::from_bytes(self.pub_key_vec.as_slice()).ok()?;
+
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Identifier");
+        signing_input.update_usize(self.id);
+        signing_input.update(self.pub_key_vec.clone());
+        signing_input.update(self.advertised_addr.to_string());
+
+        crypto::verify(&pub_key, &signing_input, &self.signature).then_some(pub_key)
+    }
 }
 
 // Note that the pre-prepare messages are the only messages which actually
@@ -76,54 +341,121 @@ pub struct Identifier {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct PrePrepare {
     pub id: NodeId,
+    /// Epoch of the membership the message belongs to (see `membership`)
+    #[serde(default)]
+    pub epoch: usize,
     pub view: usize,
     pub seq_num: usize,
     /// Hash of the associated client request
     pub client_request_digest: Vec<u8>,
+    #[serde(with = "limits::signature")]
     pub signature: Vec<u8>,
-    pub client_request: ClientRequest,
+    /// Shared with the body table of the message bank once the pre-prepare is logged
+    /// (see `State::log_pre_prepare`)
+    pub client_request: Arc<ClientRequest>,
 }
 
 impl PrePrepare {
     pub fn new_with_signature(
-        key_pair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: usize,
+        epoch: usize,
         view: usize,
         seq_num: usize,
         client_request: &ClientRequest,
     ) -> PrePrepare {
-        let key_pair = Keypair::from_bytes(key_pair_bytes.as_slice()).unwrap();
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"PrePrepare");
+        signing_input.update_usize(view);
+        signing_input.update_usize(seq_num);
+        signing_input.update(client_request.digest_at(seq_num).as_slice());
+        signing_input.update_epoch(epoch);
 
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"PrePrepare");
-        pre_hashed.update(view.to_le_bytes());
-        pre_hashed.update(seq_num.to_le_bytes());
-        pre_hashed.update(client_request.digest().as_slice());
-
-        let signature = key_pair.sign_prehashed(pre_hashed, None).unwrap();
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm_at(seq_num));
 
         PrePrepare {
             id,
+            epoch,
             view,
             seq_num,
-            client_request_digest: client_request.digest(),
-            signature: signature.to_bytes().to_vec(),
-            client_request: client_request.clone(),
+            client_request_digest: client_request.digest_at(seq_num),
+            signature,
+            client_request: Arc::new(client_request.clone()),
         }
     }
 
     pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"PrePrepare");
-        pre_hashed.update(self.view.to_le_bytes());
-        pre_hashed.update(self.seq_num.to_le_bytes());
-        pre_hashed.update(self.client_request.digest().as_slice());
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"PrePrepare");
+        signing_input.update_usize(self.view);
+        signing_input.update_usize(self.seq_num);
+        signing_input.update(self.client_request.digest_at(self.seq_num).as_slice());
+        signing_input.update_epoch(self.epoch);
+
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
+}
 
-        let signature = Signature::from_bytes(self.signature.as_slice()).unwrap();
+/// Two pre-prepares the primary signed for the same view and sequence number with different
+/// requests, which proves that the primary is faulty. Replicas pass it on to each other to
+/// change the view at once. This is not signed, as the pre-prepares carry the signatures
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EquivocationProof {
+    pub first: PrePrepare,
+    pub second: PrePrepare,
+}
+
+impl EquivocationProof {
+    pub fn view(&self) -> usize {
+        self.first.view
+    }
 
-        pub_key
-            .verify_prehashed(pre_hashed, None, &signature)
-            .is_ok()
+    /// Do the pre-prepares conflict, and were both signed by the given primary
+    pub fn is_valid(&self, primary: NodeId, pub_key: &PublicKey) -> bool {
+        let (first, second) = (&self.first, &self.second);
+        first.id == primary
+            && second.id == primary
+            && (first.view, first.seq_num) == (second.view, second.seq_num)
+            && first.client_request.digest_at(first.seq_num)
+                != second.client_request.digest_at(second.seq_num)
+            && first.is_properly_signed_by(pub_key)
+            && second.is_properly_signed_by(pub_key)
+    }
+}
+
+/// A replica vouches for evidence that another replica is faulty, so that every replica checks
+/// it, vouches for it in turn, and passes the faulty replica over as primary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blame {
+    /// Replica vouching for the evidence
+    pub id: NodeId,
+    pub evidence: Evidence,
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+}
+
+impl Blame {
+    pub fn new_with_signature(keystore: &Keystore, id: NodeId, evidence: Evidence) -> Self {
+        let signing_input = Self::signing_input(id, &evidence);
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm);
+        Self {
+            id,
+            evidence,
+            signature,
+        }
+    }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        let signing_input = Self::signing_input(self.id, &self.evidence);
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
+
+    fn signing_input(id: NodeId, evidence: &Evidence) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Blame");
+        signing_input.update_usize(id);
+        evidence.update_signing_input(&mut signing_input);
+        signing_input
     }
 }
 
@@ -132,52 +464,123 @@ impl PrePrepare {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Prepare {
     pub id: NodeId,
+    #[serde(default)]
+    pub epoch: usize,
     pub view: usize,
     pub seq_num: usize,
     /// Hash of the associated client request
     pub client_request_digest: Vec<u8>,
+    /// Empty if the prepare is authenticated by its authenticator instead
+    #[serde(with = "limits::signature")]
     pub signature: Vec<u8>,
+    /// MACs of the prepare for each replica, if the cluster authenticates votes with them
+    #[serde(default, with = "limits::authenticator")]
+    pub authenticator: AuthenticatorVector,
 }
 
 impl Prepare {
     pub fn new_with_signature(
-        key_pair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: usize,
+        epoch: usize,
         view: usize,
         seq_num: usize,
         client_request: &ClientRequest,
     ) -> Prepare {
-        let key_pair = Keypair::from_bytes(key_pair_bytes.as_slice()).unwrap();
+        Self::new_with_digest(
+            keystore,
+            id,
+            epoch,
+            view,
+            seq_num,
+            client_request.digest_at(seq_num),
+        )
+    }
 
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"Prepare");
-        pre_hashed.update(view.to_le_bytes());
-        pre_hashed.update(seq_num.to_le_bytes());
-        pre_hashed.update(client_request.digest().as_slice());
+    pub fn new_with_digest(
+        keystore: &Keystore,
+        id: usize,
+        epoch: usize,
+        view: usize,
+        seq_num: usize,
+        client_request_digest: Vec<u8>,
+    ) -> Prepare {
+        let signing_input = Self::signing_input(epoch, view, seq_num, &client_request_digest);
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm_at(seq_num));
 
-        let signature = key_pair.sign_prehashed(pre_hashed, None).unwrap();
+        Prepare {
+            id,
+            epoch,
+            view,
+            seq_num,
+            client_request_digest,
+            signature,
+            authenticator: AuthenticatorVector::new(),
+        }
+    }
 
+    /// Prepare carrying a MAC for each replica whose key is known instead of a signature
+    pub fn new_with_authenticator(
+        authenticator: &Authenticator,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+        id: usize,
+        epoch: usize,
+        view: usize,
+        seq_num: usize,
+        client_request_digest: Vec<u8>,
+    ) -> Prepare {
+        let signing_input = Self::signing_input(epoch, view, seq_num, &client_request_digest);
         Prepare {
             id,
+            epoch,
             view,
             seq_num,
-            client_request_digest: client_request.digest(),
-            signature: signature.to_bytes().to_vec(),
+            client_request_digest,
+            signature: Vec::new(),
+            authenticator: authenticator.authenticate(&signing_input, pub_keys),
         }
     }
 
     pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"Prepare");
-        pre_hashed.update(self.view.to_le_bytes());
-        pre_hashed.update(self.seq_num.to_le_bytes());
-        pre_hashed.update(self.client_request_digest.as_slice());
+        let signing_input = Self::signing_input(
+            self.epoch,
+            self.view,
+            self.seq_num,
+            &self.client_request_digest,
+        );
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
 
-        let signature = Signature::from_bytes(self.signature.as_slice()).unwrap();
+    /// Is this prepare signed by the public key of its sender, or if the replica checking
+    /// it authenticates votes with authenticators, does it carry a valid MAC for it
+    pub fn is_authentic(&self, pub_key: &PublicKey, authenticator: Option<&Authenticator>) -> bool {
+        match authenticator {
+            Some(authenticator) if self.signature.is_empty() => {
+                let signing_input = Self::signing_input(
+                    self.epoch,
+                    self.view,
+                    self.seq_num,
+                    &self.client_request_digest,
+                );
+                authenticator.verify(self.id, pub_key, &signing_input, &self.authenticator)
+            }
+            _ => self.is_properly_signed_by(pub_key),
+        }
+    }
 
-        pub_key
-            .verify_prehashed(pre_hashed, None, &signature)
-            .is_ok()
+    fn signing_input(
+        epoch: usize,
+        view: usize,
+        seq_num: usize,
+        client_request_digest: &[u8],
+    ) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Prepare");
+        signing_input.update_usize(view);
+        signing_input.update_usize(seq_num);
+        signing_input.update(client_request_digest);
+        signing_input.update_epoch(epoch);
+        signing_input
     }
 
     // does this prepare message correspond to the pre_prepare message
@@ -199,51 +602,104 @@ impl Prepare {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Commit {
     pub id: NodeId,
+    #[serde(default)]
+    pub epoch: usize,
     pub view: usize,
     pub seq_num: usize,
     pub client_request_digest: Vec<u8>,
+    /// Empty if the commit is authenticated by its authenticator instead
+    #[serde(with = "limits::signature")]
     pub signature: Vec<u8>,
+    /// MACs of the commit for each replica, if the cluster authenticates votes with them
+    #[serde(default, with = "limits::authenticator")]
+    pub authenticator: AuthenticatorVector,
 }
 
 impl Commit {
     pub fn new_with_signature(
-        key_pair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: usize,
+        epoch: usize,
         view: usize,
         seq_num: usize,
         client_request_digest: Vec<u8>,
     ) -> Commit {
-        let key_pair = Keypair::from_bytes(key_pair_bytes.as_slice()).unwrap();
-
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"Commit");
-        pre_hashed.update(view.to_le_bytes());
-        pre_hashed.update(seq_num.to_le_bytes());
-        pre_hashed.update(client_request_digest.as_slice());
+        let signing_input = Self::signing_input(epoch, view, seq_num, &client_request_digest);
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm_at(seq_num));
 
-        let signature = key_pair.sign_prehashed(pre_hashed, None).unwrap();
+        Commit {
+            id,
+            epoch,
+            view,
+            seq_num,
+            client_request_digest,
+            signature,
+            authenticator: AuthenticatorVector::new(),
+        }
+    }
 
+    /// Commit carrying a MAC for each replica whose key is known instead of a signature
+    pub fn new_with_authenticator(
+        authenticator: &Authenticator,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+        id: usize,
+        epoch: usize,
+        view: usize,
+        seq_num: usize,
+        client_request_digest: Vec<u8>,
+    ) -> Commit {
+        let signing_input = Self::signing_input(epoch, view, seq_num, &client_request_digest);
         Commit {
             id,
+            epoch,
             view,
             seq_num,
             client_request_digest,
-            signature: signature.to_bytes().to_vec(),
+            signature: Vec::new(),
+            authenticator: authenticator.authenticate(&signing_input, pub_keys),
         }
     }
 
     pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"Commit");
-        pre_hashed.update(self.view.to_le_bytes());
-        pre_hashed.update(self.seq_num.to_le_bytes());
-        pre_hashed.update(self.client_request_digest.as_slice());
+        let signing_input = Self::signing_input(
+            self.epoch,
+            self.view,
+            self.seq_num,
+            &self.client_request_digest,
+        );
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
 
-        let signature = Signature::from_bytes(self.signature.as_slice()).unwrap();
+    /// Is this commit signed by the public key of its sender, or if the replica checking
+    /// it authenticates votes with authenticators, does it carry a valid MAC for it
+    pub fn is_authentic(&self, pub_key: &PublicKey, authenticator: Option<&Authenticator>) -> bool {
+        match authenticator {
+            Some(authenticator) if self.signature.is_empty() => {
+                let signing_input = Self::signing_input(
+                    self.epoch,
+                    self.view,
+                    self.seq_num,
+                    &self.client_request_digest,
+                );
+                authenticator.verify(self.id, pub_key, &signing_input, &self.authenticator)
+            }
+            _ => self.is_properly_signed_by(pub_key),
+        }
+    }
 
-        pub_key
-            .verify_prehashed(pre_hashed, None, &signature)
-            .is_ok()
+    fn signing_input(
+        epoch: usize,
+        view: usize,
+        seq_num: usize,
+        client_request_digest: &[u8],
+    ) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Commit");
+        signing_input.update_usize(view);
+        signing_input.update_usize(seq_num);
+        signing_input.update(client_request_digest);
+        signing_input.update_epoch(epoch);
+        signing_input
     }
 
     /// Does this commit message correspond to the prepare message
@@ -264,130 +720,366 @@ impl Commit {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CheckPoint {
     pub id: NodeId,
+    #[serde(default)]
+    pub epoch: usize,
     pub committed_seq_num: usize,
     pub view: usize,
+    /// Merkle root of the state, whose entries lagging replicas fetch in chunks
+    /// (see `StateChunkRequest`)
     pub state_digest: Vec<u8>,
-    pub state: BTreeMap<Key, Value>,
+    #[serde(with = "limits::signature")]
     pub signature: Vec<u8>,
 }
 
 impl CheckPoint {
     pub fn new_with_signature(
-        key_pair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: usize,
+        epoch: usize,
         committed_seq_num: usize,
         view: usize,
         state_digest: Vec<u8>,
-        state: BTreeMap<Key, Value>,
     ) -> Self {
-        let key_pair = Keypair::from_bytes(key_pair_bytes.as_slice()).unwrap();
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"Checkpoint");
-        pre_hashed.update(committed_seq_num.to_le_bytes());
-        pre_hashed.update(state_digest.clone());
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Checkpoint");
+        signing_input.update_usize(committed_seq_num);
+        signing_input.update(state_digest.clone());
+        signing_input.update_epoch(epoch);
 
-        let signature = key_pair.sign_prehashed(pre_hashed, None).unwrap();
+        let signature = keystore.sign(
+            &signing_input,
+            crypto::policy().algorithm_at(committed_seq_num),
+        );
 
         Self {
             id,
+            epoch,
             committed_seq_num,
             view,
             state_digest,
-            state,
-            signature: signature.to_bytes().to_vec(),
+            signature,
         }
     }
 
     pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"Checkpoint");
-        pre_hashed.update(self.committed_seq_num.to_le_bytes());
-        pre_hashed.update(self.state_digest.clone());
-
-        let signature = Signature::from_bytes(self.signature.as_slice()).unwrap();
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Checkpoint");
+        signing_input.update_usize(self.committed_seq_num);
+        signing_input.update(self.state_digest.clone());
+        signing_input.update_epoch(self.epoch);
 
-        pub_key
-            .verify_prehashed(pre_hashed, None, &signature)
-            .is_ok()
+        crypto::verify(pub_key, &signing_input, &self.signature)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ViewChange {
     pub id: NodeId,
+    #[serde(default)]
+    pub epoch: usize,
     pub new_view: usize,
     pub last_stable_seq_num: usize,
+    #[serde(with = "limits::checkpoint_proof")]
     pub checkpoint_proof: Vec<CheckPoint>,
-    pub subsequent_prepares: HashMap<usize, (PrePrepare, Vec<Prepare>)>,
+    #[serde(with = "limits::subsequent_prepares")]
+    pub subsequent_prepares: BTreeMap<usize, (PrePrepare, Vec<Prepare>)>,
+    /// Prepared requests in compact form, which refer to the client request by its digest.
+    /// These take the place of subsequent_prepares in compact view changes
+    #[serde(default, with = "limits::subsequent_prepares")]
+    pub prepared_certificates: BTreeMap<usize, PreparedCertificate>,
+    #[serde(with = "limits::signature")]
     pub signature: Vec<u8>,
 }
 
 impl ViewChange {
     pub fn new_with_signature(
-        key_pair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: NodeId,
+        epoch: usize,
         new_view: usize,
         last_stable_seq_num: usize,
         checkpoint_proof: Vec<CheckPoint>,
-        subsequent_prepares: HashMap<usize, (PrePrepare, Vec<Prepare>)>,
+        subsequent_prepares: BTreeMap<usize, (PrePrepare, Vec<Prepare>)>,
     ) -> ViewChange {
-        let key_pair = Keypair::from_bytes(key_pair_bytes.as_slice()).unwrap();
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"ViewChange");
-        pre_hashed.update(new_view.to_le_bytes());
-        pre_hashed.update(last_stable_seq_num.to_le_bytes());
-        let signature = key_pair.sign_prehashed(pre_hashed, None).unwrap();
-
-        ViewChange {
+        let mut view_change = ViewChange {
             id,
+            epoch,
             new_view,
             last_stable_seq_num,
             checkpoint_proof,
             subsequent_prepares,
-            signature: signature.to_bytes().to_vec(),
+            prepared_certificates: BTreeMap::new(),
+            signature: Vec::new(),
+        };
+        view_change.signature = keystore.sign(
+            &view_change.signing_input(),
+            crypto::policy().algorithm_at(last_stable_seq_num),
+        );
+        view_change
+    }
+
+    /// Covers the checkpoint the view change starts from and the view, sequence number
+    /// and request digest of every prepared request, which are the same whether or not
+    /// the view change is compacted
+    fn signing_input(&self) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"ViewChange");
+        signing_input.update_usize(self.new_view);
+        signing_input.update_usize(self.last_stable_seq_num);
+        for checkpoint in self.checkpoint_proof.iter() {
+            signing_input.update_usize(checkpoint.id);
+            signing_input.update_usize(checkpoint.committed_seq_num);
+            signing_input.update(checkpoint.state_digest.clone());
+        }
+        let mut prepared = BTreeMap::new();
+        for (seq_num, (pre_prepare, _)) in self.subsequent_prepares.iter() {
+            prepared.insert(
+                *seq_num,
+                (pre_prepare.view, &pre_prepare.client_request_digest),
+            );
+        }
+        for (seq_num, certificate) in self.prepared_certificates.iter() {
+            prepared.insert(
+                *seq_num,
+                (certificate.view, &certificate.client_request_digest),
+            );
         }
+        for (seq_num, (view, digest)) in prepared {
+            signing_input.update_usize(seq_num);
+            signing_input.update_usize(view);
+            signing_input.update(digest.clone());
+        }
+        signing_input.update_epoch(self.epoch);
+        signing_input
+    }
+
+    /// Replaces the full pre-prepares and prepares of the view change by prepared certificates,
+    /// which carry the digest of the client request and exactly 2f prepares.
+    /// The new primary fetches any client request it does not have on demand
+    pub fn compacted(mut self, num_faulty: usize) -> ViewChange {
+        for (seq_num, (pre_prepare, mut prepares)) in std::mem::take(&mut self.subsequent_prepares)
+        {
+            // the pre-prepare counts as the prepare of the primary
+            prepares.retain(|prepare| prepare.id != pre_prepare.id);
+            prepares.sort_by_key(|prepare| prepare.id);
+            prepares.truncate(2 * num_faulty);
+            self.prepared_certificates.insert(
+                seq_num,
+                PreparedCertificate {
+                    epoch: pre_prepare.epoch,
+                    view: pre_prepare.view,
+                    seq_num,
+                    primary_id: pre_prepare.id,
+                    client_request_digest: pre_prepare.client_request_digest,
+                    pre_prepare_signature: pre_prepare.signature,
+                    prepares,
+                },
+            );
+        }
+        self
     }
 
     pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"ViewChange");
-        pre_hashed.update(self.new_view.to_le_bytes());
-        pre_hashed.update(self.last_stable_seq_num.to_le_bytes());
+        crypto::verify(pub_key, &self.signing_input(), &self.signature)
+    }
+
+    /// Are the checkpoint proof and the pre-prepares and prepares of the prepared requests
+    /// signed by the replicas they claim to come from, so that a faulty replica cannot
+    /// forge the votes of others into its view change
+    pub fn are_votes_properly_signed(&self, pub_keys: &HashMap<NodeId, PublicKey>) -> bool {
+        self.are_votes_authentic(pub_keys, None)
+    }
 
-        let signature = Signature::from_bytes(self.signature.as_slice()).unwrap();
+    /// Is every request the view change claims prepared backed by a pre-prepare of the
+    /// primary of its view and by prepares of 2f other replicas for the same view, sequence
+    /// number and request, given the primary of each view. Signatures are checked separately
+    /// (see `are_votes_authentic`)
+    pub fn are_prepared_requests_valid(
+        &self,
+        num_faulty: usize,
+        primary_of: impl Fn(usize) -> NodeId,
+    ) -> bool {
+        self.subsequent_prepares
+            .iter()
+            .all(|(seq_num, (pre_prepare, prepares))| {
+                pre_prepare.seq_num == *seq_num
+                    && pre_prepare.id == primary_of(pre_prepare.view)
+                    && is_prepare_quorum(
+                        prepares,
+                        num_faulty,
+                        pre_prepare.id,
+                        pre_prepare.view,
+                        *seq_num,
+                        &pre_prepare.client_request_digest,
+                    )
+            })
+            && self
+                .prepared_certificates
+                .iter()
+                .all(|(seq_num, certificate)| {
+                    certificate.seq_num == *seq_num
+                        && certificate.primary_id == primary_of(certificate.view)
+                        && certificate.is_quorum(num_faulty)
+                })
+    }
+
+    /// Like `are_votes_properly_signed`, but prepares may carry an authenticator instead of
+    /// a signature, checked against the MAC of each prepare meant for the replica checking it
+    pub fn are_votes_authentic(
+        &self,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+        authenticator: Option<&Authenticator>,
+    ) -> bool {
+        let signed_by =
+            |id: NodeId, verify: &dyn Fn(&PublicKey) -> bool| pub_keys.get(&id).is_some_and(verify);
+        let prepares_signed = |prepares: &[Prepare]| {
+            prepares.iter().all(|prepare| {
+                signed_by(prepare.id, &|pub_key| {
+                    prepare.is_authentic(pub_key, authenticator)
+                })
+            })
+        };
+
+        self.checkpoint_proof.iter().all(|checkpoint| {
+            signed_by(checkpoint.id, &|pub_key| {
+                checkpoint.is_properly_signed_by(pub_key)
+            })
+        }) && self
+            .subsequent_prepares
+            .values()
+            .all(|(pre_prepare, prepares)| {
+                signed_by(pre_prepare.id, &|pub_key| {
+                    pre_prepare.is_properly_signed_by(pub_key)
+                }) && prepares_signed(prepares)
+            })
+            && self.prepared_certificates.values().all(|certificate| {
+                signed_by(certificate.primary_id, &|pub_key| {
+                    certificate.is_pre_prepare_signed_by(pub_key)
+                }) && prepares_signed(&certificate.prepares)
+            })
+    }
+}
+
+/// Proof that a request was prepared, referring to the client request by its digest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PreparedCertificate {
+    #[serde(default)]
+    pub epoch: usize,
+    pub view: usize,
+    pub seq_num: usize,
+    /// Id of the primary which sent the pre-prepare
+    pub primary_id: NodeId,
+    pub client_request_digest: Vec<u8>,
+    /// Signature of the primary over the pre-prepare
+    #[serde(with = "limits::signature")]
+    pub pre_prepare_signature: Vec<u8>,
+    pub prepares: Vec<Prepare>,
+}
+
+impl PreparedCertificate {
+    /// Does the certificate carry prepares of 2f replicas other than the primary for its
+    /// view, sequence number and request
+    pub fn is_quorum(&self, num_faulty: usize) -> bool {
+        is_prepare_quorum(
+            &self.prepares,
+            num_faulty,
+            self.primary_id,
+            self.view,
+            self.seq_num,
+            &self.client_request_digest,
+        )
+    }
 
-        pub_key
-            .verify_prehashed(pre_hashed, None, &signature)
-            .is_ok()
+    /// Checks the signature of the pre-prepare the certificate was built from,
+    /// which covers the same fields as the certificate
+    pub fn is_pre_prepare_signed_by(&self, pub_key: &PublicKey) -> bool {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"PrePrepare");
+        signing_input.update_usize(self.view);
+        signing_input.update_usize(self.seq_num);
+        signing_input.update(self.client_request_digest.as_slice());
+        signing_input.update_epoch(self.epoch);
+
+        crypto::verify(pub_key, &signing_input, &self.pre_prepare_signature)
     }
 }
 
+/// Are there prepares of 2f distinct replicas other than the primary among the prepares,
+/// all for the view, sequence number and request
+fn is_prepare_quorum(
+    prepares: &[Prepare],
+    num_faulty: usize,
+    primary_id: NodeId,
+    view: usize,
+    seq_num: usize,
+    digest: &[u8],
+) -> bool {
+    let matching = prepares.iter().all(|prepare| {
+        prepare.view == view
+            && prepare.seq_num == seq_num
+            && prepare.client_request_digest == digest
+    });
+    let voters: HashSet<NodeId> = prepares
+        .iter()
+        .map(|prepare| prepare.id)
+        .filter(|id| *id != primary_id)
+        .collect();
+    matching && voters.len() >= 2 * num_faulty
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewView {
     pub id: NodeId,
+    #[serde(default)]
+    pub epoch: usize,
     pub view: usize,
     pub view_change_messages: Vec<ViewChange>,
     pub outstanding_pre_prepares: Vec<PrePrepare>,
+    #[serde(default, with = "limits::signature")]
+    pub signature: Vec<u8>,
 }
 
 impl NewView {
     pub fn new_with_signature(
-        _keypair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: usize,
+        epoch: usize,
         view: usize,
         view_change_messages: Vec<ViewChange>,
         outstanding_pre_prepares: Vec<PrePrepare>,
     ) -> Self {
-        Self {
+        let mut new_view = Self {
             id,
+            epoch,
             view,
             view_change_messages,
             outstanding_pre_prepares,
+            signature: Vec::new(),
+        };
+        new_view.signature = keystore.sign(&new_view.signing_input(), crypto::policy().algorithm);
+        new_view
+    }
+
+    /// Covers the view, the view changes the new view is based on and
+    /// the requests the new primary re-proposes
+    fn signing_input(&self) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"NewView");
+        signing_input.update_usize(self.view);
+        for view_change in self.view_change_messages.iter() {
+            signing_input.update_usize(view_change.id);
+            signing_input.update(view_change.signature.clone());
         }
+        for pre_prepare in self.outstanding_pre_prepares.iter() {
+            signing_input.update_usize(pre_prepare.seq_num);
+            signing_input.update(pre_prepare.client_request_digest.clone());
+        }
+        signing_input.update_epoch(self.epoch);
+        signing_input
     }
 
-    pub fn is_properly_signed_by(&self, _pub_key: &PublicKey) -> bool {
-        true
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        crypto::verify(pub_key, &self.signing_input(), &self.signature)
     }
 }
 
@@ -398,34 +1090,313 @@ pub struct ClientRequest {
     pub respond_addr: SocketAddr,
     pub time_stamp: usize,
     pub key: Key,
-    pub value: Option<Value>,
+    pub operation: Operation,
+    /// Replica which the client submitted this request through.
+    /// If set, responses are routed back to the client via this replica
+    #[serde(default)]
+    pub relay_id: Option<NodeId>,
+    /// Whether the replicas may answer a get from their committed state without ordering it.
+    /// Other operations are ordered whether or not they are marked read-only
+    #[serde(default)]
+    pub read_only: bool,
+    /// Operations applied atomically in place of the key and value, if not empty
+    #[serde(default, with = "limits::batch_ops")]
+    pub batch: Vec<BatchOp>,
+    /// Where the batch lies in a bulk load, if the primary chopped it from one (see `bulk`)
+    #[serde(default)]
+    pub bulk: Option<BulkBatch>,
+    /// Identity of the client which signed the request, if it registered one. The replicas
+    /// then know the client by its id rather than by its address, which may change.
+    /// Both fields are left out when unset, so that other requests encode as before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<ClientId>,
+    /// Signature of the request by the key the client registered (see `signing_input`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signature: Vec<u8>,
+}
+
+/// What the replicas know a client by, to execute its requests once and in order and to
+/// charge it for the keys it creates: the id it registered, or else the address it listens on.
+/// It is serialized as the bare address or id, so that the owners of keys recorded as
+/// addresses before clients had ids are read back unchanged
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum ClientIdentity {
+    Addr(SocketAddr),
+    Id(ClientId),
+}
+
+impl std::fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientIdentity::Addr(addr) => write!(f, "{}", addr),
+            ClientIdentity::Id(client_id) => write!(f, "client {}", client_id),
+        }
+    }
+}
+
+/// What a client request does to its key
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Reads the value of the key
+    #[default]
+    Get,
+    Set(Value),
+    Delete,
+    /// Sets the key to the new value if it has the expected value,
+    /// where None expects the key to be unset
+    Cas {
+        expected: Option<Value>,
+        new: Value,
+    },
+}
+
+/// A write within a batched client request
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum BatchOp {
+    Put { key: Key, value: Value },
+    Delete { key: Key },
+}
+
+impl BatchOp {
+    pub fn key(&self) -> &Key {
+        match self {
+            BatchOp::Put { key, .. } => key,
+            BatchOp::Delete { key } => key,
+        }
+    }
+
+    /// Bytes of the key and value written
+    pub fn size(&self) -> usize {
+        match self {
+            BatchOp::Put { key, value } => key.len() + value.len(),
+            BatchOp::Delete { key } => key.len(),
+        }
+    }
+}
+
+/// Batch of a bulk load, by the position of its first operation in the load
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct BulkBatch {
+    pub offset: usize,
+    /// Whether the batch ends the load, so that the replicas answer it with the summary
+    pub last: bool,
+}
+
+/// Operations of a bulk load, streamed by the client to the primary in parts. The parts of
+/// a load share its timestamp, and the primary reassembles them by offset
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BulkLoadPart {
+    pub respond_addr: SocketAddr,
+    pub time_stamp: usize,
+    /// Position of the first operation of the part in the load
+    pub offset: usize,
+    #[serde(with = "limits::batch_ops")]
+    pub ops: Vec<BatchOp>,
+    /// Whether the part ends the load
+    pub last: bool,
+}
+
+/// What a bulk load amounted to, which the replicas answer its last batch with
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct BulkLoadSummary {
+    /// Operations applied, from the start of the load without a gap
+    pub ops: usize,
+    /// Batches the operations were ordered in
+    pub batches: usize,
+    /// Sequence number of the first batch
+    pub first_seq_num: usize,
 }
 
 impl ClientRequest {
     /// Hash of a Client Requyest used for a compressed version
     /// of the request in future messages
+    /// Digest of the request with the algorithm of the digest policy,
+    /// used to identify the request within this node
     pub fn digest(&self) -> Vec<u8> {
-        let mut hasher = Sha512::new();
-        hasher.update(self.respond_addr.to_string().as_bytes());
-        hasher.update(self.time_stamp.to_le_bytes());
-        hasher.update(self.key.as_bytes());
-        if let Some(value) = self.value {
-            hasher.update(value.to_le_bytes());
+        self.digest_with(crypto::policy().algorithm)
+    }
+
+    /// Digest of the request when it is assigned the sequence number,
+    /// which every replica computes with the same algorithm
+    pub fn digest_at(&self, seq_num: usize) -> Vec<u8> {
+        self.digest_with(crypto::policy().algorithm_at(seq_num))
+    }
+
+    pub fn digest_with(&self, algorithm: DigestAlgorithm) -> Vec<u8> {
+        // every field is length-prefixed or tagged, so that distinct requests never encode
+        // to the same bytes, and numbers are encoded independently of the platform
+        let mut data = Vec::new();
+        encode_bytes(&mut data, self.respond_addr.to_string().as_bytes());
+        data.extend_from_slice(&crypto::encode_usize(self.time_stamp));
+        encode_bytes(&mut data, self.key.as_bytes());
+        match &self.operation {
+            Operation::Get => data.push(0u8),
+            Operation::Set(value) => {
+                data.push(1u8);
+                encode_bytes(&mut data, value.as_bytes());
+            }
+            Operation::Delete => data.push(2u8),
+            Operation::Cas { expected, new } => {
+                data.push(3u8);
+                match expected {
+                    Some(expected) => {
+                        data.push(1u8);
+                        encode_bytes(&mut data, expected.as_bytes());
+                    }
+                    None => data.push(0u8),
+                }
+                encode_bytes(&mut data, new.as_bytes());
+            }
+        }
+        // the relay decides where every replica sends its response, so replicas agreeing on
+        // the digest must agree on it too. Otherwise a faulty primary could point the
+        // responses at another replica without changing the request it proposes
+        match self.relay_id {
+            Some(relay_id) => {
+                data.push(1u8);
+                data.extend_from_slice(&crypto::encode_usize(relay_id));
+            }
+            None => data.push(0u8),
+        }
+        data.extend_from_slice(&crypto::encode_usize(self.batch.len()));
+        for op in self.batch.iter() {
+            match op {
+                BatchOp::Put { key, value } => {
+                    data.push(0u8);
+                    encode_bytes(&mut data, key.as_bytes());
+                    encode_bytes(&mut data, value.as_bytes());
+                }
+                BatchOp::Delete { key } => {
+                    data.push(1u8);
+                    encode_bytes(&mut data, key.as_bytes());
+                }
+            }
+        }
+        // appended only if set, so that the digests of other requests are unchanged
+        if self.read_only {
+            data.push(1u8);
         }
-        if self.value.is_some() {
-            hasher.update(self.value.unwrap().to_le_bytes());
+        if let Some(bulk) = self.bulk {
+            data.push(2u8);
+            data.extend_from_slice(&crypto::encode_usize(bulk.offset));
+            data.push(bulk.last as u8);
+        }
+        // the signature is covered as well, so that a primary cannot have replicas with the
+        // same digest execute the request as signed and as not
+        if let Some(client_id) = self.client_id {
+            data.push(3u8);
+            data.extend_from_slice(&client_id.to_le_bytes());
+            encode_bytes(&mut data, &self.signature);
+        }
+        algorithm.digest(&data)
+    }
+
+    pub fn identity(&self) -> ClientIdentity {
+        match self.client_id {
+            Some(client_id) => ClientIdentity::Id(client_id),
+            None => ClientIdentity::Addr(self.respond_addr),
+        }
+    }
+
+    /// Signs the request as the client with the id, by the key the client registered
+    pub fn signed_by(mut self, client_id: ClientId, keypair: &Keypair) -> Self {
+        self.client_id = Some(client_id);
+        self.signature = crypto::sign(keypair, &self.signing_input(), crypto::policy().algorithm);
+        self
+    }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        crypto::verify(pub_key, &self.signing_input(), &self.signature)
+    }
+
+    /// What the client signs: the request without the address it listens on and the relay
+    /// it goes through, so that the signature holds wherever the request is sent from
+    fn signing_input(&self) -> SigningInput {
+        let unaddressed = ClientRequest {
+            respond_addr: ClientRequest::no_op().respond_addr,
+            relay_id: None,
+            client_id: None,
+            signature: Vec::new(),
+            ..self.clone()
+        };
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"ClientRequest");
+        signing_input.update(self.client_id.unwrap_or_default().to_le_bytes());
+        signing_input.update(unaddressed.digest_with(DigestAlgorithm::Sha256));
+        signing_input
+    }
+
+    /// Whether the request is a get which replicas answer without ordering it
+    pub fn is_read_only(&self) -> bool {
+        self.read_only && self.operation == Operation::Get && self.batch.is_empty()
+    }
+
+    /// Whether the request submits a reconfiguration of the membership of the cluster
+    pub fn is_reconfiguration(&self) -> bool {
+        match &self.operation {
+            Operation::Set(value) => {
+                self.key == registry::registry_key() && Reconfiguration::decode(value).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Exclusion of a replica proven faulty the request orders, if it writes one
+    pub fn exclusion(&self) -> Option<Exclusion> {
+        match &self.operation {
+            Operation::Set(value) if registry::accused_of(&self.key).is_some() => {
+                Exclusion::decode(value)
+            }
+            _ => None,
         }
-        let result: &[u8] = &hasher.finalize();
-        result.to_vec()
+    }
+
+    /// Request the primary makes to order the exclusion, which no client waits on
+    pub fn for_exclusion(exclusion: &Exclusion) -> Self {
+        ClientRequest {
+            key: registry::evidence_key(exclusion.evidence.accused()),
+            operation: Operation::Set(exclusion.encode()),
+            ..Self::no_op()
+        }
+    }
+
+    /// Whether a client made the request, rather than a replica (no-ops and exclusions),
+    /// so that there is someone to answer
+    pub fn has_client(&self) -> bool {
+        !self.respond_addr.ip().is_unspecified()
     }
 
     pub fn no_op() -> Self {
         ClientRequest {
             respond_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
             time_stamp: 0,
-            key: String::from(""),
-            value: None,
+            key: Key::default(),
+            operation: Operation::Get,
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        }
+    }
+}
+
+/// Appends the bytes prefixed with their length
+fn encode_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&crypto::encode_usize(bytes.len()));
+    data.extend_from_slice(bytes);
+}
+
+/// Appends the value tagged with whether it is set
+fn encode_option(data: &mut Vec<u8>, value: &Option<Value>) {
+    match value {
+        Some(value) => {
+            data.push(1u8);
+            encode_bytes(data, value.as_bytes());
         }
+        None => data.push(0u8),
     }
 }
 
@@ -437,42 +1408,824 @@ pub struct ClientResponse {
     pub key: Key,
     pub value: Option<Value>,
     pub success: bool,
+    /// Why the request was rejected, if it was not a success
+    #[serde(default)]
+    pub reason: Option<FailureReason>,
+    /// Code of the reason, for applications which do not know every reason
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+    /// Value the key had before a delete or compare-and-swap, whether or not it swapped
+    #[serde(default)]
+    pub previous: Option<Value>,
+    /// For a batched request, the previous value of the key of each operation
+    #[serde(default)]
+    pub results: Vec<Option<Value>>,
+    /// For the last batch of a bulk load, what the load amounted to
+    #[serde(default)]
+    pub bulk: Option<BulkLoadSummary>,
+    /// View of the replica when it responded, which tells the client the current primary
+    #[serde(default)]
+    pub view: usize,
+    /// Sequence number the request was committed at, which is the version of the keys
+    /// it wrote. This is 0 for read-only requests, which are not ordered
+    #[serde(default)]
+    pub seq_num: usize,
+    /// Protocol version and optional features of the replica, which clients negotiate on
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    #[serde(with = "limits::signature")]
     pub signature: Vec<u8>,
 }
 
 impl ClientResponse {
     pub fn new_with_signature(
-        key_pair_bytes: Vec<u8>,
+        keystore: &Keystore,
         id: NodeId,
         time_stamp: usize,
         key: Key,
         value: Option<Value>,
-        success: bool,
+        results: Vec<Option<Value>>,
+        reason: Option<FailureReason>,
     ) -> ClientResponse {
-        let key_pair = Keypair::from_bytes(key_pair_bytes.as_slice()).unwrap();
-        let mut pre_hashed = Sha512::new();
-        pre_hashed.update(b"ViewChange");
-        pre_hashed.update(time_stamp.to_le_bytes());
-        pre_hashed.update(key.as_bytes());
-        let signature = key_pair.sign_prehashed(pre_hashed, None).unwrap();
-
         ClientResponse {
             id,
             time_stamp,
             key,
             value,
-            success,
-            signature: signature.to_bytes().to_vec(),
+            success: reason.is_none(),
+            reason,
+            code: reason.map(|reason| reason.code()),
+            previous: None,
+            results,
+            bulk: None,
+            view: 0,
+            seq_num: 0,
+            capabilities: Capabilities::default(),
+            signature: Vec::new(),
+        }
+        .signed(keystore)
+    }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        crypto::verify(pub_key, &self.signing_input(), &self.signature)
+    }
+
+    fn signed(mut self, keystore: &Keystore) -> Self {
+        self.signature = keystore.sign(&self.signing_input(), crypto::policy().algorithm);
+        self
+    }
+
+    /// What the replica vouches for: the answer to the request and the sequence number it
+    /// was committed at, so that a relay cannot alter the responses it passes on
+    fn signing_input(&self) -> SigningInput {
+        let mut data = Vec::new();
+        data.extend_from_slice(&crypto::encode_usize(self.time_stamp));
+        encode_bytes(&mut data, self.key.as_bytes());
+        encode_option(&mut data, &self.value);
+        data.extend_from_slice(&crypto::encode_usize(self.results.len()));
+        for result in self.results.iter() {
+            encode_option(&mut data, result);
+        }
+        match self.reason {
+            Some(reason) => {
+                data.push(1u8);
+                encode_bytes(&mut data, &serde_json::to_vec(&reason).unwrap());
+            }
+            None => data.push(0u8),
+        }
+        data.extend_from_slice(&crypto::encode_usize(self.seq_num));
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"ClientResponse");
+        signing_input.update_usize(self.id);
+        signing_input.update(data);
+        signing_input
+    }
+
+    /// The response with the value the key had before a delete or compare-and-swap
+    pub fn with_previous(mut self, previous: Option<Value>) -> Self {
+        self.previous = previous;
+        self
+    }
+
+    /// The response to the last batch of a bulk load, with what the load amounted to
+    pub fn with_bulk(mut self, bulk: Option<BulkLoadSummary>) -> Self {
+        self.bulk = bulk;
+        self
+    }
+
+    /// The response of a replica with the capabilities
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// The response of a replica in the given view
+    pub fn with_view(mut self, view: usize) -> Self {
+        self.view = view;
+        self
+    }
+
+    /// The response to a request committed at the given sequence number, which the
+    /// signature covers
+    pub fn with_seq_num(mut self, keystore: &Keystore, seq_num: usize) -> Self {
+        self.seq_num = seq_num;
+        self.signed(keystore)
+    }
+}
+
+/// Reasons a request is rejected when it is applied
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum FailureReason {
+    /// The write would exceed the maximum number of keys in the store
+    TotalKeyQuotaExceeded,
+    /// The write would exceed the maximum size of the store
+    TotalByteQuotaExceeded,
+    /// The write would exceed the maximum number of keys created by the client
+    ClientKeyQuotaExceeded,
+    /// The write would exceed the maximum size of the keys created by the client
+    ClientByteQuotaExceeded,
+    /// The key of a compare-and-swap did not have the expected value
+    UnexpectedValue,
+    /// The key is reserved for the registry of the cluster, which only accepts updates
+    /// signed by its root key
+    ReservedKey,
+    /// The replica is overloaded and shed the request without ordering it
+    Busy,
+    /// The client already had a later request executed, so this one never will be
+    StaleTimestamp,
+    /// A primary assigned the request a sequence number, which the primary of a later view
+    /// filled with a no-op, so the request was not ordered and has to be sent again
+    Unordered { resubmit_hint: ResubmitHint },
+    /// The request carries the id of a client which did not register, or is not signed
+    /// by the key the client registered
+    Unauthenticated,
+}
+
+impl FailureReason {
+    /// The code applications branch on, which groups the quotas together
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            FailureReason::TotalKeyQuotaExceeded
+            | FailureReason::TotalByteQuotaExceeded
+            | FailureReason::ClientKeyQuotaExceeded
+            | FailureReason::ClientByteQuotaExceeded => ErrorCode::QuotaExceeded,
+            FailureReason::UnexpectedValue => ErrorCode::UnexpectedValue,
+            FailureReason::ReservedKey => ErrorCode::Unauthorized,
+            FailureReason::Busy => ErrorCode::Busy,
+            FailureReason::StaleTimestamp => ErrorCode::StaleTimestamp,
+            FailureReason::Unordered { .. } => ErrorCode::Unordered,
+            FailureReason::Unauthenticated => ErrorCode::Unauthorized,
+        }
+    }
+}
+
+impl std::fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureReason::TotalKeyQuotaExceeded => write!(f, "store key quota exceeded"),
+            FailureReason::TotalByteQuotaExceeded => write!(f, "store byte quota exceeded"),
+            FailureReason::ClientKeyQuotaExceeded => write!(f, "client key quota exceeded"),
+            FailureReason::ClientByteQuotaExceeded => write!(f, "client byte quota exceeded"),
+            FailureReason::UnexpectedValue => write!(f, "unexpected value"),
+            FailureReason::ReservedKey => write!(f, "key reserved for the cluster registry"),
+            FailureReason::Busy => write!(f, "replica busy, retry later"),
+            FailureReason::StaleTimestamp => {
+                write!(f, "a later request of the client was executed")
+            }
+            FailureReason::Unordered { resubmit_hint } => write!(
+                f,
+                "request not ordered, send it again to the primary {} of view {}",
+                resubmit_hint.primary, resubmit_hint.view
+            ),
+            FailureReason::Unauthenticated => {
+                write!(f, "request not signed by the key the client registered")
+            }
+        }
+    }
+}
+
+/// Where a request which was not ordered is sent again: the view the replica moved to,
+/// and the primary of that view
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ResubmitHint {
+    pub view: usize,
+    pub primary: NodeId,
+}
+
+/// Stable codes of the failures a client sees, carried in client responses so that
+/// applications branch on them rather than on messages. Codes are never renumbered or
+/// reused; they serialize as their names, and `code` gives their numbers
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// The replica shed the request, which may succeed if sent again later
+    Busy = 1,
+    /// The client already had a later request executed
+    StaleTimestamp = 2,
+    /// Too few replicas agreed on an answer in time
+    QuorumUnavailable = 3,
+    /// The request is larger than a request can be
+    PayloadTooLarge = 4,
+    /// The request needs a signature the client cannot give
+    Unauthorized = 5,
+    /// The write would exceed a quota of the store or of the client
+    QuotaExceeded = 6,
+    /// The key of a compare-and-swap did not have the expected value
+    UnexpectedValue = 7,
+    /// The replicas answered such that no answer can be trusted
+    ConflictingReplies = 8,
+    /// The proof of a replica does not verify
+    InvalidProof = 9,
+    /// The request was not ordered, and may succeed if sent again
+    Unordered = 10,
+    /// Too few replicas support the feature the request relies on
+    Unsupported = 11,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 11] = [
+        ErrorCode::Busy,
+        ErrorCode::StaleTimestamp,
+        ErrorCode::QuorumUnavailable,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::Unauthorized,
+        ErrorCode::QuotaExceeded,
+        ErrorCode::UnexpectedValue,
+        ErrorCode::ConflictingReplies,
+        ErrorCode::InvalidProof,
+        ErrorCode::Unordered,
+        ErrorCode::Unsupported,
+    ];
+
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    pub fn from_code(code: u16) -> Option<ErrorCode> {
+        Self::ALL.into_iter().find(|error| error.code() == code)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::Busy => "BUSY",
+            ErrorCode::StaleTimestamp => "STALE_TIMESTAMP",
+            ErrorCode::QuorumUnavailable => "QUORUM_UNAVAILABLE",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::UnexpectedValue => "UNEXPECTED_VALUE",
+            ErrorCode::ConflictingReplies => "CONFLICTING_REPLIES",
+            ErrorCode::InvalidProof => "INVALID_PROOF",
+            ErrorCode::Unordered => "UNORDERED",
+            ErrorCode::Unsupported => "UNSUPPORTED",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name(), self.code())
+    }
+}
+
+/// A client response sent to the replica which relayed the associated request,
+/// to be passed along over the client's connection to that replica
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct RelayedClientResponse {
+    pub respond_addr: SocketAddr,
+    pub response: ClientResponse,
+}
+
+/// Sent to a peer whose message we dropped because it referred to a sequence number
+/// far below our last stable sequence number, so that the peer learns that
+/// it must catch up through a checkpoint rather than retransmit
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StaleMessage {
+    pub id: NodeId,
+    /// Sequence number of the dropped message
+    pub seq_num: usize,
+    pub last_stable_seq_num: usize,
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+}
+
+impl StaleMessage {
+    pub fn new_with_signature(
+        keystore: &Keystore,
+        id: NodeId,
+        seq_num: usize,
+        last_stable_seq_num: usize,
+    ) -> Self {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"StaleMessage");
+        signing_input.update_usize(seq_num);
+        signing_input.update_usize(last_stable_seq_num);
+
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm_at(seq_num));
+
+        Self {
+            id,
+            seq_num,
+            last_stable_seq_num,
+            signature,
+        }
+    }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"StaleMessage");
+        signing_input.update_usize(self.seq_num);
+        signing_input.update_usize(self.last_stable_seq_num);
+
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
+}
+
+/// Summary of the progress of a replica, exchanged with a peer when it becomes reachable again
+/// so that whichever of the two is behind is caught up right away
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Progress {
+    pub id: NodeId,
+    pub view: usize,
+    pub last_stable_seq_num: usize,
+    pub last_seq_num_committed: usize,
+    /// Is this the answer to the summary of the peer, which must not be answered again
+    pub is_reply: bool,
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+}
+
+impl Progress {
+    pub fn new_with_signature(
+        keystore: &Keystore,
+        id: NodeId,
+        view: usize,
+        last_stable_seq_num: usize,
+        last_seq_num_committed: usize,
+        is_reply: bool,
+    ) -> Self {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Progress");
+        signing_input.update_usize(view);
+        signing_input.update_usize(last_stable_seq_num);
+        signing_input.update_usize(last_seq_num_committed);
+        signing_input.update([is_reply as u8]);
+
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm);
+
+        Self {
+            id,
+            view,
+            last_stable_seq_num,
+            last_seq_num_committed,
+            is_reply,
+            signature,
+        }
+    }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"Progress");
+        signing_input.update_usize(self.view);
+        signing_input.update_usize(self.last_stable_seq_num);
+        signing_input.update_usize(self.last_seq_num_committed);
+        signing_input.update([self.is_reply as u8]);
+
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
+}
+
+/// Asks a peer for the body of the client request with the given digest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FetchRequestBody {
+    pub id: NodeId,
+    pub digest: Vec<u8>,
+}
+
+/// Body of a client request sent in response to a fetch.
+/// This is not signed, as the receiver checks the body against the digest it asked for
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestBody {
+    pub id: NodeId,
+    pub client_request: ClientRequest,
+}
+
+/// Asks a replica for the entries of its store at a stable checkpoint, from the position
+/// of the entry among those ordered by key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StateChunkRequest {
+    pub id: NodeId,
+    pub committed_seq_num: usize,
+    pub state_digest: Vec<u8>,
+    pub start: usize,
+}
+
+/// Entries of the store at a stable checkpoint sent in response to a chunk request.
+/// This is not signed, as the receiver checks the entries against the certified digest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StateChunkResponse {
+    pub id: NodeId,
+    pub committed_seq_num: usize,
+    pub state_digest: Vec<u8>,
+    pub entries: Vec<StateEntry>,
+    /// Proof of the inclusion of the entries, None if the store is empty
+    pub proof: Option<RangeProof>,
+}
+
+/// Entry of the store, with the client which created the key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StateEntry {
+    pub key: Key,
+    pub value: Value,
+    pub owner: Option<ClientIdentity>,
+}
+
+/// Asks the replicas what became of the requests of the client with the given timestamps,
+/// e.g. those the client was waiting for when the view changed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetRequestStatus {
+    pub respond_addr: SocketAddr,
+    #[serde(with = "limits::request_statuses")]
+    pub time_stamps: Vec<usize>,
+    /// Id of the client, if it registered one, which its requests are known by
+    #[serde(default)]
+    pub client_id: Option<ClientId>,
+}
+
+impl GetRequestStatus {
+    pub fn identity(&self) -> ClientIdentity {
+        match self.client_id {
+            Some(client_id) => ClientIdentity::Id(client_id),
+            None => ClientIdentity::Addr(self.respond_addr),
+        }
+    }
+}
+
+/// What a replica knows of a request of a client, from its reply cache
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum RequestStatus {
+    /// The request is the last one the replica executed for the client,
+    /// whose reply the replica sends again
+    Executed,
+    /// The replica executed a later request of the client, and no longer has the reply
+    Superseded,
+    /// The replica has not executed the request, nor a later one of the client
+    Unknown,
+}
+
+/// The status of each request asked for, in the order of the timestamps asked for
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestStatusReport {
+    pub id: NodeId,
+    pub respond_addr: SocketAddr,
+    #[serde(with = "limits::request_statuses")]
+    pub statuses: Vec<(usize, RequestStatus)>,
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+}
+
+impl RequestStatusReport {
+    pub fn new_with_signature(
+        keystore: &Keystore,
+        id: NodeId,
+        respond_addr: SocketAddr,
+        statuses: Vec<(usize, RequestStatus)>,
+    ) -> Self {
+        let signing_input = Self::signing_input(id, &respond_addr, &statuses);
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm);
+        Self {
+            id,
+            respond_addr,
+            statuses,
+            signature,
         }
     }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        let signing_input = Self::signing_input(self.id, &self.respond_addr, &self.statuses);
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
+
+    fn signing_input(
+        id: NodeId,
+        respond_addr: &SocketAddr,
+        statuses: &[(usize, RequestStatus)],
+    ) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"RequestStatus");
+        signing_input.update_usize(id);
+        signing_input.update(respond_addr.to_string());
+        for (time_stamp, status) in statuses {
+            signing_input.update_usize(*time_stamp);
+            signing_input.update([*status as u8]);
+        }
+        signing_input
+    }
+}
+
+/// Asks a replica for the value of the key at its latest stable checkpoint,
+/// with a proof of inclusion in the certified state
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetProof {
+    pub respond_addr: SocketAddr,
+    pub key: Key,
+}
+
+/// Value of a key at the latest stable checkpoint of the replica.
+/// This is not signed, as the client checks the proof against the certificate
+/// (see `merkle::verify_key_proof`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyProof {
+    pub id: NodeId,
+    pub key: Key,
+    pub value: Option<Value>,
+    /// Client which created the key, which is part of its entry in the state
+    pub owner: Option<ClientIdentity>,
+    pub committed_seq_num: usize,
+    /// Merkle root of the state at the checkpoint
+    pub state_digest: Vec<u8>,
+    /// Inclusion proof of the entry of the key (None if the key is not in the state)
+    pub proof: Option<MerkleProof>,
+    /// Checkpoints certifying the state digest, without the states they carry
+    #[serde(with = "limits::checkpoint_proof")]
+    pub certificate: Vec<CheckPoint>,
+}
+
+/// Asks a replica for the entry of its log at the sequence number. Archive nodes retain
+/// every entry, other replicas only those since their last stable checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetEntry {
+    pub respond_addr: SocketAddr,
+    pub seq_num: usize,
+}
+
+/// Commit the replica applied at the sequence number, with its client request.
+/// This is not signed, so a reader auditing the history compares the entries of f + 1 replicas
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub id: NodeId,
+    pub seq_num: usize,
+    /// None if the replica did not apply the sequence number, or no longer retains its entry
+    pub commit: Option<Commit>,
+    pub request: Option<ClientRequest>,
+    /// Why the request was rejected, if it was, in which case it wrote nothing
+    #[serde(default)]
+    pub rejected: Option<FailureReason>,
+}
+
+/// Asks a replica for the value of the key after the request at the sequence number was
+/// applied, which archive nodes can answer for any sequence number they committed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetAt {
+    pub respond_addr: SocketAddr,
+    pub key: Key,
+    pub seq_num: usize,
+}
+
+/// Value of the key after the request at the sequence number was applied (not signed)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ValueAt {
+    pub id: NodeId,
+    pub key: Key,
+    pub seq_num: usize,
+    /// Whether the replica retains the history needed to answer; `value` is None if not
+    pub retained: bool,
+    pub value: Option<Value>,
+}
+
+/// Asks a node for its status, which it answers over the same connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatusRequest {}
+
+/// Asks a node to compact its write-ahead log now, rather than wait for a quiet moment.
+/// The node answers with its status over the same connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompactLog {}
+
+/// Asks a node to prepare for a planned shutdown: it stops taking requests from clients,
+/// takes part in the instances in flight until it committed up to the target sequence number
+/// (or the highest one it accepted a pre-prepare for), then emits a final checkpoint and
+/// reports that it is safe to stop. The node answers with its status over the same connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Drain {
+    #[serde(default)]
+    pub target_seq_num: Option<usize>,
+}
+
+/// Asks a node for the keys written between two of its stable checkpoints, so that a reader
+/// synced up to the first can catch up without replaying the log. The node answers over
+/// the same connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetDiff {
+    pub from_seq_num: usize,
+    /// The last stable checkpoint of the node if None
+    #[serde(default)]
+    pub to_seq_num: Option<usize>,
+}
+
+/// Keys written after the stable checkpoint at `from_seq_num`, up to and including the
+/// one at `to_seq_num`, whose values are then as in the state of that checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CheckpointDiff {
+    pub id: NodeId,
+    pub from_seq_num: usize,
+    pub to_seq_num: usize,
+    /// None if the node cannot compute the diff, see `error`
+    pub keys: Option<Vec<Key>>,
+    /// Why the node cannot compute the diff
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Oldest and last stable checkpoints the node can compute diffs between
+    pub oldest_seq_num: usize,
+    pub last_stable_seq_num: usize,
+}
+
+/// Asks a node for the evidence it holds against other replicas.
+/// The node answers over the same connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetEvidence {}
+
+/// Evidence a node holds against other replicas, which the reader can check for itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceReport {
+    pub id: NodeId,
+    #[serde(flatten)]
+    pub summary: EvidenceSummary,
+}
+
+/// Ping a replica sends each of its peers periodically, which the peer answers with a pong.
+/// Like identifiers, pings and pongs are authenticated by the connection they arrive on
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Ping {
+    pub id: NodeId,
+    /// Number of the ping among those sent to the peer, counting from 0
+    pub seq: u64,
+    /// When the ping was sent, in microseconds on a monotonic clock of the sender
+    pub sent_at_micros: u64,
+}
+
+/// Answer to a ping, which echoes it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Pong {
+    pub id: NodeId,
+    pub seq: u64,
+    pub sent_at_micros: u64,
+}
+
+/// Progress of a node which is draining
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DrainStatus {
+    /// Sequence number the node commits up to before it is safe to stop
+    pub target_seq_num: usize,
+    pub safe_to_stop: bool,
+}
+
+/// Asks a node to announce the primary of its view, and of every view it moves to,
+/// over the same connection, which it keeps open until the client closes it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WatchLeader {}
+
+/// The primary of the view a node entered, announced to clients watching for leadership
+/// changes, e.g. load balancers which route writes to the primary
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Leader {
+    /// Node announcing the primary
+    pub id: NodeId,
+    pub view: usize,
+    pub primary: NodeId,
+    /// Address the primary advertised, or its configured address
+    pub primary_addr: SocketAddr,
+}
+
+/// Asks a node to announce the last request it committed, and the version of the key if any,
+/// over the same connection and again whenever it commits another request,
+/// until the client closes the connection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WatchProgress {
+    #[serde(default)]
+    pub key: Option<Key>,
+}
+
+/// Signed announcement of the last request a node committed, sent to clients watching
+/// its progress. A client holding these from f + 1 nodes knows that a correct replica
+/// reached the sequence number, or the version of the key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommitProgress {
+    pub id: NodeId,
+    pub last_seq_num_committed: usize,
+    /// Key the client watches, if any
+    #[serde(default)]
+    pub key: Option<Key>,
+    /// Sequence number of the last write to the key
+    #[serde(default)]
+    pub version: usize,
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+}
+
+impl CommitProgress {
+    pub fn new_with_signature(
+        keystore: &Keystore,
+        id: NodeId,
+        last_seq_num_committed: usize,
+        key: Option<Key>,
+        version: usize,
+    ) -> Self {
+        let signing_input = Self::signing_input(last_seq_num_committed, key.as_ref(), version);
+        let signature = keystore.sign(&signing_input, crypto::policy().algorithm);
+
+        Self {
+            id,
+            last_seq_num_committed,
+            key,
+            version,
+            signature,
+        }
+    }
+
+    pub fn is_properly_signed_by(&self, pub_key: &PublicKey) -> bool {
+        let signing_input =
+            Self::signing_input(self.last_seq_num_committed, self.key.as_ref(), self.version);
+        crypto::verify(pub_key, &signing_input, &self.signature)
+    }
+
+    fn signing_input(
+        last_seq_num_committed: usize,
+        key: Option<&Key>,
+        version: usize,
+    ) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"CommitProgress");
+        signing_input.update_usize(last_seq_num_committed);
+        if let Some(key) = key {
+            signing_input.update(key.as_bytes());
+        }
+        signing_input.update_usize(version);
+        signing_input
+    }
+}
+
+/// Progress of a node, used by operators e.g. to tell when a restarted node has caught up
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NodeStatus {
+    pub id: NodeId,
+    pub view: usize,
+    pub in_view_change: bool,
+    pub last_seq_num_committed: usize,
+    pub last_stable_seq_num: usize,
+    /// Epoch of the membership the node is in
+    #[serde(default)]
+    pub epoch: usize,
+    /// Number of stale messages the node dropped since it started
+    pub stale_messages_dropped: usize,
+    /// Messages queued for the consensus engine, and counts of those passed and dropped
+    #[serde(default)]
+    pub pipeline: PipelineStats,
+    /// Malformed frames the node received, by sender
+    #[serde(default)]
+    pub malformed: MalformedStats,
+    /// Client requests waiting to be proposed, if the node is primary
+    #[serde(default)]
+    pub mempool: MempoolStats,
+    /// Messages dropped because their signature could not be verified, by claimed sender
+    #[serde(default)]
+    pub unverified_messages: BTreeMap<NodeId, usize>,
+    /// Signatures verified, and messages shed unverified while overloaded
+    #[serde(default)]
+    pub verification: VerificationStats,
+    /// Responses the node could not deliver to their clients
+    #[serde(default)]
+    pub dead_letters: Vec<DeadLetter>,
+    /// Protocol messages held for peers the node could not reach
+    #[serde(default)]
+    pub outbox: OutboxStats,
+    /// Progress of the drain, if an operator asked the node to drain
+    #[serde(default)]
+    pub drain: Option<DrainStatus>,
+    /// Skew of the clocks of the peers relative to ours
+    #[serde(default)]
+    pub clock: ClockStats,
+    /// Protocol version and optional features the node supports
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    /// Round trips to the peers, and the peers which stopped answering pings
+    #[serde(default)]
+    pub connectivity: ConnectivityStats,
+    /// Nodes absent from most of the quorums of the last window of the quorum diagnostics,
+    /// which are likely crashed or partitioned
+    #[serde(default)]
+    pub suspected_nodes: Vec<NodeId>,
 }
 
 // Commands to Node
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum NodeCommand {
     SendMessageCommand(SendMessage),
     BroadCastMessageCommand(BroadCastMessage),
+    CatchUpCommand(CatchUp),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -486,13 +2239,26 @@ pub struct BroadCastMessage {
     pub message: Message,
 }
 
+/// Messages a lagging peer needs to catch up, sent to it in order
+/// ahead of the messages queued for the other peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatchUp {
+    pub peer_id: NodeId,
+    pub messages: Vec<Message>,
+}
+
 // Commands to Consensus Engine
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum ConsensusCommand {
     ProcessMessage(Message),
+    /// A message whose signature the verification workers already checked
+    ProcessVerifiedMessage {
+        message: Message,
+        authentic: bool,
+    },
     MisdirectedClientRequest(ClientRequest),
-    InitPrePrepare(ClientRequest),
     AcceptPrePrepare(PrePrepare),
     RebroadcastPrePrepare((usize, usize)),
     AcceptPrepare(Prepare),
@@ -501,6 +2267,29 @@ pub enum ConsensusCommand {
     InitViewChange(ClientRequest),
     AcceptViewChange(ViewChange),
     AcceptNewView(NewView),
+    /// We did not move to the view in time after sending view changes for it
+    NewViewTimeout(usize),
+    /// As the primary of the view, we did not get the client requests we fetched for the
+    /// new view in time
+    RequestBodiesTimeout(usize),
+    /// The peer became reachable again, or was reached for the first time
+    PeerReconnected(NodeId),
+    /// Compact the write-ahead log if it is due and the node is quiet enough,
+    /// or regardless of the traffic if forced by an operator
+    CompactLog {
+        forced: bool,
+    },
+    /// Prepare for a planned shutdown (see `Drain`)
+    Drain {
+        target_seq_num: Option<usize>,
+    },
+    /// Export a snapshot of the state at the last stable checkpoint to the configured file
+    ExportSnapshot,
     ApplyCommit(Commit),
     AcceptCheckpoint(CheckPoint),
+    /// The chunk of the state we are fetching at the checkpoint was not sent in time
+    StateChunkTimeout {
+        committed_seq_num: usize,
+        start: usize,
+    },
 }
@@ -0,0 +1,444 @@
+use crate::logging;
+use crate::messages::{ClientRequest, ClientResponse, Message, NodeStatus};
+use crate::observer::{Observer, QuorumKind};
+use crate::state::SlotMeta;
+use crate::NodeId;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration, Instant};
+
+/// Upper bounds of the buckets of the phase latency histograms, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// Phases of the normal case protocol a sequence number goes through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// From the pre-prepare to a quorum of prepares
+    Prepare,
+    /// From a quorum of prepares to a quorum of commits
+    Commit,
+    /// From a quorum of commits to the request being applied
+    Execute,
+}
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::Prepare => "prepare",
+            Phase::Commit => "commit",
+            Phase::Execute => "execute",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [usize; LATENCY_BUCKETS.len()],
+    count: usize,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Health of the consensus of a replica, exported in the Prometheus text format: the votes
+/// and quorums it saw, the requests in flight and how long each phase of the protocol took.
+/// Registered as an observer, it sees the messages of the node and the events of the
+/// consensus engine. The view and progress are taken from the status of the node when rendered
+#[derive(Default)]
+pub struct ConsensusMetrics {
+    prepares_received: AtomicUsize,
+    commits_received: AtomicUsize,
+    prepare_quorums: AtomicUsize,
+    commit_quorums: AtomicUsize,
+    committed_requests: AtomicUsize,
+    view_changes: AtomicUsize,
+    /// When each sequence number in flight entered its current phase
+    in_flight: Mutex<BTreeMap<usize, (Phase, Instant)>>,
+    latencies: Mutex<BTreeMap<Phase, Histogram>>,
+}
+
+impl ConsensusMetrics {
+    /// Starts the phase of the sequence number, recording the latency of the phase before it
+    fn enter(&self, seq_num: usize, phase: Phase) {
+        let now = Instant::now();
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let previous = match in_flight.get(&seq_num) {
+            // votes keep arriving after the quorum, and pre-prepares are sent again
+            Some((entered, _)) if *entered >= phase => return,
+            Some((entered, since)) => Some((*entered, *since)),
+            None => None,
+        };
+        if let Some((entered, since)) = previous {
+            self.latencies
+                .lock()
+                .unwrap()
+                .entry(entered)
+                .or_default()
+                .observe((now - since).as_secs_f64());
+        }
+        in_flight.insert(seq_num, (phase, now));
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    pub fn render(&self, status: &NodeStatus) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: usize| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric("pbft_view", "gauge", "Current view", status.view);
+        metric(
+            "pbft_in_view_change",
+            "gauge",
+            "Whether a view change is in progress",
+            status.in_view_change as usize,
+        );
+        metric(
+            "pbft_last_committed_seq_num",
+            "gauge",
+            "Last sequence number committed",
+            status.last_seq_num_committed,
+        );
+        metric(
+            "pbft_last_stable_seq_num",
+            "gauge",
+            "Sequence number of the last stable checkpoint",
+            status.last_stable_seq_num,
+        );
+        metric(
+            "pbft_prepares_received_total",
+            "counter",
+            "Prepare votes received",
+            self.prepares_received.load(Ordering::Relaxed),
+        );
+        metric(
+            "pbft_commits_received_total",
+            "counter",
+            "Commit votes received",
+            self.commits_received.load(Ordering::Relaxed),
+        );
+        metric(
+            "pbft_prepare_quorums_total",
+            "counter",
+            "Quorums of prepares collected",
+            self.prepare_quorums.load(Ordering::Relaxed),
+        );
+        metric(
+            "pbft_commit_quorums_total",
+            "counter",
+            "Quorums of commits collected",
+            self.commit_quorums.load(Ordering::Relaxed),
+        );
+        metric(
+            "pbft_committed_requests_total",
+            "counter",
+            "Requests applied",
+            self.committed_requests.load(Ordering::Relaxed),
+        );
+        metric(
+            "pbft_view_changes_total",
+            "counter",
+            "Views entered",
+            self.view_changes.load(Ordering::Relaxed),
+        );
+        metric(
+            "pbft_requests_in_flight",
+            "gauge",
+            "Requests pre-prepared but not yet applied",
+            self.in_flight.lock().unwrap().len(),
+        );
+        metric(
+            "pbft_mempool_pending",
+            "gauge",
+            "Client requests waiting to be proposed",
+            status.mempool.pending,
+        );
+        metric(
+            "pbft_stale_messages_dropped_total",
+            "counter",
+            "Messages dropped for referring to a sequence number below the window",
+            status.stale_messages_dropped,
+        );
+        metric(
+            "pbft_verifications_shed_total",
+            "counter",
+            "Messages shed without verifying their signature while overloaded",
+            status.verification.total_shed(),
+        );
+        metric(
+            "pbft_outbox_held",
+            "gauge",
+            "Protocol messages held for peers which could not be reached",
+            status.outbox.total_held(),
+        );
+        metric(
+            "pbft_pipeline_depth",
+            "gauge",
+            "Messages queued for the consensus engine",
+            status.pipeline.depth,
+        );
+        metric(
+            "pbft_pipeline_capacity",
+            "gauge",
+            "Most messages queued for the consensus engine",
+            status.pipeline.capacity,
+        );
+        metric(
+            "pbft_node_queue_depth",
+            "gauge",
+            "Commands the consensus engine queued for the node to send",
+            status.pipeline.node_queue_depth,
+        );
+        metric(
+            "pbft_client_requests_busy_total",
+            "counter",
+            "Client requests rejected as busy while the consensus engine was overloaded",
+            status.pipeline.busy_rejections,
+        );
+        metric(
+            "pbft_oversized_frames_total",
+            "counter",
+            "Connections dropped for announcing a frame over the maximum length",
+            status.malformed.oversized_frames,
+        );
+        metric(
+            "pbft_clock_skew_max_millis",
+            "gauge",
+            "Largest offset of the clock of a peer from ours, either way",
+            status.clock.max_skew_millis() as usize,
+        );
+        metric(
+            "pbft_clock_skewed_peers",
+            "gauge",
+            "Peers whose clock is off from ours past the alert threshold",
+            status.clock.skewed_peers.len(),
+        );
+        metric(
+            "pbft_peers_down",
+            "gauge",
+            "Peers which missed enough pings in a row for their connection to be taken for dead",
+            status.connectivity.down_peers().len(),
+        );
+        metric(
+            "pbft_suspected_nodes",
+            "gauge",
+            "Nodes absent from most quorums of the last window of the quorum diagnostics",
+            status.suspected_nodes.len(),
+        );
+        metric(
+            "pbft_slow_connections_total",
+            "counter",
+            "Connections dropped for not sending their message in time",
+            status.malformed.slow_connections,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP pbft_phase_latency_seconds Time sequence numbers spent in each phase"
+        );
+        let _ = writeln!(out, "# TYPE pbft_phase_latency_seconds histogram");
+        let latencies = self.latencies.lock().unwrap();
+        for phase in [Phase::Prepare, Phase::Commit, Phase::Execute] {
+            let histogram = latencies.get(&phase).cloned().unwrap_or_default();
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "pbft_phase_latency_seconds_bucket{{phase=\"{}\",le=\"{}\"}} {}",
+                    phase.name(),
+                    bound,
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "pbft_phase_latency_seconds_bucket{{phase=\"{}\",le=\"+Inf\"}} {}",
+                phase.name(),
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "pbft_phase_latency_seconds_sum{{phase=\"{}\"}} {}",
+                phase.name(),
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "pbft_phase_latency_seconds_count{{phase=\"{}\"}} {}",
+                phase.name(),
+                histogram.count
+            );
+        }
+        out
+    }
+}
+
+impl Observer for ConsensusMetrics {
+    fn on_message_in(&self, message: &Message) {
+        match message {
+            Message::PrePrepareMessage(pre_prepare) => {
+                self.enter(pre_prepare.seq_num, Phase::Prepare)
+            }
+            Message::PrepareMessage(_) => {
+                self.prepares_received.fetch_add(1, Ordering::Relaxed);
+            }
+            Message::CommitMessage(_) => {
+                self.commits_received.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_message_out(&self, message: &Message, _destination: Option<SocketAddr>) {
+        // the primary starts the sequence numbers it proposes
+        if let Message::PrePrepareMessage(pre_prepare) = message {
+            self.enter(pre_prepare.seq_num, Phase::Prepare);
+        }
+    }
+
+    fn on_quorum(&self, kind: QuorumKind, _view: usize, seq_num: usize, _participants: &[NodeId]) {
+        match kind {
+            QuorumKind::Prepare => {
+                self.prepare_quorums.fetch_add(1, Ordering::Relaxed);
+                self.enter(seq_num, Phase::Commit);
+            }
+            QuorumKind::Commit => {
+                self.commit_quorums.fetch_add(1, Ordering::Relaxed);
+                self.enter(seq_num, Phase::Execute);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_commit(&self, slot: &SlotMeta, _request: &ClientRequest, _response: &ClientResponse) {
+        self.committed_requests.fetch_add(1, Ordering::Relaxed);
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some((Phase::Execute, since)) = in_flight.get(&slot.seq_num) {
+            self.latencies
+                .lock()
+                .unwrap()
+                .entry(Phase::Execute)
+                .or_default()
+                .observe(since.elapsed().as_secs_f64());
+        }
+        // sequence numbers up to the applied one are done, or were abandoned
+        in_flight.retain(|in_flight_seq_num, _| *in_flight_seq_num > slot.seq_num);
+    }
+
+    fn on_view_change(&self, _view: usize) {
+        self.view_changes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Rate at which requests were committed over a sliding window, which tells busy periods
+/// from quiet ones
+pub struct CommitRate {
+    window: Duration,
+    commits: VecDeque<Instant>,
+}
+
+impl CommitRate {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            commits: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self) {
+        self.commits.push_back(Instant::now());
+        self.expire();
+    }
+
+    /// Commits per second over the window
+    pub fn per_second(&mut self) -> f64 {
+        self.expire();
+        self.commits.len() as f64 / self.window.as_secs_f64()
+    }
+
+    fn expire(&mut self) {
+        while self
+            .commits
+            .front()
+            .is_some_and(|commit| commit.elapsed() > self.window)
+        {
+            self.commits.pop_front();
+        }
+    }
+}
+
+/// Serves the metrics over HTTP on `GET /metrics`, with the status of the node at the time
+/// of each scrape
+pub async fn serve<F>(listener: TcpListener, metrics: Arc<ConsensusMetrics>, status: F)
+where
+    F: Fn() -> NodeStatus + Send + Sync + 'static,
+{
+    let status = Arc::new(status);
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let metrics = metrics.clone();
+        let status = status.clone();
+        logging::spawn(async move {
+            if let Err(e) = answer_scrape(&mut stream, &metrics, status()).await {
+                warn!("Failed to answer metrics request {}", e);
+            }
+        });
+    }
+}
+
+async fn answer_scrape(
+    stream: &mut TcpStream,
+    metrics: &ConsensusMetrics,
+    status: NodeStatus,
+) -> std::io::Result<()> {
+    // only the request line matters, the headers are read up to a bound and ignored
+    let mut request = vec![0u8; 4096];
+    let mut len = 0;
+    while !request[..len]
+        .windows(4)
+        .any(|window| window == b"\r\n\r\n")
+        && len < request.len()
+    {
+        let n = match timeout(Duration::from_secs(5), stream.read(&mut request[len..])).await {
+            Ok(res) => res?,
+            Err(_) => return Ok(()),
+        };
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    let request_line = String::from_utf8_lossy(&request[..len]);
+    let response = if request_line.starts_with("GET /metrics ") {
+        let body = metrics.render(&status);
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await
+}
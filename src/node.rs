@@ -1,30 +1,66 @@
+use crate::byzantine::{Fault, Faults};
+use crate::codec::{self, CodecError, MalformedFrame, MalformedLog, MessageReader};
 use crate::config::Config;
+use crate::connectivity::Connectivity;
+use crate::crypto;
+use crate::dead_letter::{DeadLetter, DeadLetters};
+use crate::keystore::Keystore;
+use crate::logging::{self, sampled};
+use crate::membership::Membership;
+use crate::metrics::{self, ConsensusMetrics};
+use crate::observer::{Observer, Observers};
+use crate::outbox::Outbox;
+use crate::pipeline::{Pipeline, PipelineStats};
+use crate::pki::CertificateError;
+use crate::time::{ClockSkew, WallClock};
+use crate::transport::{SecureChannel, SendFuture, Transport, TransportError, HANDSHAKE_MAGIC};
 
-use crate::messages::{ConsensusCommand, Identifier, Message, NodeCommand};
-use crate::{NodeId, Result};
+use crate::diffs::CheckpointDiffs;
+use crate::evidence::EvidenceLog;
+use crate::features::Capabilities;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcFrontEnd;
+#[cfg(feature = "http")]
+use crate::http::HttpFrontEnd;
+use crate::messages::{
+    CheckpointDiff, ClientRequest, ClientResponse, CommitProgress, ConsensusCommand,
+    EvidenceReport, FailureReason, GetDiff, Identifier, Leader, Message, NodeCommand, NodeStatus,
+    Pong, StaleMessage,
+};
+use crate::versions::KeyVersions;
+use crate::{Key, NodeId, Result};
 
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::time::sleep;
-use tokio::{io::AsyncBufReadExt, sync::Mutex};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, Duration};
 
 use ed25519_dalek::PublicKey;
 
-use env_logger::Env;
 use log::{info, warn};
 
+/// Channels to relayed client connections, indexed by (respond_addr, time_stamp)
+pub type RelayedRequests = HashMap<(SocketAddr, usize), Sender<ClientResponse>>;
+
 pub struct Node {
     /// Id of this node
     pub id: NodeId,
     /// Configuration of Cluster this node is in
     pub config: Config,
-    /// Socket on which this node is listening for connections from peers
+    /// Address on which this node is reachable, advertised to peers
     pub addr: SocketAddr,
+    /// Sockets on which this node is listening for connections from peers
+    pub bind_addrs: Vec<SocketAddr>,
     /// Node state which will be shared across Tokio tasks
     pub inner: InnerNode,
     /// Receive Commands from the Consensus Engine
@@ -38,13 +74,64 @@ pub struct InnerNode {
     /// Config of the cluster of the outer node
     pub config: Config,
     /// Keypair of this node used to sign messages
-    pub keypair_bytes: Vec<u8>,
+    pub keystore: Keystore,
     /// Public key of this node
     pub pub_key: PublicKey,
+    /// Signed identifier of this node, which opens every plaintext connection to a peer
+    pub identifier: Identifier,
     /// Known public keys of peers
     pub peer_pub_keys: Arc<Mutex<HashMap<NodeId, PublicKey>>>,
+    /// Addresses peers are reachable on, as advertised in their identifiers
+    pub peer_addrs: Arc<Mutex<HashMap<NodeId, SocketAddr>>>,
+    /// Peers we could not connect to the last time we sent them a message
+    pub unreachable_peers: Arc<Mutex<HashSet<NodeId>>>,
+    /// Client requests submitted through this node, indexed by (respond_addr, time_stamp),
+    /// mapped to the connection task which passes responses back to the client
+    pub relayed_requests: Arc<Mutex<RelayedRequests>>,
     /// Send Consensus Commands to Consensus engine
     pub tx_consensus: Sender<ConsensusCommand>,
+    /// Backpressure signal from the consensus engine, true while it is overloaded
+    pub rx_backpressure: watch::Receiver<bool>,
+    /// Last stable sequence number of the consensus engine
+    pub rx_stable_seq_num: watch::Receiver<usize>,
+    /// Progress of the consensus engine, reported in response to status requests
+    pub rx_status: watch::Receiver<NodeStatus>,
+    /// Nodes the quorum diagnostics of the consensus engine suspect of being down
+    pub rx_suspected_nodes: watch::Receiver<Vec<NodeId>>,
+    /// Number of stale messages dropped when they were received
+    pub stale_messages_dropped: Arc<AtomicUsize>,
+    /// Observers registered on this replica
+    pub observers: Observers,
+    /// Byzantine faults injected into the messages we send (used for testing)
+    pub faults: Faults,
+    /// Transport our messages are sent over, TCP unless the node runs in a simulation
+    pub transport: Arc<dyn Transport>,
+    /// Accounting of the messages we pass to the consensus engine
+    pub pipeline: Pipeline,
+    /// Malformed frames we received, by sender
+    pub malformed: MalformedLog,
+    /// Responses we could not deliver to their clients
+    pub dead_letters: DeadLetters,
+    /// Protocol messages held for peers we could not reach, sent once they are back
+    pub outbox: Outbox,
+    /// Versions of the keys the consensus engine wrote, reported to clients watching them
+    pub key_versions: KeyVersions,
+    /// Keys the consensus engine wrote between its stable checkpoints, reported to readers
+    /// catching up from one of them
+    pub checkpoint_diffs: CheckpointDiffs,
+    /// Evidence the consensus engine holds against replicas proven faulty, reported to
+    /// operators and taken into account when announcing the primary
+    pub evidence: EvidenceLog,
+    /// Members of the epoch the consensus engine is in, which we send to and pin the keys of
+    pub membership: Membership,
+    /// Wall clock our identifiers carry readings of
+    pub clock: WallClock,
+    /// Skew of the clocks of the peers, estimated from the readings in their identifiers
+    pub clock_skew: ClockSkew,
+    /// Round trips to the peers measured by our pings, which the view change timers adapt to
+    pub connectivity: Connectivity,
+    /// Set once an operator asked us to drain, after which client requests are refused
+    pub draining: Arc<AtomicBool>,
     /// Send Node Commands to itself
     pub tx_node: Sender<NodeCommand>,
 }
@@ -53,24 +140,53 @@ impl Node {
     pub fn new(
         id: NodeId,
         config: Config,
-        keypair_bytes: Vec<u8>,
-        pub_key: PublicKey,
+        keystore: &Keystore,
         rx_node: Receiver<NodeCommand>,
         tx_consensus: Sender<ConsensusCommand>,
         tx_node: Sender<NodeCommand>,
     ) -> Self {
-        let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
-        logger.init();
+        crypto::set_policy(config.digest_policy.clone());
 
         let addr_me = *config.peer_addrs.get(&id).unwrap();
+        let bind_addrs = if config.bind_addrs.is_empty() {
+            vec![addr_me]
+        } else {
+            config.bind_addrs.clone()
+        };
 
+        let identifier = Identifier::new_with_signature(keystore, id, addr_me)
+            .with_certificate(config.certificate.clone());
         let inner = InnerNode {
             id,
             config: config.clone(),
-            keypair_bytes,
-            pub_key,
+            keystore: keystore.clone(),
+            pub_key: keystore.public_key(),
+            identifier,
             peer_pub_keys: Arc::new(Mutex::new(HashMap::new())),
+            peer_addrs: Arc::new(Mutex::new(config.peer_addrs.clone())),
+            unreachable_peers: Arc::new(Mutex::new(HashSet::new())),
+            relayed_requests: Arc::new(Mutex::new(HashMap::new())),
             tx_consensus,
+            rx_backpressure: watch::channel(false).1,
+            rx_stable_seq_num: watch::channel(0).1,
+            rx_status: watch::channel(NodeStatus::default()).1,
+            rx_suspected_nodes: watch::channel(Vec::new()).1,
+            stale_messages_dropped: Arc::new(AtomicUsize::new(0)),
+            observers: Observers::default(),
+            faults: Faults::default(),
+            transport: Arc::new(TcpTransport),
+            pipeline: Pipeline::default(),
+            malformed: MalformedLog::default(),
+            dead_letters: DeadLetters::default(),
+            outbox: Outbox::new(config.peer_outbox_capacity, config.peer_outbox_retention),
+            key_versions: KeyVersions::default(),
+            checkpoint_diffs: CheckpointDiffs::default(),
+            evidence: EvidenceLog::default(),
+            membership: Membership::new(&config),
+            clock: WallClock::default(),
+            clock_skew: ClockSkew::new(config.clock_skew_alert),
+            connectivity: Connectivity::new(config.ping_misses),
+            draining: Arc::new(AtomicBool::new(false)),
             tx_node,
         };
 
@@ -78,62 +194,191 @@ impl Node {
             id,
             config,
             addr: addr_me,
+            bind_addrs,
             inner,
             rx_node,
         }
     }
 
+    #[cfg(feature = "grpc")]
+    async fn serve_grpc(&self, grpc_addr: SocketAddr) {
+        let listener = TcpListener::bind(grpc_addr).await.unwrap();
+        let front_end = GrpcFrontEnd::new(self.inner.clone(), grpc_addr);
+        logging::spawn(async move {
+            if let Err(e) = front_end.serve(listener).await {
+                warn!("gRPC front end failed: {}", e);
+            }
+        });
+        info!("Node {} serving gRPC on {}", self.id, grpc_addr);
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    async fn serve_grpc(&self, grpc_addr: SocketAddr) {
+        warn!(
+            "Node {} not serving gRPC on {}, as it was built without the grpc feature",
+            self.id, grpc_addr
+        );
+    }
+
+    #[cfg(feature = "http")]
+    async fn serve_http(&self, http_addr: SocketAddr) {
+        let listener = TcpListener::bind(http_addr).await.unwrap();
+        let front_end = HttpFrontEnd::new(self.inner.clone(), http_addr);
+        logging::spawn(async move {
+            if let Err(e) = front_end.serve(listener).await {
+                warn!("HTTP gateway failed: {}", e);
+            }
+        });
+        info!("Node {} serving HTTP on {}", self.id, http_addr);
+    }
+
+    #[cfg(not(feature = "http"))]
+    async fn serve_http(&self, http_addr: SocketAddr) {
+        warn!(
+            "Node {} not serving HTTP on {}, as it was built without the http feature",
+            self.id, http_addr
+        );
+    }
+
+    /// Makes this replica Byzantine, tampering with the messages it sends to its peers
+    pub fn inject_fault(&self, fault: Arc<dyn Fault>) {
+        self.inner.faults.register(fault);
+    }
+
     pub async fn spawn(&mut self) {
-        let listener = TcpListener::bind(self.addr).await.unwrap();
+        let mut listeners = Vec::new();
+        for bind_addr in self.bind_addrs.iter() {
+            listeners.push(TcpListener::bind(bind_addr).await.unwrap());
+        }
 
         if !self.config.is_equivocator {
-            info!("Node {} listening on {}", self.id, self.addr);
+            info!(
+                "Node {} listening on {:?}, advertising {}",
+                self.id, self.bind_addrs, self.addr
+            );
         } else {
-            info!("Node {} listening on {} (is Byzantine)", self.id, self.addr);
+            info!(
+                "Node {} listening on {:?}, advertising {} (is Byzantine)",
+                self.id, self.bind_addrs, self.addr
+            );
+        }
+
+        if let Some(metrics_addr) = self.config.metrics_addr {
+            let listener = TcpListener::bind(metrics_addr).await.unwrap();
+            let metrics = Arc::new(ConsensusMetrics::default());
+            self.inner.observers.register(metrics.clone());
+            let inner = self.inner.clone();
+            logging::spawn(metrics::serve(listener, metrics, move || inner.status()));
+            info!("Node {} serving metrics on {}", self.id, metrics_addr);
+        }
+
+        if let Some(grpc_addr) = self.config.grpc_addr {
+            self.serve_grpc(grpc_addr).await;
         }
 
-        // We periodically broadcast our identity to all of the other nodes in the network
+        if let Some(http_addr) = self.config.http_addr {
+            self.serve_http(http_addr).await;
+        }
+
+        // incoming connections on every interface
+        // we maintain the connection and only read from it
+        // perhaps updating the consensus state
+        for listener in listeners {
+            let inner = self.inner.clone();
+            logging::spawn(async move {
+                loop {
+                    let res = listener.accept().await;
+                    if res.is_err() {
+                        continue;
+                    }
+                    let (mut stream, remote_addr) = res.unwrap();
+                    if inner.is_refused(remote_addr.ip()) {
+                        continue;
+                    }
+                    let inner = inner.clone();
+                    logging::spawn(async move {
+                        if let Err(e) = inner.read_message(&mut stream).await {
+                            warn!("Unable to read message from incoming connection {}", e);
+                        }
+                    });
+                }
+            });
+        }
+
+        self.run().await;
+    }
+
+    /// Announces our identity and carries out the commands of the consensus engine.
+    /// Nodes on an in-memory transport run this without listening for connections
+    pub async fn run(&mut self) {
+        // We periodically broadcast our identity to all of the other nodes in the network,
+        // which serves as a heartbeat carrying a reading of our clock
         let inner = self.inner.clone();
-        tokio::spawn(async move {
+        logging::spawn(async move {
             loop {
-                inner
-                    .broadcast(&Message::IdentifierMessage(Identifier {
-                        id: inner.id,
-                        pub_key_vec: inner.pub_key.as_bytes().to_vec(),
-                    }))
-                    .await;
+                inner.broadcast_identifier().await;
                 sleep(inner.config.identity_broadcast_interval).await;
             }
         });
 
+        // and ping the peers to measure the round trips and notice dead connections
+        if !self.inner.config.ping_interval.is_zero() {
+            let inner = self.inner.clone();
+            logging::spawn(async move {
+                loop {
+                    inner.ping_peers();
+                    sleep(inner.config.ping_interval).await;
+                }
+            });
+        }
+
+        // incoming messages from the consensus engine
         loop {
-            tokio::select! {
-                // future representing an incoming connection
-                // we maintain the connection and only read from it
-                // perhaps updating the consensus state
-                res = listener.accept() => {
-                    if !res.is_ok() {continue;}
-                    let (mut stream, _) = res.unwrap();
+            let cmd = self.rx_node.recv().await.unwrap();
+            match cmd {
+                NodeCommand::SendMessageCommand(send_message) => {
+                    self.inner
+                        .observers
+                        .on_message_out(&send_message.message, Some(send_message.destination));
+                    if let Message::ClientResponseMessage(response) = send_message.message {
+                        // retries back off, which must not hold up the messages to our peers
+                        let inner = self.inner.clone();
+                        logging::spawn(async move {
+                            inner
+                                .send_client_response(send_message.destination, response)
+                                .await
+                        });
+                        continue;
+                    }
+                    let _ = self
+                        .inner
+                        .send_message(&send_message.destination, send_message.message)
+                        .await;
+                }
+                NodeCommand::BroadCastMessageCommand(broadcast_message) => {
+                    if !self.inner.config.is_voting() && broadcast_message.message.is_vote() {
+                        // a non-voting archive node only follows the cluster
+                        continue;
+                    }
+                    self.inner
+                        .observers
+                        .on_message_out(&broadcast_message.message, None);
+                    self.inner.broadcast(&broadcast_message.message).await;
+                }
+                NodeCommand::CatchUpCommand(catch_up) => {
+                    // the peer is caught up from its own task, so that it does not
+                    // wait behind the messages we send to the other peers
                     let inner = self.inner.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = inner.read_message(&mut stream).await {
-                            warn!("Unable to read message from incoming connection {}", e);
+                    logging::spawn(async move {
+                        let destination = inner.membership.addr(catch_up.peer_id);
+                        for message in catch_up.messages {
+                            inner.observers.on_message_out(&message, destination);
+                            if inner.send_to_peer(catch_up.peer_id, message).await.is_err() {
+                                break;
+                            }
                         }
                     });
                 }
-
-                // future representing an incoming message from the consensus engine
-                res = self.rx_node.recv() => {
-                    let cmd = res.unwrap();
-                    match cmd {
-                        NodeCommand::SendMessageCommand(send_message) => {
-                            let _ = self.inner.send_message(&send_message.destination, send_message.message).await;
-                        }
-                        NodeCommand::BroadCastMessageCommand(broadcast_message) => {
-                            self.inner.broadcast(&broadcast_message.message).await;
-                        }
-                    }
-                }
             }
         }
     }
@@ -141,36 +386,722 @@ impl Node {
 
 impl InnerNode {
     pub async fn read_message(&self, stream: &mut TcpStream) -> Result<()> {
-        let mut reader = BufStream::new(stream);
-        let mut buf = String::new();
-        let _ = reader.read_line(&mut buf).await?;
-        let message: Message = serde_json::from_str(&buf)?;
-        //println!("Received {:?} from {}", message, peer_addr);
-
-        if let Message::IdentifierMessage(identifier) = message.clone() {
-            // we received an identifier message from another node
-            // so we record their public key and we do not pass the message to consensus
-            let mut peer_pub_keys = self.peer_pub_keys.lock().await;
-            let peer_id = identifier.id;
-            let peer_pub_key = PublicKey::from_bytes(identifier.pub_key_vec.as_slice()).unwrap();
-            //println!("Received identifier {:?}", peer_id);
-            peer_pub_keys.insert(peer_id, peer_pub_key);
+        let sender = stream.peer_addr()?.ip();
+        let message = if self.is_encrypted(stream).await? {
+            let (data, channel) = match self.read_encrypted(stream).await {
+                Ok(read) => read,
+                Err(TransportError::Io(e)) => {
+                    self.record_read_failure(sender, &e);
+                    return Err(e.into());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let message = match MessageReader::new(data.as_slice()).read().await {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(CodecError::Malformed(frame)) => {
+                    self.record_malformed(sender, frame);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if !self.is_authenticated_by(&channel, &message).await {
+                sampled!(
+                    warn,
+                    "unauthenticated_channel",
+                    "Dropping message over a channel from node {} with an unknown key",
+                    channel.peer_id
+                );
+                return Ok(());
+            }
+            if message
+                .get_id()
+                .is_some_and(|id| id != channel.peer_id && !message.is_relayable())
+            {
+                sampled!(
+                    warn,
+                    "unauthenticated_channel",
+                    "Dropping message from node {:?} over a channel authenticated as {}",
+                    message.get_id(),
+                    channel.peer_id
+                );
+                return Ok(());
+            }
+            message
+        } else {
+            let mut reader = MessageReader::with_limits(&mut *stream, self.config.read_limits());
+            let mut message = match self.read_plaintext(&mut reader, sender).await? {
+                Some(message) => message,
+                None => return Ok(()),
+            };
+            if self.config.encrypt_transport {
+                // only clients may send us plaintext
+                if let Some(id) = message.get_id() {
+                    sampled!(
+                        warn,
+                        "unencrypted_message",
+                        "Dropping unencrypted message from node {}",
+                        id
+                    );
+                    return Ok(());
+                }
+            }
+            // replicas open their connections with their identifier, which associates the
+            // connection with the replica. The identifier is the same on every connection, so
+            // it can be replayed: it only announces the key of the replica, and the messages
+            // that follow are still checked against that key (see `should_drop`)
+            let mut authenticated_id = None;
+            if let Message::IdentifierMessage(identifier) = &message {
+                if !self.accept_identifier(identifier).await {
+                    // the connection claims an id whose key we do not accept
+                    return Ok(());
+                }
+                authenticated_id = Some(identifier.id);
+                message = match self.read_plaintext(&mut reader, sender).await? {
+                    Some(message) => message,
+                    // the identifier was all the peer had to say
+                    None => return Ok(()),
+                };
+            }
+            if let Some(id) = message.get_id() {
+                let is_relayed = authenticated_id.is_some() && message.is_relayable();
+                if authenticated_id != Some(id) && !is_relayed {
+                    sampled!(
+                        warn,
+                        "unauthenticated_connection",
+                        "Dropping message from node {} over a connection authenticated as {:?}",
+                        id,
+                        authenticated_id
+                    );
+                    return Ok(());
+                }
+            }
+            if authenticated_id.is_none() && self.refuses_client_request(&message) {
+                return Ok(());
+            }
+            message
+        };
+        self.process(message, Some(stream)).await
+    }
+
+    /// Takes in a message the in-memory network delivered from the replica, or from a client
+    /// if `from` is None. The network authenticates the sender, as a connection's identifier does
+    pub async fn receive(&self, from: Option<NodeId>, message: Message) -> Result<()> {
+        if let Some(id) = message.get_id() {
+            let is_relayed = from.is_some() && message.is_relayable();
+            if from != Some(id) && !is_relayed {
+                sampled!(
+                    warn,
+                    "unauthenticated_connection",
+                    "Dropping message from node {} delivered from {:?}",
+                    id,
+                    from
+                );
+                return Ok(());
+            }
+        }
+        if from.is_none() && self.refuses_client_request(&message) {
+            return Ok(());
+        }
+        self.process(message, None).await
+    }
+
+    /// Are we draining, so that the request of a client is refused. Client requests
+    /// which peers pass on to us are still taken, as they are part of the instances in flight
+    fn refuses_client_request(&self, message: &Message) -> bool {
+        if !matches!(message, Message::ClientRequestMessage(_))
+            || !self.draining.load(Ordering::SeqCst)
+        {
+            return false;
+        }
+        sampled!(
+            info,
+            "drain_refused_request",
+            "Refusing a client request while draining"
+        );
+        true
+    }
+
+    /// Handles an authenticated message, answering over the connection it arrived on, if any
+    async fn process(&self, message: Message, stream: Option<&mut TcpStream>) -> Result<()> {
+        if let Message::IdentifierMessage(identifier) = &message {
+            // identifiers are not passed to the consensus engine
+            self.accept_identifier(identifier).await;
+            return Ok(());
+        } else if let Message::PingMessage(ping) = &message {
+            let pong = Message::PongMessage(Pong {
+                id: self.id,
+                seq: ping.seq,
+                sent_at_micros: ping.sent_at_micros,
+            });
+            let known_addrs = self.known_addrs(ping.id).await;
+            let _ = self.send(&known_addrs, Some(ping.id), pong).await;
+            return Ok(());
+        } else if let Message::PongMessage(pong) = &message {
+            if self.connectivity.pong(pong) {
+                self.peer_reconnected(pong.id);
+            }
             return Ok(());
         } else if self.should_drop(&message).await {
-            warn!("Dropping message from {:?}", message.get_id());
+            sampled!(
+                warn,
+                "drop_message",
+                "Dropping message from {:?}",
+                message.get_id()
+            );
             return Ok(());
+        } else if self.is_stale(&message) {
+            self.drop_stale_message(&message).await;
+            return Ok(());
+        }
+        self.observers.on_message_in(&message);
+
+        match (&message, stream) {
+            (Message::ClientRequestMessage(request), Some(stream))
+                if request.relay_id == Some(self.id) =>
+            {
+                // the client submitted this request through us, so we keep its connection
+                // open and pass the responses of the cluster back over it
+                return self.relay_client_request(stream, request.clone()).await;
+            }
+            (
+                Message::StatusRequestMessage(_)
+                | Message::WatchLeaderMessage(_)
+                | Message::WatchProgressMessage(_)
+                | Message::CompactLogMessage(_)
+                | Message::GetDiffMessage(_)
+                | Message::GetEvidenceMessage(_),
+                None,
+            ) => {
+                // there is no connection to answer over
+                return Ok(());
+            }
+            (Message::StatusRequestMessage(_), Some(stream)) => {
+                let status_message = Message::StatusMessage(Box::new(self.status()));
+                return with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &status_message),
+                )
+                .await
+                .map_err(|e| e.into());
+            }
+            (Message::GetDiffMessage(get_diff), Some(stream)) => {
+                let diff_message = Message::CheckpointDiffMessage(self.checkpoint_diff(get_diff));
+                return with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &diff_message),
+                )
+                .await
+                .map_err(|e| e.into());
+            }
+            (Message::GetEvidenceMessage(_), Some(stream)) => {
+                let evidence_message = Message::EvidenceMessage(EvidenceReport {
+                    id: self.id,
+                    summary: self.evidence.summary(),
+                });
+                return with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &evidence_message),
+                )
+                .await
+                .map_err(|e| e.into());
+            }
+            (Message::CompactLogMessage(_), Some(stream)) => {
+                let _ = self
+                    .tx_consensus
+                    .send(ConsensusCommand::CompactLog { forced: true })
+                    .await;
+                let status_message = Message::StatusMessage(Box::new(self.status()));
+                return with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &status_message),
+                )
+                .await
+                .map_err(|e| e.into());
+            }
+            (Message::DrainMessage(drain), stream) => {
+                self.draining.store(true, Ordering::SeqCst);
+                let _ = self
+                    .tx_consensus
+                    .send(ConsensusCommand::Drain {
+                        target_seq_num: drain.target_seq_num,
+                    })
+                    .await;
+                let stream = match stream {
+                    Some(stream) => stream,
+                    None => return Ok(()),
+                };
+                let status_message = Message::StatusMessage(Box::new(self.status()));
+                return with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &status_message),
+                )
+                .await
+                .map_err(|e| e.into());
+            }
+            (Message::WatchLeaderMessage(_), Some(stream)) => {
+                return self.watch_leader(stream).await;
+            }
+            (Message::WatchProgressMessage(watch), Some(stream)) => {
+                return self.watch_progress(stream, watch.key.clone()).await;
+            }
+            (Message::StaleMessageNotice(stale_message), _) => {
+                warn!(
+                    "Node {} dropped our message with seq-num {} as stale (its last stable seq-num is {}), we must catch up through a checkpoint",
+                    stale_message.id, stale_message.seq_num, stale_message.last_stable_seq_num
+                );
+                return Ok(());
+            }
+            (Message::RelayedClientResponseMessage(relayed), _) => {
+                let relayed_requests = self.relayed_requests.lock().await;
+                if let Some(tx_relay) =
+                    relayed_requests.get(&(relayed.respond_addr, relayed.response.time_stamp))
+                {
+                    let _ = tx_relay.try_send(relayed.response.clone());
+                }
+                return Ok(());
+            }
+            (Message::ClientRequestMessage(request), _) => {
+                if let Err(busy) = self.enqueue_client_request(request.clone()).await {
+                    let _ = self
+                        .send(
+                            &[request.respond_addr],
+                            None,
+                            Message::ClientResponseMessage(busy),
+                        )
+                        .await;
+                }
+                return Ok(());
+            }
+            _ => {}
         }
 
+        self.pipeline.send(&self.tx_consensus, message).await;
+        Ok(())
+    }
+
+    /// Progress of the consensus engine with the counters of the node
+    pub fn status(&self) -> NodeStatus {
+        NodeStatus {
+            stale_messages_dropped: self.stale_messages_dropped.load(Ordering::Relaxed),
+            pipeline: PipelineStats {
+                capacity: self.tx_consensus.max_capacity(),
+                node_queue_depth: self.tx_node.max_capacity() - self.tx_node.capacity(),
+                node_queue_capacity: self.tx_node.max_capacity(),
+                ..self.pipeline.stats()
+            },
+            malformed: self.malformed.stats(),
+            dead_letters: self.dead_letters.list(),
+            outbox: self.outbox.stats(),
+            clock: self.clock_skew.stats(),
+            connectivity: self.connectivity.stats(),
+            suspected_nodes: self.rx_suspected_nodes.borrow().clone(),
+            ..self.rx_status.borrow().clone()
+        }
+    }
+
+    /// Keys the consensus engine wrote between the stable checkpoints asked for
+    fn checkpoint_diff(&self, get_diff: &GetDiff) -> CheckpointDiff {
+        let (oldest_seq_num, last_stable_seq_num) = self.checkpoint_diffs.range();
+        let to_seq_num = get_diff.to_seq_num.unwrap_or(last_stable_seq_num);
+        let (keys, error) = match self
+            .checkpoint_diffs
+            .diff(get_diff.from_seq_num, Some(to_seq_num))
+        {
+            Ok((keys, _)) => (Some(keys.into_iter().collect()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        CheckpointDiff {
+            id: self.id,
+            from_seq_num: get_diff.from_seq_num,
+            to_seq_num,
+            keys,
+            error,
+            oldest_seq_num,
+            last_stable_seq_num,
+        }
+    }
+
+    /// Passes the client request to the consensus engine, unless the engine is overloaded
+    /// or does not make room for it in time. Protocol messages are never shed, so the
+    /// replica keeps ordering the requests it accepted. Otherwise returns the busy response
+    /// to send the client
+    pub(crate) async fn enqueue_client_request(
+        &self,
+        request: ClientRequest,
+    ) -> std::result::Result<(), ClientResponse> {
+        let (time_stamp, key) = (request.time_stamp, request.key.clone());
+        let overloaded = *self.rx_backpressure.borrow();
+        if !overloaded
+            && self
+                .pipeline
+                .send_timeout(
+                    &self.tx_consensus,
+                    Message::ClientRequestMessage(request),
+                    self.config.enqueue_timeout,
+                )
+                .await
+        {
+            return Ok(());
+        }
+        self.pipeline.record_busy();
+        sampled!(
+            warn,
+            "busy_client_request",
+            "Rejecting a client request as busy ({} rejected)",
+            self.pipeline.stats().busy_rejections
+        );
+        Err(ClientResponse::new_with_signature(
+            &self.keystore,
+            self.id,
+            time_stamp,
+            key,
+            None,
+            Vec::new(),
+            Some(FailureReason::Busy),
+        )
+        .with_capabilities(Capabilities::new(self.config.features)))
+    }
+
+    /// Does the message refer to a sequence number far below our last stable sequence number
+    fn is_stale(&self, message: &Message) -> bool {
+        let last_stable_seq_num = *self.rx_stable_seq_num.borrow();
+        match message.get_seq_num() {
+            Some(seq_num) => seq_num + self.config.stale_message_window < last_stable_seq_num,
+            None => false,
+        }
+    }
+
+    /// Counts the dropped stale message and, if configured, tells the sender that it is lagging
+    async fn drop_stale_message(&self, message: &Message) {
+        let num_dropped = self.stale_messages_dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        let peer_id = message.get_id().unwrap();
+        let seq_num = message.get_seq_num().unwrap();
+        let last_stable_seq_num = *self.rx_stable_seq_num.borrow();
+        sampled!(
+            info,
+            "drop_stale_message",
+            "Dropping stale message with seq-num {} from {} ({} stale messages dropped)",
+            seq_num,
+            peer_id,
+            num_dropped
+        );
+
+        if !self.config.notify_stale_senders {
+            return;
+        }
+        let stale_message =
+            StaleMessage::new_with_signature(&self.keystore, self.id, seq_num, last_stable_seq_num);
         let _ = self
-            .tx_consensus
-            .send(ConsensusCommand::ProcessMessage(message.clone()))
+            .send_to_peer(peer_id, Message::StaleMessageNotice(stale_message))
             .await;
+    }
+
+    /// Records a malformed frame from the sender, who is refused once it sent too many
+    fn record_malformed(&self, sender: IpAddr, frame: MalformedFrame) {
+        sampled!(
+            warn,
+            "malformed_message",
+            "Received a malformed message from {} {}: {:?}",
+            sender,
+            frame,
+            frame.excerpt
+        );
+        let count = self.malformed.record(sender, frame);
+        if count == self.config.max_malformed_frames {
+            warn!(
+                "Refusing connections from {} after {} malformed messages",
+                sender, count
+            );
+        }
+    }
+
+    /// Counts a connection dropped because the peer sent a frame over the maximum length
+    /// or did not send its message in time
+    fn record_read_failure(&self, sender: IpAddr, e: &std::io::Error) {
+        if self.malformed.record_abuse(e) {
+            sampled!(
+                warn,
+                "abusive_connection",
+                "Dropping connection from {}: {}",
+                sender,
+                e
+            );
+        }
+    }
+
+    /// Whether connections from the address are refused for sending too many malformed frames
+    fn is_refused(&self, addr: IpAddr) -> bool {
+        self.config.max_malformed_frames > 0
+            && self.malformed.count(addr) >= self.config.max_malformed_frames
+    }
+
+    /// Forwards a client request submitted through this node to the consensus engine
+    /// and writes the responses we receive for it back over the client's connection
+    /// until every node has responded or the relay times out
+    async fn relay_client_request(
+        &self,
+        stream: &mut TcpStream,
+        request: ClientRequest,
+    ) -> Result<()> {
+        let relay_key = (request.respond_addr, request.time_stamp);
+        let num_nodes = self.membership.num_nodes().max(1);
+        let (tx_relay, mut rx_relay) = channel::<ClientResponse>(num_nodes);
+        self.relayed_requests
+            .lock()
+            .await
+            .insert(relay_key, tx_relay);
+
+        if let Err(busy) = self.enqueue_client_request(request).await {
+            self.relayed_requests.lock().await.remove(&relay_key);
+            let busy = Message::ClientResponseMessage(busy);
+            return with_timeout(
+                self.config.write_timeout,
+                codec::write_message(stream, &busy),
+            )
+            .await
+            .map_err(|e| e.into());
+        }
+
+        let relay_deadline = sleep(self.config.relay_timeout);
+        tokio::pin!(relay_deadline);
+
+        let mut num_relayed = 0;
+        while num_relayed < num_nodes {
+            tokio::select! {
+                Some(response) = rx_relay.recv() => {
+                    let response_message = Message::ClientResponseMessage(response);
+                    if let Err(e) = codec::write_message(stream, &response_message).await {
+                        warn!("Failed to relay response to client {}", e);
+                        break;
+                    }
+                    num_relayed += 1;
+                }
+                _ = &mut relay_deadline => {
+                    break;
+                }
+            }
+        }
+
+        self.relayed_requests.lock().await.remove(&relay_key);
         Ok(())
     }
 
+    /// Announces the primary of our view over the connection, and again whenever we move
+    /// to another view, until the client closes the connection
+    async fn watch_leader(&self, stream: &mut TcpStream) -> Result<()> {
+        let mut rx_status = self.rx_status.clone();
+        let mut announced_view = None;
+        let mut buf = [0u8; 1];
+        loop {
+            let view = rx_status.borrow_and_update().view;
+            if announced_view != Some(view) {
+                let primary = self.config.leader_election.leader_among(
+                    view,
+                    &self.membership.members(),
+                    &self.evidence.excluded(view, self.membership.num_faulty()),
+                );
+                let primary_addr = match self.known_addrs(primary).await.first() {
+                    Some(addr) => *addr,
+                    None => return Ok(()),
+                };
+                let leader = Message::LeaderMessage(Leader {
+                    id: self.id,
+                    view,
+                    primary,
+                    primary_addr,
+                });
+                with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &leader),
+                )
+                .await?;
+                announced_view = Some(view);
+            }
+            tokio::select! {
+                changed = rx_status.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                }
+                // the client sends nothing more, so a read returns once it closes the connection
+                _ = stream.read(&mut buf) => return Ok(()),
+            }
+        }
+    }
+
+    /// Announces the last request we committed, with the version of the key if any,
+    /// over the connection and again after every commit, until the client closes it
+    async fn watch_progress(&self, stream: &mut TcpStream, key: Option<Key>) -> Result<()> {
+        let mut rx_status = self.rx_status.clone();
+        let mut announced = None;
+        let mut buf = [0u8; 1];
+        loop {
+            let last_seq_num_committed = rx_status.borrow_and_update().last_seq_num_committed;
+            let version = match &key {
+                Some(key) => self.key_versions.get(key),
+                None => 0,
+            };
+            if announced != Some((last_seq_num_committed, version)) {
+                let progress = Message::CommitProgressMessage(CommitProgress::new_with_signature(
+                    &self.keystore,
+                    self.id,
+                    last_seq_num_committed,
+                    key.clone(),
+                    version,
+                ));
+                with_timeout(
+                    self.config.write_timeout,
+                    codec::write_message(stream, &progress),
+                )
+                .await?;
+                announced = Some((last_seq_num_committed, version));
+            }
+            tokio::select! {
+                changed = rx_status.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                }
+                // the client sends nothing more, so a read returns once it closes the connection
+                _ = stream.read(&mut buf) => return Ok(()),
+            }
+        }
+    }
+
+    /// Has the consensus engine exchange progress with the peer, which may be behind us
+    /// or have progressed without us. This is queued from a separate task,
+    /// as the consensus engine may be waiting for us to take its commands
+    fn peer_reconnected(&self, peer_id: NodeId) {
+        let tx_consensus = self.tx_consensus.clone();
+        logging::spawn(async move {
+            let _ = tx_consensus
+                .send(ConsensusCommand::PeerReconnected(peer_id))
+                .await;
+        });
+    }
+
+    /// Sends our identifier to every member, with a reading of our clock which echoes
+    /// the last reading of the member
+    async fn broadcast_identifier(&self) {
+        for peer_id in self.membership.members() {
+            let mut identifier = self.identifier.clone();
+            if peer_id != self.id {
+                identifier.clock =
+                    Some(self.clock_skew.sample_for(peer_id, self.clock.now_millis()));
+            }
+            let _ = self
+                .send_to_peer(peer_id, Message::IdentifierMessage(identifier))
+                .await;
+        }
+    }
+
+    /// Pings every peer, each from its own task so that a peer we cannot connect to
+    /// does not delay the pings to the others. Pings are not held for unreachable peers,
+    /// as a late ping measures nothing
+    fn ping_peers(&self) {
+        for peer_id in self.membership.members() {
+            if peer_id == self.id {
+                continue;
+            }
+            let ping = Message::PingMessage(self.connectivity.ping(self.id, peer_id));
+            let inner = self.clone();
+            logging::spawn(async move {
+                let known_addrs = inner.known_addrs(peer_id).await;
+                let _ = inner.send(&known_addrs, Some(peer_id), ping).await;
+            });
+        }
+    }
+
     pub async fn broadcast(&self, message: &Message) {
-        for (_, peer_addr) in self.config.peer_addrs.iter() {
-            let _ = self.send_message(peer_addr, message.clone()).await;
+        for peer_id in self.membership.members() {
+            let _ = self.send_to_peer(peer_id, message.clone()).await;
+        }
+    }
+
+    /// Addresses the peer may be reachable on: the address it advertised
+    /// followed by the address it was configured with, if they differ
+    async fn known_addrs(&self, peer_id: NodeId) -> Vec<SocketAddr> {
+        let mut known_addrs = Vec::new();
+        if let Some(advertised_addr) = self.peer_addrs.lock().await.get(&peer_id) {
+            known_addrs.push(*advertised_addr);
+        }
+        if let Some(configured_addr) = self.membership.addr(peer_id) {
+            if !known_addrs.contains(&configured_addr) {
+                known_addrs.push(configured_addr);
+            }
+        }
+        known_addrs
+    }
+
+    /// Sends the message to the peer over the first of its known addresses we can reach.
+    /// The protocol messages we could not send to the peer while it was unreachable are
+    /// sent ahead of it, and if the peer is still unreachable, the message is held with them
+    pub async fn send_to_peer(&self, peer_id: NodeId, message: Message) -> crate::Result<()> {
+        let known_addrs = self.known_addrs(peer_id).await;
+        let low_water_mark = *self.rx_stable_seq_num.borrow();
+        let mut messages = self.outbox.take(peer_id, low_water_mark);
+        let flushed = messages.len();
+        messages.push(message);
+
+        let mut messages = messages.into_iter();
+        let mut res = Ok(());
+        while let Some(message) = messages.next() {
+            match self
+                .send(&known_addrs, Some(peer_id), message.clone())
+                .await
+            {
+                Err(TransportError::Unreachable(e)) => {
+                    if peer_id != self.id {
+                        self.unreachable_peers.lock().await.insert(peer_id);
+                        self.outbox.hold(
+                            peer_id,
+                            std::iter::once(message).chain(messages),
+                            low_water_mark,
+                        );
+                    }
+                    return Err(Box::new(TransportError::Unreachable(e)));
+                }
+                Err(e) => res = Err(e),
+                Ok(()) => {}
+            }
+        }
+        if self.unreachable_peers.lock().await.remove(&peer_id) {
+            self.outbox.clear(peer_id, flushed);
+            self.peer_reconnected(peer_id);
+        }
+        res?;
+        Ok(())
+    }
+
+    /// Sends the response to the client, retrying with exponential backoff if it cannot
+    /// be reached, and records the response as a dead letter once the retries run out
+    pub async fn send_client_response(&self, respond_addr: SocketAddr, response: ClientResponse) {
+        self.dead_letters.supersede(respond_addr, &response);
+        let mut backoff = self.config.response_retry_backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let message = Message::ClientResponseMessage(response.clone());
+            let e = match self.send(&[respond_addr], None, message).await {
+                Ok(()) => return,
+                Err(e) => e,
+            };
+            if attempts > self.config.response_retries {
+                warn!(
+                    "Giving up on the response to {} for time stamp {} after {} attempts ({})",
+                    respond_addr, response.time_stamp, attempts, e
+                );
+                self.dead_letters.record(DeadLetter {
+                    respond_addr,
+                    response,
+                    attempts,
+                    reason: e.to_string(),
+                });
+                return;
+            }
+            sleep(backoff).await;
+            backoff *= 2;
         }
     }
 
@@ -180,33 +1111,374 @@ impl InnerNode {
         peer_addr: &SocketAddr,
         message: Message,
     ) -> crate::Result<()> {
-        //println!("Sending message {:?} to {:?}", message, peer_addr);
+        match self.peer_at(peer_addr).await {
+            // messages to peers are held while the peer is unreachable
+            Some(peer_id) => self.send_to_peer(peer_id, message).await,
+            None => {
+                self.send(&[*peer_addr], None, message).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends the message over our transport, tampered with by the faults injected into us
+    async fn send(
+        &self,
+        addrs: &[SocketAddr],
+        peer_id: Option<NodeId>,
+        message: Message,
+    ) -> std::result::Result<(), TransportError> {
+        let message = match peer_id {
+            // we only tamper with our own messages, not with those we pass on
+            Some(peer_id) if peer_id != self.id && message.get_id() == Some(self.id) => {
+                match self.faults.tamper(&self.keystore, peer_id, message) {
+                    Some(message) => message,
+                    None => return Ok(()),
+                }
+            }
+            _ => message,
+        };
+        self.transport.send(self, addrs, peer_id, message).await
+    }
+
+    /// Id of the peer reachable on the address, if it is the address of a peer
+    async fn peer_at(&self, addr: &SocketAddr) -> Option<NodeId> {
+        let peer_addrs = self.peer_addrs.lock().await;
+        let configured_addrs = self.membership.addrs();
+        peer_addrs
+            .iter()
+            .chain(configured_addrs.iter())
+            .find(|(_, peer_addr)| *peer_addr == addr)
+            .map(|(peer_id, _)| *peer_id)
+    }
+
+    /// Opens an encrypted channel to the peer and sends the data over it
+    async fn write_encrypted(
+        &self,
+        stream: &mut TcpStream,
+        peer_id: NodeId,
+        data: &[u8],
+    ) -> std::result::Result<(), TransportError> {
+        let expected_key = match self.membership.pub_key(peer_id) {
+            Some(configured_key) => Some(configured_key),
+            None => self.peer_pub_keys.lock().await.get(&peer_id).copied(),
+        };
+        let mut channel = SecureChannel::connect(
+            stream,
+            &self.keystore,
+            self.id,
+            peer_id,
+            expected_key.as_ref(),
+        )
+        .await?;
+        channel.write(stream, data).await
+    }
 
-        let mut stream = BufStream::new(TcpStream::connect(peer_addr).await?);
-        if let Err(e) = stream.get_mut().write(message.serialize().as_slice()).await {
-            warn!("Failed to send to {}", peer_addr);
-            return Err(Box::new(e));
+    /// Whether the connection opens an encrypted channel
+    async fn is_encrypted(&self, stream: &TcpStream) -> Result<bool> {
+        let mut magic = [0u8; HANDSHAKE_MAGIC.len()];
+        // a peer which connects without sending anything must not hold the connection open
+        let peeked = with_timeout(self.config.read_timeout, stream.peek(&mut magic)).await;
+        match peeked {
+            Ok(n) => Ok(n == magic.len() && magic == *HANDSHAKE_MAGIC),
+            Err(e) => {
+                self.record_read_failure(stream.peer_addr()?.ip(), &e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Accepts the encrypted channel the peer opened, and reads the data it sent over it
+    async fn read_encrypted(
+        &self,
+        stream: &mut TcpStream,
+    ) -> std::result::Result<(Vec<u8>, SecureChannel), TransportError> {
+        // the handshake is small, so it must be done within the read timeout
+        let mut channel = with_timeout(self.config.read_timeout, async {
+            let mut magic = [0u8; HANDSHAKE_MAGIC.len()];
+            stream.read_exact(&mut magic).await?;
+            SecureChannel::accept(stream, &self.keystore, self.id).await
+        })
+        .await?;
+        let data = channel
+            .read_within(stream, &self.config.read_limits())
+            .await?;
+        Ok((data, channel))
+    }
+
+    /// Whether the peer authenticated the channel with the key we know for it.
+    /// A peer whose key we do not know yet may only announce its key
+    async fn is_authenticated_by(&self, channel: &SecureChannel, message: &Message) -> bool {
+        if self.peer_pub_keys.lock().await.get(&channel.peer_id) == Some(&channel.peer_pub_key) {
+            return true;
+        }
+        match message {
+            Message::IdentifierMessage(identifier) => {
+                identifier.id == channel.peer_id
+                    && identifier.verified_pub_key() == Some(channel.peer_pub_key)
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes the message to the connection, encrypted if it goes to a peer
+    /// and the transport between replicas is encrypted
+    async fn write_message(
+        &self,
+        mut stream: TcpStream,
+        peer_id: Option<NodeId>,
+        message: Message,
+    ) -> std::result::Result<(), TransportError> {
+        let mut serialized_message = message.serialize();
+        if peer_id.is_some()
+            && !self.config.encrypt_transport
+            && !matches!(message, Message::IdentifierMessage(_))
+        {
+            // our identifier authenticates the plaintext connection before the message,
+            // encrypted channels are authenticated by their handshake
+            let mut handshake = Message::IdentifierMessage(self.identifier.clone()).serialize();
+            handshake.append(&mut serialized_message);
+            serialized_message = handshake;
+        }
+        let res = match peer_id {
+            Some(peer_id) if self.config.encrypt_transport => {
+                with_timeout(
+                    self.config.write_timeout,
+                    self.write_encrypted(&mut stream, peer_id, &serialized_message),
+                )
+                .await
+            }
+            _ => with_timeout(
+                self.config.write_timeout,
+                stream.write_all(serialized_message.as_slice()),
+            )
+            .await
+            .map_err(TransportError::from),
+        };
+        if let Err(e) = res {
+            sampled!(
+                warn,
+                "send_failure",
+                "Failed to send to {:?}",
+                stream.peer_addr()
+            );
+            return Err(e);
         }
         Ok(())
     }
 
+    /// Connects to the first reachable address. Connection attempts are started one after
+    /// another, staggered by the connect attempt delay, and run in parallel, so that an
+    /// unreachable address does not hold up the others. Fails after the connect timeout
+    async fn connect(&self, addrs: &[SocketAddr]) -> std::io::Result<TcpStream> {
+        let mut attempts = JoinSet::new();
+        for (i, addr) in addrs.iter().copied().enumerate() {
+            let delay = self.config.connect_attempt_delay * i as u32;
+            attempts.spawn(async move {
+                sleep(delay).await;
+                TcpStream::connect(addr).await
+            });
+        }
+
+        let connect_any = async move {
+            let mut last_error = None;
+            while let Some(res) = attempts.join_next().await {
+                match res {
+                    Ok(Ok(stream)) => return Ok(stream),
+                    Ok(Err(e)) => last_error = Some(e),
+                    Err(_) => {}
+                }
+            }
+            Err(last_error.unwrap_or_else(|| {
+                std::io::Error::new(ErrorKind::AddrNotAvailable, "no known address")
+            }))
+        };
+        let res = with_timeout(self.config.connect_timeout, connect_any).await;
+        if let Err(e) = &res {
+            sampled!(
+                warn,
+                "connect_failure",
+                "Failed to connect to {:?}: {}",
+                addrs,
+                e
+            );
+        }
+        res
+    }
+
+    /// Reads the next message of a plaintext connection, skipping malformed frames if configured
+    async fn read_plaintext(
+        &self,
+        reader: &mut MessageReader<&mut TcpStream>,
+        sender: IpAddr,
+    ) -> Result<Option<Message>> {
+        loop {
+            match reader.read().await {
+                Ok(message) => return Ok(message),
+                Err(CodecError::Malformed(frame)) => {
+                    self.record_malformed(sender, frame);
+                    if !self.config.skip_malformed_frames {
+                        return Ok(None);
+                    }
+                }
+                Err(CodecError::Io(e)) => {
+                    self.record_read_failure(sender, &e);
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    /// Checks the announced key of a peer against its configured key, or its certificate
+    /// when it has none, then records the key and advertised address of the peer.
+    /// Without either, the first key announced for the id is kept, and identifiers
+    /// announcing another one are rejected. Returns false if the identifier is rejected
+    async fn accept_identifier(&self, identifier: &Identifier) -> bool {
+        let peer_id = identifier.id;
+        let peer_pub_key = match identifier.verified_pub_key() {
+            Some(peer_pub_key) => peer_pub_key,
+            None => {
+                warn!("Dropping improperly signed identifier from {}", peer_id);
+                return false;
+            }
+        };
+        match self.membership.pub_key(peer_id) {
+            Some(configured_key) if configured_key != peer_pub_key => {
+                warn!(
+                    "Dropping identifier from {} with a key other than the configured one",
+                    peer_id
+                );
+                return false;
+            }
+            Some(_) => {}
+            None => {
+                if let Err(e) = self.check_certificate(identifier, &peer_pub_key) {
+                    sampled!(
+                        warn,
+                        "uncertified_identifier",
+                        "Dropping identifier from {}: {}",
+                        peer_id,
+                        e
+                    );
+                    // a key whose certificate expired is no longer trusted
+                    let mut peer_pub_keys = self.peer_pub_keys.lock().await;
+                    if peer_pub_keys.get(&peer_id) == Some(&peer_pub_key) {
+                        peer_pub_keys.remove(&peer_id);
+                    }
+                    return false;
+                }
+                let learned_key = self.peer_pub_keys.lock().await.get(&peer_id).copied();
+                if self.config.root_pub_key.is_none()
+                    && learned_key.is_some_and(|learned_key| learned_key != peer_pub_key)
+                {
+                    // nothing vouches for the new key, so it may be anyone claiming the id
+                    sampled!(
+                        warn,
+                        "unpinned_identifier",
+                        "Dropping identifier from {} with a key other than the one it announced first",
+                        peer_id
+                    );
+                    return false;
+                }
+            }
+        }
+        let prev_pub_key = self
+            .peer_pub_keys
+            .lock()
+            .await
+            .insert(peer_id, peer_pub_key);
+        if peer_id != self.id && prev_pub_key != Some(peer_pub_key) {
+            // the peer started (or restarted with a new key) while we are running
+            self.peer_reconnected(peer_id);
+        }
+        self.peer_addrs
+            .lock()
+            .await
+            .insert(peer_id, identifier.advertised_addr);
+        if let (Some(sample), true) = (&identifier.clock, peer_id != self.id) {
+            self.clock_skew
+                .observe(peer_id, sample, self.clock.now_millis());
+        }
+        true
+    }
+
+    /// Checks the announced key of a node without a configured key is certified by the root
+    /// key of the cluster, if one is configured
+    fn check_certificate(
+        &self,
+        identifier: &Identifier,
+        pub_key: &PublicKey,
+    ) -> std::result::Result<(), CertificateError> {
+        let root_pub_key = match self.config.root_pub_key.as_ref() {
+            Some(root_pub_key) => root_pub_key,
+            None => return Ok(()),
+        };
+        identifier
+            .certificate
+            .as_ref()
+            .ok_or(CertificateError::Missing)?
+            .certifies(identifier.id, pub_key, root_pub_key)
+    }
+
+    /// Checks the signatures of the messages the node handles itself.
+    /// Messages passed on to the consensus engine are verified by the engine
     pub async fn should_drop(&self, message: &Message) -> bool {
-        if let Message::ClientRequestMessage(_) = message {
-            // we should never drop client request messages
+        if !matches!(
+            message,
+            Message::StaleMessageNotice(_) | Message::RelayedClientResponseMessage(_)
+        ) {
             return false;
         }
 
-        let peer_pub_keys = self.peer_pub_keys.lock().await;
         let peer_id = message.get_id().unwrap();
-        if peer_pub_keys.get(&peer_id).is_none() {
+        let peer_pub_key = match self.membership.pub_key(peer_id) {
+            Some(pub_key) => Some(pub_key),
+            None => self.peer_pub_keys.lock().await.get(&peer_id).copied(),
+        };
+        match peer_pub_key {
+            Some(peer_pub_key) => !message.is_properly_signed_by(&peer_pub_key),
             // No public key found for peer id so we drop the message
-            return true;
-        }
-        let peer_pub_key = peer_pub_keys.get(&peer_id).unwrap();
-        if !message.is_properly_signed_by(peer_pub_key) {
-            // The message is not properly signed so we drop it
-            return true;
+            None => true,
         }
-        false
+    }
+}
+
+/// Sends every message over a connection opened for it
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn send<'a>(
+        &'a self,
+        node: &'a InnerNode,
+        addrs: &'a [SocketAddr],
+        peer_id: Option<NodeId>,
+        message: Message,
+    ) -> SendFuture<'a> {
+        Box::pin(async move {
+            let stream = node
+                .connect(addrs)
+                .await
+                .map_err(TransportError::Unreachable)?;
+            node.write_message(stream, peer_id, message).await
+        })
+    }
+}
+
+/// Runs the io future, failing with a timed out error if it does not complete
+/// within the timeout. A zero timeout waits indefinitely
+async fn with_timeout<T, E: From<std::io::Error>>(
+    duration: Duration,
+    fut: impl Future<Output = std::result::Result<T, E>>,
+) -> std::result::Result<T, E> {
+    if duration.is_zero() {
+        return fut.await;
+    }
+    match timeout(duration, fut).await {
+        Ok(res) => res,
+        Err(_) => Err(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!("timed out after {:?}", duration),
+        )
+        .into()),
     }
 }
@@ -0,0 +1,217 @@
+use crate::messages::{ClientRequest, ClientResponse, Message};
+use crate::state::SlotMeta;
+use crate::NodeId;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Hooks into the events of a replica, for embedders which export metrics, audit logs
+/// or other observability data without changing the consensus code.
+/// Observers are called synchronously from the node and the consensus engine,
+/// so they should return quickly. All hooks default to doing nothing
+pub trait Observer: Send + Sync {
+    /// A message from a peer or client passed the signature checks of the node
+    fn on_message_in(&self, _message: &Message) {}
+    /// The node sends a message to the destination, or to every peer if there is none
+    fn on_message_out(&self, _message: &Message, _destination: Option<SocketAddr>) {}
+    /// The replica collected a quorum of votes from the participants
+    fn on_quorum(
+        &self,
+        _kind: QuorumKind,
+        _view: usize,
+        _seq_num: usize,
+        _participants: &[NodeId],
+    ) {
+    }
+    /// The replica applied the request in the slot, which tells the sequence number and view
+    /// it was committed at and the primary which proposed it
+    fn on_commit(&self, _slot: &SlotMeta, _request: &ClientRequest, _response: &ClientResponse) {}
+    /// The replica moved to a new view
+    fn on_view_change(&self, _view: usize) {}
+}
+
+/// The vote a quorum was collected for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QuorumKind {
+    Prepare,
+    Commit,
+    CheckPoint,
+    ViewChange,
+}
+
+/// Observers registered on a replica, shared by its node and consensus engine.
+/// Events are passed on to every registered observer
+#[derive(Clone, Default)]
+pub struct Observers {
+    observers: Arc<RwLock<Vec<Arc<dyn Observer>>>>,
+}
+
+impl Observers {
+    pub fn register(&self, observer: Arc<dyn Observer>) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.read().unwrap().is_empty()
+    }
+}
+
+impl Observer for Observers {
+    fn on_message_in(&self, message: &Message) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_message_in(message);
+        }
+    }
+
+    fn on_message_out(&self, message: &Message, destination: Option<SocketAddr>) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_message_out(message, destination);
+        }
+    }
+
+    fn on_quorum(&self, kind: QuorumKind, view: usize, seq_num: usize, participants: &[NodeId]) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_quorum(kind, view, seq_num, participants);
+        }
+    }
+
+    fn on_commit(&self, slot: &SlotMeta, request: &ClientRequest, response: &ClientResponse) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_commit(slot, request, response);
+        }
+    }
+
+    fn on_view_change(&self, view: usize) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_view_change(view);
+        }
+    }
+}
+
+/// Counts the events of a replica
+#[derive(Default)]
+pub struct MetricsObserver {
+    messages_in: AtomicUsize,
+    messages_out: AtomicUsize,
+    quorums: AtomicUsize,
+    commits: AtomicUsize,
+    rejected_commits: AtomicUsize,
+    view_changes: AtomicUsize,
+}
+
+/// Counts of the events of a replica since it started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Metrics {
+    pub messages_in: usize,
+    pub messages_out: usize,
+    pub quorums: usize,
+    pub commits: usize,
+    /// Applied requests which were rejected, e.g. for exceeding a quota
+    pub rejected_commits: usize,
+    pub view_changes: usize,
+}
+
+impl MetricsObserver {
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            messages_in: self.messages_in.load(Ordering::Relaxed),
+            messages_out: self.messages_out.load(Ordering::Relaxed),
+            quorums: self.quorums.load(Ordering::Relaxed),
+            commits: self.commits.load(Ordering::Relaxed),
+            rejected_commits: self.rejected_commits.load(Ordering::Relaxed),
+            view_changes: self.view_changes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Observer for MetricsObserver {
+    fn on_message_in(&self, _message: &Message) {
+        self.messages_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_message_out(&self, _message: &Message, _destination: Option<SocketAddr>) {
+        self.messages_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_quorum(
+        &self,
+        _kind: QuorumKind,
+        _view: usize,
+        _seq_num: usize,
+        _participants: &[NodeId],
+    ) {
+        self.quorums.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_commit(&self, _slot: &SlotMeta, _request: &ClientRequest, response: &ClientResponse) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        if response.reason.is_some() {
+            self.rejected_commits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_view_change(&self, _view: usize) {
+        self.view_changes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Appends every applied request, quorum and view change of the replica
+/// to a file as one JSON object per line, for export to an audit system
+pub struct AuditLogObserver {
+    file: Mutex<File>,
+}
+
+impl AuditLogObserver {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(&self, entry: serde_json::Value) {
+        let _ = writeln!(self.file.lock().unwrap(), "{}", entry);
+    }
+}
+
+impl Observer for AuditLogObserver {
+    fn on_quorum(&self, kind: QuorumKind, view: usize, seq_num: usize, participants: &[NodeId]) {
+        self.append(serde_json::json!({
+            "event": "quorum",
+            "kind": kind,
+            "view": view,
+            "seq_num": seq_num,
+            "participants": participants,
+        }));
+    }
+
+    fn on_commit(&self, slot: &SlotMeta, request: &ClientRequest, response: &ClientResponse) {
+        self.append(serde_json::json!({
+            "event": "commit",
+            "seq_num": slot.seq_num,
+            "view": slot.view,
+            "leader": slot.leader,
+            "client": request.respond_addr,
+            "client_id": request.client_id,
+            "time_stamp": request.time_stamp,
+            "key": request.key,
+            "operation": request.operation,
+            "result": response.value,
+            "previous": response.previous,
+            "rejected": response.reason.map(|reason| reason.to_string()),
+        }));
+    }
+
+    fn on_view_change(&self, view: usize) {
+        self.append(serde_json::json!({
+            "event": "view_change",
+            "view": view,
+        }));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::messages::Message;
+use crate::NodeId;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Protocol messages we could not send to peers we lost the connection to, held so that
+/// they are sent once the peer is reachable again rather than recovered through view
+/// changes and catch-up. Only short outages are bridged: a peer unreachable for longer
+/// than the retention has its messages dropped, and catches up when it reconnects.
+/// Messages at or below the low water mark are covered by the stable checkpoint,
+/// so they are dropped rather than sent
+#[derive(Clone)]
+pub struct Outbox {
+    capacity: usize,
+    retention: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    queues: HashMap<NodeId, PeerQueue>,
+    stats: OutboxStats,
+}
+
+struct PeerQueue {
+    /// When we first failed to reach the peer
+    since: Instant,
+    messages: VecDeque<Message>,
+}
+
+/// Messages held for unreachable peers, reported in node statuses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxStats {
+    /// Messages currently held, by peer
+    pub held: BTreeMap<NodeId, usize>,
+    /// Held messages sent once their peer was reachable again
+    pub flushed: usize,
+    /// Held messages dropped as the stable checkpoint covered them
+    pub stale: usize,
+    /// Held messages dropped to make room for later ones
+    pub evicted: usize,
+    /// Held messages dropped because their peer stayed unreachable past the retention
+    pub expired: usize,
+}
+
+impl OutboxStats {
+    pub fn total_held(&self) -> usize {
+        self.held.values().sum()
+    }
+}
+
+impl Outbox {
+    /// Holds at most `capacity` messages for each peer (0 to hold none)
+    /// for as long as the peer is unreachable, up to the retention
+    pub fn new(capacity: usize, retention: Duration) -> Self {
+        Self {
+            capacity,
+            retention,
+            inner: Arc::new(Mutex::new(Inner::default())),
+        }
+    }
+
+    /// Messages of the normal case and view change protocols, which are worth holding
+    pub fn is_held(message: &Message) -> bool {
+        matches!(
+            message,
+            Message::PrePrepareMessage(_)
+                | Message::PrepareMessage(_)
+                | Message::CommitMessage(_)
+                | Message::CheckPointMessage(_)
+                | Message::ViewChangeMessage(_)
+                | Message::NewViewMessage(_)
+        )
+    }
+
+    /// Takes the messages held for the peer, in the order they were sent, to send them
+    /// ahead of the next one. The peer is still counted unreachable until it is `cleared`
+    pub fn take(&self, peer_id: NodeId, low_water_mark: usize) -> Vec<Message> {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { queues, stats } = &mut *inner;
+        let queue = match queues.get_mut(&peer_id) {
+            Some(queue) => queue,
+            None => return Vec::new(),
+        };
+        let (stale, messages): (Vec<Message>, Vec<Message>) = queue
+            .messages
+            .drain(..)
+            .partition(|message| is_stale(message, low_water_mark));
+        stats.stale += stale.len();
+        stats.held.remove(&peer_id);
+        messages
+    }
+
+    /// Holds the messages we could not send to the peer, after those still held for it.
+    /// The oldest are dropped once the peer has more than the capacity held
+    pub fn hold(
+        &self,
+        peer_id: NodeId,
+        messages: impl IntoIterator<Item = Message>,
+        low_water_mark: usize,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { queues, stats } = &mut *inner;
+        let queue = queues.entry(peer_id).or_insert_with(|| PeerQueue {
+            since: Instant::now(),
+            messages: VecDeque::new(),
+        });
+        if queue.since.elapsed() > self.retention {
+            // the outage is not short, so the peer catches up once it is back
+            stats.expired +=
+                queue.messages.len() + messages.into_iter().filter(Self::is_held).count();
+            queue.messages.clear();
+            stats.held.remove(&peer_id);
+            return;
+        }
+        for message in messages {
+            if !Self::is_held(&message) {
+                continue;
+            }
+            if is_stale(&message, low_water_mark) {
+                stats.stale += 1;
+                continue;
+            }
+            if queue.messages.len() == self.capacity {
+                queue.messages.pop_front();
+                stats.evicted += 1;
+            }
+            queue.messages.push_back(message);
+        }
+        stats.held.insert(peer_id, queue.messages.len());
+    }
+
+    /// The peer is reachable again and every message held for it was sent
+    pub fn clear(&self, peer_id: NodeId, flushed: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.queues.remove(&peer_id);
+        inner.stats.held.remove(&peer_id);
+        inner.stats.flushed += flushed;
+    }
+
+    pub fn stats(&self) -> OutboxStats {
+        self.inner.lock().unwrap().stats.clone()
+    }
+}
+
+/// Whether the stable checkpoint covers the sequence number of the message
+fn is_stale(message: &Message, low_water_mark: usize) -> bool {
+    match message {
+        // our checkpoint at the low water mark may still complete the proof of the peer
+        Message::CheckPointMessage(checkpoint) => checkpoint.committed_seq_num < low_water_mark,
+        message => message
+            .get_seq_num()
+            .is_some_and(|seq_num| seq_num <= low_water_mark),
+    }
+}
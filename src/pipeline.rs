@@ -0,0 +1,123 @@
+use crate::messages::{ConsensusCommand, Message};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+
+/// Accounting of the messages passed into the consensus engine, shared by the listener
+/// tasks of the node, which enqueue them, and the engine, which dequeues them.
+/// Listener tasks wait for room in the queue as long as it takes for protocol messages,
+/// but only a bounded time for client requests, which they reject as busy after that,
+/// so an overloaded engine shows up as queue depth and busy rejections
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stats: Arc<Mutex<PipelineStats>>,
+}
+
+/// Counts of the messages of one type which went through the pipeline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageCounts {
+    pub enqueued: usize,
+    pub dequeued: usize,
+    /// Messages dropped because the queue stayed full for the enqueue timeout
+    /// (only client requests, which are rejected as busy)
+    pub dropped: usize,
+}
+
+/// State of the pipeline, reported in node statuses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipelineStats {
+    /// Messages enqueued but not yet dequeued by the consensus engine
+    pub depth: usize,
+    /// Highest depth since the node started
+    pub high_watermark: usize,
+    /// Most messages the queue holds
+    #[serde(default)]
+    pub capacity: usize,
+    /// Commands the consensus engine queued for the node to send, and most it holds
+    #[serde(default)]
+    pub node_queue_depth: usize,
+    #[serde(default)]
+    pub node_queue_capacity: usize,
+    /// Client requests rejected as busy, as the engine was overloaded or its queue full
+    #[serde(default)]
+    pub busy_rejections: usize,
+    /// Counts by message type
+    pub by_kind: BTreeMap<String, MessageCounts>,
+}
+
+impl PipelineStats {
+    pub fn dropped(&self) -> usize {
+        self.by_kind.values().map(|counts| counts.dropped).sum()
+    }
+}
+
+impl Pipeline {
+    /// Enqueues the message, waiting as long as it takes for room in the queue
+    pub async fn send(&self, tx_consensus: &Sender<ConsensusCommand>, message: Message) {
+        let kind = message.kind();
+        self.record_enqueued(kind);
+        if tx_consensus
+            .send(ConsensusCommand::ProcessMessage(message))
+            .await
+            .is_err()
+        {
+            self.record_dropped(kind);
+        }
+    }
+
+    /// Enqueues the message, dropping it if the queue stays full for the timeout.
+    /// Returns whether the message was enqueued
+    pub async fn send_timeout(
+        &self,
+        tx_consensus: &Sender<ConsensusCommand>,
+        message: Message,
+        timeout: Duration,
+    ) -> bool {
+        let kind = message.kind();
+        self.record_enqueued(kind);
+        let res = tx_consensus
+            .send_timeout(ConsensusCommand::ProcessMessage(message), timeout)
+            .await;
+        if res.is_err() {
+            self.record_dropped(kind);
+        }
+        res.is_ok()
+    }
+
+    /// Records that a client request was rejected as busy
+    pub fn record_busy(&self) {
+        self.stats.lock().unwrap().busy_rejections += 1;
+    }
+
+    /// Records that the consensus engine took the message off the queue
+    pub fn record_dequeued(&self, message: &Message) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.depth = stats.depth.saturating_sub(1);
+        stats
+            .by_kind
+            .entry(message.kind().to_string())
+            .or_default()
+            .dequeued += 1;
+    }
+
+    fn record_enqueued(&self, kind: &str) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.depth += 1;
+        stats.high_watermark = stats.high_watermark.max(stats.depth);
+        stats.by_kind.entry(kind.to_string()).or_default().enqueued += 1;
+    }
+
+    fn record_dropped(&self, kind: &str) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.depth = stats.depth.saturating_sub(1);
+        stats.by_kind.entry(kind.to_string()).or_default().dropped += 1;
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
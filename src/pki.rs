@@ -0,0 +1,148 @@
+use crate::crypto::{self, SigningInput};
+use crate::keys::{decode_hex, encode_hex};
+use crate::time;
+use crate::NodeId;
+
+use std::path::Path;
+
+use ed25519_dalek::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+
+/// Binds the key of a replica to its id for a validity period, signed by the root key
+/// of the cluster. Replicas present their certificate in their identifiers, so that when
+/// the root key is configured, peers accept a new key of a replica without a change
+/// to their configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdentityCertificate {
+    pub id: NodeId,
+    /// Hex encoded ed25519 public key of the replica
+    pub pub_key: String,
+    /// Start of the validity period, in seconds since the unix epoch
+    pub not_before: u64,
+    /// End of the validity period, in seconds since the unix epoch
+    pub not_after: u64,
+    /// Hex encoded signature of the root key
+    pub signature: String,
+}
+
+impl IdentityCertificate {
+    pub fn issue(
+        root_keypair_bytes: &[u8],
+        id: NodeId,
+        pub_key: &PublicKey,
+        not_before: u64,
+        not_after: u64,
+    ) -> Self {
+        let root_keypair = Keypair::from_bytes(root_keypair_bytes).unwrap();
+        let mut certificate = Self {
+            id,
+            pub_key: encode_hex(pub_key.as_bytes()),
+            not_before,
+            not_after,
+            signature: String::new(),
+        };
+        certificate.signature = encode_hex(&crypto::sign(
+            &root_keypair,
+            &certificate.signing_input(),
+            crypto::policy().algorithm,
+        ));
+        certificate
+    }
+
+    /// Reads a certificate written as JSON, e.g. by `pbft_cert issue`
+    pub fn read(path: &Path) -> Result<Self, CertificateError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| CertificateError::Unreadable(format!("{}: {}", path.display(), e)))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| CertificateError::Unreadable(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Checks the certificate was issued by the root key and is valid at the given time,
+    /// returning the certified key
+    pub fn verify(
+        &self,
+        root_pub_key: &PublicKey,
+        now: u64,
+    ) -> Result<PublicKey, CertificateError> {
+        let signature = decode_hex(&self.signature).map_err(|_| CertificateError::Malformed)?;
+        if !crypto::verify(root_pub_key, &self.signing_input(), &signature) {
+            return Err(CertificateError::BadSignature);
+        }
+        if now < self.not_before {
+            return Err(CertificateError::NotYetValid);
+        }
+        if now > self.not_after {
+            return Err(CertificateError::Expired);
+        }
+        let pub_key = decode_hex(&self.pub_key).map_err(|_| CertificateError::Malformed)?;
+        PublicKey::from_bytes(&pub_key).map_err(|_| CertificateError::Malformed)
+    }
+
+    /// Checks the certificate is valid now and certifies the key for the replica
+    pub fn certifies(
+        &self,
+        id: NodeId,
+        pub_key: &PublicKey,
+        root_pub_key: &PublicKey,
+    ) -> Result<(), CertificateError> {
+        let certified_key = self.verify(root_pub_key, unix_time())?;
+        if self.id != id || certified_key != *pub_key {
+            return Err(CertificateError::WrongIdentity);
+        }
+        Ok(())
+    }
+
+    fn signing_input(&self) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"IdentityCertificate");
+        signing_input.update_usize(self.id);
+        signing_input.update(self.pub_key.as_bytes());
+        signing_input.update(self.not_before.to_be_bytes());
+        signing_input.update(self.not_after.to_be_bytes());
+        signing_input
+    }
+}
+
+/// Seconds since the unix epoch, the clock certificates are checked against
+pub fn unix_time() -> u64 {
+    time::unix_millis() / 1000
+}
+
+/// Reasons a certificate is not accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    /// The certificate could not be read or parsed
+    Unreadable(String),
+    /// The key or signature of the certificate is not properly encoded
+    Malformed,
+    /// The certificate was not issued by the root key
+    BadSignature,
+    NotYetValid,
+    Expired,
+    /// The certificate is for another replica or another key
+    WrongIdentity,
+    /// The replica presented no certificate
+    Missing,
+}
+
+impl std::fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CertificateError::Unreadable(reason) => {
+                write!(f, "certificate unreadable ({})", reason)
+            }
+            CertificateError::Malformed => write!(f, "certificate is malformed"),
+            CertificateError::BadSignature => {
+                write!(f, "certificate is not signed by the root key")
+            }
+            CertificateError::NotYetValid => write!(f, "certificate is not valid yet"),
+            CertificateError::Expired => write!(f, "certificate expired"),
+            CertificateError::WrongIdentity => {
+                write!(f, "certificate is for another node or key")
+            }
+            CertificateError::Missing => write!(f, "no certificate presented"),
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
@@ -0,0 +1,21 @@
+//! Types needed to run a replica or talk to a cluster.
+//! Downstream users should import from here rather than from the individual modules,
+//! whose internals may change between versions.
+
+pub use crate::client::{ClientError, PbftClient as Client};
+pub use crate::config::{Config, ConfigError};
+pub use crate::consensus::Consensus;
+pub use crate::data_dir::DataDirError;
+pub use crate::keys::{KeyError, KeyProvider};
+pub use crate::keystore::Keystore;
+pub use crate::merkle::ProofError;
+pub use crate::messages::{
+    ClientRequest, ClientResponse, ConsensusCommand, ErrorCode, FailureReason, Message,
+    NodeCommand, Operation,
+};
+pub use crate::node::Node;
+pub use crate::pki::CertificateError;
+pub use crate::registry::RegistryError;
+pub use crate::state::SnapshotError;
+pub use crate::transport::TransportError;
+pub use crate::{Key, NodeId, Result, Value};
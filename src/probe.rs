@@ -0,0 +1,182 @@
+use crate::merkle::{verify_key_proof, ProofError};
+use crate::messages::{ClientRequest, ClientResponse, KeyProof};
+use crate::{Key, NodeId, Value};
+
+use std::collections::HashMap;
+
+use ed25519_dalek::PublicKey;
+
+/// Responses of distinct replicas to a request of a probe. A response only counts if it is
+/// signed by the replica it claims to come from and answers the request, so that a relay
+/// passing on the responses of its peers cannot forge or alter them
+pub struct ResponseTally {
+    pub_keys: HashMap<NodeId, PublicKey>,
+    time_stamp: usize,
+    key: Key,
+    threshold: usize,
+    votes: HashMap<NodeId, ClientResponse>,
+    /// Number of responses which did not count
+    pub discarded: usize,
+}
+
+impl ResponseTally {
+    /// Tally of the responses to the request, which are accepted once `threshold` replicas
+    /// agree (f + 1, of which at least one is correct)
+    pub fn new(
+        pub_keys: HashMap<NodeId, PublicKey>,
+        request: &ClientRequest,
+        threshold: usize,
+    ) -> Self {
+        Self {
+            pub_keys,
+            time_stamp: request.time_stamp,
+            key: request.key.clone(),
+            threshold,
+            votes: HashMap::new(),
+            discarded: 0,
+        }
+    }
+
+    /// Counts the response, returning the response agreed on once enough replicas sent a
+    /// matching one. Replicas which respond again are counted with their first response
+    pub fn add(&mut self, response: ClientResponse) -> Option<ClientResponse> {
+        let authentic = self
+            .pub_keys
+            .get(&response.id)
+            .is_some_and(|pub_key| response.is_properly_signed_by(pub_key));
+        if !authentic
+            || response.time_stamp != self.time_stamp
+            || response.key != self.key
+            || self.votes.contains_key(&response.id)
+        {
+            self.discarded += 1;
+            return None;
+        }
+        let num_matching = self
+            .votes
+            .values()
+            .filter(|vote| same_outcome(vote, &response))
+            .count()
+            + 1;
+        self.votes.insert(response.id, response.clone());
+        (num_matching >= self.threshold).then_some(response)
+    }
+
+    /// Replicas whose response counted
+    pub fn voters(&self) -> Vec<NodeId> {
+        let mut voters: Vec<NodeId> = self.votes.keys().copied().collect();
+        voters.sort();
+        voters
+    }
+}
+
+/// Whether the responses agree on the outcome of the request, including the sequence number
+/// it was committed at, which the probe checks the key proofs against
+fn same_outcome(a: &ClientResponse, b: &ClientResponse) -> bool {
+    a.success == b.success && a.value == b.value && a.reason == b.reason && a.seq_num == b.seq_num
+}
+
+/// What a key proof says about a sentinel written at some sequence number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofCheck {
+    /// The checkpoint of the proof covers the write and proves the value written
+    Proven { committed_seq_num: usize },
+    /// The checkpoint of the proof is from before the write, so it cannot prove the value
+    NotYetCovered { committed_seq_num: usize },
+}
+
+/// Reasons a key proof fails a probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofCheckError {
+    /// The proof is for another key than the one asked for
+    OtherKey(Key),
+    /// The proof is not certified or does not include the value
+    Invalid(ProofError),
+    /// The proof is certified, but for another value than the one written
+    Mismatch { proven: Value },
+}
+
+impl std::fmt::Display for ProofCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProofCheckError::OtherKey(key) => write!(f, "proof is for key {}", key),
+            ProofCheckError::Invalid(e) => write!(f, "invalid proof ({})", e),
+            ProofCheckError::Mismatch { proven } => write!(f, "proof is for value {}", proven),
+        }
+    }
+}
+
+impl std::error::Error for ProofCheckError {}
+
+/// Checks the key proof of a sentinel written with the value at the sequence number. The
+/// certificate of the proof is always checked, while the value only has to be proven if the
+/// checkpoint of the proof covers the write
+pub fn check_key_proof(
+    key_proof: &KeyProof,
+    key: &Key,
+    expected: &Value,
+    written_at: usize,
+    pub_keys: &HashMap<NodeId, PublicKey>,
+    num_faulty: usize,
+) -> Result<ProofCheck, ProofCheckError> {
+    if key_proof.key != *key {
+        return Err(ProofCheckError::OtherKey(key_proof.key.clone()));
+    }
+    let committed_seq_num = key_proof.committed_seq_num;
+    let proven = match verify_key_proof(key_proof, pub_keys, num_faulty) {
+        Ok(proven) => Some(proven),
+        // the certificate holds, but the key is not in the state
+        Err(ProofError::MissingProof) => None,
+        Err(e) => return Err(ProofCheckError::Invalid(e)),
+    };
+    if committed_seq_num < written_at {
+        return Ok(ProofCheck::NotYetCovered { committed_seq_num });
+    }
+    match proven {
+        Some(proven) if proven == *expected => Ok(ProofCheck::Proven { committed_seq_num }),
+        Some(proven) => Err(ProofCheckError::Mismatch { proven }),
+        None => Err(ProofCheckError::Invalid(ProofError::MissingProof)),
+    }
+}
+
+/// What the proofs the live replicas sent for a sentinel establish together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofVerdict {
+    /// Every proof holds, and those of these replicas cover the write and prove the value
+    Proven(Vec<NodeId>),
+    /// Every proof holds, but none of their checkpoints covers the write yet
+    NotYetCovered,
+    /// Only this many replicas sent a proof, fewer than the f + 1 which include a correct one
+    TooFewProofs(usize),
+    /// The proofs of these replicas do not hold
+    Invalid(Vec<(NodeId, ProofCheckError)>),
+}
+
+/// Judges the checks of the proofs the replicas sent for a sentinel (see `check_key_proof`)
+pub fn proof_verdict(
+    checks: Vec<(NodeId, Result<ProofCheck, ProofCheckError>)>,
+    num_faulty: usize,
+) -> ProofVerdict {
+    let num_proofs = checks.len();
+    let mut proven_by = Vec::new();
+    let mut invalid = Vec::new();
+    for (id, check) in checks {
+        match check {
+            Ok(ProofCheck::Proven { .. }) => proven_by.push(id),
+            Ok(ProofCheck::NotYetCovered { .. }) => {}
+            Err(e) => invalid.push((id, e)),
+        }
+    }
+    if !invalid.is_empty() {
+        invalid.sort_by_key(|(id, _)| *id);
+        return ProofVerdict::Invalid(invalid);
+    }
+    if num_proofs <= num_faulty {
+        return ProofVerdict::TooFewProofs(num_proofs);
+    }
+    if proven_by.is_empty() {
+        return ProofVerdict::NotYetCovered;
+    }
+    proven_by.sort();
+    ProofVerdict::Proven(proven_by)
+}
@@ -0,0 +1,280 @@
+use crate::config::Config;
+use crate::crypto::{self, SigningInput};
+use crate::data_dir::write_atomically;
+use crate::keys::{decode_hex, encode_hex};
+use crate::keystore::Keystore;
+use crate::limits;
+use crate::membership::{MembershipChange, Reconfiguration};
+use crate::merkle::{self, ProofError};
+use crate::messages::KeyProof;
+use crate::{ClientId, Key, NodeId, Value};
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use ed25519_dalek::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the keys reserved for the metadata of the cluster. Clients may read them,
+/// but only registry updates signed by the root key of the cluster write them
+pub const RESERVED_PREFIX: &str = "_cluster/";
+
+/// Key the registry is stored at. The whole registry is a single entry, so that an
+/// inclusion proof of the entry shows that no part of the registry was left out
+pub fn registry_key() -> Key {
+    Key::from(format!("{}registry", RESERVED_PREFIX))
+}
+
+/// Key the public key of the client with the id is registered at, hex encoded. A client
+/// registers by writing its key there in a request signed with that key, and only the
+/// first registration of an id is accepted
+pub fn client_key(client_id: ClientId) -> Key {
+    Key::from(format!("{}clients/{}", RESERVED_PREFIX, client_id))
+}
+
+/// Id of the client whose public key is registered at the key, if it is a client key
+pub fn client_id_of(key: &Key) -> Option<ClientId> {
+    let prefix = format!("{}clients/", RESERVED_PREFIX);
+    std::str::from_utf8(key.as_bytes().strip_prefix(prefix.as_bytes())?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Key the evidence against the replica is ordered at (see `evidence::Exclusion`). Only the
+/// first evidence against a replica is kept, so the view it is passed over from never moves
+pub fn evidence_key(accused: NodeId) -> Key {
+    Key::from(format!("{}evidence/{}", RESERVED_PREFIX, accused))
+}
+
+/// Replica whose evidence is kept at the key, if it is an evidence key
+pub fn accused_of(key: &Key) -> Option<NodeId> {
+    let prefix = format!("{}evidence/", RESERVED_PREFIX);
+    std::str::from_utf8(key.as_bytes().strip_prefix(prefix.as_bytes())?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+pub fn is_reserved(key: &Key) -> bool {
+    key.as_bytes().starts_with(RESERVED_PREFIX.as_bytes())
+}
+
+/// Authoritative metadata of the cluster, replicated in the store like any other entry,
+/// so that it is covered by the checkpoints replicas sign
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClusterRegistry {
+    /// Incremented by every update, so that an older update cannot be applied again
+    pub version: usize,
+    /// Epoch of the membership, incremented by every reconfiguration
+    #[serde(default)]
+    pub epoch: usize,
+    /// Address of each member of the cluster
+    pub members: BTreeMap<NodeId, SocketAddr>,
+    /// Hex encoded public key of the members whose key is known
+    pub pub_keys: BTreeMap<NodeId, String>,
+    pub num_faulty: usize,
+    pub checkpoint_frequency: usize,
+    pub log_window: usize,
+}
+
+impl ClusterRegistry {
+    /// Registry the replicas start with, taken from the configuration of the cluster
+    /// (which every replica must share) rather than from the settings of a single node
+    pub fn genesis(config: &Config) -> Self {
+        Self {
+            version: 0,
+            epoch: config.epoch,
+            members: config
+                .peer_addrs
+                .iter()
+                .map(|(id, addr)| (*id, *addr))
+                .collect(),
+            pub_keys: config
+                .peer_pub_keys
+                .iter()
+                .map(|(id, pub_key)| (*id, encode_hex(pub_key.as_bytes())))
+                .collect(),
+            num_faulty: config.num_faulty,
+            checkpoint_frequency: config.checkpoint_frequency,
+            log_window: config.log_window,
+        }
+    }
+
+    /// The entry of the registry in the store
+    pub fn encode(&self) -> Value {
+        Value::from(serde_json::to_vec(self).unwrap())
+    }
+
+    pub fn decode(value: &Value) -> Option<Self> {
+        serde_json::from_slice(value.as_bytes()).ok()
+    }
+
+    /// Public keys of the members, skipping any which is not a valid key
+    pub fn pub_keys(&self) -> HashMap<NodeId, PublicKey> {
+        self.pub_keys
+            .iter()
+            .filter_map(|(id, pub_key)| {
+                let bytes = decode_hex(pub_key).ok()?;
+                Some((*id, PublicKey::from_bytes(&bytes).ok()?))
+            })
+            .collect()
+    }
+
+    /// Registry after the reconfiguration, or None if it does not apply to this registry:
+    /// it is not for the next epoch, adds a member twice or beyond the largest cluster, or
+    /// removes a node which is not a member or the last one. The cluster tolerates as many faulty nodes as the new
+    /// number of members allows
+    pub fn reconfigured(&self, reconfiguration: &Reconfiguration) -> Option<ClusterRegistry> {
+        if reconfiguration.epoch != self.epoch + 1 {
+            return None;
+        }
+        let mut registry = self.clone();
+        match &reconfiguration.change {
+            MembershipChange::AddNode { id, addr, pub_key } => {
+                // every member may contribute a checkpoint to the proof of a stable checkpoint
+                if registry.members.contains_key(id)
+                    || registry.members.len() >= limits::MAX_CHECKPOINT_PROOF
+                {
+                    return None;
+                }
+                let bytes = decode_hex(pub_key).ok()?;
+                PublicKey::from_bytes(&bytes).ok()?;
+                registry.members.insert(*id, *addr);
+                registry.pub_keys.insert(*id, pub_key.clone());
+            }
+            MembershipChange::RemoveNode { id } => {
+                if !registry.members.contains_key(id) || registry.members.len() == 1 {
+                    return None;
+                }
+                registry.members.remove(id);
+                registry.pub_keys.remove(id);
+            }
+        }
+        registry.num_faulty = registry.members.len().saturating_sub(1) / 3;
+        registry.version += 1;
+        registry.epoch += 1;
+        Some(registry)
+    }
+
+    /// Verifies a snapshot of the registry against the members and keys of this registry,
+    /// e.g. a snapshot trusted earlier, returning the registry of the snapshot
+    pub fn verify_snapshot(&self, snapshot: &KeyProof) -> Result<ClusterRegistry, RegistryError> {
+        if snapshot.key != registry_key() {
+            return Err(RegistryError::NotARegistry);
+        }
+        let value = merkle::verify_key_proof(snapshot, &self.pub_keys(), self.num_faulty)
+            .map_err(RegistryError::InvalidProof)?;
+        let registry = Self::decode(&value).ok_or(RegistryError::NotARegistry)?;
+        if registry.version < self.version {
+            return Err(RegistryError::Outdated {
+                version: registry.version,
+            });
+        }
+        Ok(registry)
+    }
+}
+
+/// Reads a registry snapshot exported by a replica, verified against the keys of the
+/// registry it carries. This only shows that the snapshot is consistent: whoever
+/// hands it over is trusted for the keys, as with a configuration file. Verify later
+/// snapshots against the registry of a trusted one with `verify_snapshot`
+pub fn read_snapshot(path: &Path) -> Result<(KeyProof, ClusterRegistry), RegistryError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| RegistryError::Unreadable(format!("{}: {}", path.display(), e)))?;
+    let snapshot: KeyProof = serde_json::from_str(&contents)
+        .map_err(|e| RegistryError::Unreadable(format!("{}: {}", path.display(), e)))?;
+    let registry = snapshot
+        .value
+        .as_ref()
+        .and_then(ClusterRegistry::decode)
+        .ok_or(RegistryError::NotARegistry)?;
+    let registry = registry.verify_snapshot(&snapshot)?;
+    Ok((snapshot, registry))
+}
+
+/// Writes a registry snapshot, replacing the previous one at once
+pub fn write_snapshot(path: &Path, snapshot: &KeyProof) -> std::io::Result<()> {
+    write_atomically(path, &serde_json::to_vec_pretty(snapshot).unwrap())
+}
+
+/// New contents of the registry, signed by the root key of the cluster. Clients submit the
+/// update as the value of a write to the registry key, which replicas only apply if it is
+/// properly signed and its version follows the version of the registry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RegistryUpdate {
+    pub registry: ClusterRegistry,
+    /// Hex encoded signature of the root key
+    pub signature: String,
+}
+
+impl RegistryUpdate {
+    pub fn new_with_signature(root_keystore: &Keystore, registry: ClusterRegistry) -> Self {
+        let signature =
+            root_keystore.sign(&Self::signing_input(&registry), crypto::policy().algorithm);
+        Self {
+            registry,
+            signature: encode_hex(&signature),
+        }
+    }
+
+    pub fn is_properly_signed_by(&self, root_pub_key: &PublicKey) -> bool {
+        match decode_hex(&self.signature) {
+            Ok(signature) => crypto::verify(
+                root_pub_key,
+                &Self::signing_input(&self.registry),
+                &signature,
+            ),
+            Err(_) => false,
+        }
+    }
+
+    /// The value written to the registry key to apply the update
+    pub fn encode(&self) -> Value {
+        Value::from(serde_json::to_vec(self).unwrap())
+    }
+
+    pub fn decode(value: &Value) -> Option<Self> {
+        serde_json::from_slice(value.as_bytes()).ok()
+    }
+
+    fn signing_input(registry: &ClusterRegistry) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"RegistryUpdate");
+        signing_input.update(registry.encode().as_bytes());
+        signing_input
+    }
+}
+
+/// Reasons a registry snapshot is not accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    /// The snapshot could not be read or parsed
+    Unreadable(String),
+    /// The snapshot is not a proof of the registry entry
+    NotARegistry,
+    /// The snapshot is not certified by a quorum of the trusted members
+    InvalidProof(ProofError),
+    /// The snapshot is of a registry older than the trusted one
+    Outdated { version: usize },
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::Unreadable(reason) => write!(f, "registry unreadable ({})", reason),
+            RegistryError::NotARegistry => write!(f, "not a snapshot of the cluster registry"),
+            RegistryError::InvalidProof(e) => write!(f, "invalid registry snapshot: {}", e),
+            RegistryError::Outdated { version } => {
+                write!(
+                    f,
+                    "registry version {} is older than the trusted one",
+                    version
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
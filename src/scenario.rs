@@ -0,0 +1,223 @@
+use crate::NodeId;
+
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Test scenario run by the soak tool, described as data so that regression scenarios
+/// are versioned alongside the code rather than written as code. Scenarios are JSON files:
+///
+/// ```json
+/// {
+///   "name": "primary partitioned",
+///   "num_nodes": 4,
+///   "byzantine": [3],
+///   "schedule": [
+///     { "at_secs": 3, "fault": "partition", "nodes": [0] },
+///     { "at_secs": 8, "fault": "heal" }
+///   ],
+///   "workload": { "duration_secs": 20, "num_keys": 8, "write_ratio": 0.5 },
+///   "expect": { "min_succeeded": 10 }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    pub num_nodes: usize,
+    /// Nodes which run as equivocators
+    #[serde(default)]
+    pub byzantine: Vec<NodeId>,
+    /// Faults injected during the run, in the order of their times
+    #[serde(default)]
+    pub schedule: Vec<ScheduledFault>,
+    #[serde(default)]
+    pub workload: Workload,
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+/// Fault injected at the given time after the workload starts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledFault {
+    pub at_secs: u64,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+impl ScheduledFault {
+    pub fn at(&self) -> Duration {
+        Duration::from_secs(self.at_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum Fault {
+    /// Kill the nodes, which stay down until the next heal
+    Stop { nodes: Vec<NodeId> },
+    /// Cut the nodes off from the rest of the cluster (and the clients) until the next heal
+    Partition { nodes: Vec<NodeId> },
+    /// Restart stopped nodes and reconnect partitioned ones
+    Heal,
+}
+
+/// Sequential load of reads and writes against a small set of keys
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Workload {
+    pub duration_secs: u64,
+    pub num_keys: usize,
+    /// Fraction of the operations which are writes
+    pub write_ratio: f64,
+    /// Seed of the workload, random if not set
+    pub seed: Option<u64>,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            duration_secs: 600,
+            num_keys: 8,
+            write_ratio: 0.5,
+            seed: None,
+        }
+    }
+}
+
+impl Workload {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs)
+    }
+}
+
+/// Assertions on the outcome of the run. By default a run passes as long as no read
+/// was stale, no replica diverged and the history of the operations is linearizable
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Expectations {
+    pub min_succeeded: usize,
+    pub max_timed_out: Option<usize>,
+    pub allow_stale_reads: bool,
+    pub allow_divergent_replies: bool,
+    pub allow_non_linearizable: bool,
+}
+
+/// Counts of the operations of a run, checked against the expectations of the scenario
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Outcome {
+    pub operations: usize,
+    pub succeeded: usize,
+    pub rejected: usize,
+    pub timed_out: usize,
+    pub stale_reads: usize,
+    pub divergent_replies: usize,
+    /// Whether the history of the operations is not linearizable
+    pub non_linearizable: bool,
+}
+
+impl Expectations {
+    /// The expectations the outcome does not meet, empty if the run passed
+    pub fn violations(&self, outcome: &Outcome) -> Vec<String> {
+        let mut violations = Vec::new();
+        if outcome.succeeded < self.min_succeeded {
+            violations.push(format!(
+                "{} operations succeeded but at least {} were expected to",
+                outcome.succeeded, self.min_succeeded
+            ));
+        }
+        if let Some(max_timed_out) = self.max_timed_out {
+            if outcome.timed_out > max_timed_out {
+                violations.push(format!(
+                    "{} operations timed out but at most {} were allowed to",
+                    outcome.timed_out, max_timed_out
+                ));
+            }
+        }
+        if !self.allow_stale_reads && outcome.stale_reads > 0 {
+            violations.push(format!("{} reads were stale", outcome.stale_reads));
+        }
+        if !self.allow_divergent_replies && outcome.divergent_replies > 0 {
+            violations.push(format!(
+                "{} replies diverged from the accepted result",
+                outcome.divergent_replies
+            ));
+        }
+        if !self.allow_non_linearizable && outcome.non_linearizable {
+            violations.push(String::from("the history is not linearizable"));
+        }
+        violations
+    }
+}
+
+impl Scenario {
+    /// Reads and validates the scenario in the file
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ScenarioError::Unreadable(e.to_string()))?;
+        let mut scenario: Scenario =
+            serde_json::from_str(&contents).map_err(|e| ScenarioError::Malformed(e.to_string()))?;
+        scenario.schedule.sort_by_key(|scheduled| scheduled.at_secs);
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if self.num_nodes == 0 {
+            return Err(ScenarioError::Invalid(String::from(
+                "the cluster has no nodes",
+            )));
+        }
+        let nodes = self
+            .schedule
+            .iter()
+            .flat_map(|scheduled| match &scheduled.fault {
+                Fault::Stop { nodes } | Fault::Partition { nodes } => nodes.as_slice(),
+                Fault::Heal => &[],
+            })
+            .chain(self.byzantine.iter());
+        for id in nodes {
+            if *id >= self.num_nodes {
+                return Err(ScenarioError::Invalid(format!(
+                    "node {} is not in the cluster of {} nodes",
+                    id, self.num_nodes
+                )));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.workload.write_ratio) {
+            return Err(ScenarioError::Invalid(format!(
+                "write ratio {} is not between 0 and 1",
+                self.workload.write_ratio
+            )));
+        }
+        if self.workload.num_keys == 0 {
+            return Err(ScenarioError::Invalid(String::from(
+                "the workload has no keys",
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScenarioError {
+    /// The scenario file could not be read
+    Unreadable(String),
+    /// The scenario file is not a well formed scenario
+    Malformed(String),
+    /// The scenario refers to nodes outside the cluster or has an impossible workload
+    Invalid(String),
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Unreadable(reason) => write!(f, "scenario unreadable ({})", reason),
+            ScenarioError::Malformed(reason) => write!(f, "scenario malformed ({})", reason),
+            ScenarioError::Invalid(reason) => write!(f, "scenario invalid: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
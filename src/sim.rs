@@ -0,0 +1,855 @@
+use crate::config::Config;
+use crate::consensus::Consensus;
+use crate::keys::encode_hex;
+use crate::keystore::Keystore;
+use crate::linearizability::History;
+use crate::logging::{self, NodeLog};
+use crate::membership::Reconfiguration;
+use crate::messages::{
+    BatchOp, BulkLoadPart, ClientRequest, ClientResponse, FailureReason, GetAt, GetEntry, LogEntry,
+    Message, Operation, ValueAt,
+};
+use crate::node::{InnerNode, Node};
+use crate::registry::{self, ClusterRegistry};
+use crate::time::WallClock;
+use crate::transport::{SendFuture, Transport, TransportError};
+use crate::{ClientId, Key, NodeId, Value};
+
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ed25519_dalek::Keypair;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Instant};
+
+/// Delays and losses of the links of a simulated network
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Fraction of the messages which are lost
+    pub drop_rate: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(20),
+            drop_rate: 0.0,
+        }
+    }
+}
+
+impl LinkConfig {
+    /// Links within a data center: well under a millisecond, and no losses
+    pub fn lan() -> Self {
+        Self {
+            min_delay: Duration::from_micros(100),
+            max_delay: Duration::from_micros(500),
+            drop_rate: 0.0,
+        }
+    }
+
+    /// Links over a mobile network: slow, with delays varying tenfold, and lossy
+    pub fn lossy_mobile() -> Self {
+        Self {
+            min_delay: Duration::from_millis(30),
+            max_delay: Duration::from_millis(300),
+            drop_rate: 0.05,
+        }
+    }
+}
+
+/// Links between replicas placed in regions, whose delays follow the round trip times
+/// between the regions. A message takes half the round trip time of its link, give or
+/// take the jitter
+#[derive(Debug, Clone)]
+pub struct GeoLinks {
+    /// Region of each replica, by id, indexing the matrix. Replicas past the end of the
+    /// list, such as those which join later, are in the first region
+    pub regions: Vec<usize>,
+    /// Region the clients are in
+    pub client_region: usize,
+    /// Round trip time between each pair of regions, the diagonal being the round trip
+    /// time within a region
+    pub rtts: Vec<Vec<Duration>>,
+    /// Fraction of the one-way delay by which the delay of a message varies either way
+    pub jitter: f64,
+    /// Fraction of the messages which are lost
+    pub drop_rate: f64,
+}
+
+impl GeoLinks {
+    /// Replicas spread in turn over three regions (think us-east, eu-west and
+    /// ap-southeast), with the clients in the first
+    pub fn three_regions(num_nodes: usize) -> Self {
+        let ms = Duration::from_millis;
+        Self {
+            regions: (0..num_nodes).map(|id| id % 3).collect(),
+            client_region: 0,
+            rtts: vec![
+                vec![ms(2), ms(80), ms(220)],
+                vec![ms(80), ms(2), ms(160)],
+                vec![ms(220), ms(160), ms(2)],
+            ],
+            jitter: 0.1,
+            drop_rate: 0.0,
+        }
+    }
+
+    /// Round trip time of the link between the replicas, or a client if not a replica
+    pub fn rtt(&self, from: Option<NodeId>, to: Option<NodeId>) -> Duration {
+        let region = |id: Option<NodeId>| match id {
+            Some(id) => self.regions.get(id).copied().unwrap_or(0),
+            None => self.client_region,
+        };
+        self.rtts[region(from)][region(to)]
+    }
+}
+
+/// How the simulated network delays and loses messages, link by link
+#[derive(Debug, Clone)]
+pub enum LinkModel {
+    /// Every link has the same delays and losses
+    Uniform(LinkConfig),
+    /// Replicas are in regions apart from each other
+    Geo(GeoLinks),
+}
+
+impl Default for LinkModel {
+    fn default() -> Self {
+        Self::Uniform(LinkConfig::default())
+    }
+}
+
+impl From<LinkConfig> for LinkModel {
+    fn from(links: LinkConfig) -> Self {
+        Self::Uniform(links)
+    }
+}
+
+impl From<GeoLinks> for LinkModel {
+    fn from(links: GeoLinks) -> Self {
+        Self::Geo(links)
+    }
+}
+
+impl LinkModel {
+    /// A cluster in a single data center (see `LinkConfig::lan`)
+    pub fn lan() -> Self {
+        Self::Uniform(LinkConfig::lan())
+    }
+
+    /// A cluster of the replicas spread over three regions (see `GeoLinks::three_regions`)
+    pub fn wan(num_nodes: usize) -> Self {
+        Self::Geo(GeoLinks::three_regions(num_nodes))
+    }
+
+    /// A cluster whose every link is lossy (see `LinkConfig::lossy_mobile`)
+    pub fn lossy_mobile() -> Self {
+        Self::Uniform(LinkConfig::lossy_mobile())
+    }
+
+    fn drop_rate(&self) -> f64 {
+        match self {
+            LinkModel::Uniform(links) => links.drop_rate,
+            LinkModel::Geo(links) => links.drop_rate,
+        }
+    }
+
+    /// Shortest and longest delay of a message over the link
+    fn delays(&self, from: Option<NodeId>, to: Option<NodeId>) -> (Duration, Duration) {
+        match self {
+            LinkModel::Uniform(links) => (links.min_delay, links.max_delay),
+            LinkModel::Geo(links) => {
+                let delay = links.rtt(from, to) / 2;
+                let jitter = links.jitter.clamp(0.0, 1.0);
+                (delay.mul_f64(1.0 - jitter), delay.mul_f64(1.0 + jitter))
+            }
+        }
+    }
+}
+
+/// Messages the network delivered and lost
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub delivered: usize,
+    pub dropped: usize,
+}
+
+/// In-memory network between the replicas of a simulated cluster and its clients.
+///
+/// Every message is delayed, or lost, as drawn from an RNG seeded with the seed of the network
+/// and the message itself, so that the fate of a message does not depend on the order in which
+/// tasks happen to send. On tokio's paused clock, delays pass as soon as every task is idle,
+/// and a cluster runs deterministically within a single test
+#[derive(Clone)]
+pub struct Network {
+    seed: u64,
+    links: LinkModel,
+    state: Arc<Mutex<NetworkState>>,
+}
+
+#[derive(Default)]
+struct NetworkState {
+    replicas: HashMap<SocketAddr, (Arc<InnerNode>, NodeLog)>,
+    clients: HashMap<SocketAddr, Sender<Message>>,
+    /// Replicas cut off from the rest of the cluster and the clients
+    partitioned: HashSet<NodeId>,
+    /// Links cut between pairs of replicas, lower id first
+    cut: HashSet<(NodeId, NodeId)>,
+    /// Replicas which were stopped, and send or receive nothing until they are resumed
+    stopped: HashSet<NodeId>,
+    /// Times each message was sent over each link, which sets retransmissions apart
+    sent: HashMap<[u8; 32], u64>,
+    stats: NetworkStats,
+}
+
+enum Destination {
+    Replica(NodeId, Arc<InnerNode>, NodeLog),
+    Client(Sender<Message>),
+}
+
+impl Network {
+    pub fn new(seed: u64, links: impl Into<LinkModel>) -> Self {
+        Self {
+            seed,
+            links: links.into(),
+            state: Arc::new(Mutex::new(NetworkState::default())),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Delivers the messages sent to the address to the replica, which logs them to its log
+    pub fn attach_replica(&self, addr: SocketAddr, node: InnerNode, log: NodeLog) {
+        self.state
+            .lock()
+            .unwrap()
+            .replicas
+            .insert(addr, (Arc::new(node), log));
+    }
+
+    /// Opens a client on the address, returning the messages the replicas send it
+    pub fn attach_client(&self, addr: SocketAddr) -> Receiver<Message> {
+        let (tx_client, rx_client) = channel(1024);
+        self.state.lock().unwrap().clients.insert(addr, tx_client);
+        rx_client
+    }
+
+    /// Sends the message of a client to the replica on the address
+    pub fn submit(&self, addr: SocketAddr, message: Message) -> Result<(), TransportError> {
+        self.deliver(None, &[addr], message)
+    }
+
+    /// Cuts the replicas off from the rest of the cluster and the clients until the next heal
+    pub fn partition(&self, nodes: &[NodeId]) {
+        self.state
+            .lock()
+            .unwrap()
+            .partitioned
+            .extend(nodes.iter().copied());
+    }
+
+    /// Cuts the link between the two replicas until the next heal
+    pub fn cut(&self, a: NodeId, b: NodeId) {
+        self.state.lock().unwrap().cut.insert((a.min(b), a.max(b)));
+    }
+
+    /// Takes the replica off the network, as if it crashed, until it is resumed
+    pub fn stop(&self, node: NodeId) {
+        self.state.lock().unwrap().stopped.insert(node);
+    }
+
+    /// Puts the stopped replica back on the network
+    pub fn resume(&self, node: NodeId) {
+        self.state.lock().unwrap().stopped.remove(&node);
+    }
+
+    /// Restores the partitioned replicas and the cut links, but not the stopped replicas
+    pub fn heal(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.partitioned.clear();
+        state.cut.clear();
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.state.lock().unwrap().stats
+    }
+
+    fn deliver(
+        &self,
+        from: Option<NodeId>,
+        addrs: &[SocketAddr],
+        message: Message,
+    ) -> Result<(), TransportError> {
+        let mut state = self.state.lock().unwrap();
+        let (addr, destination) = match addrs.iter().find_map(|addr| {
+            let destination = match state.replicas.get(addr) {
+                Some((node, log)) => Destination::Replica(node.id, node.clone(), log.clone()),
+                None => Destination::Client(state.clients.get(addr)?.clone()),
+            };
+            Some((*addr, destination))
+        }) {
+            Some(found) => found,
+            None => return Err(unreachable(addrs)),
+        };
+        let to = match &destination {
+            Destination::Replica(id, _, _) => Some(*id),
+            Destination::Client(_) => None,
+        };
+        let is_cut_off = |id: Option<NodeId>| id.is_some_and(|id| state.partitioned.contains(&id));
+        if from != to && is_cut_off(from) != is_cut_off(to) {
+            return Err(unreachable(addrs));
+        }
+        let is_stopped = |id: Option<NodeId>| id.is_some_and(|id| state.stopped.contains(&id));
+        if is_stopped(from) || is_stopped(to) {
+            return Err(unreachable(addrs));
+        }
+        if let (Some(from), Some(to)) = (from, to) {
+            if state.cut.contains(&(from.min(to), from.max(to))) {
+                return Err(unreachable(addrs));
+            }
+        }
+
+        let link_digest = Sha256::new()
+            .chain_update(format!("{:?}>{}", from, addr))
+            .chain_update(message.serialize())
+            .finalize();
+        let num_sent = state.sent.entry(link_digest.into()).or_default();
+        *num_sent += 1;
+        let rng_seed = Sha256::new()
+            .chain_update(self.seed.to_be_bytes())
+            .chain_update(link_digest)
+            .chain_update(num_sent.to_be_bytes())
+            .finalize();
+        let mut rng = ChaCha20Rng::from_seed(rng_seed.into());
+        if rng.gen::<f64>() < self.links.drop_rate() {
+            state.stats.dropped += 1;
+            return Ok(());
+        }
+        state.stats.delivered += 1;
+        let (min_delay, max_delay) = self.links.delays(from, to);
+        let delay = min_delay + (max_delay.saturating_sub(min_delay)).mul_f64(rng.gen());
+
+        tokio::spawn(async move {
+            sleep(delay).await;
+            match destination {
+                Destination::Replica(_, node, log) => {
+                    let _ = log.scope(node.receive(from, message)).await;
+                }
+                Destination::Client(tx_client) => {
+                    let _ = tx_client.send(message).await;
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+fn unreachable(addrs: &[SocketAddr]) -> TransportError {
+    TransportError::Unreachable(std::io::Error::new(
+        ErrorKind::ConnectionRefused,
+        format!("no route to {:?}", addrs),
+    ))
+}
+
+impl Transport for Network {
+    fn send<'a>(
+        &'a self,
+        node: &'a InnerNode,
+        addrs: &'a [SocketAddr],
+        _peer_id: Option<NodeId>,
+        message: Message,
+    ) -> SendFuture<'a> {
+        Box::pin(async move { self.deliver(Some(node.id), addrs, message) })
+    }
+}
+
+/// Cluster of replicas running in this process over an in-memory network, whose keys
+/// are derived from the seed of the network
+pub struct Simulation {
+    pub config: Config,
+    pub network: Network,
+    /// Nodes of the replicas, by id, on which observers and faults are registered
+    pub nodes: Vec<InnerNode>,
+    keystores: Vec<Keystore>,
+    /// Configuration each replica started with, which for replicas which joined the cluster
+    /// later has the membership they joined
+    replica_configs: Vec<Config>,
+    /// Tasks of the node and of the consensus engine of each replica
+    tasks: Vec<[JoinHandle<()>; 2]>,
+    /// When the simulation started, from which the clocks of the replicas count
+    started: Instant,
+}
+
+impl Simulation {
+    /// Starts a replica for every address of the configuration. Run this on a paused clock
+    /// (`tokio::time::pause`) for a deterministic run. If the configuration has a write-ahead
+    /// log path or a state path, they are taken as directories in which each replica keeps
+    /// its own log and state backend
+    pub fn start(config: Config, network: Network) -> Self {
+        Self::start_with(config, network, |_, _| {})
+    }
+
+    /// Starts a replica for every address of the configuration, each with the configuration
+    /// as changed for it by `configure`, e.g. to make one of them an archive node
+    pub fn start_with(
+        mut config: Config,
+        network: Network,
+        mut configure: impl FnMut(NodeId, &mut Config),
+    ) -> Self {
+        let mut rng = ChaCha20Rng::seed_from_u64(network.seed);
+        let keystores: Vec<Keystore> = (0..config.num_nodes)
+            .map(|_| Keystore::from_keypair(Keypair::generate(&mut rng)))
+            .collect();
+        config.peer_pub_keys = keystores
+            .iter()
+            .enumerate()
+            .map(|(id, keystore)| (id, keystore.public_key()))
+            .collect();
+        if let Some(wal_dir) = &config.wal_path {
+            std::fs::create_dir_all(wal_dir).unwrap();
+        }
+
+        let replica_configs = (0..keystores.len())
+            .map(|id| {
+                let mut replica_config = config.clone();
+                configure(id, &mut replica_config);
+                replica_config
+            })
+            .collect();
+        let mut sim = Self {
+            replica_configs,
+            config,
+            network,
+            nodes: Vec::new(),
+            keystores,
+            tasks: Vec::new(),
+            started: Instant::now(),
+        };
+        for id in 0..sim.keystores.len() {
+            let (node, tasks) = sim.start_replica(id);
+            sim.nodes.push(node);
+            sim.tasks.push(tasks);
+        }
+        sim
+    }
+
+    fn start_replica(&self, id: NodeId) -> (InnerNode, [JoinHandle<()>; 2]) {
+        let mut config = self.replica_configs[id].clone();
+        config.wal_path = config
+            .wal_path
+            .map(|wal_dir| wal_dir.join(format!("replica-{}.wal", id)));
+        config.state_path = config
+            .state_path
+            .map(|state_dir| state_dir.join(format!("replica-{}.state", id)));
+        let keystore = &self.keystores[id];
+        let log = logging::init(id, &config).expect("could not open the log of the replica");
+        let (tx_consensus, rx_consensus) = channel(config.consensus_queue_capacity);
+        let (tx_node, rx_node) = channel(config.node_queue_capacity);
+        let mut node = Node::new(
+            id,
+            config.clone(),
+            keystore,
+            rx_node,
+            tx_consensus.clone(),
+            tx_node.clone(),
+        );
+        let mut consensus = Consensus::new(
+            id,
+            config,
+            keystore,
+            rx_consensus,
+            tx_consensus,
+            tx_node,
+            node.inner.peer_pub_keys.clone(),
+        );
+        node.inner.rx_backpressure = consensus.subscribe_backpressure();
+        node.inner.rx_stable_seq_num = consensus.subscribe_stable_seq_num();
+        node.inner.rx_status = consensus.subscribe_status();
+        node.inner.rx_suspected_nodes = consensus.subscribe_suspected_nodes();
+        node.inner.observers = consensus.observers();
+        node.inner.pipeline = consensus.pipeline();
+        node.inner.key_versions = consensus.key_versions();
+        node.inner.checkpoint_diffs = consensus.checkpoint_diffs();
+        node.inner.evidence = consensus.evidence();
+        node.inner.membership = consensus.membership();
+        node.inner.connectivity = consensus.connectivity();
+        node.inner.clock = WallClock::simulated(self.started);
+        node.inner.transport = Arc::new(self.network.clone());
+
+        self.network
+            .attach_replica(node.addr, node.inner.clone(), log.clone());
+        let inner = node.inner.clone();
+        let tasks = [
+            tokio::spawn(log.scope(async move { node.run().await })),
+            tokio::spawn(log.scope(async move { consensus.spawn().await })),
+        ];
+        (inner, tasks)
+    }
+
+    /// Stops the replica, which neither sends nor receives anything anymore
+    pub fn stop(&self, id: NodeId) {
+        self.network.stop(id);
+        for task in &self.tasks[id] {
+            task.abort();
+        }
+    }
+
+    /// Stops the replica and starts it again with the same keys, recovering what its
+    /// write-ahead log and state backend hold. The messages in flight to the replica are lost
+    pub fn restart(&mut self, id: NodeId) {
+        self.stop(id);
+        self.network.resume(id);
+        let (node, tasks) = self.start_replica(id);
+        self.nodes[id] = node;
+        self.tasks[id] = tasks;
+    }
+
+    /// Starts a replica with the keys of the keystore, which joins the cluster with the
+    /// membership of the registry, that a reconfiguration adding the replica leads to.
+    /// It fetches the state once the members moved to the epoch of the registry.
+    /// Replicas join with the id after those of the replicas started so far
+    pub fn join(&mut self, id: NodeId, keystore: Keystore, registry: &ClusterRegistry) {
+        assert_eq!(id, self.nodes.len(), "replicas join with the next id");
+        let mut config = self.config.clone();
+        config.apply_membership(registry);
+        self.keystores.push(keystore);
+        self.replica_configs.push(config);
+        let (node, tasks) = self.start_replica(id);
+        self.nodes.push(node);
+        self.tasks.push(tasks);
+    }
+
+    /// Client on the address, which submits its requests to every replica started so far
+    pub fn client(&self, addr: SocketAddr) -> SimClient {
+        SimClient {
+            network: self.network.clone(),
+            rx_client: self.network.attach_client(addr),
+            addr,
+            replica_addrs: self
+                .replica_configs
+                .iter()
+                .enumerate()
+                .filter_map(|(id, config)| config.peer_addrs.get(&id).copied())
+                .collect(),
+            num_faulty: self.config.num_faulty,
+            time_stamp: 0,
+            timeout: Duration::from_secs(10),
+            read_only_timeout: Duration::from_secs(1),
+            history: None,
+            identity: None,
+        }
+    }
+}
+
+/// Client of a simulated cluster, which submits every request to every replica and accepts
+/// the result f + 1 replicas agree on, like `pbft_client`. Reads are first tried read-only,
+/// accepting the value 2f + 1 replicas read from their committed state. Signatures of the
+/// responses are not checked, as the replicas are those of the simulation
+pub struct SimClient {
+    network: Network,
+    rx_client: Receiver<Message>,
+    addr: SocketAddr,
+    replica_addrs: Vec<SocketAddr>,
+    num_faulty: usize,
+    time_stamp: usize,
+    /// How long a request may take before it is given up
+    pub timeout: Duration,
+    /// How long a read-only request may take before the read is ordered instead
+    pub read_only_timeout: Duration,
+    history: Option<History>,
+    /// Id of the client and the key it signs its requests with, if it has an identity
+    identity: Option<(ClientId, Arc<Keypair>)>,
+}
+
+impl SimClient {
+    /// Records the reads and writes of the client, but not its batches, to the history,
+    /// to check that they are linearizable
+    pub fn with_history(mut self, history: &History) -> Self {
+        self.history = Some(history.clone());
+        self
+    }
+
+    /// Signs the requests as the client with the id (see `PbftClient::with_identity`)
+    pub fn with_identity(mut self, client_id: ClientId, keypair: Keypair) -> Self {
+        self.identity = Some((client_id, Arc::new(keypair)));
+        self
+    }
+
+    /// Registers the public key of the identity of the client, returning the response the
+    /// replicas agreed on
+    pub async fn register(&mut self) -> Option<ClientResponse> {
+        let (client_id, keypair) = self.identity.clone()?;
+        let value = Value::from(encode_hex(keypair.public.as_bytes()));
+        self.execute(registry::client_key(client_id), Operation::Set(value))
+            .await
+    }
+
+    /// Reads the key, returning the response the replicas agreed on, or None if the request
+    /// timed out. Unless 2f + 1 replicas answer the read-only request alike in time,
+    /// the read is ordered like a write
+    pub async fn get(&mut self, key: Key) -> Option<ClientResponse> {
+        let id = self.invoke(&key, &Operation::Get);
+        let response = self.read(key).await;
+        self.complete(id, &response);
+        response
+    }
+
+    async fn read(&mut self, key: Key) -> Option<ClientResponse> {
+        let mut request = self.request(key.clone(), Operation::Get, Vec::new());
+        request.read_only = true;
+        let time_stamp = request.time_stamp;
+        let messages = vec![Message::ClientRequestMessage(request)];
+        let quorum = 2 * self.num_faulty + 1;
+        if let Some(response) = self
+            .collect(messages, time_stamp, quorum, self.read_only_timeout)
+            .await
+        {
+            return Some(response);
+        }
+        self.submit(key, Operation::Get, Vec::new()).await
+    }
+
+    /// Sets the key, returning the response the replicas agreed on
+    pub async fn put(&mut self, key: Key, value: Value) -> Option<ClientResponse> {
+        self.execute(key, Operation::Set(value)).await
+    }
+
+    /// Removes the key, returning the response the replicas agreed on
+    pub async fn delete(&mut self, key: Key) -> Option<ClientResponse> {
+        self.execute(key, Operation::Delete).await
+    }
+
+    /// Submits the reconfiguration of the membership, returning the response the replicas
+    /// agreed on
+    pub async fn reconfigure(
+        &mut self,
+        reconfiguration: &Reconfiguration,
+    ) -> Option<ClientResponse> {
+        let value = reconfiguration.encode();
+        self.execute(registry::registry_key(), Operation::Set(value))
+            .await
+    }
+
+    /// Sets the key to the new value if it has the expected value,
+    /// returning the response the replicas agreed on
+    pub async fn cas(
+        &mut self,
+        key: Key,
+        expected: Option<Value>,
+        new: Value,
+    ) -> Option<ClientResponse> {
+        self.execute(key, Operation::Cas { expected, new }).await
+    }
+
+    /// Applies the writes atomically, returning the response the replicas agreed on
+    pub async fn batch(&mut self, ops: Vec<BatchOp>) -> Option<ClientResponse> {
+        self.submit(Key::default(), Operation::Get, ops).await
+    }
+
+    /// Streams the writes to the replicas as a bulk load, in parts of the given number of
+    /// operations, returning the summary of the load the replicas agreed on
+    pub async fn bulk_load(
+        &mut self,
+        ops: Vec<BatchOp>,
+        part_len: usize,
+    ) -> Option<ClientResponse> {
+        self.time_stamp += 1;
+        let num_parts = ops.len().div_ceil(part_len).max(1);
+        let mut parts = ops.chunks(part_len);
+        let parts: Vec<Message> = (0..num_parts)
+            .map(|index| {
+                Message::BulkLoadMessage(BulkLoadPart {
+                    respond_addr: self.addr,
+                    time_stamp: self.time_stamp,
+                    offset: index * part_len,
+                    ops: parts.next().map(<[BatchOp]>::to_vec).unwrap_or_default(),
+                    last: index + 1 == num_parts,
+                })
+            })
+            .collect();
+        let timeout = self.timeout.saturating_mul(num_parts as u32);
+        self.collect(parts, self.time_stamp, self.num_faulty + 1, timeout)
+            .await
+    }
+
+    async fn execute(&mut self, key: Key, operation: Operation) -> Option<ClientResponse> {
+        let id = self.invoke(&key, &operation);
+        let response = self.submit(key, operation, Vec::new()).await;
+        self.complete(id, &response);
+        response
+    }
+
+    /// Records the invocation of the operation, if the client records a history
+    fn invoke(&self, key: &Key, operation: &Operation) -> Option<usize> {
+        let history = self.history.as_ref()?;
+        Some(history.invoke(self.addr, key.clone(), operation.clone()))
+    }
+
+    fn complete(&self, id: Option<usize>, response: &Option<ClientResponse>) {
+        if let (Some(history), Some(id), Some(response)) = (&self.history, id, response) {
+            history.complete(id, response);
+        }
+    }
+
+    async fn submit(
+        &mut self,
+        key: Key,
+        operation: Operation,
+        batch: Vec<BatchOp>,
+    ) -> Option<ClientResponse> {
+        let request = self.request(key, operation, batch);
+        let time_stamp = request.time_stamp;
+        let messages = vec![Message::ClientRequestMessage(request)];
+        self.collect(messages, time_stamp, self.num_faulty + 1, self.timeout)
+            .await
+    }
+
+    /// Next request of the client
+    fn request(&mut self, key: Key, operation: Operation, batch: Vec<BatchOp>) -> ClientRequest {
+        self.time_stamp += 1;
+        let request = ClientRequest {
+            respond_addr: self.addr,
+            time_stamp: self.time_stamp,
+            key,
+            operation,
+            relay_id: None,
+            read_only: false,
+            batch,
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        };
+        match &self.identity {
+            Some((client_id, keypair)) => request.signed_by(*client_id, keypair),
+            None => request,
+        }
+    }
+
+    /// Submits the messages of the request with the timestamp to every replica, returning the
+    /// response a quorum of them agreed on, or None if it timed out or every replica answered
+    /// without a quorum agreeing. The messages are submitted again the first time a replica
+    /// reports the request unordered
+    async fn collect(
+        &mut self,
+        messages: Vec<Message>,
+        time_stamp: usize,
+        quorum: usize,
+        timeout: Duration,
+    ) -> Option<ClientResponse> {
+        self.submit_all(&messages);
+
+        let deadline = Instant::now() + timeout;
+        let mut responses: HashMap<NodeId, ClientResponse> = HashMap::new();
+        let mut resubmitted = false;
+        loop {
+            let response = match timeout_at(deadline, self.rx_client.recv()).await {
+                Ok(Some(Message::ClientResponseMessage(response))) => response,
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return None,
+            };
+            if response.time_stamp != time_stamp {
+                continue;
+            }
+            if let Some(FailureReason::Unordered { .. }) = response.reason {
+                if !resubmitted {
+                    resubmitted = true;
+                    self.submit_all(&messages);
+                }
+                continue;
+            }
+            responses.insert(response.id, response.clone());
+            let num_matching = responses
+                .values()
+                .filter(|other| {
+                    (
+                        &other.value,
+                        &other.previous,
+                        other.success,
+                        &other.reason,
+                        &other.results,
+                        &other.bulk,
+                    ) == (
+                        &response.value,
+                        &response.previous,
+                        response.success,
+                        &response.reason,
+                        &response.results,
+                        &response.bulk,
+                    )
+                })
+                .count();
+            if num_matching >= quorum {
+                return Some(response);
+            }
+            if responses.len() == self.replica_addrs.len() {
+                return None;
+            }
+        }
+    }
+
+    /// Entry of the log of the replica at the sequence number, or None if it did not answer
+    /// in time (see `GetEntry`)
+    pub async fn get_entry(&mut self, id: NodeId, seq_num: usize) -> Option<LogEntry> {
+        let get_entry = Message::GetEntryMessage(GetEntry {
+            respond_addr: self.addr,
+            seq_num,
+        });
+        let _ = self.network.submit(*self.replica_addrs.get(id)?, get_entry);
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match timeout_at(deadline, self.rx_client.recv()).await {
+                Ok(Some(Message::LogEntryMessage(entry)))
+                    if entry.id == id && entry.seq_num == seq_num =>
+                {
+                    return Some(*entry)
+                }
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+
+    /// Value of the key the replica had after applying the sequence number, or None if it
+    /// did not answer in time (see `GetAt`)
+    pub async fn get_at(&mut self, id: NodeId, key: Key, seq_num: usize) -> Option<ValueAt> {
+        let get_at = Message::GetAtMessage(GetAt {
+            respond_addr: self.addr,
+            key: key.clone(),
+            seq_num,
+        });
+        let _ = self.network.submit(*self.replica_addrs.get(id)?, get_at);
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match timeout_at(deadline, self.rx_client.recv()).await {
+                Ok(Some(Message::ValueAtMessage(value_at)))
+                    if value_at.id == id && value_at.key == key && value_at.seq_num == seq_num =>
+                {
+                    return Some(value_at)
+                }
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+
+    fn submit_all(&self, messages: &[Message]) {
+        for message in messages {
+            for addr in self.replica_addrs.iter() {
+                let _ = self.network.submit(*addr, message.clone());
+            }
+        }
+    }
+}
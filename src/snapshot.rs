@@ -0,0 +1,116 @@
+use crate::crypto::{self, SigningInput};
+use crate::data_dir::write_atomically;
+use crate::keystore::Keystore;
+use crate::limits;
+use crate::messages::CheckPoint;
+use crate::state::{store_digest, SnapshotError, StateSnapshot};
+use crate::NodeId;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use ed25519_dalek::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Version of the format of snapshot files written by this build. Files of other versions
+/// are not imported
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// Backup of the state of a replica at its last stable checkpoint, which operators import
+/// to restore a replica or seed a new one. The certificate of the checkpoint vouches for the
+/// store, and the replica which exported the file signs it as a whole
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub version: u32,
+    /// Replica which exported the snapshot
+    pub id: NodeId,
+    pub epoch: usize,
+    /// View the replica was in when it exported the snapshot. Importers move to the view of
+    /// the checkpoint, which the certificate vouches for
+    pub view: usize,
+    pub snapshot: StateSnapshot,
+    /// Checkpoints of 2f + 1 replicas certifying the store at its sequence number
+    pub proof: Vec<CheckPoint>,
+    #[serde(with = "limits::signature")]
+    pub signature: Vec<u8>,
+}
+
+impl SnapshotFile {
+    /// Snapshot of the stable checkpoint the proof certifies, signed by the replica
+    pub fn new_with_signature(
+        keystore: &Keystore,
+        id: NodeId,
+        epoch: usize,
+        view: usize,
+        snapshot: StateSnapshot,
+        proof: Vec<CheckPoint>,
+    ) -> Self {
+        let mut file = Self {
+            version: SNAPSHOT_FORMAT_VERSION,
+            id,
+            epoch,
+            view,
+            snapshot,
+            proof,
+            signature: Vec::new(),
+        };
+        file.signature = keystore.sign(
+            &file.signing_input(),
+            crypto::policy().algorithm_at(file.snapshot.committed_seq_num),
+        );
+        file
+    }
+
+    /// The signature covers the digest of the store the certificate vouches for rather than
+    /// the entries, which the digest binds
+    fn signing_input(&self) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        signing_input.update(b"SnapshotFile");
+        signing_input.update(self.version.to_be_bytes());
+        signing_input.update_usize(self.id);
+        signing_input.update_usize(self.view);
+        signing_input.update_usize(self.snapshot.committed_seq_num);
+        signing_input.update(self.state_digest());
+        signing_input.update_epoch(self.epoch);
+        signing_input
+    }
+
+    /// Digest of the certified state, as the checkpoints of the proof carry it
+    pub fn state_digest(&self) -> &[u8] {
+        self.proof
+            .first()
+            .map_or(&[], |checkpoint| checkpoint.state_digest.as_slice())
+    }
+
+    /// Checks the version of the file, the signature of the replica which exported it and that
+    /// the store is the one the certificate vouches for. The certificate itself is checked
+    /// against the keys of the cluster by `State::validate_certificate`
+    pub fn verify(&self, pub_keys: &HashMap<NodeId, PublicKey>) -> Result<(), SnapshotError> {
+        if self.version != SNAPSHOT_FORMAT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(self.version));
+        }
+        match pub_keys.get(&self.id) {
+            Some(pub_key) if crypto::verify(pub_key, &self.signing_input(), &self.signature) => {}
+            _ => return Err(SnapshotError::InvalidSignature { id: self.id }),
+        }
+        let algorithm =
+            crypto::algorithm_of(self.state_digest()).ok_or(SnapshotError::MismatchedDigest)?;
+        let digest = store_digest(&self.snapshot.store, &self.snapshot.key_owners, algorithm);
+        if digest != self.state_digest() {
+            return Err(SnapshotError::MismatchedDigest);
+        }
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SnapshotError> {
+        let contents = std::fs::read(path)
+            .map_err(|e| SnapshotError::Unreadable(format!("{}: {}", path.display(), e)))?;
+        serde_json::from_slice(&contents)
+            .map_err(|e| SnapshotError::Unreadable(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Writes the snapshot, replacing the previous one at once
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        write_atomically(path, &serde_json::to_vec(self)?)
+    }
+}
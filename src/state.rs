@@ -1,15 +1,36 @@
+use crate::bulk::BulkProgress;
 use crate::config::Config;
-use crate::message_bank::MessageBank;
+use crate::crypto::{self, DigestAlgorithm};
+use crate::diagnostics::QuorumDiagnostics;
+use crate::diffs::CheckpointDiffs;
+use crate::evidence::{check_new_view, EvidenceLog, Exclusion};
+use crate::future_view::FutureViewBuffer;
+use crate::keys::decode_hex;
+use crate::keystore::Keystore;
+use crate::logging::sampled;
+use crate::membership::Reconfiguration;
+use crate::merkle;
+use crate::merkle::{LeafCache, RangeProof};
+use crate::message_bank::{AppliedCommit, MessageBank};
 use crate::messages::{
-    CheckPoint, ClientRequest, Commit, NewView, PrePrepare, Prepare, ViewChange,
+    BatchOp, BulkBatch, BulkLoadSummary, CheckPoint, ClientIdentity, ClientRequest, ClientResponse,
+    Commit, EquivocationProof, FailureReason, KeyProof, NewView, Operation, PrePrepare, Prepare,
+    RequestStatus, StateEntry, ViewChange,
 };
+use crate::registry::{self, ClusterRegistry, RegistryUpdate};
+use crate::snapshot::SnapshotFile;
+use crate::versions::KeyVersions;
 
-use crate::{Key, NodeId, Value};
+use crate::{ClientId, Key, NodeId, Value};
 
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-use ed25519_dalek::{Digest, Sha512};
+use ed25519_dalek::PublicKey;
 use log::warn;
+use serde::{Deserialize, Serialize};
 
 #[derive(Default)]
 pub struct State {
@@ -19,6 +40,8 @@ pub struct State {
     pub id: NodeId,
     /// Has this node issued a view-change message which has not been resolved
     pub in_view_change: bool,
+    /// View our latest view change message is for, while we are in a view change
+    pub pending_view: usize,
     /// Current view we are in
     pub view: usize,
     /// Used by the leader to determine the next sequence number to assign a client request
@@ -30,9 +53,9 @@ pub struct State {
     /// Maps (view, seq_num) to Ids of nodes who we
     /// have accepted prepare messages from for the associated transaction
     pub prepare_votes: HashMap<(usize, usize), HashMap<NodeId, Prepare>>,
-    /// Maps (view, seq_num) to Ids of nodes who we
-    /// have accepted prepare messages from for the associated transaction
-    pub commit_votes: HashMap<(usize, usize), HashSet<NodeId>>,
+    /// Maps (view, seq_num) to the commit messages we have accepted
+    /// from each node for the associated transaction
+    pub commit_votes: HashMap<(usize, usize), HashMap<NodeId, Commit>>,
     /// Maps (seq_num, digest) pair to the Ids of the nodes
     /// who we have received corresponding checkpoints from
     pub checkpoint_votes: HashMap<(usize, Vec<u8>), HashSet<NodeId>>,
@@ -47,24 +70,180 @@ pub struct State {
     /// Maps node Ids to view change messages we have received for the subsequent view
     /// Note that we only ever maintain <= 1 view_change message from any given node
     pub view_change_votes: HashMap<NodeId, ViewChange>,
+    /// Digests of the client requests the new primary is fetching
+    /// before it can broadcast the new view
+    pub awaiting_request_bodies: HashSet<Vec<u8>>,
+    /// Messages for views after the current one, processed once we move to their view
+    pub future_view_messages: FutureViewBuffer,
     /// Structure storing all messages, including log
-    pub message_bank: MessageBank,
+    pub(crate) message_bank: MessageBank,
     /// Key-Value store which the system actually maintains
     pub store: BTreeMap<Key, Value>,
+    /// Client which created each key in the store: its id if it signed the request,
+    /// else its response address
+    pub key_owners: BTreeMap<Key, ClientIdentity>,
+    /// Leaf hashes of the store, so that the digest only hashes the entries written since
+    /// the last one again
+    leaf_cache: Mutex<LeafCache>,
+    /// Space used by the store
+    pub total_usage: StoreUsage,
+    /// Space used by the keys each client created. Clients without an id are known by the
+    /// response address they set, so their quotas are only advisory: a client can spread
+    /// its keys over addresses of its own choosing
+    pub client_usage: HashMap<ClientIdentity, StoreUsage>,
+    /// Last reply we sent to each client (identified by its response address),
+    /// which is sent again if the client retransmits the request
+    pub reply_cache: HashMap<ClientIdentity, ClientResponse>,
+    /// Bulk load of each client whose last batch we did not apply yet
+    pub bulk_loads: HashMap<SocketAddr, BulkProgress>,
+    /// Participation of nodes in the quorums we formed
+    pub quorum_diagnostics: QuorumDiagnostics,
+    /// State of the store at each stable checkpoint, indexed by sequence number
+    /// This is only maintained by archive nodes
+    pub archived_snapshots: BTreeMap<usize, BTreeMap<Key, Value>>,
+    /// Sequence number of the last request a checkpoint was due after
+    pub last_checkpoint_boundary: usize,
+    /// Requests applied since the last checkpoint boundary
+    pub log_growth: LogGrowth,
+    /// Sequence number of the last write to each key, reported to clients watching it
+    pub key_versions: KeyVersions,
+    /// Keys written between the last stable checkpoints
+    pub checkpoint_diffs: CheckpointDiffs,
+    /// Proof that the primary of the view equivocated, for the current view and those we
+    /// have not moved past yet
+    pub equivocation_proofs: BTreeMap<usize, EquivocationProof>,
+    /// Evidence we hold against replicas proven faulty, which are passed over as primary
+    pub evidence: EvidenceLog,
+    /// Our snapshots of the store at the checkpoints which are not stable yet and at the last
+    /// stable one, which lagging replicas fetch and key proofs are made against
+    pub checkpoint_snapshots: BTreeMap<usize, StateSnapshot>,
+    /// Sequence number of the reconfiguration we applied, while we wait for its checkpoint
+    /// to become stable before we move to the next epoch. Requests after it are not applied
+    pub pending_reconfiguration: Option<usize>,
+}
+
+/// State of the store at a checkpoint, which checkpoint messages only carry the Merkle root of
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub committed_seq_num: usize,
+    pub store: BTreeMap<Key, Value>,
+    /// Client which created each key in the store, used for quota accounting
+    pub key_owners: BTreeMap<Key, ClientIdentity>,
+}
+
+impl StateSnapshot {
+    /// Up to `len` entries from position `start`, with a proof of their inclusion in the
+    /// state digest made with the algorithm (None if the store is empty)
+    pub fn chunk(
+        &self,
+        start: usize,
+        len: usize,
+        algorithm: DigestAlgorithm,
+    ) -> (Vec<StateEntry>, Option<RangeProof>) {
+        let entries: Vec<StateEntry> = self
+            .store
+            .iter()
+            .skip(start)
+            .take(len)
+            .map(|(key, value)| StateEntry {
+                key: key.clone(),
+                value: value.clone(),
+                owner: self.key_owners.get(key).copied(),
+            })
+            .collect();
+        let proof = merkle::prove_range(
+            &self.store,
+            &self.key_owners,
+            start,
+            start + entries.len(),
+            algorithm,
+        );
+        (entries, proof)
+    }
 }
 impl State {
+    /// Initial state of the node, before it took part in any view.
+    /// The store starts with the registry of the cluster
+    pub fn new(id: NodeId, config: Config) -> Self {
+        let mut store = BTreeMap::new();
+        store.insert(
+            registry::registry_key(),
+            ClusterRegistry::genesis(&config).encode(),
+        );
+        let retained_diffs = if config.is_archive {
+            0
+        } else {
+            config.retained_diffs
+        };
+        Self {
+            store,
+            checkpoint_diffs: CheckpointDiffs::new(retained_diffs),
+            future_view_messages: FutureViewBuffer::new(
+                config.future_view_buffer_size,
+                config.future_view_window,
+            ),
+            view: config.initial_view,
+            pending_view: config.initial_view,
+            config,
+            id,
+            ..Default::default()
+        }
+    }
+
     pub fn current_leader(&self) -> NodeId {
         self.get_leader_for_view(self.view)
     }
 
+    /// Primary of the view among the members of our epoch, passing over the replicas whose
+    /// evidence was ordered to exclude them from an earlier view
     pub fn get_leader_for_view(&self, view: usize) -> NodeId {
-        view % self.config.num_nodes
+        let mut members: Vec<NodeId> = self.config.peer_addrs.keys().copied().collect();
+        members.sort_unstable();
+        self.config.leader_election.leader_among(
+            view,
+            &members,
+            &self.evidence.excluded(view, self.config.num_faulty),
+        )
+    }
+
+    /// Sequence numbers up to the low water mark are covered by the last stable checkpoint
+    pub fn low_water_mark(&self) -> usize {
+        self.last_stable_seq_num
+    }
+
+    /// Highest sequence number a pre-prepare is accepted for, which bounds the log
+    /// a faulty primary can make us keep
+    pub fn high_water_mark(&self) -> usize {
+        self.last_stable_seq_num
+            .saturating_add(self.config.log_window)
+    }
+
+    pub fn is_within_water_marks(&self, seq_num: usize) -> bool {
+        seq_num > self.low_water_mark() && seq_num <= self.high_water_mark()
     }
 
     pub fn should_accept_pre_prepare(&self, pre_prepare: &PrePrepare) -> bool {
         if self.in_view_change {
             return false;
         }
+        if self
+            .pending_reconfiguration
+            .is_some_and(|seq_num| pre_prepare.seq_num > seq_num)
+        {
+            // the request is ordered in the next epoch
+            return false;
+        }
+        if !self.is_within_water_marks(pre_prepare.seq_num) {
+            sampled!(
+                warn,
+                "outside_water_marks",
+                "Dropping pre-prepare for sequence number {} outside of the water marks ({}, {}]",
+                pre_prepare.seq_num,
+                self.low_water_mark(),
+                self.high_water_mark()
+            );
+            return false;
+        }
         if self.config.is_equivocator && self.current_leader() != self.id {
             return false;
         }
@@ -75,7 +254,21 @@ impl State {
             );
             return false;
         }
-        if pre_prepare.client_request_digest != pre_prepare.client_request.digest() {
+        if pre_prepare.client_request_digest
+            != pre_prepare.client_request.digest_at(pre_prepare.seq_num)
+        {
+            return false;
+        }
+        if pre_prepare
+            .client_request
+            .exclusion()
+            .is_some_and(|exclusion| exclusion.from_view > pre_prepare.view + 1)
+        {
+            // the primary cannot put off passing a replica over
+            return false;
+        }
+        if self.equivocation_proofs.contains_key(&pre_prepare.view) {
+            // the primary is faulty, and we are changing the view
             return false;
         }
         if let Some(e_pre_prepare) = self
@@ -85,12 +278,61 @@ impl State {
         {
             // if we already saw a pre-prepare request for this (view, seq-num) pair,
             // then we will accept as along as the message digests are the same
+            // (see `equivocation` for a pre-prepare which conflicts)
             return e_pre_prepare.client_request_digest == pre_prepare.client_request_digest;
         }
 
         true
     }
 
+    /// Proof that the primary equivocated, if the pre-prepare conflicts with the one
+    /// we accepted from the primary for the same view and sequence number
+    pub fn equivocation(&self, pre_prepare: &PrePrepare) -> Option<EquivocationProof> {
+        let accepted = self
+            .message_bank
+            .accepted_pre_prepare_requests
+            .get(&(pre_prepare.view, pre_prepare.seq_num))?;
+        let primary = self.get_leader_for_view(pre_prepare.view);
+        let conflicts = accepted.id == primary
+            && pre_prepare.id == primary
+            && accepted.client_request_digest != pre_prepare.client_request_digest
+            && pre_prepare.client_request_digest
+                == pre_prepare.client_request.digest_at(pre_prepare.seq_num);
+        conflicts.then(|| EquivocationProof {
+            first: accepted.clone(),
+            second: pre_prepare.clone(),
+        })
+    }
+
+    /// Should we act on the proof another replica passed on: it is about the primary
+    /// of our view, we did not see one for the view yet, and it checks out
+    pub fn should_accept_equivocation_proof(
+        &self,
+        proof: &EquivocationProof,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+    ) -> bool {
+        if proof.view() != self.view || self.equivocation_proofs.contains_key(&proof.view()) {
+            return false;
+        }
+        let primary = self.get_leader_for_view(proof.view());
+        pub_keys
+            .get(&primary)
+            .is_some_and(|pub_key| proof.is_valid(primary, pub_key))
+    }
+
+    /// Remembers that the primary of the view equivocated
+    pub fn record_equivocation(&mut self, proof: EquivocationProof) {
+        let view = self.view;
+        self.equivocation_proofs.retain(|v, _| *v >= view);
+        self.equivocation_proofs.insert(proof.view(), proof);
+    }
+
+    /// Logs the accepted pre-prepare, which shares its client request with the other log
+    /// entries for the request (see `MessageBank::log_pre_prepare`)
+    pub fn log_pre_prepare(&mut self, pre_prepare: PrePrepare) {
+        self.message_bank.log_pre_prepare(pre_prepare);
+    }
+
     pub fn should_accept_prepare(&self, prepare: &Prepare) -> bool {
         if self.in_view_change {
             return false;
@@ -106,7 +348,8 @@ impl State {
             .accepted_pre_prepare_requests
             .get(&(prepare.view, prepare.seq_num))
         {
-            if prepare.client_request_digest != *e_request.client_request.digest() {
+            if prepare.client_request_digest != e_request.client_request.digest_at(prepare.seq_num)
+            {
                 return false;
             }
         } else {
@@ -129,15 +372,89 @@ impl State {
         true
     }
 
-    pub fn should_process_client_request(&self, _request: &ClientRequest) -> bool {
+    pub fn should_process_client_request(&self, request: &ClientRequest) -> bool {
         // this will only be called by the master replica
         if self.in_view_change {
             return false;
         }
+        // requests the client sent before the last one we replied to were executed already
+        // or are superseded, and the last one is answered from the reply cache
+        if let Some(cached) = self.reply_cache.get(&request.identity()) {
+            if request.time_stamp <= cached.time_stamp {
+                return false;
+            }
+        }
 
         true
     }
 
+    /// Whether we executed a later request of the client, so that the request never will be
+    pub fn is_stale_client_request(&self, request: &ClientRequest) -> bool {
+        self.reply_cache
+            .get(&request.identity())
+            .is_some_and(|cached| request.time_stamp < cached.time_stamp)
+    }
+
+    /// What we know of the request of the client with the timestamp, from our reply cache
+    pub fn request_status(&self, client: &ClientIdentity, time_stamp: usize) -> RequestStatus {
+        match self.reply_cache.get(client) {
+            Some(cached) if cached.time_stamp == time_stamp => RequestStatus::Executed,
+            Some(cached) if cached.time_stamp > time_stamp => RequestStatus::Superseded,
+            _ => RequestStatus::Unknown,
+        }
+    }
+
+    /// Our reply to the request, if we executed it and it is the last request of the client.
+    /// Requests are matched by timestamp only, as a retransmission may go through another relay
+    pub fn cached_reply(&self, request: &ClientRequest) -> Option<&ClientResponse> {
+        self.reply_cache
+            .get(&request.identity())
+            .filter(|cached| cached.time_stamp == request.time_stamp)
+    }
+
+    /// Remembers the reply to the request unless we replied to a later request of the client.
+    /// A request which did not authenticate says nothing of the client it claims to be from
+    pub fn cache_reply(&mut self, request: &ClientRequest, response: &ClientResponse) {
+        if response.reason == Some(FailureReason::Unauthenticated)
+            || self
+                .reply_cache
+                .get(&request.identity())
+                .is_some_and(|cached| cached.time_stamp > request.time_stamp)
+        {
+            return;
+        }
+        self.reply_cache
+            .insert(request.identity(), response.clone());
+    }
+
+    /// Checks that a request carrying the id of a client is signed by the key the client
+    /// registered, or by the key it registers, if the request is the first registration
+    /// of the id
+    pub fn authenticate(&self, request: &ClientRequest) -> Result<(), FailureReason> {
+        let Some(client_id) = request.client_id else {
+            return Ok(());
+        };
+        let registered = match (
+            &request.operation,
+            self.store.get(&registry::client_key(client_id)),
+        ) {
+            (_, Some(registered)) => Some(registered),
+            (Operation::Set(value), None)
+                if registry::client_id_of(&request.key) == Some(client_id) =>
+            {
+                Some(value)
+            }
+            _ => None,
+        };
+        let pub_key = registered
+            .and_then(|value| decode_hex(&String::from_utf8_lossy(value.as_bytes())).ok())
+            .and_then(|bytes| PublicKey::from_bytes(&bytes).ok());
+        match pub_key {
+            Some(pub_key) if request.is_properly_signed_by(&pub_key) => Ok(()),
+            _ => Err(FailureReason::Unauthenticated),
+        }
+    }
+
     pub fn should_accept_checkpoint(&self, _checkpoint: &CheckPoint) -> bool {
         // note that we accept checkpoint messages as long as they have been properly signed,
         // which must be the case by the time the message gets to this consensus layer
@@ -145,51 +462,615 @@ impl State {
     }
 
     pub fn should_accept_view_change(&self, view_change: &ViewChange) -> bool {
-        // make sure the view change is for the next view
-        if view_change.new_view != self.view + 1 {
+        // make sure the view change is for a later view, which replicas escalate to
+        // if the primary of the next view does not complete the view change
+        if view_change.new_view <= self.view {
             return false;
         }
-        // if we are not the leader for the next view, then we don't accept the view change
+        // if we are not the leader for that view, then we don't accept the view change
         if self.get_leader_for_view(view_change.new_view) != self.id {
             return false;
         }
+        // the requests it claims prepared must have been, or a faulty replica could have
+        // us re-propose a request no quorum prepared
+        if !view_change.are_prepared_requests_valid(self.config.num_faulty, |view| {
+            self.get_leader_for_view(view)
+        }) {
+            warn!(
+                "Dropping view change from {} as a prepared request it carries is not backed by a quorum",
+                view_change.id
+            );
+            return false;
+        }
 
         true
     }
 
-    pub fn should_accept_new_view(&self, _new_view: &NewView) -> bool {
-        // as long as the new view message is well formed, we should always accept it
+    /// Checks that the new view comes from the primary of that view, is based on
+    /// 2f + 1 properly signed view changes for it whose prepared requests are backed by
+    /// quorums, and re-proposes exactly the requests those view changes determine
+    pub fn should_accept_new_view(
+        &self,
+        new_view: &NewView,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+    ) -> bool {
+        if new_view.view <= self.view || new_view.id != self.get_leader_for_view(new_view.view) {
+            return false;
+        }
+
+        if let Err(fault) = check_new_view(new_view, pub_keys, self.config.num_faulty) {
+            warn!("Dropping new view {} as {}", new_view.view, fault);
+            return false;
+        }
+        let unbacked = new_view.view_change_messages.iter().find(|view_change| {
+            !view_change.are_prepared_requests_valid(self.config.num_faulty, |view| {
+                self.get_leader_for_view(view)
+            })
+        });
+        if let Some(view_change) = unbacked {
+            warn!(
+                "Dropping new view {} as a prepared request in the view change from {} is not backed by a quorum",
+                new_view.view, view_change.id
+            );
+            return false;
+        }
+
         true
     }
 
     pub fn apply_commit(
         &mut self,
-        request: &ClientRequest,
+        request: Arc<ClientRequest>,
         commit: &Commit,
-    ) -> (Option<Option<&Value>>, Vec<Commit>) {
+    ) -> (ApplyResult, Vec<Commit>) {
         self.last_seq_num_committed = commit.seq_num;
         self.message_bank
             .accepted_commits_not_applied
             .remove(&(commit.seq_num));
 
-        self.message_bank
-            .applied_commits
-            .insert(commit.seq_num, (commit.clone(), request.clone()));
-
-        let commit_res = if request.value.is_some() {
-            // request is a set request
-            self.store
-                .insert(request.clone().key, request.clone().value.unwrap());
-            None
+        // the primary is the replica which signed the pre-prepare we accepted for the slot,
+        // rather than the one our own evidence makes primary of the view
+        let leader = self
+            .message_bank
+            .accepted_pre_prepare_requests
+            .get(&(commit.view, commit.seq_num))
+            .map_or_else(
+                || self.get_leader_for_view(commit.view),
+                |pre_prepare| pre_prepare.id,
+            );
+        let slot = SlotMeta {
+            seq_num: commit.seq_num,
+            view: commit.view,
+            leader,
+            time_stamp: request.time_stamp,
+        };
+        let mut commit_res = if let Err(reason) = self.authenticate(&request) {
+            ApplyResult::rejected(reason)
+        } else if let Some(bulk) = request.bulk {
+            self.apply_bulk_batch(&request, bulk, &slot)
+        } else if !request.batch.is_empty() {
+            // request is a batch of writes
+            match self.apply_batch(&request) {
+                Ok(results) => ApplyResult {
+                    results,
+                    ..ApplyResult::default()
+                },
+                Err(reason) => ApplyResult::rejected(reason),
+            }
         } else {
-            //request is a get request
-            let ret = self.store.get(&request.key);
-            Some(ret)
+            self.apply_operation(&request)
         };
+        if commit_res.reason.is_none() && request.bulk.is_none() {
+            self.record_writes(&request, &slot);
+        }
+        commit_res.slot = slot;
+        self.message_bank.applied_commits.insert(
+            commit.seq_num,
+            AppliedCommit {
+                commit: commit.clone(),
+                request: request.clone(),
+                rejected: commit_res.reason,
+            },
+        );
+
+        self.log_growth.add(&request);
+        if self.is_checkpoint_due() {
+            self.last_checkpoint_boundary = commit.seq_num;
+            self.log_growth = LogGrowth::default();
+        }
 
         (commit_res, self.get_next_consecutive_commits())
     }
 
+    /// Whether a checkpoint is due after the last applied request: at every multiple of the
+    /// checkpoint frequency, or once the requests applied since the last checkpoint reach
+    /// the entry or byte threshold of the config, and after a reconfiguration. This only
+    /// depends on the applied requests, so every replica checkpoints at the same sequence numbers
+    fn is_checkpoint_due(&self) -> bool {
+        let reaches = |growth: usize, threshold: usize| threshold > 0 && growth >= threshold;
+        self.last_seq_num_committed
+            .is_multiple_of(self.config.checkpoint_frequency)
+            || self.pending_reconfiguration == Some(self.last_seq_num_committed)
+            || reaches(self.log_growth.entries, self.config.checkpoint_entries)
+            || reaches(self.log_growth.bytes, self.config.checkpoint_bytes)
+    }
+
+    /// Was a checkpoint due after the last applied request
+    pub fn is_at_checkpoint_boundary(&self) -> bool {
+        self.last_checkpoint_boundary == self.last_seq_num_committed
+    }
+
+    /// Records the slot the applied request wrote the keys in as their version
+    fn record_writes(&self, request: &ClientRequest, slot: &SlotMeta) {
+        let seq_num = slot.seq_num;
+        if !request.batch.is_empty() {
+            self.key_versions
+                .record(request.batch.iter().map(BatchOp::key), seq_num);
+            self.checkpoint_diffs
+                .record(request.batch.iter().map(BatchOp::key), seq_num);
+            self.leaf_cache
+                .lock()
+                .unwrap()
+                .invalidate(request.batch.iter().map(BatchOp::key));
+        } else if request.operation != Operation::Get {
+            self.key_versions.record([&request.key], seq_num);
+            self.checkpoint_diffs.record([&request.key], seq_num);
+            self.leaf_cache.lock().unwrap().invalidate([&request.key]);
+        }
+    }
+
+    /// Applies the operation of a request which is not a batch
+    fn apply_operation(&mut self, request: &ClientRequest) -> ApplyResult {
+        let key = &request.key;
+        if registry::is_reserved(key) && request.operation != Operation::Get {
+            return self.apply_registry_update(request);
+        }
+        let mut result = ApplyResult::default();
+        match &request.operation {
+            Operation::Get => result.value = self.store.get(key).cloned(),
+            Operation::Set(value) => {
+                result.reason = self.apply_set(key, request.identity(), value.clone()).err()
+            }
+            Operation::Delete => result.previous = self.apply_delete(key),
+            Operation::Cas { expected, new } => {
+                result.previous = self.store.get(key).cloned();
+                result.reason = if result.previous == *expected {
+                    self.apply_set(key, request.identity(), new.clone()).err()
+                } else {
+                    Some(FailureReason::UnexpectedValue)
+                };
+            }
+        }
+        result
+    }
+
+    /// Replaces the registry with the one of the update, if it is signed by the root key of
+    /// the cluster and follows the current version, or with the registry a reconfiguration
+    /// leads to (see `apply_reconfiguration`). Other writes to reserved keys are rejected
+    fn apply_registry_update(&mut self, request: &ClientRequest) -> ApplyResult {
+        if let Some(client_id) = registry::client_id_of(&request.key) {
+            return self.apply_client_registration(client_id, request);
+        }
+        if let Some(accused) = registry::accused_of(&request.key) {
+            return self.apply_exclusion(accused, request);
+        }
+        if let (Operation::Set(value), true) =
+            (&request.operation, request.key == registry::registry_key())
+        {
+            if let Some(reconfiguration) = Reconfiguration::decode(value) {
+                return self.apply_reconfiguration(&request.key, &reconfiguration);
+            }
+        }
+        let update = match (&request.operation, &self.config.root_pub_key) {
+            (Operation::Set(value), Some(root_pub_key))
+                if request.key == registry::registry_key() =>
+            {
+                RegistryUpdate::decode(value)
+                    .filter(|update| update.is_properly_signed_by(root_pub_key))
+            }
+            _ => None,
+        };
+        let (version, epoch) = self
+            .store
+            .get(&request.key)
+            .and_then(ClusterRegistry::decode)
+            .map_or((0, 0), |registry| (registry.version, registry.epoch));
+        match update {
+            // the membership only changes through reconfigurations
+            Some(update)
+                if update.registry.version == version + 1 && update.registry.epoch == epoch =>
+            {
+                ApplyResult {
+                    previous: self
+                        .store
+                        .insert(request.key.clone(), update.registry.encode()),
+                    ..ApplyResult::default()
+                }
+            }
+            _ => ApplyResult::rejected(FailureReason::ReservedKey),
+        }
+    }
+
+    /// Registers the public key of the client, which signed its registration with the key
+    /// (see `authenticate`). Only the client itself registers, and only once: registering
+    /// the same key again changes nothing, and the client cannot replace its key
+    fn apply_client_registration(
+        &mut self,
+        client_id: ClientId,
+        request: &ClientRequest,
+    ) -> ApplyResult {
+        let value = match &request.operation {
+            Operation::Set(value) if request.client_id == Some(client_id) => value,
+            _ => return ApplyResult::rejected(FailureReason::ReservedKey),
+        };
+        match self.store.get(&request.key) {
+            Some(registered) if registered != value => {
+                ApplyResult::rejected(FailureReason::ReservedKey)
+            }
+            _ => ApplyResult {
+                previous: self.store.insert(request.key.clone(), value.clone()),
+                ..ApplyResult::default()
+            },
+        }
+    }
+
+    /// Keeps the evidence of the exclusion, and passes its accused over as primary from the
+    /// view of the exclusion. Only the first exclusion of a replica is kept, and only if its
+    /// evidence checks out against the keys of the members and it starts after the view the
+    /// replica misbehaved in, which every replica decides alike
+    fn apply_exclusion(&mut self, accused: NodeId, request: &ClientRequest) -> ApplyResult {
+        let exclusion = match request.exclusion() {
+            Some(exclusion)
+                if exclusion.evidence.accused() == accused
+                    && exclusion.from_view > exclusion.evidence.view()
+                    && exclusion
+                        .evidence
+                        .is_valid(&self.config.peer_pub_keys, self.config.num_faulty) =>
+            {
+                exclusion
+            }
+            _ => return ApplyResult::rejected(FailureReason::ReservedKey),
+        };
+        if self.store.contains_key(&request.key) {
+            return ApplyResult::rejected(FailureReason::UnexpectedValue);
+        }
+        self.store.insert(request.key.clone(), exclusion.encode());
+        self.evidence.exclude(exclusion);
+        ApplyResult::default()
+    }
+
+    /// Writes the registry the reconfiguration leads to, if it is signed by the root key of
+    /// the cluster, and holds back the requests after it until we move to its epoch.
+    /// A reconfiguration which does not apply to the registry (see
+    /// `ClusterRegistry::reconfigured`) is rejected as an unexpected value
+    fn apply_reconfiguration(
+        &mut self,
+        key: &Key,
+        reconfiguration: &Reconfiguration,
+    ) -> ApplyResult {
+        let is_signed = self
+            .config
+            .root_pub_key
+            .as_ref()
+            .is_some_and(|root_pub_key| reconfiguration.is_properly_signed_by(root_pub_key));
+        if !is_signed {
+            return ApplyResult::rejected(FailureReason::ReservedKey);
+        }
+        let registry = self
+            .store
+            .get(key)
+            .and_then(ClusterRegistry::decode)
+            .unwrap_or_default();
+        match registry.reconfigured(reconfiguration) {
+            Some(registry) => {
+                self.pending_reconfiguration = Some(self.last_seq_num_committed);
+                ApplyResult {
+                    previous: self.store.insert(key.clone(), registry.encode()),
+                    ..ApplyResult::default()
+                }
+            }
+            None => ApplyResult::rejected(FailureReason::UnexpectedValue),
+        }
+    }
+
+    /// Moves to the epoch of the registry in the store once the reconfiguration which led
+    /// to it is covered by the last stable checkpoint, taking the membership from the registry.
+    /// Returns whether we moved to another epoch
+    pub fn adopt_registry_epoch(&mut self) -> bool {
+        let registry = match self
+            .store
+            .get(&registry::registry_key())
+            .and_then(ClusterRegistry::decode)
+        {
+            Some(registry) if registry.epoch > self.config.epoch => registry,
+            _ => return false,
+        };
+        if self
+            .pending_reconfiguration
+            .is_some_and(|seq_num| seq_num > self.last_stable_seq_num)
+        {
+            return false;
+        }
+        self.config.apply_membership(&registry);
+        self.pending_reconfiguration = None;
+        self.drop_previous_epochs();
+        true
+    }
+
+    /// Drops the messages of the epochs before ours. The requests ordered after the
+    /// reconfiguration in the previous epoch were never applied, and are ordered again
+    fn drop_previous_epochs(&mut self) {
+        let epoch = self.config.epoch;
+        let message_bank = &mut self.message_bank;
+        message_bank
+            .accepted_pre_prepare_requests
+            .retain(|_, pre_prepare| pre_prepare.epoch >= epoch);
+        message_bank
+            .accepted_commits_not_applied
+            .retain(|_, commit| commit.epoch >= epoch);
+        message_bank
+            .outstanding_prepares
+            .retain(|prepare| prepare.epoch >= epoch);
+        message_bank
+            .outstanding_commits
+            .retain(|commit| commit.epoch >= epoch);
+        message_bank.sent_requests.clear();
+        for votes in self.prepare_votes.values_mut() {
+            votes.retain(|_, prepare| prepare.epoch >= epoch);
+        }
+        self.prepare_votes.retain(|_, votes| !votes.is_empty());
+        for votes in self.commit_votes.values_mut() {
+            votes.retain(|_, commit| commit.epoch >= epoch);
+        }
+        self.commit_votes.retain(|_, votes| !votes.is_empty());
+        // the last stable checkpoint is announced again in the new epoch
+        let last_stable_seq_num = self.last_stable_seq_num;
+        self.checkpoint_votes
+            .retain(|(seq_num, _), _| *seq_num > last_stable_seq_num);
+        self.checkpoints_current_round
+            .retain(|_, checkpoint| checkpoint.epoch >= epoch);
+        self.view_change_votes
+            .retain(|_, view_change| view_change.epoch >= epoch);
+        self.equivocation_proofs
+            .retain(|_, proof| proof.first.epoch >= epoch);
+        self.seq_num = message_bank
+            .accepted_pre_prepare_requests
+            .keys()
+            .map(|(_, seq_num)| *seq_num)
+            .chain([self.last_seq_num_committed])
+            .max()
+            .unwrap_or_default();
+        self.in_view_change = false;
+        self.pending_view = self.view;
+    }
+
+    /// Writes the value unless creating the key, or growing its value, would exceed a quota.
+    /// The space of a key is charged to the client which created it.
+    /// This only depends on the applied requests so it is deterministic across replicas
+    fn apply_set(
+        &mut self,
+        key: &Key,
+        owner: ClientIdentity,
+        value: Value,
+    ) -> Result<(), FailureReason> {
+        let size = entry_size(key, &value);
+        if let Some(prev_value) = self.store.get(key) {
+            let prev_size = entry_size(key, prev_value);
+            let owner = self.key_owners.get(key).copied().unwrap_or(owner);
+            if size > prev_size {
+                let growth = size - prev_size;
+                let client_usage = self.client_usage.get(&owner).copied().unwrap_or_default();
+                exceeds(
+                    self.total_usage.bytes + growth,
+                    self.config.max_total_bytes,
+                    FailureReason::TotalByteQuotaExceeded,
+                )?;
+                exceeds(
+                    client_usage.bytes + growth,
+                    self.config.max_client_bytes,
+                    FailureReason::ClientByteQuotaExceeded,
+                )?;
+            }
+            self.total_usage.resize(prev_size, size);
+            self.client_usage
+                .entry(owner)
+                .or_default()
+                .resize(prev_size, size);
+        } else {
+            let client_usage = self.client_usage.get(&owner).copied().unwrap_or_default();
+            exceeds(
+                self.total_usage.keys + 1,
+                self.config.max_total_keys,
+                FailureReason::TotalKeyQuotaExceeded,
+            )?;
+            exceeds(
+                self.total_usage.bytes + size,
+                self.config.max_total_bytes,
+                FailureReason::TotalByteQuotaExceeded,
+            )?;
+            exceeds(
+                client_usage.keys + 1,
+                self.config.max_client_keys,
+                FailureReason::ClientKeyQuotaExceeded,
+            )?;
+            exceeds(
+                client_usage.bytes + size,
+                self.config.max_client_bytes,
+                FailureReason::ClientByteQuotaExceeded,
+            )?;
+
+            self.key_owners.insert(key.clone(), owner);
+            self.total_usage.add(size);
+            self.client_usage.entry(owner).or_default().add(size);
+        }
+        self.store.insert(key.clone(), value);
+        Ok(())
+    }
+
+    /// Removes the key, returning the space it used to the client which created it
+    fn apply_delete(&mut self, key: &Key) -> Option<Value> {
+        let value = self.store.remove(key)?;
+        let entry_size = entry_size(key, &value);
+        self.total_usage.remove(entry_size);
+        if let Some(owner) = self.key_owners.remove(key) {
+            if let Some(client_usage) = self.client_usage.get_mut(&owner) {
+                client_usage.remove(entry_size);
+            }
+        }
+        Some(value)
+    }
+
+    /// Applies the operations of the batch in order, returning the previous value of the key
+    /// of each operation. If any operation is rejected, none of them are applied
+    fn apply_batch(
+        &mut self,
+        request: &ClientRequest,
+    ) -> Result<Vec<Option<Value>>, FailureReason> {
+        if request
+            .batch
+            .iter()
+            .any(|op| registry::is_reserved(op.key()))
+        {
+            return Err(FailureReason::ReservedKey);
+        }
+        let total_usage = self.total_usage;
+        let client_usage = self.client_usage.clone();
+        // previous entry of each key written, to roll back the batch
+        let mut undo_log = Vec::with_capacity(request.batch.len());
+
+        let mut results = Vec::with_capacity(request.batch.len());
+        for op in request.batch.iter() {
+            let key = op.key();
+            let prev_value = self.store.get(key).cloned();
+            undo_log.push((key, prev_value.clone(), self.key_owners.get(key).copied()));
+            let res = match op {
+                BatchOp::Put { key, value } => {
+                    self.apply_set(key, request.identity(), value.clone())
+                }
+                BatchOp::Delete { key } => {
+                    self.apply_delete(key);
+                    Ok(())
+                }
+            };
+            if let Err(reason) = res {
+                for (key, prev_value, prev_owner) in undo_log.into_iter().rev() {
+                    match prev_value {
+                        Some(prev_value) => self.store.insert(key.clone(), prev_value),
+                        None => self.store.remove(key),
+                    };
+                    match prev_owner {
+                        Some(prev_owner) => self.key_owners.insert(key.clone(), prev_owner),
+                        None => self.key_owners.remove(key),
+                    };
+                }
+                self.total_usage = total_usage;
+                self.client_usage = client_usage;
+                return Err(reason);
+            }
+            results.push(prev_value);
+        }
+        Ok(results)
+    }
+
+    /// Applies the batch of a bulk load, unless it does not follow the batches of the load
+    /// applied before it or one of them was rejected. Only the last batch is answered, with
+    /// the operations of the load applied up to the first batch which was not
+    fn apply_bulk_batch(
+        &mut self,
+        request: &ClientRequest,
+        bulk: BulkBatch,
+        slot: &SlotMeta,
+    ) -> ApplyResult {
+        if self.is_stale_client_request(request) {
+            return ApplyResult::rejected(FailureReason::StaleTimestamp);
+        }
+        let mut progress = match self.bulk_loads.remove(&request.respond_addr) {
+            Some(progress) if progress.time_stamp > request.time_stamp => {
+                self.bulk_loads.insert(request.respond_addr, progress);
+                return ApplyResult::rejected(FailureReason::StaleTimestamp);
+            }
+            Some(progress) if progress.time_stamp == request.time_stamp => progress,
+            _ => BulkProgress {
+                time_stamp: request.time_stamp,
+                ..BulkProgress::default()
+            },
+        };
+        if progress.reason.is_none() && bulk.offset == progress.summary.ops {
+            match self.apply_batch(request) {
+                Ok(_) => {
+                    self.record_writes(request, slot);
+                    if progress.summary.batches == 0 {
+                        progress.summary.first_seq_num = slot.seq_num;
+                    }
+                    progress.summary.ops += request.batch.len();
+                    progress.summary.batches += 1;
+                }
+                Err(reason) => progress.reason = Some(reason),
+            }
+        }
+        if !bulk.last {
+            self.bulk_loads.insert(request.respond_addr, progress);
+            return ApplyResult::default();
+        }
+        ApplyResult {
+            reason: progress.reason,
+            bulk: Some(progress.summary),
+            ..ApplyResult::default()
+        }
+    }
+
+    /// Takes a snapshot of the store as it is now, at the checkpoint we are about to announce
+    pub fn take_snapshot(&mut self) {
+        self.checkpoint_snapshots.insert(
+            self.last_seq_num_committed,
+            StateSnapshot {
+                committed_seq_num: self.last_seq_num_committed,
+                store: self.store.clone(),
+                key_owners: self.key_owners.clone(),
+            },
+        );
+    }
+
+    /// Snapshot of the store at the last stable checkpoint, if we have it
+    pub fn stable_snapshot(&self) -> Option<&StateSnapshot> {
+        self.checkpoint_snapshots.get(&self.last_stable_seq_num)
+    }
+
+    /// Installs a snapshot of the store and recomputes the space used by it
+    pub fn install_snapshot(&mut self, snapshot: &StateSnapshot) {
+        self.last_checkpoint_boundary = snapshot.committed_seq_num;
+        self.log_growth = LogGrowth::default();
+        self.key_versions
+            .install_snapshot(snapshot.committed_seq_num);
+        self.checkpoint_diffs
+            .install_snapshot(snapshot.committed_seq_num);
+        self.store = snapshot.store.clone();
+        self.key_owners = snapshot.key_owners.clone();
+        self.leaf_cache.lock().unwrap().clear();
+        self.checkpoint_snapshots
+            .insert(snapshot.committed_seq_num, snapshot.clone());
+        self.total_usage = StoreUsage::default();
+        self.client_usage.clear();
+        for (key, owner) in self.key_owners.iter() {
+            let entry_size = match self.store.get(key) {
+                Some(value) => entry_size(key, value),
+                None => continue,
+            };
+            self.total_usage.add(entry_size);
+            self.client_usage.entry(*owner).or_default().add(entry_size);
+        }
+        self.evidence.install_exclusions(self.exclusions());
+    }
+
+    /// Exclusions ordered into the store
+    fn exclusions(&self) -> Vec<Exclusion> {
+        self.store
+            .iter()
+            .filter(|(key, _)| registry::accused_of(key).is_some())
+            .filter_map(|(_, value)| Exclusion::decode(value))
+            .collect()
+    }
+
     pub fn get_next_consecutive_commits(&self) -> Vec<Commit> {
         let mut ret = Vec::<Commit>::new();
         let mut try_commit = self.last_seq_num_committed + 1;
@@ -206,8 +1087,6 @@ impl State {
     }
 
     pub fn update_checkpoint_meta(&mut self, seq_num: &usize, state_digest: &[u8]) {
-        self.last_checkpoint_proof.clear();
-
         let curr_vote_set = self
             .checkpoint_votes
             .get(&(*seq_num, state_digest.to_vec()))
@@ -215,31 +1094,374 @@ impl State {
 
         // create a new checkpoint proof with only the checkpoints
         // from nodes who voted for this new checkpoint
-        for node_id in curr_vote_set.iter() {
-            self.last_checkpoint_proof
-                .push(self.checkpoints_current_round.get(node_id).unwrap().clone());
-        }
+        let proof = curr_vote_set
+            .iter()
+            .map(|node_id| self.checkpoints_current_round.get(node_id).unwrap().clone())
+            .collect();
+        self.install_checkpoint_proof(proof);
+    }
 
+    /// Makes the checkpoints the proof of the new stable checkpoint, for subsequent
+    /// view change messages, and starts counting votes for the next one
+    pub fn install_checkpoint_proof(&mut self, proof: Vec<CheckPoint>) {
+        self.last_checkpoint_proof = proof;
         self.checkpoint_votes.clear();
         self.checkpoints_current_round.clear();
     }
 
     pub fn garbage_collect(&mut self) {
+        if self.config.is_archive {
+            // archive nodes retain the full history
+            return;
+        }
         self.message_bank.garbage_collect(self.last_stable_seq_num);
 
         //todo: remove all messages from prepare_votes and checkpoint votes that pertain to old messages
     }
 
-    /// Sha512 hash of the state store
+    /// The checkpoint at the sequence number became stable, so our snapshots of earlier
+    /// checkpoints are no longer needed. Archive nodes retain the store at every one
+    pub fn stabilize_snapshot(&mut self, seq_num: usize) {
+        self.checkpoint_snapshots = self.checkpoint_snapshots.split_off(&seq_num);
+        if let (true, Some(snapshot)) = (
+            self.config.is_archive,
+            self.checkpoint_snapshots.get(&seq_num),
+        ) {
+            self.archived_snapshots
+                .insert(seq_num, snapshot.store.clone());
+        }
+    }
+
+    /// The commit applied at the given sequence number, together with the associated client
+    /// request and the outcome of applying it
+    pub fn get_entry(&self, seq_num: usize) -> Option<&AppliedCommit> {
+        self.message_bank.applied_commits.get(&seq_num)
+    }
+
+    /// Value of the key after the request with the given sequence number was applied.
+    /// Returns None if this node no longer retains the history needed to answer
+    pub fn get_at(&self, key: &Key, seq_num: usize) -> Option<Option<Value>> {
+        if seq_num > self.last_seq_num_committed {
+            return None;
+        }
+
+        // start from the latest snapshot at or before seq_num and replay the log from there
+        let (snapshot_seq_num, mut value) =
+            match self.archived_snapshots.range(..=seq_num).next_back() {
+                Some((snapshot_seq_num, snapshot)) => {
+                    (*snapshot_seq_num, snapshot.get(key).cloned())
+                }
+                None => (0, None),
+            };
+
+        for replay_seq_num in snapshot_seq_num + 1..seq_num + 1 {
+            let entry = self.get_entry(replay_seq_num)?;
+            // rejected requests, including batches rolled back as a whole, wrote nothing
+            if entry.rejected.is_some() {
+                continue;
+            }
+            let request = &entry.request;
+            if request.key == *key {
+                match &request.operation {
+                    Operation::Get => {}
+                    Operation::Set(set_value) => value = Some(set_value.clone()),
+                    Operation::Delete => value = None,
+                    Operation::Cas { new, .. } => value = Some(new.clone()),
+                }
+            }
+            for op in request.batch.iter().filter(|op| op.key() == key) {
+                value = match op {
+                    BatchOp::Put { value, .. } => Some(value.clone()),
+                    BatchOp::Delete { .. } => None,
+                };
+            }
+        }
+        Some(value)
+    }
+
+    /// Value of the key at the latest stable checkpoint, with a proof
+    /// of its inclusion in the state certified by the checkpoint proof
+    pub fn key_proof(&self, key: &Key) -> Option<KeyProof> {
+        let checkpoint = self.last_checkpoint_proof.first()?;
+        let snapshot = self
+            .checkpoint_snapshots
+            .get(&checkpoint.committed_seq_num)?;
+
+        Some(KeyProof {
+            id: self.id,
+            key: key.clone(),
+            value: snapshot.store.get(key).cloned(),
+            owner: snapshot.key_owners.get(key).copied(),
+            committed_seq_num: checkpoint.committed_seq_num,
+            state_digest: checkpoint.state_digest.clone(),
+            proof: crypto::algorithm_of(&checkpoint.state_digest).and_then(|algorithm| {
+                merkle::prove(&snapshot.store, &snapshot.key_owners, key, algorithm)
+            }),
+            certificate: self.last_checkpoint_proof.clone(),
+        })
+    }
+
+    /// Merkle root of the state store, hashing only the entries written since the last one
     pub fn digest(&self) -> Vec<u8> {
-        let mut hasher = Sha512::new();
+        self.leaf_cache.lock().unwrap().root(
+            &self.store,
+            &self.key_owners,
+            crypto::policy().algorithm_at(self.last_seq_num_committed),
+        )
+    }
+
+    /// Writes our state at the last stable checkpoint to a snapshot file signed with our key,
+    /// returning the sequence number of the checkpoint
+    pub fn export_snapshot(&self, path: &Path, keystore: &Keystore) -> std::io::Result<usize> {
+        let snapshot = self.stable_snapshot().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no snapshot of the last stable checkpoint",
+            )
+        })?;
+        let file = SnapshotFile::new_with_signature(
+            keystore,
+            self.id,
+            self.config.epoch,
+            self.view,
+            snapshot.clone(),
+            self.last_checkpoint_proof.clone(),
+        );
+        file.write(path)?;
+        Ok(snapshot.committed_seq_num)
+    }
+
+    /// Installs the state of a snapshot file exported by a replica of the cluster, once the
+    /// file and the certificate of its checkpoint check out against the keys of the cluster.
+    /// The checkpoint becomes our last stable one. Returns its sequence number
+    pub fn import_snapshot(
+        &mut self,
+        path: &Path,
+        pub_keys: &HashMap<NodeId, PublicKey>,
+    ) -> Result<usize, SnapshotError> {
+        let file = SnapshotFile::read(path)?;
+        file.verify(pub_keys)?;
+        let committed_seq_num = file.snapshot.committed_seq_num;
+        self.validate_certificate(
+            committed_seq_num,
+            file.state_digest(),
+            &file.proof,
+            pub_keys,
+        )?;
+        if committed_seq_num <= self.last_seq_num_committed {
+            return Err(SnapshotError::Stale { committed_seq_num });
+        }
+        self.install_snapshot(&file.snapshot);
+        self.last_seq_num_committed = committed_seq_num;
+        self.last_stable_seq_num = committed_seq_num;
+        self.seq_num = self.seq_num.max(committed_seq_num);
+        // the view the exporting replica was in is not certified, unlike that of the checkpoint
+        let checkpoint_view = file.proof.first().map_or(0, |checkpoint| checkpoint.view);
+        self.view = self.view.max(checkpoint_view);
+        self.last_checkpoint_proof = file.proof;
+        self.adopt_registry_epoch();
+        Ok(committed_seq_num)
+    }
+
+    /// Makes sure the state we are about to fetch is backed by a certificate of
+    /// 2f + 1 checkpoints from distinct nodes, properly signed over the same sequence number
+    /// and digest
+    pub fn validate_certificate(
+        &self,
+        committed_seq_num: usize,
+        state_digest: &[u8],
+        certificate: &[CheckPoint],
+        pub_keys: &HashMap<NodeId, PublicKey>,
+    ) -> Result<(), SnapshotError> {
+        let mut signers = HashSet::new();
+        for checkpoint in certificate.iter() {
+            if checkpoint.committed_seq_num != committed_seq_num
+                || checkpoint.state_digest != state_digest
+            {
+                return Err(SnapshotError::MismatchedCertificate { id: checkpoint.id });
+            }
+            match pub_keys.get(&checkpoint.id) {
+                Some(pub_key) if checkpoint.is_properly_signed_by(pub_key) => {}
+                _ => return Err(SnapshotError::InvalidSignature { id: checkpoint.id }),
+            }
+            signers.insert(checkpoint.id);
+        }
+
+        let needed = 2 * self.config.num_faulty + 1;
+        if signers.len() < needed {
+            return Err(SnapshotError::InsufficientCertificate {
+                votes: signers.len(),
+                needed,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Merkle root of a state store, whose leaves are its entries together with the owners of their keys
+pub fn store_digest(
+    store: &BTreeMap<Key, Value>,
+    key_owners: &BTreeMap<Key, ClientIdentity>,
+    algorithm: DigestAlgorithm,
+) -> Vec<u8> {
+    merkle::root(store, key_owners, algorithm)
+}
+
+/// Result of applying a request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyResult {
+    /// Value read by a get request
+    pub value: Option<Value>,
+    /// Value the key had before a delete or compare-and-swap
+    pub previous: Option<Value>,
+    /// For a batched request, the previous value of the key of each operation
+    pub results: Vec<Option<Value>>,
+    /// Why the request was rejected, if it was
+    pub reason: Option<FailureReason>,
+    /// For the last batch of a bulk load, what the load amounted to
+    pub bulk: Option<BulkLoadSummary>,
+    /// Slot the request was applied in
+    pub slot: SlotMeta,
+}
+
+impl ApplyResult {
+    fn rejected(reason: FailureReason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..Self::default()
+        }
+    }
+}
+
+/// Consensus slot a request was committed in, which every replica applies it with alike
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotMeta {
+    pub seq_num: usize,
+    pub view: usize,
+    /// Primary of the view, which proposed the request
+    pub leader: NodeId,
+    /// Time stamp of the committed request. Pre-prepares carry no clock of the primary,
+    /// so this is the only time every replica agrees on for the slot
+    pub time_stamp: usize,
+}
+
+/// Number of keys and bytes (keys and their values) in the store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreUsage {
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+impl StoreUsage {
+    fn add(&mut self, entry_size: usize) {
+        self.keys += 1;
+        self.bytes += entry_size;
+    }
+
+    fn remove(&mut self, entry_size: usize) {
+        self.keys = self.keys.saturating_sub(1);
+        self.bytes = self.bytes.saturating_sub(entry_size);
+    }
+
+    /// Accounts for an entry whose value was overwritten
+    fn resize(&mut self, prev_size: usize, entry_size: usize) {
+        self.bytes = (self.bytes + entry_size).saturating_sub(prev_size);
+    }
+}
+
+/// Operations and bytes of the requests applied since the last checkpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogGrowth {
+    /// Operations, where every write of a batch counts
+    pub entries: usize,
+    /// Bytes of the keys and values of the operations
+    pub bytes: usize,
+}
 
-        let state_bytes = serde_json::to_string(&self.store)
-            .unwrap()
-            .as_bytes()
-            .to_vec();
+impl LogGrowth {
+    fn add(&mut self, request: &ClientRequest) {
+        if request.batch.is_empty() {
+            self.entries += 1;
+            self.bytes += match &request.operation {
+                Operation::Set(value) | Operation::Cas { new: value, .. } => {
+                    entry_size(&request.key, value)
+                }
+                Operation::Get | Operation::Delete => request.key.len(),
+            };
+            return;
+        }
+        for op in request.batch.iter() {
+            self.entries += 1;
+            self.bytes += match op {
+                BatchOp::Put { key, value } => entry_size(key, value),
+                BatchOp::Delete { key } => key.len(),
+            };
+        }
+    }
+}
+
+/// Bytes used by a key and its value
+fn entry_size(key: &Key, value: &Value) -> usize {
+    key.len() + value.len()
+}
+
+/// Fails with the reason if the usage exceeds the quota (a quota of 0 is no limit)
+fn exceeds(usage: usize, quota: usize, reason: FailureReason) -> Result<(), FailureReason> {
+    if quota > 0 && usage > quota {
+        return Err(reason);
+    }
+    Ok(())
+}
+
+/// Reasons a snapshot offered by another node, or imported from a file, is not installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Fewer than 2f + 1 distinct nodes vouch for the snapshot
+    InsufficientCertificate { votes: usize, needed: usize },
+    /// A checkpoint in the certificate is for a different sequence number or digest
+    MismatchedCertificate { id: NodeId },
+    /// A checkpoint in the certificate, or the snapshot file, is not properly signed by its sender
+    InvalidSignature { id: NodeId },
+    /// The snapshot file could not be read or parsed
+    Unreadable(String),
+    /// The snapshot file was written in another version of the format
+    UnsupportedVersion(u32),
+    /// The store in the snapshot file is not the one its certificate vouches for
+    MismatchedDigest,
+    /// Our state is already at or past the snapshot
+    Stale { committed_seq_num: usize },
+}
 
-        hasher.update(state_bytes);
-        hasher.finalize().as_slice().to_vec()
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::InsufficientCertificate { votes, needed } => write!(
+                f,
+                "certificate has {} checkpoints but {} are needed",
+                votes, needed
+            ),
+            SnapshotError::MismatchedCertificate { id } => write!(
+                f,
+                "checkpoint from node {} does not match the certified state",
+                id
+            ),
+            SnapshotError::InvalidSignature { id } => {
+                write!(f, "checkpoint from node {} is not properly signed", id)
+            }
+            SnapshotError::Unreadable(e) => write!(f, "unreadable snapshot: {}", e),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot format version {}", version)
+            }
+            SnapshotError::MismatchedDigest => {
+                write!(f, "the store does not match the certified state digest")
+            }
+            SnapshotError::Stale { committed_seq_num } => write!(
+                f,
+                "the snapshot at seq-num {} is not ahead of our state",
+                committed_seq_num
+            ),
+        }
     }
 }
+
+impl std::error::Error for SnapshotError {}
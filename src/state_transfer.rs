@@ -0,0 +1,162 @@
+use crate::crypto;
+use crate::merkle;
+use crate::messages::{CheckPoint, StateChunkRequest, StateChunkResponse};
+use crate::state::{store_digest, StateSnapshot};
+use crate::NodeId;
+
+/// Fetch of the state at a stable checkpoint by a replica which fell behind it.
+///
+/// Checkpoints only carry the Merkle root of the state, so the replica asks the replicas
+/// which certified the checkpoint for the entries of their store in chunks, one replica at
+/// a time, and checks every chunk against the certified root before it takes the next one.
+/// A replica which sends a chunk which does not check out, or none in time, is passed over
+pub struct StateTransfer {
+    /// The stable checkpoint whose state is fetched
+    pub checkpoint: CheckPoint,
+    /// Checkpoints of the 2f + 1 replicas which certified it
+    pub certificate: Vec<CheckPoint>,
+    /// Replicas the chunks are asked from, in turn
+    sources: Vec<NodeId>,
+    source_index: usize,
+    /// Entries received so far
+    snapshot: StateSnapshot,
+    /// Number of entries of the certified state, known from the first chunk
+    num_entries: Option<usize>,
+}
+
+/// Why a chunk was not accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkError {
+    /// The chunk is for another checkpoint, or from another position than we asked for
+    Unexpected,
+    /// The entries of the chunk are not included in the certified state where claimed
+    InvalidProof,
+}
+
+impl std::fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkError::Unexpected => write!(f, "chunk was not asked for"),
+            ChunkError::InvalidProof => {
+                write!(f, "chunk is not included in the certified state")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+impl StateTransfer {
+    pub fn new(id: NodeId, checkpoint: CheckPoint, certificate: Vec<CheckPoint>) -> Self {
+        let sources = certificate
+            .iter()
+            .map(|checkpoint| checkpoint.id)
+            .filter(|source| *source != id)
+            .collect();
+        Self {
+            snapshot: StateSnapshot {
+                committed_seq_num: checkpoint.committed_seq_num,
+                ..StateSnapshot::default()
+            },
+            checkpoint,
+            certificate,
+            sources,
+            source_index: 0,
+            num_entries: None,
+        }
+    }
+
+    /// Replica the next chunk is asked from
+    pub fn source(&self) -> Option<NodeId> {
+        self.sources.get(self.source_index).copied()
+    }
+
+    /// Passes over the current source, and asks the next replica from now on
+    pub fn next_source(&mut self) {
+        if !self.sources.is_empty() {
+            self.source_index = (self.source_index + 1) % self.sources.len();
+        }
+    }
+
+    /// Position of the next entry to fetch
+    pub fn next_start(&self) -> usize {
+        self.snapshot.store.len()
+    }
+
+    pub fn request(&self, id: NodeId) -> StateChunkRequest {
+        StateChunkRequest {
+            id,
+            committed_seq_num: self.checkpoint.committed_seq_num,
+            state_digest: self.checkpoint.state_digest.clone(),
+            start: self.next_start(),
+        }
+    }
+
+    /// Takes the entries of the chunk once they check out against the certified root
+    pub fn accept(&mut self, response: StateChunkResponse) -> Result<(), ChunkError> {
+        if response.committed_seq_num != self.checkpoint.committed_seq_num
+            || response.state_digest != self.checkpoint.state_digest
+        {
+            return Err(ChunkError::Unexpected);
+        }
+        let root = &self.checkpoint.state_digest;
+        let proof = match response.proof {
+            Some(proof) => proof,
+            None => {
+                // only an empty store comes without a proof
+                let algorithm = crypto::algorithm_of(root).ok_or(ChunkError::InvalidProof)?;
+                let empty = store_digest(&Default::default(), &Default::default(), algorithm);
+                if !response.entries.is_empty() || empty != *root {
+                    return Err(ChunkError::InvalidProof);
+                }
+                self.num_entries = Some(0);
+                return Ok(());
+            }
+        };
+        if proof.start != self.next_start() {
+            return Err(ChunkError::Unexpected);
+        }
+        let algorithm = crypto::algorithm_of(root).ok_or(ChunkError::InvalidProof)?;
+        let leaves = response
+            .entries
+            .iter()
+            .map(|entry| {
+                merkle::leaf_hash(&entry.key, &entry.value, entry.owner.as_ref(), algorithm)
+            })
+            .collect();
+        // the entries are ordered by key, so they must come after those we have
+        let ordered = response
+            .entries
+            .windows(2)
+            .all(|pair| pair[0].key < pair[1].key)
+            && match (
+                self.snapshot.store.keys().next_back(),
+                response.entries.first(),
+            ) {
+                (Some(last), Some(first)) => *last < first.key,
+                _ => true,
+            };
+        if !ordered || !merkle::verify_range(root, leaves, &proof) {
+            return Err(ChunkError::InvalidProof);
+        }
+
+        self.num_entries = Some(proof.num_leaves);
+        for entry in response.entries {
+            if let Some(owner) = entry.owner {
+                self.snapshot.key_owners.insert(entry.key.clone(), owner);
+            }
+            self.snapshot.store.insert(entry.key, entry.value);
+        }
+        Ok(())
+    }
+
+    /// Whether every entry of the certified state was fetched
+    pub fn is_complete(&self) -> bool {
+        self.num_entries == Some(self.snapshot.store.len())
+    }
+
+    /// The fetched state, once complete
+    pub fn into_snapshot(self) -> StateSnapshot {
+        self.snapshot
+    }
+}
@@ -0,0 +1,231 @@
+use crate::data_dir::sync_parent;
+use crate::messages::{CheckPoint, Commit, PrePrepare, Prepare};
+use crate::state::{State, StateSnapshot};
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Entries of the write-ahead log, which are appended as the protocol progresses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WalRecord {
+    PrePrepare(PrePrepare),
+    /// Pre-prepare we assigned a sequence number to as primary, written before it is sent,
+    /// so that a primary which restarts within its view does not assign the number again
+    Proposed(PrePrepare),
+    Prepare(Prepare),
+    Commit(Commit),
+    /// We moved to the view
+    View(usize),
+    /// Proof of the latest stable checkpoint, which the log is compacted into
+    StableCheckpoint(Vec<CheckPoint>),
+    /// State of the store at the latest stable checkpoint, written ahead of its proof
+    /// unless the state backend holds it
+    Snapshot(StateSnapshot),
+}
+
+impl WalRecord {
+    /// Sequence number of the protocol message, if the record holds one
+    pub fn seq_num(&self) -> Option<usize> {
+        match self {
+            WalRecord::PrePrepare(pre_prepare) | WalRecord::Proposed(pre_prepare) => {
+                Some(pre_prepare.seq_num)
+            }
+            WalRecord::Prepare(prepare) => Some(prepare.seq_num),
+            WalRecord::Commit(commit) => Some(commit.seq_num),
+            WalRecord::View(_) | WalRecord::StableCheckpoint(_) | WalRecord::Snapshot(_) => None,
+        }
+    }
+}
+
+/// Append-only log of the accepted protocol messages, one JSON record per line,
+/// so that a replica which crashed can recover its view, sequence numbers and log on restart.
+/// Every record is synced to disk before the replica acts on the message it holds
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Wal {
+    /// Opens the log at the path, creating it if needed, and returns the records it holds.
+    /// A record which was only partially written before a crash is discarded
+    pub fn open(path: &Path) -> std::io::Result<(Self, Vec<WalRecord>)> {
+        let (records, valid_len) = Self::read_records(path)?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() > valid_len {
+            warn!("Discarding a partially written record at the end of the write-ahead log");
+            file.set_len(valid_len)?;
+        }
+        let wal = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((wal, records))
+    }
+
+    /// Records of the log, and the length of the prefix of the file which holds them
+    fn read_records(path: &Path) -> std::io::Result<(Vec<WalRecord>, u64)> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+                break;
+            }
+            match serde_json::from_str::<WalRecord>(&line) {
+                Ok(record) => records.push(record),
+                Err(_) => break,
+            }
+            valid_len += line.len() as u64;
+        }
+        Ok((records, valid_len))
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    /// Rewrites the log as the given records followed by the records for
+    /// sequence numbers after the stable sequence number, dropping the rest
+    pub fn compact(&mut self, stable_seq_num: usize, head: &[WalRecord]) -> std::io::Result<()> {
+        let (records, _) = Self::read_records(&self.path)?;
+        let tail = records
+            .iter()
+            .filter(|record| matches!(record.seq_num(), Some(seq_num) if seq_num > stable_seq_num));
+
+        let tmp_path = self.path.with_extension("compact");
+        let mut tmp = File::create(&tmp_path)?;
+        for record in head.iter().chain(tail) {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            tmp.write_all(&line)?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent(&self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Rebuilds the state from the stable checkpoint the state backend holds, if any, and the
+/// records of the write-ahead log: the latest stable checkpoint, the view, the accepted messages
+/// after the checkpoint, the pre-prepares we proposed as primary and the store with every
+/// request which was committed before the crash applied. No client responses are sent for these
+pub(crate) fn recover(
+    state: &mut State,
+    checkpoint: Option<(StateSnapshot, Vec<CheckPoint>)>,
+    records: Vec<WalRecord>,
+) {
+    if let Some((snapshot, proof)) = checkpoint {
+        install_checkpoint(state, &snapshot, proof);
+    }
+    let mut commits = HashMap::<(usize, usize), Commit>::new();
+    let mut snapshot = None;
+    for record in records {
+        match record {
+            WalRecord::Snapshot(record) => snapshot = Some(record),
+            WalRecord::StableCheckpoint(proof) => {
+                let committed_seq_num = match proof.first() {
+                    Some(checkpoint) => checkpoint.committed_seq_num,
+                    None => continue,
+                };
+                if committed_seq_num < state.last_stable_seq_num {
+                    continue;
+                }
+                // the proof is only of use with the state it certifies
+                match snapshot.take() {
+                    Some(snapshot) if snapshot.committed_seq_num == committed_seq_num => {
+                        install_checkpoint(state, &snapshot, proof)
+                    }
+                    _ => continue,
+                }
+            }
+            WalRecord::View(view) => state.view = state.view.max(view),
+            WalRecord::PrePrepare(pre_prepare) => {
+                state.seq_num = state.seq_num.max(pre_prepare.seq_num);
+                state.log_pre_prepare(pre_prepare);
+            }
+            WalRecord::Proposed(pre_prepare) => {
+                // the request is not proposed again under another sequence number
+                state
+                    .message_bank
+                    .sent_requests
+                    .insert((pre_prepare.view, pre_prepare.client_request.digest()));
+                state.seq_num = state.seq_num.max(pre_prepare.seq_num);
+                state.log_pre_prepare(pre_prepare);
+            }
+            WalRecord::Prepare(prepare) => {
+                state
+                    .prepare_votes
+                    .entry((prepare.view, prepare.seq_num))
+                    .or_default()
+                    .insert(prepare.id, prepare);
+            }
+            WalRecord::Commit(commit) => {
+                state
+                    .commit_votes
+                    .entry((commit.view, commit.seq_num))
+                    .or_default()
+                    .insert(commit.id, commit.clone());
+                commits.insert((commit.view, commit.seq_num), commit);
+            }
+        }
+    }
+
+    // the stable checkpoint may be after a reconfiguration, whose epoch we move to
+    state.adopt_registry_epoch();
+
+    // apply the requests which gathered a commit quorum, in order, up to a reconfiguration
+    while state.pending_reconfiguration.is_none() {
+        let seq_num = state.last_seq_num_committed + 1;
+        let committed = commits.iter().find_map(|((view, e_seq_num), commit)| {
+            let votes = state.commit_votes.get(&(*view, *e_seq_num))?;
+            if *e_seq_num != seq_num || votes.len() <= 2 * state.config.num_faulty {
+                return None;
+            }
+            let pre_prepare = state
+                .message_bank
+                .accepted_pre_prepare_requests
+                .get(&(*view, seq_num))?;
+            let request = state
+                .message_bank
+                .request_body(&pre_prepare.client_request_digest)?;
+            Some((commit.clone(), request))
+        });
+        match committed {
+            Some((commit, request)) => {
+                let _ = state.apply_commit(request, &commit);
+            }
+            None => break,
+        }
+    }
+}
+
+/// Installs the state of the stable checkpoint the proof certifies
+fn install_checkpoint(state: &mut State, snapshot: &StateSnapshot, proof: Vec<CheckPoint>) {
+    let checkpoint = match proof.first() {
+        Some(checkpoint) => checkpoint.clone(),
+        None => return,
+    };
+    state.install_snapshot(snapshot);
+    state.last_seq_num_committed = checkpoint.committed_seq_num;
+    state.last_stable_seq_num = checkpoint.committed_seq_num;
+    state.seq_num = state.seq_num.max(checkpoint.committed_seq_num);
+    state.view = state.view.max(checkpoint.view);
+    state.last_checkpoint_proof = proof;
+}
@@ -0,0 +1,227 @@
+use crate::config::Config;
+use crate::keystore::Keystore;
+use crate::messages::NodeStatus;
+use crate::node::InnerNode;
+use crate::registry::ClusterRegistry;
+use crate::sim::{LinkModel, Network, SimClient, Simulation};
+use crate::NodeId;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+
+/// Change to the configuration of a replica
+type Configure = Box<dyn FnOnce(&mut Config)>;
+
+/// How often the cluster is polled while waiting for it
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Builds a cluster of replicas running in this process over the in-memory network
+/// of the simulator, for tests which exercise the whole protocol:
+///
+/// ```ignore
+/// let cluster = ClusterBuilder::new(4).seed(7).build();
+/// let mut client = cluster.client();
+/// client.put(Key::from("k"), Value::from("v")).await;
+/// cluster.kill_node(3);
+/// assert!(cluster.await_commit(1).await);
+/// ```
+///
+/// Build it on a paused clock (`#[tokio::test(start_paused = true)]`), on which timeouts
+/// pass as soon as every task is idle and a run with the same seed is the same run
+pub struct ClusterBuilder {
+    config: Config,
+    /// Changes to the configuration of single replicas, by id
+    replica_configs: HashMap<NodeId, Configure>,
+    seed: u64,
+    links: LinkModel,
+    timeout: Duration,
+}
+
+impl ClusterBuilder {
+    pub fn new(num_nodes: usize) -> Self {
+        Self {
+            config: Config::new((0..num_nodes).map(|id| (id, replica_addr(id))).collect()),
+            replica_configs: HashMap::new(),
+            seed: 0,
+            links: LinkModel::default(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Seed of the network and of the keys of the replicas
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Delays and losses of the links between the replicas and the clients, the same on
+    /// every link or following a model such as `LinkModel::wan`
+    pub fn links(mut self, links: impl Into<LinkModel>) -> Self {
+        self.links = links.into();
+        self
+    }
+
+    /// How long the cluster is waited for before the wait is given up
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Directory in which each replica keeps its write-ahead log, so that a restarted
+    /// replica recovers its state
+    pub fn wal_dir(mut self, wal_dir: impl Into<PathBuf>) -> Self {
+        self.config.wal_path = Some(wal_dir.into());
+        self
+    }
+
+    /// Directory in which each replica keeps the state of its last stable checkpoint,
+    /// which a restarted replica starts from
+    pub fn state_dir(mut self, state_dir: impl Into<PathBuf>) -> Self {
+        self.config.state_path = Some(state_dir.into());
+        self
+    }
+
+    /// Changes the configuration every replica starts with
+    pub fn config(mut self, configure: impl FnOnce(&mut Config)) -> Self {
+        configure(&mut self.config);
+        self
+    }
+
+    /// Changes the configuration of the replica with the id, on top of the configuration
+    /// every replica starts with
+    pub fn replica_config(
+        mut self,
+        id: NodeId,
+        configure: impl FnOnce(&mut Config) + 'static,
+    ) -> Self {
+        self.replica_configs.insert(id, Box::new(configure));
+        self
+    }
+
+    /// Starts the replicas
+    pub fn build(mut self) -> Cluster {
+        let network = Network::new(self.seed, self.links);
+        Cluster {
+            sim: Simulation::start_with(self.config, network, |id, config| {
+                if let Some(configure) = self.replica_configs.remove(&id) {
+                    configure(config);
+                }
+            }),
+            timeout: self.timeout,
+            num_clients: AtomicU8::new(0),
+        }
+    }
+}
+
+/// Cluster started by a `ClusterBuilder`
+pub struct Cluster {
+    sim: Simulation,
+    timeout: Duration,
+    num_clients: AtomicU8,
+}
+
+impl Cluster {
+    pub fn config(&self) -> &Config {
+        &self.sim.config
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.sim.network
+    }
+
+    /// Node of the replica, on which observers and faults are registered
+    pub fn node(&self, id: NodeId) -> &InnerNode {
+        &self.sim.nodes[id]
+    }
+
+    /// Status the replica reports, with the counters of its node
+    pub fn status(&self, id: NodeId) -> NodeStatus {
+        self.node(id).status()
+    }
+
+    /// New client of the cluster, on its own address
+    pub fn client(&self) -> SimClient {
+        let num_clients = self.num_clients.fetch_add(1, Ordering::Relaxed);
+        self.sim
+            .client(SocketAddr::from(([10, 0, 1, num_clients + 1], 7000)))
+    }
+
+    /// Crashes the replica, which never comes back
+    pub fn kill_node(&self, id: NodeId) {
+        self.sim.stop(id);
+    }
+
+    /// Crashes the replica and starts it again, recovering from its write-ahead log if the
+    /// cluster keeps them. Observers have to be registered again on its new node
+    pub fn restart_node(&mut self, id: NodeId) {
+        self.sim.restart(id);
+    }
+
+    /// Cuts the link between the two replicas until the next heal
+    pub fn partition(&self, a: NodeId, b: NodeId) {
+        self.sim.network.cut(a, b);
+    }
+
+    /// Cuts the replicas off from the rest of the cluster and the clients until the next heal
+    pub fn isolate(&self, nodes: &[NodeId]) {
+        self.sim.network.partition(nodes);
+    }
+
+    /// Starts a replica with the keys of the keystore, which joins the cluster with the
+    /// membership of the registry (see `Simulation::join`)
+    pub fn join_node(&mut self, id: NodeId, keystore: Keystore, registry: &ClusterRegistry) {
+        self.sim.join(id, keystore, registry);
+    }
+
+    /// Restores every link cut by a partition, but not the killed replicas
+    pub fn heal(&self) {
+        self.sim.network.heal();
+    }
+
+    /// Waits until 2f + 1 replicas committed the sequence number, returning false
+    /// if they did not before the timeout of the cluster
+    pub async fn await_commit(&self, seq_num: usize) -> bool {
+        self.await_quorum(|status| status.last_seq_num_committed >= seq_num)
+            .await
+    }
+
+    /// Waits until 2f + 1 replicas entered the view, or a later one, and finished
+    /// the view change, returning false if they did not before the timeout of the cluster
+    pub async fn await_view(&self, view: usize) -> bool {
+        self.await_quorum(|status| status.view >= view && !status.in_view_change)
+            .await
+    }
+
+    /// Waits until 2f + 1 replicas moved to the epoch of the membership, or a later one,
+    /// returning false if they did not before the timeout of the cluster
+    pub async fn await_epoch(&self, epoch: usize) -> bool {
+        self.await_quorum(|status| status.epoch >= epoch).await
+    }
+
+    async fn await_quorum(&self, reached: impl Fn(&NodeStatus) -> bool) -> bool {
+        let quorum = 2 * self.sim.config.num_faulty + 1;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let num_reached = (0..self.sim.nodes.len())
+                .filter(|id| reached(&self.status(*id)))
+                .count();
+            if num_reached >= quorum {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Address of the replica with the id in clusters built by a `ClusterBuilder`
+pub fn replica_addr(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))
+}
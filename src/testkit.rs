@@ -0,0 +1,236 @@
+use crate::keystore::Keystore;
+use crate::messages::{
+    BatchOp, ClientRequest, Commit, NewView, Operation, PrePrepare, Prepare, ViewChange,
+};
+use crate::{Key, NodeId, Value};
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use ed25519_dalek::PublicKey;
+
+/// Builds correctly signed protocol messages for tests.
+/// All messages are signed by the builder's keypair and share its view, sequence number
+/// and client request, which can be overridden to construct messages which should be rejected
+#[derive(Clone)]
+pub struct MessageBuilder {
+    /// Keystore whose keypair signs the messages
+    keystore: Keystore,
+    /// Id of the node the messages are sent from
+    id: NodeId,
+    /// Membership epoch the messages belong to
+    epoch: usize,
+    view: usize,
+    seq_num: usize,
+    client_request: ClientRequest,
+    /// Request whose digest is placed in the messages instead of the digest of client_request
+    digest_request: Option<ClientRequest>,
+}
+
+impl MessageBuilder {
+    pub fn new(keystore: Keystore, id: NodeId) -> Self {
+        Self {
+            keystore,
+            id,
+            epoch: 0,
+            view: 0,
+            seq_num: 1,
+            client_request: RequestBuilder::new().build(),
+            digest_request: None,
+        }
+    }
+
+    /// Builder with a freshly generated keypair
+    pub fn generate(id: NodeId) -> Self {
+        Self::new(Keystore::generate(), id)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keystore.public_key()
+    }
+
+    pub fn keystore(&self) -> &Keystore {
+        &self.keystore
+    }
+
+    pub fn keypair_bytes(&self) -> Vec<u8> {
+        self.keystore.keypair().unwrap().to_bytes().to_vec()
+    }
+
+    pub fn id(mut self, id: NodeId) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn epoch(mut self, epoch: usize) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn view(mut self, view: usize) -> Self {
+        self.view = view;
+        self
+    }
+
+    pub fn seq_num(mut self, seq_num: usize) -> Self {
+        self.seq_num = seq_num;
+        self
+    }
+
+    pub fn client_request(mut self, client_request: ClientRequest) -> Self {
+        self.client_request = client_request;
+        self
+    }
+
+    /// Messages carry (and sign) a digest which does not match the client request
+    pub fn wrong_digest(mut self) -> Self {
+        let mut digest_request = self.client_request.clone();
+        digest_request.time_stamp += 1;
+        self.digest_request = Some(digest_request);
+        self
+    }
+
+    /// Messages are for the view after the current one
+    pub fn wrong_view(mut self) -> Self {
+        self.view += 1;
+        self
+    }
+
+    fn digest_request(&self) -> &ClientRequest {
+        self.digest_request.as_ref().unwrap_or(&self.client_request)
+    }
+
+    pub fn pre_prepare(&self) -> PrePrepare {
+        let mut pre_prepare = PrePrepare::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.epoch,
+            self.view,
+            self.seq_num,
+            &self.client_request,
+        );
+        pre_prepare.client_request_digest = self.digest_request().digest_at(self.seq_num);
+        pre_prepare
+    }
+
+    pub fn prepare(&self) -> Prepare {
+        Prepare::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.epoch,
+            self.view,
+            self.seq_num,
+            self.digest_request(),
+        )
+    }
+
+    pub fn commit(&self) -> Commit {
+        Commit::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.epoch,
+            self.view,
+            self.seq_num,
+            self.digest_request().digest_at(self.seq_num),
+        )
+    }
+
+    /// View change to the view after the builder's view, with no checkpoint proof
+    /// and no prepared requests
+    pub fn view_change(&self) -> ViewChange {
+        ViewChange::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.epoch,
+            self.view + 1,
+            0,
+            Vec::new(),
+            BTreeMap::new(),
+        )
+    }
+
+    /// New view for the view after the builder's view, based on the view changes,
+    /// which re-proposes no requests
+    pub fn new_view(&self, view_changes: Vec<ViewChange>) -> NewView {
+        NewView::new_with_signature(
+            &self.keystore,
+            self.id,
+            self.epoch,
+            self.view + 1,
+            view_changes,
+            Vec::new(),
+        )
+    }
+}
+
+/// Builds client requests for tests. By default the request sets x to 1 at timestamp 0,
+/// from a client without an id which listens on port 0 of localhost, where nobody answers
+#[derive(Clone)]
+pub struct RequestBuilder {
+    request: ClientRequest,
+}
+
+impl Default for RequestBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestBuilder {
+    pub fn new() -> Self {
+        Self {
+            request: ClientRequest {
+                respond_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+                time_stamp: 0,
+                key: Key::from("x"),
+                operation: Operation::Set(Value::from("1")),
+                relay_id: None,
+                read_only: false,
+                batch: Vec::new(),
+                bulk: None,
+                client_id: None,
+                signature: Vec::new(),
+            },
+        }
+    }
+
+    pub fn respond_addr(mut self, respond_addr: SocketAddr) -> Self {
+        self.request.respond_addr = respond_addr;
+        self
+    }
+
+    pub fn time_stamp(mut self, time_stamp: usize) -> Self {
+        self.request.time_stamp = time_stamp;
+        self
+    }
+
+    pub fn key(mut self, key: Key) -> Self {
+        self.request.key = key;
+        self
+    }
+
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.request.operation = operation;
+        self
+    }
+
+    /// The request sets the key to the value
+    pub fn set(self, value: &str) -> Self {
+        self.operation(Operation::Set(Value::from(value)))
+    }
+
+    pub fn relay_id(mut self, relay_id: NodeId) -> Self {
+        self.request.relay_id = Some(relay_id);
+        self
+    }
+
+    /// The operations are applied atomically in place of the key and value
+    pub fn batch(mut self, batch: Vec<BatchOp>) -> Self {
+        self.request.batch = batch;
+        self
+    }
+
+    pub fn build(&self) -> ClientRequest {
+        self.request.clone()
+    }
+}
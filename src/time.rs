@@ -0,0 +1,242 @@
+use crate::NodeId;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Milliseconds since the unix epoch on the system clock. Wall-clock time is read here
+/// rather than from `SystemTime` directly, and never decides anything in the protocol:
+/// it only stamps client requests, certificates and logs
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+}
+
+/// Time of day simulated clocks read when their simulation starts
+const SIMULATION_START_MILLIS: u64 = 1_600_000_000_000;
+
+/// Wall clock of a node: the system clock or, in a simulation, the virtual time elapsed
+/// since the simulation started, so that runs with the same seed send the same readings.
+/// It can be set off by a fixed amount to simulate replicas whose clocks disagree
+#[derive(Clone, Default)]
+pub struct WallClock {
+    /// When the simulation the clock runs in started, if any
+    simulation_start: Option<Instant>,
+    offset_millis: Arc<AtomicI64>,
+}
+
+impl WallClock {
+    /// Clock on the virtual time of the simulation which started at the instant
+    pub fn simulated(simulation_start: Instant) -> Self {
+        Self {
+            simulation_start: Some(simulation_start),
+            offset_millis: Arc::default(),
+        }
+    }
+
+    pub fn now_millis(&self) -> u64 {
+        let now_millis = match self.simulation_start {
+            Some(start) => SIMULATION_START_MILLIS + start.elapsed().as_millis() as u64,
+            None => unix_millis(),
+        };
+        now_millis.saturating_add_signed(self.offset_millis.load(Ordering::Relaxed))
+    }
+
+    /// Sets the clock ahead of the system clock (behind it for a negative offset)
+    pub fn set_offset_millis(&self, offset_millis: i64) {
+        self.offset_millis.store(offset_millis, Ordering::Relaxed);
+    }
+}
+
+/// Reading of the wall clock of a replica, piggybacked on the identifiers replicas
+/// broadcast periodically. It echoes the last reading we received from the peer it is sent
+/// to, so that the peer estimates the offset between our clocks like NTP does, from the
+/// times the two readings were sent and received. It is not signed, as it only feeds
+/// operational alerts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSample {
+    pub sent_at_millis: u64,
+    pub echo: Option<ClockEcho>,
+}
+
+/// Reading of the peer we answer, and when we received it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockEcho {
+    pub origin_millis: u64,
+    pub received_at_millis: u64,
+}
+
+/// Offsets of the clocks of the peers from ours, and the peers skewed past the alert
+/// threshold, reported in node statuses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockStats {
+    /// Latest estimate for each peer we exchanged readings with
+    pub peers: BTreeMap<NodeId, PeerSkew>,
+    /// Median of the offsets of the peers. Far from zero, it is our clock which is off
+    pub median_offset_millis: Option<i64>,
+    /// Peers whose clock is off from ours by more than the alert threshold
+    pub skewed_peers: Vec<NodeId>,
+}
+
+impl ClockStats {
+    /// Largest offset of a peer from our clock, either way
+    pub fn max_skew_millis(&self) -> u64 {
+        self.peers
+            .values()
+            .map(|peer| peer.offset_millis.unsigned_abs())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerSkew {
+    /// How far the clock of the peer is ahead of ours (behind it when negative)
+    pub offset_millis: i64,
+    /// Round trip of the exchange the offset was estimated from, which bounds its error
+    pub round_trip_millis: u64,
+}
+
+/// Skew of the clocks of the peers relative to ours, estimated from the readings
+/// piggybacked on identifiers. Replicas warn operators when a clock is off by more than
+/// the alert threshold, before timestamps in client requests and logs become confusing;
+/// the protocol itself does not depend on clocks
+#[derive(Clone, Default)]
+pub struct ClockSkew {
+    alert_threshold: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    peers: BTreeMap<NodeId, PeerClock>,
+    /// Whether we warned that our own clock is off
+    is_skewed: bool,
+}
+
+#[derive(Default)]
+struct PeerClock {
+    /// Last reading of the peer, which we echo in the next one we send it
+    to_echo: Option<ClockEcho>,
+    skew: Option<PeerSkew>,
+    /// Whether we warned that the clock of the peer is off
+    is_skewed: bool,
+}
+
+impl ClockSkew {
+    /// Alerts on clocks off by more than the threshold (zero for no alerts)
+    pub fn new(alert_threshold: Duration) -> Self {
+        Self {
+            alert_threshold,
+            inner: Arc::default(),
+        }
+    }
+
+    /// Our reading to send to the peer at the time, echoing its last one
+    pub fn sample_for(&self, peer_id: NodeId, now_millis: u64) -> ClockSample {
+        let mut inner = self.inner.lock().unwrap();
+        ClockSample {
+            sent_at_millis: now_millis,
+            echo: inner
+                .peers
+                .get_mut(&peer_id)
+                .and_then(|peer| peer.to_echo.take()),
+        }
+    }
+
+    /// Takes the reading of the peer, received at the time. If it echoes one of ours,
+    /// the offset of its clock is estimated again, and an alert raised or cleared
+    pub fn observe(&self, peer_id: NodeId, sample: &ClockSample, received_at_millis: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let peer = inner.peers.entry(peer_id).or_default();
+        peer.to_echo = Some(ClockEcho {
+            origin_millis: sample.sent_at_millis,
+            received_at_millis,
+        });
+        let echo = match sample.echo {
+            Some(echo) => echo,
+            None => return,
+        };
+        // t0 and t3 are read on our clock, t1 and t2 on the clock of the peer
+        let (t0, t1, t2, t3) = (
+            echo.origin_millis as i64,
+            echo.received_at_millis as i64,
+            sample.sent_at_millis as i64,
+            received_at_millis as i64,
+        );
+        let skew = PeerSkew {
+            offset_millis: ((t1 - t0) + (t2 - t3)) / 2,
+            round_trip_millis: ((t3 - t0) - (t2 - t1)).max(0) as u64,
+        };
+        peer.skew = Some(skew);
+
+        if self.alert_threshold.is_zero() {
+            return;
+        }
+        let threshold = self.alert_threshold.as_millis() as u64;
+        let is_skewed = skew.offset_millis.unsigned_abs() > threshold;
+        if is_skewed && !peer.is_skewed {
+            warn!(
+                "The clock of node {} is {} ms {} ours, past the alert threshold of {} ms",
+                peer_id,
+                skew.offset_millis.unsigned_abs(),
+                if skew.offset_millis > 0 {
+                    "ahead of"
+                } else {
+                    "behind"
+                },
+                threshold
+            );
+        } else if !is_skewed && peer.is_skewed {
+            info!("The clock of node {} is back in line with ours", peer_id);
+        }
+        peer.is_skewed = is_skewed;
+
+        let median_offset_millis = median_offset(&inner.peers);
+        let is_skewed =
+            median_offset_millis.is_some_and(|median| median.unsigned_abs() > threshold);
+        if is_skewed && !inner.is_skewed {
+            warn!(
+                "Our clock is {} ms off the median clock of our peers, past the alert threshold of {} ms",
+                median_offset_millis.unwrap_or(0).unsigned_abs(),
+                threshold
+            );
+        } else if !is_skewed && inner.is_skewed {
+            info!("Our clock is back in line with those of our peers");
+        }
+        inner.is_skewed = is_skewed;
+    }
+
+    pub fn stats(&self) -> ClockStats {
+        let inner = self.inner.lock().unwrap();
+        ClockStats {
+            peers: inner
+                .peers
+                .iter()
+                .filter_map(|(peer_id, peer)| Some((*peer_id, peer.skew?)))
+                .collect(),
+            median_offset_millis: median_offset(&inner.peers),
+            skewed_peers: inner
+                .peers
+                .iter()
+                .filter(|(_, peer)| peer.is_skewed)
+                .map(|(peer_id, _)| *peer_id)
+                .collect(),
+        }
+    }
+}
+
+fn median_offset(peers: &BTreeMap<NodeId, PeerClock>) -> Option<i64> {
+    let mut offsets: Vec<i64> = peers
+        .values()
+        .filter_map(|peer| Some(peer.skew?.offset_millis))
+        .collect();
+    offsets.sort_unstable();
+    offsets.get(offsets.len() / 2).copied()
+}
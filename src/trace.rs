@@ -0,0 +1,337 @@
+use crate::messages::{ClientRequest, ClientResponse, Message, Operation};
+use crate::observer::{Observer, QuorumKind};
+use crate::state::SlotMeta;
+use crate::time;
+use crate::NodeId;
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Event of a replica in a recorded run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Replica the event happened at
+    pub node: NodeId,
+    /// Wall clock time of the event, so that the traces of several replicas can be merged
+    pub at_millis: u64,
+    pub event: TraceEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceEvent {
+    Received {
+        message: Message,
+    },
+    /// The destination is not set for messages broadcast to every peer
+    Sent {
+        message: Message,
+        destination: Option<SocketAddr>,
+    },
+    Quorum {
+        kind: QuorumKind,
+        view: usize,
+        seq_num: usize,
+        participants: Vec<NodeId>,
+    },
+    Applied {
+        seq_num: usize,
+        request: ClientRequest,
+        response: ClientResponse,
+    },
+    ViewChange {
+        view: usize,
+    },
+}
+
+/// Records every message and protocol event of the replica to a file as JSON lines,
+/// which `pbft_trace` turns into a readable narrative of the run
+pub struct TraceObserver {
+    id: NodeId,
+    file: Mutex<File>,
+}
+
+impl TraceObserver {
+    pub fn new(path: &Path, id: NodeId) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            id,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(&self, event: TraceEvent) {
+        let entry = TraceEntry {
+            node: self.id,
+            at_millis: time::unix_millis(),
+            event,
+        };
+        let _ = writeln!(
+            self.file.lock().unwrap(),
+            "{}",
+            serde_json::to_string(&entry).unwrap()
+        );
+    }
+}
+
+impl Observer for TraceObserver {
+    fn on_message_in(&self, message: &Message) {
+        self.append(TraceEvent::Received {
+            message: message.clone(),
+        });
+    }
+
+    fn on_message_out(&self, message: &Message, destination: Option<SocketAddr>) {
+        self.append(TraceEvent::Sent {
+            message: message.clone(),
+            destination,
+        });
+    }
+
+    fn on_quorum(&self, kind: QuorumKind, view: usize, seq_num: usize, participants: &[NodeId]) {
+        self.append(TraceEvent::Quorum {
+            kind,
+            view,
+            seq_num,
+            participants: participants.to_vec(),
+        });
+    }
+
+    fn on_commit(&self, slot: &SlotMeta, request: &ClientRequest, response: &ClientResponse) {
+        self.append(TraceEvent::Applied {
+            seq_num: slot.seq_num,
+            request: request.clone(),
+            response: response.clone(),
+        });
+    }
+
+    fn on_view_change(&self, view: usize) {
+        self.append(TraceEvent::ViewChange { view });
+    }
+}
+
+/// Reads the entries of a trace file. Lines which are not entries (e.g. one cut off
+/// when the replica was killed) are skipped
+pub fn read_trace(path: &Path) -> std::io::Result<Vec<TraceEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Describes the entry as a sentence of the protocol narrative, e.g.
+/// "node 2 prepared (v=0, n=5) with votes from {0,2,3}".
+/// Received messages are only described if `with_received` is set, since every message
+/// is then described twice in a merged trace, and identifiers, progress and status messages
+/// are never described
+pub fn narrate(entry: &TraceEntry, with_received: bool) -> Option<String> {
+    let node = entry.node;
+    let sentence = match &entry.event {
+        TraceEvent::Received { message } => {
+            if !with_received || !is_protocol_message(message) {
+                return None;
+            }
+            match message.get_id() {
+                Some(sender) => format!(
+                    "node {} received {} from node {}",
+                    node,
+                    describe_message(message),
+                    sender
+                ),
+                None => format!("node {} received {}", node, describe_message(message)),
+            }
+        }
+        TraceEvent::Sent {
+            message,
+            destination,
+        } => {
+            if !is_protocol_message(message) {
+                return None;
+            }
+            match destination {
+                Some(destination) => format!(
+                    "node {} sent {} to {}",
+                    node,
+                    describe_message(message),
+                    destination
+                ),
+                None => format!("node {} broadcast {}", node, describe_message(message)),
+            }
+        }
+        TraceEvent::Quorum {
+            kind,
+            view,
+            seq_num,
+            participants,
+        } => {
+            let votes = describe_nodes(participants);
+            match kind {
+                QuorumKind::Prepare => format!(
+                    "node {} prepared (v={}, n={}) with votes from {}",
+                    node, view, seq_num, votes
+                ),
+                QuorumKind::Commit => format!(
+                    "node {} committed (v={}, n={}) with votes from {}",
+                    node, view, seq_num, votes
+                ),
+                QuorumKind::CheckPoint => format!(
+                    "node {} made the checkpoint at n={} stable with votes from {}",
+                    node, seq_num, votes
+                ),
+                QuorumKind::ViewChange => format!(
+                    "node {} gathered view changes to v={} from {}",
+                    node, view, votes
+                ),
+            }
+        }
+        TraceEvent::Applied {
+            seq_num,
+            request,
+            response,
+        } => format!(
+            "node {} executed n={}: {} for client {} -> {}",
+            node,
+            seq_num,
+            describe_request(request),
+            request.respond_addr,
+            describe_response(response)
+        ),
+        TraceEvent::ViewChange { view } => format!("node {} moved to view {}", node, view),
+    };
+    Some(sentence)
+}
+
+/// Messages which make up the protocol, as opposed to membership, progress and status traffic
+fn is_protocol_message(message: &Message) -> bool {
+    !matches!(
+        message,
+        Message::IdentifierMessage(_)
+            | Message::StatusRequestMessage(_)
+            | Message::StatusMessage(_)
+            | Message::ProgressMessage(_)
+            | Message::WatchLeaderMessage(_)
+            | Message::LeaderMessage(_)
+            | Message::CompactLogMessage(_)
+            | Message::WatchProgressMessage(_)
+            | Message::CommitProgressMessage(_)
+            | Message::DrainMessage(_)
+            | Message::GetDiffMessage(_)
+            | Message::CheckpointDiffMessage(_)
+            | Message::GetEvidenceMessage(_)
+            | Message::EvidenceMessage(_)
+            | Message::PingMessage(_)
+            | Message::PongMessage(_)
+    )
+}
+
+fn describe_message(message: &Message) -> String {
+    match message {
+        Message::PrePrepareMessage(pre_prepare) => format!(
+            "pre-prepare (v={}, n={}) for {}",
+            pre_prepare.view,
+            pre_prepare.seq_num,
+            describe_request(&pre_prepare.client_request)
+        ),
+        Message::PrepareMessage(prepare) => {
+            format!("prepare (v={}, n={})", prepare.view, prepare.seq_num)
+        }
+        Message::CommitMessage(commit) => {
+            format!("commit (v={}, n={})", commit.view, commit.seq_num)
+        }
+        Message::CheckPointMessage(checkpoint) => format!(
+            "checkpoint (v={}, n={})",
+            checkpoint.view, checkpoint.committed_seq_num
+        ),
+        Message::ViewChangeMessage(view_change) => format!(
+            "view change to v={} (stable at n={}, {} prepared)",
+            view_change.new_view,
+            view_change.last_stable_seq_num,
+            view_change.prepared_certificates.len() + view_change.subsequent_prepares.len()
+        ),
+        Message::NewViewMessage(new_view) => format!(
+            "new view v={} from view changes of {} with {} pre-prepares",
+            new_view.view,
+            describe_nodes(
+                &new_view
+                    .view_change_messages
+                    .iter()
+                    .map(|view_change| view_change.id)
+                    .collect::<Vec<NodeId>>()
+            ),
+            new_view.outstanding_pre_prepares.len()
+        ),
+        Message::ClientRequestMessage(request) => format!(
+            "request {} from client {}",
+            describe_request(request),
+            request.respond_addr
+        ),
+        Message::ClientResponseMessage(response) => {
+            format!("reply {}", describe_response(response))
+        }
+        Message::RelayedClientResponseMessage(relayed) => format!(
+            "reply {} for client {}",
+            describe_response(&relayed.response),
+            relayed.respond_addr
+        ),
+        Message::StaleMessageNotice(stale_message) => format!(
+            "stale message notice for n={} (stable at n={})",
+            stale_message.seq_num, stale_message.last_stable_seq_num
+        ),
+        Message::GetProofMessage(get_proof) => format!("proof request for {}", get_proof.key),
+        Message::KeyProofMessage(key_proof) => format!(
+            "proof of {} at n={}",
+            key_proof.key, key_proof.committed_seq_num
+        ),
+        message => message.kind().to_string(),
+    }
+}
+
+fn describe_request(request: &ClientRequest) -> String {
+    if !request.batch.is_empty() {
+        format!(
+            "a batch of {} writes (t={})",
+            request.batch.len(),
+            request.time_stamp
+        )
+    } else {
+        match &request.operation {
+            Operation::Get => format!("get {} (t={})", request.key, request.time_stamp),
+            Operation::Set(value) => {
+                format!("put {}={} (t={})", request.key, value, request.time_stamp)
+            }
+            Operation::Delete => format!("delete {} (t={})", request.key, request.time_stamp),
+            Operation::Cas { expected, new } => format!(
+                "cas {} {:?}->{} (t={})",
+                request.key, expected, new, request.time_stamp
+            ),
+        }
+    }
+}
+
+fn describe_response(response: &ClientResponse) -> String {
+    match (response.reason, &response.value) {
+        (Some(reason), _) => format!("rejected ({})", reason),
+        (None, Some(value)) => format!("{}={} (t={})", response.key, value, response.time_stamp),
+        (None, None) => format!("{} unset (t={})", response.key, response.time_stamp),
+    }
+}
+
+fn describe_nodes(nodes: &[NodeId]) -> String {
+    let nodes = nodes
+        .iter()
+        .collect::<BTreeSet<&NodeId>>()
+        .into_iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>();
+    format!("{{{}}}", nodes.join(","))
+}
@@ -0,0 +1,394 @@
+use crate::codec::{self, ReadLimits};
+use crate::crypto::{self, SigningInput};
+use crate::keystore::Keystore;
+use crate::messages::Message;
+use crate::node::InnerNode;
+use crate::NodeId;
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::PublicKey;
+use rand::rngs::OsRng;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Bytes an initiator sends before its handshake, which set encrypted connections apart
+/// from plaintext messages on the same port
+pub const HANDSHAKE_MAGIC: &[u8; 8] = b"PBFTSEC1";
+
+/// Length of the authentication tag of a record
+const TAG_LEN: usize = 32;
+
+/// Largest handshake accepted, well above the JSON encoding of a `Hello`, so that a peer
+/// cannot make us buffer a large frame before it authenticated
+const MAX_HELLO_LEN: usize = 4096;
+
+/// Encrypted channel between two replicas over a single connection.
+///
+/// The replica opening the connection sends `HANDSHAKE_MAGIC` and a hello carrying its id,
+/// its ed25519 public key and an ephemeral X25519 key, signed with its identity key.
+/// The other replica answers with its own hello, whose signature also covers the ephemeral
+/// key of the initiator. Both derive a key per direction from the X25519 shared secret
+/// and the transcript, and every frame sent afterwards is a record encrypted with ChaCha20
+/// and authenticated with HMAC-SHA256 (encrypt-then-MAC), numbered to prevent replays.
+///
+/// The initiator authenticates the other replica against the key it learned for it, if any.
+/// The accepting replica reports the key the initiator proved it holds, which it checks
+/// against the key it knows once it read the message (a restarted peer first announces
+/// its new key in an identifier)
+pub struct SecureChannel {
+    /// Id of the authenticated peer
+    pub peer_id: NodeId,
+    pub peer_pub_key: PublicKey,
+    send: DirectionKeys,
+    recv: DirectionKeys,
+}
+
+struct DirectionKeys {
+    enc_key: [u8; 32],
+    mac_key: [u8; 32],
+    counter: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    id: NodeId,
+    pub_key: Vec<u8>,
+    ephemeral: [u8; 32],
+    signature: Vec<u8>,
+}
+
+impl Hello {
+    fn new(
+        keystore: &Keystore,
+        id: NodeId,
+        ephemeral: [u8; 32],
+        initiator: Option<&[u8; 32]>,
+    ) -> Self {
+        let signing_input = Self::signing_input(id, &ephemeral, initiator);
+        Self {
+            id,
+            pub_key: keystore.public_key().as_bytes().to_vec(),
+            ephemeral,
+            signature: keystore.sign(&signing_input, crypto::policy().algorithm),
+        }
+    }
+
+    /// The hello of the initiator covers its ephemeral key,
+    /// and the answer covers both ephemeral keys
+    fn signing_input(
+        id: NodeId,
+        ephemeral: &[u8; 32],
+        initiator: Option<&[u8; 32]>,
+    ) -> SigningInput {
+        let mut signing_input = SigningInput::new();
+        match initiator {
+            None => signing_input.update(b"TransportHello"),
+            Some(initiator) => {
+                signing_input.update(b"TransportHelloReply");
+                signing_input.update(initiator);
+            }
+        }
+        signing_input.update_usize(id);
+        signing_input.update(ephemeral);
+        signing_input
+    }
+
+    /// Public key of the sender, if the hello is signed with it and it is the key we expect
+    fn verify(
+        &self,
+        initiator: Option<&[u8; 32]>,
+        expected_key: Option<&PublicKey>,
+    ) -> Result<PublicKey, TransportError> {
+        let pub_key = PublicKey::from_bytes(&self.pub_key)
+            .map_err(|_| TransportError::Unauthenticated(self.id))?;
+        if expected_key.is_some_and(|expected_key| *expected_key != pub_key) {
+            return Err(TransportError::Unauthenticated(self.id));
+        }
+        let signing_input = Self::signing_input(self.id, &self.ephemeral, initiator);
+        if !crypto::verify(&pub_key, &signing_input, &self.signature) {
+            return Err(TransportError::Unauthenticated(self.id));
+        }
+        Ok(pub_key)
+    }
+}
+
+/// Ephemeral X25519 secret and public key
+fn ephemeral_key() -> (Scalar, [u8; 32]) {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    secret[0] &= 248;
+    secret[31] &= 127;
+    secret[31] |= 64;
+    let secret = Scalar::from_bits(secret);
+    let public = (X25519_BASEPOINT * secret).to_bytes();
+    (secret, public)
+}
+
+impl SecureChannel {
+    /// Opens a channel to the peer, which must authenticate with the expected key if given
+    pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        keystore: &Keystore,
+        id: NodeId,
+        peer_id: NodeId,
+        expected_key: Option<&PublicKey>,
+    ) -> Result<Self, TransportError> {
+        let (secret, ephemeral) = ephemeral_key();
+        let hello = Hello::new(keystore, id, ephemeral, None);
+        let mut handshake = HANDSHAKE_MAGIC.to_vec();
+        handshake.extend(frame(&serde_json::to_vec(&hello).unwrap()));
+        stream.write_all(&handshake).await?;
+
+        let reply: Hello = serde_json::from_slice(&read_frame(stream, &hello_limits()).await?)
+            .map_err(|_| TransportError::Malformed)?;
+        if reply.id != peer_id {
+            return Err(TransportError::Unauthenticated(reply.id));
+        }
+        let peer_pub_key = reply.verify(Some(&ephemeral), expected_key)?;
+
+        let shared = (MontgomeryPoint(reply.ephemeral) * secret).to_bytes();
+        let transcript = Transcript {
+            shared,
+            initiator: (id, ephemeral),
+            responder: (peer_id, reply.ephemeral),
+        };
+        Ok(Self {
+            peer_id,
+            peer_pub_key,
+            send: transcript.keys(b"initiator to responder"),
+            recv: transcript.keys(b"responder to initiator"),
+        })
+    }
+
+    /// Completes the handshake of a peer which opened a channel to us, after its magic bytes
+    /// were read
+    pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        keystore: &Keystore,
+        id: NodeId,
+    ) -> Result<Self, TransportError> {
+        let hello: Hello = serde_json::from_slice(&read_frame(stream, &hello_limits()).await?)
+            .map_err(|_| TransportError::Malformed)?;
+        let peer_id = hello.id;
+        let peer_pub_key = hello.verify(None, None)?;
+
+        let (secret, ephemeral) = ephemeral_key();
+        let reply = Hello::new(keystore, id, ephemeral, Some(&hello.ephemeral));
+        stream
+            .write_all(&frame(&serde_json::to_vec(&reply).unwrap()))
+            .await?;
+
+        let shared = (MontgomeryPoint(hello.ephemeral) * secret).to_bytes();
+        let transcript = Transcript {
+            shared,
+            initiator: (peer_id, hello.ephemeral),
+            responder: (id, ephemeral),
+        };
+        Ok(Self {
+            peer_id,
+            peer_pub_key,
+            send: transcript.keys(b"responder to initiator"),
+            recv: transcript.keys(b"initiator to responder"),
+        })
+    }
+
+    /// Encrypts and writes the data (e.g. a serialized message) as one record
+    pub async fn write<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+        data: &[u8],
+    ) -> Result<(), TransportError> {
+        let counter = self.send.counter;
+        self.send.counter += 1;
+        let mut record = data.to_vec();
+        apply_keystream(&self.send.enc_key, counter, &mut record);
+        let tag = hmac_sha256(&self.send.mac_key, &[&counter.to_le_bytes(), &record]);
+        record.extend_from_slice(&tag);
+        writer.write_all(&frame(&record)).await?;
+        Ok(())
+    }
+
+    /// Reads and decrypts the next record, which must be the next one the peer sent
+    pub async fn read<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Vec<u8>, TransportError> {
+        self.read_within(reader, &ReadLimits::default()).await
+    }
+
+    /// Reads and decrypts the next record, failing if the peer sends it over the limits
+    pub async fn read_within<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        limits: &ReadLimits,
+    ) -> Result<Vec<u8>, TransportError> {
+        // the limits are on the data, which the tag follows
+        let limits = ReadLimits {
+            max_frame_len: limits.max_frame_len + TAG_LEN,
+            ..*limits
+        };
+        let mut record = read_frame(reader, &limits).await?;
+        if record.len() < TAG_LEN {
+            return Err(TransportError::Malformed);
+        }
+        let tag = record.split_off(record.len() - TAG_LEN);
+        let counter = self.recv.counter;
+        let expected = hmac_sha256(&self.recv.mac_key, &[&counter.to_le_bytes(), &record]);
+        // compare in constant time
+        if tag
+            .iter()
+            .zip(expected.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            != 0
+        {
+            return Err(TransportError::Unauthenticated(self.peer_id));
+        }
+        self.recv.counter += 1;
+        apply_keystream(&self.recv.enc_key, counter, &mut record);
+        Ok(record)
+    }
+}
+
+/// Secret shared by the ends of a channel and the handshake it came from
+struct Transcript {
+    shared: [u8; 32],
+    initiator: (NodeId, [u8; 32]),
+    responder: (NodeId, [u8; 32]),
+}
+
+impl Transcript {
+    fn keys(&self, direction: &[u8]) -> DirectionKeys {
+        let material = Sha512::new()
+            .chain_update(b"pbft transport ")
+            .chain_update(direction)
+            .chain_update(self.shared)
+            .chain_update(crypto::encode_usize(self.initiator.0))
+            .chain_update(self.initiator.1)
+            .chain_update(crypto::encode_usize(self.responder.0))
+            .chain_update(self.responder.1)
+            .finalize();
+        DirectionKeys {
+            enc_key: material[..32].try_into().unwrap(),
+            mac_key: material[32..].try_into().unwrap(),
+            counter: 0,
+        }
+    }
+}
+
+/// XORs the data with the ChaCha20 keystream of the key, using the record number as nonce
+pub(crate) fn apply_keystream(key: &[u8; 32], counter: u64, data: &mut [u8]) {
+    let mut cipher = ChaCha20Rng::from_seed(*key);
+    cipher.set_stream(counter);
+    let mut keystream = vec![0u8; data.len()];
+    cipher.fill_bytes(&mut keystream);
+    for (byte, key_byte) in data.iter_mut().zip(keystream) {
+        *byte ^= key_byte;
+    }
+}
+
+pub(crate) fn hmac_sha256(key: &[u8; 32], data: &[&[u8]]) -> [u8; 32] {
+    let mut inner_pad = [0x36u8; 64];
+    let mut outer_pad = [0x5cu8; 64];
+    for (i, byte) in key.iter().enumerate() {
+        inner_pad[i] ^= byte;
+        outer_pad[i] ^= byte;
+    }
+    let mut inner = Sha256::new().chain_update(inner_pad);
+    for part in data {
+        inner.update(part);
+    }
+    Sha256::new()
+        .chain_update(outer_pad)
+        .chain_update(inner.finalize())
+        .finalize()
+        .into()
+}
+
+/// The data prefixed with its length, as in the wire format of messages
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut framed = (data.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(data);
+    framed
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limits: &ReadLimits,
+) -> Result<Vec<u8>, TransportError> {
+    let started = tokio::time::Instant::now();
+    let len = codec::read_by(limits.deadline(started, None), reader.read_u32()).await? as usize;
+    limits.check_len(len)?;
+    let mut data = vec![0u8; len];
+    codec::read_by(
+        limits.deadline(started, Some(len)),
+        reader.read_exact(&mut data),
+    )
+    .await?;
+    Ok(data)
+}
+
+fn hello_limits() -> ReadLimits {
+    ReadLimits {
+        max_frame_len: MAX_HELLO_LEN,
+        ..ReadLimits::default()
+    }
+}
+
+/// Future of a message sent over a `Transport`
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), TransportError>> + Send + 'a>>;
+
+/// Carries the messages a replica sends to its peers and to clients. Replicas send over TCP
+/// (`node::TcpTransport`), simulated clusters over an in-memory network (`sim::Network`)
+pub trait Transport: Send + Sync {
+    /// Sends the message of the node to the first of the addresses which is reachable.
+    /// `peer_id` is the replica the addresses belong to, if they are a replica's
+    fn send<'a>(
+        &'a self,
+        node: &'a InnerNode,
+        addrs: &'a [SocketAddr],
+        peer_id: Option<NodeId>,
+        message: Message,
+    ) -> SendFuture<'a>;
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    Io(std::io::Error),
+    /// None of the addresses of the destination could be reached
+    Unreachable(std::io::Error),
+    /// The handshake or a record is not well formed
+    Malformed,
+    /// The peer did not prove it holds the key of the node it claims to be,
+    /// or a record was not sent by it
+    Unauthenticated(NodeId),
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(e: std::io::Error) -> Self {
+        TransportError::Io(e)
+    }
+}
+
+impl std::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::Io(e) => write!(f, "encrypted connection failed ({})", e),
+            TransportError::Unreachable(e) => write!(f, "destination unreachable ({})", e),
+            TransportError::Malformed => write!(f, "malformed handshake or record"),
+            TransportError::Unauthenticated(id) => {
+                write!(f, "could not authenticate node {}", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
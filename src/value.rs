@@ -0,0 +1,126 @@
+use crate::key::{parse_text, write_text, HEX_PREFIX};
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Value of the store, an arbitrary byte string.
+///
+/// Like keys, values are written as text in messages, snapshots and commands: UTF-8 values
+/// as they are, and other values hex encoded after `hex:` (e.g. `hex:00ff`)
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Value(Vec<u8>);
+
+impl Value {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value(bytes)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value(bytes.to_vec())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value(value.into_bytes())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value(value.as_bytes().to_vec())
+    }
+}
+
+impl FromStr for Value {
+    type Err = InvalidValue;
+
+    /// Parses the text form of the value
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_text(s)
+            .map(Value)
+            .ok_or_else(|| InvalidValue(s.to_string()))
+    }
+}
+
+impl fmt::Display for Value {
+    /// Writes the text form of the value
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_text(f, &self.0)
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ValueVisitor;
+
+        impl Visitor<'_> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "a value, as text or hex encoded after \"{}\"",
+                    HEX_PREFIX
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(ValueVisitor)
+    }
+}
+
+/// Text which starts with `hex:` but is not followed by hex encoded bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidValue(pub String);
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "value {} is not hex encoded after \"{}\"",
+            self.0, HEX_PREFIX
+        )
+    }
+}
+
+impl std::error::Error for InvalidValue {}
@@ -0,0 +1,312 @@
+use crate::authenticator::Authenticator;
+use crate::config::Config;
+use crate::membership::Membership;
+use crate::messages::{ConsensusCommand, Message};
+use crate::NodeId;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+
+use ed25519_dalek::PublicKey;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{Duration, Instant};
+
+/// Signature verifications of the consensus engine, and the messages it put off or shed
+/// while it was overloaded
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationStats {
+    pub verified: usize,
+    /// Messages the verification workers verified before they reached the engine
+    #[serde(default)]
+    pub offloaded: usize,
+    /// Messages put off to be verified once the engine is no longer overloaded
+    #[serde(default)]
+    pub deferred: usize,
+    /// Messages waiting to be verified
+    #[serde(default)]
+    pub backlog: usize,
+    /// Messages dropped without verifying them while the engine was overloaded, by type
+    pub shed: BTreeMap<String, usize>,
+}
+
+impl VerificationStats {
+    pub fn total_shed(&self) -> usize {
+        self.shed.values().sum()
+    }
+}
+
+/// What becomes of a message whose signature the engine is to verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Triage {
+    Verify,
+    /// The message waits in the backlog until the engine is no longer overloaded
+    Defer,
+    /// The message is dropped unverified, as it is sent again or its effect is recovered
+    /// through state transfer
+    Shed,
+}
+
+/// Overload policy for signature verification, which keeps a flood of messages from stalling
+/// the pipeline head behind crypto.
+///
+/// While the engine is overloaded, the time spent verifying in each tick is bounded by the
+/// budget. View changes, new views, checkpoints and blames, and the pre-prepares, prepares and
+/// commits of the sequence numbers up to the next checkpoint, are always verified, so that
+/// checkpoints stabilise and view changes complete whatever the load. Other messages are
+/// verified while the budget lasts, and the less of it remains, the closer to the low
+/// watermark their sequence number must be. The rest wait in a backlog, verified once the
+/// engine is no longer overloaded. Only when the backlog is full are messages shed, and only
+/// those which are recovered without them: protocol messages of later sequence numbers,
+/// which the primary rebroadcasts and checkpoints cover through state transfer, and
+/// messages which are sent periodically or answer requests that are retried
+pub struct VerificationBudget {
+    budget: Duration,
+    tick: Duration,
+    /// Sequence numbers above the low watermark whose messages are always verified
+    head_window: usize,
+    log_window: usize,
+    tick_started: Instant,
+    spent: Duration,
+    /// Messages put off while overloaded, in the order they arrived
+    backlog: VecDeque<Message>,
+    /// How many messages the backlog holds before recoverable messages are shed
+    backlog_capacity: usize,
+    stats: VerificationStats,
+}
+
+impl VerificationBudget {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            budget: config.verification_budget,
+            tick: config.verification_tick,
+            head_window: config.checkpoint_frequency,
+            log_window: config.log_window,
+            tick_started: Instant::now(),
+            spent: Duration::ZERO,
+            backlog: VecDeque::new(),
+            backlog_capacity: config.verification_backlog,
+            stats: VerificationStats::default(),
+        }
+    }
+
+    /// Whether the message should be verified now, put off, or shed unverified. A message
+    /// put off is kept in the backlog (see `take_deferred`)
+    pub fn admit(&mut self, message: &Message, low_watermark: usize, overloaded: bool) -> Triage {
+        if self.tick_started.elapsed() >= self.tick {
+            self.tick_started = Instant::now();
+            self.spent = Duration::ZERO;
+        }
+        if !overloaded || self.budget.is_zero() || self.is_critical(message, low_watermark) {
+            return Triage::Verify;
+        }
+
+        let remaining = self.budget.saturating_sub(self.spent);
+        let reach = self.log_window as f64 * remaining.as_secs_f64() / self.budget.as_secs_f64();
+        let distance = match message.get_seq_num() {
+            Some(seq_num) => seq_num.saturating_sub(low_watermark),
+            None => self.log_window,
+        };
+        if !remaining.is_zero() && distance as f64 <= reach {
+            return Triage::Verify;
+        }
+        if self.backlog.len() < self.backlog_capacity || !is_recoverable(message) {
+            self.backlog.push_back(message.clone());
+            self.stats.deferred += 1;
+            return Triage::Defer;
+        }
+        *self
+            .stats
+            .shed
+            .entry(message.kind().to_string())
+            .or_default() += 1;
+        Triage::Shed
+    }
+
+    /// The message which waited longest in the backlog, to be verified now that the engine
+    /// is no longer overloaded
+    pub fn take_deferred(&mut self) -> Option<Message> {
+        self.backlog.pop_front()
+    }
+
+    /// Accounts for the time a verification took
+    pub fn record(&mut self, elapsed: Duration) {
+        self.spent += elapsed;
+        self.stats.verified += 1;
+    }
+
+    /// Accounts for a message the verification workers verified
+    pub fn record_offloaded(&mut self) {
+        self.stats.offloaded += 1;
+    }
+
+    pub fn stats(&self) -> VerificationStats {
+        VerificationStats {
+            backlog: self.backlog.len(),
+            ..self.stats.clone()
+        }
+    }
+
+    fn is_critical(&self, message: &Message, low_watermark: usize) -> bool {
+        match message {
+            Message::PrePrepareMessage(_)
+            | Message::PrepareMessage(_)
+            | Message::CommitMessage(_) => message
+                .get_seq_num()
+                .is_some_and(|seq_num| seq_num <= low_watermark + self.head_window),
+            Message::ViewChangeMessage(_)
+            | Message::NewViewMessage(_)
+            | Message::CheckPointMessage(_)
+            | Message::BlameMessage(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Whether the effect of the message is recovered if it is dropped: a primary rebroadcasts its
+/// pre-prepares and the sequence numbers a replica misses are covered by the next stable
+/// checkpoint, identifiers, pings and progress reports are sent periodically, and responses
+/// answer requests which are retried
+fn is_recoverable(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::PrePrepareMessage(_)
+            | Message::PrepareMessage(_)
+            | Message::CommitMessage(_)
+            | Message::IdentifierMessage(_)
+            | Message::PingMessage(_)
+            | Message::PongMessage(_)
+            | Message::ProgressMessage(_)
+            | Message::CommitProgressMessage(_)
+            | Message::LeaderMessage(_)
+            | Message::StatusMessage(_)
+            | Message::ClientResponseMessage(_)
+            | Message::RelayedClientResponseMessage(_)
+            | Message::KeyProofMessage(_)
+            | Message::LogEntryMessage(_)
+            | Message::ValueAtMessage(_)
+            | Message::CheckpointDiffMessage(_)
+            | Message::RequestStatusMessage(_)
+            | Message::RequestBodyMessage(_)
+            | Message::StateChunkResponseMessage(_)
+    )
+}
+
+/// Stage in front of the consensus engine which verifies the signatures of incoming messages
+/// on a pool of blocking workers, so that crypto does not serialize the engine.
+///
+/// It takes batches of the commands waiting in the queue, splits each batch into consecutive
+/// runs which the workers verify in parallel, and passes the commands on in the order they
+/// were queued, so the messages of every sender reach the engine in the order they were read.
+/// Signatures are verified one by one: ed25519 batch verification does not cover the
+/// prehashed signatures of the default digest policy
+pub struct VerificationPool {
+    workers: usize,
+    batch_size: usize,
+    /// Our id and key, which with the keys pinned for the members take precedence over
+    /// those learned from identifiers
+    id: NodeId,
+    pub_key: PublicKey,
+    membership: Membership,
+    peer_pub_keys: Arc<tokio::sync::Mutex<HashMap<NodeId, PublicKey>>>,
+    authenticator: Option<Authenticator>,
+}
+
+impl VerificationPool {
+    pub fn new(
+        id: NodeId,
+        pub_key: PublicKey,
+        config: &Config,
+        membership: Membership,
+        peer_pub_keys: Arc<tokio::sync::Mutex<HashMap<NodeId, PublicKey>>>,
+        authenticator: Option<Authenticator>,
+    ) -> Self {
+        Self {
+            workers: config.verification_workers.max(1),
+            batch_size: config.verification_batch_size.max(1),
+            id,
+            pub_key,
+            membership,
+            peer_pub_keys,
+            authenticator,
+        }
+    }
+
+    /// Verifies the messages of the commands from `rx_queued` and passes the commands
+    /// on to `tx_verified`, until either side closes
+    pub async fn run(
+        self,
+        mut rx_queued: Receiver<ConsensusCommand>,
+        tx_verified: Sender<ConsensusCommand>,
+    ) {
+        while let Some(cmd) = rx_queued.recv().await {
+            let mut batch = vec![cmd];
+            while batch.len() < self.batch_size {
+                match rx_queued.try_recv() {
+                    Ok(cmd) => batch.push(cmd),
+                    Err(_) => break,
+                }
+            }
+            for cmd in self.verify(batch).await {
+                if tx_verified.send(cmd).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn verify(&self, batch: Vec<ConsensusCommand>) -> Vec<ConsensusCommand> {
+        let mut pub_keys = self.peer_pub_keys.lock().await.clone();
+        pub_keys.extend(self.membership.pub_keys());
+        pub_keys.insert(self.id, self.pub_key);
+        let pub_keys = Arc::new(pub_keys);
+
+        let run_len = batch.len().div_ceil(self.workers);
+        let mut batch = batch.into_iter();
+        let mut runs = Vec::new();
+        loop {
+            let run: Vec<ConsensusCommand> = batch.by_ref().take(run_len).collect();
+            if run.is_empty() {
+                break;
+            }
+            let pub_keys = pub_keys.clone();
+            let authenticator = self.authenticator.clone();
+            runs.push(tokio::task::spawn_blocking(move || {
+                run.into_iter()
+                    .map(|cmd| verify_command(cmd, &pub_keys, authenticator.as_ref()))
+                    .collect::<Vec<_>>()
+            }));
+        }
+
+        let mut verified = Vec::new();
+        for run in runs {
+            verified.extend(run.await.unwrap());
+        }
+        verified
+    }
+}
+
+fn verify_command(
+    cmd: ConsensusCommand,
+    pub_keys: &HashMap<NodeId, PublicKey>,
+    authenticator: Option<&Authenticator>,
+) -> ConsensusCommand {
+    let message = match cmd {
+        ConsensusCommand::ProcessMessage(message) => message,
+        cmd => return cmd,
+    };
+    let peer_id = match message.get_id() {
+        Some(peer_id) => peer_id,
+        None => return ConsensusCommand::ProcessMessage(message),
+    };
+    let authentic = pub_keys
+        .get(&peer_id)
+        .is_some_and(|pub_key| match &message {
+            Message::ViewChangeMessage(view_change) => {
+                message.is_properly_signed_by(pub_key)
+                    && view_change.are_votes_authentic(pub_keys, authenticator)
+            }
+            _ => message.is_authentic(pub_key, authenticator),
+        });
+    ConsensusCommand::ProcessVerifiedMessage { message, authentic }
+}
@@ -0,0 +1,44 @@
+use crate::Key;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Version of each key: the sequence number of the last committed request which wrote it.
+/// Shared by the consensus engine, which records the writes it applies, and the node,
+/// which reports versions to clients watching a key
+#[derive(Clone, Default)]
+pub struct KeyVersions {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    versions: BTreeMap<Key, usize>,
+    /// Sequence number of the last snapshot installed. The writes it covers were not
+    /// applied here, so every key is reported at least at this version, which is safe
+    /// as the snapshot reflects every write up to it
+    snapshot_seq_num: usize,
+}
+
+impl KeyVersions {
+    /// Records that the request committed at the sequence number wrote the keys
+    pub fn record<'a>(&self, keys: impl IntoIterator<Item = &'a Key>, seq_num: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            inner.versions.insert(key.clone(), seq_num);
+        }
+    }
+
+    /// Every key is now at least at the version of the installed snapshot
+    pub fn install_snapshot(&self, seq_num: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.versions.clear();
+        inner.snapshot_seq_num = seq_num;
+    }
+
+    pub fn get(&self, key: &Key) -> usize {
+        let inner = self.inner.lock().unwrap();
+        let version = inner.versions.get(key).copied().unwrap_or(0);
+        version.max(inner.snapshot_seq_num)
+    }
+}
@@ -1,9 +1,12 @@
 use crate::config::Config;
-use crate::messages::{ClientRequest, ConsensusCommand};
+use crate::connectivity::Connectivity;
+use crate::messages::{ClientRequest, ConsensusCommand, ViewChange};
 use crate::NodeId;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use tokio::sync::mpsc::Sender;
 use tokio::time::sleep;
@@ -20,11 +23,19 @@ pub struct ViewChanger {
     /// or we accept a pre-prepare message
     /// Used to initiate view changes
     pub wait_set: Arc<Mutex<HashSet<ClientRequest>>>,
+    /// Number of times the wait set was reset on entering a new view. Timers started
+    /// in an earlier view do not fire, since the requests are waited for again
+    pub resets: Arc<AtomicUsize>,
+    /// Number of view changes we started since we last executed a request,
+    /// which the view change timers back off with
+    pub consecutive_view_changes: Arc<AtomicUsize>,
     /// These are pre-prepares sent by the leader which we have not applied yet
     /// If a certain amount of time expires and we have not yet applied it
     /// we re-broadcast the pre-prepare to the other peers
     /// Pre-prepares are indexed by (view, seq_num)
     pub sent_pre_prepares: Arc<Mutex<HashSet<(usize, usize)>>>,
+    /// Round trips to the peers, measured by the pings of the node
+    pub connectivity: Connectivity,
 }
 
 impl ViewChanger {
@@ -73,9 +84,44 @@ impl ViewChanger {
         outstanding_requests.clone()
     }
 
+    /// Timeout of the view change timers: the request timeout, doubled for every view change
+    /// since we last executed a request and kept within the bounds of the config, so that
+    /// under load the views do not change faster than a primary can make progress.
+    /// On links slow enough that a request cannot commit within the request timeout,
+    /// the timers start at a multiple of the round trip to a quorum of peers instead
+    pub fn timeout(&self) -> Duration {
+        let doublings = self
+            .consecutive_view_changes
+            .load(Ordering::SeqCst)
+            .min(u32::BITS as usize - 1) as u32;
+        let quorum_rtt = self
+            .connectivity
+            .quorum_rtt(self.config.num_faulty)
+            .unwrap_or_default();
+        self.config
+            .request_timeout
+            .max(quorum_rtt.saturating_mul(self.config.rtt_timeout_factor))
+            .saturating_mul(1 << doublings)
+            .clamp(
+                self.config.view_change_timeout_min,
+                self.config.view_change_timeout_max,
+            )
+    }
+
+    /// We started a view change, so the timers wait twice as long from now on
+    pub fn record_view_change(&self) {
+        self.consecutive_view_changes.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// We executed a request, so the timers are back to the request timeout
+    pub fn record_progress(&self) {
+        self.consecutive_view_changes.store(0, Ordering::SeqCst);
+    }
+
     pub async fn wait_for(&self, request: &ClientRequest) {
-        sleep(self.config.request_timeout).await;
-        if self.is_in_wait_set(&request.clone()) {
+        let resets = self.resets.load(Ordering::SeqCst);
+        sleep(self.timeout()).await;
+        if resets == self.resets.load(Ordering::SeqCst) && self.is_in_wait_set(&request.clone()) {
             let _ = self
                 .tx_consensus
                 .send(ConsensusCommand::InitViewChange(request.clone()))
@@ -83,11 +129,107 @@ impl ViewChanger {
         }
     }
 
+    /// Escalates to the view after the new view if we have not moved to it once the timeout expires
+    pub async fn wait_for_new_view(&self, new_view: usize, timeout: Duration) {
+        sleep(timeout).await;
+        let _ = self
+            .tx_consensus
+            .send(ConsensusCommand::NewViewTimeout(new_view))
+            .await;
+    }
+
     pub fn reset(&mut self) {
         let mut wait_set = self.wait_set.lock().unwrap();
         wait_set.clear();
+        self.resets.fetch_add(1, Ordering::SeqCst);
 
         let mut sent_pre_prepares = self.sent_pre_prepares.lock().unwrap();
         sent_pre_prepares.clear();
     }
 }
+
+/// Requests which the primary of a new view re-proposes, computed from the view changes
+/// the new view is based on. The new primary uses these to build the new view,
+/// and the other replicas to check the pre-prepares it carries
+#[derive(Debug, Default)]
+pub struct NewViewRequests {
+    /// Latest stable sequence number among the view changes
+    pub min_seq_num: usize,
+    /// Highest sequence number prepared in any of the view changes
+    pub max_seq_num: usize,
+    /// The request prepared in the highest view at each sequence number,
+    /// identified by its digest, with its body if a view change carried it
+    pub prepared: BTreeMap<usize, (usize, Vec<u8>, Option<ClientRequest>)>,
+}
+
+impl NewViewRequests {
+    /// The view changes are those accepted, whose prepared requests are backed by quorums
+    /// (see `ViewChange::are_prepared_requests_valid`)
+    pub fn from_view_changes(view_changes: &[ViewChange]) -> Self {
+        let mut requests = Self::default();
+        for view_change in view_changes.iter() {
+            requests.min_seq_num = requests.min_seq_num.max(view_change.last_stable_seq_num);
+            for (seq_num, (pre_prepare, _)) in view_change.subsequent_prepares.iter() {
+                requests.prefer_prepared(
+                    *seq_num,
+                    pre_prepare.view,
+                    &pre_prepare.client_request_digest,
+                    Some(&pre_prepare.client_request),
+                );
+            }
+            for (seq_num, certificate) in view_change.prepared_certificates.iter() {
+                requests.prefer_prepared(
+                    *seq_num,
+                    certificate.view,
+                    &certificate.client_request_digest,
+                    None,
+                );
+            }
+        }
+        // requests at or below the latest stable checkpoint need not be proposed again
+        requests.prepared = requests.prepared.split_off(&(requests.min_seq_num + 1));
+        requests.max_seq_num = requests
+            .prepared
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(requests.min_seq_num);
+        requests
+    }
+
+    /// Keeps the request prepared in the highest view at the sequence number,
+    /// preferring an entry which carries the body of the request
+    fn prefer_prepared(
+        &mut self,
+        seq_num: usize,
+        view: usize,
+        digest: &[u8],
+        client_request: Option<&ClientRequest>,
+    ) {
+        let replace = match self.prepared.get(&seq_num) {
+            None => true,
+            Some((e_view, e_digest, e_client_request)) => {
+                view > *e_view
+                    || (view == *e_view
+                        && digest == e_digest.as_slice()
+                        && e_client_request.is_none()
+                        && client_request.is_some())
+            }
+        };
+        if replace {
+            self.prepared
+                .insert(seq_num, (view, digest.to_vec(), client_request.cloned()));
+        }
+    }
+
+    /// Digest of the request to propose at each sequence number after the stable one,
+    /// with no-op requests filling the gaps between prepared requests
+    pub fn digests(&self) -> BTreeMap<usize, Vec<u8>> {
+        (self.min_seq_num + 1..self.max_seq_num + 1)
+            .map(|seq_num| match self.prepared.get(&seq_num) {
+                Some((_, digest, _)) => (seq_num, digest.clone()),
+                None => (seq_num, ClientRequest::no_op().digest_at(seq_num)),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,92 @@
+use pbft::config::{ArchiveRole, Config};
+use pbft::messages::Operation;
+use pbft::testing::ClusterBuilder;
+use pbft::{Key, Value};
+
+fn config(config: &mut Config) {
+    config.checkpoint_frequency = 2;
+    config.log_window = 4;
+}
+
+#[tokio::test(start_paused = true)]
+async fn archive_nodes_answer_for_the_history_other_replicas_truncated() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(5)
+        .config(config)
+        .replica_config(3, |config| config.is_archive = true)
+        .build();
+    let mut client = cluster.client();
+    for i in 1..=6 {
+        assert!(client
+            .put(Key::from("k"), Value::from(i.to_string()))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(6).await);
+
+    let entry = client.get_entry(3, 1).await.unwrap();
+    let request = entry.request.unwrap();
+    assert_eq!(request.key, Key::from("k"));
+    assert_eq!(request.operation, Operation::Set(Value::from("1")));
+    assert_eq!(entry.commit.unwrap().seq_num, 1);
+    assert!(entry.rejected.is_none());
+    for seq_num in 1..=6 {
+        let value_at = client.get_at(3, Key::from("k"), seq_num).await.unwrap();
+        assert!(value_at.retained);
+        assert_eq!(value_at.value, Some(Value::from(seq_num.to_string())));
+    }
+
+    // the other replicas only keep the log since their last stable checkpoint
+    let entry = client.get_entry(0, 1).await.unwrap();
+    assert!(entry.commit.is_none() && entry.request.is_none());
+    let value_at = client.get_at(0, Key::from("k"), 1).await.unwrap();
+    assert!(!value_at.retained);
+    assert!(value_at.value.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn non_voting_archive_nodes_follow_the_cluster_without_voting() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(6)
+        .config(config)
+        .replica_config(3, |config| {
+            config.is_archive = true;
+            config.archive_role = ArchiveRole::NonVoting;
+        })
+        .build();
+    let mut client = cluster.client();
+    for i in 1..=4 {
+        assert!(client
+            .put(Key::from("k"), Value::from(i.to_string()))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(4).await);
+
+    // it applied what the others committed
+    assert!(cluster.status(3).last_seq_num_committed >= 4);
+    let value_at = client.get_at(3, Key::from("k"), 2).await.unwrap();
+    assert_eq!(value_at.value, Some(Value::from("2")));
+
+    // but as it does not vote, nothing commits once another replica is down
+    cluster.kill_node(0);
+    assert!(client.put(Key::from("k"), Value::from("5")).await.is_none());
+    for id in 1..4 {
+        assert_eq!(cluster.status(id).last_seq_num_committed, 4);
+    }
+}
+
+#[test]
+fn non_voting_archive_nodes_need_a_cluster_which_tolerates_a_fault() {
+    let mut config = Config::new(
+        (0..3)
+            .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+            .collect(),
+    );
+    config.is_archive = true;
+    config.archive_role = ArchiveRole::NonVoting;
+    assert!(config.validate().is_err());
+
+    config.archive_role = ArchiveRole::Voting;
+    assert!(config.validate().is_ok());
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::authenticator::{Authentication, Authenticator};
+use pbft::config::Config;
+use pbft::messages::{Commit, Message, Prepare};
+use pbft::sim::{LinkConfig, Network, Simulation};
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+#[test]
+fn authenticators_carry_a_mac_for_each_replica() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let pub_keys: HashMap<_, _> = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    let authenticators: Vec<Authenticator> = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| Authenticator::new(id, builder.keystore()))
+        .collect();
+
+    let prepare =
+        Prepare::new_with_authenticator(&authenticators[1], &pub_keys, 1, 0, 0, 1, vec![7; 32]);
+    assert!(prepare.signature.is_empty());
+    assert_eq!(prepare.authenticator.len(), 4);
+    // every replica checks the MAC meant for it, which only the sender could compute
+    for authenticator in authenticators.iter() {
+        assert!(prepare.is_authentic(&pub_keys[&1], Some(authenticator)));
+    }
+    assert!(!prepare.is_authentic(&pub_keys[&1], None));
+    assert!(!prepare.is_authentic(&pub_keys[&2], Some(&authenticators[0])));
+    let mut forged = prepare.clone();
+    forged.client_request_digest = vec![8; 32];
+    assert!(!forged.is_authentic(&pub_keys[&1], Some(&authenticators[0])));
+    let mut relabeled = prepare.clone();
+    relabeled.id = 2;
+    assert!(!relabeled.is_authentic(&pub_keys[&2], Some(&authenticators[0])));
+
+    // signed votes are still accepted from replicas which sign them
+    let commit = Commit::new_with_signature(builders[3].keystore(), 3, 0, 0, 1, vec![7; 32]);
+    let commit = Message::CommitMessage(commit);
+    assert!(commit.is_authentic(&pub_keys[&3], Some(&authenticators[0])));
+}
+
+#[tokio::test(start_paused = true)]
+async fn cluster_with_authenticators_commits_through_a_view_change() {
+    let mut config = Config::new(
+        (0..4)
+            .map(|id| (id, SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))))
+            .collect(),
+    );
+    config.authentication = Authentication::Authenticators;
+    config.request_timeout = Duration::from_secs(1);
+    let sim = Simulation::start(config, Network::new(6, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+
+    for round in 0..3 {
+        let value = Value::from(round.to_string());
+        let response = client.put(Key::from("k"), value).await.unwrap();
+        assert!(response.success);
+    }
+    sim.network.partition(&[0]);
+    for round in 3..6 {
+        let value = Value::from(round.to_string());
+        let response = client.put(Key::from("k"), value).await.unwrap();
+        assert!(response.success);
+        assert!(response.view > 0);
+    }
+    let read = client.get(Key::from("k")).await.unwrap();
+    assert_eq!(read.value, Some(Value::from("5")));
+}
@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pbft::backend::{SledBackend, StateBackend};
+use pbft::config::Config;
+use pbft::crypto;
+use pbft::merkle::{self, LeafCache};
+use pbft::messages::{BatchOp, CheckPoint, ClientIdentity, ClientRequest, Operation};
+use pbft::state::{State, StateSnapshot};
+use pbft::storage::{Wal, WalRecord};
+use pbft::testing::ClusterBuilder;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+use tokio::time::sleep;
+
+fn state_dir(name: &str) -> PathBuf {
+    let state_dir = std::env::temp_dir().join(format!("pbft-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&state_dir);
+    state_dir
+}
+
+fn snapshot(committed_seq_num: usize, entries: &[(&str, &str)]) -> StateSnapshot {
+    let store: BTreeMap<Key, Value> = entries
+        .iter()
+        .map(|(key, value)| (Key::from(*key), Value::from(*value)))
+        .collect();
+    let key_owners = store
+        .keys()
+        .map(|key| {
+            let owner = ClientIdentity::Addr("10.0.0.1:9000".parse().unwrap());
+            (key.clone(), owner)
+        })
+        .collect();
+    StateSnapshot {
+        committed_seq_num,
+        store,
+        key_owners,
+    }
+}
+
+#[test]
+fn sled_backend_writes_the_keys_written_since_its_checkpoint() {
+    let state_dir = state_dir("sled-backend");
+    let builder = MessageBuilder::generate(0);
+    let proof = vec![CheckPoint::new_with_signature(
+        builder.keystore(),
+        0,
+        0,
+        20,
+        0,
+        vec![1],
+    )];
+
+    let mut backend = SledBackend::open(&state_dir).unwrap();
+    assert_eq!(backend.load().unwrap(), None);
+    let first = snapshot(10, &[("a", "1"), ("b", "1"), ("c", "1")]);
+    backend.persist(&first, &[], None).unwrap();
+    assert_eq!(backend.stable_seq_num(), Some(10));
+
+    // b was overwritten, c deleted and d created
+    let second = snapshot(20, &[("a", "1"), ("b", "2"), ("d", "2")]);
+    let written: BTreeSet<Key> = ["b", "c", "d"].into_iter().map(Key::from).collect();
+    backend.persist(&second, &proof, Some(&written)).unwrap();
+    drop(backend);
+
+    let backend = SledBackend::open(&state_dir).unwrap();
+    assert_eq!(backend.stable_seq_num(), Some(20));
+    assert_eq!(backend.load().unwrap(), Some((second, proof)));
+
+    // entries which are not in a snapshot written in full are dropped
+    let mut backend = backend;
+    let third = snapshot(30, &[("e", "3")]);
+    backend.persist(&third, &[], None).unwrap();
+    assert_eq!(backend.load().unwrap(), Some((third, Vec::new())));
+    drop(backend);
+    let _ = std::fs::remove_dir_all(&state_dir);
+}
+
+#[test]
+fn digests_follow_the_writes_to_the_store() {
+    let builder = MessageBuilder::generate(0);
+    let addr = "10.0.0.1:9000".parse().unwrap();
+    let mut state = State::new(1, Config::new((0..4).map(|id| (id, addr)).collect()));
+    let algorithm = crypto::policy().algorithm_at(0);
+    let requests = [
+        (Key::from("a"), Operation::Set(Value::from("1")), Vec::new()),
+        (Key::from("b"), Operation::Set(Value::from("1")), Vec::new()),
+        (Key::from("a"), Operation::Set(Value::from("2")), Vec::new()),
+        (
+            Key::default(),
+            Operation::Get,
+            vec![
+                BatchOp::Delete {
+                    key: Key::from("b"),
+                },
+                BatchOp::Put {
+                    key: Key::from("c"),
+                    value: Value::from("3"),
+                },
+            ],
+        ),
+        (Key::from("c"), Operation::Delete, Vec::new()),
+    ];
+    for (seq_num, (key, operation, batch)) in requests.into_iter().enumerate() {
+        let request = ClientRequest {
+            respond_addr: addr,
+            time_stamp: seq_num + 1,
+            key,
+            operation,
+            batch,
+            ..ClientRequest::no_op()
+        };
+        let commit = builder
+            .clone()
+            .seq_num(seq_num + 1)
+            .client_request(request.clone())
+            .commit();
+        state.apply_commit(Arc::new(request), &commit);
+        assert_eq!(
+            state.digest(),
+            merkle::root(&state.store, &state.key_owners, algorithm)
+        );
+    }
+
+    // a cache which is not told of a write goes stale, and one which is catches up
+    let mut cache = LeafCache::default();
+    let mut store = state.store.clone();
+    let root = cache.root(&store, &state.key_owners, algorithm);
+    store.insert(Key::from("a"), Value::from("3"));
+    assert_eq!(cache.root(&store, &state.key_owners, algorithm), root);
+    cache.invalidate([&Key::from("a")]);
+    assert_eq!(
+        cache.root(&store, &state.key_owners, algorithm),
+        merkle::root(&store, &state.key_owners, algorithm)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn restarted_replica_starts_from_its_state_backend() {
+    let dir = state_dir("restart-backend");
+    let mut cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .wal_dir(dir.join("wal"))
+        .state_dir(dir.join("state"))
+        .build();
+    let mut client = cluster.client();
+    for i in 0..12 {
+        assert!(client
+            .put(Key::from(format!("k{}", i)), Value::from("v"))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(12).await);
+    sleep(Duration::from_secs(1)).await;
+
+    // the log was compacted into the checkpoint the backend holds, without the store
+    let (_, records) = Wal::open(&dir.join("wal").join("replica-3.wal")).unwrap();
+    assert!(records
+        .iter()
+        .any(|record| matches!(record, WalRecord::StableCheckpoint(_))));
+    assert!(!records
+        .iter()
+        .any(|record| matches!(record, WalRecord::Snapshot(_))));
+
+    // the database of the replica is released once its task is dropped
+    cluster.kill_node(3);
+    sleep(Duration::from_millis(10)).await;
+    cluster.restart_node(3);
+    sleep(Duration::from_millis(100)).await;
+    let status = cluster.status(3);
+    assert_eq!(status.last_stable_seq_num, 10);
+    assert_eq!(status.last_seq_num_committed, 12);
+
+    assert!(client.put(Key::from("k"), Value::from("v")).await.is_some());
+    assert!(cluster.await_commit(13).await);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,92 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::messages::{ClientRequest, FailureReason, Message, Operation};
+use pbft::sim::{LinkConfig, Network, Simulation};
+use pbft::{Key, Value};
+
+const NUM_NODES: usize = 4;
+const NUM_CLIENTS: u8 = 60;
+
+#[tokio::test(start_paused = true)]
+async fn flooded_replicas_reject_client_requests_as_busy_but_keep_ordering() {
+    let mut config = Config::new(
+        (0..NUM_NODES)
+            .map(|id| (id, SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))))
+            .collect(),
+    );
+    config.consensus_queue_capacity = 8;
+    config.backpressure_high_watermark = 4;
+    config.backpressure_low_watermark = 2;
+    // the engine drains its queue without the paused clock moving, so a request is only
+    // rejected if it finds the queue full
+    config.enqueue_timeout = Duration::ZERO;
+    // a fixed delay, so the writes of the clients all arrive at the same moment
+    let links = LinkConfig {
+        min_delay: Duration::from_millis(5),
+        max_delay: Duration::from_millis(5),
+        ..LinkConfig::default()
+    };
+    let sim = Simulation::start(config, Network::new(3, links));
+
+    let mut rx_clients = Vec::new();
+    for index in 0..NUM_CLIENTS {
+        let respond_addr = SocketAddr::from(([10, 0, 1, index + 1], 7000));
+        rx_clients.push(sim.network.attach_client(respond_addr));
+        let request = Message::ClientRequestMessage(ClientRequest {
+            respond_addr,
+            time_stamp: 1,
+            key: Key::from(format!("k{}", index)),
+            operation: Operation::Set(Value::from("1")),
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        });
+        for id in 0..NUM_NODES {
+            let _ = sim
+                .network
+                .submit(sim.config.peer_addrs[&id], request.clone());
+        }
+    }
+    tokio::time::sleep(Duration::from_secs(30)).await;
+
+    let mut busy = 0;
+    for rx_client in rx_clients.iter_mut() {
+        while let Ok(Message::ClientResponseMessage(response)) = rx_client.try_recv() {
+            if response.reason == Some(FailureReason::Busy) {
+                busy += 1;
+            }
+        }
+    }
+    assert!(busy > 0);
+
+    // the requests which were taken are all ordered, as no vote was dropped
+    let statuses: Vec<_> = sim.nodes.iter().map(|node| node.status()).collect();
+    let total_busy: usize = statuses
+        .iter()
+        .map(|status| status.pipeline.busy_rejections)
+        .sum();
+    assert_eq!(total_busy, busy);
+    for status in statuses.iter() {
+        assert_eq!(status.pipeline.capacity, 8);
+        let dropped_votes = ["PrePrepare", "Prepare", "Commit"]
+            .iter()
+            .filter_map(|kind| status.pipeline.by_kind.get(*kind))
+            .map(|counts| counts.dropped)
+            .sum::<usize>();
+        assert_eq!(dropped_votes, 0);
+        println!(
+            "{:?} committed {}",
+            status.pipeline, status.last_seq_num_committed
+        );
+    }
+    let committed = statuses[0].last_seq_num_committed;
+    assert!(committed > 0);
+    assert!(statuses
+        .iter()
+        .all(|status| status.last_seq_num_committed == committed));
+}
@@ -0,0 +1,134 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use pbft::bulk::BulkIngest;
+use pbft::config::Config;
+use pbft::limits::MAX_BATCH_OPS;
+use pbft::messages::{BatchOp, BulkBatch, BulkLoadPart, BulkLoadSummary, ClientRequest};
+use pbft::state::State;
+use pbft::testing::ClusterBuilder;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+fn client() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7100))
+}
+
+fn puts(from: usize, to: usize) -> Vec<BatchOp> {
+    (from..to)
+        .map(|i| BatchOp::Put {
+            key: Key::from(format!("k{}", i).as_str()),
+            value: Value::from("v"),
+        })
+        .collect()
+}
+
+fn part(time_stamp: usize, offset: usize, len: usize, last: bool) -> BulkLoadPart {
+    BulkLoadPart {
+        respond_addr: client(),
+        time_stamp,
+        offset,
+        ops: puts(offset, offset + len),
+        last,
+    }
+}
+
+fn batches(ingest: &mut BulkIngest) -> Vec<(usize, usize, bool)> {
+    std::iter::from_fn(|| ingest.pop())
+        .map(|request| {
+            let bulk = request.bulk.unwrap();
+            (bulk.offset, request.batch.len(), bulk.last)
+        })
+        .collect()
+}
+
+#[test]
+fn parts_are_reassembled_and_chopped_into_full_batches() {
+    let mut ingest = BulkIngest::new(usize::MAX);
+    // parts arrive out of order, and one twice
+    ingest.take(part(5, 1000, 1000, false));
+    assert!(ingest.is_empty());
+    ingest.take(part(5, 0, 1000, false));
+    ingest.take(part(5, 0, 1000, false));
+    ingest.take(part(5, 2000, 100, true));
+    assert_eq!(
+        batches(&mut ingest),
+        vec![
+            (0, MAX_BATCH_OPS, false),
+            (MAX_BATCH_OPS, MAX_BATCH_OPS, false),
+            (2 * MAX_BATCH_OPS, 2100 - 2 * MAX_BATCH_OPS, true)
+        ]
+    );
+
+    // an empty load is answered too, and parts of an earlier load are dropped
+    ingest.take(part(7, 0, 0, true));
+    ingest.take(part(6, 0, 10, true));
+    assert_eq!(batches(&mut ingest), vec![(0, 0, true)]);
+
+    // batches are cut short of the byte limit
+    let mut ingest = BulkIngest::new(30);
+    ingest.take(part(1, 0, 40, true));
+    let lens: Vec<usize> = batches(&mut ingest).iter().map(|batch| batch.1).collect();
+    assert!(lens.len() > 2);
+    assert_eq!(lens.iter().sum::<usize>(), 40);
+}
+
+#[test]
+fn batches_after_a_gap_are_not_applied() {
+    let builder = MessageBuilder::generate(0);
+    let mut state = State::new(1, Config::new((0..4).map(|id| (id, client())).collect()));
+    let num_keys = state.store.len();
+    let mut seq_num = 0;
+    let mut apply = |state: &mut State, offset: usize, len: usize, last: bool| {
+        seq_num += 1;
+        let request = ClientRequest {
+            bulk: Some(BulkBatch { offset, last }),
+            ..ClientRequest {
+                respond_addr: client(),
+                time_stamp: 3,
+                batch: puts(offset, offset + len),
+                ..ClientRequest::no_op()
+            }
+        };
+        let commit = builder
+            .clone()
+            .seq_num(seq_num)
+            .client_request(request.clone())
+            .commit();
+        state.apply_commit(Arc::new(request), &commit).0
+    };
+
+    assert_eq!(apply(&mut state, 0, 10, false).bulk, None);
+    // the batch at offset 10 was lost, so the load stops short of it
+    assert_eq!(apply(&mut state, 20, 10, false).bulk, None);
+    let result = apply(&mut state, 30, 5, true);
+    assert_eq!(result.reason, None);
+    assert_eq!(
+        result.bulk,
+        Some(BulkLoadSummary {
+            ops: 10,
+            batches: 1,
+            first_seq_num: 1,
+        })
+    );
+    assert_eq!(state.store.len(), num_keys + 10);
+    assert!(state.bulk_loads.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn bulk_loads_are_answered_once_with_a_summary() {
+    let cluster = ClusterBuilder::new(4).seed(5).build();
+    let mut client = cluster.client();
+    let ops = puts(0, 3000);
+    let response = client.bulk_load(ops, 700).await.unwrap();
+    assert_eq!(response.reason, None);
+    let summary = response.bulk.unwrap();
+    assert_eq!((summary.ops, summary.batches), (3000, 3));
+    assert_eq!(response.seq_num, summary.first_seq_num + 2);
+
+    let response = client.get(Key::from("k2999")).await.unwrap();
+    assert_eq!(response.value, Some(Value::from("v")));
+    for id in 0..4 {
+        assert!(cluster.status(id).last_seq_num_committed >= 3);
+    }
+}
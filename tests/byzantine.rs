@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pbft::byzantine::{CorruptDigests, DropCommits, EquivocatePrePrepares, Fault, StaleView};
+use pbft::config::Config;
+use pbft::consensus::Consensus;
+use pbft::messages::{ClientRequest, ClientResponse, Message, Operation};
+use pbft::node::Node;
+use pbft::observer::Observer;
+use pbft::state::SlotMeta;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, NodeId, Value};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::channel;
+
+const NUM_NODES: usize = 4;
+const NUM_REQUESTS: usize = 6;
+
+/// Requests committed by a replica, by sequence number, and the last view it entered
+#[derive(Default)]
+struct Recorder {
+    committed: Mutex<BTreeMap<usize, (usize, Operation)>>,
+    view: Mutex<usize>,
+}
+
+impl Observer for Recorder {
+    fn on_commit(&self, slot: &SlotMeta, request: &ClientRequest, _response: &ClientResponse) {
+        self.committed.lock().unwrap().insert(
+            slot.seq_num,
+            (request.time_stamp, request.operation.clone()),
+        );
+    }
+
+    fn on_view_change(&self, view: usize) {
+        *self.view.lock().unwrap() = view;
+    }
+}
+
+impl Recorder {
+    fn has_committed(&self, time_stamp: usize) -> bool {
+        self.committed
+            .lock()
+            .unwrap()
+            .values()
+            .any(|(committed, _)| *committed == time_stamp)
+    }
+}
+
+/// A cluster of 3f + 1 replicas running in this process on consecutive ports,
+/// one of which is Byzantine
+struct Cluster {
+    base_port: u16,
+    faulty: NodeId,
+    recorders: Vec<Arc<Recorder>>,
+}
+
+impl Cluster {
+    async fn start(base_port: u16, faulty: NodeId, faults: Vec<Arc<dyn Fault>>) -> Self {
+        let builders: Vec<MessageBuilder> = (0..NUM_NODES).map(MessageBuilder::generate).collect();
+        let mut config = Config::new(
+            (0..NUM_NODES)
+                .map(|id| (id, Self::addr(base_port, id)))
+                .collect(),
+        );
+        config.peer_pub_keys = builders
+            .iter()
+            .enumerate()
+            .map(|(id, builder)| (id, builder.public_key()))
+            .collect();
+        config.request_timeout = Duration::from_secs(1);
+
+        let mut recorders = Vec::new();
+        for (id, builder) in builders.iter().enumerate() {
+            let (tx_consensus, rx_consensus) = channel(32);
+            let (tx_node, rx_node) = channel(32);
+            let mut node = Node::new(
+                id,
+                config.clone(),
+                builder.keystore(),
+                rx_node,
+                tx_consensus.clone(),
+                tx_node.clone(),
+            );
+            let mut consensus = Consensus::new(
+                id,
+                config.clone(),
+                builder.keystore(),
+                rx_consensus,
+                tx_consensus,
+                tx_node,
+                node.inner.peer_pub_keys.clone(),
+            );
+            node.inner.rx_backpressure = consensus.subscribe_backpressure();
+            node.inner.rx_stable_seq_num = consensus.subscribe_stable_seq_num();
+            node.inner.rx_status = consensus.subscribe_status();
+            node.inner.observers = consensus.observers();
+            node.inner.pipeline = consensus.pipeline();
+
+            let recorder = Arc::new(Recorder::default());
+            consensus.register_observer(recorder.clone());
+            recorders.push(recorder);
+            if id == faulty {
+                for fault in faults.iter() {
+                    node.inject_fault(fault.clone());
+                }
+            }
+            tokio::spawn(async move { node.spawn().await });
+            tokio::spawn(async move { consensus.spawn().await });
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        Self {
+            base_port,
+            faulty,
+            recorders,
+        }
+    }
+
+    fn addr(base_port: u16, id: NodeId) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], base_port + id as u16))
+    }
+
+    /// Submits the requests to every replica, as clients do, from a client which
+    /// discards the responses
+    async fn submit(&self, num_requests: usize) {
+        let respond_addr = Self::addr(self.base_port, NUM_NODES);
+        let listener = TcpListener::bind(respond_addr).await.unwrap();
+        tokio::spawn(async move { while listener.accept().await.is_ok() {} });
+
+        for time_stamp in 1..=num_requests {
+            let request = Message::ClientRequestMessage(ClientRequest {
+                respond_addr,
+                time_stamp,
+                key: Key::from(format!("k{}", time_stamp).as_str()),
+                operation: Operation::Set(Value::from(time_stamp.to_string())),
+                relay_id: None,
+                read_only: false,
+                batch: Vec::new(),
+                bulk: None,
+                client_id: None,
+                signature: Vec::new(),
+            });
+            for id in 0..NUM_NODES {
+                let mut stream = TcpStream::connect(Self::addr(self.base_port, id))
+                    .await
+                    .unwrap();
+                stream
+                    .write_all(request.serialize().as_slice())
+                    .await
+                    .unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn honest(&self) -> impl Iterator<Item = &Arc<Recorder>> {
+        self.recorders
+            .iter()
+            .enumerate()
+            .filter(move |(id, _)| *id != self.faulty)
+            .map(|(_, recorder)| recorder)
+    }
+
+    /// Liveness: every honest replica commits every request
+    async fn all_committed(&self, num_requests: usize) -> bool {
+        for _ in 0..300 {
+            if self
+                .honest()
+                .all(|recorder| (1..=num_requests).all(|ts| recorder.has_committed(ts)))
+            {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    /// Safety: the honest replicas committed the same request at every sequence number
+    /// they both committed
+    fn assert_agreement(&self) {
+        let logs: Vec<_> = self
+            .honest()
+            .map(|recorder| recorder.committed.lock().unwrap().clone())
+            .collect();
+        for log in logs.iter() {
+            for (seq_num, request) in log.iter() {
+                if let Some(other_request) = logs[0].get(seq_num) {
+                    assert_eq!(request, other_request, "disagreement at {}", seq_num);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn faulty_backup_does_not_stop_the_cluster() {
+    let faults: Vec<Arc<dyn Fault>> = vec![Arc::new(CorruptDigests), Arc::new(DropCommits)];
+    let cluster = Cluster::start(7960, 3, faults).await;
+    cluster.submit(NUM_REQUESTS).await;
+
+    assert!(cluster.all_committed(NUM_REQUESTS).await);
+    cluster.assert_agreement();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn equivocating_primary_is_replaced() {
+    let faults: Vec<Arc<dyn Fault>> = vec![Arc::new(EquivocatePrePrepares), Arc::new(StaleView)];
+    let cluster = Cluster::start(7970, 0, faults).await;
+    cluster.submit(NUM_REQUESTS).await;
+
+    assert!(cluster.all_committed(NUM_REQUESTS).await);
+    cluster.assert_agreement();
+    assert!(cluster
+        .honest()
+        .all(|recorder| *recorder.view.lock().unwrap() > 0));
+}
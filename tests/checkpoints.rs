@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use pbft::config::Config;
+use pbft::messages::{BatchOp, ClientRequest};
+use pbft::state::State;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, Value};
+
+fn request(time_stamp: usize, batch: Vec<BatchOp>) -> ClientRequest {
+    RequestBuilder::new()
+        .time_stamp(time_stamp)
+        .key(Key::from(format!("k{:02}", time_stamp).as_str()))
+        .set(&format!("{:04}", time_stamp))
+        .batch(batch)
+        .build()
+}
+
+/// Sequence numbers among the first 20 after which each replica checkpoints
+fn boundaries(config: &Config, batch_size: impl Fn(usize) -> usize) -> Vec<usize> {
+    let mut state = State::new(1, config.clone());
+    let primary = MessageBuilder::generate(0).view(0);
+    let mut boundaries = Vec::new();
+    for seq_num in 1..=20 {
+        let batch = (0..batch_size(seq_num))
+            .map(|op| BatchOp::Put {
+                key: Key::from(format!("b{}-{}", seq_num, op).as_str()),
+                value: Value::from("1"),
+            })
+            .collect();
+        let request = request(seq_num, batch);
+        let commit = primary
+            .clone()
+            .seq_num(seq_num)
+            .client_request(request.clone())
+            .commit();
+        let _ = state.apply_commit(Arc::new(request), &commit);
+        if state.is_at_checkpoint_boundary() {
+            boundaries.push(seq_num);
+        }
+    }
+    boundaries
+}
+
+#[test]
+fn checkpoints_follow_the_growth_of_the_log() {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.checkpoint_frequency = 10;
+    assert_eq!(boundaries(&config, |_| 0), vec![10, 20]);
+
+    // large batches reach the entry threshold before the frequency
+    config.checkpoint_entries = 8;
+    let batch_size = |seq_num: usize| if seq_num <= 6 { 4 } else { 0 };
+    assert_eq!(boundaries(&config, batch_size), vec![2, 4, 6, 10, 18, 20]);
+
+    // keys of 3 bytes and values of 4 bytes
+    config.checkpoint_entries = 0;
+    config.checkpoint_bytes = 21;
+    assert_eq!(
+        boundaries(&config, |_| 0),
+        vec![3, 6, 9, 10, 13, 16, 19, 20]
+    );
+}
@@ -0,0 +1,332 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ed25519_dalek::PublicKey;
+
+use pbft::client::{ClientError, PbftClient};
+use pbft::codec::MessageReader;
+use pbft::config::Config;
+use pbft::messages::{
+    ClientRequest, ClientResponse, FailureReason, Message, NodeStatus, RequestStatus,
+    RequestStatusReport,
+};
+use pbft::node::Node;
+use pbft::testkit::MessageBuilder;
+use pbft::versions::KeyVersions;
+use pbft::{Key, NodeId, Value};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::channel;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7960 + id as u16))
+}
+
+/// Stands in for a replica of a cluster in view 5, which orders every request it receives
+/// with replicas 1 to 3, which all answer with the value "v". Replica 0 ignores requests.
+/// Returns the timestamps of the requests the replica received
+async fn replica(id: NodeId) -> Arc<Mutex<Vec<usize>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let listener = TcpListener::bind(addr_of(id)).await.unwrap();
+    let requests = received.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let request = match MessageReader::new(stream).read().await {
+                Ok(Some(Message::ClientRequestMessage(request))) => request,
+                _ => continue,
+            };
+            requests.lock().unwrap().push(request.time_stamp);
+            if id == 0 {
+                continue;
+            }
+            for responder in 1..4 {
+                let response = ClientResponse::new_with_signature(
+                    MessageBuilder::generate(responder).keystore(),
+                    responder,
+                    request.time_stamp,
+                    request.key.clone(),
+                    Some(Value::from("v")),
+                    Vec::new(),
+                    None,
+                )
+                .with_view(5);
+                let mut client = TcpStream::connect(request.respond_addr).await.unwrap();
+                let message = Message::ClientResponseMessage(response);
+                client
+                    .write_all(message.serialize().as_slice())
+                    .await
+                    .unwrap();
+            }
+        }
+    });
+    received
+}
+
+#[tokio::test]
+async fn requests_are_retransmitted_and_follow_the_primary() {
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let mut received = Vec::new();
+    for id in 0..4 {
+        received.push(replica(id).await);
+    }
+    let mut client = PbftClient::new(&config, SocketAddr::from(([127, 0, 0, 1], 7970)));
+    client.request_timeout = Duration::from_millis(300);
+    let runner = client.clone();
+    tokio::spawn(async move { runner.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the request first goes to the primary of view 0 only, and to every replica once it times out
+    client.set(Key::from("x"), Value::from("v")).await.unwrap();
+    assert_eq!(received[0].lock().unwrap().len(), 2);
+    assert_eq!(received[1].lock().unwrap().len(), 1);
+
+    // the replicas reported view 5, whose primary is node 1
+    client.set(Key::from("x"), Value::from("v")).await.unwrap();
+    assert_eq!(received[0].lock().unwrap().len(), 2);
+    assert_eq!(received[1].lock().unwrap().len(), 2);
+    assert_eq!(received[2].lock().unwrap().len(), 1);
+}
+
+fn reconciled_addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7900 + id as u16))
+}
+
+async fn send_to(addr: SocketAddr, message: Message) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(message.serialize().as_slice())
+        .await
+        .unwrap();
+}
+
+/// Stands in for a replica which answers no request, and reports the status of the requests
+/// asked for as if it executed the second oldest, answering it again. Returns the requests the replica received
+async fn reconciled_replica(id: NodeId, builder: MessageBuilder) -> Arc<Mutex<Vec<ClientRequest>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let listener = TcpListener::bind(reconciled_addr_of(id)).await.unwrap();
+    let requests = received.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let get_status = match MessageReader::new(stream).read().await {
+                Ok(Some(Message::ClientRequestMessage(request))) => {
+                    requests.lock().unwrap().push(request);
+                    continue;
+                }
+                Ok(Some(Message::GetRequestStatusMessage(get_status))) => get_status,
+                _ => continue,
+            };
+            let executed = get_status.time_stamps[1];
+            // the second oldest request, for key "b", is the last one we executed
+            let response = ClientResponse::new_with_signature(
+                builder.keystore(),
+                id,
+                executed,
+                Key::from("b"),
+                None,
+                Vec::new(),
+                None,
+            );
+            send_to(
+                get_status.respond_addr,
+                Message::ClientResponseMessage(response),
+            )
+            .await;
+            let statuses = get_status
+                .time_stamps
+                .iter()
+                .map(|time_stamp| {
+                    let status = match time_stamp.cmp(&executed) {
+                        std::cmp::Ordering::Less => RequestStatus::Superseded,
+                        std::cmp::Ordering::Equal => RequestStatus::Executed,
+                        std::cmp::Ordering::Greater => RequestStatus::Unknown,
+                    };
+                    (*time_stamp, status)
+                })
+                .collect();
+            let report = RequestStatusReport::new_with_signature(
+                builder.keystore(),
+                id,
+                get_status.respond_addr,
+                statuses,
+            );
+            send_to(
+                get_status.respond_addr,
+                Message::RequestStatusMessage(report),
+            )
+            .await;
+        }
+    });
+    received
+}
+
+#[tokio::test]
+async fn requests_in_flight_are_reconciled_with_the_reply_caches() {
+    let config = Config::new((0..4).map(|id| (id, reconciled_addr_of(id))).collect());
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let mut received = Vec::new();
+    for (id, builder) in builders.iter().enumerate() {
+        received.push(reconciled_replica(id, builder.clone()).await);
+    }
+    let pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    let mut client =
+        PbftClient::new(&config, SocketAddr::from(([127, 0, 0, 1], 7910))).with_pub_keys(pub_keys);
+    client.request_timeout = Duration::from_secs(10);
+    let runner = client.clone();
+    tokio::spawn(async move { runner.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut pending = Vec::new();
+    for key in ["a", "b", "c"] {
+        let client = client.clone();
+        pending.push(tokio::spawn(async move {
+            client.set(Key::from(key), Value::from("v")).await
+        }));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let outstanding = client.outstanding();
+    assert_eq!(outstanding.len(), 3);
+
+    let statuses = client.reconcile().await;
+    assert_eq!(
+        statuses.into_values().collect::<Vec<_>>(),
+        [
+            RequestStatus::Superseded,
+            RequestStatus::Executed,
+            RequestStatus::Unknown
+        ]
+    );
+    let mut results = Vec::new();
+    for pending in pending.drain(..2) {
+        results.push(pending.await.unwrap());
+    }
+    assert!(matches!(
+        results[0],
+        Err(ClientError::Rejected {
+            reason: FailureReason::StaleTimestamp,
+            ..
+        })
+    ));
+    assert!(results[1].is_ok());
+
+    // only the request no replica executed was sent again, to every replica
+    assert_eq!(client.outstanding(), [outstanding[2]]);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let time_stamps = |id: NodeId| -> Vec<usize> {
+        received[id]
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.time_stamp)
+            .collect()
+    };
+    assert_eq!(
+        time_stamps(0),
+        [
+            outstanding[0],
+            outstanding[1],
+            outstanding[2],
+            outstanding[2]
+        ]
+    );
+    for id in 1..4 {
+        assert_eq!(time_stamps(id), [outstanding[2]]);
+    }
+}
+
+fn watched_addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7920 + id as u16))
+}
+
+/// Starts a node of a cluster at the `watched_addr_of` addresses, whose progress and key
+/// versions are reported by the consensus engine we stand in for
+async fn watched_node(id: NodeId) -> (watch::Sender<NodeStatus>, KeyVersions, PublicKey) {
+    let builder = MessageBuilder::generate(id);
+    let config = Config::new((0..4).map(|id| (id, watched_addr_of(id))).collect());
+    let (tx_consensus, rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        id,
+        config,
+        builder.keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    let (tx_status, rx_status) = watch::channel(NodeStatus::default());
+    node.inner.rx_status = rx_status;
+    let key_versions = node.inner.key_versions.clone();
+    tokio::spawn(async move {
+        let _rx_consensus = rx_consensus;
+        node.spawn().await
+    });
+    (tx_status, key_versions, builder.public_key())
+}
+
+#[tokio::test]
+async fn waits_until_f_plus_one_replicas_reach_the_point() {
+    let mut nodes = Vec::new();
+    for id in 0..4 {
+        nodes.push(watched_node(id).await);
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let config = Config::new((0..4).map(|id| (id, watched_addr_of(id))).collect());
+    let pub_keys = nodes
+        .iter()
+        .enumerate()
+        .map(|(id, node)| (id, node.2))
+        .collect();
+    let client =
+        PbftClient::new(&config, SocketAddr::from(([127, 0, 0, 1], 7930))).with_pub_keys(pub_keys);
+
+    let waiter = client.clone();
+    let seq_waiter = tokio::spawn(async move { waiter.wait_for_seq(5).await });
+    let waiter = client.clone();
+    let version_waiter =
+        tokio::spawn(async move { waiter.wait_for_key_version(Key::from("x"), 4).await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // a single replica may be faulty, so its progress is not enough
+    let commit = |node: &(watch::Sender<NodeStatus>, KeyVersions, PublicKey), seq_num: usize| {
+        node.0
+            .send_modify(|status| status.last_seq_num_committed = seq_num)
+    };
+    nodes[1].1.record([&Key::from("x")], 4);
+    commit(&nodes[1], 5);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!seq_waiter.is_finished());
+    assert!(!version_waiter.is_finished());
+
+    // another replica reached sequence number 5, but wrote x last at 2
+    nodes[3].1.record([&Key::from("x")], 2);
+    commit(&nodes[3], 5);
+    let certificate = timeout(Duration::from_secs(2), seq_waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut reached: Vec<NodeId> = certificate.reports.iter().map(|report| report.id).collect();
+    reached.sort_unstable();
+    assert_eq!(reached, vec![1, 3]);
+    assert!(!version_waiter.is_finished());
+
+    nodes[2].1.record([&Key::from("x")], 4);
+    commit(&nodes[2], 4);
+    let certificate = timeout(Duration::from_secs(2), version_waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(certificate.reports.len(), 2);
+    assert!(certificate
+        .reports
+        .iter()
+        .all(|report| report.key == Some(Key::from("x")) && report.version >= 4));
+}
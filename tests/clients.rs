@@ -0,0 +1,125 @@
+use std::net::SocketAddr;
+
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+use pbft::messages::{ClientRequest, FailureReason, Operation};
+use pbft::testing::ClusterBuilder;
+use pbft::{Key, Value};
+
+#[test]
+fn client_signatures_hold_wherever_the_request_is_sent_from() {
+    let keypair = Keypair::generate(&mut OsRng);
+    let request = ClientRequest {
+        respond_addr: SocketAddr::from(([10, 0, 0, 1], 7000)),
+        time_stamp: 3,
+        key: Key::from("k"),
+        operation: Operation::Set(Value::from("v")),
+        relay_id: None,
+        read_only: false,
+        batch: Vec::new(),
+        bulk: None,
+        client_id: None,
+        signature: Vec::new(),
+    }
+    .signed_by(7, &keypair);
+    assert!(request.is_properly_signed_by(&keypair.public));
+
+    // behind a NAT and through a relay
+    let mut moved = request.clone();
+    moved.respond_addr = SocketAddr::from(([192, 168, 0, 9], 41000));
+    moved.relay_id = Some(2);
+    assert!(moved.is_properly_signed_by(&keypair.public));
+
+    let mut tampered = request.clone();
+    tampered.operation = Operation::Set(Value::from("w"));
+    assert!(!tampered.is_properly_signed_by(&keypair.public));
+    let mut other_client = request.clone();
+    other_client.client_id = Some(8);
+    assert!(!other_client.is_properly_signed_by(&keypair.public));
+
+    // replicas agree on whether the request is signed, as the digest covers the signature
+    let mut unsigned = request.clone();
+    unsigned.signature = Vec::new();
+    assert_ne!(unsigned.digest(), request.digest());
+}
+
+#[tokio::test(start_paused = true)]
+async fn registered_clients_are_known_by_their_id() {
+    let cluster = ClusterBuilder::new(4).seed(3).build();
+    let keypair = Keypair::generate(&mut OsRng);
+    let mut client = cluster
+        .client()
+        .with_identity(7, Keypair::from_bytes(&keypair.to_bytes()).unwrap());
+    assert_eq!(client.register().await.unwrap().reason, None);
+    let response = client.put(Key::from("k"), Value::from("1")).await.unwrap();
+    assert_eq!(response.reason, None);
+
+    // the same client from another address continues from the timestamps it used
+    let mut moved = cluster
+        .client()
+        .with_identity(7, Keypair::from_bytes(&keypair.to_bytes()).unwrap());
+    let response = moved.put(Key::from("k"), Value::from("2")).await.unwrap();
+    assert_eq!(response.reason, Some(FailureReason::StaleTimestamp));
+
+    // the id is taken, and requests signed by another key are not the client's
+    let mut impostor = cluster
+        .client()
+        .with_identity(7, Keypair::generate(&mut OsRng));
+    let response = impostor
+        .put(Key::from("k"), Value::from("3"))
+        .await
+        .unwrap();
+    assert_eq!(response.reason, Some(FailureReason::Unauthenticated));
+    assert_eq!(
+        impostor.register().await.unwrap().reason,
+        Some(FailureReason::Unauthenticated)
+    );
+    let mut unregistered = cluster
+        .client()
+        .with_identity(8, Keypair::generate(&mut OsRng));
+    let response = unregistered.get(Key::from("k")).await.unwrap();
+    assert_eq!(response.reason, Some(FailureReason::Unauthenticated));
+
+    // the client carries on where it left off
+    let response = client.put(Key::from("k"), Value::from("4")).await.unwrap();
+    assert_eq!(response.reason, None);
+    let response = cluster.client().get(Key::from("k")).await.unwrap();
+    assert_eq!(response.value, Some(Value::from("4")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn quotas_are_charged_to_the_id_of_signed_clients() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(4)
+        .config(|config| config.max_client_keys = 1)
+        .build();
+    let keypair = Keypair::generate(&mut OsRng);
+    let mut client = cluster
+        .client()
+        .with_identity(7, Keypair::from_bytes(&keypair.to_bytes()).unwrap());
+    assert_eq!(client.register().await.unwrap().reason, None);
+    let response = client.put(Key::from("a"), Value::from("1")).await.unwrap();
+    assert_eq!(response.reason, None);
+
+    // moving to another address does not give the client a fresh quota
+    let mut moved = cluster
+        .client()
+        .with_identity(7, Keypair::from_bytes(&keypair.to_bytes()).unwrap());
+    let response = moved.put(Key::from("b"), Value::from("2")).await.unwrap();
+    assert_eq!(response.reason, Some(FailureReason::StaleTimestamp));
+    // the timestamp of the last write is answered from the reply cache
+    assert!(moved.put(Key::from("b"), Value::from("2")).await.is_some());
+    let response = moved.put(Key::from("b"), Value::from("2")).await.unwrap();
+    assert_eq!(response.reason, Some(FailureReason::ClientKeyQuotaExceeded));
+    let response = moved.put(Key::from("a"), Value::from("3")).await.unwrap();
+    assert_eq!(response.reason, None);
+
+    // while a client known only by its address has a quota of its own
+    let response = cluster
+        .client()
+        .put(Key::from("b"), Value::from("2"))
+        .await
+        .unwrap();
+    assert_eq!(response.reason, None);
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use pbft::testing::ClusterBuilder;
+use pbft::time::ClockSkew;
+use pbft::{Key, Value};
+
+#[test]
+fn offsets_are_estimated_from_readings_echoed_both_ways() {
+    // the clock of b is 5 s ahead of the clock of a, and readings take 10 ms either way
+    let a = ClockSkew::new(Duration::from_secs(1));
+    let b = ClockSkew::new(Duration::from_secs(1));
+    let first = a.sample_for(1, 1_000);
+    assert_eq!(first.echo, None);
+    b.observe(0, &first, 6_010);
+    assert!(b.stats().peers.is_empty());
+
+    // b holds the reading for 40 ms before it answers, which is not counted as delay
+    let answer = b.sample_for(0, 6_050);
+    assert!(answer.echo.is_some());
+    a.observe(1, &answer, 1_060);
+    let stats = a.stats();
+    assert_eq!(stats.peers[&1].offset_millis, 5_000);
+    assert_eq!(stats.peers[&1].round_trip_millis, 20);
+    assert_eq!(stats.skewed_peers, vec![1]);
+    assert_eq!(stats.median_offset_millis, Some(5_000));
+    assert_eq!(stats.max_skew_millis(), 5_000);
+
+    // a reading is echoed once
+    assert_eq!(b.sample_for(0, 6_100).echo, None);
+    let reply = a.sample_for(1, 1_100);
+    b.observe(0, &reply, 6_110);
+    assert_eq!(b.stats().peers[&0].offset_millis, -5_000);
+
+    // clocks back in line clear the alert
+    let next = a.sample_for(1, 2_000);
+    b.observe(0, &next, 2_010);
+    a.observe(1, &b.sample_for(0, 2_020), 2_030);
+    let stats = a.stats();
+    assert_eq!(stats.peers[&1].offset_millis, 0);
+    assert!(stats.skewed_peers.is_empty());
+
+    // no alerts without a threshold
+    let quiet = ClockSkew::new(Duration::ZERO);
+    quiet.observe(1, &b.sample_for(2, 9_000), 0);
+    let answer = b.sample_for(2, 9_000);
+    quiet.observe(1, &answer, 0);
+    assert!(quiet.stats().skewed_peers.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn replicas_report_the_peer_whose_clock_is_off() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(2)
+        .config(|config| config.identity_broadcast_interval = Duration::from_secs(1))
+        .build();
+    cluster.node(3).clock.set_offset_millis(5_000);
+    let mut client = cluster.client();
+    assert!(client.put(Key::from("k"), Value::from("1")).await.is_some());
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    for id in 0..3 {
+        let clock = cluster.status(id).clock;
+        assert_eq!(clock.skewed_peers, vec![3]);
+        assert!(clock.peers[&3].offset_millis.abs_diff(5_000) < 100);
+        assert!(clock.median_offset_millis.unwrap().abs() < 100);
+    }
+    // the replica whose clock is off sees every peer off the other way, and so its own clock
+    let clock = cluster.status(3).clock;
+    assert_eq!(clock.skewed_peers, vec![0, 1, 2]);
+    assert!(clock.median_offset_millis.unwrap().abs_diff(-5_000) < 100);
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use pbft::testing::ClusterBuilder;
+use pbft::{Key, Value};
+
+#[tokio::test(start_paused = true)]
+async fn cluster_commits_without_a_killed_backup() {
+    let cluster = ClusterBuilder::new(4).seed(3).build();
+    let mut client = cluster.client();
+    for i in 1..=3 {
+        let response = client
+            .put(Key::from("k"), Value::from(i.to_string()))
+            .await
+            .unwrap();
+        assert!(response.success);
+    }
+    assert!(cluster.await_commit(3).await);
+
+    cluster.kill_node(3);
+    let committed = cluster.status(3).last_seq_num_committed;
+    for i in 4..=5 {
+        assert!(client
+            .put(Key::from("k"), Value::from(i.to_string()))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(5).await);
+    assert_eq!(cluster.status(3).last_seq_num_committed, committed);
+    let response = cluster.client().get(Key::from("k")).await.unwrap();
+    assert_eq!(response.value, Some(Value::from("5")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn primary_cut_off_from_two_backups_is_replaced() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(5)
+        .timeout(Duration::from_secs(30))
+        .config(|config| config.request_timeout = Duration::from_secs(1))
+        .build();
+    cluster.partition(0, 1);
+    cluster.partition(0, 2);
+
+    let mut client = cluster.client();
+    client.timeout = Duration::from_secs(30);
+    let response = client.put(Key::from("k"), Value::from("v")).await.unwrap();
+    assert!(response.view >= 1);
+    assert!(cluster.await_view(1).await);
+    assert!(cluster.await_commit(1).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn cluster_without_a_quorum_commits_nothing() {
+    let stalled = ClusterBuilder::new(4)
+        .timeout(Duration::from_secs(5))
+        .build();
+    stalled.kill_node(1);
+    stalled.kill_node(2);
+    let mut client = stalled.client();
+    client.timeout = Duration::from_secs(2);
+    assert!(client.put(Key::from("k"), Value::from("v")).await.is_none());
+    assert!(!stalled.await_commit(1).await);
+}
@@ -0,0 +1,90 @@
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+use pbft::codec::{CodecError, MessageCodec, MessageReader, HEADER_LEN, MAX_FRAME_LEN};
+use pbft::messages::Message;
+use pbft::testkit::MessageBuilder;
+
+fn seq_num(message: &Message) -> usize {
+    match message {
+        Message::PrepareMessage(prepare) => prepare.seq_num,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn frames_are_decoded_once_complete() {
+    let builder = MessageBuilder::generate(1);
+    let frame = Message::PrepareMessage(builder.clone().seq_num(7).prepare()).serialize();
+    assert_eq!(
+        u32::from_be_bytes(frame[..HEADER_LEN].try_into().unwrap()) as usize,
+        frame.len() - HEADER_LEN
+    );
+
+    let mut codec = MessageCodec::default();
+    let mut buf = BytesMut::from(&frame[..frame.len() - 1]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(&frame[frame.len() - 1..]);
+    assert_eq!(seq_num(&codec.decode(&mut buf).unwrap().unwrap()), 7);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn malformed_and_oversized_frames_are_rejected() {
+    let builder = MessageBuilder::generate(1);
+    let mut codec = MessageCodec::default();
+
+    // a malformed payload is skipped, and the message after it is still read
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&3u32.to_be_bytes());
+    buf.extend_from_slice(b"{\n}");
+    buf.extend_from_slice(
+        &Message::PrepareMessage(builder.clone().seq_num(2).prepare()).serialize(),
+    );
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Err(CodecError::Malformed(_))
+    ));
+    assert_eq!(seq_num(&codec.decode(&mut buf).unwrap().unwrap()), 2);
+
+    // the error locates the byte at which decoding failed
+    let payload = b"{\"PrepareMessage\":\n{\"id\":\"one\"}}";
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+    let frame = match codec.decode(&mut buf) {
+        Err(CodecError::Malformed(frame)) => frame,
+        res => panic!("decoded {:?}", res),
+    };
+    // the end of the string "one", where a sender id was expected
+    assert_eq!(frame.offset, payload.len() - 3);
+    assert_eq!(frame.len, payload.len());
+    assert!(frame.reason.contains("expected usize"), "{}", frame.reason);
+
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+    assert!(matches!(codec.decode(&mut buf), Err(CodecError::Io(_))));
+}
+
+#[tokio::test]
+async fn reader_reads_consecutive_messages() {
+    let builder = MessageBuilder::generate(1);
+    let mut stream = Vec::new();
+    for seq_num in 1..=3 {
+        stream.extend(
+            Message::PrepareMessage(builder.clone().seq_num(seq_num).prepare()).serialize(),
+        );
+    }
+
+    let mut reader = MessageReader::new(stream.as_slice());
+    for expected in 1..=3 {
+        assert_eq!(seq_num(&reader.read().await.unwrap().unwrap()), expected);
+    }
+    assert!(reader.read().await.unwrap().is_none());
+
+    // a connection closed in the middle of a frame is an error
+    let mut reader = MessageReader::new(&stream[..stream.len() - 1]);
+    reader.read().await.unwrap();
+    reader.read().await.unwrap();
+    assert!(reader.read().await.is_err());
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use pbft::config::{ConfigError, ConfigFile};
+use pbft::keys::encode_hex;
+use pbft::testkit::MessageBuilder;
+
+#[test]
+fn config_files_override_the_defaults() {
+    let pub_key = MessageBuilder::generate(1).public_key();
+    let file: ConfigFile = serde_json::from_str(&format!(
+        r#"{{
+            "nodes": [
+                {{ "id": 0, "addr": "127.0.0.1:7000" }},
+                {{ "id": 1, "addr": "127.0.0.1:7001", "pub_key": "{}" }},
+                {{ "id": 2, "addr": "127.0.0.1:7002" }},
+                {{ "id": 3, "addr": "127.0.0.1:7003" }},
+                {{ "id": 4, "addr": "127.0.0.1:7004" }}
+            ],
+            "checkpoint_frequency": 5,
+            "checkpoint_bytes": 4096,
+            "initial_view": 6,
+            "timeouts": {{ "request_ms": 1500, "view_change_max_ms": 20000 }}
+        }}"#,
+        encode_hex(pub_key.as_bytes())
+    ))
+    .unwrap();
+    let config = file.into_config().unwrap();
+
+    assert_eq!(config.num_nodes, 5);
+    assert_eq!(config.num_faulty, 1);
+    assert_eq!(config.peer_addrs[&4].port(), 7004);
+    assert_eq!(config.peer_pub_keys.len(), 1);
+    assert_eq!(config.peer_pub_keys[&1], pub_key);
+    assert_eq!(config.checkpoint_frequency, 5);
+    assert_eq!(config.checkpoint_bytes, 4096);
+    assert_eq!(config.checkpoint_entries, 0);
+    assert_eq!(config.initial_view, 6);
+    assert_eq!(config.request_timeout, Duration::from_millis(1500));
+    assert_eq!(config.rebroadcast_timeout, Duration::from_secs(8));
+    assert_eq!(config.view_change_timeout_max, Duration::from_secs(20));
+}
+
+#[test]
+fn inconsistent_config_files_are_rejected() {
+    let nodes = r#"[
+        { "id": 0, "addr": "127.0.0.1:7000" },
+        { "id": 1, "addr": "127.0.0.1:7001" },
+        { "id": 2, "addr": "127.0.0.1:7002" },
+        { "id": 3, "addr": "127.0.0.1:7003" }
+    ]"#;
+    let load = |extra: &str| {
+        serde_json::from_str::<ConfigFile>(&format!(r#"{{ "nodes": {}{} }}"#, nodes, extra))
+            .unwrap()
+            .into_config()
+    };
+    assert!(load("").is_ok());
+    // 4 nodes tolerate a single faulty node
+    assert!(matches!(
+        load(r#", "num_faulty": 2"#),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        load(r#", "checkpoint_frequency": 0"#),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        load(r#", "timeouts": { "view_change_min_ms": 5000, "view_change_max_ms": 4000 }"#),
+        Err(ConfigError::Invalid(_))
+    ));
+
+    // ids must be 0 to n - 1, each listed once
+    let gap = r#"{ "nodes": [
+        { "id": 0, "addr": "127.0.0.1:7000" },
+        { "id": 2, "addr": "127.0.0.1:7002" }
+    ] }"#;
+    let duplicate = r#"{ "nodes": [
+        { "id": 0, "addr": "127.0.0.1:7000" },
+        { "id": 0, "addr": "127.0.0.1:7001" }
+    ] }"#;
+    let bad_key = r#"{ "nodes": [ { "id": 0, "addr": "127.0.0.1:7000", "pub_key": "00" } ] }"#;
+    for contents in [gap, duplicate, bad_key] {
+        let file: ConfigFile = serde_json::from_str(contents).unwrap();
+        assert!(matches!(file.into_config(), Err(ConfigError::Invalid(_))));
+    }
+
+    // misspelled settings are not silently ignored
+    assert!(serde_json::from_str::<ConfigFile>(&format!(
+        r#"{{ "nodes": {}, "checkpoint_freq": 5 }}"#,
+        nodes
+    ))
+    .is_err());
+}
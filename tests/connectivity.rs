@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use pbft::connectivity::Connectivity;
+use pbft::messages::{Ping, Pong};
+use pbft::sim::LinkModel;
+use pbft::testing::ClusterBuilder;
+
+use tokio::time::{advance, sleep};
+
+fn answer(peer_id: usize, ping: Ping) -> Pong {
+    Pong {
+        id: peer_id,
+        seq: ping.seq,
+        sent_at_micros: ping.sent_at_micros,
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn pings_measure_round_trips_and_missed_answers() {
+    let connectivity = Connectivity::new(2);
+    let ping = connectivity.ping(0, 1);
+    advance(Duration::from_millis(40)).await;
+    assert!(!connectivity.pong(&answer(1, ping)));
+    let ping = connectivity.ping(0, 1);
+    advance(Duration::from_millis(80)).await;
+    connectivity.pong(&answer(1, ping));
+    let link = connectivity.stats().peers[&1];
+    assert_eq!(link.last_rtt_micros, Some(80_000));
+    assert_eq!(link.rtt_micros, Some(45_000));
+    assert_eq!((link.pings_sent, link.pongs_received), (2, 2));
+
+    // pongs for pings we never sent measure nothing
+    let forged = Pong {
+        id: 1,
+        seq: 99,
+        sent_at_micros: 0,
+    };
+    assert!(!connectivity.pong(&forged));
+    assert_eq!(connectivity.stats().peers[&1].pongs_received, 2);
+
+    // the peer is taken for dead once it leaves two pings unanswered, until it answers again
+    connectivity.ping(0, 1);
+    connectivity.ping(0, 1);
+    assert!(!connectivity.is_down(1));
+    let ping = connectivity.ping(0, 1);
+    assert!(connectivity.is_down(1));
+    assert_eq!(connectivity.stats().down_peers(), vec![1]);
+    assert!(connectivity.pong(&answer(1, ping)));
+    assert!(connectivity.stats().down_peers().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_quorum_round_trip_leaves_out_the_slowest_peers() {
+    let connectivity = Connectivity::new(1);
+    let pings: Vec<_> = (1..4)
+        .map(|peer_id| connectivity.ping(0, peer_id))
+        .collect();
+    assert_eq!(connectivity.quorum_rtt(1), None);
+    for (peer_id, ping) in (1..4).zip(pings) {
+        advance(Duration::from_millis(100)).await;
+        connectivity.pong(&answer(peer_id, ping));
+    }
+    // 2f peers answer within the round trip of the second fastest peer
+    assert_eq!(connectivity.quorum_rtt(1), Some(Duration::from_millis(200)));
+    assert_eq!(connectivity.quorum_rtt(0), None);
+
+    // peers taken for dead do not count
+    connectivity.ping(0, 1);
+    connectivity.ping(0, 1);
+    assert_eq!(connectivity.quorum_rtt(1), Some(Duration::from_millis(300)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn replicas_report_round_trips_and_dead_peers() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .links(LinkModel::wan(4))
+        .build();
+    sleep(Duration::from_secs(5)).await;
+    let peers = cluster.status(0).connectivity.peers;
+    assert_eq!(peers.len(), 3);
+    // replica 3 is in the region of replica 0, replica 1 one region away
+    let rtt = |peer_id| peers[&peer_id].rtt_micros.unwrap();
+    assert!(rtt(3) < 10_000);
+    assert!((60_000..100_000).contains(&rtt(1)));
+    assert!(rtt(2) > rtt(1));
+    assert!(cluster.status(0).connectivity.down_peers().is_empty());
+
+    cluster.kill_node(2);
+    sleep(Duration::from_secs(5)).await;
+    for id in [0, 1, 3] {
+        assert_eq!(cluster.status(id).connectivity.down_peers(), vec![2]);
+    }
+}
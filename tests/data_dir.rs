@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use pbft::config::Config;
+use pbft::data_dir::{DataDir, DataDirError, LegacyPaths, STORAGE_VERSION};
+use pbft::keys::KeyProvider;
+use pbft::storage::{Wal, WalRecord};
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pbft-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn a_fresh_directory_is_laid_out_for_the_node() {
+    let root = data_dir("data-dir-fresh");
+    let data_dir = DataDir::open(&root, 2, &LegacyPaths::default()).unwrap();
+    assert_eq!(data_dir.version(), Ok(STORAGE_VERSION));
+    for dir in ["wal", "snapshots", "keys", "meta"] {
+        assert!(root.join(dir).is_dir());
+    }
+
+    let mut config = Config {
+        snapshot_export: Some(PathBuf::from("/elsewhere.snapshot")),
+        ..Config::default()
+    };
+    data_dir.configure(&mut config);
+    assert_eq!(config.wal_path, Some(root.join("wal").join("log.jsonl")));
+    assert_eq!(
+        config.state_path,
+        Some(root.join("snapshots").join("state"))
+    );
+    assert_eq!(
+        config.snapshot_export,
+        Some(PathBuf::from("/elsewhere.snapshot"))
+    );
+    assert_eq!(
+        config.registry_export,
+        Some(root.join("meta").join("registry.json"))
+    );
+
+    // the key is generated on first use and kept
+    let keypair_bytes = data_dir.keypair_bytes().unwrap();
+    let data_dir = DataDir::open(&root, 2, &LegacyPaths::default()).unwrap();
+    assert_eq!(data_dir.keypair_bytes().unwrap(), keypair_bytes);
+
+    // the directory is the node's own
+    assert_eq!(
+        DataDir::open(&root, 3, &LegacyPaths::default()).err(),
+        Some(DataDirError::OtherNode(2))
+    );
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn directories_of_a_later_version_are_refused() {
+    let root = data_dir("data-dir-later");
+    DataDir::open(&root, 0, &LegacyPaths::default()).unwrap();
+    std::fs::write(root.join("VERSION"), format!("{}\n", STORAGE_VERSION + 1)).unwrap();
+    assert_eq!(
+        DataDir::open(&root, 0, &LegacyPaths::default()).err(),
+        Some(DataDirError::NewerVersion {
+            found: STORAGE_VERSION + 1
+        })
+    );
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn the_write_ahead_log_of_a_legacy_node_is_moved_in() {
+    let root = data_dir("data-dir-legacy");
+    std::fs::create_dir_all(&root).unwrap();
+    let legacy_wal = root.with_extension("wal");
+    let _ = std::fs::remove_file(&legacy_wal);
+    {
+        let (mut wal, _) = Wal::open(&legacy_wal).unwrap();
+        wal.append(&WalRecord::View(4)).unwrap();
+    }
+    // a previous start crashed after staging the log
+    std::fs::rename(&legacy_wal, root.join("wal.migrating")).unwrap();
+
+    let legacy = LegacyPaths {
+        wal_path: Some(legacy_wal.clone()),
+        state_path: None,
+    };
+    let data_dir = DataDir::open(&root, 1, &legacy).unwrap();
+    assert_eq!(data_dir.version(), Ok(STORAGE_VERSION));
+    assert!(!root.join("wal.migrating").exists());
+    let (_, records) = Wal::open(&data_dir.wal_path()).unwrap();
+    assert!(matches!(records[..], [WalRecord::View(4)]));
+    let _ = std::fs::remove_dir_all(&root);
+}
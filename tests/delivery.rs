@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::consensus::Consensus;
+use pbft::messages::{
+    CheckPoint, ClientRequest, ClientResponse, ConsensusCommand, Message, NodeCommand, Operation,
+};
+use pbft::observer::{Observer, QuorumKind};
+use pbft::state::SlotMeta;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, NodeId, Value};
+
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use tokio::sync::mpsc::{channel, Sender};
+
+/// Id of the replica under test, which is not the primary of view 0
+const ID: NodeId = 1;
+const NUM_REQUESTS: usize = 7;
+
+#[derive(Default)]
+struct Recorder {
+    quorums: Mutex<Vec<(QuorumKind, usize)>>,
+    applied: Mutex<Vec<SlotMeta>>,
+}
+
+impl Observer for Recorder {
+    fn on_quorum(&self, kind: QuorumKind, _view: usize, seq_num: usize, _participants: &[NodeId]) {
+        self.quorums.lock().unwrap().push((kind, seq_num));
+    }
+
+    fn on_commit(&self, slot: &SlotMeta, _request: &ClientRequest, _response: &ClientResponse) {
+        self.applied.lock().unwrap().push(*slot);
+    }
+}
+
+impl Recorder {
+    fn quorums_of(&self, kind: QuorumKind) -> Vec<usize> {
+        let mut seq_nums: Vec<usize> = self
+            .quorums
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(quorum_kind, _)| *quorum_kind == kind)
+            .map(|(_, seq_num)| *seq_num)
+            .collect();
+        seq_nums.sort();
+        seq_nums
+    }
+}
+
+/// Runs the consensus engine of replica 1 in a cluster of 4, with the network replaced by
+/// channels. Returns the sender of the messages the engine receives, the observed events
+/// and the messages the engine broadcast
+fn start_engine(
+    builders: &[MessageBuilder],
+) -> (
+    Sender<ConsensusCommand>,
+    Arc<Recorder>,
+    Arc<Mutex<Vec<Message>>>,
+) {
+    let peer_addrs: HashMap<NodeId, SocketAddr> = (0..4)
+        .map(|id| (id, SocketAddr::from(([127, 0, 0, 1], 1 + id as u16))))
+        .collect();
+    let mut config = Config::new(peer_addrs);
+    config.checkpoint_frequency = 5;
+    // no view change is started while the test runs
+    config.request_timeout = Duration::from_secs(600);
+    config.rebroadcast_timeout = Duration::from_secs(600);
+
+    let (tx_consensus, rx_consensus) = channel(1024);
+    let (tx_node, mut rx_node) = channel(1024);
+    let peer_pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    let mut consensus = Consensus::new(
+        ID,
+        config,
+        builders[ID].keystore(),
+        rx_consensus,
+        tx_consensus.clone(),
+        tx_node,
+        Arc::new(tokio::sync::Mutex::new(peer_pub_keys)),
+    );
+    let recorder = Arc::new(Recorder::default());
+    consensus.register_observer(recorder.clone());
+
+    let broadcast = Arc::new(Mutex::new(Vec::new()));
+    let sent = broadcast.clone();
+    tokio::spawn(async move {
+        while let Some(command) = rx_node.recv().await {
+            if let NodeCommand::BroadCastMessageCommand(broadcast) = command {
+                sent.lock().unwrap().push(broadcast.message);
+            }
+        }
+    });
+    tokio::spawn(async move {
+        consensus.spawn().await;
+    });
+    (tx_consensus, recorder, broadcast)
+}
+
+/// Delivers every message one to three times, in a random order
+async fn deliver(
+    tx_consensus: &Sender<ConsensusCommand>,
+    mut messages: Vec<Message>,
+    rng: &mut ChaCha8Rng,
+) {
+    let copies = messages.clone();
+    for message in copies {
+        for _ in 0..[0, 1, 2].choose(rng).copied().unwrap() {
+            messages.push(message.clone());
+        }
+    }
+    messages.shuffle(rng);
+    for message in messages {
+        tx_consensus
+            .send(ConsensusCommand::ProcessMessage(message))
+            .await
+            .unwrap();
+    }
+}
+
+/// Waits for the condition to hold, giving up after a few seconds
+async fn eventually(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..100 {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn duplicated_and_reordered_messages_are_counted_once() {
+    let mut rng = ChaCha8Rng::seed_from_u64(2013);
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let (tx_consensus, recorder, broadcast) = start_engine(&builders);
+
+    // the primary (0) and two backups (2, 3) vote, so every quorum needs all of their votes
+    let mut messages = Vec::new();
+    for seq_num in 1..=NUM_REQUESTS {
+        let request = ClientRequest {
+            respond_addr: SocketAddr::from(([127, 0, 0, 1], 7100)),
+            time_stamp: seq_num,
+            key: Key::from(format!("k{}", seq_num).as_str()),
+            operation: Operation::Set(Value::from(seq_num.to_string())),
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        };
+        let slot = |id: NodeId| {
+            builders[id]
+                .clone()
+                .seq_num(seq_num)
+                .client_request(request.clone())
+        };
+        messages.push(Message::PrePrepareMessage(slot(0).pre_prepare()));
+        for id in [0, 2, 3] {
+            messages.push(Message::PrepareMessage(slot(id).prepare()));
+            messages.push(Message::CommitMessage(slot(id).commit()));
+        }
+    }
+    deliver(&tx_consensus, messages, &mut rng).await;
+
+    assert!(eventually(|| recorder.applied.lock().unwrap().len() == NUM_REQUESTS).await);
+    let all_slots: Vec<usize> = (1..=NUM_REQUESTS).collect();
+    // every request was applied in the slot the primary of view 0 proposed it in
+    let applied_slots: Vec<SlotMeta> = all_slots
+        .iter()
+        .map(|seq_num| SlotMeta {
+            seq_num: *seq_num,
+            view: 0,
+            leader: 0,
+            time_stamp: *seq_num,
+        })
+        .collect();
+    assert_eq!(*recorder.applied.lock().unwrap(), applied_slots);
+    assert_eq!(recorder.quorums_of(QuorumKind::Prepare), all_slots);
+    assert_eq!(recorder.quorums_of(QuorumKind::Commit), all_slots);
+    // the replica sent a single commit for each request
+    let mut commits_sent: Vec<usize> = broadcast
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|message| match message {
+            Message::CommitMessage(commit) => Some(commit.seq_num),
+            _ => None,
+        })
+        .collect();
+    commits_sent.sort();
+    assert_eq!(commits_sent, all_slots);
+
+    // the other replicas agree with the checkpoint the replica took after request 5
+    let own_checkpoint = broadcast
+        .lock()
+        .unwrap()
+        .iter()
+        .find_map(|message| match message {
+            Message::CheckPointMessage(checkpoint) => Some(checkpoint.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let checkpoints = [0, 2, 3]
+        .iter()
+        .map(|id| {
+            Message::CheckPointMessage(CheckPoint::new_with_signature(
+                builders[*id].keystore(),
+                *id,
+                0,
+                own_checkpoint.committed_seq_num,
+                own_checkpoint.view,
+                own_checkpoint.state_digest.clone(),
+            ))
+        })
+        .collect();
+    deliver(&tx_consensus, checkpoints, &mut rng).await;
+
+    assert!(eventually(|| !recorder.quorums_of(QuorumKind::CheckPoint).is_empty()).await);
+    // give the engine time to process the remaining duplicates
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(recorder.quorums_of(QuorumKind::CheckPoint), vec![5]);
+    assert_eq!(recorder.applied.lock().unwrap().len(), NUM_REQUESTS);
+}
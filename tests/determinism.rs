@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+use pbft::crypto::{self, DigestAlgorithm, SigningInput};
+use pbft::keys::encode_hex;
+use pbft::keystore::Keystore;
+use pbft::merkle;
+use pbft::messages::{
+    BatchOp, ClientIdentity, ClientRequest, Commit, Operation, PrePrepare, Prepare, ViewChange,
+};
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, Value};
+
+// Digests and signatures are pinned to the values computed on a 64-bit little-endian host,
+// so a replica on any other architecture which computes different bytes fails these tests
+
+fn request() -> ClientRequest {
+    RequestBuilder::new()
+        .respond_addr("127.0.0.1:7100".parse().unwrap())
+        .time_stamp(7)
+        .key(Key::from("x"))
+        .set("42")
+        .relay_id(2)
+        .batch(vec![
+            BatchOp::Put {
+                key: Key::from("y"),
+                value: Value::from("1"),
+            },
+            BatchOp::Delete {
+                key: Key::from("z"),
+            },
+        ])
+        .build()
+}
+
+fn keystore() -> Keystore {
+    let secret = SecretKey::from_bytes(&[7u8; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keystore::from_keypair(Keypair { secret, public })
+}
+
+#[test]
+fn request_digests_are_pinned() {
+    assert_eq!(
+        encode_hex(&request().digest_with(DigestAlgorithm::Sha512)),
+        "01e1b612029e515a563e8cf3208d7c24ab5007524a59e7a7313134c7e9701326d01efaeb0e831b0c238dfac8d3087b404597ecb692f132ac68b1afe31391e2aa8d"
+    );
+    assert_eq!(
+        encode_hex(&request().digest_with(DigestAlgorithm::Sha256)),
+        "02a2ec28d3f08ceae0304326787c482e2b4a093ec2d47e7334fcecca8c175dc552"
+    );
+}
+
+#[test]
+fn request_encoding_is_unambiguous() {
+    // moving bytes between adjacent fields changes the digest
+    let mut shifted = request();
+    shifted.key = Key::from("xy");
+    shifted.batch = vec![BatchOp::Delete {
+        key: Key::from("z"),
+    }];
+    assert_ne!(request().digest(), shifted.digest());
+    let mut shifted = request();
+    shifted.key = Key::from("x4");
+    shifted.operation = Operation::Set(Value::from("2"));
+    assert_ne!(request().digest(), shifted.digest());
+
+    let mut get = request();
+    get.operation = Operation::Get;
+    let mut no_relay = request();
+    no_relay.relay_id = None;
+    assert_ne!(get.digest(), no_relay.digest());
+}
+
+#[test]
+fn state_digest_is_pinned() {
+    let mut store = BTreeMap::new();
+    let mut key_owners = BTreeMap::<Key, ClientIdentity>::new();
+    for (i, key) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+        store.insert(Key::from(*key), Value::from((i * 100).to_string()));
+    }
+    // keys created by clients without an id hash as they did when owners were addresses
+    key_owners.insert(
+        Key::from("b"),
+        ClientIdentity::Addr("10.0.0.1:9000".parse().unwrap()),
+    );
+    assert_eq!(
+        encode_hex(&merkle::root(&store, &key_owners, DigestAlgorithm::Sha256)),
+        "022355ec810ecfef37796d87c0f8b9e59742a316bb87e07a027c5ae39bddd104aa"
+    );
+}
+
+#[test]
+fn signatures_are_pinned() {
+    // ed25519 signatures are deterministic, so this pins the signed encoding of the message
+    let commit = Commit::new_with_signature(
+        &keystore(),
+        3,
+        0,
+        1,
+        12,
+        request().digest_with(DigestAlgorithm::Sha512),
+    );
+    assert_eq!(
+        encode_hex(&commit.signature),
+        "01742d6fcdf0ef18895c2b213d23baecf504fba268265b989e12184ad0f6028c304bc95a497917f8947ea7adcca625d0e3e91db6af9f8299684dd6b087c1f1140c"
+    );
+
+    // numbers are signed as 8 little-endian bytes, whatever the width of usize
+    assert_eq!(crypto::encode_usize(0x0102_0304), [4, 3, 2, 1, 0, 0, 0, 0]);
+    let mut signing_input = SigningInput::new();
+    signing_input.update_usize(0x0102_0304);
+    assert_eq!(
+        encode_hex(&keystore().sign(&signing_input, DigestAlgorithm::Sha256)),
+        "022e6b1ffe92a32109825cd21c310599ab937ab26ca93b5876eb0b14e4dcdd0a66cf5f12983ca6a955006cf78943d164da11f5193c6371005aa6d9608485c6070b"
+    );
+}
+
+#[test]
+fn view_change_serialization_does_not_depend_on_insertion_order() {
+    let builder = MessageBuilder::generate(1);
+    let entry = |seq_num: usize| {
+        let builder = builder.clone().seq_num(seq_num);
+        let pre_prepare: PrePrepare = builder.pre_prepare();
+        let prepares: Vec<Prepare> = vec![builder.clone().id(2).prepare()];
+        (seq_num, (pre_prepare, prepares))
+    };
+
+    let mut view_changes = Vec::new();
+    for order in [[1, 2, 3, 4], [4, 2, 3, 1]] {
+        let mut view_change: ViewChange = builder.view_change();
+        for seq_num in order {
+            let (seq_num, prepared) = entry(seq_num);
+            view_change.subsequent_prepares.insert(seq_num, prepared);
+        }
+        let view_change = view_change.compacted(1);
+        view_changes.push(serde_json::to_string(&view_change).unwrap());
+    }
+    assert_eq!(view_changes[0], view_changes[1]);
+}
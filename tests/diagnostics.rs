@@ -0,0 +1,41 @@
+use pbft::diagnostics::QuorumDiagnostics;
+use pbft::testing::ClusterBuilder;
+use pbft::{Key, Value};
+
+#[test]
+fn nodes_absent_from_most_quorums_of_a_window_are_suspected() {
+    let mut diagnostics = QuorumDiagnostics::default();
+    let quorums = [[0, 1, 2], [0, 1, 2], [0, 2, 3], [0, 1, 3]];
+    for (i, quorum) in quorums.iter().enumerate() {
+        // the window is analyzed once it holds enough quorums
+        assert_eq!(diagnostics.record_quorum(quorum.iter(), 4), i == 3);
+    }
+    // node 3 joined half of the quorums
+    assert_eq!(diagnostics.analyze(4), &vec![3]);
+
+    // every window starts afresh
+    assert!(diagnostics.record_quorum([0, 1, 2, 3].iter(), 1));
+    assert!(diagnostics.analyze(4).is_empty());
+    assert_eq!(diagnostics.quorums_observed, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_replicas_are_reported_in_the_status() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(11)
+        .config(|config| config.quorum_diagnostics_interval = 4)
+        .build();
+    cluster.kill_node(3);
+
+    let mut client = cluster.client();
+    for i in 1..=4 {
+        assert!(client
+            .put(Key::from("k"), Value::from(i.to_string()))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(4).await);
+    for id in 0..3 {
+        assert_eq!(cluster.status(id).suspected_nodes, vec![3]);
+    }
+}
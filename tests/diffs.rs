@@ -0,0 +1,110 @@
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::client::PbftClient;
+use pbft::config::Config;
+use pbft::diffs::{CheckpointDiffs, DiffError};
+use pbft::node::Node;
+use pbft::sim::{LinkConfig, Network, Simulation};
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, NodeId, Value};
+
+use tokio::sync::mpsc::channel;
+
+#[tokio::test(start_paused = true)]
+async fn replicas_retain_the_keys_written_between_stable_checkpoints() {
+    let mut config = Config::new(
+        (0..4)
+            .map(|id| (id, SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))))
+            .collect(),
+    );
+    config.checkpoint_frequency = 4;
+    config.retained_diffs = 2;
+    let sim = Simulation::start(config, Network::new(8, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+
+    let mut written = Vec::new();
+    for round in 0..12 {
+        let key = Key::from(format!("k{}", round % 5));
+        let response = client.put(key.clone(), Value::from("v")).await.unwrap();
+        written.push((response.seq_num, key));
+    }
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    let written_between = |from: usize, to: usize| -> BTreeSet<Key> {
+        written
+            .iter()
+            .filter(|(seq_num, _)| *seq_num > from && *seq_num <= to)
+            .map(|(_, key)| key.clone())
+            .collect()
+    };
+    for node in sim.nodes.iter() {
+        let diffs = &node.checkpoint_diffs;
+        assert_eq!(diffs.range(), (4, 12));
+        assert_eq!(diffs.diff(4, None).unwrap(), (written_between(4, 12), 12));
+        assert_eq!(
+            diffs.diff(8, Some(12)).unwrap(),
+            (written_between(8, 12), 12)
+        );
+        // only the diffs of the last two stable checkpoints are retained
+        assert_eq!(
+            diffs.diff(0, Some(8)),
+            Err(DiffError::NotRetained { oldest_seq_num: 4 })
+        );
+        assert_eq!(diffs.diff(4, Some(10)), Err(DiffError::NotStable(10)));
+    }
+}
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7990 + id as u16))
+}
+
+/// Starts a node answering diff requests from the diffs we stand in for its consensus engine with
+fn diff_node(id: NodeId, diffs: CheckpointDiffs) {
+    let builder = MessageBuilder::generate(id);
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let (tx_consensus, rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        id,
+        config,
+        builder.keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    node.inner.checkpoint_diffs = diffs;
+    tokio::spawn(async move {
+        let _rx_consensus = rx_consensus;
+        node.spawn().await
+    });
+}
+
+#[tokio::test]
+async fn clients_take_the_union_of_the_diffs_of_f_plus_one_replicas() {
+    for id in 0..4 {
+        let diffs = CheckpointDiffs::new(0);
+        diffs.record([&Key::from("a")], 3);
+        diffs.stabilize(4);
+        // node 3 is faulty and leaves out a key it wrote
+        if id != 3 {
+            diffs.record([&Key::from("b")], 6);
+        }
+        diffs.stabilize(8);
+        diff_node(id, diffs);
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let client = PbftClient::new(&config, addr_of(4));
+    assert_eq!(
+        client.checkpoint_diff(0, 8).await.unwrap(),
+        BTreeSet::from([Key::from("a"), Key::from("b")])
+    );
+    assert_eq!(
+        client.checkpoint_diff(4, 8).await.unwrap(),
+        BTreeSet::from([Key::from("b")])
+    );
+    assert!(client.checkpoint_diff(4, 6).await.is_err());
+}
@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+use pbft::crypto::{self, DigestAlgorithm, DigestMigration, DigestPolicy, SigningInput};
+use pbft::keystore::Keystore;
+use pbft::merkle::{self, ProofError};
+use pbft::messages::{CheckPoint, ClientRequest, KeyProof};
+use pbft::{Key, Value};
+
+/// Tests which change the digest policy of the process must not run concurrently
+static POLICY_LOCK: Mutex<()> = Mutex::new(());
+
+fn old_policy() -> DigestPolicy {
+    DigestPolicy {
+        algorithm: DigestAlgorithm::Sha512,
+        migration: None,
+    }
+}
+
+fn migration_policy(from_seq_num: usize) -> DigestPolicy {
+    DigestPolicy {
+        algorithm: DigestAlgorithm::Sha512,
+        migration: Some(DigestMigration {
+            algorithm: DigestAlgorithm::Sha256,
+            from_seq_num,
+        }),
+    }
+}
+
+fn new_policy() -> DigestPolicy {
+    DigestPolicy {
+        algorithm: DigestAlgorithm::Sha256,
+        migration: None,
+    }
+}
+
+fn keypair() -> Keypair {
+    Keypair::generate(&mut OsRng {})
+}
+
+#[test]
+fn both_algorithms_are_accepted_during_migration() {
+    let keypair = keypair();
+    let mut input = SigningInput::new();
+    input.update(b"Test");
+    input.update(7usize.to_le_bytes());
+
+    let old_signature = crypto::sign(&keypair, &input, DigestAlgorithm::Sha512);
+    let new_signature = crypto::sign(&keypair, &input, DigestAlgorithm::Sha256);
+    assert_eq!(
+        crypto::algorithm_of(&old_signature),
+        Some(DigestAlgorithm::Sha512)
+    );
+    assert_eq!(
+        crypto::algorithm_of(&new_signature),
+        Some(DigestAlgorithm::Sha256)
+    );
+
+    let during = migration_policy(10);
+    assert!(during.verify(&keypair.public, &input, &old_signature));
+    assert!(during.verify(&keypair.public, &input, &new_signature));
+
+    assert!(old_policy().verify(&keypair.public, &input, &old_signature));
+    assert!(!old_policy().verify(&keypair.public, &input, &new_signature));
+    assert!(!new_policy().verify(&keypair.public, &input, &old_signature));
+    assert!(new_policy().verify(&keypair.public, &input, &new_signature));
+
+    // a signature over other data does not verify with either algorithm
+    let mut other_input = SigningInput::new();
+    other_input.update(b"Test");
+    other_input.update(8usize.to_le_bytes());
+    assert!(!during.verify(&keypair.public, &other_input, &old_signature));
+    assert!(!during.verify(&keypair.public, &other_input, &new_signature));
+}
+
+#[test]
+fn request_digests_switch_at_the_migration_seq_num() {
+    let _lock = POLICY_LOCK.lock().unwrap();
+    crypto::set_policy(migration_policy(5));
+
+    let request = ClientRequest::no_op();
+    assert_eq!(
+        crypto::algorithm_of(&request.digest_at(4)),
+        Some(DigestAlgorithm::Sha512)
+    );
+    assert_eq!(
+        crypto::algorithm_of(&request.digest_at(5)),
+        Some(DigestAlgorithm::Sha256)
+    );
+    assert_eq!(
+        request.digest_at(5),
+        request.digest_with(DigestAlgorithm::Sha256)
+    );
+    assert_ne!(request.digest_at(4), request.digest_at(5));
+
+    crypto::set_policy(DigestPolicy::default());
+}
+
+#[test]
+fn mixed_algorithm_checkpoint_quorum() {
+    let _lock = POLICY_LOCK.lock().unwrap();
+    let keystores: Vec<Keystore> = (0..4).map(|_| Keystore::generate()).collect();
+    let pub_keys: HashMap<usize, _> = keystores
+        .iter()
+        .enumerate()
+        .map(|(id, keystore)| (id, keystore.public_key()))
+        .collect();
+
+    let mut store = BTreeMap::new();
+    store.insert(Key::from("x"), Value::from("42"));
+    store.insert(Key::from("y"), Value::from("7"));
+    let key_owners = BTreeMap::new();
+    let state_digest = merkle::root(&store, &key_owners, DigestAlgorithm::Sha512);
+
+    // nodes 0 and 1 have not completed the migration yet while node 2 has,
+    // so their checkpoints over the same state are signed with different algorithms
+    let checkpoint = |id: usize| {
+        CheckPoint::new_with_signature(&keystores[id], id, 0, 10, 0, state_digest.clone())
+    };
+    crypto::set_policy(old_policy());
+    let mut certificate = vec![checkpoint(0), checkpoint(1)];
+    crypto::set_policy(new_policy());
+    certificate.push(checkpoint(2));
+
+    let key_proof = KeyProof {
+        id: 0,
+        key: Key::from("x"),
+        value: Some(Value::from("42")),
+        owner: None,
+        committed_seq_num: 10,
+        state_digest: state_digest.clone(),
+        proof: merkle::prove(
+            &store,
+            &key_owners,
+            &Key::from("x"),
+            DigestAlgorithm::Sha512,
+        ),
+        certificate,
+    };
+
+    // during the migration the mixed quorum certifies the state
+    crypto::set_policy(migration_policy(0));
+    assert_eq!(
+        merkle::verify_key_proof(&key_proof, &pub_keys, 1),
+        Ok(Value::from("42"))
+    );
+
+    // without the migration only the checkpoints of one algorithm count
+    crypto::set_policy(old_policy());
+    assert_eq!(
+        merkle::verify_key_proof(&key_proof, &pub_keys, 1),
+        Err(ProofError::InsufficientCertificate { signers: 2 })
+    );
+
+    crypto::set_policy(DigestPolicy::default());
+}
+
+#[test]
+fn algorithms_produce_distinct_tagged_digests() {
+    let keypair = keypair();
+    let mut input = SigningInput::new();
+    input.update(b"Test");
+    let request = ClientRequest::no_op();
+
+    let mut digests = Vec::new();
+    for algorithm in DigestAlgorithm::ALL {
+        assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        let digest = request.digest_with(algorithm);
+        assert_eq!(crypto::algorithm_of(&digest), Some(algorithm));
+        digests.push(digest);
+
+        let policy = DigestPolicy {
+            algorithm,
+            migration: None,
+        };
+        assert!(policy.verify(
+            &keypair.public,
+            &input,
+            &crypto::sign(&keypair, &input, algorithm)
+        ));
+    }
+    // sha512/256 is not a truncation of sha512, which starts from other initial values
+    assert_eq!(digests[2].len(), 33);
+    assert_ne!(digests[2][1..], digests[0][1..33]);
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use pbft::config::Config;
+use pbft::messages::{ClientRequest, EquivocationProof};
+use pbft::state::State;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+
+fn request(value: &str) -> ClientRequest {
+    RequestBuilder::new().set(value).build()
+}
+
+fn state_of_backup() -> State {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    State::new(1, Config::new(peer_addrs))
+}
+
+#[test]
+fn conflicting_pre_prepares_of_the_primary_prove_it_faulty() {
+    let primary = MessageBuilder::generate(0).seq_num(3);
+    let proof = EquivocationProof {
+        first: primary.clone().client_request(request("a")).pre_prepare(),
+        second: primary.clone().client_request(request("b")).pre_prepare(),
+    };
+    assert!(proof.is_valid(0, &primary.public_key()));
+
+    // pre-prepares for the same request, or another slot, or signed with another key
+    let same = EquivocationProof {
+        second: proof.first.clone(),
+        ..proof.clone()
+    };
+    assert!(!same.is_valid(0, &primary.public_key()));
+    let other_slot = EquivocationProof {
+        second: primary
+            .clone()
+            .seq_num(4)
+            .client_request(request("b"))
+            .pre_prepare(),
+        ..proof.clone()
+    };
+    assert!(!other_slot.is_valid(0, &primary.public_key()));
+    let impostor = MessageBuilder::generate(0).seq_num(3);
+    let forged = EquivocationProof {
+        second: impostor.client_request(request("b")).pre_prepare(),
+        ..proof.clone()
+    };
+    assert!(!forged.is_valid(0, &primary.public_key()));
+}
+
+#[test]
+fn replicas_stop_accepting_pre_prepares_of_a_primary_proven_faulty() {
+    let primary = MessageBuilder::generate(0).seq_num(3);
+    let proof = EquivocationProof {
+        first: primary.clone().client_request(request("a")).pre_prepare(),
+        second: primary.clone().client_request(request("b")).pre_prepare(),
+    };
+    let pub_keys = HashMap::from([(0, primary.public_key())]);
+    let mut state = state_of_backup();
+    assert!(state.should_accept_pre_prepare(&proof.first));
+    assert!(state.should_accept_equivocation_proof(&proof, &pub_keys));
+
+    state.record_equivocation(proof.clone());
+    assert!(!state.should_accept_pre_prepare(&proof.first));
+    // a proof we already have is not passed on again
+    assert!(!state.should_accept_equivocation_proof(&proof, &pub_keys));
+
+    // nor is one for a view we are not in
+    let mut later = state_of_backup();
+    later.view = 4;
+    assert!(!later.should_accept_equivocation_proof(&proof, &pub_keys));
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, HashSet};
+
+use pbft::client::ClientError;
+use pbft::config::Config;
+use pbft::messages::{ClientRequest, ClientResponse, ErrorCode, FailureReason, ResubmitHint};
+use pbft::state::State;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+
+fn request(time_stamp: usize) -> ClientRequest {
+    RequestBuilder::new().time_stamp(time_stamp).build()
+}
+
+#[test]
+fn codes_are_stable_and_unique() {
+    let names: HashSet<&str> = ErrorCode::ALL.iter().map(ErrorCode::name).collect();
+    let codes: HashSet<u16> = ErrorCode::ALL.iter().map(ErrorCode::code).collect();
+    assert_eq!(names.len(), ErrorCode::ALL.len());
+    assert_eq!(codes.len(), ErrorCode::ALL.len());
+
+    for error in ErrorCode::ALL {
+        assert_eq!(ErrorCode::from_code(error.code()), Some(error));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            format!("\"{}\"", error.name())
+        );
+    }
+    assert_eq!(ErrorCode::Busy.code(), 1);
+    assert_eq!(
+        ErrorCode::QuorumUnavailable.to_string(),
+        "QUORUM_UNAVAILABLE (3)"
+    );
+    assert_eq!(ErrorCode::from_code(0), None);
+}
+
+#[test]
+fn rejections_carry_the_code_of_their_reason() {
+    let builder = MessageBuilder::generate(1);
+    let respond = |reason| {
+        ClientResponse::new_with_signature(
+            builder.keystore(),
+            1,
+            7,
+            "k".into(),
+            None,
+            Vec::new(),
+            reason,
+        )
+    };
+    assert_eq!(respond(None).code, None);
+    assert_eq!(
+        respond(Some(FailureReason::ClientByteQuotaExceeded)).code,
+        Some(ErrorCode::QuotaExceeded)
+    );
+    assert_eq!(
+        respond(Some(FailureReason::ReservedKey)).code,
+        Some(ErrorCode::Unauthorized)
+    );
+    let resubmit_hint = ResubmitHint {
+        view: 2,
+        primary: 2,
+    };
+    assert_eq!(
+        respond(Some(FailureReason::Unordered { resubmit_hint })).code,
+        Some(ErrorCode::Unordered)
+    );
+
+    let error = ClientError::Rejected {
+        timestamp: 7,
+        reason: FailureReason::Busy,
+        previous: None,
+    };
+    assert_eq!(error.code(), ErrorCode::Busy);
+    assert_eq!(
+        ClientError::Timeout { timestamp: 7 }.code(),
+        ErrorCode::QuorumUnavailable
+    );
+    assert_eq!(
+        ClientError::BatchTooLarge { ops: 100 }.code(),
+        ErrorCode::PayloadTooLarge
+    );
+}
+
+#[test]
+fn requests_older_than_the_last_executed_one_are_stale() {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut state = State::new(1, Config::new(peer_addrs));
+    assert!(!state.is_stale_client_request(&request(5)));
+
+    let response = ClientResponse::new_with_signature(
+        MessageBuilder::generate(1).keystore(),
+        1,
+        5,
+        "k".into(),
+        None,
+        Vec::new(),
+        None,
+    );
+    state.cache_reply(&request(5), &response);
+    assert!(state.is_stale_client_request(&request(4)));
+    // the executed request itself is answered from the reply cache
+    assert!(!state.is_stale_client_request(&request(5)));
+    assert!(!state.is_stale_client_request(&request(6)));
+}
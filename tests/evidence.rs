@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use pbft::config::Config;
+use pbft::evidence::{check_new_view, Evidence, EvidenceLog, Exclusion, NewViewFault};
+use pbft::leader::LeaderPolicy;
+use pbft::messages::{Blame, ClientRequest, EquivocationProof, FailureReason, Message};
+use pbft::registry;
+use pbft::state::{State, StateSnapshot};
+use pbft::testing::ClusterBuilder;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, NodeId, Value};
+
+use ed25519_dalek::PublicKey;
+
+fn request(value: &str) -> ClientRequest {
+    RequestBuilder::new().set(value).build()
+}
+
+/// Builders of four replicas, and their public keys
+fn replicas() -> (Vec<MessageBuilder>, HashMap<NodeId, PublicKey>) {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    (builders, pub_keys)
+}
+
+#[test]
+fn new_views_are_evidence_only_of_faults_their_primary_signed() {
+    let (builders, pub_keys) = replicas();
+    let view_changes: Vec<_> = builders.iter().map(MessageBuilder::view_change).collect();
+    let primary = &builders[1];
+
+    let justified = primary.new_view(view_changes[..3].to_vec());
+    assert_eq!(check_new_view(&justified, &pub_keys, 1), Ok(()));
+    assert!(!Evidence::InvalidNewView(justified).is_valid(&pub_keys, 1));
+
+    let too_few = primary.new_view(view_changes[..2].to_vec());
+    assert_eq!(
+        check_new_view(&too_few, &pub_keys, 1),
+        Err(NewViewFault::TooFewViewChanges(2))
+    );
+    assert!(Evidence::InvalidNewView(too_few.clone()).is_valid(&pub_keys, 1));
+    // the primary signed the new view, and no other replica can be blamed for it
+    let mut impostor = too_few.clone();
+    impostor.id = 2;
+    assert!(!Evidence::InvalidNewView(impostor).is_valid(&pub_keys, 1));
+
+    // a view change tampered with on the way is not the fault of the primary
+    let mut tampered = view_changes[..3].to_vec();
+    tampered[2].last_stable_seq_num = 7;
+    let tampered = primary.new_view(tampered);
+    let fault = check_new_view(&tampered, &pub_keys, 1).unwrap_err();
+    assert_eq!(fault, NewViewFault::UnsignedViewChange(2));
+    assert!(!fault.is_provable());
+    assert!(!Evidence::InvalidNewView(tampered).is_valid(&pub_keys, 1));
+}
+
+#[test]
+fn blames_are_signed_by_the_replica_vouching_for_the_evidence() {
+    let (builders, pub_keys) = replicas();
+    let primary = builders[0].clone().seq_num(3);
+    let evidence = Evidence::Equivocation(Box::new(EquivocationProof {
+        first: primary.clone().client_request(request("a")).pre_prepare(),
+        second: primary.clone().client_request(request("b")).pre_prepare(),
+    }));
+    assert_eq!(evidence.accused(), 0);
+    assert!(evidence.is_valid(&pub_keys, 1));
+
+    let blame = Blame::new_with_signature(builders[2].keystore(), 2, evidence);
+    let message = Message::BlameMessage(blame.clone());
+    assert_eq!(message.get_id(), Some(2));
+    assert!(message.is_properly_signed_by(&pub_keys[&2]));
+    assert!(!message.is_properly_signed_by(&pub_keys[&3]));
+
+    // the blame covers the evidence it vouches for
+    let mut other = blame;
+    other.evidence = Evidence::InvalidNewView(builders[0].new_view(Vec::new()));
+    assert!(!Message::BlameMessage(other).is_properly_signed_by(&pub_keys[&2]));
+}
+
+#[test]
+fn replicas_proven_faulty_are_passed_over_as_primary_of_later_views() {
+    let (builders, _) = replicas();
+    let log = EvidenceLog::default();
+    let evidence = Evidence::InvalidNewView(builders[1].new_view(Vec::new()));
+    assert!(log.record(evidence.clone()));
+    assert!(!log.record(evidence.clone()));
+    log.corroborate(1, 2);
+    log.corroborate(1, 3);
+    assert!(log.is_proven_faulty(1));
+    assert_eq!(log.summary().blamers[&1], BTreeSet::from([2, 3]));
+
+    // holding the evidence passes no one over until it is ordered
+    assert_eq!(log.unordered().len(), 1);
+    assert!(log.excluded(9, 1).is_empty());
+    log.exclude(Exclusion {
+        evidence,
+        from_view: 5,
+    });
+    assert!(log.unordered().is_empty());
+
+    let policy = LeaderPolicy::default();
+    let leader = |view| policy.leader_excluding(view, 4, &log.excluded(view, 1));
+    // the views before the one of the exclusion keep their primary
+    assert_eq!(leader(1), 1);
+    assert_eq!(leader(4), 0);
+    assert_eq!(leader(5), 2);
+    assert_eq!(leader(6), 2);
+
+    // at most f replicas are passed over, so that there is always a primary
+    log.exclude(Exclusion {
+        evidence: Evidence::InvalidNewView(builders[2].new_view(Vec::new())),
+        from_view: 4,
+    });
+    assert_eq!(log.excluded(5, 1), BTreeSet::from([1]));
+    assert_eq!(
+        policy.leader_excluding(5, 4, &BTreeSet::from([0, 1, 2, 3])),
+        1
+    );
+}
+
+#[test]
+fn replicas_which_learn_of_evidence_in_different_views_agree_on_the_primaries() {
+    let (builders, pub_keys) = replicas();
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.peer_pub_keys = pub_keys;
+    let mut states: Vec<State> = (2..4).map(|id| State::new(id, config.clone())).collect();
+    let evidence = Evidence::InvalidNewView(builders[1].new_view(Vec::new()));
+    let leaders = |state: &State| -> Vec<NodeId> {
+        (0..12)
+            .map(|view| state.get_leader_for_view(view))
+            .collect()
+    };
+    let rotation: Vec<NodeId> = (0..12).map(|view| view % 4).collect();
+
+    // replica 2 learns of the evidence in view 2 and replica 3 in view 4,
+    // which passes no one over by itself
+    states[0].view = 2;
+    states[1].view = 4;
+    for state in states.iter() {
+        assert!(state.evidence.record(evidence.clone()));
+        assert_eq!(leaders(state), rotation);
+    }
+
+    // the primary of view 4 orders the exclusion from the view after its own,
+    // and cannot put it off
+    let exclusion = |from_view| {
+        ClientRequest::for_exclusion(&Exclusion {
+            evidence: evidence.clone(),
+            from_view,
+        })
+    };
+    let primary = builders[0].clone().view(4).seq_num(1);
+    let deferred = primary.clone().client_request(exclusion(9)).pre_prepare();
+    assert!(!states[1].should_accept_pre_prepare(&deferred));
+    let ordered = primary.clone().client_request(exclusion(5));
+    assert!(states[1].should_accept_pre_prepare(&ordered.pre_prepare()));
+
+    // every replica which applies it passes replica 1 over from view 5
+    for state in states.iter_mut() {
+        let (result, _) = state.apply_commit(Arc::new(exclusion(5)), &ordered.commit());
+        assert_eq!(result.reason, None);
+    }
+    assert_eq!(leaders(&states[0]), leaders(&states[1]));
+    assert_eq!(states[0].get_leader_for_view(1), 1);
+    assert_eq!(states[0].get_leader_for_view(5), 2);
+
+    // only the first exclusion of a replica is kept, and only with valid evidence
+    let again = primary.clone().seq_num(2).client_request(exclusion(6));
+    let (result, _) = states[0].apply_commit(Arc::new(exclusion(6)), &again.commit());
+    assert_eq!(result.reason, Some(FailureReason::UnexpectedValue));
+    let mut forged = ClientRequest::for_exclusion(&Exclusion {
+        evidence: Evidence::InvalidNewView(builders[2].new_view(Vec::new())),
+        from_view: 5,
+    });
+    forged.key = registry::evidence_key(3);
+    let forged_commit = primary.seq_num(3).client_request(forged.clone()).commit();
+    let (result, _) = states[0].apply_commit(Arc::new(forged), &forged_commit);
+    assert_eq!(result.reason, Some(FailureReason::ReservedKey));
+
+    // a replica which fetches the state takes the exclusions from it
+    let mut lagging = State::new(0, config);
+    lagging.install_snapshot(&StateSnapshot {
+        committed_seq_num: 3,
+        store: states[0].store.clone(),
+        key_owners: states[0].key_owners.clone(),
+    });
+    assert_eq!(leaders(&lagging), leaders(&states[0]));
+}
+
+#[test]
+fn slots_are_attributed_to_the_primary_which_proposed_them() {
+    let (builders, pub_keys) = replicas();
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.peer_pub_keys = pub_keys;
+    let mut state = State::new(2, config);
+    state.view = 4;
+
+    // an exclusion only this replica applied yet makes another replica primary of view 4
+    state.evidence.exclude(Exclusion {
+        evidence: Evidence::InvalidNewView(builders[0].new_view(Vec::new())),
+        from_view: 1,
+    });
+    assert_eq!(state.get_leader_for_view(4), 1);
+
+    let slot = builders[0]
+        .clone()
+        .view(4)
+        .seq_num(1)
+        .client_request(request("v"));
+    state.log_pre_prepare(slot.pre_prepare());
+    let (result, _) = state.apply_commit(slot.pre_prepare().client_request, &slot.commit());
+    assert_eq!(result.slot.leader, 0);
+    assert_eq!(result.slot.view, 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn blames_are_corroborated_by_every_replica() {
+    let cluster = ClusterBuilder::new(4).seed(9).build();
+    let mut client = cluster.client();
+    assert!(client.put(Key::from("k"), Value::from("1")).await.is_some());
+
+    // replica 3 saw the primary sign two requests for the same slot, and vouches for it
+    let primary = MessageBuilder::new(cluster.node(0).keystore.clone(), 0).seq_num(9);
+    let evidence = Evidence::Equivocation(Box::new(EquivocationProof {
+        first: primary.clone().client_request(request("a")).pre_prepare(),
+        second: primary.client_request(request("b")).pre_prepare(),
+    }));
+    let blame = Blame::new_with_signature(&cluster.node(3).keystore, 3, evidence);
+    cluster
+        .node(1)
+        .receive(Some(3), Message::BlameMessage(blame))
+        .await
+        .unwrap();
+
+    // the view changes at once, and the replicas vouch for the evidence in turn
+    assert!(cluster.await_view(1).await);
+    for id in 0..4 {
+        let summary = cluster.node(id).evidence.summary();
+        assert_eq!(summary.evidence.len(), 1);
+        assert_eq!(summary.evidence[0].accused(), 0);
+        assert!(summary.blamers[&0].len() >= 3);
+    }
+    assert!(client.put(Key::from("k"), Value::from("2")).await.is_some());
+
+    // the new primary ordered the exclusion ahead of the request, so every replica passes
+    // replica 0 over from the view after the one it was ordered in
+    for id in 0..4 {
+        let excluded_from = cluster.node(id).evidence.summary().excluded_from;
+        assert_eq!(excluded_from, BTreeMap::from([(0, 2)]));
+    }
+}
@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::client::{ClientError, PbftClient};
+use pbft::config::Config;
+use pbft::features::{Capabilities, Feature, Features, PROTOCOL_VERSION};
+use pbft::messages::{ErrorCode, NodeStatus};
+use pbft::node::Node;
+use pbft::testing::ClusterBuilder;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, NodeId, Value};
+
+use tokio::sync::mpsc::channel;
+use tokio::sync::watch;
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7800 + id as u16))
+}
+
+/// Starts a node of a cluster at the `addr_of` addresses, which reports the capabilities
+/// in its status as the consensus engine we stand in for would
+async fn advertising_node(id: NodeId, capabilities: Capabilities) {
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let (tx_consensus, rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        id,
+        config,
+        MessageBuilder::generate(id).keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    let (tx_status, rx_status) = watch::channel(NodeStatus {
+        id,
+        capabilities,
+        ..NodeStatus::default()
+    });
+    node.inner.rx_status = rx_status;
+    tokio::spawn(async move {
+        let _tx_status = tx_status;
+        let _rx_consensus = rx_consensus;
+        node.spawn().await
+    });
+}
+
+#[test]
+fn features_keep_their_names_and_bits() {
+    for feature in Feature::ALL {
+        assert_eq!(feature.to_string().parse::<Feature>(), Ok(feature));
+    }
+    assert!("zstd".parse::<Feature>().is_err());
+
+    let features = Features::supported();
+    assert!(features.contains(Feature::ReadOnly));
+    assert!(!features.contains(Feature::Compression));
+    assert_eq!(
+        features.to_string(),
+        "batches, read-only, key-proofs, bulk-loads"
+    );
+
+    // features of later versions survive a round trip through this one
+    let later: Features = serde_json::from_str("4294967295").unwrap();
+    assert!(Feature::ALL
+        .into_iter()
+        .all(|feature| later.contains(feature)));
+    assert_eq!(serde_json::to_string(&later).unwrap(), "4294967295");
+}
+
+#[tokio::test]
+async fn clients_rely_on_features_a_quorum_supports() {
+    let without_proofs = Capabilities::new(
+        Features::supported()
+            .iter()
+            .filter(|feature| *feature != Feature::KeyProofs)
+            .collect(),
+    );
+    advertising_node(0, Capabilities::new(Features::supported())).await;
+    advertising_node(1, Capabilities::new(Features::supported())).await;
+    advertising_node(2, without_proofs).await;
+    // a replica of a version before the advertisement of capabilities
+    advertising_node(3, Capabilities::default()).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let client = PbftClient::new(&config, SocketAddr::from(([127, 0, 0, 1], 7810)));
+    // replicas the client has not heard from are assumed to support every feature
+    assert!(client.supports(Feature::KeyProofs));
+
+    let capabilities = client.negotiate().await;
+    assert_eq!(capabilities.len(), 4);
+    assert_eq!(capabilities[&0].protocol_version, PROTOCOL_VERSION);
+    assert_eq!(capabilities[&3].protocol_version, 0);
+    // only replica 3 lacks read-only requests, which leaves a quorum supporting them
+    assert!(client.supports(Feature::ReadOnly));
+    assert!(!client.supports(Feature::KeyProofs));
+    assert!(!client.supports(Feature::Compression));
+
+    let error = client.get_proof(Key::from("x")).await.unwrap_err();
+    assert!(matches!(
+        error,
+        ClientError::Unsupported {
+            feature: Feature::KeyProofs
+        }
+    ));
+    assert_eq!(error.code(), ErrorCode::Unsupported);
+}
+
+#[tokio::test(start_paused = true)]
+async fn replicas_advertise_their_features_in_responses() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .config(|config| config.features.remove(Feature::ReadOnly))
+        .build();
+    let mut client = cluster.client();
+    let response = client.put(Key::from("x"), Value::from("v")).await.unwrap();
+    assert_eq!(response.capabilities.protocol_version, PROTOCOL_VERSION);
+    assert!(response.capabilities.features.contains(Feature::Batches));
+    assert!(!response.capabilities.features.contains(Feature::ReadOnly));
+    assert!(cluster.await_commit(1).await);
+    assert_eq!(cluster.status(0).capabilities, response.capabilities);
+}
@@ -0,0 +1,81 @@
+use pbft::future_view::FutureViewBuffer;
+use pbft::messages::Message;
+use pbft::testkit::MessageBuilder;
+
+fn prepare(builder: &MessageBuilder, view: usize, seq_num: usize) -> Message {
+    Message::PrepareMessage(builder.clone().view(view).seq_num(seq_num).prepare())
+}
+
+fn seq_nums(messages: &[Message]) -> Vec<usize> {
+    messages
+        .iter()
+        .map(|message| match message {
+            Message::PrepareMessage(prepare) => prepare.seq_num,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[test]
+fn only_messages_within_the_window_are_buffered() {
+    let builder = MessageBuilder::generate(1);
+    let mut buffer = FutureViewBuffer::new(8, 2);
+
+    assert!(!buffer.push(3, prepare(&builder, 2, 1)));
+    assert!(!buffer.push(3, prepare(&builder, 3, 1)));
+    assert!(buffer.push(3, prepare(&builder, 4, 1)));
+    assert!(buffer.push(3, prepare(&builder, 5, 1)));
+    assert!(!buffer.push(3, prepare(&builder, 6, 1)));
+    assert!(!buffer.push(3, Message::ViewChangeMessage(builder.view_change())));
+    assert_eq!(buffer.len(), 2);
+
+    // a disabled buffer keeps nothing
+    let mut disabled = FutureViewBuffer::new(0, 2);
+    assert!(!disabled.push(3, prepare(&builder, 4, 1)));
+    assert!(disabled.is_empty());
+}
+
+#[test]
+fn drain_returns_the_messages_of_the_view_in_order() {
+    let builder = MessageBuilder::generate(1);
+    let mut buffer = FutureViewBuffer::new(8, 3);
+    buffer.push(0, prepare(&builder, 1, 1));
+    buffer.push(0, prepare(&builder, 2, 1));
+    buffer.push(0, prepare(&builder, 2, 2));
+    buffer.push(0, prepare(&builder, 3, 1));
+    buffer.push(0, prepare(&builder, 2, 3));
+
+    // messages for view 1 are outdated once we moved to view 2
+    assert_eq!(seq_nums(&buffer.drain(2)), vec![1, 2, 3]);
+    assert_eq!(buffer.len(), 1);
+    assert!(buffer.drain(2).is_empty());
+    assert_eq!(seq_nums(&buffer.drain(3)), vec![1]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn nearer_views_evict_the_furthest_view() {
+    let builder = MessageBuilder::generate(1);
+    let mut buffer = FutureViewBuffer::new(3, 2);
+    assert!(buffer.push(0, prepare(&builder, 2, 1)));
+    assert!(buffer.push(0, prepare(&builder, 2, 2)));
+    assert!(buffer.push(0, prepare(&builder, 1, 1)));
+
+    // the buffer is full, so messages for the furthest view are dropped
+    assert!(!buffer.push(0, prepare(&builder, 2, 3)));
+    // while a message for a nearer view evicts the latest message of the furthest view
+    assert!(buffer.push(0, prepare(&builder, 1, 2)));
+    assert_eq!(buffer.len(), 3);
+
+    assert_eq!(seq_nums(&buffer.drain(1)), vec![1, 2]);
+    assert_eq!(seq_nums(&buffer.drain(2)), vec![1]);
+
+    // once every message of the furthest view is evicted, the next view is the furthest
+    let mut buffer = FutureViewBuffer::new(2, 3);
+    buffer.push(0, prepare(&builder, 3, 1));
+    buffer.push(0, prepare(&builder, 2, 1));
+    assert!(buffer.push(0, prepare(&builder, 1, 1)));
+    assert!(buffer.push(0, prepare(&builder, 1, 2)));
+    assert_eq!(seq_nums(&buffer.drain(1)), vec![1, 2]);
+    assert!(buffer.is_empty());
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "grpc")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::grpc::proto::pbft_client::PbftClient;
+use pbft::grpc::proto::{DeleteRequest, GetRequest, KeyEvent, SetRequest, SubscribeRequest};
+use pbft::grpc::{GrpcFrontEnd, ERROR_CODE_METADATA};
+use pbft::messages::ErrorCode;
+use pbft::testing::ClusterBuilder;
+
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tonic::{Code, Streaming};
+
+async fn next_event(events: &mut Streaming<KeyEvent>) -> KeyEvent {
+    timeout(Duration::from_secs(5), events.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn calls_are_answered_once_the_replicas_agree() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .config(|config| config.max_total_keys = 1)
+        .build();
+    let grpc_addr = SocketAddr::from(([127, 0, 0, 1], 7820));
+    let listener = TcpListener::bind(grpc_addr).await.unwrap();
+    let front_end = GrpcFrontEnd::new(cluster.node(1).clone(), grpc_addr);
+    tokio::spawn(front_end.serve(listener));
+    let mut client = PbftClient::connect(format!("http://{}", grpc_addr))
+        .await
+        .unwrap();
+
+    let get = |key: &str| GetRequest {
+        key: key.as_bytes().to_vec(),
+    };
+    let read = client.get(get("k")).await.unwrap().into_inner();
+    assert_eq!(read.value, None);
+    let set = SetRequest {
+        key: b"k".to_vec(),
+        value: b"1".to_vec(),
+    };
+    let written = client.set(set).await.unwrap().into_inner();
+    assert!(written.seq_num > 0);
+    let read = client.get(get("k")).await.unwrap().into_inner();
+    assert_eq!(read.value, Some(b"1".to_vec()));
+
+    // rejections carry the error code
+    let set = SetRequest {
+        key: b"other".to_vec(),
+        value: b"1".to_vec(),
+    };
+    let status = client.set(set).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    let error_code = status.metadata().get(ERROR_CODE_METADATA).unwrap();
+    assert_eq!(
+        error_code.to_str().unwrap(),
+        ErrorCode::QuotaExceeded.code().to_string()
+    );
+
+    // the subscription starts with the value the key has, and follows the writes to it
+    let mut events = client
+        .subscribe(SubscribeRequest { key: b"k".to_vec() })
+        .await
+        .unwrap()
+        .into_inner();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.value, Some(b"1".to_vec()));
+    assert_eq!(event.version, written.seq_num);
+
+    let deleted = client
+        .delete(DeleteRequest { key: b"k".to_vec() })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(deleted.previous, Some(b"1".to_vec()));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.value, None);
+    assert_eq!(event.version, deleted.seq_num);
+    assert!(client
+        .get(get("k"))
+        .await
+        .unwrap()
+        .into_inner()
+        .value
+        .is_none());
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::messages::{ConsensusCommand, Identifier, Message};
+use pbft::node::Node;
+use pbft::testkit::MessageBuilder;
+use pbft::NodeId;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7940 + id as u16))
+}
+
+fn unpinned_addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7950 + id as u16))
+}
+
+/// Opens a connection to the node at the address and writes the messages to it,
+/// one after another
+async fn send(addr: SocketAddr, messages: &[Message]) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    for message in messages {
+        stream
+            .write_all(message.serialize().as_slice())
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn connections_are_authenticated_by_the_identifier_they_open_with() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let mut config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    config.peer_pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect::<HashMap<_, _>>();
+
+    let (tx_consensus, mut rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        0,
+        config,
+        builders[0].keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    tokio::spawn(async move { node.spawn().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let identifier = |id: NodeId, signer: NodeId| {
+        Message::IdentifierMessage(Identifier::new_with_signature(
+            builders[signer].keystore(),
+            id,
+            addr_of(id),
+        ))
+    };
+    let prepare = |id: NodeId, seq_num: usize| {
+        Message::PrepareMessage(builders[id].clone().seq_num(seq_num).prepare())
+    };
+
+    // without an identifier, the connection is not associated with a replica
+    send(addr_of(0), &[prepare(1, 1)]).await;
+    // node 1 claims to be node 2, whose key is configured
+    send(addr_of(0), &[identifier(2, 1), prepare(2, 2)]).await;
+    // node 1 passes on its own vote and the vote of node 2
+    send(addr_of(0), &[identifier(1, 1), prepare(1, 3)]).await;
+    send(addr_of(0), &[identifier(1, 1), prepare(2, 4)]).await;
+    // but it does not speak for node 2 otherwise
+    send(
+        addr_of(0),
+        &[
+            identifier(1, 1),
+            Message::ViewChangeMessage(builders[2].view_change()),
+        ],
+    )
+    .await;
+
+    let mut received = Vec::new();
+    while let Ok(Some(command)) =
+        tokio::time::timeout(Duration::from_millis(500), rx_consensus.recv()).await
+    {
+        if let ConsensusCommand::ProcessMessage(message) = command {
+            received.push((message.get_id(), message.get_seq_num()));
+        }
+    }
+    received.sort();
+    assert_eq!(received, vec![(Some(1), Some(3)), (Some(2), Some(4))]);
+}
+
+#[tokio::test]
+async fn the_first_key_announced_for_an_id_is_kept_without_a_configured_one() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let config = Config::new((0..4).map(|id| (id, unpinned_addr_of(id))).collect());
+
+    let (tx_consensus, mut rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        0,
+        config,
+        builders[0].keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    tokio::spawn(async move { node.spawn().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let identifier = |id: NodeId, signer: NodeId| {
+        Message::IdentifierMessage(Identifier::new_with_signature(
+            builders[signer].keystore(),
+            id,
+            unpinned_addr_of(id),
+        ))
+    };
+    let prepare = |id: NodeId, signer: NodeId, seq_num: usize| {
+        Message::PrepareMessage(builders[signer].clone().id(id).seq_num(seq_num).prepare())
+    };
+
+    send(unpinned_addr_of(0), &[identifier(1, 1), prepare(1, 1, 1)]).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // node 2 announces a key of its own for the id of node 1
+    send(unpinned_addr_of(0), &[identifier(1, 2), prepare(1, 2, 2)]).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // while node 1 carries on with the key it announced first
+    send(unpinned_addr_of(0), &[identifier(1, 1), prepare(1, 1, 3)]).await;
+
+    let mut received = Vec::new();
+    while let Ok(Some(command)) =
+        tokio::time::timeout(Duration::from_millis(500), rx_consensus.recv()).await
+    {
+        if let ConsensusCommand::ProcessMessage(message) = command {
+            received.push((message.get_id(), message.get_seq_num()));
+        }
+    }
+    received.sort();
+    assert_eq!(received, vec![(Some(1), Some(1)), (Some(1), Some(3))]);
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use pbft::config::Config;
+use pbft::messages::{BatchOp, ClientRequest, FailureReason, Operation};
+use pbft::state::State;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, Value};
+
+fn request(time_stamp: usize, key: &str, operation: Operation) -> ClientRequest {
+    RequestBuilder::new()
+        .time_stamp(time_stamp)
+        .key(Key::from(key))
+        .operation(operation)
+        .build()
+}
+
+fn state(configure: impl FnOnce(&mut Config)) -> State {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    configure(&mut config);
+    State::new(1, config)
+}
+
+fn apply(state: &mut State, seq_num: usize, request: ClientRequest) -> Option<FailureReason> {
+    let commit = MessageBuilder::generate(0)
+        .view(0)
+        .seq_num(seq_num)
+        .client_request(request.clone())
+        .commit();
+    state.apply_commit(Arc::new(request), &commit).0.reason
+}
+
+#[test]
+fn writes_over_quota_are_not_visible_in_the_history() {
+    let mut state = state(|config| config.max_total_keys = 1);
+    let set = |value: &str| Operation::Set(Value::from(value));
+    assert_eq!(apply(&mut state, 1, request(1, "a", set("1"))), None);
+    assert_eq!(
+        apply(&mut state, 2, request(2, "b", set("1"))),
+        Some(FailureReason::TotalKeyQuotaExceeded)
+    );
+    // a compare-and-swap which did not match wrote nothing either
+    let cas = Operation::Cas {
+        expected: Some(Value::from("0")),
+        new: Value::from("2"),
+    };
+    assert_eq!(
+        apply(&mut state, 3, request(3, "a", cas)),
+        Some(FailureReason::UnexpectedValue)
+    );
+    assert_eq!(apply(&mut state, 4, request(4, "a", set("3"))), None);
+
+    assert_eq!(state.get_at(&Key::from("b"), 2), Some(None));
+    assert_eq!(state.get_at(&Key::from("b"), 4), Some(None));
+    assert_eq!(
+        state.get_at(&Key::from("a"), 3),
+        Some(Some(Value::from("1")))
+    );
+    assert_eq!(
+        state.get_at(&Key::from("a"), 4),
+        Some(Some(Value::from("3")))
+    );
+    assert_eq!(state.get_at(&Key::from("a"), 5), None);
+}
+
+#[test]
+fn batches_rolled_back_are_not_visible_in_the_history() {
+    let mut state = state(|config| config.max_total_keys = 2);
+    let put = |key: &str, value: &str| BatchOp::Put {
+        key: Key::from(key),
+        value: Value::from(value),
+    };
+    let batch = |time_stamp, batch| ClientRequest {
+        batch,
+        ..request(time_stamp, "", Operation::Get)
+    };
+    assert_eq!(apply(&mut state, 1, batch(1, vec![put("a", "1")])), None);
+    // the third key exceeds the quota, so the writes before it are rolled back
+    assert_eq!(
+        apply(
+            &mut state,
+            2,
+            batch(2, vec![put("a", "2"), put("b", "2"), put("c", "2")])
+        ),
+        Some(FailureReason::TotalKeyQuotaExceeded)
+    );
+    assert_eq!(apply(&mut state, 3, batch(3, vec![put("b", "3")])), None);
+
+    assert_eq!(
+        state.get_at(&Key::from("a"), 2),
+        Some(Some(Value::from("1")))
+    );
+    assert_eq!(state.get_at(&Key::from("b"), 2), Some(None));
+    assert_eq!(
+        state.get_at(&Key::from("b"), 3),
+        Some(Some(Value::from("3")))
+    );
+    assert_eq!(state.get_at(&Key::from("c"), 3), Some(None));
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "http")]
+
+use std::net::SocketAddr;
+
+use pbft::http::HttpFrontEnd;
+use pbft::messages::{ErrorCode, NodeStatus};
+use pbft::testing::ClusterBuilder;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Sends the request and returns the status code, headers and body of the answer
+async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let code = head[9..12].parse().unwrap();
+    (code, head.to_lowercase(), body.to_string())
+}
+
+#[tokio::test]
+async fn keys_are_read_and_written_once_the_replicas_agree() {
+    let cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .config(|config| config.max_total_keys = 1)
+        .build();
+    let http_addr = SocketAddr::from(([127, 0, 0, 1], 7830));
+    let listener = TcpListener::bind(http_addr).await.unwrap();
+    let front_end = HttpFrontEnd::new(cluster.node(1).clone(), http_addr);
+    tokio::spawn(front_end.serve(listener));
+
+    let (code, _, _) = request(http_addr, "GET", "/kv/k", "").await;
+    assert_eq!(code, 404);
+    let (code, head, _) = request(http_addr, "PUT", "/kv/k", "1").await;
+    assert_eq!(code, 204);
+    assert!(head.contains("pbft-seq-num: 1"));
+    let (code, head, body) = request(http_addr, "GET", "/kv/k", "").await;
+    assert_eq!(code, 200);
+    assert_eq!(body, "1");
+    assert!(head.contains("pbft-seq-num:"));
+
+    // rejections carry the error code
+    let (code, head, _) = request(http_addr, "PUT", "/kv/other", "1").await;
+    assert_eq!(code, 429);
+    assert!(head.contains(&format!(
+        "pbft-error-code: {}",
+        ErrorCode::QuotaExceeded.code()
+    )));
+
+    let (code, _, body) = request(http_addr, "GET", "/status", "").await;
+    assert_eq!(code, 200);
+    let status: NodeStatus = serde_json::from_str(&body).unwrap();
+    assert_eq!(status.id, 1);
+    assert!(status.last_seq_num_committed >= 1);
+}
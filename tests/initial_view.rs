@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use pbft::config::Config;
+use pbft::state::State;
+use pbft::testkit::MessageBuilder;
+
+#[test]
+fn replicas_start_in_the_configured_view() {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.initial_view = 6;
+    let state = State::new(1, config);
+
+    assert_eq!(state.view, 6);
+    assert_eq!(state.current_leader(), 2);
+    let pre_prepare = |id, view| {
+        MessageBuilder::generate(id)
+            .view(view)
+            .seq_num(1)
+            .pre_prepare()
+    };
+    assert!(state.should_accept_pre_prepare(&pre_prepare(2, 6)));
+    // the primary of view 0 is not the primary
+    assert!(!state.should_accept_pre_prepare(&pre_prepare(0, 0)));
+}
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+
+use pbft::{Key, Value};
+
+#[test]
+fn keys_round_trip_through_their_text_form() {
+    let keys = [
+        Key::from("x"),
+        Key::from(vec![0x00, 0xff, 0x10]),
+        // text which looks like an encoded key is itself encoded
+        Key::from("hex:00"),
+        Key::default(),
+    ];
+    for key in keys.iter() {
+        assert_eq!(key.to_string().parse::<Key>().unwrap(), *key);
+    }
+    assert_eq!(Key::from("x").to_string(), "x");
+    assert_eq!(Key::from(vec![0x00, 0xff]).to_string(), "hex:00ff");
+    assert_eq!(Key::from("hex:00").to_string(), "hex:6865783a3030");
+    assert!("hex:0g".parse::<Key>().is_err());
+}
+
+#[test]
+fn binary_keys_can_key_json_maps() {
+    let mut store = BTreeMap::new();
+    store.insert(Key::from("b"), 2);
+    store.insert(Key::from(vec![0xff]), 3);
+    store.insert(Key::from("a"), 1);
+
+    let encoded = serde_json::to_string(&store).unwrap();
+    assert_eq!(encoded, r#"{"a":1,"b":2,"hex:ff":3}"#);
+    let decoded: BTreeMap<Key, u32> = serde_json::from_str(&encoded).unwrap();
+    assert_eq!(decoded, store);
+
+    // keys are ordered by their bytes
+    let keys: Vec<&Key> = decoded.keys().collect();
+    assert_eq!(
+        keys,
+        vec![&Key::from("a"), &Key::from("b"), &Key::from(vec![0xff])]
+    );
+}
+
+#[test]
+fn binary_values_round_trip_through_json() {
+    let values = [
+        Value::from("42"),
+        Value::from(vec![0xde, 0xad, 0xbe, 0xef]),
+        Value::default(),
+    ];
+    for value in values.iter() {
+        let encoded = serde_json::to_string(value).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&encoded).unwrap(), *value);
+    }
+    assert_eq!(
+        serde_json::to_string(&Value::from(vec![0x00, 0xff])).unwrap(),
+        r#""hex:00ff""#
+    );
+    assert!(serde_json::from_str::<Value>(r#""hex:0g""#).is_err());
+}
@@ -0,0 +1,90 @@
+use ed25519_dalek::{Keypair, PublicKey, Signature};
+use rand::rngs::OsRng;
+
+use pbft::crypto::{self, DigestAlgorithm, SigningInput};
+use pbft::keys::{KeyError, KeyProvider};
+use pbft::keystore::{self, Keystore};
+use pbft::messages::{CheckPoint, Identifier};
+
+#[test]
+fn keystores_round_trip_and_rotate_with_their_passphrase() {
+    let path = std::env::temp_dir().join(format!("pbft-keystore-{}.json", std::process::id()));
+    let keystore = Keystore::generate();
+    keystore.save(&path, "correct horse").unwrap();
+
+    let loaded = Keystore::load(&path, "correct horse").unwrap();
+    assert_eq!(loaded.public_key(), keystore.public_key());
+    let identifier = Identifier::new_with_signature(&loaded, 1, "127.0.0.1:7000".parse().unwrap());
+    assert_eq!(identifier.verified_pub_key(), Some(keystore.public_key()));
+    assert_eq!(
+        Keystore::load(&path, "battery staple").err(),
+        Some(KeyError::WrongPassphrase)
+    );
+
+    // the key is stored encrypted, and altering it is detected
+    let encoded = std::fs::read_to_string(&path).unwrap();
+    let secret_hex = pbft::keys::encode_hex(keystore.keypair().unwrap().secret.as_bytes());
+    assert!(!encoded.contains(&secret_hex));
+    let mut file: serde_json::Value = serde_json::from_str(&encoded).unwrap();
+    let encrypted = file["encrypted_secret_key"].as_str().unwrap();
+    let flipped = if encrypted.starts_with('0') { "1" } else { "0" };
+    file["encrypted_secret_key"] =
+        serde_json::Value::from(format!("{}{}", flipped, &encrypted[1..]));
+    std::fs::write(&path, file.to_string()).unwrap();
+    assert_eq!(
+        Keystore::load(&path, "correct horse").err(),
+        Some(KeyError::WrongPassphrase)
+    );
+    std::fs::write(&path, encoded).unwrap();
+
+    // rotation needs the passphrase, and keeps the previous key next to the new one
+    assert!(Keystore::rotate(&path, "battery staple").is_err());
+    let rotated = Keystore::rotate(&path, "correct horse").unwrap();
+    assert_ne!(rotated.public_key(), keystore.public_key());
+    let current = Keystore::load(&path, "correct horse").unwrap();
+    assert_eq!(current.public_key(), rotated.public_key());
+    let previous = Keystore::load(&keystore::previous_path(&path), "correct horse").unwrap();
+    assert_eq!(previous.public_key(), keystore.public_key());
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(keystore::previous_path(&path)).unwrap();
+}
+
+/// Provider which signs with a key it never hands out, like a key management service
+struct RemoteKeyProvider {
+    keypair: Keypair,
+}
+
+impl KeyProvider for RemoteKeyProvider {
+    fn keypair_bytes(&self) -> Result<Vec<u8>, KeyError> {
+        Err(KeyError::Unexportable)
+    }
+
+    fn public_key(&self) -> Result<PublicKey, KeyError> {
+        Ok(self.keypair.public)
+    }
+
+    fn sign(
+        &self,
+        input: &SigningInput,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Signature, KeyError> {
+        Ok(crypto::untagged_signature(&self.keypair, input, algorithm))
+    }
+}
+
+#[test]
+fn messages_are_signed_by_providers_which_keep_the_secret_key() {
+    let keypair = Keypair::generate(&mut OsRng);
+    let public_key = keypair.public;
+    let keystore = Keystore::from_provider(Box::new(RemoteKeyProvider { keypair })).unwrap();
+    assert!(keystore.keypair().is_none());
+    assert_eq!(keystore.public_key(), public_key);
+    assert_eq!(keystore.keypair_bytes().err(), Some(KeyError::Unexportable));
+
+    let identifier =
+        Identifier::new_with_signature(&keystore, 1, "127.0.0.1:7000".parse().unwrap());
+    assert_eq!(identifier.verified_pub_key(), Some(public_key));
+    let checkpoint = CheckPoint::new_with_signature(&keystore, 1, 0, 1, 0, Vec::new());
+    assert!(checkpoint.is_properly_signed_by(&public_key));
+}
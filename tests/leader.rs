@@ -0,0 +1,110 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::codec::MessageReader;
+use pbft::config::{Config, ConfigError, ConfigFile};
+use pbft::messages::{Leader, Message, NodeStatus, WatchLeader};
+use pbft::node::Node;
+use pbft::state::State;
+use pbft::testkit::MessageBuilder;
+use pbft::NodeId;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7980 + id as u16))
+}
+
+async fn next_leader(reader: &mut MessageReader<TcpStream>) -> Leader {
+    match timeout(Duration::from_secs(2), reader.read()).await {
+        Ok(Ok(Some(Message::LeaderMessage(leader)))) => leader,
+        other => panic!("expected a leader announcement, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn leadership_changes_are_announced_to_watchers() {
+    let builder = MessageBuilder::generate(2);
+    let config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    let (tx_consensus, _rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        2,
+        config,
+        builder.keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    // the view is reported by the consensus engine, which we stand in for
+    let (tx_status, rx_status) = watch::channel(NodeStatus::default());
+    node.inner.rx_status = rx_status;
+    tokio::spawn(async move { node.spawn().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut stream = TcpStream::connect(addr_of(2)).await.unwrap();
+    stream
+        .write_all(
+            Message::WatchLeaderMessage(WatchLeader {})
+                .serialize()
+                .as_slice(),
+        )
+        .await
+        .unwrap();
+    let mut reader = MessageReader::new(stream);
+    let announced = |view: usize, primary: NodeId| Leader {
+        id: 2,
+        view,
+        primary,
+        primary_addr: addr_of(primary),
+    };
+
+    assert_eq!(next_leader(&mut reader).await, announced(0, 0));
+    // progress within the view is not announced, a new view is
+    tx_status.send_modify(|status| status.last_seq_num_committed = 3);
+    tx_status.send_modify(|status| status.view = 5);
+    assert_eq!(next_leader(&mut reader).await, announced(5, 1));
+}
+
+#[test]
+fn weighted_rotation_spreads_the_turns_of_each_node() {
+    let load = |weights: [&str; 4]| {
+        let nodes: Vec<String> = weights
+            .iter()
+            .enumerate()
+            .map(|(id, weight)| {
+                format!(
+                    r#"{{ "id": {}, "addr": "127.0.0.1:{}"{} }}"#,
+                    id,
+                    7000 + id,
+                    weight
+                )
+            })
+            .collect();
+        serde_json::from_str::<ConfigFile>(&format!(r#"{{ "nodes": [{}] }}"#, nodes.join(",")))
+            .unwrap()
+            .into_config()
+    };
+
+    // node 1 is primary twice as often as nodes 0 and 2, and node 3 never is
+    let config = load(["", r#", "leader_weight": 2"#, "", r#", "leader_weight": 0"#]).unwrap();
+    let state = State::new(3, config);
+    let leaders: Vec<NodeId> = (0..8).map(|view| state.get_leader_for_view(view)).collect();
+    assert_eq!(leaders, vec![1, 0, 2, 1, 1, 0, 2, 1]);
+
+    // without weights the nodes take turns
+    let state = State::new(3, load(["", "", "", ""]).unwrap());
+    let leaders: Vec<NodeId> = (0..5).map(|view| state.get_leader_for_view(view)).collect();
+    assert_eq!(leaders, vec![0, 1, 2, 3, 0]);
+
+    // a single faulty primary would stall the cluster
+    let zero = r#", "leader_weight": 0"#;
+    assert!(matches!(
+        load(["", zero, zero, zero]),
+        Err(ConfigError::Invalid(_))
+    ));
+}
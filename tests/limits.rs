@@ -0,0 +1,116 @@
+use bytes::BytesMut;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use serde_json::json;
+use tokio_util::codec::{Decoder, Encoder};
+
+use pbft::codec::{CodecError, MessageCodec};
+use pbft::limits::{
+    MAX_BATCH_OPS, MAX_CHECKPOINT_PROOF, MAX_SIGNATURE_LEN, MAX_SUBSEQUENT_PREPARES,
+};
+use pbft::messages::{BatchOp, CheckPoint, Message};
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+/// Lengths at the limit and on either side of it, and random lengths below and above it
+fn lengths(max: usize, rng: &mut ChaChaRng) -> Vec<usize> {
+    let mut lengths = vec![0, max - 1, max, max + 1];
+    for _ in 0..2 {
+        lengths.push(rng.gen_range(0, max));
+        lengths.push(rng.gen_range(max + 1, 2 * max));
+    }
+    lengths
+}
+
+/// Checks that a message whose field holds `len` elements is encoded, and decoded when the
+/// sender encodes it anyway, exactly when `len` is within the limit
+fn check_limit(
+    max: usize,
+    field: &str,
+    message: impl Fn(usize) -> Message,
+    elements: impl Fn(usize) -> serde_json::Value,
+) {
+    let mut rng = ChaChaRng::seed_from_u64(max as u64);
+    for len in lengths(max, &mut rng) {
+        let within = len <= max;
+        let encoded = MessageCodec::default().encode(&message(len), &mut BytesMut::new());
+        assert_eq!(encoded.is_ok(), within, "encoding {} {}", len, field);
+
+        // the payload a sender which ignores the limit would send
+        let mut payload = serde_json::to_value(message(0)).unwrap();
+        let (_, fields) = payload.as_object_mut().unwrap().iter_mut().next().unwrap();
+        fields[field] = elements(len);
+        let payload = serde_json::to_vec(&payload).unwrap();
+        let mut frame = BytesMut::new();
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        match MessageCodec::default().decode(&mut frame) {
+            Ok(Some(_)) => assert!(within, "decoded {} {}", len, field),
+            Err(CodecError::Malformed(frame)) => {
+                assert!(!within, "could not decode {} {}: {}", len, field, frame);
+                assert!(frame.reason.contains("more than"), "{}", frame.reason);
+            }
+            res => panic!("decoding {} {}: {:?}", len, field, res),
+        }
+    }
+}
+
+#[test]
+fn fields_are_bounded_at_their_limits() {
+    let builder = MessageBuilder::generate(1).seq_num(1);
+
+    check_limit(
+        MAX_SIGNATURE_LEN,
+        "signature",
+        |len| {
+            let mut prepare = builder.prepare();
+            prepare.signature = vec![7; len];
+            Message::PrepareMessage(prepare)
+        },
+        |len| json!(vec![7; len]),
+    );
+
+    let op = |index: usize| BatchOp::Put {
+        key: Key::from(format!("k{}", index).as_str()),
+        value: Value::from("v"),
+    };
+    check_limit(
+        MAX_BATCH_OPS,
+        "batch",
+        |len| {
+            let mut request = (*builder.pre_prepare().client_request).clone();
+            request.batch = (0..len).map(op).collect();
+            Message::ClientRequestMessage(request)
+        },
+        |len| json!((0..len).map(op).collect::<Vec<_>>()),
+    );
+
+    let checkpoint = CheckPoint::new_with_signature(builder.keystore(), 1, 0, 10, 0, Vec::new());
+    check_limit(
+        MAX_CHECKPOINT_PROOF,
+        "checkpoint_proof",
+        |len| {
+            let mut view_change = builder.view_change();
+            view_change.checkpoint_proof = vec![checkpoint.clone(); len];
+            Message::ViewChangeMessage(view_change)
+        },
+        |len| json!(vec![checkpoint.clone(); len]),
+    );
+
+    let prepared = (builder.pre_prepare(), vec![builder.prepare()]);
+    let subsequent_prepares = |len: usize| {
+        (1..=len)
+            .map(|seq_num| (seq_num, prepared.clone()))
+            .collect()
+    };
+    check_limit(
+        MAX_SUBSEQUENT_PREPARES,
+        "subsequent_prepares",
+        |len| {
+            let mut view_change = builder.view_change();
+            view_change.subsequent_prepares = subsequent_prepares(len);
+            Message::ViewChangeMessage(view_change)
+        },
+        |len| json!(subsequent_prepares(len)),
+    );
+}
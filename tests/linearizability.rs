@@ -0,0 +1,94 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::linearizability::{self, Entry, History, Response};
+use pbft::messages::Operation;
+use pbft::sim::{LinkConfig, Network, Simulation};
+use pbft::{Key, Value};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+const NUM_NODES: usize = 4;
+const NUM_CLIENTS: u8 = 3;
+const NUM_OPERATIONS: usize = 12;
+
+fn client_addr(index: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 1, index + 1], 7000))
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrent_clients_see_a_linearizable_store_through_a_partition() {
+    let config = Config::new(
+        (0..NUM_NODES)
+            .map(|id| (id, SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))))
+            .collect(),
+    );
+    let links = LinkConfig {
+        drop_rate: 0.02,
+        ..LinkConfig::default()
+    };
+    let sim = Simulation::start(config, Network::new(21, links));
+    let history = History::new();
+
+    let mut clients = tokio::task::JoinSet::new();
+    for index in 0..NUM_CLIENTS {
+        let mut client = sim.client(client_addr(index)).with_history(&history);
+        let mut rng = ChaCha20Rng::seed_from_u64(index as u64);
+        clients.spawn(async move {
+            for _ in 0..NUM_OPERATIONS {
+                let key = Key::from(format!("k{}", rng.gen_range(0, 2)));
+                let value = Value::from(rng.gen_range(0, 4).to_string());
+                match rng.gen_range(0, 4) {
+                    0 => client.get(key).await,
+                    1 => client.put(key, value).await,
+                    2 => client.delete(key).await,
+                    _ => client.cas(key, Some(Value::from("1")), value).await,
+                };
+            }
+        });
+    }
+    // the primary is cut off for a while, so some operations span a view change
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    sim.network.partition(&[0]);
+    tokio::time::sleep(Duration::from_secs(20)).await;
+    sim.network.heal();
+    while let Some(joined) = clients.join_next().await {
+        joined.unwrap();
+    }
+
+    let entries = history.entries();
+    assert_eq!(entries.len(), NUM_CLIENTS as usize * NUM_OPERATIONS);
+    let completed = entries.iter().filter(|entry| entry.response.is_some());
+    assert!(completed.count() > NUM_OPERATIONS);
+    if let Err(counterexample) = history.check() {
+        panic!("{}", counterexample);
+    }
+
+    // a read missing an acknowledged write is caught, and the concurrent
+    // operations which do not matter are left out of the counterexample
+    let entry = |client: u8, operation: Operation, call: usize, value: Option<&str>| Entry {
+        client: client_addr(client),
+        key: Key::from("k"),
+        operation,
+        call,
+        response: Some(Response {
+            at: call + 1,
+            value: value.map(Value::from),
+            previous: None,
+            reason: None,
+        }),
+    };
+    let stale = vec![
+        entry(0, Operation::Set(Value::from("1")), 0, None),
+        entry(2, Operation::Get, 0, None),
+        entry(1, Operation::Get, 2, None),
+    ];
+    let counterexample = linearizability::check(&stale).unwrap_err();
+    assert_eq!(counterexample.key, Key::from("k"));
+    assert_eq!(
+        counterexample.entries,
+        vec![stale[0].clone(), stale[2].clone()]
+    );
+}
@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use pbft::config::Config;
+use pbft::logging;
+use pbft::testing::ClusterBuilder;
+use pbft::{Key, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+fn log_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("pbft-{}-{}.log", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn nodes_of_one_process_log_to_their_own_files() {
+    let paths = [log_path("logging-0"), log_path("logging-1")];
+    let logs: Vec<_> = paths
+        .iter()
+        .enumerate()
+        .map(|(id, path)| {
+            let config = Config {
+                log_file: Some(path.clone()),
+                ..Config::default()
+            };
+            logging::init(id, &config).unwrap()
+        })
+        .collect();
+
+    for log in logs.iter() {
+        let id = log.id();
+        log.scope(async move {
+            log::info!("started {}", id);
+            // tasks spawned by the node log to its file as well
+            logging::spawn(async move { log::info!("spawned by {}", id) })
+                .await
+                .unwrap();
+        })
+        .await;
+    }
+
+    for (id, path) in paths.iter().enumerate() {
+        let contents = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert!(lines[0].contains(&format!("node {}", id)));
+        assert!(lines[0].ends_with(&format!("started {}", id)));
+        assert!(lines[1].ends_with(&format!("spawned by {}", id)));
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn a_log_file_which_cannot_be_opened_is_an_error() {
+    let config = Config {
+        log_file: Some(log_path("logging-missing").join("node.log")),
+        ..Config::default()
+    };
+    assert!(logging::init(0, &config).is_err());
+}
+
+/// Records the view and sequence number of every instance span opened
+#[derive(Clone, Default)]
+struct InstanceSpans(Arc<Mutex<Vec<(u64, u64)>>>);
+
+#[derive(Default)]
+struct InstanceFields {
+    view: u64,
+    seq_num: u64,
+}
+
+impl Visit for InstanceFields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "view" => self.view = value,
+            "seq_num" => self.seq_num = value,
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+impl Subscriber for InstanceSpans {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "pbft::instance"
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = InstanceFields::default();
+        span.record(&mut fields);
+        let mut spans = self.0.lock().unwrap();
+        spans.push((fields.view, fields.seq_num));
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+    fn event(&self, _event: &Event<'_>) {}
+    fn enter(&self, _span: &Id) {}
+    fn exit(&self, _span: &Id) {}
+}
+
+#[tokio::test(start_paused = true)]
+async fn messages_of_a_protocol_instance_are_handled_in_its_span() {
+    let spans = InstanceSpans::default();
+    // the simulated cluster runs on the thread of the test
+    let _guard = tracing::subscriber::set_default(spans.clone());
+
+    let cluster = ClusterBuilder::new(4).seed(3).build();
+    let mut client = cluster.client();
+    assert!(client.put(Key::from("k"), Value::from("v")).await.is_some());
+    assert!(cluster.await_commit(1).await);
+
+    let spans = spans.0.lock().unwrap();
+    // each replica handles the pre-prepare (bar the primary), and a prepare and a commit of each other replica
+    let instance = spans.iter().filter(|span| **span == (0, 1)).count();
+    assert!(instance >= 3 + 4 * 3, "{:?}", spans);
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::keys::encode_hex;
+use pbft::membership::{MembershipChange, Reconfiguration};
+use pbft::messages::{ClientRequest, FailureReason, Operation};
+use pbft::registry::{self, ClusterRegistry};
+use pbft::state::State;
+use pbft::testing::{replica_addr, ClusterBuilder};
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, NodeId, Value};
+
+use tokio::time::sleep;
+
+fn add_node(
+    root: &MessageBuilder,
+    epoch: usize,
+    id: NodeId,
+    joiner: &MessageBuilder,
+) -> Reconfiguration {
+    let change = MembershipChange::AddNode {
+        id,
+        addr: replica_addr(id),
+        pub_key: encode_hex(joiner.public_key().as_bytes()),
+    };
+    Reconfiguration::new_with_signature(root.keystore(), epoch, change)
+}
+
+#[test]
+fn reconfigurations_move_the_registry_to_the_next_epoch() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let root = MessageBuilder::generate(9);
+    let mut config = Config::new((0..4).map(|id| (id, replica_addr(id))).collect());
+    config.peer_pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect::<HashMap<_, _>>();
+    config.root_pub_key = Some(root.public_key());
+    let mut state = State::new(1, config.clone());
+    let genesis = ClusterRegistry::genesis(&config);
+
+    let mut seq_num = 0;
+    let mut apply = |state: &mut State, reconfiguration: &Reconfiguration| {
+        seq_num += 1;
+        let request = ClientRequest {
+            respond_addr: SocketAddr::from(([127, 0, 0, 1], 7100)),
+            time_stamp: seq_num,
+            key: registry::registry_key(),
+            operation: Operation::Set(reconfiguration.encode()),
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        };
+        let commit = builders[0]
+            .clone()
+            .seq_num(seq_num)
+            .client_request(request.clone())
+            .commit();
+        state.apply_commit(Arc::new(request), &commit).0.reason
+    };
+
+    // reconfigurations must be signed by the root key and follow the current epoch
+    let joiner = MessageBuilder::generate(4);
+    let forged = add_node(&builders[0], 1, 4, &joiner);
+    assert_eq!(apply(&mut state, &forged), Some(FailureReason::ReservedKey));
+    assert!(apply(&mut state, &add_node(&root, 2, 4, &joiner)).is_some());
+    let removal = Reconfiguration::new_with_signature(
+        root.keystore(),
+        1,
+        MembershipChange::RemoveNode { id: 7 },
+    );
+    assert!(apply(&mut state, &removal).is_some());
+    assert_eq!(state.pending_reconfiguration, None);
+
+    // a valid one waits for the checkpoint at its sequence number before it takes effect
+    let add = add_node(&root, 1, 4, &joiner);
+    assert_eq!(apply(&mut state, &add), None);
+    assert_eq!(state.pending_reconfiguration, Some(4));
+    assert!(!state.adopt_registry_epoch());
+    let next = genesis.reconfigured(&add).unwrap();
+    assert_eq!((next.epoch, next.version), (1, 1));
+    assert_eq!(next.members.len(), 5);
+    assert_eq!(next.num_faulty, 1);
+    assert_eq!(
+        state.store.get(&registry::registry_key()),
+        Some(&next.encode())
+    );
+
+    state.last_stable_seq_num = 4;
+    assert!(state.adopt_registry_epoch());
+    assert_eq!(state.config.epoch, 1);
+    assert_eq!(state.config.num_nodes, 5);
+    assert_eq!(state.config.peer_addrs.get(&4), Some(&replica_addr(4)));
+    assert_eq!(state.pending_reconfiguration, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn nodes_join_and_leave_a_running_cluster() {
+    let root = MessageBuilder::generate(9);
+    let mut cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .config(|config| config.root_pub_key = Some(root.public_key()))
+        .build();
+    let mut client = cluster.client();
+    assert!(client.put(Key::from("k"), Value::from("1")).await.is_some());
+
+    // the members commit the reconfiguration and switch once it is covered by a checkpoint.
+    // The new replica fetches the state from them
+    let genesis = ClusterRegistry::genesis(cluster.config());
+    let joiner = MessageBuilder::generate(4);
+    let add = add_node(&root, 1, 4, &joiner);
+    let response = client.reconfigure(&add).await.unwrap();
+    assert_eq!(response.reason, None);
+    let next = genesis.reconfigured(&add).unwrap();
+    cluster.join_node(4, joiner.keystore().clone(), &next);
+    assert!(cluster.await_epoch(1).await);
+
+    let mut client = cluster.client();
+    assert!(client.put(Key::from("k"), Value::from("2")).await.is_some());
+    let mut waited = Duration::ZERO;
+    while cluster.status(4).last_seq_num_committed < 3 && waited < Duration::from_secs(30) {
+        sleep(Duration::from_millis(100)).await;
+        waited += Duration::from_millis(100);
+    }
+    assert_eq!(cluster.status(4).epoch, 1);
+    assert!(cluster.status(4).last_seq_num_committed >= 3);
+
+    // once replica 0 left, the cluster of four goes on without it and a crashed member
+    let removal = Reconfiguration::new_with_signature(
+        root.keystore(),
+        2,
+        MembershipChange::RemoveNode { id: 0 },
+    );
+    let response = client.reconfigure(&removal).await.unwrap();
+    assert_eq!(response.reason, None);
+    assert!(cluster.await_epoch(2).await);
+    cluster.kill_node(0);
+    cluster.kill_node(1);
+    assert!(client.put(Key::from("k"), Value::from("3")).await.is_some());
+}
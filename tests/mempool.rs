@@ -0,0 +1,78 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use pbft::mempool::{Admission, Mempool, Priority};
+use pbft::messages::ClientRequest;
+use pbft::testkit::RequestBuilder;
+
+fn request(time_stamp: usize) -> ClientRequest {
+    RequestBuilder::new()
+        .time_stamp(time_stamp)
+        .set(&time_stamp.to_string())
+        .build()
+}
+
+fn pop_time_stamp(mempool: &mut Mempool) -> Option<usize> {
+    mempool.pop().map(|pending| pending.request.time_stamp)
+}
+
+#[test]
+fn requests_are_proposed_by_priority_then_arrival() {
+    let mut mempool = Mempool::new(0, Duration::ZERO);
+    assert_eq!(
+        mempool.insert(request(1), Priority::Normal),
+        Admission::Admitted
+    );
+    assert_eq!(
+        mempool.insert(request(2), Priority::Normal),
+        Admission::Admitted
+    );
+    assert_eq!(
+        mempool.insert(request(3), Priority::Reissued),
+        Admission::Admitted
+    );
+    assert_eq!(
+        mempool.insert(request(1), Priority::Normal),
+        Admission::Duplicate
+    );
+    assert_eq!(mempool.stats().pending, 3);
+
+    assert_eq!(pop_time_stamp(&mut mempool), Some(3));
+    assert_eq!(pop_time_stamp(&mut mempool), Some(1));
+    assert_eq!(pop_time_stamp(&mut mempool), Some(2));
+    assert_eq!(pop_time_stamp(&mut mempool), None);
+
+    let stats = mempool.stats();
+    assert_eq!(
+        (stats.admitted, stats.proposed, stats.duplicates),
+        (3, 3, 1)
+    );
+    assert_eq!((stats.pending, stats.pending_bytes), (0, 0));
+}
+
+#[test]
+fn full_mempools_evict_lower_priorities_and_expire_old_requests() {
+    let mut mempool = Mempool::new(2, Duration::ZERO);
+    mempool.insert(request(1), Priority::Normal);
+    mempool.insert(request(2), Priority::Normal);
+    assert_eq!(
+        mempool.insert(request(3), Priority::Normal),
+        Admission::Full
+    );
+    // the most recent request of the lowest priority makes room
+    assert_eq!(
+        mempool.insert(request(4), Priority::Reissued),
+        Admission::Admitted
+    );
+    assert_eq!(pop_time_stamp(&mut mempool), Some(4));
+    assert_eq!(pop_time_stamp(&mut mempool), Some(1));
+    assert_eq!((mempool.stats().rejected, mempool.stats().evicted), (1, 1));
+
+    let mut mempool = Mempool::new(0, Duration::from_millis(20));
+    mempool.insert(request(1), Priority::Normal);
+    sleep(Duration::from_millis(40));
+    mempool.insert(request(2), Priority::Normal);
+    assert_eq!(pop_time_stamp(&mut mempool), Some(2));
+    assert!(mempool.is_empty());
+    assert_eq!(mempool.stats().expired, 1);
+}
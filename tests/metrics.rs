@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use pbft::messages::{ClientResponse, Message, NodeStatus};
+use pbft::metrics::{self, CommitRate, ConsensusMetrics};
+use pbft::observer::{Observer, QuorumKind};
+use pbft::state::SlotMeta;
+use pbft::testkit::MessageBuilder;
+use pbft::Key;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn scrape(stream: &mut TcpStream, path: &str) -> String {
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn phases_are_timed_and_served_to_prometheus() {
+    let metrics = Arc::new(ConsensusMetrics::default());
+    let builder = MessageBuilder::generate(1).seq_num(3);
+    let pre_prepare = builder.clone().id(0).pre_prepare();
+    let response = ClientResponse {
+        id: 1,
+        time_stamp: pre_prepare.client_request.time_stamp,
+        key: Key::from("x"),
+        value: None,
+        success: true,
+        reason: None,
+        code: None,
+        previous: None,
+        view: 0,
+        seq_num: 0,
+        results: Vec::new(),
+        bulk: None,
+        capabilities: Default::default(),
+        signature: Vec::new(),
+    };
+
+    tokio::time::pause();
+    metrics.on_message_in(&Message::PrePrepareMessage(pre_prepare.clone()));
+    tokio::time::advance(Duration::from_millis(20)).await;
+    metrics.on_message_in(&Message::PrepareMessage(builder.prepare()));
+    metrics.on_quorum(QuorumKind::Prepare, 0, 3, &[0, 1, 2]);
+    // the pre-prepare sent again does not restart the sequence number
+    metrics.on_message_in(&Message::PrePrepareMessage(pre_prepare.clone()));
+    tokio::time::advance(Duration::from_millis(200)).await;
+    metrics.on_quorum(QuorumKind::Commit, 0, 3, &[0, 1, 2]);
+    let slot = SlotMeta {
+        seq_num: 3,
+        view: 0,
+        leader: 0,
+        time_stamp: pre_prepare.client_request.time_stamp,
+    };
+    metrics.on_commit(&slot, &pre_prepare.client_request, &response);
+    tokio::time::resume();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let status = || NodeStatus {
+        view: 2,
+        last_seq_num_committed: 3,
+        ..NodeStatus::default()
+    };
+    tokio::spawn(metrics::serve(listener, metrics, status));
+
+    let body = scrape(&mut TcpStream::connect(addr).await.unwrap(), "/metrics").await;
+    assert!(body.starts_with("HTTP/1.1 200 OK"));
+    for line in [
+        "pbft_view 2",
+        "pbft_last_committed_seq_num 3",
+        "pbft_prepares_received_total 1",
+        "pbft_commit_quorums_total 1",
+        "pbft_requests_in_flight 0",
+        "pbft_phase_latency_seconds_bucket{phase=\"prepare\",le=\"0.01\"} 0",
+        "pbft_phase_latency_seconds_bucket{phase=\"prepare\",le=\"0.025\"} 1",
+        "pbft_phase_latency_seconds_bucket{phase=\"commit\",le=\"0.1\"} 0",
+        "pbft_phase_latency_seconds_bucket{phase=\"commit\",le=\"0.25\"} 1",
+        "pbft_phase_latency_seconds_count{phase=\"execute\"} 1",
+    ] {
+        assert!(body.lines().any(|body_line| body_line == line), "{}", line);
+    }
+
+    let not_found = scrape(&mut TcpStream::connect(addr).await.unwrap(), "/").await;
+    assert!(not_found.starts_with("HTTP/1.1 404"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn commit_rate_only_counts_the_window() {
+    let mut rate = CommitRate::new(Duration::from_secs(10));
+    for _ in 0..20 {
+        rate.record();
+    }
+    assert_eq!(rate.per_second(), 2.0);
+
+    tokio::time::advance(Duration::from_secs(6)).await;
+    rate.record();
+    assert_eq!(rate.per_second(), 2.1);
+
+    // the burst leaves the window while the later commit is still in it
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(rate.per_second(), 0.1);
+}
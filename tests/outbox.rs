@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use pbft::messages::{Message, StatusRequest};
+use pbft::outbox::Outbox;
+use pbft::testkit::MessageBuilder;
+
+fn commit(seq_num: usize) -> Message {
+    Message::CommitMessage(MessageBuilder::generate(1).seq_num(seq_num).commit())
+}
+
+/// Kinds and sequence numbers of the messages
+fn describe(messages: &[Message]) -> Vec<(&'static str, Option<usize>)> {
+    messages
+        .iter()
+        .map(|message| (message.kind(), message.get_seq_num()))
+        .collect()
+}
+
+#[tokio::test]
+async fn messages_are_held_for_a_short_outage_of_the_peer() {
+    tokio::time::pause();
+    let outbox = Outbox::new(3, Duration::from_secs(10));
+    let view_change = Message::ViewChangeMessage(MessageBuilder::generate(1).view_change());
+
+    // only protocol messages are held, in the order they were sent, up to the capacity
+    outbox.hold(2, [commit(1), commit(2)], 0);
+    outbox.hold(2, [Message::StatusRequestMessage(StatusRequest {})], 0);
+    outbox.hold(2, [commit(3), view_change.clone()], 0);
+    let stats = outbox.stats();
+    assert_eq!(stats.total_held(), 3);
+    assert_eq!(stats.evicted, 1);
+
+    // those the stable checkpoint covers by the time the peer is back are not sent
+    assert_eq!(
+        describe(&outbox.take(2, 2)),
+        describe(&[commit(3), view_change])
+    );
+    outbox.clear(2, 2);
+    let stats = outbox.stats();
+    assert_eq!((stats.total_held(), stats.stale, stats.flushed), (0, 1, 2));
+    assert!(outbox.take(2, 2).is_empty());
+}
+
+#[tokio::test]
+async fn messages_are_dropped_once_the_peer_stays_unreachable() {
+    tokio::time::pause();
+    let outbox = Outbox::new(8, Duration::from_secs(10));
+    outbox.hold(3, [commit(1)], 0);
+    tokio::time::advance(Duration::from_secs(5)).await;
+    outbox.hold(3, outbox.take(3, 0).into_iter().chain([commit(2)]), 0);
+    assert_eq!(outbox.stats().total_held(), 2);
+
+    // the peer catches up once it is back, rather than from the held messages
+    tokio::time::advance(Duration::from_secs(6)).await;
+    outbox.hold(3, outbox.take(3, 0).into_iter().chain([commit(3)]), 0);
+    assert_eq!(outbox.stats().expired, 3);
+    assert!(outbox.take(3, 0).is_empty());
+
+    // a peer which reconnected starts a new outage
+    outbox.clear(3, 0);
+    outbox.hold(3, [commit(4)], 0);
+    assert_eq!(describe(&outbox.take(3, 0)), describe(&[commit(4)]));
+}
@@ -0,0 +1,51 @@
+use pbft::pki::{self, CertificateError, IdentityCertificate};
+use pbft::testkit::MessageBuilder;
+
+#[test]
+fn identity_certificates_bind_keys_to_nodes() {
+    let root = MessageBuilder::generate(0);
+    let node = MessageBuilder::generate(2);
+    let now = pki::unix_time();
+    let certificate =
+        IdentityCertificate::issue(&root.keypair_bytes(), 2, &node.public_key(), now, now + 60);
+
+    assert_eq!(
+        certificate.verify(&root.public_key(), now),
+        Ok(node.public_key())
+    );
+    assert_eq!(
+        certificate.certifies(2, &node.public_key(), &root.public_key()),
+        Ok(())
+    );
+    assert_eq!(
+        certificate.certifies(1, &node.public_key(), &root.public_key()),
+        Err(CertificateError::WrongIdentity)
+    );
+    assert_eq!(
+        certificate.certifies(2, &root.public_key(), &root.public_key()),
+        Err(CertificateError::WrongIdentity)
+    );
+
+    // only the root key issues certificates
+    let self_issued =
+        IdentityCertificate::issue(&node.keypair_bytes(), 2, &node.public_key(), now, now + 60);
+    assert_eq!(
+        self_issued.verify(&root.public_key(), now),
+        Err(CertificateError::BadSignature)
+    );
+    let mut tampered = certificate.clone();
+    tampered.id = 1;
+    assert_eq!(
+        tampered.verify(&root.public_key(), now),
+        Err(CertificateError::BadSignature)
+    );
+
+    assert_eq!(
+        certificate.verify(&root.public_key(), now - 1),
+        Err(CertificateError::NotYetValid)
+    );
+    assert_eq!(
+        certificate.verify(&root.public_key(), now + 61),
+        Err(CertificateError::Expired)
+    );
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ed25519_dalek::PublicKey;
+
+use pbft::config::Config;
+use pbft::merkle::ProofError;
+use pbft::messages::{CheckPoint, ClientRequest, ClientResponse, Operation};
+use pbft::probe::{
+    check_key_proof, proof_verdict, ProofCheck, ProofCheckError, ProofVerdict, ResponseTally,
+};
+use pbft::state::{State, StateSnapshot};
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, NodeId, Value};
+
+fn request(time_stamp: usize, key: Key, operation: Operation) -> ClientRequest {
+    RequestBuilder::new()
+        .time_stamp(time_stamp)
+        .key(key)
+        .operation(operation)
+        .relay_id(0)
+        .build()
+}
+
+fn builders_and_keys(num_nodes: usize) -> (Vec<MessageBuilder>, HashMap<NodeId, PublicKey>) {
+    let builders: Vec<MessageBuilder> = (0..num_nodes).map(MessageBuilder::generate).collect();
+    let pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    (builders, pub_keys)
+}
+
+#[test]
+fn only_responses_signed_by_distinct_replicas_are_counted() {
+    let (builders, pub_keys) = builders_and_keys(4);
+    let key = Key::from("sentinel");
+    let request = request(5, key.clone(), Operation::Get);
+    let response = |id: usize, value: &str| {
+        ClientResponse::new_with_signature(
+            builders[id].keystore(),
+            id,
+            5,
+            key.clone(),
+            Some(Value::from(value)),
+            Vec::new(),
+            None,
+        )
+        .with_seq_num(builders[id].keystore(), 7)
+    };
+    let mut tally = ResponseTally::new(pub_keys, &request, 2);
+    assert_eq!(tally.add(response(0, "v")), None);
+
+    // a relay cannot make up the responses of its peers, nor alter them
+    let mut forged = response(0, "v");
+    forged.id = 1;
+    assert_eq!(tally.add(forged), None);
+    let mut unsigned = response(1, "v");
+    unsigned.signature = Vec::new();
+    assert_eq!(tally.add(unsigned), None);
+    let mut altered = response(2, "w");
+    altered.value = Some(Value::from("v"));
+    assert_eq!(tally.add(altered), None);
+    let mut moved = response(2, "v");
+    moved.seq_num = 8;
+    assert_eq!(tally.add(moved), None);
+
+    // nor count a replica twice, or a response to another request
+    assert_eq!(tally.add(response(0, "v")), None);
+    let mut other_request = ClientResponse::new_with_signature(
+        builders[3].keystore(),
+        3,
+        6,
+        key.clone(),
+        Some(Value::from("v")),
+        Vec::new(),
+        None,
+    );
+    other_request.seq_num = 7;
+    assert_eq!(tally.add(other_request), None);
+    assert_eq!(tally.discarded, 6);
+    assert_eq!(tally.voters(), vec![0]);
+
+    // a replica which disagrees does not make up a quorum
+    assert_eq!(tally.add(response(3, "w")), None);
+    let agreed = tally.add(response(1, "v")).unwrap();
+    assert_eq!(agreed.value, Some(Value::from("v")));
+    assert_eq!(agreed.seq_num, 7);
+    assert_eq!(tally.voters(), vec![0, 1, 3]);
+}
+
+#[test]
+fn key_proofs_prove_sentinels_once_their_checkpoint_covers_the_write() {
+    let (builders, pub_keys) = builders_and_keys(4);
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.peer_pub_keys = pub_keys.clone();
+    let mut state = State::new(1, config);
+
+    let key = Key::from("sentinel");
+    let value = Value::from("v");
+    let write = request(1, key.clone(), Operation::Set(value.clone()));
+    let commit = builders[0]
+        .clone()
+        .seq_num(1)
+        .client_request(write.clone())
+        .commit();
+    assert_eq!(state.apply_commit(Arc::new(write), &commit).0.reason, None);
+
+    let digest = state.digest();
+    state.checkpoint_snapshots.insert(
+        1,
+        StateSnapshot {
+            committed_seq_num: 1,
+            store: state.store.clone(),
+            key_owners: state.key_owners.clone(),
+        },
+    );
+    state.last_checkpoint_proof = builders[..3]
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| {
+            CheckPoint::new_with_signature(builder.keystore(), id, 0, 1, 0, digest.clone())
+        })
+        .collect();
+    let key_proof = state.key_proof(&key).unwrap();
+    let check = |key_proof, expected: &str, written_at| {
+        check_key_proof(
+            key_proof,
+            &key,
+            &Value::from(expected),
+            written_at,
+            &pub_keys,
+            1,
+        )
+    };
+    assert_eq!(
+        check(&key_proof, "v", 1),
+        Ok(ProofCheck::Proven {
+            committed_seq_num: 1
+        })
+    );
+    assert_eq!(
+        check(&key_proof, "w", 1),
+        Err(ProofCheckError::Mismatch { proven: value })
+    );
+    // a checkpoint from before the write cannot prove it yet
+    assert_eq!(
+        check(&key_proof, "w", 2),
+        Ok(ProofCheck::NotYetCovered {
+            committed_seq_num: 1
+        })
+    );
+
+    // a replica cannot leave out the key once the checkpoint covers the write
+    let mut withheld = key_proof.clone();
+    withheld.value = None;
+    withheld.proof = None;
+    assert_eq!(
+        check(&withheld, "v", 1),
+        Err(ProofCheckError::Invalid(ProofError::MissingProof))
+    );
+    let other_key = state.key_proof(&Key::from("other")).unwrap();
+    assert_eq!(
+        check(&other_key, "v", 1),
+        Err(ProofCheckError::OtherKey(Key::from("other")))
+    );
+
+    // the certificate is checked whether or not the checkpoint covers the write
+    state.last_checkpoint_proof.pop();
+    let uncertified = state.key_proof(&key).unwrap();
+    for written_at in [1, 2] {
+        assert_eq!(
+            check(&uncertified, "v", written_at),
+            Err(ProofCheckError::Invalid(
+                ProofError::InsufficientCertificate { signers: 2 }
+            ))
+        );
+    }
+}
+
+#[test]
+fn sentinels_are_proven_by_a_checkpoint_of_one_of_f_plus_one_proofs() {
+    let proven = || {
+        Ok(ProofCheck::Proven {
+            committed_seq_num: 10,
+        })
+    };
+    let not_yet_covered = || {
+        Ok(ProofCheck::NotYetCovered {
+            committed_seq_num: 0,
+        })
+    };
+    assert_eq!(
+        proof_verdict(
+            vec![(2, proven()), (0, not_yet_covered()), (1, proven())],
+            1
+        ),
+        ProofVerdict::Proven(vec![1, 2])
+    );
+    assert_eq!(
+        proof_verdict(vec![(0, not_yet_covered()), (1, not_yet_covered())], 1),
+        ProofVerdict::NotYetCovered
+    );
+
+    // a single proof could come from a faulty replica
+    assert_eq!(
+        proof_verdict(vec![(0, proven())], 1),
+        ProofVerdict::TooFewProofs(1)
+    );
+    assert_eq!(proof_verdict(Vec::new(), 1), ProofVerdict::TooFewProofs(0));
+
+    // a proof which does not hold fails the sentinel, even if others prove it
+    let mismatch = ProofCheckError::Mismatch {
+        proven: Value::from("w"),
+    };
+    assert_eq!(
+        proof_verdict(
+            vec![(0, proven()), (1, proven()), (3, Err(mismatch.clone()))],
+            1
+        ),
+        ProofVerdict::Invalid(vec![(3, mismatch)])
+    );
+}
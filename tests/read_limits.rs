@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use pbft::config::Config;
+use pbft::messages::{ClientRequest, ConsensusCommand, Message, Operation};
+use pbft::node::Node;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, NodeId, Value};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 7950 + id as u16))
+}
+
+/// Waits for the node to close the connection, returning how long it took
+async fn closed_after(stream: &mut TcpStream, started: Instant) -> Duration {
+    let mut buf = [0u8; 16];
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "{:?}", read);
+    started.elapsed()
+}
+
+#[tokio::test]
+async fn slow_and_oversized_senders_are_dropped() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let mut config = Config::new((0..4).map(|id| (id, addr_of(id))).collect());
+    config.read_timeout = Duration::from_millis(300);
+    config.min_read_rate = 1000;
+    config.max_frame_len = 4096;
+
+    let (tx_consensus, mut rx_consensus) = channel(64);
+    let (tx_node, rx_node) = channel(64);
+    let mut node = Node::new(
+        0,
+        config,
+        builders[0].keystore(),
+        rx_node,
+        tx_consensus,
+        tx_node,
+    );
+    let inner = node.inner.clone();
+    tokio::spawn(async move { node.spawn().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // a connection which sends nothing is closed at the read timeout
+    let started = Instant::now();
+    let mut idle = TcpStream::connect(addr_of(0)).await.unwrap();
+    assert!(closed_after(&mut idle, started).await < Duration::from_secs(1));
+
+    // a frame announcing more than the limit is refused before its payload is read
+    let started = Instant::now();
+    let mut giant = TcpStream::connect(addr_of(0)).await.unwrap();
+    giant.write_all(&(1u32 << 30).to_be_bytes()).await.unwrap();
+    assert!(closed_after(&mut giant, started).await < Duration::from_secs(1));
+
+    // a 500 byte payload has 0.3s + 0.5s to arrive, which a byte every 50ms misses
+    let started = Instant::now();
+    let mut slow = TcpStream::connect(addr_of(0)).await.unwrap();
+    slow.write_all(&500u32.to_be_bytes()).await.unwrap();
+    let trickle = async {
+        loop {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if slow.write_all(b" ").await.is_err() {
+                break;
+            }
+        }
+    };
+    let _ = tokio::time::timeout(Duration::from_secs(2), trickle).await;
+    let elapsed = closed_after(&mut slow, started).await;
+    assert!(elapsed >= Duration::from_millis(800) && elapsed < Duration::from_secs(2));
+
+    // a sender within the limits is still heard
+    let mut stream = TcpStream::connect(addr_of(0)).await.unwrap();
+    let request = Message::ClientRequestMessage(ClientRequest {
+        respond_addr: SocketAddr::from(([127, 0, 0, 1], 7955)),
+        time_stamp: 1,
+        key: Key::from("k"),
+        operation: Operation::Set(Value::from("1")),
+        relay_id: None,
+        read_only: false,
+        batch: Vec::new(),
+        bulk: None,
+        client_id: None,
+        signature: Vec::new(),
+    });
+    stream.write_all(&request.serialize()).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(1), rx_consensus.recv()).await;
+    assert!(matches!(
+        received,
+        Ok(Some(ConsensusCommand::ProcessMessage(
+            Message::ClientRequestMessage(_)
+        )))
+    ));
+
+    let malformed = inner.status().malformed;
+    assert_eq!(malformed.oversized_frames, 1);
+    assert_eq!(malformed.slow_connections, 2);
+}
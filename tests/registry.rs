@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use pbft::config::Config;
+use pbft::merkle::ProofError;
+use pbft::messages::{BatchOp, CheckPoint, ClientRequest, FailureReason, Operation};
+use pbft::registry::{self, ClusterRegistry, RegistryError, RegistryUpdate};
+use pbft::state::{State, StateSnapshot};
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, Value};
+
+fn request(time_stamp: usize, key: Key, operation: Operation) -> ClientRequest {
+    RequestBuilder::new()
+        .time_stamp(time_stamp)
+        .key(key)
+        .operation(operation)
+        .build()
+}
+
+#[test]
+fn registry_only_takes_root_signed_updates_and_verifies_against_its_members() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let root = MessageBuilder::generate(0);
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.peer_pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    config.root_pub_key = Some(root.public_key());
+
+    // replicas start with the registry of the config
+    let mut state = State::new(1, config.clone());
+    let genesis = ClusterRegistry::genesis(&config);
+    assert_eq!(genesis.pub_keys(), config.peer_pub_keys);
+    assert_eq!(
+        state.store.get(&registry::registry_key()),
+        Some(&genesis.encode())
+    );
+
+    let mut seq_num = 0;
+    let mut apply = |state: &mut State, request: ClientRequest| {
+        seq_num += 1;
+        let commit = builders[0]
+            .clone()
+            .seq_num(seq_num)
+            .client_request(request.clone())
+            .commit();
+        state.apply_commit(Arc::new(request), &commit).0.reason
+    };
+    let mut updated = genesis.clone();
+    updated.version = 1;
+    updated.members.remove(&3);
+    let reserved = Some(FailureReason::ReservedKey);
+
+    // clients cannot write the reserved keys, nor submit updates not signed by the root key
+    let write = Operation::Set(Value::from("1"));
+    let key = Key::from("_cluster/members");
+    assert_eq!(apply(&mut state, request(1, key, write)), reserved);
+    let forged = RegistryUpdate::new_with_signature(builders[0].keystore(), updated.clone());
+    let forged = Operation::Set(forged.encode());
+    assert_eq!(
+        apply(&mut state, request(2, registry::registry_key(), forged)),
+        reserved
+    );
+    let mut batch = request(3, Key::default(), Operation::Get);
+    batch.batch = vec![BatchOp::Delete {
+        key: registry::registry_key(),
+    }];
+    assert_eq!(apply(&mut state, batch), reserved);
+
+    // an update signed by the root key applies once
+    let update = RegistryUpdate::new_with_signature(root.keystore(), updated.clone());
+    let update = Operation::Set(update.encode());
+    let key = registry::registry_key();
+    assert_eq!(
+        apply(&mut state, request(4, key.clone(), update.clone())),
+        None
+    );
+    assert_eq!(apply(&mut state, request(5, key.clone(), update)), reserved);
+    assert_eq!(state.store.get(&key), Some(&updated.encode()));
+
+    // the registry is proven by the checkpoints of 2f + 1 members
+    let digest = state.digest();
+    state.checkpoint_snapshots.insert(
+        5,
+        StateSnapshot {
+            committed_seq_num: 5,
+            store: state.store.clone(),
+            key_owners: BTreeMap::new(),
+        },
+    );
+    state.last_checkpoint_proof = builders[..3]
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| {
+            CheckPoint::new_with_signature(builder.keystore(), id, 0, 5, 0, digest.clone())
+        })
+        .collect();
+    let snapshot = state.key_proof(&key).unwrap();
+    assert_eq!(genesis.verify_snapshot(&snapshot), Ok(updated.clone()));
+    let mut newer = genesis.clone();
+    newer.version = 2;
+    assert_eq!(
+        newer.verify_snapshot(&snapshot),
+        Err(RegistryError::Outdated { version: 1 })
+    );
+
+    state.last_checkpoint_proof.pop();
+    let snapshot = state.key_proof(&key).unwrap();
+    assert_eq!(
+        genesis.verify_snapshot(&snapshot),
+        Err(RegistryError::InvalidProof(
+            ProofError::InsufficientCertificate { signers: 2 }
+        ))
+    );
+}
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pbft::messages::Message;
+use pbft::observer::Observer;
+use pbft::sim::LinkConfig;
+use pbft::storage::{Wal, WalRecord};
+use pbft::testing::ClusterBuilder;
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+use tokio::time::sleep;
+
+/// Digests of the pre-prepares of each slot, by view and sequence number
+type Slots = BTreeMap<(usize, usize), BTreeSet<Vec<u8>>>;
+
+/// Pre-prepares sent by the primary, or received from it
+#[derive(Default)]
+struct PrePrepares {
+    outgoing: bool,
+    digests: Mutex<Slots>,
+}
+
+impl PrePrepares {
+    fn record(&self, message: &Message) {
+        if let Message::PrePrepareMessage(pre_prepare) = message {
+            self.digests
+                .lock()
+                .unwrap()
+                .entry((pre_prepare.view, pre_prepare.seq_num))
+                .or_default()
+                .insert(pre_prepare.client_request_digest.clone());
+        }
+    }
+
+    fn last_seq_num(&self) -> usize {
+        let digests = self.digests.lock().unwrap();
+        digests
+            .keys()
+            .map(|(_, seq_num)| *seq_num)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn conflicting(&self) -> Vec<(usize, usize)> {
+        let digests = self.digests.lock().unwrap();
+        digests
+            .iter()
+            .filter(|(_, digests)| digests.len() > 1)
+            .map(|(slot, _)| *slot)
+            .collect()
+    }
+}
+
+impl Observer for PrePrepares {
+    fn on_message_in(&self, message: &Message) {
+        if !self.outgoing {
+            self.record(message);
+        }
+    }
+
+    fn on_message_out(&self, message: &Message, _destination: Option<SocketAddr>) {
+        if self.outgoing {
+            self.record(message);
+        }
+    }
+}
+
+fn wal_dir(name: &str) -> PathBuf {
+    let wal_dir = std::env::temp_dir().join(format!("pbft-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&wal_dir);
+    wal_dir
+}
+
+#[tokio::test(start_paused = true)]
+async fn primary_restarted_mid_batch_assigns_no_sequence_number_twice() {
+    let wal_dir = wal_dir("restart");
+    let mut cluster = ClusterBuilder::new(4)
+        .seed(11)
+        .links(LinkConfig {
+            min_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(5),
+            drop_rate: 0.0,
+        })
+        .wal_dir(&wal_dir)
+        .config(|config| config.rebroadcast_timeout = Duration::from_millis(500))
+        .build();
+    let received = Arc::new(PrePrepares::default());
+    for id in 1..4 {
+        cluster.node(id).observers.register(received.clone());
+    }
+    let sent = Arc::new(PrePrepares {
+        outgoing: true,
+        ..Default::default()
+    });
+    cluster.node(0).observers.register(sent.clone());
+
+    let mut client = cluster.client();
+    for i in 1..=2 {
+        assert!(client
+            .put(Key::from("k"), Value::from(i.to_string()))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(2).await);
+
+    // the primary restarts once it sent the pre-prepares of the batch,
+    // before they reach it and are accepted
+    let mut batch = Vec::new();
+    for i in 0..3 {
+        let mut client = cluster.client();
+        batch.push(tokio::spawn(async move {
+            client
+                .put(Key::from(format!("b{}", i)), Value::from("v"))
+                .await
+        }));
+    }
+    while sent.last_seq_num() < 5 {
+        sleep(Duration::from_millis(1)).await;
+    }
+    cluster.restart_node(0);
+    for put in batch {
+        assert!(put.await.unwrap().is_some());
+    }
+
+    // the next request is assigned the sequence number after the batch
+    assert!(client.put(Key::from("k"), Value::from("3")).await.is_some());
+    assert!(cluster.await_commit(6).await);
+    assert_eq!(received.conflicting(), Vec::new());
+    assert_eq!(received.last_seq_num(), 6);
+    assert!((0..4).all(|id| cluster.status(id).view == 0));
+    let _ = std::fs::remove_dir_all(&wal_dir);
+}
+
+#[test]
+fn compaction_keeps_the_proposals_after_the_stable_checkpoint() {
+    let wal_dir = wal_dir("proposals");
+    std::fs::create_dir_all(&wal_dir).unwrap();
+    let wal_path = wal_dir.join("replica-0.wal");
+    let (mut wal, records) = Wal::open(&wal_path).unwrap();
+    assert!(records.is_empty());
+    for seq_num in 1..=4 {
+        let pre_prepare = MessageBuilder::generate(0).seq_num(seq_num).pre_prepare();
+        wal.append(&WalRecord::Proposed(pre_prepare)).unwrap();
+    }
+    wal.compact(2, &[WalRecord::View(0)]).unwrap();
+
+    let (_, records) = Wal::open(&wal_path).unwrap();
+    let kept: Vec<Option<usize>> = records.iter().map(WalRecord::seq_num).collect();
+    assert_eq!(kept, vec![None, Some(3), Some(4)]);
+    let _ = std::fs::remove_dir_all(&wal_dir);
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use pbft::scenario::{Expectations, Fault, Outcome, Scenario, ScenarioError, ScheduledFault};
+
+#[test]
+fn bundled_scenarios_are_valid() {
+    let scenario = Scenario::load(Path::new("scenarios/primary_partition.json")).unwrap();
+    assert_eq!(scenario.num_nodes, 4);
+    assert_eq!(
+        scenario.schedule[0],
+        ScheduledFault {
+            at_secs: 3,
+            fault: Fault::Partition { nodes: vec![0] },
+        }
+    );
+    assert_eq!(scenario.schedule[1].fault, Fault::Heal);
+    assert_eq!(scenario.workload.seed, Some(1));
+}
+
+#[test]
+fn faults_on_nodes_outside_the_cluster_are_rejected() {
+    let scenario: Scenario = serde_json::from_str(
+        r#"{ "num_nodes": 4, "schedule": [{ "at_secs": 1, "fault": "stop", "nodes": [4] }] }"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        scenario.validate(),
+        Err(ScenarioError::Invalid(_))
+    ));
+
+    let scenario: Scenario =
+        serde_json::from_str(r#"{ "num_nodes": 4, "byzantine": [3] }"#).unwrap();
+    assert_eq!(scenario.validate(), Ok(()));
+    assert_eq!(scenario.workload.num_keys, 8);
+}
+
+#[test]
+fn expectations_default_to_consistency() {
+    let outcome = Outcome {
+        operations: 10,
+        succeeded: 4,
+        timed_out: 6,
+        ..Default::default()
+    };
+    assert!(Expectations::default().violations(&outcome).is_empty());
+
+    let strict = Expectations {
+        min_succeeded: 5,
+        max_timed_out: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(strict.violations(&outcome).len(), 2);
+
+    let inconsistent = Outcome {
+        stale_reads: 1,
+        ..outcome
+    };
+    assert_eq!(Expectations::default().violations(&inconsistent).len(), 1);
+}
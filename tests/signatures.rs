@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+
+use pbft::messages::ViewChange;
+use pbft::testkit::MessageBuilder;
+
+#[test]
+fn view_changes_with_forged_votes_are_detected() {
+    let builders: Vec<MessageBuilder> = (0..4)
+        .map(|id| MessageBuilder::generate(id).view(0).seq_num(3))
+        .collect();
+    let pub_keys: HashMap<_, _> = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    let view_change = |prepares| {
+        let mut subsequent_prepares = BTreeMap::new();
+        subsequent_prepares.insert(3, (builders[0].pre_prepare(), prepares));
+        ViewChange::new_with_signature(
+            builders[3].keystore(),
+            3,
+            0,
+            1,
+            0,
+            Vec::new(),
+            subsequent_prepares,
+        )
+    };
+
+    let honest = view_change(vec![builders[1].prepare(), builders[2].prepare()]);
+    assert!(honest.are_votes_properly_signed(&pub_keys));
+    assert!(honest
+        .clone()
+        .compacted(1)
+        .are_votes_properly_signed(&pub_keys));
+
+    // node 3 claims a prepare of node 2 which it signed itself
+    let forged = view_change(vec![
+        builders[1].prepare(),
+        builders[3].clone().id(2).prepare(),
+    ]);
+    assert!(forged.is_properly_signed_by(&pub_keys[&3]));
+    assert!(!forged.are_votes_properly_signed(&pub_keys));
+    assert!(!forged
+        .clone()
+        .compacted(1)
+        .are_votes_properly_signed(&pub_keys));
+
+    // votes of replicas whose key is unknown cannot be verified
+    let mut partial_keys = pub_keys.clone();
+    partial_keys.remove(&2);
+    assert!(!honest.are_votes_properly_signed(&partial_keys));
+}
+
+#[test]
+fn prepared_requests_of_view_changes_must_be_backed_by_a_quorum() {
+    let builders: Vec<MessageBuilder> = (0..4)
+        .map(|id| MessageBuilder::generate(id).view(0).seq_num(3))
+        .collect();
+    let view_change = |pre_prepare, prepares| {
+        let mut subsequent_prepares = BTreeMap::new();
+        subsequent_prepares.insert(3, (pre_prepare, prepares));
+        ViewChange::new_with_signature(
+            builders[3].keystore(),
+            3,
+            0,
+            1,
+            0,
+            Vec::new(),
+            subsequent_prepares,
+        )
+    };
+    let primary_of = |view| view % 4;
+
+    let honest = view_change(
+        builders[0].pre_prepare(),
+        vec![builders[1].prepare(), builders[2].prepare()],
+    );
+    assert!(honest.are_prepared_requests_valid(1, primary_of));
+    assert!(honest
+        .clone()
+        .compacted(1)
+        .are_prepared_requests_valid(1, primary_of));
+
+    // a single prepare, counted twice
+    let too_few = view_change(
+        builders[0].pre_prepare(),
+        vec![builders[1].prepare(), builders[1].prepare()],
+    );
+    assert!(!too_few.are_prepared_requests_valid(1, primary_of));
+    assert!(!too_few
+        .compacted(1)
+        .are_prepared_requests_valid(1, primary_of));
+
+    // a prepare for another request
+    let other_request = builders[2].clone().wrong_digest().prepare();
+    let mismatched = view_change(
+        builders[0].pre_prepare(),
+        vec![builders[1].prepare(), other_request],
+    );
+    assert!(!mismatched.are_prepared_requests_valid(1, primary_of));
+    assert!(!mismatched
+        .compacted(1)
+        .are_prepared_requests_valid(1, primary_of));
+
+    // a pre-prepare of a replica which is not the primary of the view
+    let not_primary = view_change(
+        builders[1].pre_prepare(),
+        vec![builders[0].prepare(), builders[2].prepare()],
+    );
+    assert!(!not_primary.are_prepared_requests_valid(1, primary_of));
+    assert!(!not_primary
+        .compacted(1)
+        .are_prepared_requests_valid(1, primary_of));
+}
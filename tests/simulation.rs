@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::messages::{
+    ClientRequest, ClientResponse, Drain, DrainStatus, FailureReason, Message, Operation,
+};
+use pbft::observer::Observer;
+use pbft::sim::{GeoLinks, LinkConfig, LinkModel, Network, NetworkStats, Simulation};
+use pbft::state::SlotMeta;
+use pbft::{Key, NodeId, Value};
+
+use tokio::time::Instant;
+
+const NUM_NODES: usize = 4;
+const NUM_REQUESTS: usize = 8;
+/// Replicas which commit every request, 2f + 1
+const QUORUM: usize = 3;
+
+/// Requests committed by a replica, by sequence number, and the last view it entered
+#[derive(Default)]
+struct Recorder {
+    committed: Mutex<BTreeMap<usize, (usize, Operation)>>,
+    view: Mutex<usize>,
+}
+
+impl Observer for Recorder {
+    fn on_commit(&self, slot: &SlotMeta, request: &ClientRequest, _response: &ClientResponse) {
+        self.committed.lock().unwrap().insert(
+            slot.seq_num,
+            (request.time_stamp, request.operation.clone()),
+        );
+    }
+
+    fn on_view_change(&self, view: usize) {
+        *self.view.lock().unwrap() = view;
+    }
+}
+
+/// What a run of the cluster did: the log of every replica, their views, the virtual time
+/// the last request was committed at and the traffic of the network until then
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    logs: Vec<BTreeMap<usize, (usize, Operation)>>,
+    views: Vec<usize>,
+    elapsed: Duration,
+    stats: NetworkStats,
+}
+
+fn addr_of(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, id as u8 + 1], 7000))
+}
+
+/// Runs a cluster on the network, cutting off the partitioned replicas, until a quorum
+/// of replicas committed every request
+async fn run(network: Network, partitioned: &[NodeId]) -> Outcome {
+    let mut config = Config::new((0..NUM_NODES).map(|id| (id, addr_of(id))).collect());
+    config.request_timeout = Duration::from_secs(1);
+    let sim = Simulation::start(config, network.clone());
+    let recorders: Vec<Arc<Recorder>> = sim
+        .nodes
+        .iter()
+        .map(|node| {
+            let recorder = Arc::new(Recorder::default());
+            node.observers.register(recorder.clone());
+            recorder
+        })
+        .collect();
+    network.partition(partitioned);
+
+    let started = Instant::now();
+    let respond_addr = SocketAddr::from(([10, 0, 1, 1], 7000));
+    let _rx_client = network.attach_client(respond_addr);
+    for time_stamp in 1..=NUM_REQUESTS {
+        let request = Message::ClientRequestMessage(ClientRequest {
+            respond_addr,
+            time_stamp,
+            key: Key::from(format!("k{}", time_stamp).as_str()),
+            operation: Operation::Set(Value::from(time_stamp.to_string())),
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        });
+        for id in 0..NUM_NODES {
+            let _ = network.submit(addr_of(id), request.clone());
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // without retransmissions, a replica which lost a vote lags until the next checkpoint
+    let is_done = || {
+        recorders
+            .iter()
+            .filter(|recorder| recorder.committed.lock().unwrap().len() == NUM_REQUESTS)
+            .count()
+            >= QUORUM
+    };
+    while !is_done() {
+        assert!(
+            started.elapsed() < Duration::from_secs(120),
+            "cluster stalled"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    Outcome {
+        logs: recorders
+            .iter()
+            .map(|recorder| recorder.committed.lock().unwrap().clone())
+            .collect(),
+        views: recorders
+            .iter()
+            .map(|recorder| *recorder.view.lock().unwrap())
+            .collect(),
+        elapsed: started.elapsed(),
+        stats: network.stats(),
+    }
+}
+
+fn lossy() -> LinkConfig {
+    LinkConfig {
+        drop_rate: 0.05,
+        ..LinkConfig::default()
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn runs_with_the_same_seed_are_identical() {
+    let outcome = run(Network::new(2, lossy()), &[]).await;
+    assert!(outcome.stats.dropped > 0);
+    assert_eq!(run(Network::new(2, lossy()), &[]).await, outcome);
+}
+
+#[tokio::test(start_paused = true)]
+async fn link_models_set_the_pace_of_the_cluster() {
+    let links = GeoLinks::three_regions(NUM_NODES);
+    assert_eq!(links.rtt(Some(0), Some(3)), Duration::from_millis(2));
+    assert_eq!(links.rtt(None, Some(2)), Duration::from_millis(220));
+
+    // replicas 0 and 3 share a region, so the third replica of a quorum is 40 ms away,
+    // which its prepare and then its commit have to cover
+    let lan = run(Network::new(2, LinkModel::lan()), &[]).await;
+    let wan = run(Network::new(2, LinkModel::wan(NUM_NODES)), &[]).await;
+    assert_eq!(lan.views, vec![0; NUM_NODES]);
+    assert_eq!(wan.views, vec![0; NUM_NODES]);
+    assert!(wan.elapsed >= lan.elapsed + Duration::from_millis(80));
+    assert_eq!(lan.stats.dropped + wan.stats.dropped, 0);
+
+    // clients on lossy links still get their writes through
+    let config = Config::new((0..NUM_NODES).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(2, LinkModel::lossy_mobile()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+    let mut written = 0;
+    for i in 0..NUM_REQUESTS {
+        let value = Value::from(i.to_string());
+        if client.put(Key::from("k"), value).await.is_some() {
+            written += 1;
+        }
+    }
+    assert!(written > 0);
+    assert!(sim.network.stats().dropped > 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn partitioned_primary_is_replaced() {
+    let outcome = run(Network::new(11, LinkConfig::default()), &[0]).await;
+    assert!(outcome.logs[0].is_empty());
+    assert!(outcome.views[1..].iter().all(|view| *view > 0));
+    assert_eq!(outcome.logs[1], outcome.logs[2]);
+    assert_eq!(outcome.logs[2], outcome.logs[3]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn undeliverable_responses_are_dead_lettered() {
+    let config = Config::new((0..NUM_NODES).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(5, LinkConfig::default()));
+    let respond_addr = SocketAddr::from(([10, 0, 1, 1], 7000));
+    let submit = |time_stamp: usize| {
+        let request = Message::ClientRequestMessage(ClientRequest {
+            respond_addr,
+            time_stamp,
+            key: Key::from("k"),
+            operation: Operation::Set(Value::from(time_stamp.to_string())),
+            relay_id: None,
+            read_only: false,
+            batch: Vec::new(),
+            bulk: None,
+            client_id: None,
+            signature: Vec::new(),
+        });
+        for id in 0..NUM_NODES {
+            let _ = sim.network.submit(addr_of(id), request.clone());
+        }
+    };
+
+    // the client is gone before its response is sent
+    submit(1);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    for node in sim.nodes.iter() {
+        let dead_letters = node.status().dead_letters;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].response.time_stamp, 1);
+        assert_eq!(dead_letters[0].attempts, sim.config.response_retries + 1);
+    }
+
+    // its next request supersedes the dead letter
+    let _rx_client = sim.network.attach_client(respond_addr);
+    submit(2);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(sim
+        .nodes
+        .iter()
+        .all(|node| node.status().dead_letters.is_empty()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn deletes_and_compare_and_swaps_return_the_previous_value() {
+    let config = Config::new((0..NUM_NODES).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(9, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+    let key = Key::from("k");
+
+    let created = client
+        .cas(key.clone(), None, Value::from("1"))
+        .await
+        .unwrap();
+    assert!(created.success);
+    assert_eq!(created.previous, None);
+
+    let stale = client
+        .cas(key.clone(), None, Value::from("2"))
+        .await
+        .unwrap();
+    assert_eq!(stale.reason, Some(FailureReason::UnexpectedValue));
+    assert_eq!(stale.previous, Some(Value::from("1")));
+
+    let swapped = client
+        .cas(key.clone(), Some(Value::from("1")), Value::from("2"))
+        .await
+        .unwrap();
+    assert!(swapped.success);
+    assert_eq!(swapped.previous, Some(Value::from("1")));
+
+    let deleted = client.delete(key.clone()).await.unwrap();
+    assert_eq!(deleted.previous, Some(Value::from("2")));
+    assert_eq!(client.get(key).await.unwrap().value, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reads_are_answered_without_ordering_them() {
+    let config = Config::new((0..NUM_NODES).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(5, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+    client.put(Key::from("k"), Value::from("1")).await.unwrap();
+    // every replica applies the write before it is read
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let committed = sim.nodes[0].status().last_seq_num_committed;
+
+    let read = client.get(Key::from("k")).await.unwrap();
+    assert_eq!(read.value, Some(Value::from("1")));
+    assert_eq!(sim.nodes[0].status().last_seq_num_committed, committed);
+
+    // replica 3 misses the next write and replica 0 is cut off, so no 2f + 1 replicas
+    // agree on the value and the read is ordered, by the next primary
+    sim.network.partition(&[3]);
+    client.put(Key::from("k"), Value::from("2")).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    sim.network.heal();
+    sim.network.partition(&[0]);
+    let committed = sim.nodes[1].status().last_seq_num_committed;
+    let read = client.get(Key::from("k")).await.unwrap();
+    assert_eq!(read.value, Some(Value::from("2")));
+    assert!(sim.nodes[1].status().last_seq_num_committed > committed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn drained_replicas_refuse_clients_and_report_when_safe_to_stop() {
+    let config = Config::new((0..NUM_NODES).map(|id| (id, addr_of(id))).collect());
+    let sim = Simulation::start(config, Network::new(6, LinkConfig::default()));
+    let mut client = sim.client(SocketAddr::from(([10, 0, 1, 1], 7000)));
+    client.put(Key::from("k"), Value::from("1")).await.unwrap();
+
+    let drain = Message::DrainMessage(Drain {
+        target_seq_num: Some(2),
+    });
+    sim.nodes[3].receive(None, drain).await.unwrap();
+    let client_requests = |node: usize| {
+        sim.nodes[node]
+            .status()
+            .pipeline
+            .by_kind
+            .get("ClientRequest")
+            .map_or(0, |counts| counts.enqueued)
+    };
+    let refused_before = client_requests(3);
+
+    // the replica still takes part in ordering the request up to its target
+    client.put(Key::from("k"), Value::from("2")).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(client_requests(3), refused_before);
+    let status = sim.nodes[3].status();
+    assert_eq!(status.last_seq_num_committed, 2);
+    assert_eq!(
+        status.drain,
+        Some(DrainStatus {
+            target_seq_num: 2,
+            safe_to_stop: true
+        })
+    );
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::time::Duration;
+
+use pbft::messages::ConsensusCommand;
+use pbft::snapshot::{SnapshotFile, SNAPSHOT_FORMAT_VERSION};
+use pbft::state::{SnapshotError, State};
+use pbft::testing::ClusterBuilder;
+use pbft::{Key, Value};
+
+use tokio::time::sleep;
+
+/// Imports the snapshot file after the change into a fresh state of the cluster
+fn import_changed(
+    path: &Path,
+    state: &State,
+    change: impl FnOnce(&mut SnapshotFile),
+) -> Result<usize, SnapshotError> {
+    let mut file = SnapshotFile::read(path).unwrap();
+    change(&mut file);
+    let changed_path = path.with_extension("changed");
+    file.write(&changed_path).unwrap();
+    let mut fresh = State::new(3, state.config.clone());
+    fresh.import_snapshot(&changed_path, &state.config.peer_pub_keys)
+}
+
+#[tokio::test(start_paused = true)]
+async fn exported_snapshots_seed_replicas_once_they_check_out() {
+    let dir = std::env::temp_dir().join(format!("pbft-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("snapshot.json");
+    let export_path = path.clone();
+    let cluster = ClusterBuilder::new(4)
+        .seed(3)
+        .config(|config| config.snapshot_export = Some(export_path))
+        .build();
+    let mut client = cluster.client();
+    for i in 0..10 {
+        assert!(client
+            .put(Key::from(format!("k{}", i)), Value::from("v"))
+            .await
+            .is_some());
+    }
+    assert!(cluster.await_commit(10).await);
+    sleep(Duration::from_secs(1)).await;
+    cluster
+        .node(1)
+        .tx_consensus
+        .send(ConsensusCommand::ExportSnapshot)
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    let file = SnapshotFile::read(&path).unwrap();
+    assert_eq!((file.version, file.id), (SNAPSHOT_FORMAT_VERSION, 1));
+    let mut state = State::new(3, cluster.config().clone());
+    let pub_keys = cluster.config().peer_pub_keys.clone();
+    assert_eq!(state.import_snapshot(&path, &pub_keys), Ok(10));
+    assert_eq!(state.last_stable_seq_num, 10);
+    assert_eq!(state.last_seq_num_committed, 10);
+    assert_eq!(state.store.get(&Key::from("k9")), Some(&Value::from("v")));
+    assert_eq!(state.digest(), file.state_digest());
+    assert_eq!(
+        state.import_snapshot(&path, &pub_keys),
+        Err(SnapshotError::Stale {
+            committed_seq_num: 10
+        })
+    );
+
+    // files which were tampered with are not imported
+    let changed = import_changed(&path, &state, |file| {
+        file.snapshot
+            .store
+            .insert(Key::from("k0"), Value::from("forged"));
+    });
+    assert_eq!(changed, Err(SnapshotError::MismatchedDigest));
+    let changed = import_changed(&path, &state, |file| file.view += 1);
+    assert_eq!(changed, Err(SnapshotError::InvalidSignature { id: 1 }));
+    let changed = import_changed(&path, &state, |file| file.version += 1);
+    assert_eq!(
+        changed,
+        Err(SnapshotError::UnsupportedVersion(
+            SNAPSHOT_FORMAT_VERSION + 1
+        ))
+    );
+    let changed = import_changed(&path, &state, |file| file.proof.truncate(1));
+    assert!(matches!(
+        changed,
+        Err(SnapshotError::InsufficientCertificate { votes: 1, .. })
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use pbft::config::Config;
+use pbft::crypto::DigestAlgorithm;
+use pbft::merkle;
+use pbft::messages::{CheckPoint, ClientIdentity, StateChunkResponse};
+use pbft::state::{store_digest, SnapshotError, State, StateSnapshot};
+use pbft::state_transfer::{ChunkError, StateTransfer};
+use pbft::testkit::MessageBuilder;
+use pbft::{Key, Value};
+
+const ALGORITHM: DigestAlgorithm = DigestAlgorithm::Sha256;
+
+fn snapshot_of(num_entries: usize) -> StateSnapshot {
+    let mut snapshot = StateSnapshot {
+        committed_seq_num: 10,
+        ..StateSnapshot::default()
+    };
+    let owner = ClientIdentity::Addr("127.0.0.1:7100".parse().unwrap());
+    for i in 0..num_entries {
+        let key = Key::from(format!("k{:02}", i).as_str());
+        snapshot
+            .store
+            .insert(key.clone(), Value::from(i.to_string()));
+        if i % 3 == 0 {
+            snapshot.key_owners.insert(key, owner);
+        } else if i % 5 == 0 {
+            snapshot
+                .key_owners
+                .insert(key, ClientIdentity::Id(i as u64));
+        }
+    }
+    snapshot
+}
+
+fn transfer_of(snapshot: &StateSnapshot) -> StateTransfer {
+    let state_digest = store_digest(&snapshot.store, &snapshot.key_owners, ALGORITHM);
+    let certificate: Vec<CheckPoint> = (0..3)
+        .map(|id| {
+            CheckPoint::new_with_signature(
+                MessageBuilder::generate(id).keystore(),
+                id,
+                0,
+                snapshot.committed_seq_num,
+                0,
+                state_digest.clone(),
+            )
+        })
+        .collect();
+    StateTransfer::new(3, certificate[0].clone(), certificate)
+}
+
+fn response(
+    transfer: &StateTransfer,
+    snapshot: &StateSnapshot,
+    start: usize,
+) -> StateChunkResponse {
+    let request = transfer.request(3);
+    let (entries, proof) = snapshot.chunk(start, 4, ALGORITHM);
+    StateChunkResponse {
+        id: transfer.source().unwrap(),
+        committed_seq_num: request.committed_seq_num,
+        state_digest: request.state_digest,
+        entries,
+        proof,
+    }
+}
+
+#[test]
+fn range_proofs_verify_every_run_of_leaves() {
+    for num_entries in 1..=9 {
+        let snapshot = snapshot_of(num_entries);
+        let root = merkle::root(&snapshot.store, &snapshot.key_owners, ALGORITHM);
+        let leaves: Vec<Vec<u8>> = snapshot
+            .store
+            .iter()
+            .map(|(key, value)| {
+                merkle::leaf_hash(key, value, snapshot.key_owners.get(key), ALGORITHM)
+            })
+            .collect();
+        for start in 0..num_entries {
+            for end in start + 1..=num_entries {
+                let proof = merkle::prove_range(
+                    &snapshot.store,
+                    &snapshot.key_owners,
+                    start,
+                    end,
+                    ALGORITHM,
+                )
+                .unwrap();
+                assert!(merkle::verify_range(
+                    &root,
+                    leaves[start..end].to_vec(),
+                    &proof
+                ));
+                // the run does not verify at another position
+                if end < num_entries {
+                    assert!(!merkle::verify_range(
+                        &root,
+                        leaves[start + 1..end + 1].to_vec(),
+                        &proof
+                    ));
+                }
+            }
+        }
+        assert!(merkle::prove_range(
+            &snapshot.store,
+            &snapshot.key_owners,
+            0,
+            num_entries + 1,
+            ALGORITHM
+        )
+        .is_none());
+    }
+}
+
+#[test]
+fn lagging_replica_fetches_the_state_in_verified_chunks() {
+    let snapshot = snapshot_of(10);
+    let mut transfer = transfer_of(&snapshot);
+    assert_eq!(transfer.source(), Some(0));
+
+    while !transfer.is_complete() {
+        let start = transfer.next_start();
+        assert_eq!(
+            transfer.accept(response(&transfer, &snapshot, start)),
+            Ok(())
+        );
+    }
+    assert_eq!(transfer.next_start(), 10);
+    assert_eq!(transfer.into_snapshot(), snapshot);
+
+    // an empty store comes without a proof
+    let empty = snapshot_of(0);
+    let mut transfer = transfer_of(&empty);
+    assert_eq!(transfer.accept(response(&transfer, &empty, 0)), Ok(()));
+    assert!(transfer.is_complete());
+}
+
+#[test]
+fn chunks_which_do_not_check_out_are_rejected() {
+    let snapshot = snapshot_of(10);
+    let mut transfer = transfer_of(&snapshot);
+
+    // a chunk from another position than we asked for
+    assert_eq!(
+        transfer.accept(response(&transfer, &snapshot, 4)),
+        Err(ChunkError::Unexpected)
+    );
+
+    // a chunk whose values were tampered with
+    let mut tampered = response(&transfer, &snapshot, 0);
+    tampered.entries[1].value = Value::from("forged");
+    assert_eq!(transfer.accept(tampered), Err(ChunkError::InvalidProof));
+
+    // a chunk leaving out the owner of a key
+    let mut tampered = response(&transfer, &snapshot, 0);
+    tampered.entries[0].owner = None;
+    assert_eq!(transfer.accept(tampered), Err(ChunkError::InvalidProof));
+
+    // a chunk claiming the store is empty
+    let mut tampered = response(&transfer, &snapshot, 0);
+    tampered.entries.clear();
+    tampered.proof = None;
+    assert_eq!(transfer.accept(tampered), Err(ChunkError::InvalidProof));
+    assert_eq!(transfer.next_start(), 0);
+
+    // the next replica of the certificate is asked instead
+    transfer.next_source();
+    assert_eq!(transfer.source(), Some(1));
+    assert_eq!(transfer.accept(response(&transfer, &snapshot, 0)), Ok(()));
+    assert_eq!(transfer.next_start(), 4);
+}
+
+#[test]
+fn malicious_snapshot_offers_are_refused() {
+    let builders: Vec<MessageBuilder> = (0..4).map(MessageBuilder::generate).collect();
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.peer_pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    let state = State::new(3, config.clone());
+    let validate = |state_digest: &[u8], certificate: &[CheckPoint]| {
+        state.validate_certificate(10, state_digest, certificate, &config.peer_pub_keys)
+    };
+
+    let snapshot = snapshot_of(10);
+    let state_digest = store_digest(&snapshot.store, &snapshot.key_owners, ALGORITHM);
+    let checkpoint = |id: usize, signer: usize, state_digest: &[u8]| {
+        CheckPoint::new_with_signature(
+            builders[signer].keystore(),
+            id,
+            0,
+            10,
+            0,
+            state_digest.to_vec(),
+        )
+    };
+    let certificate: Vec<CheckPoint> = (0..3).map(|id| checkpoint(id, id, &state_digest)).collect();
+    assert_eq!(validate(&state_digest, &certificate), Ok(()));
+
+    // a forged digest, which the replicas of the certificate never signed
+    let mut forged = snapshot.clone();
+    forged.store.insert(Key::from("k00"), Value::from("forged"));
+    let forged_digest = store_digest(&forged.store, &forged.key_owners, ALGORITHM);
+    let mut forged_certificate = certificate.clone();
+    for checkpoint in forged_certificate.iter_mut() {
+        checkpoint.state_digest = forged_digest.clone();
+    }
+    assert_eq!(
+        validate(&forged_digest, &forged_certificate),
+        Err(SnapshotError::InvalidSignature { id: 0 })
+    );
+    assert_eq!(
+        validate(&forged_digest, &certificate),
+        Err(SnapshotError::MismatchedCertificate { id: 0 })
+    );
+    let mut impersonated = certificate.clone();
+    impersonated[2] = checkpoint(2, 3, &state_digest);
+    assert_eq!(
+        validate(&state_digest, &impersonated),
+        Err(SnapshotError::InvalidSignature { id: 2 })
+    );
+
+    // too few checkpoint signatures, however often a replica signs
+    assert_eq!(
+        validate(&state_digest, &certificate[..2]),
+        Err(SnapshotError::InsufficientCertificate {
+            votes: 2,
+            needed: 3
+        })
+    );
+    let repeated = vec![
+        certificate[0].clone(),
+        certificate[1].clone(),
+        certificate[1].clone(),
+    ];
+    assert_eq!(
+        validate(&state_digest, &repeated),
+        Err(SnapshotError::InsufficientCertificate {
+            votes: 2,
+            needed: 3
+        })
+    );
+
+    // snapshot bytes which do not match the certified digest are refused by every source
+    let mut transfer = StateTransfer::new(3, certificate[0].clone(), certificate);
+    for _ in 0..3 {
+        assert_eq!(
+            transfer.accept(response(&transfer, &forged, 0)),
+            Err(ChunkError::InvalidProof)
+        );
+        transfer.next_source();
+    }
+    assert_eq!(transfer.next_start(), 0);
+    assert!(!transfer.is_complete());
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+use pbft::config::Config;
+use pbft::messages::Operation;
+use pbft::state::State;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, Value};
+
+fn state_of_backup() -> State {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    State::new(1, Config::new(peer_addrs))
+}
+
+#[test]
+fn built_messages_are_signed_by_the_builder() {
+    let builder = MessageBuilder::generate(0);
+    let pub_key = builder.public_key();
+    let other_key = MessageBuilder::generate(1).public_key();
+
+    assert!(builder.pre_prepare().is_properly_signed_by(&pub_key));
+    assert!(builder.prepare().is_properly_signed_by(&pub_key));
+    assert!(builder.commit().is_properly_signed_by(&pub_key));
+    let view_change = builder.view_change();
+    assert!(view_change.is_properly_signed_by(&pub_key));
+    assert!(builder
+        .new_view(vec![view_change])
+        .is_properly_signed_by(&pub_key));
+    assert!(!builder.prepare().is_properly_signed_by(&other_key));
+
+    // the overrides still sign what they carry
+    let wrong_digest = builder.clone().wrong_digest();
+    assert!(wrong_digest.pre_prepare().is_properly_signed_by(&pub_key));
+    assert!(wrong_digest.prepare().is_properly_signed_by(&pub_key));
+    assert!(builder
+        .clone()
+        .wrong_view()
+        .commit()
+        .is_properly_signed_by(&pub_key));
+}
+
+#[test]
+fn overridden_messages_are_rejected() {
+    let mut state = state_of_backup();
+    let primary = MessageBuilder::generate(0);
+    let backup = MessageBuilder::generate(2);
+
+    let pre_prepare = primary.pre_prepare();
+    assert!(state.should_accept_pre_prepare(&pre_prepare));
+    assert!(!state.should_accept_pre_prepare(&primary.clone().wrong_digest().pre_prepare()));
+    assert!(!state.should_accept_pre_prepare(&primary.clone().wrong_view().pre_prepare()));
+    state.log_pre_prepare(pre_prepare);
+
+    assert!(state.should_accept_prepare(&backup.prepare()));
+    assert!(!state.should_accept_prepare(&backup.clone().wrong_digest().prepare()));
+    assert!(!state.should_accept_prepare(&backup.clone().wrong_view().prepare()));
+
+    assert!(state.should_accept_commit(&backup.commit()));
+    assert!(!state.should_accept_commit(&backup.clone().wrong_view().commit()));
+}
+
+#[test]
+fn requests_are_built_with_defaults() {
+    let request = RequestBuilder::new().build();
+    assert_eq!(request.key, Key::from("x"));
+    assert_eq!(request.operation, Operation::Set(Value::from("1")));
+    assert_eq!(request.time_stamp, 0);
+    assert!(request.batch.is_empty() && request.client_id.is_none());
+    assert_eq!(
+        *MessageBuilder::generate(0).pre_prepare().client_request,
+        request
+    );
+
+    let request = RequestBuilder::new()
+        .time_stamp(3)
+        .key(Key::from("k"))
+        .operation(Operation::Delete)
+        .relay_id(2)
+        .build();
+    assert_eq!(
+        (request.time_stamp, request.key.clone(), request.relay_id),
+        (3, Key::from("k"), Some(2))
+    );
+    assert_eq!(request.operation, Operation::Delete);
+
+    // a built request can be signed by a client
+    let keypair = Keypair::generate(&mut OsRng);
+    let signed = RequestBuilder::new()
+        .set("2")
+        .build()
+        .signed_by(7, &keypair);
+    assert!(signed.is_properly_signed_by(&keypair.public));
+}
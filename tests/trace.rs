@@ -0,0 +1,113 @@
+use pbft::itf;
+use pbft::keys::encode_hex;
+use pbft::messages::Message;
+use pbft::observer::{Observer, QuorumKind};
+use pbft::testkit::MessageBuilder;
+use pbft::trace::{self, TraceEntry, TraceEvent, TraceObserver};
+
+#[test]
+fn recorded_events_are_narrated() {
+    let path = std::env::temp_dir().join(format!("pbft-trace-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let observer = TraceObserver::new(&path, 2).unwrap();
+
+    let builder = MessageBuilder::generate(2).view(0).seq_num(5);
+    observer.on_message_in(&Message::PrePrepareMessage(builder.clone().pre_prepare()));
+    observer.on_message_out(&Message::PrepareMessage(builder.clone().prepare()), None);
+    observer.on_quorum(QuorumKind::Prepare, 0, 5, &[3, 0, 2]);
+    observer.on_view_change(1);
+
+    let entries = trace::read_trace(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(entries.len(), 4);
+    assert!(matches!(entries[0].event, TraceEvent::Received { .. }));
+
+    let narrative: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| trace::narrate(entry, false))
+        .collect();
+    assert_eq!(
+        narrative,
+        vec![
+            "node 2 broadcast prepare (v=0, n=5)",
+            "node 2 prepared (v=0, n=5) with votes from {0,2,3}",
+            "node 2 moved to view 1",
+        ]
+    );
+    assert!(trace::narrate(&entries[0], true)
+        .unwrap()
+        .starts_with("node 2 received pre-prepare (v=0, n=5) for "));
+}
+
+#[test]
+fn traces_are_exported_as_protocol_states() {
+    let builder = MessageBuilder::generate(1).view(0).seq_num(5);
+    let entry = |node, event| TraceEntry {
+        node,
+        at_millis: 0,
+        event,
+    };
+    let prepare = Message::PrepareMessage(builder.clone().prepare());
+    let entries = vec![
+        entry(
+            1,
+            TraceEvent::Sent {
+                message: prepare.clone(),
+                destination: None,
+            },
+        ),
+        // received messages and messages sent again change no state
+        entry(
+            2,
+            TraceEvent::Received {
+                message: prepare.clone(),
+            },
+        ),
+        entry(
+            1,
+            TraceEvent::Sent {
+                message: prepare,
+                destination: None,
+            },
+        ),
+        entry(
+            2,
+            TraceEvent::Quorum {
+                kind: QuorumKind::Prepare,
+                view: 0,
+                seq_num: 5,
+                participants: vec![0, 1, 2],
+            },
+        ),
+        entry(2, TraceEvent::ViewChange { view: 1 }),
+    ];
+
+    let itf = itf::export(&entries, 0);
+    let states = itf["states"].as_array().unwrap();
+    let actions: Vec<&str> = states
+        .iter()
+        .map(|state| state["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        vec!["Init", "SendPrepare", "Prepared", "EnterView"]
+    );
+
+    let last = states.last().unwrap();
+    assert_eq!(last["#meta"]["index"], 3);
+    assert_eq!(last["view"]["#map"], serde_json::json!([[1, 0], [2, 1]]));
+    assert_eq!(
+        last["prepared"]["#map"][1],
+        serde_json::json!([2, { "#set": [{ "#tup": [0, 5] }] }])
+    );
+    assert_eq!(
+        last["sent"]["#set"][0],
+        serde_json::json!({
+            "type": "Prepare",
+            "src": 1,
+            "view": 0,
+            "seq": 5,
+            "digest": encode_hex(&builder.prepare().client_request_digest),
+        })
+    );
+}
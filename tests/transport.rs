@@ -0,0 +1,82 @@
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use pbft::keystore::Keystore;
+use pbft::transport::{SecureChannel, TransportError, HANDSHAKE_MAGIC};
+
+/// Opens a channel from node 0 to node 1, with node 0 expecting the given key for node 1
+async fn open(
+    initiator: &Keystore,
+    responder: &Keystore,
+    expected_key: Option<&Keystore>,
+) -> (
+    Result<(SecureChannel, DuplexStream), TransportError>,
+    Result<(SecureChannel, DuplexStream), TransportError>,
+) {
+    let (mut a, mut b) = duplex(1 << 16);
+    let expected_key = expected_key.map(|keystore| keystore.public_key());
+    let connect =
+        async { SecureChannel::connect(&mut a, initiator, 0, 1, expected_key.as_ref()).await };
+    let accept = async {
+        let mut magic = [0u8; HANDSHAKE_MAGIC.len()];
+        b.read_exact(&mut magic).await?;
+        assert_eq!(&magic, HANDSHAKE_MAGIC);
+        SecureChannel::accept(&mut b, responder, 1).await
+    };
+    let (connected, accepted) = tokio::join!(connect, accept);
+    (connected.map(|c| (c, a)), accepted.map(|c| (c, b)))
+}
+
+#[tokio::test]
+async fn records_are_exchanged_in_both_directions() {
+    let initiator = Keystore::generate();
+    let responder = Keystore::generate();
+    let (connected, accepted) = open(&initiator, &responder, Some(&responder)).await;
+    let (mut a_channel, mut a) = connected.unwrap();
+    let (mut b_channel, mut b) = accepted.unwrap();
+    assert_eq!(b_channel.peer_id, 0);
+    assert_eq!(b_channel.peer_pub_key, initiator.public_key());
+
+    for data in [&b"first"[..], b"second", b""] {
+        a_channel.write(&mut a, data).await.unwrap();
+        assert_eq!(b_channel.read(&mut b).await.unwrap(), data);
+    }
+    b_channel.write(&mut b, b"reply").await.unwrap();
+    assert_eq!(a_channel.read(&mut a).await.unwrap(), b"reply");
+}
+
+#[tokio::test]
+async fn records_are_encrypted_and_authenticated() {
+    let initiator = Keystore::generate();
+    let responder = Keystore::generate();
+    let (connected, accepted) = open(&initiator, &responder, None).await;
+    let (mut a_channel, _a) = connected.unwrap();
+    let (mut b_channel, _b) = accepted.unwrap();
+
+    let (mut tap_in, mut tap_out) = duplex(1 << 16);
+    a_channel.write(&mut tap_in, b"secret value").await.unwrap();
+    drop(tap_in);
+    let mut record = Vec::new();
+    tap_out.read_to_end(&mut record).await.unwrap();
+    assert!(!record
+        .windows(b"secret".len())
+        .any(|window| window == b"secret"));
+
+    // a flipped bit is detected
+    let last = record.len() - 1;
+    record[last] ^= 1;
+    let (mut tampered_in, mut tampered_out) = duplex(1 << 16);
+    tampered_in.write_all(&record).await.unwrap();
+    assert!(matches!(
+        b_channel.read(&mut tampered_out).await,
+        Err(TransportError::Unauthenticated(0))
+    ));
+}
+
+#[tokio::test]
+async fn responders_must_hold_the_expected_key() {
+    let initiator = Keystore::generate();
+    let responder = Keystore::generate();
+    let impostor = Keystore::generate();
+    let (connected, _) = open(&initiator, &impostor, Some(&responder)).await;
+    assert!(matches!(connected, Err(TransportError::Unauthenticated(1))));
+}
@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use pbft::messages::{ClientRequest, ClientResponse, FailureReason, Message};
+use pbft::observer::Observer;
+use pbft::testing::ClusterBuilder;
+use pbft::testkit::{MessageBuilder, RequestBuilder};
+use pbft::{Key, Value};
+
+fn request(time_stamp: usize, value: &str) -> ClientRequest {
+    RequestBuilder::new()
+        .time_stamp(time_stamp)
+        .set(value)
+        .build()
+}
+
+/// Responses a replica sent to clients
+#[derive(Default)]
+struct Responses(Mutex<Vec<ClientResponse>>);
+
+impl Observer for Responses {
+    fn on_message_out(&self, message: &Message, _destination: Option<SocketAddr>) {
+        if let Message::ClientResponseMessage(response) = message {
+            self.0.lock().unwrap().push(response.clone());
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn clients_are_told_of_requests_a_new_view_fills_with_no_ops() {
+    let cluster = ClusterBuilder::new(4).seed(5).build();
+    let mut client = cluster.client();
+    assert!(client.put(Key::from("k"), Value::from("1")).await.is_some());
+    assert!(cluster.await_commit(1).await);
+    let responses = Arc::new(Responses::default());
+    cluster.node(1).observers.register(responses.clone());
+
+    // the primary assigns a request to the second slot, which only replica 1 learns of,
+    // and another one to the third slot, which prepares
+    let primary = MessageBuilder::new(cluster.node(0).keystore.clone(), 0);
+    let unordered = request(7, "a");
+    let pre_prepare = primary
+        .clone()
+        .seq_num(2)
+        .client_request(unordered.clone())
+        .pre_prepare();
+    cluster
+        .node(1)
+        .receive(Some(0), Message::PrePrepareMessage(pre_prepare))
+        .await
+        .unwrap();
+    let pre_prepare = primary
+        .seq_num(3)
+        .client_request(request(8, "b"))
+        .pre_prepare();
+    for id in 1..4 {
+        cluster
+            .node(id)
+            .receive(Some(0), Message::PrePrepareMessage(pre_prepare.clone()))
+            .await
+            .unwrap();
+    }
+    cluster.kill_node(0);
+
+    // the backups wait for the next request in vain too, and change the view. The new primary
+    // fills the second slot with a no-op, and replica 1 tells the client
+    assert!(client.put(Key::from("k"), Value::from("2")).await.is_some());
+    assert!(cluster.await_view(1).await);
+    let view = cluster.status(1).view;
+    let unordered_responses: Vec<ClientResponse> = responses
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|response| response.time_stamp == unordered.time_stamp)
+        .filter(|response| matches!(response.reason, Some(FailureReason::Unordered { .. })))
+        .cloned()
+        .collect();
+    assert_eq!(unordered_responses.len(), 1);
+    match unordered_responses[0].reason {
+        Some(FailureReason::Unordered { resubmit_hint }) => {
+            assert_eq!(resubmit_hint.view, view);
+            assert_ne!(resubmit_hint.primary, 0);
+            assert_eq!(unordered_responses[0].view, view);
+        }
+        reason => panic!("unexpected reason {:?}", reason),
+    }
+}
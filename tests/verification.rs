@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::membership::Membership;
+use pbft::messages::{CheckPoint, ConsensusCommand, FetchRequestBody, Message, Ping};
+use pbft::testkit::MessageBuilder;
+use pbft::verification::{Triage, VerificationBudget, VerificationPool};
+
+use tokio::sync::mpsc::channel;
+
+#[tokio::test(start_paused = true)]
+async fn overload_puts_off_messages_far_from_the_pipeline_head() {
+    let mut config = Config::new(HashMap::new());
+    config.checkpoint_frequency = 10;
+    config.log_window = 40;
+    config.verification_budget = Duration::from_millis(10);
+    config.verification_tick = Duration::from_millis(100);
+    config.verification_backlog = 3;
+    let mut budget = VerificationBudget::new(&config);
+
+    let builder = MessageBuilder::generate(1);
+    let prepare = |seq_num| Message::PrepareMessage(builder.clone().seq_num(seq_num).prepare());
+    let view_change = Message::ViewChangeMessage(builder.view_change());
+    let checkpoint = Message::CheckPointMessage(CheckPoint {
+        id: 1,
+        epoch: 0,
+        committed_seq_num: 30,
+        view: 0,
+        state_digest: Vec::new(),
+        signature: Vec::new(),
+    });
+    let ping = Message::PingMessage(Ping {
+        id: 1,
+        seq: 0,
+        sent_at_micros: 0,
+    });
+
+    // with the whole budget left, the entire log window is verified
+    assert_eq!(budget.admit(&prepare(35), 0, true), Triage::Verify);
+    budget.record(Duration::from_millis(4));
+
+    // with 60% of it left, only sequence numbers within 60% of the window are
+    assert_eq!(budget.admit(&prepare(20), 0, true), Triage::Verify);
+    assert_eq!(budget.admit(&prepare(30), 0, true), Triage::Defer);
+    budget.record(Duration::from_millis(6));
+    assert_eq!(budget.admit(&prepare(12), 0, true), Triage::Defer);
+    // the sequence numbers up to the next checkpoint, view changes and checkpoints are
+    // always verified
+    assert_eq!(budget.admit(&prepare(10), 0, true), Triage::Verify);
+    assert_eq!(budget.admit(&prepare(25), 20, true), Triage::Verify);
+    assert_eq!(budget.admit(&view_change, 0, true), Triage::Verify);
+    assert_eq!(budget.admit(&checkpoint, 0, true), Triage::Verify);
+
+    // once the backlog is full, only messages which are recovered without them are shed
+    assert_eq!(budget.admit(&prepare(13), 0, true), Triage::Defer);
+    assert_eq!(budget.admit(&prepare(14), 0, true), Triage::Shed);
+    assert_eq!(budget.admit(&ping, 0, true), Triage::Shed);
+    let fetch = Message::FetchRequestBodyMessage(FetchRequestBody {
+        id: 1,
+        digest: Vec::new(),
+    });
+    assert_eq!(budget.admit(&fetch, 0, true), Triage::Defer);
+
+    let stats = budget.stats();
+    assert_eq!((stats.deferred, stats.backlog), (4, 4));
+    assert_eq!(stats.shed.get("Prepare"), Some(&1));
+    assert_eq!(stats.shed.get("Ping"), Some(&1));
+
+    // the backlog is verified in the order the messages arrived
+    let seq_nums: Vec<_> = std::iter::from_fn(|| budget.take_deferred())
+        .map(|message| message.get_seq_num())
+        .collect();
+    assert_eq!(seq_nums, vec![Some(30), Some(12), Some(13), None]);
+    assert_eq!(budget.admit(&prepare(35), 0, false), Triage::Verify);
+    assert_eq!(budget.stats().backlog, 0);
+}
+
+#[tokio::test]
+async fn verification_workers_pass_messages_on_in_queue_order() {
+    let builders: Vec<MessageBuilder> = (0..3).map(MessageBuilder::generate).collect();
+    let mut config = Config::new(HashMap::new());
+    config.peer_pub_keys = builders
+        .iter()
+        .enumerate()
+        .map(|(id, builder)| (id, builder.public_key()))
+        .collect();
+    config.verification_workers = 4;
+    config.verification_batch_size = 16;
+    let pool = VerificationPool::new(
+        0,
+        builders[0].public_key(),
+        &config,
+        Membership::new(&config),
+        Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+        None,
+    );
+
+    // node 2 claims to be node 1 on every third message
+    let impostor = builders[2].clone().id(1);
+    let (tx_queued, rx_queued) = channel(64);
+    let (tx_verified, mut rx_verified) = channel(64);
+    tokio::spawn(pool.run(rx_queued, tx_verified));
+    let mut expected = Vec::new();
+    for seq_num in 1..=40 {
+        let (builder, authentic) = match seq_num % 3 {
+            0 => (&impostor, false),
+            1 => (&builders[1], true),
+            _ => (&builders[2], true),
+        };
+        let prepare = builder.clone().seq_num(seq_num).prepare();
+        expected.push((prepare.id, seq_num, authentic));
+        let cmd = ConsensusCommand::ProcessMessage(Message::PrepareMessage(prepare));
+        tx_queued.send(cmd).await.unwrap();
+    }
+    tx_queued
+        .send(ConsensusCommand::PeerReconnected(1))
+        .await
+        .unwrap();
+
+    for (id, seq_num, authentic) in expected {
+        match rx_verified.recv().await.unwrap() {
+            ConsensusCommand::ProcessVerifiedMessage {
+                message: Message::PrepareMessage(prepare),
+                authentic: verdict,
+            } => assert_eq!(
+                (prepare.id, prepare.seq_num, verdict),
+                (id, seq_num, authentic)
+            ),
+            cmd => panic!("unexpected {:?}", cmd),
+        }
+    }
+    assert!(matches!(
+        rx_verified.recv().await,
+        Some(ConsensusCommand::PeerReconnected(1))
+    ));
+}
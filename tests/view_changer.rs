@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pbft::config::Config;
+use pbft::connectivity::Connectivity;
+use pbft::messages::Pong;
+use pbft::view_changer::ViewChanger;
+use tokio::sync::mpsc::channel;
+
+#[test]
+fn view_change_timers_back_off_until_progress() {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.request_timeout = Duration::from_secs(2);
+    config.view_change_timeout_min = Duration::from_secs(3);
+    config.view_change_timeout_max = Duration::from_secs(20);
+    let view_changer = ViewChanger {
+        id: 1,
+        config,
+        tx_consensus: channel(1).0,
+        wait_set: Arc::new(Mutex::new(HashSet::new())),
+        resets: Arc::new(AtomicUsize::new(0)),
+        consecutive_view_changes: Arc::new(AtomicUsize::new(0)),
+        sent_pre_prepares: Arc::new(Mutex::new(HashSet::new())),
+        connectivity: Connectivity::new(3),
+    };
+
+    // the timeout doubles with every consecutive view change, within the bounds
+    let mut timeouts = Vec::new();
+    for _ in 0..5 {
+        timeouts.push(view_changer.timeout().as_secs());
+        view_changer.record_view_change();
+    }
+    assert_eq!(timeouts, vec![3, 4, 8, 16, 20]);
+
+    // executing a request resets it
+    view_changer.record_progress();
+    assert_eq!(view_changer.timeout(), Duration::from_secs(3));
+    for _ in 0..100 {
+        view_changer.record_view_change();
+    }
+    assert_eq!(view_changer.timeout(), Duration::from_secs(20));
+}
+
+#[tokio::test(start_paused = true)]
+async fn view_change_timers_start_at_a_multiple_of_the_quorum_round_trip() {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut config = Config::new(peer_addrs);
+    config.request_timeout = Duration::from_secs(2);
+    config.rtt_timeout_factor = 10;
+    let connectivity = Connectivity::new(3);
+    let view_changer = ViewChanger {
+        id: 0,
+        config,
+        tx_consensus: channel(1).0,
+        wait_set: Arc::new(Mutex::new(HashSet::new())),
+        resets: Arc::new(AtomicUsize::new(0)),
+        consecutive_view_changes: Arc::new(AtomicUsize::new(0)),
+        sent_pre_prepares: Arc::new(Mutex::new(HashSet::new())),
+        connectivity: connectivity.clone(),
+    };
+    assert_eq!(view_changer.timeout(), Duration::from_secs(2));
+
+    // a quorum of peers answers within 300 ms, so a request cannot commit much faster
+    let pings: Vec<_> = (1..4)
+        .map(|peer_id| connectivity.ping(0, peer_id))
+        .collect();
+    for (peer_id, ping) in (1..4).zip(pings) {
+        tokio::time::advance(Duration::from_millis(150)).await;
+        connectivity.pong(&Pong {
+            id: peer_id,
+            seq: ping.seq,
+            sent_at_micros: ping.sent_at_micros,
+        });
+    }
+    assert_eq!(view_changer.timeout(), Duration::from_secs(3));
+    view_changer.record_view_change();
+    assert_eq!(view_changer.timeout(), Duration::from_secs(6));
+}
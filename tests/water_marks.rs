@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+
+use pbft::config::Config;
+use pbft::state::State;
+use pbft::testkit::MessageBuilder;
+
+#[test]
+fn pre_prepares_are_only_accepted_within_the_water_marks() {
+    let peer_addrs = (0..4)
+        .map(|id| (id, format!("127.0.0.1:{}", 7000 + id).parse().unwrap()))
+        .collect::<HashMap<_, _>>();
+    let mut state = State::new(1, Config::new(peer_addrs));
+    let window = state.config.log_window;
+    let primary = MessageBuilder::generate(0).view(0);
+    let accepts = |state: &State, seq_num: usize| {
+        state.should_accept_pre_prepare(&primary.clone().seq_num(seq_num).pre_prepare())
+    };
+
+    assert!(accepts(&state, 1));
+    assert!(accepts(&state, window));
+    assert!(!accepts(&state, window + 1));
+    assert!(!accepts(&state, usize::MAX));
+
+    // a stable checkpoint moves the window
+    state.last_stable_seq_num = 10;
+    assert!(!accepts(&state, 10));
+    assert!(accepts(&state, 11));
+    assert!(accepts(&state, 10 + window));
+    assert!(!accepts(&state, 11 + window));
+}